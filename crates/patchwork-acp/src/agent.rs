@@ -16,11 +16,13 @@
 //! 3. Agent creates LLM sessions and accumulates responses
 //! 4. Results are sent back via `ThinkResponse` on `std::sync::mpsc`
 
+use std::collections::{HashMap, VecDeque};
 use std::sync::Arc;
 
 use sacp::schema::{
-    ContentBlock, NewSessionRequest, NewSessionResponse, PromptRequest, PromptResponse,
-    SessionNotification, SessionUpdate, StopReason,
+    ContentBlock, NewSessionRequest, NewSessionResponse, PermissionOptionKind, PromptRequest,
+    PromptResponse, RequestPermissionOutcome, RequestPermissionRequest, RequestPermissionResponse,
+    SessionId, SessionNotification, SessionUpdate, StopReason, ToolCall,
 };
 use sacp::JrConnectionCx;
 use sacp_proxy::{JrCxExt, McpServer, McpServiceRegistry};
@@ -29,19 +31,19 @@ use serde::{Deserialize, Serialize};
 use tokio::sync::mpsc::{channel, unbounded_channel, Sender, UnboundedReceiver, UnboundedSender};
 use tokio::sync::{oneshot, Mutex};
 
-use patchwork_eval::{AgentHandle, ThinkRequest, ThinkResponse, Value};
+use patchwork_eval::{AgentHandle, FollowUp, ThinkRequest, ThinkResponse, Value};
 
 /// Result of a think block execution.
 pub type ThinkResult = Result<Value, String>;
 
 /// Messages internal to the agent for routing between sessions.
 pub enum RedirectMessage {
-    /// An incoming message from SACP to route to the active thinker.
+    /// An incoming message from SACP to route to the thinker it belongs to.
     IncomingMessage(PerSessionMessage),
-    /// Push a new thinker onto the stack (for nested think blocks).
-    PushThinker(Sender<PerSessionMessage>),
-    /// Pop the top thinker from the stack.
-    PopThinker,
+    /// Start routing a session's messages to a thinker.
+    AddThinker(SessionId, Sender<PerSessionMessage>),
+    /// Stop routing a session's messages once its turn is over.
+    RemoveThinker(SessionId),
 }
 
 /// Messages that get routed to individual think sessions.
@@ -50,8 +52,8 @@ pub enum PerSessionMessage {
     SessionNotification(SessionNotification),
     /// The LLM invoked the "do" tool.
     DoInvocation(DoArg, oneshot::Sender<String>),
-    /// The prompt completed.
-    PromptResponse(PromptResponse),
+    /// The prompt sent in a session completed.
+    PromptResponse(SessionId, PromptResponse),
 }
 
 /// Argument for the MCP "do" tool.
@@ -74,6 +76,35 @@ pub struct AgentState {
     pub redirect_tx: UnboundedSender<RedirectMessage>,
     /// MCP registry with the "do" tool.
     pub mcp_registry: McpServiceRegistry,
+    /// The tools each think session may use, for sessions that restrict them.
+    pub allowed_tools: std::sync::Mutex<HashMap<SessionId, Vec<String>>>,
+}
+
+impl AgentState {
+    /// The answer to a permission request from the successor, if one of our
+    /// think sessions forbids the tool; `None` leaves it to the client.
+    ///
+    /// Denying the request keeps the tool from running, where the tool
+    /// calls `think_turn` sees are only reported after the fact.
+    pub fn permission_denial(&self, request: &RequestPermissionRequest) -> Option<RequestPermissionResponse> {
+        let allowed_tools = self.allowed_tools.lock().unwrap();
+        let tools = allowed_tools.get(&request.session_id)?;
+        let call = &request.tool_call;
+        let name = reported_tool_name(call.meta.as_ref(), call.fields.title.as_deref().unwrap_or_default());
+        if tool_allowed(&name, tools) {
+            return None;
+        }
+        tracing::warn!("denying tool {} outside the think block's tools list", name);
+        let options = || request.options.iter();
+        let reject = options()
+            .find(|option| matches!(option.kind, PermissionOptionKind::RejectOnce))
+            .or_else(|| options().find(|option| matches!(option.kind, PermissionOptionKind::RejectAlways)));
+        let outcome = match reject {
+            Some(option) => RequestPermissionOutcome::Selected { option_id: option.id.clone() },
+            None => RequestPermissionOutcome::Cancelled,
+        };
+        Some(RequestPermissionResponse { outcome, meta: None })
+    }
 }
 
 /// Create an agent that bridges the interpreter to async LLM sessions.
@@ -103,6 +134,7 @@ pub fn create_agent(
     let state = Arc::new(AgentState {
         redirect_tx: redirect_tx.clone(),
         mcp_registry,
+        allowed_tools: std::sync::Mutex::new(HashMap::new()),
     });
 
     // Spawn redirect actor via cx.spawn() - it doesn't need to call block_task()
@@ -121,98 +153,172 @@ pub fn create_agent(
 
 
 /// Process a single think request from the interpreter.
+///
+/// A `think.start` request comes with follow-ups. Its session stays open
+/// after the first answer, and each follow-up is prompted in it, so the
+/// successor keeps the earlier turns in context. The session is left once
+/// the interpreter closes the conversation.
 pub async fn process_think_request(cx: JrConnectionCx, request: ThinkRequest, state: Arc<AgentState>) -> Result<(), sacp::Error> {
     let ThinkRequest {
         prompt,
         bindings: _,
         expect,
+        tools,
+        follow_ups,
         response_tx,
     } = request;
 
     // Execute the think block and send responses
-    let result = think_message(cx, prompt, expect, state).await;
+    let session = open_session(cx.clone(), tools.as_deref(), &state).await;
+    let result = match &session {
+        Ok(session_id) => {
+            think_turn(cx.clone(), session_id.clone(), prompt, &expect, tools.as_deref(), response_tx.clone(), &state)
+                .await
+        }
+        Err(e) => Err(e.clone()),
+    };
 
     // Send the Complete response
     let _ = response_tx.send(ThinkResponse::Complete { result });
 
+    // Answer the rest of a conversation in the same session
+    if let (Ok(session_id), Some(mut follow_ups)) = (&session, follow_ups) {
+        while let Some(FollowUp { prompt, response_tx }) = follow_ups.recv().await {
+            let result =
+                think_turn(cx.clone(), session_id.clone(), prompt, &expect, tools.as_deref(), response_tx.clone(), &state)
+                    .await;
+            let _ = response_tx.send(ThinkResponse::Complete { result });
+        }
+    }
+
+    if let Ok(session_id) = &session {
+        state.allowed_tools.lock().unwrap().remove(session_id);
+    }
+
     Ok(())
 }
 
-/// The redirect actor maintains a stack of active thinkers and routes messages.
+/// The redirect actor tracks the active thinkers by session and routes
+/// messages to them.
 ///
-/// When nested think blocks occur, each one pushes onto the stack. Messages
-/// are always routed to the top of the stack (the innermost active think).
+/// Nested think blocks, spawned tasks, and `think_all` all keep several
+/// sessions active at once. Notifications and prompt responses name their
+/// session. A `do` invocation doesn't, so it goes to the session that
+/// announced the `do` tool call earliest without having invoked it yet, or
+/// to the only active session.
 async fn redirect_actor(mut rx: UnboundedReceiver<RedirectMessage>) {
-    let mut stack: Vec<Sender<PerSessionMessage>> = vec![];
+    let mut thinkers: HashMap<SessionId, Sender<PerSessionMessage>> = HashMap::new();
+    let mut pending_dos: VecDeque<SessionId> = VecDeque::new();
 
     while let Some(message) = rx.recv().await {
         match message {
             RedirectMessage::IncomingMessage(msg) => {
-                if let Some(sender) = stack.last() {
-                    if sender.send(msg).await.is_err() {
-                        tracing::warn!("Failed to send message to thinker");
+                let session_id = match &msg {
+                    PerSessionMessage::SessionNotification(notification) => {
+                        if let SessionUpdate::ToolCall(call) = &notification.update {
+                            if is_do_tool(&tool_call_name(call)) {
+                                pending_dos.push_back(notification.session_id.clone());
+                            }
+                        }
+                        Some(notification.session_id.clone())
                     }
+                    PerSessionMessage::DoInvocation(..) => pending_dos.pop_front(),
+                    PerSessionMessage::PromptResponse(session_id, _) => Some(session_id.clone()),
+                };
+                let sender = match session_id {
+                    Some(session_id) => thinkers.get(&session_id),
+                    None if thinkers.len() == 1 => thinkers.values().next(),
+                    None => None,
+                };
+                match sender {
+                    Some(sender) => {
+                        if sender.send(msg).await.is_err() {
+                            tracing::warn!("Failed to send message to thinker");
+                        }
+                    }
+                    None => tracing::warn!("Dropping a message no active thinker is waiting for"),
                 }
             }
-            RedirectMessage::PushThinker(sender) => {
-                stack.push(sender);
+            RedirectMessage::AddThinker(session_id, sender) => {
+                thinkers.insert(session_id, sender);
             }
-            RedirectMessage::PopThinker => {
-                stack.pop();
+            RedirectMessage::RemoveThinker(session_id) => {
+                thinkers.remove(&session_id);
+                pending_dos.retain(|pending| *pending != session_id);
             }
         }
     }
 }
 
-/// Handle a single think block by creating an LLM session with the successor.
-async fn think_message(
-    cx: JrConnectionCx,
-    prompt: String,
-    expect: String,
-    state: Arc<AgentState>,
-) -> ThinkResult {
-    // Build the augmented prompt with type hints
-    let augmented_prompt = augment_prompt_with_type_hint(&prompt, &expect);
-
+/// Create an LLM session with the successor for a think block.
+///
+/// When the block restricts its tools, the list is passed to the successor in
+/// the session's `_meta`, and the session's permission requests for other
+/// tools are denied.
+async fn open_session(cx: JrConnectionCx, tools: Option<&[String]>, state: &AgentState) -> Result<SessionId, String> {
     // Create session request with our MCP server
     let mut new_session = NewSessionRequest {
         cwd: std::env::current_dir().unwrap_or_default(),
         mcp_servers: vec![],
-        meta: None,
+        meta: tools.map(|tools| serde_json::json!({ "patchwork": { "allowedTools": tools } })),
     };
     state
         .mcp_registry
         .add_registered_mcp_servers_to(&mut new_session);
 
     // Start a new session with the successor agent (e.g., claude-code-acp)
-    // This uses block_task().await directly because think_message is spawned via cx.spawn(),
+    // This uses block_task().await directly because process_think_request is spawned via cx.spawn(),
     // so it's part of the connection's event loop and can receive responses.
     tracing::info!("THINK_MSG: about to send session/new to successor");
     let response_future = cx.send_request_to_successor(new_session);
     tracing::info!("THINK_MSG: request future created, now calling block_task()");
     let session_result = response_future.block_task().await;
     tracing::info!("THINK_MSG: block_task() RETURNED! is_ok={:?}", session_result.is_ok());
-    let NewSessionResponse { session_id, .. } = match session_result {
-        Ok(resp) => {
-            tracing::info!("think_message: got session_id={}", resp.session_id);
-            resp
+    match session_result {
+        Ok(NewSessionResponse { session_id, .. }) => {
+            tracing::info!("open_session: got session_id={}", session_id);
+            if let Some(tools) = tools {
+                state.allowed_tools.lock().unwrap().insert(session_id.clone(), tools.to_vec());
+            }
+            Ok(session_id)
         }
-        Err(e) => return Err(format!("Failed to create session: {}", e)),
-    };
+        Err(e) => Err(format!("Failed to create session: {}", e)),
+    }
+}
+
+/// Prompt the successor in a think block's session and accumulate its answer.
+///
+/// When the block restricts its tools, the list is spelled out in the prompt,
+/// and a call to any other tool fails the turn. Such calls are denied when
+/// the successor asks permission; this catches those it runs without asking.
+async fn think_turn(
+    cx: JrConnectionCx,
+    session_id: SessionId,
+    prompt: String,
+    expect: &str,
+    tools: Option<&[String]>,
+    response_tx: std::sync::mpsc::Sender<ThinkResponse>,
+    state: &AgentState,
+) -> ThinkResult {
+    // Build the augmented prompt with type hints
+    let mut augmented_prompt = augment_prompt_with_type_hint(&prompt, expect);
+    if let Some(tools) = tools {
+        augmented_prompt.push_str(&tool_restriction(tools));
+    }
 
     // Create channel for receiving messages for this think session
     let (think_tx, mut think_rx) = channel(128);
     if state
         .redirect_tx
-        .send(RedirectMessage::PushThinker(think_tx))
+        .send(RedirectMessage::AddThinker(session_id.clone(), think_tx))
         .is_err()
     {
         return Err("Redirect actor not running".to_string());
     }
-    tracing::info!("think_message: pushed thinker onto stack");
+    tracing::info!("think_turn: added thinker for session {}", session_id);
 
     // Send the prompt request to the successor
-    tracing::info!("think_message: sending prompt to successor for session {}", session_id);
+    tracing::info!("think_turn: sending prompt to successor for session {}", session_id);
     let prompt_result = cx
         .send_request_to_successor(PromptRequest {
             session_id: session_id.clone(),
@@ -221,39 +327,67 @@ async fn think_message(
         })
         .await_when_result_received({
             let redirect_tx = state.redirect_tx.clone();
+            let session_id = session_id.clone();
             async move |response| {
                 redirect_tx
                     .send(RedirectMessage::IncomingMessage(
-                        PerSessionMessage::PromptResponse(response?),
+                        PerSessionMessage::PromptResponse(session_id, response?),
                     ))
                     .map_err(sacp::util::internal_error)
             }
         });
 
     if let Err(e) = prompt_result {
-        let _ = state.redirect_tx.send(RedirectMessage::PopThinker);
+        let _ = state.redirect_tx.send(RedirectMessage::RemoveThinker(session_id));
         return Err(format!("Failed to send prompt: {}", e));
     }
 
-    // Accumulate the response
+    // Accumulate the response, noting the first tool call the block doesn't allow
     let mut result_text = String::new();
+    let mut disallowed_tool = None;
 
     while let Some(message) = think_rx.recv().await {
         match message {
-            PerSessionMessage::SessionNotification(notification) => {
+            PerSessionMessage::SessionNotification(notification) => match notification.update {
                 // Accumulate streaming text from the LLM
-                if let SessionUpdate::AgentMessageChunk(chunk) = notification.update {
+                SessionUpdate::AgentMessageChunk(chunk) => {
                     if let ContentBlock::Text(text) = chunk.content {
                         result_text.push_str(&text.text);
                     }
                 }
-            }
+                SessionUpdate::ToolCall(call) => {
+                    if let Some(tools) = tools {
+                        let name = tool_call_name(&call);
+                        if !tool_allowed(&name, tools) && disallowed_tool.is_none() {
+                            tracing::warn!("think block called tool {} outside its tools list", name);
+                            disallowed_tool = Some(name);
+                        }
+                    }
+                }
+                _ => {}
+            },
             PerSessionMessage::DoInvocation(DoArg { number }, do_tx) => {
-                // TODO: In Phase 5, this will trigger recursive evaluation
-                // For now, return an error
-                let _ = do_tx.send(format!("do({}) not yet implemented", number));
+                // Hand the do-block back to the interpreter thread, which is
+                // blocked on response_tx, and relay its output to the LLM
+                let (result_tx, result_rx) = std::sync::mpsc::sync_channel(1);
+                let text = if response_tx
+                    .send(ThinkResponse::Do { index: number, result_tx })
+                    .is_ok()
+                {
+                    tokio::task::spawn_blocking(move || result_rx.recv())
+                        .await
+                        .ok()
+                        .and_then(|r| r.ok())
+                        .unwrap_or_else(|| format!("do({}) failed: interpreter did not respond", number))
+                } else {
+                    format!("do({}) failed: interpreter is gone", number)
+                };
+                let _ = do_tx.send(text);
             }
-            PerSessionMessage::PromptResponse(response) => {
+            PerSessionMessage::PromptResponse(_, response) => {
+                if let Some(tokens) = reported_tokens(response.meta.as_ref()) {
+                    let _ = response_tx.send(ThinkResponse::Usage { tokens });
+                }
                 match response.stop_reason {
                     StopReason::EndTurn => break,
                     reason => {
@@ -265,11 +399,74 @@ async fn think_message(
         }
     }
 
-    // Pop ourselves from the redirect stack
-    let _ = state.redirect_tx.send(RedirectMessage::PopThinker);
+    // Stop receiving this session's messages
+    let _ = state.redirect_tx.send(RedirectMessage::RemoveThinker(session_id));
+
+    if let (Some(name), Some(tools)) = (disallowed_tool, tools) {
+        return Err(format!(
+            "Think block used tool `{}`, which isn't in its tools list ({})",
+            name,
+            tools.join(", ")
+        ));
+    }
 
     // Extract the typed value from the response
-    extract_response_value(&result_text, &expect)
+    extract_response_value(&result_text, expect)
+}
+
+/// The instruction appended to a prompt whose think block restricts tools.
+fn tool_restriction(tools: &[String]) -> String {
+    if tools.is_empty() {
+        "\n\nDo not use any tools while answering.".to_string()
+    } else {
+        format!("\n\nUse only these tools while answering: {}.", tools.join(", "))
+    }
+}
+
+/// The name of the tool behind a call.
+fn tool_call_name(call: &ToolCall) -> String {
+    reported_tool_name(call.meta.as_ref(), &call.title)
+}
+
+/// The `toolName` the successor reports in a call's `_meta`, at the top
+/// level or one object down, or else the call's title.
+fn reported_tool_name(meta: Option<&serde_json::Value>, title: &str) -> String {
+    let reported = meta.and_then(|meta| {
+        meta.get("toolName")
+            .or_else(|| meta.as_object()?.values().find_map(|nested| nested.get("toolName")))
+    });
+    match reported.and_then(|name| name.as_str()) {
+        Some(name) => name.to_string(),
+        None => title.to_string(),
+    }
+}
+
+/// The tokens a prompt response reports using in its `_meta`: a `usage`
+/// object, at the top level or one object down, with `inputTokens` and
+/// `outputTokens` or else `totalTokens`.
+fn reported_tokens(meta: Option<&serde_json::Value>) -> Option<u64> {
+    let meta = meta?;
+    let usage = meta
+        .get("usage")
+        .or_else(|| meta.as_object()?.values().find_map(|nested| nested.get("usage")))?;
+    let count = |field: &str| usage.get(field).and_then(serde_json::Value::as_u64);
+    match (count("inputTokens"), count("outputTokens")) {
+        (None, None) => count("totalTokens"),
+        (input, output) => Some(input.unwrap_or(0) + output.unwrap_or(0)),
+    }
+}
+
+/// Whether a think block limited to `tools` may call `name`. Names match
+/// without regard to case, and the interpreter's own `do` tool is always
+/// allowed, however the successor prefixes MCP tool names.
+fn tool_allowed(name: &str, tools: &[String]) -> bool {
+    is_do_tool(name) || tools.iter().any(|tool| tool.eq_ignore_ascii_case(name))
+}
+
+/// Whether `name` is the interpreter's `do` tool, however the successor
+/// prefixes MCP tool names.
+fn is_do_tool(name: &str) -> bool {
+    name == "do" || name.ends_with("__do")
 }
 
 /// Create the MCP server offering the "do" tool.
@@ -450,6 +647,124 @@ That's all!"#;
         assert!(result.contains("```text"));
     }
 
+    #[tokio::test]
+    async fn test_redirect_routes_overlapping_thinks_by_session() {
+        let session = |id: &str| -> SessionId { serde_json::from_value(serde_json::json!(id)).unwrap() };
+        let notification = |id: &str, update: serde_json::Value| {
+            let notification = serde_json::from_value(serde_json::json!({ "sessionId": id, "update": update })).unwrap();
+            RedirectMessage::IncomingMessage(PerSessionMessage::SessionNotification(notification))
+        };
+        let chunk = |text: &str| serde_json::json!({
+            "sessionUpdate": "agent_message_chunk",
+            "content": { "type": "text", "text": text },
+        });
+        let done = |id: &str| {
+            let response = serde_json::from_value(serde_json::json!({ "stopReason": "end_turn" })).unwrap();
+            RedirectMessage::IncomingMessage(PerSessionMessage::PromptResponse(session(id), response))
+        };
+
+        let (redirect_tx, redirect_rx) = unbounded_channel();
+        tokio::spawn(redirect_actor(redirect_rx));
+        let (first_tx, mut first_rx) = channel(8);
+        let (second_tx, mut second_rx) = channel(8);
+        redirect_tx.send(RedirectMessage::AddThinker(session("first"), first_tx)).unwrap();
+        redirect_tx.send(RedirectMessage::AddThinker(session("second"), second_tx)).unwrap();
+
+        // The first think finishes while the second, started later, still runs
+        redirect_tx.send(notification("first", chunk("one"))).unwrap();
+        redirect_tx.send(done("first")).unwrap();
+        redirect_tx.send(RedirectMessage::RemoveThinker(session("first"))).unwrap();
+        redirect_tx
+            .send(notification("second", serde_json::json!({
+                "sessionUpdate": "tool_call",
+                "toolCallId": "call-1",
+                "title": "mcp__patchwork__do",
+            })))
+            .unwrap();
+        let (do_tx, _do_rx) = oneshot::channel();
+        redirect_tx
+            .send(RedirectMessage::IncomingMessage(PerSessionMessage::DoInvocation(DoArg { number: 0 }, do_tx)))
+            .unwrap();
+        redirect_tx.send(notification("second", chunk("two"))).unwrap();
+        redirect_tx.send(done("second")).unwrap();
+
+        let received = |message: PerSessionMessage| match message {
+            PerSessionMessage::SessionNotification(notification) => match notification.update {
+                SessionUpdate::AgentMessageChunk(chunk) => match chunk.content {
+                    ContentBlock::Text(text) => text.text,
+                    _ => "other content".to_string(),
+                },
+                SessionUpdate::ToolCall(_) => "tool call".to_string(),
+                _ => "other update".to_string(),
+            },
+            PerSessionMessage::DoInvocation(DoArg { number }, _) => format!("do {}", number),
+            PerSessionMessage::PromptResponse(session_id, _) => format!("done {}", session_id),
+        };
+        let mut first = Vec::new();
+        while let Some(message) = first_rx.recv().await {
+            first.push(received(message));
+        }
+        assert_eq!(first, ["one", "done first"]);
+        let mut second = Vec::new();
+        for _ in 0..4 {
+            second.push(received(second_rx.recv().await.unwrap()));
+        }
+        assert_eq!(second, ["tool call", "do 0", "two", "done second"]);
+    }
+
+    #[test]
+    fn test_disallowed_tools_are_denied_before_they_run() {
+        let (redirect_tx, _redirect_rx) = unbounded_channel();
+        let state = AgentState {
+            redirect_tx,
+            mcp_registry: McpServiceRegistry::default(),
+            allowed_tools: std::sync::Mutex::new(HashMap::new()),
+        };
+        let restricted: SessionId = serde_json::from_value(serde_json::json!("restricted")).unwrap();
+        state.allowed_tools.lock().unwrap().insert(restricted, vec!["read_file".to_string()]);
+        let request = |session: &str, tool: &str| -> RequestPermissionRequest {
+            serde_json::from_value(serde_json::json!({
+                "sessionId": session,
+                "toolCall": { "toolCallId": "call-1", "title": tool },
+                "options": [
+                    { "optionId": "allow", "name": "Allow", "kind": "allow_once" },
+                    { "optionId": "reject", "name": "Reject", "kind": "reject_once" },
+                ],
+            }))
+            .unwrap()
+        };
+
+        // The successor only runs a tool it asked about once the answer allows it
+        let denial = state.permission_denial(&request("restricted", "edit_file")).unwrap();
+        assert!(matches!(
+            denial.outcome,
+            RequestPermissionOutcome::Selected { option_id } if serde_json::to_value(&option_id).unwrap() == "reject"
+        ));
+        assert!(state.permission_denial(&request("restricted", "read_file")).is_none());
+        assert!(state.permission_denial(&request("unrestricted", "edit_file")).is_none());
+    }
+
+    #[test]
+    fn test_reported_tokens() {
+        let meta = serde_json::json!({ "usage": { "inputTokens": 120, "outputTokens": 30 } });
+        assert_eq!(reported_tokens(Some(&meta)), Some(150));
+        let meta = serde_json::json!({ "claudeCode": { "usage": { "totalTokens": 42 } } });
+        assert_eq!(reported_tokens(Some(&meta)), Some(42));
+        assert_eq!(reported_tokens(Some(&serde_json::json!({ "usage": {} }))), None);
+        assert_eq!(reported_tokens(None), None);
+    }
+
+    #[test]
+    fn test_tool_allowed() {
+        let tools = vec!["read_file".to_string(), "Grep".to_string()];
+        assert!(tool_allowed("read_file", &tools));
+        assert!(tool_allowed("grep", &tools));
+        assert!(tool_allowed("mcp__patchwork__do", &tools));
+        assert!(!tool_allowed("edit_file", &tools));
+        assert!(!tool_allowed("grep", &[]));
+        assert!(tool_restriction(&tools).ends_with("Use only these tools while answering: read_file, Grep."));
+    }
+
     #[test]
     fn test_augment_prompt_json() {
         let prompt = "Give me data";
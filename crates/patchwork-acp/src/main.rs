@@ -11,7 +11,8 @@ use std::sync::{Arc, Mutex};
 
 use sacp::schema::{
     ContentBlock, ContentChunk, Plan, PlanEntry, PlanEntryPriority, PlanEntryStatus,
-    PromptRequest, PromptResponse, SessionNotification, SessionUpdate, StopReason, TextContent,
+    PromptRequest, PromptResponse, RequestPermissionRequest, RequestPermissionResponse,
+    SessionNotification, SessionUpdate, StopReason, TextContent,
 };
 use sacp::{JrConnectionCx, JrHandlerChain, JrRequestCx};
 use sacp_proxy::{AcpProxyExt, JrCxExt, McpServiceRegistry};
@@ -20,12 +21,12 @@ use tokio_util::compat::{TokioAsyncReadCompatExt, TokioAsyncWriteCompatExt};
 use tracing_subscriber::EnvFilter;
 
 use patchwork_eval::{
-    AgentHandle, Error as EvalError, Interpreter,
-    PlanReporter, PlanUpdate as EvalPlanUpdate, PrintSink,
+    spawn_event_writer, AgentHandle, Error as EvalError, Interpreter, LogLevel,
+    OutputLine, OutputSink, PlanReporter, PlanUpdate as EvalPlanUpdate,
     ThoughtChunk as EvalThoughtChunk, ThoughtReporter,
 };
 
-use crate::agent::{PerSessionMessage, RedirectMessage};
+use crate::agent::{AgentState, PerSessionMessage, RedirectMessage};
 
 /// The Patchwork proxy state.
 struct PatchworkProxy {
@@ -35,6 +36,8 @@ struct PatchworkProxy {
     agent_handle: Option<AgentHandle>,
     /// Redirect channel for routing session notifications to think blocks.
     redirect_tx: Option<UnboundedSender<RedirectMessage>>,
+    /// The agent's state, for answering permission requests from think sessions.
+    agent_state: Option<Arc<AgentState>>,
 }
 
 impl PatchworkProxy {
@@ -43,6 +46,7 @@ impl PatchworkProxy {
             active_sessions: HashSet::new(),
             agent_handle: None,
             redirect_tx: None,
+            agent_state: None,
         }
     }
 
@@ -58,9 +62,15 @@ impl PatchworkProxy {
         self.active_sessions.remove(session_id);
     }
 
-    fn set_agent(&mut self, handle: AgentHandle, redirect_tx: UnboundedSender<RedirectMessage>) {
+    fn set_agent(
+        &mut self,
+        handle: AgentHandle,
+        redirect_tx: UnboundedSender<RedirectMessage>,
+        state: Arc<AgentState>,
+    ) {
         self.agent_handle = Some(handle);
         self.redirect_tx = Some(redirect_tx);
+        self.agent_state = Some(state);
     }
 
     fn agent_handle(&self) -> Option<AgentHandle> {
@@ -70,6 +80,10 @@ impl PatchworkProxy {
     fn redirect_tx(&self) -> Option<UnboundedSender<RedirectMessage>> {
         self.redirect_tx.clone()
     }
+
+    fn agent_state(&self) -> Option<Arc<AgentState>> {
+        self.agent_state.clone()
+    }
 }
 
 /// Check if a message appears to be Patchwork code or shell shorthand.
@@ -185,8 +199,8 @@ async fn run_patchwork_evaluation(
     agent_handle: Option<AgentHandle>,
     cx: JrRequestCx<PromptResponse>,
 ) -> Result<(), sacp::Error> {
-    // Create a channel for print output
-    let (print_tx, print_rx): (PrintSink, std::sync::mpsc::Receiver<String>) =
+    // Create a channel for print and log output
+    let (output_tx, output_rx): (OutputSink, std::sync::mpsc::Receiver<OutputLine>) =
         std::sync::mpsc::channel();
 
     // Create a channel for plan updates
@@ -197,20 +211,37 @@ async fn run_patchwork_evaluation(
     let (thought_tx, thought_rx): (ThoughtReporter, std::sync::mpsc::Receiver<EvalThoughtChunk>) =
         std::sync::mpsc::channel();
 
-    // Create interpreter with agent handle, print sink, plan reporter, and thought reporter
+    // Create interpreter with agent handle, output sink, plan reporter, and thought reporter
     let mut interp = match agent_handle {
         Some(handle) => Interpreter::with_agent(handle),
         None => Interpreter::new(),
     };
-    interp.set_print_sink(print_tx);
+    interp.set_output_sink(output_tx);
     interp.set_plan_reporter(plan_tx);
     interp.set_thought_reporter(thought_tx);
+    // The prompt is the user's own code, but the files it imports need trust
+    interp.require_trusted_modules();
+
+    // Stream runtime events to $PATCHWORK_EVENTS, as `patchwork run --events` does
+    let events_writer = match std::env::var("PATCHWORK_EVENTS") {
+        Ok(path) => match spawn_event_writer(&path) {
+            Ok((event_tx, writer)) => {
+                interp.set_event_sink(event_tx);
+                Some(writer)
+            }
+            Err(e) => {
+                tracing::warn!("Cannot write events to {}: {}", path, e);
+                None
+            }
+        },
+        Err(_) => None,
+    };
 
-    // Spawn a task to forward print messages as notifications
+    // Spawn a task to forward printed and logged lines as notifications
     let connection_cx = cx.connection_cx().clone();
-    let session_id_for_prints = session_id.clone();
-    let print_forwarder = tokio::task::spawn_blocking(move || {
-        forward_prints_to_notifications(print_rx, &connection_cx, &session_id_for_prints)
+    let session_id_for_output = session_id.clone();
+    let output_forwarder = tokio::task::spawn_blocking(move || {
+        forward_output_to_notifications(output_rx, &connection_cx, &session_id_for_output)
     });
 
     // Spawn a task to forward plan updates as notifications
@@ -233,9 +264,12 @@ async fn run_patchwork_evaluation(
         .map_err(|e| sacp::Error::internal_error().with_data(format!("Task error: {}", e)))?;
 
     // Wait for forwarders to complete (they will finish when channels are dropped)
-    let _ = print_forwarder.await;
+    let _ = output_forwarder.await;
     let _ = plan_forwarder.await;
     let _ = thought_forwarder.await;
+    if let Some(writer) = events_writer {
+        let _ = tokio::task::spawn_blocking(move || writer.join()).await;
+    }
 
     // End the evaluation regardless of result
     {
@@ -272,37 +306,49 @@ async fn run_patchwork_evaluation(
     Ok(())
 }
 
-/// Forward print messages from the interpreter to ACP notifications.
+/// Forward printed and logged lines from the interpreter to ACP notifications.
 ///
-/// This runs in a blocking context and sends each print as an AgentMessageChunk.
-fn forward_prints_to_notifications(
-    rx: std::sync::mpsc::Receiver<String>,
+/// This runs in a blocking context. Prints are sent as AgentMessageChunks.
+/// Debug and info logs are sent as AgentThoughtChunks, which clients show
+/// apart from the answer. Warnings and errors are sent as AgentMessageChunks
+/// set off as a quoted paragraph, so they aren't missed.
+fn forward_output_to_notifications(
+    rx: std::sync::mpsc::Receiver<OutputLine>,
     connection_cx: &JrConnectionCx,
     session_id: &str,
 ) {
-    while let Ok(message) = rx.recv() {
-        tracing::debug!("Forwarding print output: {}", message);
+    while let Ok(line) = rx.recv() {
+        tracing::debug!("Forwarding output: {}", line);
 
         let notification = SessionNotification {
             session_id: session_id.to_string().into(),
-            update: SessionUpdate::AgentMessageChunk(ContentChunk {
-                content: ContentBlock::Text(TextContent {
-                    annotations: None,
-                    text: message,
-                    meta: None,
-                }),
-                meta: None,
-            }),
+            update: output_update(line),
             meta: None,
         };
 
         if let Err(e) = connection_cx.send_notification(notification) {
-            tracing::warn!("Failed to send print notification: {}", e);
+            tracing::warn!("Failed to send output notification: {}", e);
             break;
         }
     }
 }
 
+/// The session update for one line of program output.
+fn output_update(line: OutputLine) -> SessionUpdate {
+    let chunk = |text: String| ContentChunk {
+        content: ContentBlock::Text(TextContent { annotations: None, text, meta: None }),
+        meta: None,
+    };
+    match line.level {
+        None => SessionUpdate::AgentMessageChunk(chunk(line.text)),
+        Some(level @ (LogLevel::Debug | LogLevel::Info)) => {
+            SessionUpdate::AgentThoughtChunk(chunk(format!("[{}] {}\n", level, line.text)))
+        }
+        Some(LogLevel::Warn) => SessionUpdate::AgentMessageChunk(chunk(format!("\n\n> **Warning:** {}\n\n", line.text))),
+        Some(LogLevel::Error) => SessionUpdate::AgentMessageChunk(chunk(format!("\n\n> **Error:** {}\n\n", line.text))),
+    }
+}
+
 /// Forward plan updates from the interpreter to ACP notifications.
 ///
 /// This runs in a blocking context and sends each plan update as a SessionUpdate::Plan.
@@ -412,6 +458,7 @@ async fn main() -> anyhow::Result<()> {
     // Build the handler chain
     let proxy_clone = Arc::clone(&proxy);
     let proxy_for_notifs = Arc::clone(&proxy);
+    let proxy_for_permissions = Arc::clone(&proxy);
     JrHandlerChain::new()
         .name("patchwork-acp")
         .on_receive_request(move |request: PromptRequest, cx: JrRequestCx<PromptResponse>| {
@@ -432,6 +479,17 @@ async fn main() -> anyhow::Result<()> {
                 Ok(())
             }
         })
+        // Deny think sessions the tools their blocks don't allow, before they
+        // run, and pass every other permission request on to the editor
+        .on_receive_request_from_successor({
+            async move |request: RequestPermissionRequest, cx: JrRequestCx<RequestPermissionResponse>| {
+                let state = proxy_for_permissions.lock().unwrap().agent_state();
+                match state.and_then(|state| state.permission_denial(&request)) {
+                    Some(denial) => cx.respond(denial),
+                    None => cx.connection_cx().send_request(request).forward_to_request_cx(cx),
+                }
+            }
+        })
         .provide_mcp(mcp_registry)
         .proxy()
         .connect_to(sacp::ByteStreams::new(
@@ -449,7 +507,7 @@ async fn main() -> anyhow::Result<()> {
                 // Store in proxy so handle_prompt can access it
                 {
                     let mut proxy = proxy_for_client.lock().unwrap();
-                    proxy.set_agent(agent_handle, redirect_tx);
+                    proxy.set_agent(agent_handle, redirect_tx, state.clone());
                 }
 
                 tracing::info!("Agent created, running main loop");
@@ -0,0 +1,80 @@
+//! Checking requests to the local servers (`patchwork serve`).
+//!
+//! The servers only listen on localhost, but any web page the user has open
+//! can send requests there too. So a request that calls a skill or changes a
+//! run has to carry the shared token from `$PATCHWORK_SERVE_TOKEN`:
+//!
+//! ```text
+//! Authorization: Bearer <token>
+//! Content-Type: application/json
+//! ```
+//!
+//! and its body has to be JSON. A browser won't send a JSON request to
+//! another origin without asking the server first, so a page can't get
+//! around the check with a plain form post.
+
+// Without a server, only the tests call it
+#![cfg_attr(not(any(feature = "triggers", feature = "dashboard")), allow(dead_code))]
+
+use std::env;
+
+/// Environment variable holding the token requests must carry.
+pub const TOKEN_VAR: &str = "PATCHWORK_SERVE_TOKEN";
+
+/// The token requests must carry; a server won't start without one.
+pub fn token() -> Result<String, String> {
+    match env::var(TOKEN_VAR) {
+        Ok(token) if !token.trim().is_empty() => Ok(token.trim().to_string()),
+        _ => Err(format!("Set ${} to the token requests must send as `Authorization: Bearer <token>`", TOKEN_VAR)),
+    }
+}
+
+/// Check a request's `Authorization` and `Content-Type` headers, refusing
+/// it with `401 Unauthorized` or `415 Unsupported Media Type`.
+pub fn check(token: &str, authorization: Option<&str>, content_type: Option<&str>) -> Result<(), (u16, String)> {
+    let given = authorization.and_then(|value| value.strip_prefix("Bearer ")).map(str::trim);
+    if !given.is_some_and(|given| same(given.as_bytes(), token.as_bytes())) {
+        return Err((401, format!("Send the token from ${} as `Authorization: Bearer <token>`", TOKEN_VAR)));
+    }
+    // Parameters like `; charset=utf-8` don't change the type
+    let media_type = content_type.and_then(|value| value.split(';').next()).map(str::trim);
+    if !media_type.is_some_and(|media_type| media_type.eq_ignore_ascii_case("application/json")) {
+        return Err((415, "Send the body as `Content-Type: application/json`".to_string()));
+    }
+    Ok(())
+}
+
+/// The response refusing a request that fails [`check`], if it does.
+#[cfg(any(feature = "triggers", feature = "dashboard"))]
+pub fn refusal(token: &str, headers: &axum::http::HeaderMap) -> Option<axum::response::Response> {
+    use axum::http::{header, HeaderName, StatusCode};
+    use axum::response::IntoResponse;
+
+    let text = |name: HeaderName| headers.get(name).and_then(|value| value.to_str().ok());
+    let (status, message) = check(token, text(header::AUTHORIZATION), text(header::CONTENT_TYPE)).err()?;
+    let status = StatusCode::from_u16(status).unwrap_or(StatusCode::BAD_REQUEST);
+    Some((status, message).into_response())
+}
+
+/// Compare without stopping at the first difference, so the time taken
+/// doesn't tell how much of a guess was right.
+fn same(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check_requires_token_and_json() {
+        let json = Some("application/json");
+        assert_eq!(check("s3cret", None, json).unwrap_err().0, 401);
+        assert_eq!(check("s3cret", Some("Bearer guess"), json).unwrap_err().0, 401);
+        assert_eq!(check("s3cret", Some("s3cret"), json).unwrap_err().0, 401);
+        assert_eq!(check("s3cret", Some("Bearer s3cret"), None).unwrap_err().0, 415);
+        assert_eq!(check("s3cret", Some("Bearer s3cret"), Some("text/plain")).unwrap_err().0, 415);
+        assert_eq!(check("s3cret", Some("Bearer s3cret"), json), Ok(()));
+        assert_eq!(check("s3cret", Some("Bearer s3cret"), Some("application/json; charset=utf-8")), Ok(()));
+    }
+}
@@ -0,0 +1,46 @@
+//! A stand-in agent that answers think blocks from a recorded trace.
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use patchwork_eval::{parse_trace, AgentHandle, ThinkRequest, ThinkResponse, TraceEntry, Value};
+use tokio::sync::mpsc;
+
+/// Start an agent on a background thread that answers each think block
+/// with the answer recorded for the same prompt in the trace at `path`,
+/// and return its handle.
+///
+/// A prompt the trace has no answer for, or a trace that can't be read,
+/// fails the block, so a fallback chain moves on to its next backend.
+pub fn spawn(path: PathBuf) -> AgentHandle {
+    let (tx, mut rx) = mpsc::unbounded_channel::<ThinkRequest>();
+    std::thread::spawn(move || {
+        let answers = load_answers(&path);
+        while let Some(request) = rx.blocking_recv() {
+            let result = match &answers {
+                Ok(answers) => answers
+                    .get(&request.prompt)
+                    .cloned()
+                    .ok_or_else(|| format!("{} has no answer for this prompt", path.display())),
+                Err(e) => Err(e.clone()),
+            };
+            let _ = request.response_tx.send(ThinkResponse::Complete { result });
+        }
+    });
+    AgentHandle::new(tx)
+}
+
+/// The answers a trace recorded, by prompt. Where a prompt was answered
+/// more than once, the last answer wins.
+fn load_answers(path: &Path) -> Result<HashMap<String, Value>, String> {
+    let text = fs::read_to_string(path).map_err(|e| format!("Error reading trace '{}': {}", path.display(), e))?;
+    let entries = parse_trace(&text).map_err(|e| format!("{}: {}", path.display(), e))?;
+    Ok(entries
+        .into_iter()
+        .filter_map(|entry| match entry {
+            TraceEntry::Think { prompt, result: Ok(value), .. } => Some((prompt, value)),
+            _ => None,
+        })
+        .collect())
+}
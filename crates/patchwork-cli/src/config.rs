@@ -0,0 +1,488 @@
+//! Project configuration from `patchwork.toml`.
+//!
+//! The config holds named run profiles, so the same program can run against a
+//! mock agent in CI and a real one locally:
+//!
+//! ```toml
+//! [profiles.ci]
+//! agent = "mock"          # or "cache:<trace.jsonl>", or a fallback chain
+//! mock_response = "ok"
+//! shell = ["git", "ls"]
+//! max_thinks = 20
+//! type_check = "error"
+//! variant = "random"      # or a variant name, or "env:VAR"
+//! edition = "2025"
+//! heartbeat = 30          # seconds between "still waiting" reports
+//! timeout = 600           # seconds any one command or think block may take
+//! log_level = "info"      # least severe `log.*` level shown
+//! ask_default = "skip"    # answer to ask blocks when there's no terminal
+//!
+//! [profiles.ci.config]    # overrides for the program's `config { ... }` block
+//! model = "haiku"
+//! ```
+//!
+//! An array of agents is a fallback chain: each think block goes to the
+//! first, and to the next whenever one fails, with a warning. A `cache:`
+//! agent answers from the think blocks recorded in a trace, resolved
+//! against the config file's directory, so it can stand in for a live
+//! agent that's down:
+//!
+//! ```toml
+//! [profiles.nightly]
+//! agent = ["cache:traces/nightly.jsonl", "mock"]
+//! ```
+//!
+//! Schedules for `patchwork schedule` live in `[schedules.<name>]` tables;
+//! see [`crate::schedule`]. Routes for `patchwork serve --triggers` map
+//! paths to the skills or functions they call:
+//!
+//! ```toml
+//! [triggers.http]
+//! "/rewrite" = "rewriting_git_branch"
+//! ```
+
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use patchwork_eval::{Edition, LogLevel, ShellPolicy, TypeCheckMode, Value, VariantPolicy};
+use serde::Deserialize;
+
+use crate::schedule::{Cron, Schedule};
+
+/// Name of the project config file.
+pub const CONFIG_FILE: &str = "patchwork.toml";
+
+/// Profile used when `--profile` isn't given, if the config defines it.
+pub const DEFAULT_PROFILE: &str = "default";
+
+/// Which agent answers think blocks.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub enum AgentBackend {
+    /// No agent: think blocks evaluate to placeholders.
+    #[default]
+    None,
+    /// Answer every think block with a canned response.
+    Mock,
+    /// Answer think blocks from the ones recorded in a trace file.
+    Cache(PathBuf),
+    /// Try each backend in turn, moving on to the next when one fails.
+    Fallback(Vec<AgentBackend>),
+}
+
+impl AgentBackend {
+    /// Parse one agent named in a profile.
+    fn parse(spec: &str) -> Result<AgentBackend, String> {
+        match spec {
+            "none" => Ok(AgentBackend::None),
+            "mock" => Ok(AgentBackend::Mock),
+            _ => match spec.strip_prefix("cache:") {
+                Some(path) if !path.is_empty() => Ok(AgentBackend::Cache(PathBuf::from(path))),
+                _ => Err(format!(
+                    "unsupported agent '{}' (the CLI supports \"none\", \"mock\" and \"cache:<trace.jsonl>\")",
+                    spec
+                )),
+            },
+        }
+    }
+
+    /// This backend with trace paths resolved against `dir`.
+    fn resolved(self, dir: &Path) -> AgentBackend {
+        match self {
+            AgentBackend::Cache(path) => AgentBackend::Cache(dir.join(path)),
+            AgentBackend::Fallback(chain) => {
+                AgentBackend::Fallback(chain.into_iter().map(|backend| backend.resolved(dir)).collect())
+            }
+            other => other,
+        }
+    }
+}
+
+/// Agents show as they're written in a profile, with chains in brackets.
+impl fmt::Display for AgentBackend {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AgentBackend::None => write!(f, "none"),
+            AgentBackend::Mock => write!(f, "mock"),
+            AgentBackend::Cache(path) => write!(f, "cache:{}", path.display()),
+            AgentBackend::Fallback(chain) => {
+                let names: Vec<String> = chain.iter().map(AgentBackend::to_string).collect();
+                write!(f, "[{}]", names.join(", "))
+            }
+        }
+    }
+}
+
+/// Settings for one run profile.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Profile {
+    pub agent: AgentBackend,
+    /// Response the mock agent gives to every think block.
+    pub mock_response: Option<String>,
+    pub shell: ShellPolicy,
+    /// Maximum number of think/ask blocks per run.
+    pub max_thinks: Option<usize>,
+    pub type_check: TypeCheckMode,
+    /// Which `variant` text think and ask blocks run with.
+    pub variant: VariantPolicy,
+    /// Language edition the program is written for.
+    pub edition: Edition,
+    /// Seconds between liveness reports while waiting on a command or the agent.
+    pub heartbeat: Option<u64>,
+    /// Seconds any one shell command or think block may take.
+    pub timeout: Option<u64>,
+    /// Least severe level of `log.*` lines to show; all of them if None.
+    pub log_level: Option<LogLevel>,
+    /// Values replacing fields of the program's `config` block.
+    pub config: HashMap<String, Value>,
+    /// Answer to `ask` blocks when there's no terminal to ask on.
+    pub ask_default: Option<String>,
+}
+
+/// The parts of `patchwork.toml` the CLI reads. Other tables are ignored.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct ConfigFile {
+    profiles: HashMap<String, ProfileTable>,
+    schedules: HashMap<String, ScheduleTable>,
+    triggers: TriggersTable,
+}
+
+/// A setting given as one string or a list of them.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(untagged)]
+enum OneOrMany {
+    One(String),
+    Many(Vec<String>),
+}
+
+/// A `[profiles.<name>]` table as written.
+#[derive(Debug, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct ProfileTable {
+    agent: Option<OneOrMany>,
+    mock_response: Option<String>,
+    shell: Option<OneOrMany>,
+    max_thinks: Option<usize>,
+    type_check: Option<String>,
+    variant: Option<String>,
+    edition: Option<String>,
+    heartbeat: Option<u64>,
+    timeout: Option<u64>,
+    log_level: Option<String>,
+    ask_default: Option<String>,
+    config: HashMap<String, toml::Value>,
+}
+
+/// A `[schedules.<name>]` table as written.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct ScheduleTable {
+    program: Option<PathBuf>,
+    profile: Option<String>,
+    cron: Option<String>,
+    watch: Option<OneOrMany>,
+    git: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct TriggersTable {
+    http: Option<HashMap<String, String>>,
+}
+
+/// Find the config file in `start` or the nearest ancestor that has one.
+pub fn find_config(start: &Path) -> Option<PathBuf> {
+    start
+        .ancestors()
+        .map(|dir| dir.join(CONFIG_FILE))
+        .find(|path| path.is_file())
+}
+
+/// Load the profile called `name` from the config file at `path`.
+pub fn load_profile(path: &Path, name: &str) -> Result<Profile, String> {
+    let mut config = read_config(path)?;
+    let table = config
+        .profiles
+        .remove(name)
+        .ok_or_else(|| format!("{}: no profile named '{}'", path.display(), name))?;
+    let mut profile =
+        profile_from_table(table).map_err(|e| format!("{}: profile '{}': {}", path.display(), name, e))?;
+    profile.agent = profile.agent.resolved(path.parent().unwrap_or(Path::new(".")));
+    Ok(profile)
+}
+
+/// Load the HTTP trigger routes from the config file at `path`, as
+/// `(route, callable)` pairs in route order.
+pub fn load_http_triggers(path: &Path) -> Result<Vec<(String, String)>, String> {
+    let table = read_config(path)?
+        .triggers
+        .http
+        .ok_or_else(|| format!("{}: no [triggers.http] routes", path.display()))?;
+    if let Some(route) = table.keys().find(|route| !route.starts_with('/')) {
+        return Err(format!("{}: route '{}' must start with '/'", path.display(), route));
+    }
+    let mut routes: Vec<(String, String)> = table.into_iter().collect();
+    routes.sort();
+    Ok(routes)
+}
+
+/// Whether the config at `path` defines the profile `name`.
+pub fn has_profile(path: &Path, name: &str) -> bool {
+    read_config(path).is_ok_and(|config| config.profiles.contains_key(name))
+}
+
+/// Load every schedule the config file at `path` defines, by name.
+pub fn load_schedules(path: &Path) -> Result<Vec<Schedule>, String> {
+    let mut schedules = read_config(path)?
+        .schedules
+        .into_iter()
+        .map(|(name, table)| {
+            schedule_from_table(&name, table)
+                .map_err(|e| format!("{}: schedule '{}': {}", path.display(), name, e))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    schedules.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(schedules)
+}
+
+fn read_config(path: &Path) -> Result<ConfigFile, String> {
+    let text = fs::read_to_string(path)
+        .map_err(|e| format!("Error reading {}: {}", path.display(), e))?;
+    toml::from_str(&text).map_err(|e| format!("{}: {}", path.display(), e))
+}
+
+fn schedule_from_table(name: &str, table: ScheduleTable) -> Result<Schedule, String> {
+    let schedule = Schedule {
+        name: name.to_string(),
+        program: table.program.ok_or("missing `program`")?,
+        profile: table.profile,
+        cron: table.cron.as_deref().map(Cron::parse).transpose()?,
+        watch: match table.watch {
+            Some(OneOrMany::One(path)) => vec![PathBuf::from(path)],
+            Some(OneOrMany::Many(paths)) => paths.iter().map(PathBuf::from).collect(),
+            None => Vec::new(),
+        },
+        git: table.git,
+    };
+    if schedule.cron.is_none() && schedule.watch.is_empty() && schedule.git.is_none() {
+        return Err("needs a `cron`, `watch` or `git` trigger".to_string());
+    }
+    Ok(schedule)
+}
+
+fn profile_from_table(table: ProfileTable) -> Result<Profile, String> {
+    let mut profile = Profile {
+        mock_response: table.mock_response,
+        max_thinks: table.max_thinks,
+        ask_default: table.ask_default,
+        config: table.config.into_iter().map(|(key, value)| (key, to_value(value))).collect(),
+        ..Profile::default()
+    };
+    match table.agent {
+        Some(OneOrMany::One(agent)) => profile.agent = AgentBackend::parse(&agent)?,
+        Some(OneOrMany::Many(agents)) => {
+            if agents.is_empty() {
+                return Err("agent chain is empty".to_string());
+            }
+            let chain = agents.iter().map(|agent| AgentBackend::parse(agent)).collect::<Result<Vec<_>, _>>()?;
+            // Without an agent, think blocks get placeholders; nothing can fall through to that
+            if chain.contains(&AgentBackend::None) {
+                return Err("\"none\" can't be part of an agent chain".to_string());
+            }
+            profile.agent = AgentBackend::Fallback(chain);
+        }
+        None => {}
+    }
+    match table.shell {
+        Some(OneOrMany::One(policy)) => {
+            profile.shell = match policy.as_str() {
+                "allow" => ShellPolicy::Allow,
+                "deny" => ShellPolicy::Deny,
+                other => return Err(format!("unknown shell policy '{}'", other)),
+            }
+        }
+        Some(OneOrMany::Many(commands)) => profile.shell = ShellPolicy::AllowList(commands),
+        None => {}
+    }
+    if let Some(mode) = table.type_check {
+        profile.type_check = match mode.as_str() {
+            "off" => TypeCheckMode::Off,
+            "warn" => TypeCheckMode::Warn,
+            "error" => TypeCheckMode::Error,
+            other => return Err(format!("unknown type_check mode '{}'", other)),
+        }
+    }
+    if let Some(policy) = table.variant {
+        profile.variant = match policy.as_str() {
+            "default" => VariantPolicy::Default,
+            "random" => VariantPolicy::Random,
+            other => match other.strip_prefix("env:") {
+                Some(var) => VariantPolicy::Env(var.to_string()),
+                None => VariantPolicy::Fixed(other.to_string()),
+            },
+        }
+    }
+    for (key, secs) in [("heartbeat", table.heartbeat), ("timeout", table.timeout)] {
+        if secs == Some(0) {
+            return Err(format!("`{}` must be at least one second", key));
+        }
+    }
+    profile.heartbeat = table.heartbeat;
+    profile.timeout = table.timeout;
+    if let Some(name) = table.log_level {
+        profile.log_level = Some(LogLevel::parse(&name).ok_or_else(|| format!("unknown log level '{}'", name))?);
+    }
+    if let Some(year) = table.edition {
+        profile.edition = Edition::parse(&year).ok_or_else(|| format!("unknown edition '{}'", year))?;
+    }
+    Ok(profile)
+}
+
+/// A `config` override as the program sees it.
+fn to_value(value: toml::Value) -> Value {
+    match value {
+        toml::Value::String(s) => Value::String(s),
+        toml::Value::Integer(n) => Value::Number(n as f64),
+        toml::Value::Float(n) => Value::Number(n),
+        toml::Value::Boolean(b) => Value::Boolean(b),
+        toml::Value::Datetime(date) => Value::String(date.to_string()),
+        toml::Value::Array(items) => Value::Array(items.into_iter().map(to_value).collect()),
+        toml::Value::Table(table) => Value::Object(table.into_iter().map(|(key, value)| (key, to_value(value))).collect()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_load_profile() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(CONFIG_FILE);
+        fs::write(
+            &path,
+            r#"
+# Local runs use the defaults
+[profiles.default]
+
+[profiles.ci]
+agent = "mock"          # never call a real model in CI
+mock_response = "{\"ok\": true}"
+shell = ["git", "ls"]
+max_thinks = 20
+type_check = "warn"
+variant = "env:PROMPT_VARIANT"
+edition = "2025"
+heartbeat = 30
+timeout = 600
+log_level = "warn"
+ask_default = "skip"
+
+[profiles.ci.config]
+model = "haiku"
+max_retries = 1
+
+[profiles.nightly]
+agent = ["cache:traces/last.jsonl", "mock"]
+"#,
+        )
+        .unwrap();
+
+        let nested = dir.path().join("src");
+        fs::create_dir(&nested).unwrap();
+        assert_eq!(find_config(&nested), Some(path.clone()));
+
+        let profile = load_profile(&path, "ci").unwrap();
+        assert_eq!(
+            profile,
+            Profile {
+                agent: AgentBackend::Mock,
+                mock_response: Some(r#"{"ok": true}"#.to_string()),
+                shell: ShellPolicy::AllowList(vec!["git".to_string(), "ls".to_string()]),
+                max_thinks: Some(20),
+                type_check: TypeCheckMode::Warn,
+                variant: VariantPolicy::Env("PROMPT_VARIANT".to_string()),
+                edition: Edition::E2025,
+                heartbeat: Some(30),
+                timeout: Some(600),
+                log_level: Some(LogLevel::Warn),
+                config: [
+                    ("model".to_string(), Value::String("haiku".to_string())),
+                    ("max_retries".to_string(), Value::Number(1.0)),
+                ]
+                .into(),
+                ask_default: Some("skip".to_string()),
+            }
+        );
+        assert_eq!(load_profile(&path, "default").unwrap(), Profile::default());
+        assert_eq!(
+            load_profile(&path, "nightly").unwrap().agent,
+            AgentBackend::Fallback(vec![AgentBackend::Cache(dir.path().join("traces/last.jsonl")), AgentBackend::Mock])
+        );
+        assert!(load_profile(&path, "prod").is_err());
+    }
+
+    #[test]
+    fn test_load_schedules() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(CONFIG_FILE);
+        fs::write(
+            &path,
+            r#"
+[profiles.ci]
+agent = "mock"
+
+[schedules.nightly]
+program = "release.pw"
+cron = "0 3 * * 1-5"
+profile = "ci"
+
+[schedules.docs]
+program = "docs.pw"
+watch = ["docs", "README.md"]
+git = "main"
+"#,
+        )
+        .unwrap();
+        let schedules = load_schedules(&path).unwrap();
+        let names: Vec<&str> = schedules.iter().map(|s| s.name.as_str()).collect();
+        assert_eq!(names, ["docs", "nightly"]);
+        assert_eq!(schedules[0].watch, [PathBuf::from("docs"), PathBuf::from("README.md")]);
+        assert_eq!(schedules[0].git.as_deref(), Some("main"));
+        assert_eq!(schedules[1].cron, Some(Cron::parse("0 3 * * 1-5").unwrap()));
+        assert_eq!(schedules[1].profile.as_deref(), Some("ci"));
+
+        fs::write(&path, "[schedules.idle]\nprogram = \"idle.pw\"\n").unwrap();
+        assert!(load_schedules(&path).unwrap_err().contains("needs a `cron`, `watch` or `git` trigger"));
+        fs::write(&path, "[schedules.bad]\nprogram = \"x.pw\"\ncron = \"every day\"\n").unwrap();
+        assert!(load_schedules(&path).unwrap_err().contains("schedule 'bad': cron spec"));
+    }
+
+    #[test]
+    fn test_load_http_triggers() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(CONFIG_FILE);
+        fs::write(&path, "[triggers.http]\n\"/rewrite\" = \"rewriting_git_branch\"\n\"/ping\" = \"ping\"\n").unwrap();
+        assert_eq!(
+            load_http_triggers(&path).unwrap(),
+            [
+                ("/ping".to_string(), "ping".to_string()),
+                ("/rewrite".to_string(), "rewriting_git_branch".to_string())
+            ]
+        );
+        fs::write(&path, "[triggers.http]\nrewrite = \"rewriting_git_branch\"\n").unwrap();
+        assert!(load_http_triggers(&path).unwrap_err().contains("must start with '/'"));
+    }
+
+    #[test]
+    fn test_rejects_unknown_settings() {
+        let profile = |text: &str| profile_from_table(toml::from_str(text).map_err(|e| e.to_string())?);
+        assert!(profile("agent = \"gpt\"").is_err());
+        assert!(profile("colour = true").unwrap_err().contains("unknown field `colour`"));
+        assert!(profile("model = \"haiku\"").unwrap_err().contains("unknown field `model`"));
+        assert!(profile("heartbeat = 0").unwrap_err().contains("at least one second"));
+        assert!(profile("agent = [\"mock\", \"none\"]").unwrap_err().contains("can't be part of an agent chain"));
+    }
+}
@@ -0,0 +1,409 @@
+//! A local web dashboard for watching runs (`patchwork serve --dashboard`).
+//!
+//! The dashboard follows runs through the files they leave in a runs
+//! directory, `.patchwork/runs` unless `--runs` says otherwise: `<name>.jsonl`
+//! is a run's event stream and `<name>.control` its control file. A run
+//! started with
+//!
+//! ```text
+//! patchwork run deploy.pw --events .patchwork/runs/deploy.jsonl \
+//!     --control .patchwork/runs/deploy.control
+//! ```
+//!
+//! shows up as `deploy`. The page lists each run's status, plan, and what it
+//! has done so far, tails its log, and pauses, resumes, or aborts it by
+//! writing its control file; resuming is how an operator approves a run
+//! held at a gate.
+//!
+//! Changing a run takes the same token as the trigger server (see
+//! [`crate::auth`]). The server prints the page's address with the token in
+//! its fragment, `#token=...`, and the page sends it with each command as
+//! `{"command": "pause"}`.
+//!
+//! Everything but the HTTP server is plain code. The server needs the
+//! `dashboard` feature.
+
+// Without the server, only the tests read the runs
+#![cfg_attr(not(feature = "dashboard"), allow(dead_code))]
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+use patchwork_eval::Value;
+
+/// Where runs leave their files unless `--runs` is given.
+pub const DEFAULT_RUNS_DIR: &str = ".patchwork/runs";
+
+/// Port the dashboard listens on, on localhost only, unless `--port` is given.
+pub const DEFAULT_PORT: u16 = 7878;
+
+/// How recently a run must have written an event to count as running.
+const ACTIVE_WINDOW: Duration = Duration::from_secs(60);
+
+/// What a run has left in the runs directory.
+struct Run {
+    name: String,
+    events: Vec<Value>,
+    /// The command in the run's control file, if it has one.
+    control: Option<String>,
+    modified: Option<SystemTime>,
+}
+
+/// Names of the runs in `dir`, sorted.
+fn run_names(dir: &Path) -> Result<Vec<String>, String> {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(format!("Error reading {}: {}", dir.display(), e)),
+    };
+    let mut names: Vec<String> = entries
+        .filter_map(|entry| entry.ok()?.file_name().to_str()?.strip_suffix(".jsonl").map(str::to_string))
+        .collect();
+    names.sort();
+    Ok(names)
+}
+
+/// A run name from a URL, refused if it could reach outside the runs directory.
+fn checked_name(name: &str) -> Result<&str, String> {
+    if name.is_empty() || name.starts_with('.') || name.contains(['/', '\\']) {
+        return Err(format!("Invalid run name: {}", name));
+    }
+    Ok(name)
+}
+
+fn load_run(dir: &Path, name: &str) -> Result<Run, String> {
+    let path = dir.join(format!("{}.jsonl", checked_name(name)?));
+    let text = fs::read_to_string(&path).map_err(|e| format!("Error reading {}: {}", path.display(), e))?;
+    // The last line may be half written
+    let events = text.lines().filter_map(|line| Value::from_json(line).ok()).collect();
+    let control = fs::read_to_string(dir.join(format!("{}.control", name))).ok().map(|c| c.trim().to_string());
+    let modified = fs::metadata(&path).and_then(|meta| meta.modified()).ok();
+    Ok(Run { name: name.to_string(), events, control, modified })
+}
+
+fn field<'a>(event: &'a Value, name: &str) -> Option<&'a Value> {
+    match event {
+        Value::Object(fields) => fields.get(name),
+        _ => None,
+    }
+}
+
+fn text_field(event: &Value, name: &str) -> String {
+    field(event, name).map(Value::to_string_value).unwrap_or_default()
+}
+
+fn object(fields: Vec<(&str, Value)>) -> Value {
+    Value::Object(fields.into_iter().map(|(key, value)| (key.to_string(), value)).collect())
+}
+
+/// The run as the dashboard lists it.
+fn summary(run: &Run, now: SystemTime) -> Value {
+    let count = |kind: &str| run.events.iter().filter(|event| text_field(event, "event") == kind).count();
+    let last = |kind: &str| run.events.iter().rev().find(|event| text_field(event, "event") == kind);
+    // Exceptions without a thrown value are the run failing
+    let failure =
+        run.events.iter().find(|event| text_field(event, "event") == "exception" && field(event, "value").is_none());
+    let recent = run.modified.and_then(|m| now.duration_since(m).ok()).is_some_and(|age| age < ACTIVE_WINDOW);
+    let status = match (run.control.as_deref(), failure) {
+        (_, Some(_)) => "failed",
+        (Some("pause"), _) => "paused",
+        _ if recent => "running",
+        _ => "idle",
+    };
+    // The runtime doesn't see token counts, so prompt size stands in for them
+    let prompt_chars: usize = run
+        .events
+        .iter()
+        .filter(|event| text_field(event, "event") == "think_request")
+        .map(|event| text_field(event, "prompt").chars().count())
+        .sum();
+
+    object(vec![
+        ("name", Value::String(run.name.clone())),
+        ("status", Value::String(status.to_string())),
+        ("statement", last("statement_started").map(|e| Value::String(text_field(e, "text"))).unwrap_or(Value::Null)),
+        ("plan", last("plan_update").and_then(|e| field(e, "entries")).cloned().unwrap_or(Value::Array(Vec::new()))),
+        ("statements", Value::Number(count("statement_started") as f64)),
+        ("shell_commands", Value::Number(count("shell_exec") as f64)),
+        ("think_requests", Value::Number(count("think_request") as f64)),
+        ("prompt_chars", Value::Number(prompt_chars as f64)),
+        ("exceptions", Value::Number(count("exception") as f64)),
+        ("error", failure.map(|e| Value::String(text_field(e, "message"))).unwrap_or(Value::Null)),
+    ])
+}
+
+/// Log lines for the run's events from index `from` on, and the index to
+/// ask for next.
+fn log(run: &Run, from: usize) -> Value {
+    let lines = run
+        .events
+        .iter()
+        .skip(from)
+        .filter_map(|event| {
+            let text = match text_field(event, "event").as_str() {
+                "print" => text_field(event, "text"),
+                "log" => format!("[{}] {}", text_field(event, "level"), text_field(event, "text")),
+                "shell_exec" => {
+                    let mut line = format!("$ {}", text_field(event, "command"));
+                    if let Some(Value::Array(args)) = field(event, "args") {
+                        for arg in args {
+                            line.push(' ');
+                            line.push_str(&arg.to_string_value());
+                        }
+                    }
+                    if let Some(err) = field(event, "err") {
+                        line.push_str(&format!("  (failed: {})", err.to_string_value()));
+                    }
+                    line
+                }
+                "think_request" => {
+                    let prompt = text_field(event, "prompt");
+                    format!("think: {}", prompt.trim().lines().next().unwrap_or_default())
+                }
+                "exception" => format!("exception: {}", text_field(event, "message")),
+                _ => return None,
+            };
+            Some(object(vec![
+                ("time", field(event, "time").cloned().unwrap_or(Value::Null)),
+                ("text", Value::String(text)),
+            ]))
+        })
+        .collect();
+    object(vec![("next", Value::Number(run.events.len() as f64)), ("lines", Value::Array(lines))])
+}
+
+/// The command in a control request's body, `{"command": <command>}`.
+fn command(body: &str) -> Result<String, String> {
+    match Value::from_json(body)? {
+        Value::Object(fields) => match fields.get("command") {
+            Some(Value::String(command)) => Ok(command.clone()),
+            _ => Err("Expected a \"command\" string in the body".to_string()),
+        },
+        _ => Err("Expected a JSON object with a \"command\"".to_string()),
+    }
+}
+
+/// Write an operator command to a run's control file.
+fn control(dir: &Path, name: &str, command: &str) -> Result<(), String> {
+    let command = command.trim();
+    if !matches!(command, "pause" | "resume" | "abort") {
+        return Err(format!("Unknown control command: {}", command));
+    }
+    let path = dir.join(format!("{}.control", checked_name(name)?));
+    fs::write(&path, format!("{}\n", command)).map_err(|e| format!("Error writing {}: {}", path.display(), e))
+}
+
+/// Summaries of every run in `dir`, as JSON.
+fn runs_json(dir: &Path) -> Result<String, String> {
+    let now = SystemTime::now();
+    let runs = run_names(dir)?
+        .iter()
+        .map(|name| load_run(dir, name).map(|run| summary(&run, now)))
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(Value::Array(runs).to_json())
+}
+
+fn log_json(dir: &Path, name: &str, from: usize) -> Result<String, String> {
+    Ok(log(&load_run(dir, name)?, from).to_json())
+}
+
+/// Serve the dashboard for the runs in `dir` on localhost until interrupted.
+#[cfg(feature = "dashboard")]
+pub fn serve(dir: PathBuf, port: u16) -> Result<(), String> {
+    server::serve(dir, port)
+}
+
+#[cfg(not(feature = "dashboard"))]
+pub fn serve(_dir: PathBuf, _port: u16) -> Result<(), String> {
+    Err("The dashboard isn't available; build patchwork with the `dashboard` feature".to_string())
+}
+
+#[cfg(feature = "dashboard")]
+mod server {
+    use std::collections::HashMap;
+    use std::path::PathBuf;
+    use std::sync::Arc;
+
+    use axum::extract::{Path, Query, State};
+    use axum::http::{header, HeaderMap, StatusCode};
+    use axum::response::{Html, IntoResponse, Response};
+    use axum::routing::{get, post};
+    use axum::Router;
+
+    use crate::auth;
+
+    /// The runs directory and the token commands must carry
+    type Shared = Arc<(PathBuf, String)>;
+
+    pub(super) fn serve(dir: PathBuf, port: u16) -> Result<(), String> {
+        let token = auth::token()?;
+        let runtime = tokio::runtime::Builder::new_multi_thread()
+            .enable_all()
+            .build()
+            .map_err(|e| format!("Error starting the dashboard: {}", e))?;
+        runtime.block_on(async move {
+            let listener = tokio::net::TcpListener::bind(("127.0.0.1", port))
+                .await
+                .map_err(|e| format!("Error listening on port {}: {}", port, e))?;
+            eprintln!("Dashboard for {} at http://127.0.0.1:{}/#token={}", dir.display(), port, token);
+            let app = Router::new()
+                .route("/", get(|| async { Html(super::PAGE) }))
+                .route("/api/runs", get(runs))
+                .route("/api/runs/:name/log", get(log))
+                .route("/api/runs/:name/control", post(control))
+                .with_state(Arc::new((dir, token)));
+            axum::serve(listener, app).await.map_err(|e| format!("Dashboard error: {}", e))
+        })
+    }
+
+    fn reply(result: Result<String, String>) -> Response {
+        match result {
+            Ok(json) => ([(header::CONTENT_TYPE, "application/json")], json).into_response(),
+            Err(message) => (StatusCode::BAD_REQUEST, message).into_response(),
+        }
+    }
+
+    async fn runs(State(shared): State<Shared>) -> Response {
+        reply(super::runs_json(&shared.0))
+    }
+
+    async fn log(
+        State(shared): State<Shared>,
+        Path(name): Path<String>,
+        Query(query): Query<HashMap<String, String>>,
+    ) -> Response {
+        let from = query.get("from").and_then(|from| from.parse().ok()).unwrap_or(0);
+        reply(super::log_json(&shared.0, &name, from))
+    }
+
+    async fn control(State(shared): State<Shared>, Path(name): Path<String>, headers: HeaderMap, body: String) -> Response {
+        let (dir, token) = &*shared;
+        if let Some(refused) = auth::refusal(token, &headers) {
+            return refused;
+        }
+        reply(super::command(&body).and_then(|command| super::control(dir, &name, &command)).map(|_| "{}".to_string()))
+    }
+}
+
+/// The dashboard page. It polls the JSON endpoints once a second.
+#[cfg(feature = "dashboard")]
+const PAGE: &str = r#"<!doctype html>
+<html>
+<head>
+<meta charset="utf-8">
+<title>patchwork runs</title>
+<style>
+  body { font: 14px system-ui, sans-serif; margin: 2em; color: #222; }
+  table { border-collapse: collapse; width: 100%; }
+  th, td { text-align: left; padding: 4px 8px; border-bottom: 1px solid #ddd; vertical-align: top; }
+  tr.selected { background: #eef4ff; }
+  .failed { color: #b00; } .paused { color: #a60; } .running { color: #070; } .idle { color: #888; }
+  ul.plan { margin: 0; padding-left: 1.2em; }
+  li.completed { text-decoration: line-through; color: #888; } li.in_progress { font-weight: bold; }
+  pre { background: #f6f6f6; padding: 1em; height: 24em; overflow: auto; }
+</style>
+</head>
+<body>
+<h1>Runs</h1>
+<table>
+  <thead><tr><th>Run</th><th>Status</th><th>Now at</th><th>Plan</th><th>Commands</th><th>Thinks (prompt chars)</th><th></th></tr></thead>
+  <tbody id="runs"></tbody>
+</table>
+<h2 id="log-title"></h2>
+<pre id="log"></pre>
+<script>
+let selected = null, next = 0;
+const token = new URLSearchParams(location.hash.slice(1)).get("token");
+const esc = s => String(s ?? "").replace(/[&<>"]/g, c => ({"&": "&amp;", "<": "&lt;", ">": "&gt;", '"': "&quot;"}[c]));
+async function send(name, command) {
+  const response = await fetch(`/api/runs/${encodeURIComponent(name)}/control`, {
+    method: "POST",
+    headers: {"Authorization": `Bearer ${token}`, "Content-Type": "application/json"},
+    body: JSON.stringify({command}),
+  });
+  if (!response.ok) alert(await response.text());
+  refresh();
+}
+function select(name) {
+  selected = name; next = 0;
+  document.getElementById("log").textContent = "";
+  document.getElementById("log-title").textContent = `Log: ${name}`;
+  refresh();
+}
+async function refresh() {
+  const runs = await (await fetch("/api/runs")).json();
+  document.getElementById("runs").innerHTML = runs.map(run => `
+    <tr class="${run.name === selected ? "selected" : ""}" onclick="select('${esc(run.name)}')">
+      <td>${esc(run.name)}</td>
+      <td class="${run.status}">${run.status}${run.error ? `<br>${esc(run.error)}` : ""}</td>
+      <td><code>${esc(run.statement)}</code></td>
+      <td><ul class="plan">${run.plan.map(e => `<li class="${e.status}">${esc(e.content)}</li>`).join("")}</ul></td>
+      <td>${run.shell_commands}</td>
+      <td>${run.think_requests} (${run.prompt_chars})</td>
+      <td onclick="event.stopPropagation()">${run.status === "paused"
+        ? `<button onclick="send('${esc(run.name)}', 'resume')">Approve</button>`
+        : `<button onclick="send('${esc(run.name)}', 'pause')">Pause</button>`}
+        <button onclick="send('${esc(run.name)}', 'abort')">Abort</button></td>
+    </tr>`).join("");
+  if (selected) {
+    const log = await (await fetch(`/api/runs/${encodeURIComponent(selected)}/log?from=${next}`)).json();
+    next = log.next;
+    const pre = document.getElementById("log");
+    pre.textContent += log.lines.map(l => l.text + "\n").join("");
+    pre.scrollTop = pre.scrollHeight;
+  }
+}
+refresh();
+setInterval(refresh, 1000);
+</script>
+</body>
+</html>
+"#;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_run_summary_and_control() {
+        let dir = tempfile::tempdir().unwrap();
+        let events = [
+            r#"{"event":"statement_started","line":2,"text":"$ cargo build","time":1}"#,
+            r#"{"args":["build"],"command":"cargo","event":"shell_exec","ok":"","time":2}"#,
+            r#"{"entries":[{"content":"deploy","status":"in_progress"}],"event":"plan_update","time":3}"#,
+            r#"{"ask":false,"event":"think_request","prompt":"Summarize\nthe build","time":4}"#,
+            r#"{"event":"print","text":"built","time":5}"#,
+        ];
+        fs::write(dir.path().join("deploy.jsonl"), events.join("\n")).unwrap();
+        fs::write(dir.path().join("notes.txt"), "not a run").unwrap();
+        assert_eq!(run_names(dir.path()).unwrap(), ["deploy"]);
+
+        let run = load_run(dir.path(), "deploy").unwrap();
+        let Value::Object(fields) = summary(&run, SystemTime::now()) else { panic!("Expected an object") };
+        assert_eq!(fields["status"], Value::String("running".to_string()));
+        assert_eq!(fields["statement"], Value::String("$ cargo build".to_string()));
+        assert_eq!(fields["prompt_chars"], Value::Number(19.0));
+        let later = SystemTime::now() + 2 * ACTIVE_WINDOW;
+        let Value::Object(fields) = summary(&run, later) else { panic!("Expected an object") };
+        assert_eq!(fields["status"], Value::String("idle".to_string()));
+
+        let Value::Object(tail) = log(&run, 3) else { panic!("Expected an object") };
+        assert_eq!(tail["next"], Value::Number(5.0));
+        let texts: Vec<String> = match &tail["lines"] {
+            Value::Array(lines) => lines.iter().map(|line| text_field(line, "text")).collect(),
+            other => panic!("Expected lines, got {:?}", other),
+        };
+        assert_eq!(texts, ["think: Summarize", "built"]);
+
+        control(dir.path(), "deploy", "pause").unwrap();
+        let run = load_run(dir.path(), "deploy").unwrap();
+        let Value::Object(fields) = summary(&run, SystemTime::now()) else { panic!("Expected an object") };
+        assert_eq!(fields["status"], Value::String("paused".to_string()));
+        assert_eq!(command(r#"{"command": "resume"}"#).unwrap(), "resume");
+        assert!(command("resume").is_err());
+        assert!(command(r#"{"command": 1}"#).is_err());
+        assert!(control(dir.path(), "deploy", "rm -rf").is_err());
+        assert!(control(dir.path(), "../elsewhere", "pause").is_err());
+    }
+}
@@ -0,0 +1,204 @@
+//! Differential runs: the same program against two think backends.
+//!
+//! `patchwork diff main.pw <left> <right>` runs the program once per side and
+//! compares what each think block answered, in order, and the final results.
+//! A side is a run profile from `patchwork.toml`, or a trace recorded with
+//! `--trace` to compare a cached run against a live one.
+
+use std::path::Path;
+
+use patchwork_eval::{TraceEntry, Value};
+
+use crate::config::AgentBackend;
+
+/// What answers think blocks on one side of the comparison.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Backend {
+    Profile(String),
+    /// A recorded trace, replayed in place of the agent and shell.
+    Trace(String),
+}
+
+impl Backend {
+    /// A `.jsonl` file names a trace; anything else names a profile.
+    pub fn parse(arg: &str) -> Self {
+        if arg.ends_with(".jsonl") && Path::new(arg).is_file() {
+            Backend::Trace(arg.to_string())
+        } else {
+            Backend::Profile(arg.to_string())
+        }
+    }
+}
+
+/// The think answers and final result of one run.
+#[derive(Debug)]
+pub struct Outcome {
+    pub thinks: Vec<(String, Result<Value, String>)>,
+    pub result: Result<Value, String>,
+}
+
+/// Run `code` against `backend`, importing only trusted modules unless
+/// `yes`.
+pub fn run_side(code: &str, backend: &Backend, yes: bool) -> Result<Outcome, String> {
+    match backend {
+        Backend::Profile(name) => {
+            let profile = crate::resolve_profile(Some(name))?;
+            let mut interpreter = crate::interpreter_for(&profile);
+            if !yes {
+                crate::require_trusted_modules(&mut interpreter, true);
+            }
+            interpreter.runtime_mut().start_recording();
+            let result = interpreter.eval(code).map_err(|e| e.to_string());
+            let trace = interpreter.runtime_mut().take_trace();
+            Ok(Outcome { thinks: thinks_in(&trace), result })
+        }
+        Backend::Trace(path) => {
+            let trace = crate::read_trace(path)?;
+            let mut profile = crate::resolve_profile(None)?;
+            profile.agent = AgentBackend::None;
+            let mut interpreter = crate::interpreter_for(&profile);
+            interpreter.runtime_mut().start_replay(trace.clone());
+            let result = interpreter.eval(code).map_err(|e| e.to_string());
+            Ok(Outcome { thinks: thinks_in(&trace), result })
+        }
+    }
+}
+
+fn thinks_in(trace: &[TraceEntry]) -> Vec<(String, Result<Value, String>)> {
+    trace
+        .iter()
+        .filter_map(|entry| match entry {
+            TraceEntry::Think { prompt, result, .. } => Some((prompt.clone(), result.clone())),
+            TraceEntry::Shell { .. } | TraceEntry::Task { .. } => None,
+        })
+        .collect()
+}
+
+/// Describe every difference between two runs, one per line.
+pub fn compare(left: &Outcome, right: &Outcome) -> Vec<String> {
+    let mut differences = Vec::new();
+
+    for (index, ((left_prompt, left), (right_prompt, right))) in
+        left.thinks.iter().zip(&right.thinks).enumerate()
+    {
+        let label = format!("think #{}", index + 1);
+        if left_prompt != right_prompt {
+            // Usually fallout from an earlier answer that was interpolated
+            differences.push(format!("{}: prompts differ", label));
+        }
+        diff_results(&label, left, right, &mut differences);
+    }
+    if left.thinks.len() != right.thinks.len() {
+        differences.push(format!(
+            "left ran {} think block(s), right ran {}",
+            left.thinks.len(),
+            right.thinks.len()
+        ));
+    }
+
+    diff_results("result", &left.result, &right.result, &mut differences);
+    differences
+}
+
+fn diff_results(
+    label: &str,
+    left: &Result<Value, String>,
+    right: &Result<Value, String>,
+    out: &mut Vec<String>,
+) {
+    match (left, right) {
+        (Ok(left), Ok(right)) => diff_values(label, "", left, right, out),
+        (Err(left), Err(right)) if left == right => {}
+        _ => out.push(format!("{}: {} vs {}", label, describe(left), describe(right))),
+    }
+}
+
+/// Compare two values field by field, so a large structured answer reports
+/// only the parts that changed.
+fn diff_values(label: &str, path: &str, left: &Value, right: &Value, out: &mut Vec<String>) {
+    match (left, right) {
+        (Value::Object(left_fields), Value::Object(right_fields)) => {
+            let mut keys: Vec<&String> = left_fields.keys().chain(right_fields.keys()).collect();
+            keys.sort();
+            keys.dedup();
+            for key in keys {
+                let path = format!("{}.{}", path, key);
+                match (left_fields.get(key), right_fields.get(key)) {
+                    (Some(l), Some(r)) => diff_values(label, &path, l, r, out),
+                    (l, r) => out.push(format!(
+                        "{} {}: {} vs {}",
+                        label,
+                        path,
+                        l.map_or("(missing)".to_string(), Value::to_json),
+                        r.map_or("(missing)".to_string(), Value::to_json)
+                    )),
+                }
+            }
+        }
+        (Value::Array(left_items), Value::Array(right_items)) if left_items.len() == right_items.len() => {
+            for (index, (l, r)) in left_items.iter().zip(right_items).enumerate() {
+                diff_values(label, &format!("{}[{}]", path, index), l, r, out);
+            }
+        }
+        _ if left == right => {}
+        _ => {
+            let at = if path.is_empty() { String::new() } else { format!(" {}", path) };
+            out.push(format!("{}{}: {} vs {}", label, at, left.to_json(), right.to_json()));
+        }
+    }
+}
+
+fn describe(result: &Result<Value, String>) -> String {
+    match result {
+        Ok(value) => value.to_json(),
+        Err(message) => format!("error ({})", message),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn outcome(thinks: Vec<Value>, result: Value) -> Outcome {
+        Outcome {
+            thinks: thinks.into_iter().map(|v| ("Plan".to_string(), Ok(v))).collect(),
+            result: Ok(result),
+        }
+    }
+
+    #[test]
+    fn test_compare_reports_changed_fields() {
+        let plan = |first: &str| {
+            Value::from_json(&format!(r#"{{"steps": ["{}", "push"], "risky": false}}"#, first)).unwrap()
+        };
+        let left = outcome(vec![plan("commit")], Value::String("done".to_string()));
+        let right = outcome(vec![plan("amend"), Value::Null], Value::String("done".to_string()));
+
+        assert_eq!(
+            compare(&left, &right),
+            vec![
+                r#"think #1 .steps[0]: "commit" vs "amend""#.to_string(),
+                "left ran 1 think block(s), right ran 2".to_string(),
+            ]
+        );
+        assert!(compare(&left, &left).is_empty());
+    }
+
+    #[test]
+    fn test_trace_side_replays_cached_answers() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("cached.jsonl");
+        let entry = TraceEntry::Think {
+            prompt: "Name a colour".to_string(),
+            result: Ok(Value::String("red".to_string())),
+            nested: 0,
+        };
+        std::fs::write(&path, entry.to_json_line()).unwrap();
+
+        let backend = Backend::parse(path.to_str().unwrap());
+        assert!(matches!(backend, Backend::Trace(_)));
+        let outcome = run_side("{\n    think { Name a colour }\n}", &backend, false).unwrap();
+        assert_eq!(outcome.result, Ok(Value::String("red".to_string())));
+        assert_eq!(outcome.thinks.len(), 1);
+    }
+}
@@ -0,0 +1,248 @@
+//! Reporting a run to GitHub Actions, for `--output github`.
+//!
+//! While the program runs, each shell command and think or ask block is
+//! folded into a `::group::` in the job log, and logged warnings and errors
+//! become `::warning` and `::error` annotations. A failed run is annotated with
+//! an `::error` workflow command at the line it failed on, and a Markdown
+//! job summary (the final plan, the prompts sent and an estimate of their
+//! tokens) is appended to `$GITHUB_STEP_SUMMARY`, or printed outside CI.
+
+use std::env;
+use std::fs::OpenOptions;
+use std::io::{self, Write};
+use std::sync::mpsc;
+use std::thread::{self, JoinHandle};
+
+use patchwork_eval::{EventSink, LogLevel, PlanEntry, PlanEntryStatus, RuntimeEvent};
+
+/// The file GitHub Actions reads a step's job summary from.
+pub const SUMMARY_VAR: &str = "GITHUB_STEP_SUMMARY";
+
+/// Agents don't report usage, so tokens are estimated from prompt length.
+const CHARS_PER_TOKEN: usize = 4;
+
+/// A think or ask block sent during the run.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Prompt {
+    pub ask: bool,
+    pub chars: usize,
+}
+
+/// What a run did, gathered from its events.
+#[derive(Debug, Default)]
+pub struct Report {
+    /// The line of the statement that ran last, where a failure happened.
+    pub last_line: Option<usize>,
+    pub plan: Vec<PlanEntry>,
+    pub prompts: Vec<Prompt>,
+    /// Shell commands run, and whether each succeeded.
+    pub commands: Vec<(String, bool)>,
+}
+
+impl Report {
+    /// Take in one event, writing any workflow commands it calls for to `out`.
+    pub fn record(&mut self, event: &RuntimeEvent, out: &mut dyn Write) -> io::Result<()> {
+        match event {
+            RuntimeEvent::StatementStarted { line, .. } => {
+                if line.is_some() {
+                    self.last_line = *line;
+                }
+            }
+            RuntimeEvent::ShellExec { command, args, result } => {
+                let command_line = std::iter::once(command).chain(args).cloned().collect::<Vec<_>>().join(" ");
+                writeln!(out, "::group::$ {}", escape_data(&command_line))?;
+                match result {
+                    Ok(value) => writeln!(out, "{}", value.to_string_value().trim_end())?,
+                    Err(message) => writeln!(out, "failed: {}", message)?,
+                }
+                writeln!(out, "::endgroup::")?;
+                self.commands.push((command_line, result.is_ok()));
+            }
+            RuntimeEvent::ThinkRequest { prompt, ask } => {
+                self.prompts.push(Prompt { ask: *ask, chars: prompt.chars().count() });
+                let kind = if *ask { "ask" } else { "think" };
+                writeln!(out, "::group::{} #{}", kind, self.prompts.len())?;
+                writeln!(out, "{}", prompt.trim_end())?;
+                writeln!(out, "::endgroup::")?;
+            }
+            RuntimeEvent::PlanUpdate(update) => self.plan = update.entries.clone(),
+            RuntimeEvent::Log { level, text } => {
+                let command = match level {
+                    LogLevel::Debug => "debug",
+                    LogLevel::Info => return Ok(()),
+                    LogLevel::Warn => "warning",
+                    LogLevel::Error => "error",
+                };
+                writeln!(out, "::{}::{}", command, escape_data(text))?;
+            }
+            // A fallback is also logged as a warning
+            RuntimeEvent::Print { .. } | RuntimeEvent::Exception { .. } | RuntimeEvent::AgentFallback { .. } => {}
+        }
+        Ok(())
+    }
+
+    /// The job summary, in Markdown, for a run of `title` that ended with
+    /// `outcome`.
+    pub fn summary(&self, title: &str, outcome: Result<(), &str>) -> String {
+        let status = match outcome {
+            Ok(()) => "**Succeeded**".to_string(),
+            Err(message) => format!("**Failed:** {}", message.lines().next().unwrap_or_default()),
+        };
+        let mut out = format!("## Patchwork: {}\n\n{}\n", title, status);
+
+        if !self.plan.is_empty() {
+            out.push_str("\n### Plan\n\n");
+            for entry in &self.plan {
+                let mark = match entry.status {
+                    PlanEntryStatus::Completed => "[x]",
+                    PlanEntryStatus::InProgress => "[ ] (in progress)",
+                    PlanEntryStatus::Pending => "[ ]",
+                };
+                out.push_str(&format!("- {} {}\n", mark, entry.content));
+            }
+        }
+
+        let failed = self.commands.iter().filter(|(_, ok)| !ok).count();
+        out.push_str(&format!("\n{} shell command(s) ran, {} failed.\n", self.commands.len(), failed));
+
+        if !self.prompts.is_empty() {
+            out.push_str("\n### Prompts\n\n| # | Kind | Characters | Tokens (est.) |\n|---|---|---|---|\n");
+            for (n, prompt) in self.prompts.iter().enumerate() {
+                let kind = if prompt.ask { "ask" } else { "think" };
+                out.push_str(&format!("| {} | {} | {} | {} |\n", n + 1, kind, prompt.chars, estimate(prompt.chars)));
+            }
+            let chars: usize = self.prompts.iter().map(|prompt| prompt.chars).sum();
+            out.push_str(&format!(
+                "\nAbout {} prompt token(s) in all, estimated at {} characters a token.\n",
+                estimate(chars),
+                CHARS_PER_TOKEN
+            ));
+        }
+        out
+    }
+}
+
+fn estimate(chars: usize) -> usize {
+    chars.div_ceil(CHARS_PER_TOKEN)
+}
+
+/// Follow a run's events on a background thread, writing workflow commands
+/// to stdout and passing each event on to `forward`, if given.
+///
+/// The thread finishes with the run's report once every sender is dropped.
+pub fn spawn(forward: Option<EventSink>) -> (EventSink, JoinHandle<Report>) {
+    let (tx, rx) = mpsc::channel::<RuntimeEvent>();
+    let reporter = thread::spawn(move || {
+        let mut report = Report::default();
+        let mut stdout = io::stdout();
+        for event in rx {
+            let _ = report.record(&event, &mut stdout);
+            if let Some(forward) = &forward {
+                let _ = forward.send(event);
+            }
+        }
+        report
+    });
+    (tx, reporter)
+}
+
+/// An `::error` workflow command, annotating `file` at `line` and `column`
+/// when they're known.
+pub fn error_command(message: &str, file: Option<&str>, line: Option<usize>, column: Option<usize>) -> String {
+    let mut properties = Vec::new();
+    if let Some(file) = file {
+        properties.push(format!("file={}", escape_property(file)));
+        if let Some(line) = line {
+            properties.push(format!("line={}", line));
+        }
+        if let Some(column) = column {
+            properties.push(format!("col={}", column));
+        }
+    }
+    properties.push("title=Patchwork".to_string());
+    format!("::error {}::{}", properties.join(","), escape_data(message))
+}
+
+/// Append `summary` to the job summary, or print it when not running in
+/// GitHub Actions.
+pub fn write_summary(summary: &str) -> Result<(), String> {
+    match env::var_os(SUMMARY_VAR) {
+        Some(path) => OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .and_then(|mut file| file.write_all(summary.as_bytes()))
+            .map_err(|e| format!("Error writing job summary to {}: {}", path.to_string_lossy(), e)),
+        None => {
+            print!("{}", summary);
+            Ok(())
+        }
+    }
+}
+
+/// Escape a workflow command's message, which ends at a line break.
+fn escape_data(text: &str) -> String {
+    text.replace('%', "%25").replace('\r', "%0D").replace('\n', "%0A")
+}
+
+/// Escape a workflow command property, which also ends at `,` or `:`.
+fn escape_property(text: &str) -> String {
+    escape_data(text).replace(':', "%3A").replace(',', "%2C")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use patchwork_eval::{PlanUpdate, Value};
+
+    #[test]
+    fn test_events_become_groups_and_a_summary() {
+        let mut report = Report::default();
+        let mut log = Vec::new();
+        let events = [
+            RuntimeEvent::StatementStarted { line: Some(2), text: "$ git status".to_string() },
+            RuntimeEvent::ShellExec {
+                command: "git".to_string(),
+                args: vec!["status".to_string()],
+                result: Ok(Value::String("clean\n".to_string())),
+            },
+            RuntimeEvent::StatementStarted { line: Some(3), text: "var plan = think { ... }".to_string() },
+            RuntimeEvent::ThinkRequest { prompt: "Plan the release".to_string(), ask: false },
+            RuntimeEvent::PlanUpdate(PlanUpdate {
+                entries: vec![
+                    PlanEntry { content: "Tag".to_string(), status: PlanEntryStatus::Completed },
+                    PlanEntry { content: "Publish".to_string(), status: PlanEntryStatus::Pending },
+                ],
+            }),
+            RuntimeEvent::Log { level: LogLevel::Info, text: "tagging".to_string() },
+            RuntimeEvent::Log { level: LogLevel::Warn, text: "no changelog\nentry".to_string() },
+            RuntimeEvent::StatementStarted { line: None, text: String::new() },
+        ];
+        for event in &events {
+            report.record(event, &mut log).unwrap();
+        }
+        assert_eq!(
+            String::from_utf8(log).unwrap(),
+            concat!(
+                "::group::$ git status\nclean\n::endgroup::\n::group::think #1\nPlan the release\n::endgroup::\n",
+                "::warning::no changelog%0Aentry\n",
+            )
+        );
+        assert_eq!(report.last_line, Some(3));
+
+        let summary = report.summary("release.pw", Err("Runtime error: no tag\nmore"));
+        assert!(summary.starts_with("## Patchwork: release.pw\n\n**Failed:** Runtime error: no tag\n"), "{}", summary);
+        assert!(summary.contains("- [x] Tag\n- [ ] Publish\n"), "{}", summary);
+        assert!(summary.contains("1 shell command(s) ran, 0 failed."), "{}", summary);
+        assert!(summary.contains("| 1 | think | 16 | 4 |"), "{}", summary);
+    }
+
+    #[test]
+    fn test_error_command_escapes() {
+        assert_eq!(
+            error_command("50% done\nthen failed", Some("ci/a,b.pw"), Some(4), Some(7)),
+            "::error file=ci/a%2Cb.pw,line=4,col=7,title=Patchwork::50%25 done%0Athen failed"
+        );
+        assert_eq!(error_command("boom", None, Some(4), None), "::error title=Patchwork::boom");
+    }
+}
@@ -0,0 +1,1266 @@
+//! The `patchwork` command-line interface.
+
+mod auth;
+mod cached_agent;
+mod config;
+mod dashboard;
+mod differential;
+mod github;
+mod mock_agent;
+mod prompt;
+mod schedule;
+mod triggers;
+
+use std::env;
+use std::fs;
+use std::io::{self, IsTerminal, Read};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::time::Duration;
+
+use patchwork_eval::{parse_trace, spawn_event_writer, trust, AgentHandle, Edition, Error, History, Interpreter, TraceEntry, Value};
+use patchwork_parser::ast_dump::{self, DumpFormat, DumpOptions};
+use patchwork_parser::literate;
+use patchwork_parser::migrate;
+use patchwork_parser::token_diff::{self, TokenChange};
+use patchwork_parser::ParseError;
+
+use config::{AgentBackend, Profile, DEFAULT_PROFILE};
+use differential::Backend;
+use schedule::Schedule;
+
+const USAGE: &str = "\
+Usage:
+    patchwork run <file.pw | -> [options]
+    patchwork eval <file.pw | -> [options]
+    patchwork eval -e <code> [options]
+    patchwork replay <trace.jsonl> <file.pw | -> [options]
+    patchwork diff <file.pw | -> <left> <right>
+    patchwork ast <file.pw | -> [--json] [--compact] [--spans] [--depth <n>] [--item <name>]
+    patchwork tokens <file.pw | ->
+    patchwork tokens --diff <old.pw> <new.pw>
+    patchwork fix --edition <year> [--write] <file.pw>...
+    patchwork trust <file.pw>...
+    patchwork serve --dashboard [--runs <dir>] [--port <n>]
+    patchwork serve --triggers <file.pw> [--port <n>] [--profile <name>] [-y]
+    patchwork schedule [--daemon | --run <name>] [--runs <dir>]
+
+Pass `-` as the file to read the program from stdin.
+
+A `.pw.md` file is a literate program: its fenced `patchwork` blocks run in
+order, sharing variables and declarations, and each block's result is
+printed. Errors are reported at their line in the Markdown file.
+
+`replay` re-runs a program with the shell and think results recorded by
+`--trace`, without running commands or calling an agent. It still writes
+files and imports modules, so it needs the same trust as `run`.
+
+`diff` runs a program against two think backends and reports where their
+think answers and results differ. Each side is a profile name or a trace
+file (`.jsonl`), so a cached run can be compared with a live one.
+
+`ast` prints the parsed syntax tree: as JSON with `--json` (on one line
+with `--compact`), with byte spans on named nodes with `--spans`, cut off
+below <n> levels with `--depth`, or for a single declaration with `--item`.
+
+`tokens` lists the tokens the parser sees, with byte spans. With `--diff`
+it compares the token streams of two files instead, ignoring positions.
+
+`fix` rewrites programs for a newer edition, renaming identifiers that
+become keywords, and prints the changes as a diff. `--write` saves them.
+
+Without an agent, `ask` blocks and `approve()` ask you on the terminal.
+When stdin isn't a terminal they take their default (`approve(msg, default)`,
+or the profile's `ask_default` for ask blocks) and fail without one.
+`--answers` runs them unattended: the file maps an ask's variable name, or
+a prompt's number in the run (counting from 1), to its answer, and an
+unanswered ask fails the run.
+
+`serve --dashboard` hosts a web page on localhost for watching runs. It
+follows the runs writing `--events <dir>/<name>.jsonl`, where <dir> is
+`--runs` (.patchwork/runs by default), and pauses, resumes or aborts them
+through `--control <dir>/<name>.control`. Like `serve --triggers`, it
+needs a token in $PATCHWORK_SERVE_TOKEN, which the page's address carries
+to its pause, resume and abort requests. It needs patchwork built with the
+`dashboard` feature.
+
+`serve --triggers` calls the program's skills and functions from HTTP POST
+requests to the routes in patchwork.toml's `[triggers.http]` table, taking
+arguments from the query and JSON body and streaming prints and the result
+back as JSON lines. A request must send the token in $PATCHWORK_SERVE_TOKEN
+as `Authorization: Bearer <token>`, and `Content-Type: application/json`.
+It listens on localhost (port 7879 by default) and needs patchwork built
+with the `triggers` feature.
+
+`schedule` lists the schedules in patchwork.toml and when each next runs.
+A schedule runs a program on a cron spec (in UTC), when watched files
+change, or when a git ref gets new commits. `--daemon` keeps running them
+as they come due, and `--run` runs one now. Scheduled runs write their
+events, control file and trace to `<dir>/<name>.*` under the project, where
+<dir> is `--runs` (.patchwork/runs by default), and only run trusted files.
+
+`run`, `eval`, `replay` and `diff` only run a file once it's trusted, and only import
+modules that are trusted too. `trust` records a grant for each file as it
+is now; editing the file revokes it. Without a grant, you're asked on a
+terminal; elsewhere the run fails unless `--yes` is given. Grants are stored in $PATCHWORK_TRUST_STORE, or in
+patchwork/trusted under $XDG_CONFIG_HOME or ~/.config.
+
+Options:
+    -e, --expr <code>   Evaluate a single expression or statement list
+    --json              Print the resulting value as JSON
+    --output <format>   `text` (the default), `json` (as --json), or `github`
+                        to fold commands and prompts into groups in a GitHub
+                        Actions log, annotate a failure with `::error`, and
+                        write a job summary to $GITHUB_STEP_SUMMARY
+    -q, --quiet         Keep stdout for print() output; the result goes to stderr
+    --profile <name>    Use a run profile from patchwork.toml
+    -y, --yes           Run the file even if it hasn't been trusted
+    --trace <file>      Record shell and think results to a JSON-lines trace
+    --inspect <var@line>
+                        After the run, print what <var> was once <line> last ran
+                        (may be repeated)
+    --control <file>    Between statements, obey `pause`, `resume`, `abort` or
+                        `status` written to <file>; status goes to <file>.status
+    --answers <file>    Answer ask blocks and approve() from a YAML or JSON file
+                        instead of the terminal
+    --events <file>     Append the run's events to <file> as JSON lines (`-` for
+                        stderr): statement_started, shell_exec, think_request,
+                        plan_update, print and exception";
+
+/// Files with this extension are literate programs: Markdown whose fenced
+/// `patchwork` blocks run in order.
+const LITERATE_EXTENSION: &str = ".pw.md";
+
+/// Where the program text comes from.
+#[derive(Debug, PartialEq)]
+enum Source {
+    File(String),
+    /// Read the program from stdin (`-`).
+    Stdin,
+    Expr(String),
+}
+
+impl Source {
+    /// The directory of a program read from a file.
+    fn file_dir(&self) -> Option<PathBuf> {
+        match self {
+            Source::File(path) => Path::new(path).parent().map(Path::to_path_buf),
+            Source::Stdin | Source::Expr(_) => None,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+struct EvalOptions {
+    source: Source,
+    /// Print the result as JSON instead of its display form.
+    json: bool,
+    /// Send the result to stderr so stdout only carries `print` output.
+    quiet: bool,
+    /// Report the run as GitHub Actions workflow commands and a job summary.
+    github: bool,
+    /// Run profile to load from `patchwork.toml`.
+    profile: Option<String>,
+    /// File to write the run's trace to.
+    trace: Option<String>,
+    /// Variables to look up in the run's history: `(name, line)`.
+    inspect: Vec<(String, usize)>,
+    /// File to watch for operator commands during the run.
+    control: Option<String>,
+    /// File of canned answers for ask blocks and approvals.
+    answers: Option<String>,
+    /// File to write the run's events to, as JSON lines (`-` for stderr).
+    events: Option<String>,
+    /// Run the file without a trust grant.
+    yes: bool,
+}
+
+#[derive(Debug, PartialEq)]
+enum Command {
+    Eval(EvalOptions),
+    /// Re-run a program against a recorded trace.
+    Replay { trace: String, options: EvalOptions },
+    /// Run a program against two backends and compare the runs.
+    Diff { source: Source, left: Backend, right: Backend, yes: bool },
+    /// Print a program's syntax tree.
+    Ast { source: Source, options: DumpOptions },
+    /// List a program's tokens.
+    Tokens(Source),
+    /// Compare the token streams of two files.
+    TokenDiff { old: String, new: String },
+    /// Migrate programs to an edition.
+    Fix { edition: Edition, write: bool, files: Vec<String> },
+    /// Trust program files to run.
+    Trust(Vec<String>),
+    /// Serve the web dashboard for the runs in a directory.
+    Dashboard { runs: PathBuf, port: u16 },
+    /// Serve a program's skills at the HTTP routes in the config.
+    Triggers { program: String, port: Option<u16>, profile: Option<String>, yes: bool },
+    /// List the configured schedules, run one now, or run them as they come due.
+    Schedule { run: Option<String>, daemon: bool, runs: PathBuf },
+    Help,
+}
+
+fn parse_args(args: &[String]) -> Result<Command, String> {
+    let Some((command, rest)) = args.split_first() else {
+        return Ok(Command::Help);
+    };
+    match command.as_str() {
+        // `run` and `eval` take the same options
+        "run" | "eval" => parse_eval_args(rest).map(Command::Eval),
+        "replay" => {
+            let (trace, rest) = rest
+                .split_first()
+                .ok_or_else(|| "replay expects a trace file and a program".to_string())?;
+            let options = parse_eval_args(rest)?;
+            if options.trace.is_some() {
+                return Err("--trace can't be used with replay".to_string());
+            }
+            Ok(Command::Replay { trace: trace.clone(), options })
+        }
+        "diff" => {
+            let yes = rest.iter().any(|arg| arg == "-y" || arg == "--yes");
+            let rest: Vec<_> = rest.iter().filter(|arg| *arg != "-y" && *arg != "--yes").collect();
+            match rest[..] {
+                [file, left, right] => Ok(Command::Diff {
+                    source: if file == "-" { Source::Stdin } else { Source::File(file.clone()) },
+                    left: Backend::parse(left),
+                    right: Backend::parse(right),
+                    yes,
+                }),
+                _ => Err("diff expects a program and two backends".to_string()),
+            }
+        }
+        "ast" => parse_ast_args(rest),
+        "serve" => parse_serve_args(rest),
+        "schedule" => parse_schedule_args(rest),
+        "fix" => parse_fix_args(rest),
+        "trust" => match rest {
+            [] => Err("trust expects at least one file".to_string()),
+            files => Ok(Command::Trust(files.to_vec())),
+        },
+        "tokens" => match rest {
+            [flag, old, new] if flag == "--diff" => Ok(Command::TokenDiff { old: old.clone(), new: new.clone() }),
+            [file] if file != "--diff" => {
+                Ok(Command::Tokens(if file == "-" { Source::Stdin } else { Source::File(file.clone()) }))
+            }
+            _ => Err("tokens expects a file, or --diff and two files".to_string()),
+        },
+        "-h" | "--help" | "help" => Ok(Command::Help),
+        other => Err(format!("Unknown command: {}", other)),
+    }
+}
+
+fn parse_eval_args(args: &[String]) -> Result<EvalOptions, String> {
+    let mut source = None;
+    let mut json = false;
+    let mut quiet = false;
+    let mut github = false;
+    let mut profile = None;
+    let mut trace = None;
+    let mut inspect = Vec::new();
+    let mut control = None;
+    let mut answers = None;
+    let mut events = None;
+    let mut yes = false;
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let next_source = match arg.as_str() {
+            "--json" => {
+                json = true;
+                continue;
+            }
+            "-q" | "--quiet" => {
+                quiet = true;
+                continue;
+            }
+            "--output" => {
+                match args.next().map(String::as_str) {
+                    Some("text") => {}
+                    Some("json") => json = true,
+                    Some("github") => github = true,
+                    Some(other) => return Err(format!("Unknown output format: {} (expected text, json or github)", other)),
+                    None => return Err(format!("{} requires an argument", arg)),
+                }
+                continue;
+            }
+            "-y" | "--yes" => {
+                yes = true;
+                continue;
+            }
+            "--profile" => {
+                let name = args.next().ok_or_else(|| format!("{} requires an argument", arg))?;
+                profile = Some(name.clone());
+                continue;
+            }
+            "--trace" => {
+                let path = args.next().ok_or_else(|| format!("{} requires an argument", arg))?;
+                trace = Some(path.clone());
+                continue;
+            }
+            "--inspect" => {
+                let query = args.next().ok_or_else(|| format!("{} requires an argument", arg))?;
+                inspect.push(parse_inspect(query)?);
+                continue;
+            }
+            "--control" => {
+                let path = args.next().ok_or_else(|| format!("{} requires an argument", arg))?;
+                control = Some(path.clone());
+                continue;
+            }
+            "--answers" => {
+                let path = args.next().ok_or_else(|| format!("{} requires an argument", arg))?;
+                answers = Some(path.clone());
+                continue;
+            }
+            "--events" => {
+                let path = args.next().ok_or_else(|| format!("{} requires an argument", arg))?;
+                events = Some(path.clone());
+                continue;
+            }
+            "-" => Source::Stdin,
+            "-e" | "--expr" => {
+                let code = args.next().ok_or_else(|| format!("{} requires an argument", arg))?;
+                Source::Expr(code.clone())
+            }
+            flag if flag.starts_with('-') => return Err(format!("Unknown option: {}", flag)),
+            path => Source::File(path.to_string()),
+        };
+        if source.replace(next_source).is_some() {
+            return Err("Expected a single file or -e expression".to_string());
+        }
+    }
+
+    let source = source.ok_or_else(|| "Expected a file or -e expression".to_string())?;
+    Ok(EvalOptions { source, json, quiet, github, profile, trace, inspect, control, answers, events, yes })
+}
+
+fn parse_ast_args(args: &[String]) -> Result<Command, String> {
+    let mut source = None;
+    let mut options = DumpOptions::default();
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--json" => {
+                // `--compact` already implies JSON
+                if options.format == DumpFormat::Tree {
+                    options.format = DumpFormat::Json;
+                }
+            }
+            "--compact" => options.format = DumpFormat::CompactJson,
+            "--spans" => options.spans = true,
+            "--depth" => {
+                let depth = args.next().ok_or_else(|| format!("{} requires an argument", arg))?;
+                let depth = depth.parse().map_err(|_| format!("--depth expects a number, got '{}'", depth))?;
+                options.max_depth = Some(depth);
+            }
+            "--item" => {
+                let name = args.next().ok_or_else(|| format!("{} requires an argument", arg))?;
+                options.item = Some(name.clone());
+            }
+            flag if flag.starts_with('-') && flag != "-" => return Err(format!("Unknown option: {}", flag)),
+            path => {
+                let next_source = if path == "-" { Source::Stdin } else { Source::File(path.to_string()) };
+                if source.replace(next_source).is_some() {
+                    return Err("ast expects a single file".to_string());
+                }
+            }
+        }
+    }
+
+    let source = source.ok_or_else(|| "ast expects a file".to_string())?;
+    Ok(Command::Ast { source, options })
+}
+
+fn parse_serve_args(args: &[String]) -> Result<Command, String> {
+    let mut dashboard = false;
+    let mut triggers = None;
+    let mut runs = None;
+    let mut port = None;
+    let mut profile = None;
+    let mut yes = false;
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--dashboard" => dashboard = true,
+            "--triggers" => {
+                let file = args.next().ok_or_else(|| format!("{} requires an argument", arg))?;
+                triggers = Some(file.clone());
+            }
+            "--runs" => {
+                let dir = args.next().ok_or_else(|| format!("{} requires an argument", arg))?;
+                runs = Some(PathBuf::from(dir));
+            }
+            "--port" => {
+                let number = args.next().ok_or_else(|| format!("{} requires an argument", arg))?;
+                port = Some(number.parse().map_err(|_| format!("--port expects a port number, got '{}'", number))?);
+            }
+            "--profile" => {
+                let name = args.next().ok_or_else(|| format!("{} requires an argument", arg))?;
+                profile = Some(name.clone());
+            }
+            "-y" | "--yes" => yes = true,
+            other => return Err(format!("Unknown option: {}", other)),
+        }
+    }
+
+    match (dashboard, triggers) {
+        (true, None) if profile.is_none() && !yes => Ok(Command::Dashboard {
+            runs: runs.unwrap_or_else(|| PathBuf::from(dashboard::DEFAULT_RUNS_DIR)),
+            port: port.unwrap_or(dashboard::DEFAULT_PORT),
+        }),
+        (true, None) => Err("--profile and --yes are for serve --triggers".to_string()),
+        (false, Some(_)) if runs.is_some() => Err("--runs is for serve --dashboard".to_string()),
+        (false, Some(program)) => Ok(Command::Triggers { program, port, profile, yes }),
+        (true, Some(_)) => Err("--dashboard and --triggers can't be used together".to_string()),
+        (false, None) => Err("serve expects --dashboard or --triggers".to_string()),
+    }
+}
+
+fn parse_schedule_args(args: &[String]) -> Result<Command, String> {
+    let mut run = None;
+    let mut daemon = false;
+    let mut runs = PathBuf::from(dashboard::DEFAULT_RUNS_DIR);
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--daemon" => daemon = true,
+            "--run" => {
+                let name = args.next().ok_or_else(|| format!("{} requires an argument", arg))?;
+                run = Some(name.clone());
+            }
+            "--runs" => {
+                let dir = args.next().ok_or_else(|| format!("{} requires an argument", arg))?;
+                runs = PathBuf::from(dir);
+            }
+            other => return Err(format!("Unknown option: {}", other)),
+        }
+    }
+
+    if daemon && run.is_some() {
+        return Err("--daemon and --run can't be used together".to_string());
+    }
+    Ok(Command::Schedule { run, daemon, runs })
+}
+
+fn parse_fix_args(args: &[String]) -> Result<Command, String> {
+    let mut edition = None;
+    let mut write = false;
+    let mut files = Vec::new();
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--edition" => {
+                let year = args.next().ok_or_else(|| format!("{} requires an argument", arg))?;
+                edition = Some(Edition::parse(year).ok_or_else(|| format!("Unknown edition: {}", year))?);
+            }
+            "--write" => write = true,
+            flag if flag.starts_with('-') => return Err(format!("Unknown option: {}", flag)),
+            path => files.push(path.to_string()),
+        }
+    }
+
+    let edition = edition.ok_or_else(|| "fix requires --edition".to_string())?;
+    if files.is_empty() {
+        return Err("fix expects at least one file".to_string());
+    }
+    Ok(Command::Fix { edition, write, files })
+}
+
+/// Parse a `name@line` history query.
+fn parse_inspect(query: &str) -> Result<(String, usize), String> {
+    query
+        .rsplit_once('@')
+        .and_then(|(name, line)| Some((name.to_string(), line.parse().ok()?)))
+        .filter(|(name, _)| !name.is_empty())
+        .ok_or_else(|| format!("--inspect expects <var>@<line>, got '{}'", query))
+}
+
+/// Describe what `name` was at `line`, for `--inspect`.
+fn describe_at(history: &History, name: &str, line: usize) -> String {
+    match history.value_at(name, line) {
+        Some(value) => format!("{}@{} = {}", name, line, value.to_json()),
+        None if history.reached(line) => format!("{}@{}: not in scope", name, line),
+        None => format!("{}@{}: line {} never ran", name, line, line),
+    }
+}
+
+/// The program text to hand to the interpreter.
+fn program_text(source: &Source) -> Result<String, String> {
+    match source {
+        Source::File(path) => {
+            fs::read_to_string(path).map_err(|e| format!("Error reading file '{}': {}", path, e))
+        }
+        Source::Stdin => {
+            let mut code = String::new();
+            io::stdin()
+                .read_to_string(&mut code)
+                .map_err(|e| format!("Error reading stdin: {}", e))?;
+            Ok(code)
+        }
+        // A one-liner is a statement list, so run it as a block
+        Source::Expr(code) => Ok(format!("{{\n{}\n}}", code)),
+    }
+}
+
+/// Check that a program file may run, asking the user to trust it if `ask`
+/// and they can answer. Code given on the command line or stdin is always
+/// trusted.
+fn ensure_trusted(source: &Source, code: &str, yes: bool, ask: bool) -> Result<(), String> {
+    let Source::File(path) = source else {
+        return Ok(());
+    };
+    if yes {
+        return Ok(());
+    }
+    ensure_file_trusted(Path::new(path), code, ask)
+}
+
+/// Check that `file`, whose text is `code`, may run, asking the user to
+/// trust it if `ask` and they can answer.
+fn ensure_file_trusted(file: &Path, code: &str, ask: bool) -> Result<(), String> {
+    let path = file.display();
+    let store = trust::store_path()
+        .ok_or_else(|| format!("No trust store found; set ${} or pass --yes", trust::STORE_VAR))?;
+    if trust::is_trusted(&store, file, code) {
+        return Ok(());
+    }
+    if !ask || !io::stdin().is_terminal() {
+        return Err(format!("'{}' isn't trusted; run `patchwork trust {}` or pass --yes", path, path));
+    }
+
+    eprint!("'{}' hasn't been trusted and can run shell commands. Trust it and run? [y/N] ", path);
+    let mut answer = String::new();
+    io::stdin().read_line(&mut answer).map_err(|e| format!("Error reading answer: {}", e))?;
+    if !matches!(answer.trim(), "y" | "Y" | "yes") {
+        return Err(format!("Not running untrusted '{}'", path));
+    }
+    trust::grant(&store, file, code)
+}
+
+/// Hold the modules `interpreter` imports to the same trust as a program
+/// file, asking the user about each untrusted one if `ask`.
+fn require_trusted_modules(interpreter: &mut Interpreter, ask: bool) {
+    interpreter.set_module_check(move |file, code| ensure_file_trusted(file, code, ask));
+}
+
+/// An interpreter for `profile` that only imports trusted modules, for
+/// servers with no one to ask.
+fn trusted_interpreter_for(profile: &Profile) -> Interpreter {
+    let mut interpreter = interpreter_for(profile);
+    require_trusted_modules(&mut interpreter, false);
+    interpreter
+}
+
+/// Format the program's result, or `None` if there's nothing to show.
+fn render(value: &Value, json: bool) -> Option<String> {
+    match value {
+        _ if json => Some(value.to_json()),
+        Value::Null => None,
+        _ => Some(value.to_string_value()),
+    }
+}
+
+/// Load the requested profile, or the config's default profile if there is one.
+fn resolve_profile(name: Option<&str>) -> Result<Profile, String> {
+    let cwd = env::current_dir().map_err(|e| format!("Error reading current directory: {}", e))?;
+    let config = config::find_config(&cwd);
+    match (name, config) {
+        (Some(name), Some(path)) => config::load_profile(&path, name),
+        (Some(_), None) => Err(format!("--profile given but no {} found", config::CONFIG_FILE)),
+        (None, Some(path)) if config::has_profile(&path, DEFAULT_PROFILE) => {
+            config::load_profile(&path, DEFAULT_PROFILE)
+        }
+        (None, _) => Ok(Profile::default()),
+    }
+}
+
+fn interpreter_for(profile: &Profile) -> Interpreter {
+    let mut interpreter = match agent_for(&profile.agent, profile) {
+        Some(agent) => Interpreter::with_agent(agent),
+        None => Interpreter::new(),
+    };
+    interpreter.set_type_check_mode(profile.type_check);
+    let runtime = interpreter.runtime_mut();
+    runtime.set_shell_policy(profile.shell.clone());
+    runtime.set_think_budget(profile.max_thinks);
+    runtime.set_variant_policy(profile.variant.clone());
+    runtime.set_edition(profile.edition);
+    runtime.set_config_overrides(profile.config.clone());
+    runtime.set_heartbeat_interval(profile.heartbeat.map(Duration::from_secs));
+    runtime.set_default_timeout(profile.timeout.map(Duration::from_secs));
+    if let Some(level) = profile.log_level {
+        runtime.set_log_filter(level);
+    }
+    interpreter
+}
+
+/// The agent that answers think blocks for `backend`, or None if think
+/// blocks get placeholders.
+fn agent_for(backend: &AgentBackend, profile: &Profile) -> Option<AgentHandle> {
+    match backend {
+        AgentBackend::None => None,
+        AgentBackend::Mock => Some(mock_agent::spawn(profile.mock_response.clone().unwrap_or_default())),
+        AgentBackend::Cache(path) => Some(cached_agent::spawn(path.clone())),
+        AgentBackend::Fallback(chain) => {
+            let backends = chain
+                .iter()
+                .filter_map(|backend| Some((backend.to_string(), agent_for(backend, profile)?)))
+                .collect();
+            Some(AgentHandle::fallback_chain(backends))
+        }
+    }
+}
+
+fn read_trace(path: &str) -> Result<Vec<TraceEntry>, String> {
+    let text = fs::read_to_string(path).map_err(|e| format!("Error reading trace '{}': {}", path, e))?;
+    parse_trace(&text).map_err(|e| format!("{}: {}", path, e))
+}
+
+fn write_trace(path: &str, entries: &[TraceEntry]) -> Result<(), String> {
+    let text: String = entries.iter().map(|entry| entry.to_json_line() + "\n").collect();
+    fs::write(path, text).map_err(|e| format!("Error writing trace '{}': {}", path, e))
+}
+
+/// Run a program, replaying `replay` instead of running effects if given.
+fn eval(options: &EvalOptions, replay: Option<Vec<TraceEntry>>) -> Result<(), String> {
+    run_program(options, replay, true)
+}
+
+/// Run a program as `eval` does, asking the user to trust untrusted files
+/// only if `ask`.
+fn run_program(options: &EvalOptions, replay: Option<Vec<TraceEntry>>, ask: bool) -> Result<(), String> {
+    let code = program_text(&options.source)?;
+    // A replay still writes files and imports modules, so it needs trust too
+    ensure_trusted(&options.source, &code, options.yes, ask)?;
+    let mut profile = resolve_profile(options.profile.as_deref())?;
+    if replay.is_some() {
+        // Recorded answers stand in for the agent
+        profile.agent = AgentBackend::None;
+    }
+    if let (Some(name), false) = (&options.profile, options.quiet) {
+        eprintln!("Using profile '{}' (agent {})", name, profile.agent);
+    }
+
+    let answers = options.answers.as_deref().map(prompt::Answers::load).transpose()?;
+    let events = match &options.events {
+        Some(path) => Some(spawn_event_writer(path).map_err(|e| format!("Error creating {}: {}", path, e))?),
+        None => None,
+    };
+    let (sink, events_writer) = events.unzip();
+    // The GitHub reporter passes events on to the --events writer
+    let (sink, reporter) = match options.github {
+        true => {
+            let (sink, reporter) = github::spawn(sink);
+            (Some(sink), Some(reporter))
+        }
+        false => (sink, None),
+    };
+    let mut interpreter = interpreter_for(&profile);
+    if !options.yes {
+        require_trusted_modules(&mut interpreter, ask);
+    }
+    if let Some(sink) = sink {
+        interpreter.set_event_sink(sink);
+    }
+    // A program's imports are found next to it
+    if let Some(dir) = options.source.file_dir() {
+        interpreter.set_module_root(dir);
+    }
+    match replay {
+        Some(entries) => interpreter.runtime_mut().start_replay(entries),
+        None if options.trace.is_some() => interpreter.runtime_mut().start_recording(),
+        None => {}
+    }
+    if !options.inspect.is_empty() {
+        interpreter.runtime_mut().start_history();
+    }
+    interpreter.runtime_mut().set_control_file(options.control.as_ref().map(PathBuf::from));
+    interpreter.runtime_mut().set_user_prompter(prompt::spawn(profile.ask_default.clone(), answers));
+    let result = match &options.source {
+        Source::File(path) if path.ends_with(LITERATE_EXTENSION) => run_cells(&mut interpreter, path, &code, options),
+        _ => interpreter.eval(&code).map_err(|e| describe_error(&options.source, e)),
+    };
+    // Write the trace even if the run failed, since that's when it's wanted
+    if let Some(path) = &options.trace {
+        write_trace(path, &interpreter.runtime_mut().take_trace())?;
+    }
+    if let Some(history) = interpreter.runtime().history() {
+        for (name, line) in &options.inspect {
+            eprintln!("{}", describe_at(history, name, *line));
+        }
+    }
+    // The writer finishes once the interpreter drops its end of the stream
+    drop(interpreter);
+    if let Some(reporter) = reporter {
+        let report = reporter.join().unwrap_or_default();
+        report_to_github(options, &report, &result)?;
+    }
+    if let Some(writer) = events_writer {
+        let _ = writer.join();
+    }
+    show(&result?, options);
+    Ok(())
+}
+
+/// Annotate a failed run and write its job summary, for `--output github`.
+fn report_to_github(options: &EvalOptions, report: &github::Report, result: &Result<Value, String>) -> Result<(), String> {
+    let (file, title) = match &options.source {
+        Source::File(path) => (Some(path.as_str()), path.as_str()),
+        Source::Stdin => (None, "stdin"),
+        Source::Expr(_) => (None, "expression"),
+    };
+    if let Err(message) = result {
+        // A literate program's errors already name their line in the Markdown
+        let (line, column) = match file {
+            Some(path) if path.ends_with(LITERATE_EXTENSION) => (None, None),
+            _ => error_position(message, file).map_or((report.last_line, None), |(l, c)| (Some(l), Some(c))),
+        };
+        println!("{}", github::error_command(message, file, line, column));
+    }
+    github::write_summary(&report.summary(title, result.as_ref().map(|_| ()).map_err(String::as_str)))
+}
+
+/// An error ending a run, with a runtime error in a file reported as
+/// `path:line:column: ...` the way compilers do.
+fn describe_error(source: &Source, error: Error) -> String {
+    match (source, error) {
+        (Source::File(path), Error::RuntimeAt { message, line, column }) => {
+            format!("{}:{}:{}: Runtime error: {}", path, line, column, message)
+        }
+        (_, error) => error.to_string(),
+    }
+}
+
+/// The line and column an error message from `file` points at.
+fn error_position(message: &str, file: Option<&str>) -> Option<(usize, usize)> {
+    if let Some(rest) = file.and_then(|path| message.strip_prefix(path)?.strip_prefix(':')) {
+        let mut parts = rest.splitn(3, ':');
+        return Some((parts.next()?.parse().ok()?, parts.next()?.parse().ok()?));
+    }
+    let rest = message.strip_prefix("Parse error: at line ").or_else(|| message.strip_prefix("Runtime error at line "))?;
+    let (line, rest) = rest.split_once(", column ")?;
+    let (column, _) = rest.split_once(':')?;
+    Some((line.parse().ok()?, column.parse().ok()?))
+}
+
+/// Print a program's result as the options ask.
+fn show(value: &Value, options: &EvalOptions) {
+    if let Some(output) = render(value, options.json) {
+        if options.quiet {
+            eprintln!("{}", output);
+        } else {
+            println!("{}", output);
+        }
+    }
+}
+
+/// Run the `patchwork` blocks of a literate program in order, showing each
+/// one's result. Every block is checked before any of them runs.
+fn run_cells(interpreter: &mut Interpreter, path: &str, markdown: &str, options: &EvalOptions) -> Result<Value, String> {
+    let line_of = |offset: usize| markdown[..offset.min(markdown.len())].matches('\n').count() + 1;
+    let cells = literate::cells(markdown);
+
+    let edition = interpreter.runtime().edition();
+    let errors: Vec<ParseError> = cells.iter().flat_map(|cell| cell.check(edition)).collect();
+    for error in &errors {
+        eprint!("{}", error.render(markdown, path));
+    }
+    if !errors.is_empty() {
+        return Err(format!("{} error(s) in {}", errors.len(), path));
+    }
+
+    for cell in &cells {
+        let value = interpreter.eval_cell(&cell.program_text()).map_err(|e| match e {
+            Error::RuntimeAt { message, line, column } => {
+                let line = line_of(cell.offset) + cell.code_line(line) - 1;
+                format!("{}:{}:{}: Runtime error: {}", path, line, column, message)
+            }
+            e => format!("{}:{}: {}", path, line_of(cell.offset), e),
+        })?;
+        show(&value, options);
+    }
+    Ok(Value::Null)
+}
+
+fn diff(source: &Source, left: &Backend, right: &Backend, yes: bool) -> Result<(), String> {
+    let code = program_text(source)?;
+    ensure_trusted(source, &code, yes, true)?;
+    let left = differential::run_side(&code, left, yes)?;
+    let right = differential::run_side(&code, right, yes)?;
+    let differences = differential::compare(&left, &right);
+    for line in &differences {
+        println!("{}", line);
+    }
+    match differences.len() {
+        0 => Ok(()),
+        n => Err(format!("{} difference(s)", n)),
+    }
+}
+
+/// A parse error as a diagnostic showing where in `source` it is.
+fn parse_failure(error: &ParseError, code: &str, source: &Source) -> String {
+    let name = match source {
+        Source::File(path) => path.as_str(),
+        Source::Stdin => "<stdin>",
+        Source::Expr(_) => "<expr>",
+    };
+    error.render(code, name).trim_end().to_string()
+}
+
+fn ast(source: &Source, options: &DumpOptions) -> Result<(), String> {
+    let code = program_text(source)?;
+    let program = patchwork_parser::parse(&code).map_err(|e| parse_failure(&e, &code, source))?;
+    if let Some(name) = &options.item {
+        if !program.items.iter().any(|item| ast_dump::item_name(item) == Some(name.as_str())) {
+            return Err(format!("No top-level item named '{}'", name));
+        }
+    }
+    print!("{}", ast_dump::dump_program_with(&program, options));
+    Ok(())
+}
+
+fn tokens(source: &Source) -> Result<(), String> {
+    let code = program_text(source)?;
+    for (start, token, end) in patchwork_parser::tokenize(&code).map_err(|e| parse_failure(&e, &code, source))? {
+        println!("{}..{} {:?}", start, end, token);
+    }
+    Ok(())
+}
+
+fn token_diff(old: &str, new: &str) -> Result<(), String> {
+    let old_code = program_text(&Source::File(old.to_string()))?;
+    let new_code = program_text(&Source::File(new.to_string()))?;
+    let changes = token_diff::diff_tokens(&old_code, &new_code).map_err(|e| e.to_string())?;
+    let rendered = token_diff::render_token_diff(&old_code, &new_code, &changes, 3);
+    if rendered.is_empty() {
+        return Ok(());
+    }
+    print!("{}", rendered);
+    let changed = changes.iter().filter(|c| !matches!(c, TokenChange::Same(..))).count();
+    Err(format!("{} token(s) differ", changed))
+}
+
+fn fix(edition: Edition, write: bool, files: &[String]) -> Result<(), String> {
+    // Programs are migrated from the edition their profile declares
+    let from = resolve_profile(None)?.edition;
+    let mut warnings = 0;
+    for path in files {
+        let code = program_text(&Source::File(path.clone()))?;
+        let migration = migrate::migrate(&code, from, edition)
+            .map_err(|e| parse_failure(&e, &code, &Source::File(path.clone())))?;
+        for (offset, warning) in &migration.warnings {
+            let line = code[..*offset].matches('\n').count() + 1;
+            eprintln!("{}:{}: {}", path, line, warning);
+        }
+        warnings += migration.warnings.len();
+        if migration.renames.is_empty() {
+            continue;
+        }
+        print!("{}", line_diff(path, &code, &migration.source));
+        if write {
+            fs::write(path, &migration.source).map_err(|e| format!("Error writing '{}': {}", path, e))?;
+        }
+    }
+    match warnings {
+        0 => Ok(()),
+        n => Err(format!("{} place(s) need a manual fix", n)),
+    }
+}
+
+/// A unified diff of two versions of a file with the same line count,
+/// which renames always preserve.
+fn line_diff(path: &str, old: &str, new: &str) -> String {
+    let mut out = format!("--- a/{}\n+++ b/{}\n", path, path);
+    for (n, (old_line, new_line)) in old.split('\n').zip(new.split('\n')).enumerate() {
+        if old_line != new_line {
+            out.push_str(&format!("@@ -{0},1 +{0},1 @@\n-{1}\n+{2}\n", n + 1, old_line, new_line));
+        }
+    }
+    out
+}
+
+fn trust(files: &[String]) -> Result<(), String> {
+    let store = trust::store_path().ok_or_else(|| format!("No trust store found; set ${}", trust::STORE_VAR))?;
+    for path in files {
+        let code = program_text(&Source::File(path.clone()))?;
+        trust::grant(&store, path.as_ref(), &code)?;
+        eprintln!("Trusted {}", path);
+    }
+    Ok(())
+}
+
+/// Serve `program`'s skills at the routes in the project's config.
+fn serve_triggers(program: String, port: u16, profile: Option<&str>, yes: bool) -> Result<(), String> {
+    let source = Source::File(program.clone());
+    let code = program_text(&source)?;
+    ensure_trusted(&source, &code, yes, true)?;
+    let cwd = env::current_dir().map_err(|e| format!("Error reading current directory: {}", e))?;
+    let config = config::find_config(&cwd).ok_or_else(|| format!("No {} found", config::CONFIG_FILE))?;
+    let routes = config::load_http_triggers(&config)?;
+    let triggers = triggers::Triggers {
+        program: PathBuf::from(program),
+        code,
+        routes,
+        profile: resolve_profile(profile)?,
+        interpreter: if yes { interpreter_for } else { trusted_interpreter_for },
+    };
+    triggers::serve(triggers, port)
+}
+
+fn schedule(run: Option<&str>, daemon: bool, runs: &Path) -> Result<(), String> {
+    let cwd = env::current_dir().map_err(|e| format!("Error reading current directory: {}", e))?;
+    let config = config::find_config(&cwd).ok_or_else(|| format!("No {} found", config::CONFIG_FILE))?;
+    // Programs, watched paths and the runs directory are relative to the project
+    let root = config.parent().unwrap_or(&cwd);
+    let runs = root.join(runs);
+    let schedules = config::load_schedules(&config)?;
+    match run {
+        Some(name) => {
+            let schedule = schedules
+                .iter()
+                .find(|schedule| schedule.name == name)
+                .ok_or_else(|| format!("No schedule named '{}' in {}", name, config.display()))?;
+            run_scheduled(schedule, root, &runs)
+        }
+        None if daemon => {
+            fs::create_dir_all(&runs).map_err(|e| format!("Error creating {}: {}", runs.display(), e))?;
+            schedule::daemon(schedules, root, |schedule, _| run_scheduled(schedule, root, &runs))
+        }
+        None => {
+            print!("{}", schedule::describe(&schedules));
+            Ok(())
+        }
+    }
+}
+
+/// Run a scheduled program as `patchwork run` would, keeping its events,
+/// control file and trace in `runs`.
+fn run_scheduled(schedule: &Schedule, root: &Path, runs: &Path) -> Result<(), String> {
+    let program = root.join(&schedule.program).to_string_lossy().into_owned();
+    // No one is there to ask, so only trusted programs run
+    let code = program_text(&Source::File(program.clone()))?;
+    let store = trust::store_path()
+        .ok_or_else(|| format!("No trust store found; set ${}", trust::STORE_VAR))?;
+    if !trust::is_trusted(&store, program.as_ref(), &code) {
+        return Err(format!("'{}' isn't trusted; run `patchwork trust {}`", program, program));
+    }
+    fs::create_dir_all(runs).map_err(|e| format!("Error creating {}: {}", runs.display(), e))?;
+    let file = |extension: &str| Some(runs.join(format!("{}{}", schedule.name, extension)).to_string_lossy().into_owned());
+    let options = EvalOptions {
+        source: Source::File(program.clone()),
+        json: false,
+        quiet: false,
+        github: false,
+        profile: schedule.profile.clone(),
+        trace: file(".trace.jsonl"),
+        inspect: Vec::new(),
+        control: file(".control"),
+        answers: None,
+        events: file(".jsonl"),
+        // Checked above; its modules are checked as they're imported
+        yes: false,
+    };
+    // A daemon has no one to ask, so an untrusted module fails the run
+    // instead of waiting on stdin
+    run_program(&options, None, false)
+}
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+
+    let result = match parse_args(&args) {
+        Ok(Command::Help) => {
+            println!("{}", USAGE);
+            Ok(())
+        }
+        Ok(Command::Eval(options)) => eval(&options, None),
+        Ok(Command::Replay { trace, options }) => read_trace(&trace).and_then(|entries| eval(&options, Some(entries))),
+        Ok(Command::Diff { source, left, right, yes }) => diff(&source, &left, &right, yes),
+        Ok(Command::Ast { source, options }) => ast(&source, &options),
+        Ok(Command::Tokens(source)) => tokens(&source),
+        Ok(Command::TokenDiff { old, new }) => token_diff(&old, &new),
+        Ok(Command::Fix { edition, write, files }) => fix(edition, write, &files),
+        Ok(Command::Trust(files)) => trust(&files),
+        Ok(Command::Dashboard { runs, port }) => dashboard::serve(runs, port),
+        Ok(Command::Triggers { program, port, profile, yes }) => {
+            serve_triggers(program, port.unwrap_or(triggers::DEFAULT_PORT), profile.as_deref(), yes)
+        }
+        Ok(Command::Schedule { run, daemon, runs }) => schedule(run.as_deref(), daemon, &runs),
+        Err(message) => {
+            eprintln!("{}", message);
+            eprintln!();
+            eprintln!("{}", USAGE);
+            process::exit(2);
+        }
+    };
+
+    if let Err(message) = result {
+        eprintln!("{}", message);
+        process::exit(1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(list: &[&str]) -> Vec<String> {
+        list.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn test_parse_eval_expr_with_json() {
+        let command = parse_args(&args(&["eval", "-e", "1 + 2", "--json"])).unwrap();
+        assert_eq!(
+            command,
+            Command::Eval(EvalOptions {
+                source: Source::Expr("1 + 2".to_string()),
+                json: true,
+                quiet: false,
+                github: false,
+                profile: None,
+                trace: None,
+                inspect: Vec::new(),
+                control: None,
+                answers: None,
+                events: None,
+                yes: false,
+            })
+        );
+        assert!(parse_args(&args(&["eval", "-e"])).is_err());
+        assert!(parse_args(&args(&["eval", "a.pw", "-e", "1"])).is_err());
+    }
+
+    #[test]
+    fn test_parse_output_github() {
+        let Command::Eval(options) = parse_args(&args(&["run", "ci.pw", "--output", "github"])).unwrap() else {
+            panic!("expected a run");
+        };
+        assert!(options.github && !options.json);
+        assert!(parse_args(&args(&["run", "ci.pw", "--output", "xml"])).is_err());
+
+        let mut interpreter = Interpreter::new();
+        let message = interpreter.eval("{\n    var x = (\n}").unwrap_err().to_string();
+        assert_eq!(error_position(&message, None), Some((2, 14)));
+        assert_eq!(error_position("Runtime error: boom", None), None);
+
+        let source = Source::File("ci.pw".to_string());
+        let error = interpreter.eval("{\n    var x = 1\n    x + missing\n}").unwrap_err();
+        let message = describe_error(&source, error);
+        assert_eq!(message, "ci.pw:3:5: Runtime error: Undefined variable: missing");
+        assert_eq!(error_position(&message, Some("ci.pw")), Some((3, 5)));
+        let message = interpreter.eval("{ missing }").unwrap_err().to_string();
+        assert_eq!(error_position(&message, None), Some((1, 3)));
+    }
+
+    #[test]
+    fn test_parse_serve_triggers() {
+        assert_eq!(
+            parse_args(&args(&["serve", "--triggers", "hooks.pw", "--port", "9000", "-y"])).unwrap(),
+            Command::Triggers { program: "hooks.pw".to_string(), port: Some(9000), profile: None, yes: true }
+        );
+        assert!(matches!(parse_args(&args(&["serve", "--dashboard"])).unwrap(), Command::Dashboard { port: 7878, .. }));
+        assert!(parse_args(&args(&["serve", "--dashboard", "--triggers", "hooks.pw"])).is_err());
+        assert!(parse_args(&args(&["serve", "--triggers", "hooks.pw", "--runs", "runs"])).is_err());
+        assert!(parse_args(&args(&["serve"])).is_err());
+    }
+
+    #[test]
+    fn test_parse_schedule_args() {
+        assert_eq!(
+            parse_args(&args(&["schedule", "--run", "nightly"])).unwrap(),
+            Command::Schedule {
+                run: Some("nightly".to_string()),
+                daemon: false,
+                runs: PathBuf::from(dashboard::DEFAULT_RUNS_DIR)
+            }
+        );
+        assert!(parse_args(&args(&["schedule", "--daemon", "--run", "nightly"])).is_err());
+    }
+
+    #[test]
+    fn test_expr_evaluates_as_block() {
+        let code = program_text(&Source::Expr("var x = [1, 2]\nlen(x) + 1".to_string())).unwrap();
+        let value = Interpreter::new().eval(&code).unwrap();
+        assert_eq!(render(&value, false), Some("3".to_string()));
+        assert_eq!(render(&Value::Null, false), None);
+        assert_eq!(render(&Value::Null, true), Some("null".to_string()));
+    }
+
+    #[test]
+    fn test_parse_run_stdin_quiet() {
+        let command = parse_args(&args(&["run", "-", "--quiet", "--profile", "ci"])).unwrap();
+        assert_eq!(
+            command,
+            Command::Eval(EvalOptions {
+                source: Source::Stdin,
+                json: false,
+                quiet: true,
+                github: false,
+                profile: Some("ci".to_string()),
+                trace: None,
+                inspect: Vec::new(),
+                control: None,
+                answers: None,
+                events: None,
+                yes: false,
+            })
+        );
+    }
+
+    #[test]
+    fn test_parse_trust_and_yes() {
+        assert_eq!(
+            parse_args(&args(&["trust", "a.pw", "b.pw"])).unwrap(),
+            Command::Trust(vec!["a.pw".to_string(), "b.pw".to_string()])
+        );
+        assert!(parse_args(&args(&["trust"])).is_err());
+        assert!(matches!(parse_args(&args(&["run", "a.pw", "-y"])).unwrap(), Command::Eval(options) if options.yes));
+        assert!(matches!(
+            parse_args(&args(&["diff", "--yes", "a.pw", "ci", "live"])).unwrap(),
+            Command::Diff { yes: true, .. }
+        ));
+        // stdin and -e code were handed over directly, so need no grant
+        assert!(ensure_trusted(&Source::Stdin, "", false, true).is_ok());
+
+        // Without asking, an untrusted file fails at once instead of waiting
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("nightly.pw");
+        fs::write(&file, "skill main() {}").unwrap();
+        let source = Source::File(file.to_string_lossy().into_owned());
+        assert!(ensure_trusted(&source, "skill main() {}", false, false).is_err());
+        assert!(ensure_trusted(&source, "skill main() {}", true, false).is_ok());
+    }
+
+    #[test]
+    fn test_literate_cells_run_in_order() {
+        let markdown = "# Greeting\n\n```patchwork\nvar name = \"world\"\n```\n\nThen:\n\n```patchwork\n\"hello \" + name\n```\n";
+        let Command::Eval(options) = parse_args(&args(&["run", "notes.pw.md"])).unwrap() else {
+            panic!("Expected an eval command");
+        };
+        let mut interpreter = Interpreter::new();
+        assert_eq!(run_cells(&mut interpreter, "notes.pw.md", markdown, &options), Ok(Value::Null));
+        assert_eq!(interpreter.eval_cell("{\n    name\n}").unwrap(), Value::String("world".to_string()));
+
+        let broken = "Text\n\n```patchwork\nvar x = )\n```\n";
+        assert_eq!(
+            run_cells(&mut Interpreter::new(), "broken.pw.md", broken, &options),
+            Err("1 error(s) in broken.pw.md".to_string())
+        );
+    }
+
+    #[test]
+    fn test_mock_profile_answers_thinks() {
+        let profile = Profile {
+            agent: AgentBackend::Mock,
+            mock_response: Some("canned".to_string()),
+            max_thinks: Some(2),
+            ..Profile::default()
+        };
+        let mut interpreter = interpreter_for(&profile);
+        let value = interpreter.eval("{\n    think { anything }\n}").unwrap();
+        assert_eq!(value, Value::String("canned".to_string()));
+    }
+
+    #[test]
+    fn test_fallback_chain_profile() {
+        let dir = tempfile::tempdir().unwrap();
+        let trace = dir.path().join("cache.jsonl");
+        let entry = TraceEntry::Think {
+            prompt: "Name a colour".to_string(),
+            result: Ok(Value::String("teal".to_string())),
+            nested: 0,
+        };
+        write_trace(trace.to_str().unwrap(), &[entry]).unwrap();
+
+        // The cache answers what it recorded, and the mock everything else
+        let profile = Profile {
+            agent: AgentBackend::Fallback(vec![AgentBackend::Cache(trace), AgentBackend::Mock]),
+            mock_response: Some("canned".to_string()),
+            ..Profile::default()
+        };
+        let mut interpreter = interpreter_for(&profile);
+        let value = interpreter.eval("{\n    [think { Name a colour }, think { Name a fruit }]\n}").unwrap();
+        assert_eq!(value, Value::Array(vec![Value::String("teal".to_string()), Value::String("canned".to_string())]));
+    }
+
+    #[test]
+    fn test_replay_reuses_recorded_trace() {
+        let dir = tempfile::tempdir().unwrap();
+        let trace = dir.path().join("trace.jsonl");
+        let trace = trace.to_str().unwrap();
+
+        let command = parse_args(&args(&["replay", trace, "main.pw", "--json"])).unwrap();
+        assert!(matches!(command, Command::Replay { ref options, .. } if options.json));
+        assert!(parse_args(&args(&["replay", trace, "main.pw", "--trace", "out.jsonl"])).is_err());
+
+        let code = "{\n    var out = $(echo recorded)\n    out\n}";
+        let mut interpreter = Interpreter::new();
+        interpreter.runtime_mut().start_recording();
+        interpreter.eval(code).unwrap();
+        write_trace(trace, &interpreter.runtime_mut().take_trace()).unwrap();
+
+        // Edit the recording so the replayed value can only come from the trace
+        let edited = fs::read_to_string(trace).unwrap().replace("recorded\\n", "from the trace");
+        fs::write(trace, edited).unwrap();
+
+        let mut replayed = Interpreter::new();
+        replayed.runtime_mut().start_replay(read_trace(trace).unwrap());
+        assert_eq!(replayed.eval(code).unwrap().to_string_value(), "from the trace");
+    }
+
+    #[test]
+    fn test_parse_ast_args() {
+        let command = parse_args(&args(&["ast", "main.pw", "--compact", "--json", "--depth", "2", "--item", "main"])).unwrap();
+        assert_eq!(
+            command,
+            Command::Ast {
+                source: Source::File("main.pw".to_string()),
+                options: DumpOptions {
+                    format: DumpFormat::CompactJson,
+                    spans: false,
+                    max_depth: Some(2),
+                    item: Some("main".to_string()),
+                },
+            }
+        );
+        assert!(parse_args(&args(&["ast", "main.pw", "--depth", "deep"])).is_err());
+    }
+
+    #[test]
+    fn test_fix_prints_rename_diff() {
+        assert_eq!(
+            parse_args(&args(&["fix", "--edition", "2025", "a.pw", "b.pw"])).unwrap(),
+            Command::Fix { edition: Edition::E2025, write: false, files: vec!["a.pw".to_string(), "b.pw".to_string()] }
+        );
+        assert!(parse_args(&args(&["fix", "a.pw"])).is_err());
+
+        let old = "fun f(try) {\n    return try\n}";
+        let new = migrate::migrate(old, Edition::E2024, Edition::E2025).unwrap().source;
+        assert_eq!(
+            line_diff("f.pw", old, &new),
+            "--- a/f.pw\n+++ b/f.pw\n@@ -1,1 +1,1 @@\n-fun f(try) {\n+fun f(try_) {\n@@ -2,1 +2,1 @@\n-    return try\n+    return try_\n"
+        );
+    }
+
+    #[test]
+    fn test_inspect_queries() {
+        assert_eq!(parse_inspect("commit_plan@42"), Ok(("commit_plan".to_string(), 42)));
+        assert!(parse_inspect("commit_plan").is_err());
+        assert!(parse_inspect("@3").is_err());
+
+        let mut interpreter = Interpreter::new();
+        interpreter.runtime_mut().start_history();
+        interpreter.eval("{\n    var x = \"a\"\n    x = x + \"b\"\n}").unwrap();
+        let history = interpreter.runtime().history().unwrap();
+        assert_eq!(describe_at(history, "x", 2), "x@2 = \"a\"");
+        assert_eq!(describe_at(history, "y", 3), "y@3: not in scope");
+        assert_eq!(describe_at(history, "x", 9), "x@9: line 9 never ran");
+    }
+}
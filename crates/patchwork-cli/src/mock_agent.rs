@@ -0,0 +1,23 @@
+//! A stand-in agent that answers every think block with a canned response.
+
+use patchwork_eval::{AgentHandle, ThinkRequest, ThinkResponse, Value};
+use tokio::sync::mpsc;
+
+/// Start a mock agent on a background thread and return its handle.
+///
+/// Blocks that expect JSON get `response` parsed as JSON (or null if it
+/// doesn't parse); all others get it as a string.
+pub fn spawn(response: String) -> AgentHandle {
+    let (tx, mut rx) = mpsc::unbounded_channel::<ThinkRequest>();
+    std::thread::spawn(move || {
+        while let Some(request) = rx.blocking_recv() {
+            let value = if request.expect == "json" {
+                Value::from_json(&response).unwrap_or(Value::Null)
+            } else {
+                Value::String(response.clone())
+            };
+            let _ = request.response_tx.send(ThinkResponse::Complete { result: Ok(value) });
+        }
+    });
+    AgentHandle::new(tx)
+}
@@ -0,0 +1,263 @@
+//! Answering `ask` blocks and `approve()` from the terminal.
+//!
+//! On a terminal, prompts are shown on stderr and answered on stdin: an ask
+//! takes lines until a blank one, and an approval takes y or n. Pressing
+//! enter alone gives the default, if there is one. When stdin isn't a
+//! terminal, prompts take their default, or fail the run if they have none.
+//!
+//! With an answers file (`--answers`) no one is asked at all; see [`Answers`].
+
+use std::collections::HashMap;
+use std::fs;
+use std::io::{self, BufRead, IsTerminal, Write};
+use std::sync::mpsc;
+use std::thread;
+
+use patchwork_eval::{PromptKind, UserPrompt, UserPrompter, Value};
+
+/// Canned answers for an unattended run, keyed by the variable an ask
+/// block is bound to or by the prompt's ordinal:
+///
+/// ```yaml
+/// reviewer: alice
+/// plan: {steps: [build, test]}   # given to the program as JSON
+/// 3: yes                         # the third prompt, e.g. an approve()
+/// ```
+///
+/// A name takes precedence over an ordinal. An ask with no answer fails the
+/// run; an approval with none takes its default.
+#[derive(Debug)]
+pub struct Answers {
+    path: String,
+    answers: HashMap<String, String>,
+}
+
+impl Answers {
+    /// Read answers from a JSON file (`.json`) or a YAML one.
+    pub fn load(path: &str) -> Result<Answers, String> {
+        let text = fs::read_to_string(path).map_err(|e| format!("Error reading {}: {}", path, e))?;
+        let parsed = if path.ends_with(".json") { Value::from_json(&text) } else { Value::from_yaml(&text) };
+        let answers = match parsed.map_err(|e| format!("{}: {}", path, e))? {
+            Value::Object(fields) => fields
+                .into_iter()
+                .map(|(key, value)| {
+                    let answer = match value {
+                        Value::String(text) => text,
+                        Value::Boolean(yes) => if yes { "yes" } else { "no" }.to_string(),
+                        other => other.to_json(),
+                    };
+                    (key, answer)
+                })
+                .collect(),
+            Value::Null => HashMap::new(),
+            _ => return Err(format!("{}: answers must be a mapping", path)),
+        };
+        Ok(Answers { path: path.to_string(), answers })
+    }
+
+    fn get(&self, prompt: &UserPrompt) -> Option<&str> {
+        let by_name = prompt.name.as_ref().and_then(|name| self.answers.get(name));
+        by_name.or_else(|| self.answers.get(&prompt.ordinal.to_string())).map(String::as_str)
+    }
+}
+
+/// Start answering prompts on a background thread.
+///
+/// `ask_default` answers ask blocks when no one can. With `answers`, every
+/// prompt is answered from them instead of the terminal.
+pub fn spawn(ask_default: Option<String>, answers: Option<Answers>) -> UserPrompter {
+    let (tx, rx) = mpsc::channel::<UserPrompt>();
+    thread::spawn(move || {
+        let interactive = io::stdin().is_terminal();
+        for prompt in rx {
+            let answer = match &answers {
+                Some(answers) => answer_from(answers, &prompt),
+                None => {
+                    let stdin = io::stdin();
+                    let mut input = stdin.lock();
+                    let input: Option<&mut dyn BufRead> = if interactive { Some(&mut input) } else { None };
+                    answer(&prompt, ask_default.as_deref(), input, &mut io::stderr())
+                }
+            };
+            let _ = prompt.response_tx.send(answer);
+        }
+    });
+    tx
+}
+
+/// Answer one prompt from an answers file.
+fn answer_from(answers: &Answers, prompt: &UserPrompt) -> Result<String, String> {
+    if let Some(answer) = answers.get(prompt) {
+        return Ok(answer.to_string());
+    }
+    match (prompt.kind, &prompt.default) {
+        (PromptKind::Approve, Some(default)) => Ok(default.clone()),
+        (kind, _) => {
+            let what = match kind {
+                PromptKind::Ask => "ask block",
+                PromptKind::Approve => "approve()",
+            };
+            let key = match &prompt.name {
+                Some(name) => format!("'{}' or {}", name, prompt.ordinal),
+                None => prompt.ordinal.to_string(),
+            };
+            Err(format!(
+                "No answer in {} for {} #{} (key {}): {}",
+                answers.path,
+                what,
+                prompt.ordinal,
+                key,
+                prompt.text.trim()
+            ))
+        }
+    }
+}
+
+/// Answer one prompt, reading from `input` if someone is there to type.
+fn answer(
+    prompt: &UserPrompt,
+    ask_default: Option<&str>,
+    input: Option<&mut dyn BufRead>,
+    output: &mut dyn Write,
+) -> Result<String, String> {
+    let default = prompt.default.as_deref().or(match prompt.kind {
+        PromptKind::Ask => ask_default,
+        PromptKind::Approve => None,
+    });
+    let Some(input) = input else {
+        let what = match prompt.kind {
+            PromptKind::Ask => "ask block",
+            PromptKind::Approve => "approve()",
+        };
+        return match default {
+            Some(default) => {
+                let _ = writeln!(
+                    output,
+                    "[patchwork] no terminal; answering {} #{} with {:?}",
+                    what, prompt.ordinal, default
+                );
+                Ok(default.to_string())
+            }
+            None => Err(format!(
+                "No one to answer {} #{} (stdin isn't a terminal): {}",
+                what,
+                prompt.ordinal,
+                prompt.text.trim()
+            )),
+        };
+    };
+
+    let read_error = |e: io::Error| format!("Error reading answer: {}", e);
+    match prompt.kind {
+        PromptKind::Approve => {
+            let choices = match default {
+                Some("yes") => "[Y/n]",
+                Some("no") => "[y/N]",
+                _ => "[y/n]",
+            };
+            loop {
+                let _ = write!(output, "{} {} ", prompt.text.trim(), choices);
+                let _ = output.flush();
+                let mut line = String::new();
+                if input.read_line(&mut line).map_err(read_error)? == 0 {
+                    return default.map(str::to_string).ok_or_else(|| "No answer to approve()".to_string());
+                }
+                match line.trim().to_lowercase().as_str() {
+                    "y" | "yes" => return Ok("yes".to_string()),
+                    "n" | "no" => return Ok("no".to_string()),
+                    "" => {
+                        if let Some(default) = default {
+                            return Ok(default.to_string());
+                        }
+                    }
+                    _ => {}
+                }
+            }
+        }
+        PromptKind::Ask => {
+            let _ = writeln!(output, "{}", prompt.text.trim());
+            let hint = match default {
+                Some(default) => format!("(end with a blank line; blank for {:?})", default),
+                None => "(end with a blank line)".to_string(),
+            };
+            let _ = write!(output, "{}\n> ", hint);
+            let _ = output.flush();
+            let mut lines = Vec::new();
+            loop {
+                let mut line = String::new();
+                if input.read_line(&mut line).map_err(read_error)? == 0 {
+                    break;
+                }
+                let line = line.trim_end_matches(['\n', '\r']);
+                if line.is_empty() {
+                    break;
+                }
+                lines.push(line.to_string());
+                let _ = write!(output, "> ");
+                let _ = output.flush();
+            }
+            match (lines.is_empty(), default) {
+                (true, Some(default)) => Ok(default.to_string()),
+                _ => Ok(lines.join("\n")),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn prompt(kind: PromptKind, text: &str, default: Option<&str>) -> UserPrompt {
+        let (response_tx, _) = mpsc::channel();
+        let default = default.map(str::to_string);
+        UserPrompt { kind, name: None, text: text.to_string(), default, ordinal: 2, response_tx }
+    }
+
+    #[test]
+    fn test_answers_on_and_off_a_terminal() {
+        let mut shown = Vec::new();
+        let ask = prompt(PromptKind::Ask, "Release notes?", None);
+        let mut typed: &[u8] = b"Fixed the lexer\nand the parser\n\nleft over\n";
+        assert_eq!(
+            answer(&ask, None, Some(&mut typed), &mut shown),
+            Ok("Fixed the lexer\nand the parser".to_string())
+        );
+        assert!(String::from_utf8(shown).unwrap().starts_with("Release notes?\n"));
+
+        let approve = prompt(PromptKind::Approve, "Push?", Some("no"));
+        let mut typed: &[u8] = b"maybe\n\n";
+        assert_eq!(answer(&approve, None, Some(&mut typed), &mut Vec::new()), Ok("no".to_string()));
+
+        // Off a terminal, defaults answer or the run fails
+        assert_eq!(answer(&approve, None, None, &mut Vec::new()), Ok("no".to_string()));
+        assert_eq!(answer(&ask, Some("n/a"), None, &mut Vec::new()), Ok("n/a".to_string()));
+        assert_eq!(
+            answer(&ask, None, None, &mut Vec::new()),
+            Err("No one to answer ask block #2 (stdin isn't a terminal): Release notes?".to_string())
+        );
+    }
+
+    #[test]
+    fn test_answers_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("answers.json");
+        fs::write(&path, r#"{"branch": "main", "2": {"files": 3}, "3": true}"#).unwrap();
+        let answers = Answers::load(path.to_str().unwrap()).unwrap();
+
+        let mut ask = prompt(PromptKind::Ask, "Which branch?", None);
+        ask.name = Some("branch".to_string());
+        assert_eq!(answer_from(&answers, &ask), Ok("main".to_string()));
+        ask.name = None;
+        assert!(answer_from(&answers, &ask).unwrap().contains("\"files\": 3"));
+        ask.ordinal = 4;
+        let err = answer_from(&answers, &ask).unwrap_err();
+        assert!(err.contains("No answer in") && err.contains("ask block #4"), "{}", err);
+
+        let mut approve = prompt(PromptKind::Approve, "Push?", Some("no"));
+        approve.ordinal = 3;
+        assert_eq!(answer_from(&answers, &approve), Ok("yes".to_string()));
+        approve.ordinal = 5;
+        assert_eq!(answer_from(&answers, &approve), Ok("no".to_string()));
+    }
+}
@@ -0,0 +1,397 @@
+//! Running programs on a schedule, for `patchwork schedule`.
+//!
+//! Schedules are tables in `patchwork.toml`, each naming a program and what
+//! triggers it: a cron spec, paths to watch for changes, or a git ref to
+//! watch for new commits. Any of them may be combined.
+//!
+//! ```toml
+//! [schedules.nightly]
+//! program = "release.pw"
+//! cron = "0 3 * * 1-5"      # minute hour day month weekday, in UTC
+//! profile = "ci"
+//!
+//! [schedules.docs]
+//! program = "docs.pw"
+//! watch = ["docs", "README.md"]
+//!
+//! [schedules.review]
+//! program = "review.pw"
+//! git = "main"
+//! ```
+//!
+//! The daemon runs each triggered program like `patchwork run`, one at a
+//! time, with its events, control file and trace under the runs directory
+//! (`<name>.jsonl`, `<name>.control` and `<name>.trace.jsonl`), so the
+//! dashboard can follow it and `patchwork replay` can rerun it.
+
+use std::collections::hash_map::DefaultHasher;
+use std::fs;
+use std::hash::{Hash, Hasher};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// How often the daemon looks at watched paths and git refs.
+const POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Directories never worth watching: they change on every run.
+const IGNORED_DIRS: &[&str] = &[".git", ".patchwork", "target"];
+
+/// A program and what triggers it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Schedule {
+    pub name: String,
+    pub program: PathBuf,
+    /// Run profile to use, as with `--profile`.
+    pub profile: Option<String>,
+    pub cron: Option<Cron>,
+    /// Files and directories whose changes trigger a run.
+    pub watch: Vec<PathBuf>,
+    /// A git ref whose new commits trigger a run.
+    pub git: Option<String>,
+}
+
+/// A five-field cron spec: minute, hour, day of month, month and day of
+/// week (0 or 7 for Sunday). Fields take `*`, numbers, ranges (`1-5`),
+/// lists (`1,15`) and steps (`*/10`, `8-18/2`).
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cron {
+    spec: String,
+    minutes: u64,
+    hours: u64,
+    days: u64,
+    months: u64,
+    weekdays: u64,
+    /// Whether the day of month and day of week were both restricted, in
+    /// which case either matching is enough, as in cron.
+    either_day: bool,
+}
+
+impl Cron {
+    pub fn parse(spec: &str) -> Result<Cron, String> {
+        let fields: Vec<&str> = spec.split_whitespace().collect();
+        let [minutes, hours, days, months, weekdays] = fields[..] else {
+            return Err(format!("cron spec '{}' needs 5 fields, found {}", spec, fields.len()));
+        };
+        let field = |text: &str, min: u32, max: u32| {
+            parse_field(text, min, max).map_err(|e| format!("cron spec '{}': {}", spec, e))
+        };
+        let mut weekday_bits = field(weekdays, 0, 7)?;
+        // Sunday is both 0 and 7
+        if weekday_bits & (1 << 7) != 0 {
+            weekday_bits |= 1;
+        }
+        Ok(Cron {
+            spec: spec.to_string(),
+            minutes: field(minutes, 0, 59)?,
+            hours: field(hours, 0, 23)?,
+            days: field(days, 1, 31)?,
+            months: field(months, 1, 12)?,
+            weekdays: weekday_bits,
+            either_day: days != "*" && weekdays != "*",
+        })
+    }
+
+    /// Whether the spec fires in the minute containing `time`.
+    pub fn matches(&self, time: &UtcTime) -> bool {
+        let bit = |set: u64, n: u32| set & (1 << n) != 0;
+        let day = bit(self.days, time.day);
+        let weekday = bit(self.weekdays, time.weekday);
+        let day_matches = if self.either_day { day || weekday } else { day && weekday };
+        bit(self.minutes, time.minute) && bit(self.hours, time.hour) && bit(self.months, time.month) && day_matches
+    }
+
+    /// The first minute after `secs` (seconds since the Unix epoch) that the
+    /// spec fires in, looking up to a year ahead.
+    pub fn next_after(&self, secs: u64) -> Option<u64> {
+        let first = secs / 60 + 1;
+        (first..first + 366 * 24 * 60).map(|minute| minute * 60).find(|&at| self.matches(&UtcTime::from_secs(at)))
+    }
+}
+
+/// Parse one cron field into a bit set of the values it allows.
+fn parse_field(text: &str, min: u32, max: u32) -> Result<u64, String> {
+    let mut set = 0;
+    for part in text.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => {
+                let step: u32 = step.parse().map_err(|_| format!("invalid step '{}'", step))?;
+                if step == 0 {
+                    return Err("a step can't be 0".to_string());
+                }
+                (range, step)
+            }
+            None => (part, 1),
+        };
+        let number = |text: &str| match text.parse::<u32>() {
+            Ok(n) if (min..=max).contains(&n) => Ok(n),
+            _ => Err(format!("'{}' isn't a number from {} to {}", text, min, max)),
+        };
+        let (start, end) = match range.split_once('-') {
+            _ if range == "*" => (min, max),
+            Some((start, end)) => (number(start)?, number(end)?),
+            // A step from a single number runs to the end of the field
+            None if step > 1 => (number(range)?, max),
+            None => (number(range)?, number(range)?),
+        };
+        if start > end {
+            return Err(format!("range '{}' runs backwards", range));
+        }
+        for n in (start..=end).step_by(step as usize) {
+            set |= 1 << n;
+        }
+    }
+    Ok(set)
+}
+
+/// A moment broken into its UTC calendar fields.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct UtcTime {
+    pub year: i64,
+    pub month: u32,
+    pub day: u32,
+    pub hour: u32,
+    pub minute: u32,
+    /// 0 for Sunday.
+    pub weekday: u32,
+}
+
+impl UtcTime {
+    pub fn from_secs(secs: u64) -> UtcTime {
+        let days = (secs / 86_400) as i64;
+        let in_day = secs % 86_400;
+        // Days to a civil date, after Howard Hinnant's `civil_from_days`
+        let z = days + 719_468;
+        let era = z.div_euclid(146_097);
+        let doe = z.rem_euclid(146_097);
+        let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+        let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+        let mp = (5 * doy + 2) / 153;
+        let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+        let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+        let year = yoe + era * 400 + i64::from(month <= 2);
+        UtcTime {
+            year,
+            month,
+            day,
+            hour: (in_day / 3600) as u32,
+            minute: (in_day % 3600 / 60) as u32,
+            // The epoch fell on a Thursday
+            weekday: ((days + 4).rem_euclid(7)) as u32,
+        }
+    }
+}
+
+impl std::fmt::Display for UtcTime {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}-{:02}-{:02} {:02}:{:02} UTC", self.year, self.month, self.day, self.hour, self.minute)
+    }
+}
+
+fn now_secs() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+}
+
+/// Describe each schedule and when it next runs, for `patchwork schedule`.
+pub fn describe(schedules: &[Schedule]) -> String {
+    let now = now_secs();
+    let mut out = String::new();
+    for schedule in schedules {
+        out.push_str(&format!("{}: {}", schedule.name, schedule.program.display()));
+        if let Some(profile) = &schedule.profile {
+            out.push_str(&format!(" (profile {})", profile));
+        }
+        out.push('\n');
+        if let Some(cron) = &schedule.cron {
+            let next = match cron.next_after(now) {
+                Some(at) => format!("next {}", UtcTime::from_secs(at)),
+                None => "never fires".to_string(),
+            };
+            out.push_str(&format!("    cron \"{}\", {}\n", cron.spec, next));
+        }
+        for path in &schedule.watch {
+            out.push_str(&format!("    on changes to {}\n", path.display()));
+        }
+        if let Some(git) = &schedule.git {
+            out.push_str(&format!("    on new commits to {}\n", git));
+        }
+    }
+    out
+}
+
+/// What a schedule last saw of its triggers.
+struct Watcher {
+    schedule: Schedule,
+    /// The minute the cron spec last fired in.
+    fired_minute: Option<u64>,
+    fingerprint: Option<u64>,
+    head: Option<String>,
+}
+
+impl Watcher {
+    fn new(schedule: Schedule, root: &Path) -> Watcher {
+        let fingerprint = (!schedule.watch.is_empty()).then(|| fingerprint(root, &schedule.watch));
+        let head = schedule.git.as_deref().and_then(|git| resolve_ref(root, git));
+        Watcher { schedule, fired_minute: None, fingerprint, head }
+    }
+
+    /// Why the schedule should run now, if it should.
+    fn due(&mut self, now: u64, poll: bool, root: &Path) -> Option<String> {
+        if let Some(cron) = &self.schedule.cron {
+            let minute = now / 60;
+            if self.fired_minute != Some(minute) && cron.matches(&UtcTime::from_secs(now)) {
+                self.fired_minute = Some(minute);
+                return Some(format!("cron \"{}\"", cron.spec));
+            }
+        }
+        if !poll {
+            return None;
+        }
+        if let Some(old) = self.fingerprint {
+            let new = fingerprint(root, &self.schedule.watch);
+            if new != old {
+                self.fingerprint = Some(new);
+                return Some("watched files changed".to_string());
+            }
+        }
+        if let Some(git) = &self.schedule.git {
+            let head = resolve_ref(root, git);
+            if head.is_some() && head != self.head {
+                let was_known = self.head.is_some();
+                self.head = head;
+                if was_known {
+                    return Some(format!("new commits on {}", git));
+                }
+            }
+        }
+        None
+    }
+}
+
+/// Run schedules as they come due, forever, calling `run` for each one
+/// with the reason it was triggered. Paths are relative to `root`.
+///
+/// Runs happen one at a time; a trigger that comes due during a run is
+/// noticed once it ends.
+pub fn daemon(
+    schedules: Vec<Schedule>,
+    root: &Path,
+    mut run: impl FnMut(&Schedule, &str) -> Result<(), String>,
+) -> Result<(), String> {
+    if schedules.is_empty() {
+        return Err("No schedules to run".to_string());
+    }
+    let mut watchers: Vec<Watcher> = schedules.into_iter().map(|schedule| Watcher::new(schedule, root)).collect();
+    let mut last_poll = Instant::now();
+    loop {
+        let poll = last_poll.elapsed() >= POLL_INTERVAL;
+        if poll {
+            last_poll = Instant::now();
+        }
+        for watcher in &mut watchers {
+            let Some(reason) = watcher.due(now_secs(), poll, root) else {
+                continue;
+            };
+            let name = &watcher.schedule.name;
+            eprintln!("[schedule] {}: running {} ({})", name, watcher.schedule.program.display(), reason);
+            match run(&watcher.schedule, &reason) {
+                Ok(()) => eprintln!("[schedule] {}: done", name),
+                Err(message) => eprintln!("[schedule] {}: failed: {}", name, message),
+            }
+        }
+        thread::sleep(Duration::from_millis(500));
+    }
+}
+
+/// A hash of the names, sizes and modification times of the files under
+/// `paths`, which changes whenever one of them does.
+fn fingerprint(root: &Path, paths: &[PathBuf]) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    let mut pending: Vec<PathBuf> = paths.iter().map(|path| root.join(path)).collect();
+    while let Some(path) = pending.pop() {
+        let Ok(metadata) = fs::metadata(&path) else {
+            // A missing path hashes differently from an empty one
+            (&path, "missing").hash(&mut hasher);
+            continue;
+        };
+        if metadata.is_dir() {
+            let mut entries: Vec<PathBuf> = fs::read_dir(&path)
+                .map(|entries| entries.flatten().map(|entry| entry.path()).collect())
+                .unwrap_or_default();
+            entries.retain(|entry| {
+                !entry.file_name().is_some_and(|name| IGNORED_DIRS.iter().any(|ignored| name == *ignored))
+            });
+            entries.sort();
+            // Popped in order, last first
+            pending.extend(entries.into_iter().rev());
+        } else {
+            (&path, metadata.len(), metadata.modified().ok()).hash(&mut hasher);
+        }
+    }
+    hasher.finish()
+}
+
+/// The commit `git` names in the repository at `root`.
+fn resolve_ref(root: &Path, git: &str) -> Option<String> {
+    let output = Command::new("git")
+        .args(["rev-parse", "--verify", "--quiet", &format!("{}^{{commit}}", git)])
+        .current_dir(root)
+        .output()
+        .ok()?;
+    output.status.success().then(|| String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cron_specs() {
+        // Friday 2026-10-16, 00:00 UTC
+        let friday = 1_792_108_800;
+        let time = UtcTime::from_secs(friday + 3 * 3600);
+        assert_eq!(time, UtcTime { year: 2026, month: 10, day: 16, hour: 3, minute: 0, weekday: 5 });
+        assert_eq!(time.to_string(), "2026-10-16 03:00 UTC");
+
+        let nightly = Cron::parse("0 3 * * 1-5").unwrap();
+        assert!(nightly.matches(&time));
+        assert!(!nightly.matches(&UtcTime { minute: 1, ..time }));
+        assert!(!nightly.matches(&UtcTime { weekday: 0, ..time }));
+        // From Friday evening, the next run skips the weekend
+        assert_eq!(nightly.next_after(friday + 20 * 3600), Some(friday + 3 * 86_400 + 3 * 3600));
+
+        let every = Cron::parse("*/15 8-18/2 1,15 * 7").unwrap();
+        assert!(every.matches(&UtcTime { minute: 45, hour: 10, day: 1, weekday: 4, ..time }));
+        assert!(every.matches(&UtcTime { minute: 30, hour: 8, day: 9, weekday: 0, ..time }));
+        assert!(!every.matches(&UtcTime { minute: 30, hour: 9, day: 1, ..time }));
+
+        assert!(Cron::parse("0 3 * *").unwrap_err().contains("5 fields"));
+        assert!(Cron::parse("60 * * * *").unwrap_err().contains("from 0 to 59"));
+        assert!(Cron::parse("*/0 * * * *").is_err());
+        assert!(Cron::parse("5-1 * * * *").unwrap_err().contains("backwards"));
+    }
+
+    #[test]
+    fn test_watched_files_trigger_once_per_change() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir(dir.path().join("docs")).unwrap();
+        fs::write(dir.path().join("docs/intro.md"), "hello").unwrap();
+        let schedule = Schedule {
+            name: "docs".to_string(),
+            program: PathBuf::from("docs.pw"),
+            profile: None,
+            cron: None,
+            watch: vec![PathBuf::from("docs")],
+            git: None,
+        };
+        let mut watcher = Watcher::new(schedule, dir.path());
+        assert_eq!(watcher.due(0, true, dir.path()), None);
+
+        fs::write(dir.path().join("docs/usage.md"), "more").unwrap();
+        // Only polls look at the files
+        assert_eq!(watcher.due(0, false, dir.path()), None);
+        assert_eq!(watcher.due(0, true, dir.path()), Some("watched files changed".to_string()));
+        assert_eq!(watcher.due(0, true, dir.path()), None);
+    }
+}
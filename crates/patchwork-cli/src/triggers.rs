@@ -0,0 +1,348 @@
+//! Calling skills over HTTP (`patchwork serve --triggers <file.pw>`).
+//!
+//! Each route in the config's `[triggers.http]` table names a skill or
+//! function in the program. A `POST` to the route calls it, once it passes
+//! the checks in [`crate::auth`]: it carries the server's token and a JSON
+//! body. The call takes its arguments from the request:
+//!
+//! - a query parameter or a field of an object body fills the parameter of
+//!   the same name;
+//! - a callable with a single parameter takes a body that doesn't name it
+//!   whole.
+//!
+//! Text from the query is read as the parameter's declared type where that
+//! is a number or boolean, and every argument is checked
+//! against its declared type before the call starts. A request whose
+//! arguments don't fit is refused with `422 Unprocessable Entity`.
+//!
+//! The run's output streams back as JSON lines, `{"print": <text>}` for each
+//! line the program prints, `{"log": {"level": <level>, "text": <text>}}` for
+//! each line it logs, and then `{"result": <value>}` or
+//! `{"error": <message>}` once the call returns. Each request runs on a fresh
+//! interpreter, with the program read again so edits are picked up; an edit
+//! the server wasn't started with isn't trusted, so the request is refused.
+//!
+//! Everything but the HTTP server is plain code. The server needs the
+//! `triggers` feature.
+
+// Without the server, only the tests load and call
+#![cfg_attr(not(feature = "triggers"), allow(dead_code))]
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::PathBuf;
+
+use patchwork_eval::{Interpreter, OutputLine, Type, Value};
+
+use crate::config::Profile;
+
+/// Port the trigger server listens on, on localhost only, unless `--port`
+/// is given.
+pub const DEFAULT_PORT: u16 = 7879;
+
+/// What the trigger server calls, and how it sets up each call.
+pub struct Triggers {
+    pub program: PathBuf,
+    /// The program's text when it was trusted.
+    pub code: String,
+    /// `(route, callable)` pairs, as the config lists them.
+    pub routes: Vec<(String, String)>,
+    pub profile: Profile,
+    /// Make an interpreter for one call.
+    pub interpreter: fn(&Profile) -> Interpreter,
+}
+
+/// Why a request was refused before its call started.
+#[derive(Debug, PartialEq)]
+pub struct Rejection {
+    pub status: u16,
+    pub message: String,
+}
+
+impl Rejection {
+    fn new(status: u16, message: impl Into<String>) -> Self {
+        Rejection { status, message: message.into() }
+    }
+}
+
+impl Triggers {
+    /// An interpreter with the program loaded, and the arguments to call
+    /// `target` with for a request with `query` and `body`.
+    pub fn prepare(
+        &self,
+        target: &str,
+        query: &HashMap<String, String>,
+        body: &str,
+    ) -> Result<(Interpreter, Vec<Value>), Rejection> {
+        let code = fs::read_to_string(&self.program)
+            .map_err(|e| Rejection::new(500, format!("Error reading {}: {}", self.program.display(), e)))?;
+        if code != self.code {
+            return Err(Rejection::new(
+                500,
+                format!("{} changed since the server started; restart it to trust the change", self.program.display()),
+            ));
+        }
+        let mut interpreter = (self.interpreter)(&self.profile);
+        interpreter.reload_module(&self.program).map_err(|e| Rejection::new(500, e.to_string()))?;
+        let params = interpreter.params(target).ok_or_else(|| {
+            Rejection::new(500, format!("{} defines no skill or function '{}'", self.program.display(), target))
+        })?;
+        let args = arguments(&params, interpreter.runtime().types(), query, body)
+            .map_err(|message| Rejection::new(422, message))?;
+        Ok((interpreter, args))
+    }
+}
+
+/// The arguments for a callable with `params`, from a request's `query` and
+/// `body`.
+pub fn arguments(
+    params: &[(String, Type)],
+    aliases: &HashMap<String, Type>,
+    query: &HashMap<String, String>,
+    body: &str,
+) -> Result<Vec<Value>, String> {
+    let body = match body.trim() {
+        "" => None,
+        text => Some(Value::from_json(text).map_err(|e| format!("The body isn't JSON: {}", e))?),
+    };
+    let fields = match &body {
+        Some(Value::Object(fields)) => Some(fields),
+        _ => None,
+    };
+
+    params
+        .iter()
+        .map(|(name, ty)| {
+            let given = query
+                .get(name)
+                .map(|text| Value::String(text.clone()))
+                .or_else(|| fields.and_then(|fields| fields.get(name)).cloned())
+                .or_else(|| body.clone().filter(|_| params.len() == 1));
+            let value = match given {
+                Some(value) => coerce(value, ty, aliases),
+                // A parameter that takes null may be left out
+                None if ty.check(&Value::Null, aliases).is_ok() => Value::Null,
+                None => return Err(format!("Missing argument '{}'", name)),
+            };
+            ty.check(&value, aliases).map_err(|e| format!("Argument '{}' {}", name, e))?;
+            Ok(value)
+        })
+        .collect()
+}
+
+/// Text read as `ty`, where that's a number or boolean.
+fn coerce(value: Value, ty: &Type, aliases: &HashMap<String, Type>) -> Value {
+    let Value::String(text) = &value else {
+        return value;
+    };
+    match ty.resolve(aliases) {
+        Ok(Type::Number) => text.trim().parse().map(Value::Number).unwrap_or(value),
+        Ok(Type::Boolean) => match text.trim() {
+            "true" => Value::Boolean(true),
+            "false" => Value::Boolean(false),
+            _ => value,
+        },
+        _ => value,
+    }
+}
+
+/// One line of a streamed response: `{"<key>": <value>}`.
+pub fn line(key: &str, value: Value) -> String {
+    Value::Object([(key.to_string(), value)].into()).to_json_line() + "\n"
+}
+
+/// The response line for a printed or logged line.
+pub fn output_line(output: OutputLine) -> String {
+    match output.level {
+        None => line("print", Value::String(output.text)),
+        Some(level) => {
+            let fields = [("level", level.name().to_string()), ("text", output.text)];
+            line("log", Value::Object(fields.map(|(key, value)| (key.to_string(), Value::String(value))).into()))
+        }
+    }
+}
+
+/// Serve the routes in `triggers` on localhost until interrupted.
+#[cfg(feature = "triggers")]
+pub fn serve(triggers: Triggers, port: u16) -> Result<(), String> {
+    server::serve(triggers, port)
+}
+
+#[cfg(not(feature = "triggers"))]
+pub fn serve(_triggers: Triggers, _port: u16) -> Result<(), String> {
+    Err("Triggers aren't available; build patchwork with the `triggers` feature".to_string())
+}
+
+#[cfg(feature = "triggers")]
+mod server {
+    use std::collections::HashMap;
+    use std::convert::Infallible;
+    use std::sync::{mpsc, Arc};
+    use std::thread;
+
+    use axum::body::Body;
+    use axum::extract::{Query, State};
+    use axum::http::{header, HeaderMap, StatusCode};
+    use axum::response::{IntoResponse, Response};
+    use axum::routing::post;
+    use axum::Router;
+    use futures_util::stream;
+    use patchwork_eval::{OutputLine, Value};
+    use tokio::sync::{mpsc as channel, oneshot};
+
+    use super::{line, output_line, Rejection, Triggers};
+    use crate::auth;
+
+    pub(super) fn serve(triggers: Triggers, port: u16) -> Result<(), String> {
+        let token: Arc<str> = auth::token()?.into();
+        let runtime = tokio::runtime::Builder::new_multi_thread()
+            .enable_all()
+            .build()
+            .map_err(|e| format!("Error starting the trigger server: {}", e))?;
+        runtime.block_on(async move {
+            let listener = tokio::net::TcpListener::bind(("127.0.0.1", port))
+                .await
+                .map_err(|e| format!("Error listening on port {}: {}", port, e))?;
+            let mut app = Router::new();
+            for (route, target) in &triggers.routes {
+                eprintln!("POST http://127.0.0.1:{}{} -> {}", port, route, target);
+                let target = target.clone();
+                let token = token.clone();
+                app = app.route(
+                    route,
+                    post(move |State(triggers), headers: HeaderMap, Query(query), body: String| async move {
+                        match auth::refusal(&token, &headers) {
+                            Some(refused) => refused,
+                            None => call(triggers, target, query, body).await,
+                        }
+                    }),
+                );
+            }
+            let app = app.with_state(Arc::new(triggers));
+            axum::serve(listener, app).await.map_err(|e| format!("Trigger server error: {}", e))
+        })
+    }
+
+    /// Call `target` for one request, streaming its output back.
+    async fn call(
+        triggers: Arc<Triggers>,
+        target: String,
+        query: HashMap<String, String>,
+        body: String,
+    ) -> Response {
+        let (ready_tx, ready_rx) = oneshot::channel::<Result<(), Rejection>>();
+        let (lines_tx, lines_rx) = channel::unbounded_channel::<String>();
+
+        // The interpreter blocks, so the call runs on its own thread
+        tokio::task::spawn_blocking(move || {
+            let (mut interpreter, args) = match triggers.prepare(&target, &query, &body) {
+                Ok(prepared) => prepared,
+                Err(rejection) => {
+                    let _ = ready_tx.send(Err(rejection));
+                    return;
+                }
+            };
+            let _ = ready_tx.send(Ok(()));
+
+            let (output_tx, output_rx) = mpsc::channel::<OutputLine>();
+            interpreter.set_output_sink(output_tx);
+            let prints = {
+                let lines_tx = lines_tx.clone();
+                thread::spawn(move || {
+                    for output in output_rx {
+                        let _ = lines_tx.send(output_line(output));
+                    }
+                })
+            };
+            let result = interpreter.call(&target, args);
+            // Dropping the interpreter closes the output sink, so every line
+            // is sent before the result
+            drop(interpreter);
+            let _ = prints.join();
+            let _ = lines_tx.send(match result {
+                Ok(value) => line("result", value),
+                Err(e) => line("error", Value::String(e.to_string())),
+            });
+        });
+
+        match ready_rx.await {
+            Ok(Ok(())) => {}
+            Ok(Err(Rejection { status, message })) => {
+                let status = StatusCode::from_u16(status).unwrap_or(StatusCode::INTERNAL_SERVER_ERROR);
+                return (status, message).into_response();
+            }
+            Err(_) => return (StatusCode::INTERNAL_SERVER_ERROR, "The call failed to start").into_response(),
+        }
+        let lines = stream::unfold(lines_rx, |mut lines_rx| async move {
+            let line = lines_rx.recv().await?;
+            Some((Ok::<_, Infallible>(line), lines_rx))
+        });
+        ([(header::CONTENT_TYPE, "application/x-ndjson")], Body::from_stream(lines)).into_response()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn query(pairs: &[(&str, &str)]) -> HashMap<String, String> {
+        pairs.iter().map(|(key, value)| (key.to_string(), value.to_string())).collect()
+    }
+
+    #[test]
+    fn test_arguments_from_query_and_body() {
+        let params = vec![("branch".to_string(), Type::String), ("depth".to_string(), Type::Number)];
+        let aliases = HashMap::new();
+        assert_eq!(
+            arguments(&params, &aliases, &query(&[("depth", "3")]), r#"{"branch": "main"}"#),
+            Ok(vec![Value::String("main".to_string()), Value::Number(3.0)])
+        );
+        assert_eq!(
+            arguments(&params, &aliases, &query(&[("branch", "main")]), ""),
+            Err("Missing argument 'depth'".to_string())
+        );
+        let refused = arguments(&params, &aliases, &query(&[("branch", "main"), ("depth", "deep")]), "");
+        assert!(refused.unwrap_err().starts_with("Argument 'depth' at value: expected number"));
+
+        // A single parameter takes the whole body, which has to be JSON
+        let single = vec![("notes".to_string(), Type::Any)];
+        assert_eq!(
+            arguments(&single, &aliases, &HashMap::new(), "\"fix the build\\n\""),
+            Ok(vec![Value::String("fix the build\n".to_string())])
+        );
+        assert_eq!(arguments(&single, &aliases, &HashMap::new(), "[1]"), Ok(vec![Value::Array(vec![Value::Number(1.0)])]));
+        assert!(arguments(&single, &aliases, &HashMap::new(), "fix the build").unwrap_err().starts_with("The body isn't JSON"));
+    }
+
+    #[test]
+    fn test_prepare_loads_the_program() {
+        let dir = tempfile::tempdir().unwrap();
+        let program = dir.path().join("hooks.pw");
+        let code = "skill greet(name: string) {\n    print(\"hello \" + name)\n    \"done\"\n}\n";
+        fs::write(&program, code).unwrap();
+        let triggers = Triggers {
+            program: program.clone(),
+            code: code.to_string(),
+            routes: vec![("/greet".to_string(), "greet".to_string())],
+            profile: Profile::default(),
+            interpreter: |_| Interpreter::new(),
+        };
+
+        let (mut interpreter, args) = triggers.prepare("greet", &query(&[("name", "ci")]), "").unwrap();
+        assert_eq!(interpreter.call("greet", args).unwrap(), Value::String("done".to_string()));
+        assert_eq!(triggers.prepare("greet", &HashMap::new(), "").err().unwrap().status, 422);
+        assert_eq!(triggers.prepare("missing", &HashMap::new(), "").err().unwrap().status, 500);
+
+        fs::write(&program, code.replace("hello", "bye")).unwrap();
+        assert!(triggers.prepare("greet", &query(&[("name", "ci")]), "").err().unwrap().message.contains("changed"));
+        assert_eq!(line("result", Value::Number(1.0)), "{\"result\":1.0}\n");
+
+        let logged = OutputLine {
+            level: Some(patchwork_eval::LogLevel::Warn),
+            text: "slow".to_string(),
+            timestamp: std::time::SystemTime::now(),
+            span: None,
+        };
+        assert_eq!(output_line(logged), "{\"log\":{\"level\":\"warn\",\"text\":\"slow\"}}\n");
+    }
+}
@@ -12,8 +12,10 @@
 //! - Think responses: Received via `std::sync::mpsc::Receiver` (blocking receive in sync interpreter)
 
 use std::collections::HashMap;
-use std::sync::mpsc;
-use tokio::sync::mpsc::UnboundedSender;
+use std::fmt;
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
 
 use crate::value::Value;
 
@@ -39,6 +41,24 @@ pub enum ThinkResponse {
         /// The extracted value from the LLM response.
         result: Result<Value, String>,
     },
+
+    /// A backend in a fallback chain failed, and the next one is answering
+    /// instead. Any number may arrive before `Complete`.
+    Fallback {
+        /// Name of the backend that failed.
+        backend: String,
+        /// Why it failed.
+        error: String,
+        /// Name of the backend trying next.
+        next: String,
+    },
+
+    /// Tokens the LLM used answering, for agents that report them. Any
+    /// number may arrive before `Complete`; they add up.
+    Usage {
+        /// Input and output tokens together.
+        tokens: u64,
+    },
 }
 
 /// A request to execute a think block.
@@ -52,6 +72,13 @@ pub struct ThinkRequest {
     pub bindings: HashMap<String, Value>,
     /// Expected type hint for response extraction (e.g., "string", "json").
     pub expect: String,
+    /// Names of the tools the agent may use while answering, from
+    /// `think(tools: [...])`. None leaves the agent's tools unrestricted.
+    pub tools: Option<Vec<String>>,
+    /// For `think.start`, the later turns of the conversation. The agent
+    /// keeps its session open after answering the prompt and answers each
+    /// follow-up in that session, until the sender is dropped.
+    pub follow_ups: Option<UnboundedReceiver<FollowUp>>,
     /// Channel to receive responses from the agent.
     ///
     /// The agent will send ThinkResponse messages:
@@ -60,6 +87,14 @@ pub struct ThinkRequest {
     pub response_tx: mpsc::Sender<ThinkResponse>,
 }
 
+/// A later turn of a conversation opened with `think.start`.
+pub struct FollowUp {
+    /// The program's reply, to send in the conversation's session.
+    pub prompt: String,
+    /// Channel to receive responses from the agent, as for a ThinkRequest.
+    pub response_tx: mpsc::Sender<ThinkResponse>,
+}
+
 /// A handle to the agent that can be used by the interpreter.
 ///
 /// This is cloneable so it can be passed to different interpreter threads.
@@ -85,6 +120,8 @@ impl AgentHandle {
         prompt: String,
         bindings: HashMap<String, Value>,
         expect: String,
+        tools: Option<Vec<String>>,
+        follow_ups: Option<UnboundedReceiver<FollowUp>>,
     ) -> Result<mpsc::Receiver<ThinkResponse>, String> {
         let (response_tx, response_rx) = mpsc::channel();
 
@@ -92,6 +129,8 @@ impl AgentHandle {
             prompt,
             bindings,
             expect,
+            tools,
+            follow_ups,
             response_tx,
         };
 
@@ -101,4 +140,159 @@ impl AgentHandle {
 
         Ok(response_rx)
     }
+
+    /// An agent that passes each think request to the first of `backends`,
+    /// named for warnings, and falls through to the next when one fails.
+    ///
+    /// A backend fails if it answers with an error or stops without
+    /// answering. The interpreter is sent a `Fallback` response each time,
+    /// and the last backend's error if every one of them fails. A backend
+    /// that fails after running any of the block's do-blocks is not retried
+    /// with the next, which would run them again. A conversation stays with
+    /// the backend that answered its first turn.
+    pub fn fallback_chain(backends: Vec<(String, AgentHandle)>) -> Self {
+        let (tx, mut rx) = unbounded_channel::<ThinkRequest>();
+        let backends = Arc::new(backends);
+        thread::spawn(move || {
+            while let Some(request) = rx.blocking_recv() {
+                let backends = backends.clone();
+                // Requests from concurrent tasks don't wait on each other
+                thread::spawn(move || answer_with_fallback(request, &backends));
+            }
+        });
+        AgentHandle::new(tx)
+    }
+}
+
+/// Answer `request` with the first of `backends` that succeeds.
+fn answer_with_fallback(request: ThinkRequest, backends: &[(String, AgentHandle)]) {
+    let ThinkRequest { prompt, bindings, expect, tools, mut follow_ups, response_tx } = request;
+    for (i, (name, backend)) in backends.iter().enumerate() {
+        // Each backend gets its own turns, so one that fails can't take the conversation with it
+        let (turns_tx, turns_rx) = match follow_ups {
+            Some(_) => {
+                let (tx, rx) = unbounded_channel();
+                (Some(tx), Some(rx))
+            }
+            None => (None, None),
+        };
+        let error = match backend.think(prompt.clone(), bindings.clone(), expect.clone(), tools.clone(), turns_rx) {
+            Ok(rx) => match relay_answer(&rx, &response_tx) {
+                Ok(()) => {
+                    if let (Some(follow_ups), Some(turns_tx)) = (&mut follow_ups, turns_tx) {
+                        while let Some(follow_up) = follow_ups.blocking_recv() {
+                            if turns_tx.send(follow_up).is_err() {
+                                break;
+                            }
+                        }
+                    }
+                    return;
+                }
+                Err(error) => error,
+            },
+            Err(error) => error,
+        };
+        let response = match backends.get(i + 1) {
+            Some((next, _)) => ThinkResponse::Fallback { backend: name.clone(), error, next: next.clone() },
+            None => ThinkResponse::Complete { result: Err(format!("Agent backend '{}' failed: {}", name, error)) },
+        };
+        if response_tx.send(response).is_err() {
+            // The interpreter stopped waiting
+            return;
+        }
+    }
+}
+
+/// Pass one backend's responses on to the interpreter. Returns the
+/// backend's error if it failed before running any do-blocks.
+fn relay_answer(rx: &mpsc::Receiver<ThinkResponse>, response_tx: &mpsc::Sender<ThinkResponse>) -> Result<(), String> {
+    let mut ran_code = false;
+    loop {
+        let response = match rx.recv() {
+            Ok(ThinkResponse::Complete { result: Err(error) }) if !ran_code => return Err(error),
+            Ok(response) => response,
+            Err(_) if !ran_code => return Err("it stopped without answering".to_string()),
+            // The interpreter reports the missing answer
+            Err(_) => return Ok(()),
+        };
+        let done = matches!(response, ThinkResponse::Complete { .. });
+        ran_code |= matches!(response, ThinkResponse::Do { .. });
+        let _ = response_tx.send(response);
+        if done {
+            return Ok(());
+        }
+    }
+}
+
+/// A conversation opened with `think.start`, as returned to the program.
+///
+/// `reply` sends the program's next turn to the same agent session, so the
+/// agent still has the earlier turns in context, and `close` ends it.
+pub struct Conversation {
+    state: Mutex<ConversationState>,
+    /// The agent's latest answer
+    answer: Mutex<Value>,
+}
+
+enum ConversationState {
+    /// An agent is answering; dropping the sender ends its session
+    Open(UnboundedSender<FollowUp>),
+    /// No agent is answering, so replies get placeholder answers
+    Unanswered,
+    Closed,
+}
+
+impl Conversation {
+    /// A conversation whose replies go to `follow_ups`, or get placeholder
+    /// answers with `None`, after the agent first answered with `answer`.
+    pub(crate) fn new(follow_ups: Option<UnboundedSender<FollowUp>>, answer: Value) -> Self {
+        let state = match follow_ups {
+            Some(tx) => ConversationState::Open(tx),
+            None => ConversationState::Unanswered,
+        };
+        Conversation { state: Mutex::new(state), answer: Mutex::new(answer) }
+    }
+
+    /// The agent's latest answer.
+    pub fn answer(&self) -> Value {
+        self.answer.lock().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+
+    pub(crate) fn set_answer(&self, answer: Value) {
+        *self.answer.lock().unwrap_or_else(|e| e.into_inner()) = answer;
+    }
+
+    /// Send the program's next turn. Returns a receiver for the agent's
+    /// responses, or None if no agent is answering.
+    pub(crate) fn reply(&self, prompt: String) -> Result<Option<mpsc::Receiver<ThinkResponse>>, String> {
+        match &*self.state.lock().unwrap_or_else(|e| e.into_inner()) {
+            ConversationState::Open(tx) => {
+                let (response_tx, response_rx) = mpsc::channel();
+                tx.send(FollowUp { prompt, response_tx })
+                    .map_err(|_| "The agent has ended the conversation".to_string())?;
+                Ok(Some(response_rx))
+            }
+            ConversationState::Unanswered => Ok(None),
+            ConversationState::Closed => Err("The conversation is closed".to_string()),
+        }
+    }
+
+    /// End the conversation, letting the agent close its session. Closing
+    /// it again does nothing.
+    pub(crate) fn close(&self) {
+        *self.state.lock().unwrap_or_else(|e| e.into_inner()) = ConversationState::Closed;
+    }
+}
+
+impl fmt::Debug for Conversation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Conversation").finish_non_exhaustive()
+    }
+}
+
+/// Conversations are equal only to themselves.
+impl PartialEq for Conversation {
+    fn eq(&self, other: &Self) -> bool {
+        std::ptr::eq(self, other)
+    }
 }
@@ -0,0 +1,176 @@
+//! Operator control of a running evaluation through a watched file.
+//!
+//! Unattended runs can be steered from outside by writing one command to the
+//! control file:
+//!
+//! - `pause`: stop before the next statement until the command changes
+//! - `resume` (or an empty or missing file): keep running
+//! - `abort`: stop the run with an error
+//! - `status`: describe the statement about to run in `<file>.status`
+//!
+//! The file is read between statements, at most once per `CHECK_INTERVAL`.
+//! While paused, the status file says where the run stopped.
+
+use std::fs;
+use std::path::PathBuf;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use patchwork_parser::ast::{Spanned, Statement};
+
+use crate::error::Error;
+use crate::history::line_of;
+
+/// Minimum time between reads of the control file.
+pub const CHECK_INTERVAL: Duration = Duration::from_millis(100);
+
+/// A command read from the control file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ControlCommand {
+    Resume,
+    Pause,
+    Abort,
+    Status,
+}
+
+impl ControlCommand {
+    /// Parse the control file's contents; an empty file means `Resume`.
+    pub fn parse(text: &str) -> Option<Self> {
+        match text.trim() {
+            "" | "resume" => Some(ControlCommand::Resume),
+            "pause" => Some(ControlCommand::Pause),
+            "abort" => Some(ControlCommand::Abort),
+            "status" => Some(ControlCommand::Status),
+            _ => None,
+        }
+    }
+}
+
+/// A watched control file.
+#[derive(Debug)]
+pub struct ControlFile {
+    path: PathBuf,
+    next_check: Instant,
+}
+
+impl ControlFile {
+    pub fn new(path: PathBuf) -> Self {
+        Self { path, next_check: Instant::now() }
+    }
+
+    /// A watcher of the same file for a spawned task, which pauses, aborts,
+    /// and reports its status along with the program.
+    pub(crate) fn fork(&self) -> Self {
+        Self::new(self.path.clone())
+    }
+
+    /// Where `status` and `pause` describe the current statement.
+    pub fn status_path(&self) -> PathBuf {
+        let mut path = self.path.clone().into_os_string();
+        path.push(".status");
+        PathBuf::from(path)
+    }
+
+    /// The command in the file. A missing file means `Resume`; unknown text
+    /// is passed to `warn` once per check and otherwise ignored.
+    fn read(&self, warn: impl Fn(String)) -> ControlCommand {
+        let text = fs::read_to_string(&self.path).unwrap_or_default();
+        ControlCommand::parse(&text).unwrap_or_else(|| {
+            warn(format!("Ignoring unknown command {:?} in {}", text.trim(), self.path.display()));
+            ControlCommand::Resume
+        })
+    }
+
+    /// Act on the file before running the statement `at` describes. Blocks
+    /// while paused and fails on `abort`.
+    pub(crate) fn check(&mut self, at: impl Fn() -> String, warn: impl Fn(String)) -> Result<(), Error> {
+        let now = Instant::now();
+        if now < self.next_check {
+            return Ok(());
+        }
+        self.next_check = now + CHECK_INTERVAL;
+
+        let mut paused = false;
+        loop {
+            match self.read(&warn) {
+                ControlCommand::Resume => return Ok(()),
+                ControlCommand::Status => {
+                    let _ = fs::write(self.status_path(), format!("running {}\n", at()));
+                    return Ok(());
+                }
+                ControlCommand::Abort => {
+                    return Err(Error::Runtime(format!(
+                        "Aborted by operator at {} ({})",
+                        at(),
+                        self.path.display()
+                    )));
+                }
+                ControlCommand::Pause => {
+                    if !paused {
+                        let _ = fs::write(self.status_path(), format!("paused before {}\n", at()));
+                        paused = true;
+                    }
+                    thread::sleep(CHECK_INTERVAL);
+                }
+            }
+        }
+    }
+}
+
+/// Where `stmt` is, as its line number and source text.
+pub(crate) fn describe(source: &str, stmt: &Spanned<Statement>) -> String {
+    match line_of(source, stmt.span) {
+        Some(line) => {
+            let text = source.lines().nth(line - 1).unwrap_or_default();
+            format!("line {}: {}", line, text.trim())
+        }
+        None => "a statement without a source position".to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_control_commands() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut control = ControlFile::new(dir.path().join("control"));
+        let at = || "line 3: $ make".to_string();
+
+        // No file yet: keep running
+        assert!(control.check(at, |message| panic!("Unexpected warning: {}", message)).is_ok());
+
+        fs::write(&control.path, "status\n").unwrap();
+        control.next_check = Instant::now();
+        assert!(control.check(at, |message| panic!("Unexpected warning: {}", message)).is_ok());
+        assert_eq!(fs::read_to_string(control.status_path()).unwrap(), "running line 3: $ make\n");
+
+        // Pause holds the run until the operator resumes it
+        fs::write(&control.path, "pause").unwrap();
+        let path = control.path.clone();
+        let resumer = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(250));
+            fs::write(path, "resume").unwrap();
+        });
+        control.next_check = Instant::now();
+        let started = Instant::now();
+        assert!(control.check(at, |message| panic!("Unexpected warning: {}", message)).is_ok());
+        assert!(started.elapsed() >= Duration::from_millis(200));
+        resumer.join().unwrap();
+
+        fs::write(&control.path, "abort").unwrap();
+        control.next_check = Instant::now();
+        match control.check(at, |message| panic!("Unexpected warning: {}", message)) {
+            Err(Error::Runtime(msg)) => assert!(msg.starts_with("Aborted by operator at line 3"), "{}", msg),
+            other => panic!("Expected an abort, got {:?}", other),
+        }
+
+        // Unknown commands are reported and otherwise ignored
+        fs::write(&control.path, "stop").unwrap();
+        control.next_check = Instant::now();
+        let warnings = std::cell::RefCell::new(Vec::new());
+        assert!(control.check(at, |message| warnings.borrow_mut().push(message)).is_ok());
+        assert_eq!(warnings.into_inner().len(), 1);
+    }
+}
@@ -0,0 +1,283 @@
+//! Text and JSON diffs, for the `diff.*` builtins.
+//!
+//! Diffs are ordinary values, so programs can inspect them and pass them to
+//! agents. A text diff is an array of hunks:
+//!
+//! ```text
+//! {old_start: 3, old_lines: 2, new_start: 3, new_lines: 3,
+//!  lines: [{kind: "context", text: "..."}, {kind: "removed", ...}, {kind: "added", ...}]}
+//! ```
+//!
+//! with 1-based line numbers, as in a unified diff. A JSON diff is an array
+//! of changes, `{path: "steps[2].name", kind: "changed", old: ..., new: ...}`,
+//! where `kind` is `added`, `removed`, or `changed`.
+
+use std::collections::BTreeSet;
+
+use indexmap::IndexMap;
+
+use crate::eval::type_name;
+use crate::value::Value;
+
+/// Unchanged lines kept around each change in a hunk.
+const CONTEXT_LINES: usize = 3;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Edit {
+    Equal,
+    Delete,
+    Insert,
+}
+
+/// The shortest edit script turning `old` into `new` (Myers' algorithm).
+fn edits(old: &[&str], new: &[&str]) -> Vec<Edit> {
+    let (n, m) = (old.len() as isize, new.len() as isize);
+    let max = (n + m) as usize;
+    let offset = max as isize + 1;
+    // Furthest x reached on each diagonal k = x - y, saved per step for the backtrack
+    let mut furthest = vec![0isize; 2 * max + 3];
+    let mut trace = Vec::new();
+
+    'search: for d in 0..=max as isize {
+        trace.push(furthest.clone());
+        for k in (-d..=d).step_by(2) {
+            let i = (k + offset) as usize;
+            let mut x = if k == -d || (k != d && furthest[i - 1] < furthest[i + 1]) {
+                furthest[i + 1]
+            } else {
+                furthest[i - 1] + 1
+            };
+            let mut y = x - k;
+            while x < n && y < m && old[x as usize] == new[y as usize] {
+                x += 1;
+                y += 1;
+            }
+            furthest[i] = x;
+            if x >= n && y >= m {
+                break 'search;
+            }
+        }
+    }
+
+    // Walk back from the end, one step of the search at a time
+    let mut script = Vec::new();
+    let (mut x, mut y) = (n, m);
+    for (d, furthest) in trace.iter().enumerate().rev() {
+        let d = d as isize;
+        let k = x - y;
+        let i = (k + offset) as usize;
+        let prev_k = if k == -d || (k != d && furthest[i - 1] < furthest[i + 1]) { k + 1 } else { k - 1 };
+        let prev_x = if d == 0 { 0 } else { furthest[(prev_k + offset) as usize] };
+        let prev_y = prev_x - prev_k;
+        while x > prev_x && y > prev_y {
+            script.push(Edit::Equal);
+            x -= 1;
+            y -= 1;
+        }
+        if d > 0 {
+            script.push(if x == prev_x { Edit::Insert } else { Edit::Delete });
+        }
+        x = prev_x;
+        y = prev_y;
+    }
+    script.reverse();
+    script
+}
+
+fn object(fields: Vec<(&str, Value)>) -> Value {
+    Value::Object(fields.into_iter().map(|(key, value)| (key.to_string(), value)).collect())
+}
+
+/// Line diff of two texts, as hunks with `CONTEXT_LINES` of context.
+pub(crate) fn text(old: &str, new: &str) -> Value {
+    let old: Vec<&str> = old.lines().collect();
+    let new: Vec<&str> = new.lines().collect();
+    let script = edits(&old, &new);
+
+    // Every line of the script with its position in each text
+    let mut lines = Vec::with_capacity(script.len());
+    let (mut o, mut n) = (0, 0);
+    for edit in script {
+        lines.push((edit, o, n));
+        match edit {
+            Edit::Equal => {
+                o += 1;
+                n += 1;
+            }
+            Edit::Delete => o += 1,
+            Edit::Insert => n += 1,
+        }
+    }
+
+    let mut hunks = Vec::new();
+    let mut i = 0;
+    while let Some(first_change) = lines[i..].iter().position(|(edit, _, _)| *edit != Edit::Equal) {
+        let start = (i + first_change).saturating_sub(CONTEXT_LINES).max(i);
+        // Extend the hunk while changes are close enough to share context
+        let mut end = i + first_change;
+        let mut last_change = end;
+        while end < lines.len() && end - last_change <= 2 * CONTEXT_LINES {
+            if lines[end].0 != Edit::Equal {
+                last_change = end;
+            }
+            end += 1;
+        }
+        let end = (last_change + 1 + CONTEXT_LINES).min(lines.len());
+
+        let hunk = &lines[start..end];
+        let (_, old_start, new_start) = hunk[0];
+        let old_lines = hunk.iter().filter(|(edit, _, _)| *edit != Edit::Insert).count();
+        let new_lines = hunk.iter().filter(|(edit, _, _)| *edit != Edit::Delete).count();
+        let hunk_lines = hunk
+            .iter()
+            .map(|(edit, o, n)| {
+                let (kind, text) = match edit {
+                    Edit::Equal => ("context", old[*o]),
+                    Edit::Delete => ("removed", old[*o]),
+                    Edit::Insert => ("added", new[*n]),
+                };
+                object(vec![("kind", Value::String(kind.to_string())), ("text", Value::String(text.to_string()))])
+            })
+            .collect();
+        // An empty side starts at the line before, as in unified diffs
+        let line_number = |start: usize, count: usize| (if count == 0 { start } else { start + 1 }) as f64;
+        hunks.push(object(vec![
+            ("old_start", Value::Number(line_number(old_start, old_lines))),
+            ("old_lines", Value::Number(old_lines as f64)),
+            ("new_start", Value::Number(line_number(new_start, new_lines))),
+            ("new_lines", Value::Number(new_lines as f64)),
+            ("lines", Value::Array(hunk_lines)),
+        ]));
+        i = end;
+    }
+    Value::Array(hunks)
+}
+
+/// Structural diff of two values, as changes at JSON paths.
+pub(crate) fn json(old: &Value, new: &Value) -> Value {
+    let mut changes = Vec::new();
+    json_changes("", old, new, &mut changes);
+    Value::Array(changes)
+}
+
+fn json_changes(path: &str, old: &Value, new: &Value, changes: &mut Vec<Value>) {
+    // Children present on one side only are added or removed whole
+    let mut compare = |path: String, old: Option<&Value>, new: Option<&Value>| match (old, new) {
+        (Some(old), Some(new)) => json_changes(&path, old, new, changes),
+        (old, new) => changes.push(change(path, if old.is_some() { "removed" } else { "added" }, old, new)),
+    };
+    match (old, new) {
+        (Value::Object(old), Value::Object(new)) => {
+            let keys: BTreeSet<&String> = old.keys().chain(new.keys()).collect();
+            for key in keys {
+                let path = if path.is_empty() { key.clone() } else { format!("{}.{}", path, key) };
+                compare(path, old.get(key), new.get(key));
+            }
+        }
+        (Value::Array(old), Value::Array(new)) => {
+            for i in 0..old.len().max(new.len()) {
+                compare(format!("{}[{}]", path, i), old.get(i), new.get(i));
+            }
+        }
+        (old, new) if old == new => {}
+        (old, new) => {
+            let path = if path.is_empty() { "$".to_string() } else { path.to_string() };
+            changes.push(change(path, "changed", Some(old), Some(new)));
+        }
+    }
+}
+
+fn change(path: String, kind: &str, old: Option<&Value>, new: Option<&Value>) -> Value {
+    let mut fields = vec![("path", Value::String(path)), ("kind", Value::String(kind.to_string()))];
+    fields.extend(old.map(|old| ("old", old.clone())));
+    fields.extend(new.map(|new| ("new", new.clone())));
+    object(fields)
+}
+
+/// Render a diff from `text` or `json` for reading: unified diff hunks, or
+/// one line per JSON change.
+pub(crate) fn render(diff: &Value) -> Result<String, String> {
+    let Value::Array(entries) = diff else {
+        return Err(format!("diff.render() expects a diff, got {}", type_name(diff)));
+    };
+    let mut out = String::new();
+    for entry in entries {
+        let Value::Object(fields) = entry else {
+            return Err(format!("diff.render() expects a diff, got an array of {}", type_name(entry)));
+        };
+        if let Some(Value::Array(lines)) = fields.get("lines") {
+            render_hunk(fields, lines, &mut out);
+        } else {
+            render_change(fields, &mut out);
+        }
+    }
+    Ok(out)
+}
+
+fn render_hunk(fields: &IndexMap<String, Value>, lines: &[Value], out: &mut String) {
+    let number = |key: &str| fields.get(key).map(Value::to_string_value).unwrap_or_default();
+    out.push_str(&format!(
+        "@@ -{},{} +{},{} @@\n",
+        number("old_start"),
+        number("old_lines"),
+        number("new_start"),
+        number("new_lines")
+    ));
+    for line in lines {
+        let Value::Object(line) = line else { continue };
+        let prefix = match line.get("kind").map(Value::to_string_value).as_deref() {
+            Some("removed") => '-',
+            Some("added") => '+',
+            _ => ' ',
+        };
+        let text = line.get("text").map(Value::to_string_value).unwrap_or_default();
+        out.push_str(&format!("{}{}\n", prefix, text));
+    }
+}
+
+fn render_change(fields: &IndexMap<String, Value>, out: &mut String) {
+    let path = fields.get("path").map(Value::to_string_value).unwrap_or_default();
+    let json = |key: &str| fields.get(key).map(literal).unwrap_or_default();
+    match fields.get("kind").map(Value::to_string_value).as_deref() {
+        Some("added") => out.push_str(&format!("+ {}: {}\n", path, json("new"))),
+        Some("removed") => out.push_str(&format!("- {}: {}\n", path, json("old"))),
+        _ => out.push_str(&format!("~ {}: {} -> {}\n", path, json("old"), json("new"))),
+    }
+}
+
+/// Strings and containers as JSON, other values as they print.
+fn literal(value: &Value) -> String {
+    match value {
+        Value::String(_) | Value::Array(_) | Value::Object(_) => value.to_json_value().to_string(),
+        other => other.to_string_value(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_text_diff_hunks() {
+        let old = "a\nb\nc\nd\ne\nf\ng\nh\ni\nj\nk\nl\nm\n";
+        let new = "a\nB\nc\nd\ne\nf\ng\nh\ni\nj\nk\nl\nm\nn\n";
+        let diff = text(old, new);
+        assert_eq!(
+            render(&diff).unwrap(),
+            "@@ -1,5 +1,5 @@\n a\n-b\n+B\n c\n d\n e\n@@ -11,3 +11,4 @@\n k\n l\n m\n+n\n"
+        );
+        assert_eq!(text("same\n", "same\n"), Value::Array(Vec::new()));
+        assert_eq!(render(&text("", "x")).unwrap(), "@@ -0,0 +1,1 @@\n+x\n");
+    }
+
+    #[test]
+    fn test_json_diff_changes() {
+        let old = Value::from_json(r#"{"name": "ci", "steps": ["build", "test"], "env": {"CI": "1"}}"#).unwrap();
+        let new = Value::from_json(r#"{"name": "ci", "steps": ["build", "lint", "test"], "timeout": 10}"#).unwrap();
+        assert_eq!(
+            render(&json(&old, &new)).unwrap(),
+            "- env: {\"CI\":\"1\"}\n~ steps[1]: \"test\" -> \"lint\"\n+ steps[2]: \"test\"\n+ timeout: 10\n"
+        );
+        assert_eq!(render(&json(&Value::Number(1.0), &Value::Number(2.0))).unwrap(), "~ $: 1 -> 2\n");
+    }
+}
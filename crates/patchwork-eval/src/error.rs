@@ -11,9 +11,17 @@ pub enum Error {
     Parse(String),
     /// A runtime error occurred.
     Runtime(String),
+    /// A runtime error that ended the program, with the 1-based line and
+    /// column of the statement it came from.
+    RuntimeAt { message: String, line: usize, column: usize },
     /// A Patchwork exception was thrown (via `throw` keyword).
     /// This propagates up the call stack using Rust's `?` operator.
     Exception(Value),
+    /// A `break` statement is unwinding to the nearest enclosing loop.
+    /// Loops catch this; if it escapes, the `break` was outside any loop.
+    Break,
+    /// A `return` statement is unwinding to the enclosing function call.
+    Return(Value),
 }
 
 impl fmt::Display for Error {
@@ -21,7 +29,17 @@ impl fmt::Display for Error {
         match self {
             Error::Parse(msg) => write!(f, "Parse error: {}", msg),
             Error::Runtime(msg) => write!(f, "Runtime error: {}", msg),
+            Error::RuntimeAt { message, line, column } => {
+                write!(f, "Runtime error at line {}, column {}: {}", line, column, message)
+            }
+            Error::Exception(Value::Object(fields)) if fields.contains_key("__tag") && fields.contains_key("message") => {
+                // A tagged error such as `TimeoutError { message }` reads as its tag and message
+                let text = |key: &str| fields[key].to_string_value();
+                write!(f, "Exception: {}: {}", text("__tag"), text("message"))
+            }
             Error::Exception(value) => write!(f, "Exception: {}", value.to_string_value()),
+            Error::Break => write!(f, "Runtime error: break outside of loop"),
+            Error::Return(_) => write!(f, "Runtime error: return outside of function"),
         }
     }
 }
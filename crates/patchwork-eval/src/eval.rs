@@ -6,23 +6,47 @@
 //!
 //! Think blocks block on channel operations waiting for LLM responses from the agent.
 
-use std::collections::HashMap;
+use std::cell::Cell;
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::fs;
-use std::process::Command;
-
+use std::io::{self, Write};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use hmac::{Hmac, Mac};
+use indexmap::IndexMap;
 use patchwork_parser::ast::{
-    Block, BinOp, CommandArg, Expr, ObjectPatternField, Pattern, Program,
-    RedirectOp, Statement, StringLiteral, StringPart, UnOp, PromptBlock, PromptItem,
+    Block, BinOp, CommandArg, Expr, FragmentDecl, FunctionDecl, MatchArm, MatchPattern, ObjectPatternField, Pattern, Program,
+    RedirectOp, Spanned, Statement, StringLiteral, StringPart, UnOp, PromptBlock, PromptItem, PromptValidator,
 };
+use regex::Regex;
+use sha2::{Digest, Sha256};
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver};
 
-use crate::agent::{AgentHandle, ThinkResponse};
+use crate::agent::{AgentHandle, Conversation, FollowUp, ThinkResponse};
+use crate::diff;
 use crate::error::Error;
-use crate::runtime::{PlanEntry, PlanEntryStatus, PlanUpdate, Runtime};
-use crate::value::Value;
+use crate::events::RuntimeEvent;
+use crate::formats;
+use crate::git;
+use crate::methods;
+use crate::objects;
+use crate::render;
+use crate::runtime::{LogLevel, ModuleId, PlanEntry, PlanEntryStatus, PlanUpdate, PromptKind, Runtime, VariantPolicy};
+use crate::scheduler;
+use crate::shell::{ShellCommand, Tick};
+use crate::stdlib;
+use crate::template;
+use crate::types::{Type, TypeCheckMode};
+use crate::value::{Canonical, Closure, ShellResult, Value, DURATION_UNITS, SIZE_UNITS};
 
 /// Evaluate a complete program.
 pub fn eval_program(
-    program: &Program,
+    program: &Program<'static>,
     runtime: &mut Runtime,
     agent: Option<&AgentHandle>,
 ) -> Result<Value, Error> {
@@ -36,39 +60,127 @@ pub fn eval_program(
     Ok(Value::Null)
 }
 
+/// Stack space to keep free before evaluating one level deeper.
+const STACK_RED_ZONE: usize = 256 * 1024;
+/// Size of each extra stack segment allocated for deep evaluation.
+const STACK_SEGMENT: usize = 4 * 1024 * 1024;
+
+/// Run one level of nested evaluation.
+///
+/// Deep nesting and deep recursion would otherwise overflow the Rust stack, so
+/// each level counts against the runtime's recursion limit and runs on a
+/// freshly allocated stack segment when the current one is nearly full.
+fn nested(
+    runtime: &mut Runtime,
+    eval: impl FnOnce(&mut Runtime) -> Result<Value, Error>,
+) -> Result<Value, Error> {
+    runtime.enter_nested().map_err(Error::Runtime)?;
+    let result = stacker::maybe_grow(STACK_RED_ZONE, STACK_SEGMENT, || eval(runtime));
+    runtime.exit_nested();
+    result
+}
+
 /// Evaluate a block of statements.
 pub fn eval_block(
-    block: &Block,
+    block: &Block<'static>,
+    runtime: &mut Runtime,
+    agent: Option<&AgentHandle>,
+) -> Result<Value, Error> {
+    nested(runtime, |runtime| eval_block_statements(block, runtime, agent))
+}
+
+/// Evaluate a block's statements in the current scope, so the variables it
+/// declares outlive it.
+pub(crate) fn eval_block_in_place(
+    block: &Block<'static>,
+    runtime: &mut Runtime,
+    agent: Option<&AgentHandle>,
+) -> Result<Value, Error> {
+    nested(runtime, |runtime| eval_statements(block, runtime, agent))
+}
+
+/// Every block gets its own scope, so a `var` declared in an `if` branch or
+/// a loop body is gone once the block ends, and each loop iteration starts
+/// with a fresh one. A nested block may shadow an outer variable; declaring
+/// the same name twice in one scope is an error. These match JavaScript's
+/// `let`, including that a function body shares its parameters' scope.
+fn eval_block_statements(
+    block: &Block<'static>,
     runtime: &mut Runtime,
     agent: Option<&AgentHandle>,
 ) -> Result<Value, Error> {
     runtime.push_scope();
-    let mut result = Value::Null;
+    let result = eval_statements(block, runtime, agent);
+    // Pop even on error so unwinding (break, exceptions) leaves scopes balanced
+    runtime.pop_scope();
+    result
+}
+
+/// Evaluate a block's statements in the current scope.
+fn eval_statements(
+    block: &Block<'static>,
+    runtime: &mut Runtime,
+    agent: Option<&AgentHandle>,
+) -> Result<Value, Error> {
+    let mut result = Ok(Value::Null);
 
     for stmt in &block.statements {
-        result = eval_statement(stmt, runtime, agent)?;
+        runtime.check_control(stmt)?;
+        runtime.emit_statement(stmt);
+        let outer = runtime.set_statement_span(Some(stmt.span));
+        runtime.clear_error_span();
+        result = eval_statement(stmt, runtime, agent);
+        runtime.set_statement_span(outer);
+        runtime.record_snapshot(stmt);
+        if result.is_err() {
+            // Spans in an imported module's code are in another file, so the
+            // program's statement that called into it stands for them
+            if let (Err(Error::Runtime(_)), None) = (&result, runtime.module()) {
+                runtime.note_error_span(stmt.span);
+            }
+            break;
+        }
     }
-
-    runtime.pop_scope();
-    Ok(result)
+    result
 }
 
 /// Evaluate a single statement.
 pub fn eval_statement(
-    stmt: &Statement,
+    stmt: &Statement<'static>,
     runtime: &mut Runtime,
     agent: Option<&AgentHandle>,
 ) -> Result<Value, Error> {
     match stmt {
         Statement::VarDecl { pattern, init } => {
             let value = match init {
-                Some(expr) => eval_expr(expr, runtime, agent)?,
+                Some(init) => eval_initializer(pattern, init, runtime, agent)?,
                 None => Value::Null,
             };
-            bind_pattern(pattern, value, runtime)?;
+            bind_pattern(pattern, value, runtime, false)?;
+            Ok(Value::Null)
+        }
+
+        Statement::ConstDecl { pattern, init } => {
+            let value = eval_initializer(pattern, init, runtime, agent)?;
+            bind_pattern(pattern, value, runtime, true)?;
             Ok(Value::Null)
         }
 
+        Statement::Timeout { limit, body } => {
+            let limit_ms = match eval_expr(limit, runtime, agent)? {
+                Value::Duration(ms) => ms,
+                other => {
+                    return Err(Error::Runtime(format!(
+                        "@timeout expects a duration, got {}", type_name(&other)
+                    )))
+                }
+            };
+            runtime.push_deadline(limit_ms);
+            let result = eval_statement(body, runtime, agent);
+            runtime.pop_deadline();
+            result
+        }
+
         Statement::Expr(expr) => eval_expr(expr, runtime, agent),
 
         Statement::If { condition, then_block, else_block } => {
@@ -83,15 +195,22 @@ pub fn eval_statement(
             }
         }
 
-        Statement::ForIn { var, iter, body } => {
+        Statement::ForIn { pattern, iter, body } => {
             let iter_value = eval_expr(iter, runtime, agent)?;
 
+            // Objects iterate as `{key, value}` entries, and show in the
+            // plan by key
+            let mut keys = None;
             let items = match iter_value {
                 Value::Array(arr) => arr,
                 Value::String(s) => {
                     // Iterate over lines
                     s.lines().map(|line| Value::String(line.to_string())).collect()
                 }
+                Value::Object(map) => {
+                    keys = Some(map.keys().cloned().collect::<Vec<_>>());
+                    entries(map)
+                }
                 other => {
                     return Err(Error::Runtime(format!(
                         "Cannot iterate over {}", type_name(&other)
@@ -101,14 +220,20 @@ pub fn eval_statement(
 
             // Emit a thought chunk announcing the loop
             if !items.is_empty() {
-                let thought = generate_loop_thought(var, items.len());
+                let noun = match &pattern.node {
+                    Pattern::Identifier { name, .. } => name,
+                    _ => "item",
+                };
+                let thought = generate_loop_thought(noun, items.len());
                 runtime.report_thought(thought);
             }
 
             // Build the initial plan with all entries as pending
-            let item_strings: Vec<String> = items.iter()
-                .map(|v| v.to_string_value())
-                .collect();
+            let item_strings: Vec<String> = keys.unwrap_or_else(|| {
+                items.iter()
+                    .map(|v| v.to_string_value())
+                    .collect()
+            });
 
             // Report initial plan (all pending)
             if !item_strings.is_empty() {
@@ -142,9 +267,14 @@ pub fn eval_statement(
                 }
 
                 runtime.push_scope();
-                runtime.define_var(var, item).map_err(Error::Runtime)?;
-                result = eval_block(body, runtime, agent)?;
+                let body_result = bind_pattern(pattern, item, runtime, false)
+                    .and_then(|_| eval_block(body, runtime, agent));
                 runtime.pop_scope();
+                match body_result {
+                    Ok(value) => result = value,
+                    Err(Error::Break) => break,
+                    Err(e) => return Err(e),
+                }
             }
 
             // Report final plan (all completed)
@@ -170,38 +300,122 @@ pub fn eval_statement(
                     break;
                 }
 
-                result = eval_block(body, runtime, agent)?;
+                match eval_block(body, runtime, agent) {
+                    Ok(value) => result = value,
+                    Err(Error::Break) => break,
+                    Err(e) => return Err(e),
+                }
+            }
+            Ok(result)
+        }
+
+        Statement::WhileVar { pattern, init, body } => {
+            let mut result = Value::Null;
+            loop {
+                let value = eval_expr(init, runtime, agent)?;
+
+                if value.is_null() {
+                    break;
+                }
+
+                runtime.push_scope();
+                let body_result = bind_pattern(pattern, value, runtime, false)
+                    .and_then(|_| eval_block(body, runtime, agent));
+                runtime.pop_scope();
+                match body_result {
+                    Ok(value) => result = value,
+                    Err(Error::Break) => break,
+                    Err(e) => return Err(e),
+                }
             }
             Ok(result)
         }
 
+        Statement::Try { body, catch, finally_block } => {
+            let mut result = eval_block(body, runtime, agent);
+            if let (Some(catch), Some(caught)) = (catch, result.as_ref().err().and_then(caught_value)) {
+                runtime.push_scope();
+                result = match &catch.var {
+                    Some(var) => runtime.define_var(var, caught).map_err(Error::Runtime),
+                    None => Ok(()),
+                }
+                .and_then(|_| eval_block(&catch.body, runtime, agent));
+                runtime.pop_scope();
+            }
+            // The finally block runs however the rest ended, `break` and
+            // `return` included; only its own failure replaces that outcome
+            if let Some(finally_block) = finally_block {
+                eval_block(finally_block, runtime, agent)?;
+            }
+            result
+        }
+
         Statement::Return(expr) => {
             let value = match expr {
                 Some(e) => eval_expr(e, runtime, agent)?,
                 None => Value::Null,
             };
-            // For now, just return the value. Proper return handling
-            // will need control flow tracking.
-            Ok(value)
+            // Unwinds to the enclosing function call, which catches it
+            Err(Error::Return(value))
         }
 
         Statement::Succeed => Ok(Value::Null),
 
         Statement::Break => {
-            // Break handling will need control flow tracking
-            Err(Error::Runtime("break outside of loop".to_string()))
+            // Unwinds to the nearest enclosing loop, which catches it
+            Err(Error::Break)
         }
 
-        Statement::TypeDecl { .. } => {
-            // Type declarations are compile-time only
+        Statement::TypeDecl { name, type_expr } => {
+            // Register the alias so later annotations can resolve through it
+            runtime.define_type(name, Type::from_expr(type_expr));
             Ok(Value::Null)
         }
     }
 }
 
-/// Bind a value to a pattern, defining variables in the runtime.
-fn bind_pattern(pattern: &Pattern, value: Value, runtime: &mut Runtime) -> Result<(), Error> {
+/// The value a `catch` clause binds for `error`: what was thrown, or the
+/// message of a failed command, call or other runtime error. Control flow
+/// unwinding through the try statement isn't caught.
+fn caught_value(error: &Error) -> Option<Value> {
+    match error {
+        Error::Exception(value) => Some(value.clone()),
+        Error::Runtime(message) | Error::RuntimeAt { message, .. } => Some(Value::String(message.clone())),
+        Error::Parse(_) | Error::Break | Error::Return(_) => None,
+    }
+}
+
+/// Evaluate the initializer of a `var` or `const` declaration.
+fn eval_initializer(
+    pattern: &Pattern<'static>,
+    init: &Expr<'static>,
+    runtime: &mut Runtime,
+    agent: Option<&AgentHandle>,
+) -> Result<Value, Error> {
+    match (init, pattern) {
+        // A typed think/ask asks for structured output and validates it
+        (
+            Expr::Think(prompt_block) | Expr::Ask(prompt_block),
+            Pattern::Identifier { name, type_ann: Some(type_ann) },
+        ) => {
+            let asked = if matches!(init, Expr::Ask(_)) { Asked::User(Some(name)) } else { Asked::Agent };
+            eval_typed_think(prompt_block, asked, &Type::from_expr(type_ann), runtime, agent)
+        }
+        // A bound ask can be answered by the variable's name
+        (Expr::Ask(prompt_block), Pattern::Identifier { name, type_ann: None }) => {
+            eval_think_block(prompt_block, Asked::User(Some(name)), "string", runtime, agent)
+        }
+        _ => eval_expr(init, runtime, agent),
+    }
+}
+
+/// Bind a value to a pattern, defining variables (or, if `constant`,
+/// constants) in the runtime.
+fn bind_pattern(pattern: &Pattern<'static>, value: Value, runtime: &mut Runtime, constant: bool) -> Result<(), Error> {
     match pattern {
+        Pattern::Identifier { name, .. } if constant => {
+            runtime.define_const(name, value).map_err(Error::Runtime)?;
+        }
         Pattern::Identifier { name, .. } => {
             runtime.define_var(name, value).map_err(Error::Runtime)?;
         }
@@ -220,8 +434,8 @@ fn bind_pattern(pattern: &Pattern, value: Value, runtime: &mut Runtime) -> Resul
                 }
             };
             for field in fields {
-                let field_value = obj.get(field.key).cloned().unwrap_or(Value::Null);
-                bind_object_pattern_field(field, field_value, runtime)?;
+                let field_value = obj.get(&*field.key).cloned().unwrap_or(Value::Null);
+                bind_object_pattern_field(field, field_value, runtime, constant)?;
             }
         }
 
@@ -234,9 +448,15 @@ fn bind_pattern(pattern: &Pattern, value: Value, runtime: &mut Runtime) -> Resul
                     )));
                 }
             };
-            for (i, pat) in patterns.iter().enumerate() {
-                let item_value = arr.get(i).cloned().unwrap_or(Value::Null);
-                bind_pattern(pat, item_value, runtime)?;
+            // Extra elements are dropped, but every pattern needs one
+            if arr.len() < patterns.len() {
+                return Err(Error::Runtime(format!(
+                    "Array pattern expects at least {} element(s), got {}",
+                    patterns.len(), arr.len()
+                )));
+            }
+            for (pat, item_value) in patterns.iter().zip(arr) {
+                bind_pattern(pat, item_value, runtime, constant)?;
             }
         }
     }
@@ -245,25 +465,52 @@ fn bind_pattern(pattern: &Pattern, value: Value, runtime: &mut Runtime) -> Resul
 
 /// Bind an object pattern field.
 fn bind_object_pattern_field(
-    field: &ObjectPatternField,
+    field: &ObjectPatternField<'static>,
     value: Value,
     runtime: &mut Runtime,
+    constant: bool,
 ) -> Result<(), Error> {
-    bind_pattern(&field.pattern, value, runtime)
+    bind_pattern(&field.pattern, value, runtime, constant)
 }
 
 /// Evaluate an expression.
 pub fn eval_expr(
-    expr: &Expr,
+    expr: &Expr<'static>,
+    runtime: &mut Runtime,
+    agent: Option<&AgentHandle>,
+) -> Result<Value, Error> {
+    nested(runtime, |runtime| eval_expr_kind(expr, runtime, agent))
+}
+
+fn eval_expr_kind(
+    expr: &Expr<'static>,
     runtime: &mut Runtime,
     agent: Option<&AgentHandle>,
 ) -> Result<Value, Error> {
     match expr {
         Expr::Identifier(name) => {
-            let value = runtime.get_var(name)
-                .cloned()
-                .ok_or_else(|| Error::Runtime(format!("Undefined variable: {}", name)))?;
-            Ok(value)
+            if let Some(value) = runtime.get_var(name) {
+                return Ok(value.clone());
+            }
+            // A named function can be passed around like a closure
+            match runtime.resolve_function(name) {
+                Some((decl, module)) => Ok(Value::Closure(Arc::new(Closure { decl, captured: HashMap::new(), module }))),
+                None => Err(Error::Runtime(format!("Undefined variable: {}", name))),
+            }
+        }
+
+        Expr::Lambda { params, body } => {
+            let decl = FunctionDecl {
+                name: "anonymous".into(),
+                params: params.clone(),
+                body: body.clone(),
+                annotations: Vec::new(),
+                is_exported: false,
+                is_default: false,
+                is_memo: false,
+            };
+            let captured = runtime.capture_locals();
+            Ok(Value::Closure(Arc::new(Closure { decl: Arc::new(decl), captured, module: runtime.module() })))
         }
 
         Expr::Number(s) => {
@@ -272,6 +519,10 @@ pub fn eval_expr(
             Ok(Value::Number(n))
         }
 
+        Expr::Duration(s) => Ok(Value::Duration(parse_unit_literal(s, DURATION_UNITS)?)),
+
+        Expr::Size(s) => Ok(Value::Size(parse_unit_literal(s, SIZE_UNITS)?)),
+
         Expr::String(string_lit) => eval_string_literal(string_lit, runtime, agent),
 
         Expr::True => Ok(Value::Boolean(true)),
@@ -286,13 +537,13 @@ pub fn eval_expr(
         }
 
         Expr::Object(fields) => {
-            let mut map = std::collections::HashMap::new();
+            let mut map = IndexMap::new();
             for field in fields {
                 let value = match &field.value {
                     Some(expr) => eval_expr(expr, runtime, agent)?,
                     None => {
                         // Shorthand: {x} means {x: x}
-                        runtime.get_var(field.key)
+                        runtime.get_var(&field.key)
                             .cloned()
                             .ok_or_else(|| Error::Runtime(format!("Undefined variable: {}", field.key)))?
                     }
@@ -302,6 +553,22 @@ pub fn eval_expr(
             Ok(Value::Object(map))
         }
 
+        Expr::Variant { tag, fields } => {
+            // Variants are objects carrying their tag under `__tag`
+            let mut map = IndexMap::new();
+            map.insert("__tag".to_string(), Value::String(tag.to_string()));
+            for field in fields {
+                let value = match &field.value {
+                    Some(expr) => eval_expr(expr, runtime, agent)?,
+                    None => runtime.get_var(&field.key)
+                        .cloned()
+                        .ok_or_else(|| Error::Runtime(format!("Undefined variable: {}", field.key)))?,
+                };
+                map.insert(field.key.to_string(), value);
+            }
+            Ok(Value::Object(map))
+        }
+
         Expr::Binary { op, left, right } => eval_binary(op, left, right, runtime, agent),
 
         Expr::Unary { op, operand } => eval_unary(op, operand, runtime, agent),
@@ -313,8 +580,19 @@ pub fn eval_expr(
 
             match obj_value {
                 Value::Object(map) => {
-                    Ok(map.get(*field).cloned().unwrap_or(Value::Null))
+                    Ok(map.get(&**field).cloned().unwrap_or(Value::Null))
                 }
+                Value::String(s) => methods::string_property(&s, field).ok_or_else(|| {
+                    Error::Runtime(format!("Cannot access field '{}' on string", field))
+                }),
+                Value::Shell(result) => result
+                    .field(field)
+                    .or_else(|| methods::string_property(result.text(), field))
+                    .ok_or_else(|| Error::Runtime(format!("Cannot access field '{}' on command output", field))),
+                Value::Array(items) => methods::array_property(&items, field).ok_or_else(|| {
+                    Error::Runtime(format!("Cannot access field '{}' on array", field))
+                }),
+                Value::Conversation(conversation) if *field == "answer" => Ok(conversation.answer()),
                 other => Err(Error::Runtime(format!(
                     "Cannot access field '{}' on {}", field, type_name(&other)
                 )))
@@ -347,20 +625,41 @@ pub fn eval_expr(
         Expr::Paren(inner) => eval_expr(inner, runtime, agent),
 
         Expr::Await(inner) => {
-            // In synchronous evaluation, await is a no-op
-            eval_expr(inner, runtime, agent)
+            let value = eval_expr(inner, runtime, agent)?;
+            join_task(value, runtime)
         }
 
-        Expr::Think(prompt_block) => eval_think_block(prompt_block, runtime, agent),
+        Expr::AwaitAll(inner) => {
+            let items = match eval_expr(inner, runtime, agent)? {
+                Value::Array(items) => items,
+                other => return Err(Error::Runtime(format!("await all expects an array, got {}", type_name(&other)))),
+            };
+            // Join every task before reporting a failure, so none is left running unseen
+            let results: Vec<_> = items.into_iter().map(|item| join_task(item, runtime)).collect();
+            results.into_iter().collect::<Result<Vec<_>, _>>().map(Value::Array)
+        }
 
-        Expr::Ask(prompt_block) => eval_think_block(prompt_block, runtime, agent),
+        Expr::Think(prompt_block) => eval_think_block(prompt_block, Asked::Agent, "string", runtime, agent),
+
+        Expr::Ask(prompt_block) => eval_think_block(prompt_block, Asked::User(None), "string", runtime, agent),
 
         Expr::Do(block) => eval_block(block, runtime, agent),
 
+        Expr::Match { subject, arms } => eval_match(subject, arms, runtime, agent),
+
         Expr::BareCommand { name, args } => eval_bare_command(name, args, runtime, agent),
 
         Expr::CommandSubst(inner) => {
-            // Execute inner expression as command, return stdout
+            // A single command gives its whole result, and doesn't fail when
+            // the command does, so the program can check its status
+            if let Expr::BareCommand { name, args } = &inner.node {
+                let args = eval_command_args(args, runtime, agent)?;
+                let result = exec_command(name, &args, runtime)?;
+                if result.success() && lists_lines(name, &args) {
+                    return Ok(command_value(name, &args, result));
+                }
+                return Ok(Value::Shell(Arc::new(result)));
+            }
             let result = eval_expr(inner, runtime, agent)?;
 
             match result {
@@ -404,7 +703,7 @@ pub fn eval_expr(
 
 /// Evaluate a string literal with interpolation.
 fn eval_string_literal(
-    lit: &StringLiteral,
+    lit: &StringLiteral<'static>,
     runtime: &mut Runtime,
     agent: Option<&AgentHandle>,
 ) -> Result<Value, Error> {
@@ -457,34 +756,281 @@ fn process_escape_sequences(s: &str) -> String {
     result
 }
 
+/// Who a prompt block is for.
+#[derive(Debug, Clone, Copy)]
+enum Asked<'a> {
+    /// A think block, for the agent
+    Agent,
+    /// An ask block, with the variable its answer is bound to, if any
+    User(Option<&'a str>),
+}
+
 /// Evaluate a think or ask block.
 ///
 /// If an agent is available, this blocks on the agent channel waiting for the
-/// LLM response. Otherwise, it returns a placeholder with the interpolated prompt.
+/// LLM response. Embedded do-blocks are left in the prompt as `do(N)` markers
+/// and only run when the agent asks for them by index. Without an agent, each
+/// do-block runs in place and its output is spliced into the prompt. An ask
+/// block then goes to the person running the program, if the runtime can
+/// prompt them; otherwise the result is a placeholder holding the
+/// interpolated prompt.
 fn eval_think_block(
-    prompt_block: &PromptBlock,
+    prompt_block: &PromptBlock<'static>,
+    asked: Asked,
+    expect: &str,
     runtime: &mut Runtime,
     agent: Option<&AgentHandle>,
 ) -> Result<Value, Error> {
-    // Interpolate the prompt text
-    let mut prompt_text = String::new();
+    runtime.charge_think().map_err(Error::Runtime)?;
+
+    let examples = prompt_block
+        .examples
+        .iter()
+        .map(|name| runtime.get_example(name).ok_or_else(|| Error::Runtime(format!("Unknown example '{}'", name))))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let fragments = runtime.fragments().clone();
+
+    // Interpolate the prompt text, after any attached examples
+    let mut prompt =
+        RenderedPrompt { text: String::new(), children: Vec::new(), fragments: &fragments, splicing: Vec::new() };
+    for example in &examples {
+        prompt.text.push_str("<example>\n");
+        prompt.push_items(&example.items, runtime, agent)?;
+        prompt.text.push_str("\n</example>\n\n");
+    }
+    prompt.push_items(select_prompt_items(prompt_block, runtime), runtime, agent)?;
+    let RenderedPrompt { text: prompt_text, children, .. } = prompt;
+    let tools = match &prompt_block.tools {
+        Some(tools) => Some(tool_names(tools, runtime, agent)?),
+        None => None,
+    };
+    // A conversation keeps the agent's session open for the program's replies
+    let (turns, follow_ups) = match agent {
+        Some(_) if prompt_block.conversation => {
+            let (tx, rx) = unbounded_channel();
+            (Some(tx), Some(rx))
+        }
+        _ => (None, None),
+    };
+    let request = AgentRequest { children: &children, tools: tools.as_deref(), follow_ups: Cell::new(follow_ups) };
+    if prompt_block.conversation {
+        let answer = send_prompt(prompt_text, &request, asked, expect, runtime, agent)?;
+        return Ok(Value::Conversation(Arc::new(Conversation::new(turns, answer))));
+    }
 
-    for item in &prompt_block.items {
-        match item {
-            PromptItem::Text(text) => {
-                prompt_text.push_str(text);
-            }
-            PromptItem::Interpolation(expr) => {
-                let value = eval_expr(expr, runtime, agent)?;
-                prompt_text.push_str(&value.to_string_value());
-            }
-            PromptItem::Code(block) => {
-                // Embedded code blocks - execute them
-                let _result = eval_block(block, runtime, agent)?;
+    let Some(validator) = &prompt_block.validator else {
+        return send_prompt(prompt_text, &request, asked, expect, runtime, agent);
+    };
+    let retries = match &validator.retries {
+        Some(n) => n.parse().map_err(|_| Error::Runtime(format!("validate retries must be a whole number, got {}", n)))?,
+        None => runtime.validation_retries(),
+    };
+    // Each re-ask repeats the prompt with why the last answer was rejected
+    let mut attempt_prompt = prompt_text.clone();
+    let mut attempts = 0;
+    loop {
+        attempts += 1;
+        let answer = send_prompt(attempt_prompt, &request, asked, expect, runtime, agent)?;
+        let Some(problem) = check_answer(validator, &answer, runtime, agent)? else {
+            return Ok(answer);
+        };
+        if attempts > retries {
+            return Err(validation_error(problem, answer, attempts));
+        }
+        runtime.charge_think().map_err(Error::Runtime)?;
+        let shown = match &answer {
+            Value::String(text) => text.clone(),
+            other => other.to_json(),
+        };
+        attempt_prompt = format!(
+            "{}\n\nYour previous answer was rejected: {}\n\nPrevious answer:\n{}\n\nAnswer again, fixing the problem.",
+            prompt_text, problem, shown
+        );
+    }
+}
+
+/// What a prompt passes to the agent besides its text.
+struct AgentRequest<'a> {
+    /// The do-blocks the agent can ask to run by index
+    children: &'a [&'a Block<'static>],
+    /// The tools the agent may use, if restricted
+    tools: Option<&'a [String]>,
+    /// For `think.start`, where the agent takes the conversation's later
+    /// turns from. Taken by the first request that reaches the agent.
+    follow_ups: Cell<Option<UnboundedReceiver<FollowUp>>>,
+}
+
+/// Evaluate a `think(tools: ...)` list into tool names.
+fn tool_names(tools: &Expr<'static>, runtime: &mut Runtime, agent: Option<&AgentHandle>) -> Result<Vec<String>, Error> {
+    let invalid = |got: &Value| Error::Runtime(format!("think tools must be an array of names, got {}", type_name(got)));
+    match eval_expr(tools, runtime, agent)? {
+        Value::Array(items) => items
+            .iter()
+            .map(|item| match item {
+                Value::String(name) => Ok(name.clone()),
+                other => Err(invalid(other)),
+            })
+            .collect(),
+        other => Err(invalid(&other)),
+    }
+}
+
+/// Send a rendered prompt to the agent or the user, or take its answer from
+/// the trace being replayed.
+fn send_prompt(
+    prompt_text: String,
+    request: &AgentRequest,
+    asked: Asked,
+    expect: &str,
+    runtime: &mut Runtime,
+    agent: Option<&AgentHandle>,
+) -> Result<Value, Error> {
+    runtime.emit(RuntimeEvent::ThinkRequest { prompt: prompt_text.clone(), ask: matches!(asked, Asked::User(_)) });
+
+    // A replayed run takes the recorded answer instead of asking the agent
+    if let Some(recorded) = runtime.tracer_mut().replay_think(&prompt_text).map_err(Error::Runtime)? {
+        return recorded.map_err(Error::Runtime);
+    }
+
+    let slot = runtime.tracer_mut().begin_think(&prompt_text);
+    let result = match asked {
+        Asked::User(binding) if agent.is_none() && runtime.has_user_prompter() => {
+            ask_user(prompt_text, binding, expect, runtime)
+        }
+        _ => ask_agent(prompt_text, request, expect, runtime, agent),
+    };
+    runtime.tracer_mut().end_think(slot, &result);
+    result
+}
+
+/// Run a `validate` clause on an answer, returning why it was rejected, or
+/// None if it passed. The reason is the thrown value's message, if the
+/// clause threw one.
+fn check_answer(
+    validator: &PromptValidator<'static>,
+    answer: &Value,
+    runtime: &mut Runtime,
+    agent: Option<&AgentHandle>,
+) -> Result<Option<String>, Error> {
+    runtime.push_scope();
+    let result = runtime
+        .define_var(&validator.param, answer.clone())
+        .map_err(Error::Runtime)
+        .and_then(|_| eval_block(&validator.body, runtime, agent));
+    runtime.pop_scope();
+    match result {
+        Ok(passed) | Err(Error::Return(passed)) if passed.to_bool() => Ok(None),
+        Ok(_) | Err(Error::Return(_)) => Ok(Some("it failed the `validate` check".to_string())),
+        Err(Error::Exception(Value::Object(fields))) if fields.contains_key("message") => {
+            Ok(Some(fields["message"].to_string_value()))
+        }
+        Err(Error::Exception(value)) => Ok(Some(value.to_string_value())),
+        Err(e) => Err(e),
+    }
+}
+
+/// The text of a think or ask block, as it's rendered.
+struct RenderedPrompt<'a> {
+    text: String,
+    /// With an agent, the do-blocks it can ask to run by index
+    children: Vec<&'a Block<'static>>,
+    fragments: &'a HashMap<String, Arc<FragmentDecl<'static>>>,
+    /// The fragments being spliced in, innermost last
+    splicing: Vec<&'a str>,
+}
+
+impl<'a> RenderedPrompt<'a> {
+    /// Append the text of `items`. With an agent, do-blocks become `do(N)`
+    /// markers for the agent to run on request; without one they run in
+    /// place. `${name}` splices in the prompt fragment `name` unless a
+    /// variable by that name is in scope.
+    fn push_items(
+        &mut self,
+        items: &'a [PromptItem<'static>],
+        runtime: &mut Runtime,
+        agent: Option<&AgentHandle>,
+    ) -> Result<(), Error> {
+        for item in items {
+            match item {
+                PromptItem::Text(text) => {
+                    self.text.push_str(text);
+                }
+                PromptItem::Interpolation(Spanned { node: Expr::Identifier(name), .. })
+                    if runtime.get_var(name).is_none() && self.fragments.contains_key(&**name) =>
+                {
+                    if self.splicing.contains(&&**name) {
+                        return Err(Error::Runtime(format!("Prompt fragment '{}' includes itself", name)));
+                    }
+                    self.splicing.push(name);
+                    let fragments = self.fragments;
+                    let result = self.push_items(&fragments[&**name].items, runtime, agent);
+                    self.splicing.pop();
+                    result?;
+                }
+                PromptItem::Interpolation(expr) => {
+                    let value = eval_expr(expr, runtime, agent)?;
+                    self.text.push_str(&value.to_string_value());
+                }
+                PromptItem::Code(block) => {
+                    if agent.is_some() {
+                        self.text.push_str(&format!("do({})", self.children.len()));
+                        self.children.push(block);
+                    } else {
+                        self.text.push_str(&eval_do_block(block, runtime, agent)?);
+                    }
+                }
             }
         }
+        Ok(())
+    }
+}
+
+/// Choose the text a think or ask block runs with under the variant policy.
+///
+/// A block whose first text is unnamed offers it as "default". Policies that
+/// name a variant the block doesn't declare fall back to the first text.
+fn select_prompt_items<'a, 'input>(
+    prompt_block: &'a PromptBlock<'input>,
+    runtime: &Runtime,
+) -> &'a [PromptItem<'input>] {
+    let variants = &prompt_block.variants;
+    if variants.is_empty() {
+        return &prompt_block.items;
     }
 
+    let wanted = match runtime.variant_policy() {
+        VariantPolicy::Default => None,
+        VariantPolicy::Fixed(name) => Some(name.clone()),
+        VariantPolicy::Env(var) => std::env::var(var).ok(),
+        VariantPolicy::Random => {
+            use std::hash::{BuildHasher, Hasher};
+
+            let mut names: Vec<&str> = variants.iter().map(|v| &*v.name).collect();
+            if variants[0].items != prompt_block.items {
+                names.insert(0, "default");
+            }
+            let roll = std::collections::hash_map::RandomState::new().build_hasher().finish();
+            let name = names[roll as usize % names.len()];
+            runtime.warn(format!("Using prompt variant '{}', chosen at random", name));
+            Some(name.to_string())
+        }
+    };
+
+    wanted
+        .and_then(|name| variants.iter().find(|v| v.name == name))
+        .map_or(&prompt_block.items, |v| &v.items)
+}
+
+/// Send an interpolated prompt to the agent and wait for its answer, running
+/// do-blocks as the agent requests them.
+fn ask_agent(
+    prompt_text: String,
+    request: &AgentRequest,
+    expect: &str,
+    runtime: &mut Runtime,
+    agent: Option<&AgentHandle>,
+) -> Result<Value, Error> {
     // If we have an agent, send the think request and block waiting for response
     if let Some(agent) = agent {
         // Collect current variable bindings for context
@@ -492,40 +1038,233 @@ fn eval_think_block(
 
         // Send think request and get receiver for responses
         let rx = agent
-            .think(prompt_text.clone(), bindings, "string".to_string())
+            .think(
+                prompt_text.clone(),
+                bindings,
+                expect.to_string(),
+                request.tools.map(<[String]>::to_vec),
+                request.follow_ups.take(),
+            )
             .map_err(Error::Runtime)?;
+        return await_agent(rx, request.children, runtime, agent);
+    }
 
-        // Block waiting for responses (following threadbare pattern)
-        for response in rx {
-            match response {
-                ThinkResponse::Do { index, result_tx } => {
-                    // The LLM invoked do(index) - we need recursive evaluation
-                    // For now, send back a placeholder (full implementation needs
-                    // access to think block children)
-                    let _ = result_tx.send(format!("do({}) not yet implemented", index));
-                }
-                ThinkResponse::Complete { result } => {
-                    // Think block completed - return the value
-                    return result.map_err(Error::Runtime);
+    // No agent - return placeholder so tests can verify interpolation works
+    let mut result = IndexMap::new();
+    result.insert("__think_prompt".to_string(), Value::String(prompt_text));
+    if let Some(tools) = request.tools {
+        let names = tools.iter().cloned().map(Value::String).collect();
+        result.insert("__think_tools".to_string(), Value::Array(names));
+    }
+    Ok(Value::Object(result))
+}
+
+/// Wait for the agent's answer to a prompt, running do-blocks from
+/// `children` as the agent requests them.
+fn await_agent(
+    rx: mpsc::Receiver<ThinkResponse>,
+    children: &[&Block<'static>],
+    runtime: &mut Runtime,
+    agent: &AgentHandle,
+) -> Result<Value, Error> {
+    // Block waiting for responses (following threadbare pattern), waking
+    // up for heartbeats and the deadline of an enclosing @timeout
+    let mut wait = Wait::new("think block".to_string(), runtime);
+    loop {
+        let response = match wait.next_wakeup(runtime) {
+            Some(wakeup) => match rx.recv_timeout(wakeup.saturating_duration_since(Instant::now())) {
+                Ok(response) => response,
+                Err(RecvTimeoutError::Timeout) => {
+                    wait.tick(runtime)?;
+                    continue;
                 }
+                Err(RecvTimeoutError::Disconnected) => break,
+            },
+            None => match rx.recv() {
+                Ok(response) => response,
+                Err(_) => break,
+            },
+        };
+        match response {
+            ThinkResponse::Do { index, result_tx } => {
+                // The LLM invoked do(index) - evaluate that child and report
+                // its output back. Errors are reported as text so the LLM
+                // can react to them.
+                let text = match children.get(index) {
+                    Some(block) => match eval_do_block(block, runtime, Some(agent)) {
+                        Ok(text) => text,
+                        Err(e) => format!("Error: {}", e),
+                    },
+                    None => format!("Error: no do block with index {} ({} available)", index, children.len()),
+                };
+                let _ = result_tx.send(text);
+            }
+            ThinkResponse::Complete { result } => {
+                // Think block completed - return the value
+                return result.map_err(Error::Runtime);
             }
+            ThinkResponse::Fallback { backend, error, next } => {
+                let message = format!("Agent backend '{}' failed ({}); trying '{}'", backend, error, next);
+                runtime.emit(RuntimeEvent::AgentFallback { backend, error, next });
+                runtime.log(LogLevel::Warn, message).map_err(Error::Runtime)?;
+            }
+            ThinkResponse::Usage { tokens } => runtime.charge_tokens(tokens),
+        }
+    }
+
+    // Channel closed without Complete - error
+    Err(Error::Runtime("Think block terminated without completion".to_string()))
+}
+
+/// Send the program's next turn in a `think.start` conversation and wait
+/// for the answer, or take it from the trace being replayed.
+fn reply_in_conversation(
+    conversation: &Conversation,
+    args: &[Value],
+    runtime: &mut Runtime,
+    agent: Option<&AgentHandle>,
+) -> Result<Value, Error> {
+    let [message] = args else {
+        return Err(Error::Runtime("reply() takes exactly 1 argument".to_string()));
+    };
+    let prompt_text = message.to_string_value();
+    runtime.charge_think().map_err(Error::Runtime)?;
+    runtime.emit(RuntimeEvent::ThinkRequest { prompt: prompt_text.clone(), ask: false });
+
+    let result = match runtime.tracer_mut().replay_think(&prompt_text).map_err(Error::Runtime)? {
+        Some(recorded) => recorded.map_err(Error::Runtime),
+        None => {
+            let slot = runtime.tracer_mut().begin_think(&prompt_text);
+            let result = match (conversation.reply(prompt_text.clone()).map_err(Error::Runtime)?, agent) {
+                (Some(rx), Some(agent)) => await_agent(rx, &[], runtime, agent),
+                // No agent - a placeholder, as for a think block
+                _ => Ok(Value::Object(IndexMap::from([(
+                    "__think_prompt".to_string(),
+                    Value::String(prompt_text),
+                )]))),
+            };
+            runtime.tracer_mut().end_think(slot, &result);
+            result
         }
+    };
+    if let Ok(answer) = &result {
+        conversation.set_answer(answer.clone());
+    }
+    result
+}
+
+/// Put an ask block's prompt to the person running the program.
+///
+/// Answers to blocks that expect JSON are parsed as JSON when they can be,
+/// so a typed ask can take a number or a list.
+fn ask_user(prompt_text: String, binding: Option<&str>, expect: &str, runtime: &mut Runtime) -> Result<Value, Error> {
+    let answer = runtime
+        .prompt_user(PromptKind::Ask, binding, prompt_text, None)
+        .unwrap_or_else(|| Err("No one to answer the ask block".to_string()))
+        .map_err(Error::Runtime)?;
+    Ok(match expect {
+        "json" => Value::from_json(&answer).unwrap_or(Value::String(answer)),
+        _ => Value::String(answer),
+    })
+}
 
-        // Channel closed without Complete - error
-        return Err(Error::Runtime("Think block terminated without completion".to_string()));
+/// Evaluate a think or ask block whose result is bound to a typed variable.
+///
+/// Non-string types request JSON output from the agent, and the result is
+/// checked against the (alias-resolved) type. Without an agent the placeholder
+/// result is returned unchecked.
+fn eval_typed_think(
+    prompt_block: &PromptBlock<'static>,
+    asked: Asked,
+    ty: &Type,
+    runtime: &mut Runtime,
+    agent: Option<&AgentHandle>,
+) -> Result<Value, Error> {
+    let resolved = ty.resolve(runtime.types()).map_err(Error::Runtime)?;
+    let expect = if matches!(resolved, Type::String | Type::Literal(_)) { "string" } else { "json" };
+
+    let value = eval_think_block(prompt_block, asked, expect, runtime, agent)?;
+    if agent.is_some() || (matches!(asked, Asked::User(_)) && runtime.has_user_prompter()) {
+        ty.check(&value, runtime.types()).map_err(|reason| {
+            Error::Runtime(format!("Think result does not match type {}: {}", ty, reason))
+        })?;
     }
+    Ok(value)
+}
 
-    // No agent - return placeholder so tests can verify interpolation works
-    let mut result = HashMap::new();
-    result.insert("__think_prompt".to_string(), Value::String(prompt_text));
-    Ok(Value::Object(result))
+/// Evaluate a match expression: the block of the first arm whose pattern
+/// fits the subject.
+fn eval_match(
+    subject: &Expr<'static>,
+    arms: &[MatchArm<'static>],
+    runtime: &mut Runtime,
+    agent: Option<&AgentHandle>,
+) -> Result<Value, Error> {
+    let value = eval_expr(subject, runtime, agent)?;
+    for arm in arms {
+        let binding = match &arm.pattern.node {
+            MatchPattern::Literal(literal) => {
+                if eval_expr(literal, runtime, agent)? != shell_as_string(value.clone()) {
+                    continue;
+                }
+                None
+            }
+            MatchPattern::Type { name, type_expr } => {
+                let ty = Type::from_expr(type_expr);
+                let fits = match &ty {
+                    // A name that isn't a type matches the variant it tags
+                    Type::Named(tag) if !runtime.types().contains_key(tag) => match &value {
+                        Value::Object(map) => map.get("__tag") == Some(&Value::String(tag.clone())),
+                        _ => false,
+                    },
+                    _ => ty.check(&value, runtime.types()).is_ok(),
+                };
+                if !fits {
+                    continue;
+                }
+                name.as_deref()
+            }
+            MatchPattern::Binding(name) => Some(&**name),
+            MatchPattern::Wildcard => None,
+        };
+
+        runtime.push_scope();
+        let result = match binding {
+            Some(name) => runtime.define_var(name, value).map_err(Error::Runtime),
+            None => Ok(()),
+        }
+        .and_then(|_| eval_block(&arm.body, runtime, agent));
+        runtime.pop_scope();
+        return result;
+    }
+    Err(Error::Runtime(format!("No match arm for {} {}", type_name(&value), value.to_json_line())))
+}
+
+/// Evaluate a do-block embedded in a prompt.
+///
+/// Runs the statements with print output captured, and returns the captured
+/// output followed by the block's final value (if not null) as text.
+fn eval_do_block(
+    block: &Block<'static>,
+    runtime: &mut Runtime,
+    agent: Option<&AgentHandle>,
+) -> Result<String, Error> {
+    runtime.begin_capture();
+    let result = eval_block(block, runtime, agent);
+    let mut output = runtime.end_capture();
+
+    match result? {
+        Value::Null => {}
+        value => output.push_str(&value.to_string_value()),
+    }
+    Ok(output.trim_end_matches('\n').to_string())
 }
 
 /// Evaluate a binary operation.
 fn eval_binary(
     op: &BinOp,
-    left: &Expr,
-    right: &Expr,
+    left: &Expr<'static>,
+    right: &Expr<'static>,
     runtime: &mut Runtime,
     agent: Option<&AgentHandle>,
 ) -> Result<Value, Error> {
@@ -542,8 +1281,13 @@ fn eval_binary(
         }
     }
 
-    let left_val = eval_expr(left, runtime, agent)?;
-    let right_val = eval_expr(right, runtime, agent)?;
+    let left_val = shell_as_string(eval_expr(left, runtime, agent)?);
+    let right_val = shell_as_string(eval_expr(right, runtime, agent)?);
+
+    // Durations and sizes have their own arithmetic rules
+    if let Some(result) = quantity_op(op, &left_val, &right_val) {
+        return result;
+    }
 
     let result = match op {
         BinOp::Add => {
@@ -592,6 +1336,63 @@ fn eval_binary(
     Ok(result)
 }
 
+/// Arithmetic and ordering on durations and sizes.
+///
+/// Quantities of the same kind add, subtract and compare; they scale by plain
+/// numbers, and dividing two of the same kind gives a ratio. Returns None when
+/// neither operand is a quantity (or for string concatenation and equality,
+/// which the generic paths handle).
+fn quantity_op(op: &BinOp, left: &Value, right: &Value) -> Option<Result<Value, Error>> {
+    use Value::{Boolean, Duration, Number, Size};
+
+    let is_quantity = |v: &Value| matches!(v, Duration(_) | Size(_));
+    if !is_quantity(left) && !is_quantity(right) {
+        return None;
+    }
+
+    let value = match (op, left, right) {
+        (BinOp::Add, Duration(a), Duration(b)) => Duration(a + b),
+        (BinOp::Sub, Duration(a), Duration(b)) => Duration(a - b),
+        (BinOp::Mul, Duration(a), Number(b)) | (BinOp::Mul, Number(b), Duration(a)) => Duration(a * b),
+        (BinOp::Div, Duration(a), Number(b)) => Duration(a / b),
+        (BinOp::Div, Duration(a), Duration(b)) => Number(a / b),
+        (BinOp::Lt, Duration(a), Duration(b)) => Boolean(a < b),
+        (BinOp::Gt, Duration(a), Duration(b)) => Boolean(a > b),
+
+        (BinOp::Add, Size(a), Size(b)) => Size(a + b),
+        (BinOp::Sub, Size(a), Size(b)) => Size(a - b),
+        (BinOp::Mul, Size(a), Number(b)) | (BinOp::Mul, Number(b), Size(a)) => Size(a * b),
+        (BinOp::Div, Size(a), Number(b)) => Size(a / b),
+        (BinOp::Div, Size(a), Size(b)) => Number(a / b),
+        (BinOp::Lt, Size(a), Size(b)) => Boolean(a < b),
+        (BinOp::Gt, Size(a), Size(b)) => Boolean(a > b),
+
+        // String concatenation formats the quantity with its unit
+        (BinOp::Add, Value::String(_), _) | (BinOp::Add, _, Value::String(_)) => return None,
+
+        (BinOp::Add | BinOp::Sub | BinOp::Mul | BinOp::Div | BinOp::Lt | BinOp::Gt, _, _) => {
+            return Some(Err(Error::Runtime(format!(
+                "Cannot combine {} and {} with {:?}",
+                type_name(left), type_name(right), op
+            ))));
+        }
+        _ => return None,
+    };
+    Some(Ok(value))
+}
+
+/// Parse a literal like `200ms` or `10kb` into its base unit amount.
+fn parse_unit_literal(text: &str, units: &[(&str, f64)]) -> Result<f64, Error> {
+    let split = text.find(|c: char| !c.is_ascii_digit()).unwrap_or(text.len());
+    let (digits, suffix) = text.split_at(split);
+    let amount: f64 = digits.parse()
+        .map_err(|_| Error::Runtime(format!("Invalid literal: {}", text)))?;
+    units.iter()
+        .find(|(unit, _)| *unit == suffix)
+        .map(|(_, scale)| amount * scale)
+        .ok_or_else(|| Error::Runtime(format!("Unknown unit '{}' in {}", suffix, text)))
+}
+
 /// Numeric binary operation helper.
 fn num_op(left: &Value, right: &Value, op: fn(f64, f64) -> f64) -> Result<Value, Error> {
     match (left, right) {
@@ -610,6 +1411,8 @@ fn values_equal(a: &Value, b: &Value) -> bool {
         (Value::Boolean(a), Value::Boolean(b)) => a == b,
         (Value::Number(a), Value::Number(b)) => a == b,
         (Value::String(a), Value::String(b)) => a == b,
+        (Value::Duration(a), Value::Duration(b)) => a == b,
+        (Value::Size(a), Value::Size(b)) => a == b,
         (Value::Array(a), Value::Array(b)) => {
             a.len() == b.len() && a.iter().zip(b.iter()).all(|(x, y)| values_equal(x, y))
         }
@@ -635,7 +1438,7 @@ fn compare_values(a: &Value, b: &Value, pred: fn(std::cmp::Ordering) -> bool) ->
 /// Evaluate a unary operation.
 fn eval_unary(
     op: &UnOp,
-    operand: &Expr,
+    operand: &Expr<'static>,
     runtime: &mut Runtime,
     agent: Option<&AgentHandle>,
 ) -> Result<Value, Error> {
@@ -646,46 +1449,452 @@ fn eval_unary(
         UnOp::Neg => {
             match value {
                 Value::Number(n) => Ok(Value::Number(-n)),
+                Value::Duration(n) => Ok(Value::Duration(-n)),
+                Value::Size(n) => Ok(Value::Size(-n)),
                 _ => Err(Error::Runtime(format!("Cannot negate {}", type_name(&value)))),
             }
         }
-        UnOp::Throw => Err(Error::Exception(value)),
+        UnOp::Throw => {
+            runtime.emit(RuntimeEvent::Exception { message: value.to_string_value(), value: Some(value.clone()) });
+            Err(Error::Exception(value))
+        }
     }
 }
 
 /// Evaluate a function call.
 fn eval_call(
-    callee: &Expr,
-    args: &[Expr],
+    callee: &Expr<'static>,
+    args: &[Spanned<Expr<'static>>],
     runtime: &mut Runtime,
     agent: Option<&AgentHandle>,
 ) -> Result<Value, Error> {
-    // Check for builtin functions
+    // Calls by name: closures in variables first, then user-defined
+    // functions, then builtins
     if let Expr::Identifier(name) = callee {
         let mut arg_values = Vec::new();
         for arg in args {
             arg_values.push(eval_expr(arg, runtime, agent)?);
         }
 
+        if let Some(Value::Closure(closure)) = runtime.get_var(name) {
+            let closure = closure.clone();
+            return call_closure(&closure, arg_values, runtime, agent);
+        }
+        if let Some((func, module)) = runtime.resolve_function(name) {
+            return call_function(&func, module, arg_values, runtime, agent);
+        }
+        // Builtins read command output as the string it stands for
+        let arg_values: Vec<Value> = arg_values.into_iter().map(shell_as_string).collect();
+        if let Some(result) = eval_callback_builtin(name, &arg_values, runtime, agent) {
+            return result;
+        }
         return eval_builtin(name, &arg_values, runtime);
     }
 
-    // For now, only builtins are supported
-    Err(Error::Runtime("User-defined functions not yet implemented".to_string()))
-}
-
-/// Evaluate a builtin function call.
-fn eval_builtin(name: &str, args: &[Value], runtime: &Runtime) -> Result<Value, Error> {
-    let result = match name {
-        "cat" => {
-            // cat(value) - serialize to pretty JSON
-            if args.len() != 1 {
-                return Err(Error::Runtime("cat() takes exactly 1 argument".to_string()));
+    // Namespaced builtins such as `render.table(...)`, and the exports of
+    // imported modules, unless the namespace is shadowed by a variable
+    if let Expr::Member { object, field } = callee {
+        if let Expr::Identifier(namespace) = &object.node {
+            if runtime.get_var(namespace).is_none() {
+                let qualified = format!("{}.{}", namespace, field);
+                let mut arg_values = Vec::new();
+                for arg in args {
+                    arg_values.push(eval_expr(arg, runtime, agent)?);
+                }
+                // An imported module's export
+                if let Some((func, module)) = runtime.resolve_function(&qualified) {
+                    return call_function(&func, module, arg_values, runtime, agent);
+                }
+                let arg_values: Vec<Value> = arg_values.into_iter().map(shell_as_string).collect();
+                return eval_builtin(&qualified, &arg_values, runtime);
             }
-            Value::String(args[0].to_json())
         }
+    }
 
-        "json" => {
+    // `push` and `pop` change an array where it's stored, without copying it
+    if let Expr::Member { object, field } = callee {
+        if methods::mutates_array(field) {
+            if let Some(place) = Place::of(object, runtime, agent)? {
+                if let Some(Value::Array(_)) = place.get(runtime) {
+                    let mut arg_values = Vec::new();
+                    for arg in args {
+                        arg_values.push(eval_expr(arg, runtime, agent)?);
+                    }
+                    // The arguments may have changed what's stored there
+                    return match place.get_mut(runtime).map_err(Error::Runtime)? {
+                        Value::Array(items) => methods::array_mutation(items, field, &arg_values).map_err(Error::Runtime),
+                        other => Err(Error::Runtime(format!("Cannot call method '{}' on {}", field, type_name(other)))),
+                    };
+                }
+            }
+        }
+    }
+
+    // Methods on other values, or a function stored in an object's field
+    let function = match callee {
+        Expr::Member { object, field } => match eval_expr(object, runtime, agent)? {
+            Value::Object(map) => map.get(&**field).cloned().unwrap_or(Value::Null),
+            receiver => {
+                let mut arg_values = Vec::new();
+                for arg in args {
+                    arg_values.push(eval_expr(arg, runtime, agent)?);
+                }
+                return match receiver {
+                    // Arrays stored somewhere were handled above
+                    Value::Array(_) if methods::mutates_array(field) => {
+                        Err(Error::Runtime(format!("{}() can only change an array stored in a variable", field)))
+                    }
+                    receiver => eval_method(receiver, field, &arg_values, runtime, agent),
+                };
+            }
+        },
+        // Anything else must evaluate to a function
+        _ => eval_expr(callee, runtime, agent)?,
+    };
+    let Value::Closure(closure) = function else {
+        return Err(Error::Runtime(format!("Cannot call {}", type_name(&function))));
+    };
+    let mut arg_values = Vec::new();
+    for arg in args {
+        arg_values.push(eval_expr(arg, runtime, agent)?);
+    }
+    call_closure(&closure, arg_values, runtime, agent)
+}
+
+/// Call a method on a value that isn't an object.
+fn eval_method(
+    receiver: Value,
+    name: &str,
+    args: &[Value],
+    runtime: &mut Runtime,
+    agent: Option<&AgentHandle>,
+) -> Result<Value, Error> {
+    match receiver {
+        Value::String(s) => methods::string_method(&s, name, args).map_err(Error::Runtime),
+        Value::Shell(result) => methods::string_method(result.text(), name, args).map_err(Error::Runtime),
+        Value::Array(items) if matches!(name, "map" | "filter") && matches!(args, [Value::Closure(_)]) => {
+            let callback_args = [Value::Array(items), args[0].clone()];
+            eval_callback_builtin(name, &callback_args, runtime, agent).unwrap_or(Ok(Value::Null))
+        }
+        Value::Array(items) => methods::array_method(items, name, args).map_err(Error::Runtime),
+        Value::Conversation(conversation) => match name {
+            "reply" => reply_in_conversation(&conversation, args, runtime, agent),
+            "close" if args.is_empty() => {
+                conversation.close();
+                Ok(Value::Null)
+            }
+            "close" => Err(Error::Runtime("close() takes no arguments".to_string())),
+            _ => Err(Error::Runtime(format!("Cannot call method '{}' on conversation", name))),
+        },
+        Value::Task(task) if name == "send" => match args {
+            [message] => {
+                task.mailbox().send(message.clone());
+                Ok(Value::Null)
+            }
+            _ => Err(Error::Runtime("send() takes exactly 1 argument".to_string())),
+        },
+        other => Err(Error::Runtime(format!("Cannot call method '{}' on {}", name, type_name(&other)))),
+    }
+}
+
+/// An object's fields as `{key, value}` objects, in the order they were
+/// added.
+fn entries(fields: IndexMap<String, Value>) -> Vec<Value> {
+    fields
+        .into_iter()
+        .map(|(key, value)| {
+            Value::Object(IndexMap::from([
+                ("key".to_string(), Value::String(key)),
+                ("value".to_string(), value),
+            ]))
+        })
+        .collect()
+}
+
+/// Where a value is stored: a variable, or a field or element inside one,
+/// like `obj.items` or `rows[0]`.
+struct Place<'a> {
+    var: &'a str,
+    /// Steps from the variable's value, outermost first
+    path: Vec<Step>,
+}
+
+enum Step {
+    Field(String),
+    Index(usize),
+}
+
+impl<'a> Place<'a> {
+    /// The place `target` names, evaluating its indexes, or None if it
+    /// isn't one.
+    fn of(target: &'a Expr<'static>, runtime: &mut Runtime, agent: Option<&AgentHandle>) -> Result<Option<Self>, Error> {
+        let mut path = Vec::new();
+        let mut target = target;
+        let var = loop {
+            match target {
+                Expr::Identifier(var) => break var,
+                Expr::Member { object, field } => {
+                    path.push(Step::Field(field.to_string()));
+                    target = object;
+                }
+                Expr::Index { object, index } => {
+                    path.push(match eval_expr(index, runtime, agent)? {
+                        Value::Number(n) => Step::Index(n as usize),
+                        Value::String(key) => Step::Field(key),
+                        _ => return Ok(None),
+                    });
+                    target = object;
+                }
+                Expr::Paren(inner) => target = inner,
+                _ => return Ok(None),
+            }
+        };
+        path.reverse();
+        Ok(Some(Place { var, path }))
+    }
+
+    /// The value stored here, if there is one.
+    fn get<'r>(&self, runtime: &'r Runtime) -> Option<&'r Value> {
+        let mut value = runtime.get_var(self.var)?;
+        for step in &self.path {
+            value = match (value, step) {
+                (Value::Object(fields), Step::Field(key)) => fields.get(key)?,
+                (Value::Array(items), Step::Index(i)) => items.get(*i)?,
+                _ => return None,
+            };
+        }
+        Some(value)
+    }
+
+    /// The value stored here, to change in place. Fails for constants.
+    fn get_mut<'r>(&self, runtime: &'r mut Runtime) -> Result<&'r mut Value, String> {
+        let mut value = runtime.var_mut(self.var)?;
+        for step in &self.path {
+            value = match (value, step) {
+                (Value::Object(fields), Step::Field(key)) => fields.get_mut(key),
+                (Value::Array(items), Step::Index(i)) => items.get_mut(*i),
+                _ => None,
+            }
+            .ok_or_else(|| format!("Nothing is stored at {}", self.var))?;
+        }
+        Ok(value)
+    }
+}
+
+/// Call a closure, with the variables it captured in scope around its
+/// parameters.
+pub(crate) fn call_closure(
+    closure: &Closure,
+    args: Vec<Value>,
+    runtime: &mut Runtime,
+    agent: Option<&AgentHandle>,
+) -> Result<Value, Error> {
+    let caller = runtime.enter_module(closure.module);
+    let result = invoke(&closure.decl, &closure.captured, args, runtime, agent);
+    runtime.enter_module(caller);
+    result
+}
+
+/// Builtins that call back into the program: `map(array, f)`,
+/// `filter(array, f)`, and `group_by(array, f)`. `f` gets each element, and
+/// its index too if it takes two parameters.
+///
+/// `group_by` returns `{key, items}` groups in the order their keys first
+/// came up, with keys matched by content as in `unique`.
+fn eval_callback_builtin(
+    name: &str,
+    args: &[Value],
+    runtime: &mut Runtime,
+    agent: Option<&AgentHandle>,
+) -> Option<Result<Value, Error>> {
+    if name == "with_timeout" {
+        return Some(with_timeout(args, runtime, agent));
+    }
+    if name == "spawn" {
+        return Some(spawn_task(args, runtime, agent));
+    }
+    if name == "think_all" {
+        return Some(think_all(args, runtime, agent));
+    }
+    if !matches!(name, "map" | "filter" | "group_by" | "sort_by" | "count_by" | "min" | "max" | "sum") {
+        return None;
+    }
+    let (items, closure) = match args {
+        [Value::Array(items), Value::Closure(closure)] => (items, Some(closure)),
+        // The key function is optional for these; without one, elements are their own keys
+        [Value::Array(items)] if matches!(name, "min" | "max" | "sum") => (items, None),
+        _ if matches!(name, "min" | "max" | "sum") => {
+            return Some(Err(Error::Runtime(format!("{}() takes an array and an optional function", name))));
+        }
+        _ => return Some(Err(Error::Runtime(format!("{}() takes an array and a function", name)))),
+    };
+    let mut results = Vec::new();
+    for (i, item) in items.iter().enumerate() {
+        let Some(closure) = closure else {
+            results.push(item.clone());
+            continue;
+        };
+        let mut call_args = vec![item.clone()];
+        if closure.decl.params.len() == 2 {
+            call_args.push(Value::Number(i as f64));
+        }
+        let result = match call_closure(closure, call_args, runtime, agent) {
+            Ok(result) => result,
+            Err(e) => return Some(Err(e)),
+        };
+        match name {
+            "filter" if !result.to_bool() => {}
+            "filter" => results.push(item.clone()),
+            _ => results.push(result),
+        }
+    }
+    let value = match name {
+        "group_by" => Ok(group_by(items, &results)),
+        "count_by" => Ok(count_by(&results)),
+        "sort_by" => methods::sorted_by_keys(items.clone(), &results).map(Value::Array),
+        "min" => methods::extreme(items, &results, Ordering::Less),
+        "max" => methods::extreme(items, &results, Ordering::Greater),
+        "sum" => methods::sum(&results),
+        _ => Ok(Value::Array(results)),
+    };
+    Some(value.map_err(Error::Runtime))
+}
+
+/// How many times each key occurs, keyed by the key as a string, in the
+/// order the keys first appear.
+fn count_by(keys: &[Value]) -> Value {
+    let mut counts: IndexMap<String, Value> = IndexMap::new();
+    for key in keys {
+        let count = counts.entry(key.to_string_value()).or_insert(Value::Number(0.0));
+        if let Value::Number(n) = count {
+            *n += 1.0;
+        }
+    }
+    Value::Object(counts)
+}
+
+/// Group `items` by the key computed for each, in `keys`.
+fn group_by(items: &[Value], keys: &[Value]) -> Value {
+    let mut groups: Vec<(&Value, Vec<Value>)> = Vec::new();
+    let mut index: HashMap<Canonical, usize> = HashMap::new();
+    for (item, key) in items.iter().zip(keys) {
+        let i = *index.entry(Canonical(key)).or_insert_with(|| {
+            groups.push((key, Vec::new()));
+            groups.len() - 1
+        });
+        groups[i].1.push(item.clone());
+    }
+    let groups = groups
+        .into_iter()
+        .map(|(key, items)| {
+            Value::Object(IndexMap::from([
+                ("key".to_string(), key.clone()),
+                ("items".to_string(), Value::Array(items)),
+            ]))
+        })
+        .collect();
+    Value::Array(groups)
+}
+
+/// Call a user-defined function.
+///
+/// Arguments for typed parameters are checked against their annotations at
+/// entry, according to the runtime's type check mode. The body runs with only
+/// globals visible, in `module`'s scope if an imported module defines it,
+/// and a `return` inside it ends the call with its value.
+pub(crate) fn call_function(
+    func: &FunctionDecl<'static>,
+    module: Option<ModuleId>,
+    args: Vec<Value>,
+    runtime: &mut Runtime,
+    agent: Option<&AgentHandle>,
+) -> Result<Value, Error> {
+    let caller = runtime.enter_module(module);
+    let result = invoke(func, &HashMap::new(), args, runtime, agent);
+    runtime.enter_module(caller);
+    result
+}
+
+/// Run a function's body on `args`, with `captured` bound beneath them.
+fn invoke(
+    func: &FunctionDecl<'static>,
+    captured: &HashMap<String, Value>,
+    args: Vec<Value>,
+    runtime: &mut Runtime,
+    agent: Option<&AgentHandle>,
+) -> Result<Value, Error> {
+    if args.len() != func.params.len() {
+        return Err(Error::Runtime(format!(
+            "fun {} expects {} argument(s), got {}",
+            func.name, func.params.len(), args.len()
+        )));
+    }
+
+    if runtime.type_check_mode() != TypeCheckMode::Off {
+        for (param, arg) in func.params.iter().zip(&args) {
+            let Some(type_ann) = &param.type_ann else { continue };
+            let ty = Type::from_expr(type_ann);
+            if let Err(reason) = ty.check(arg, runtime.types()) {
+                let message = format!(
+                    "Argument '{}' of fun {} does not match type {}: {}",
+                    param.name, func.name, ty, reason
+                );
+                match runtime.type_check_mode() {
+                    TypeCheckMode::Error => return Err(Error::Runtime(message)),
+                    _ => runtime.warn(message),
+                }
+            }
+        }
+    }
+
+    // A memo fun reuses the result of an earlier call with equal arguments
+    let memo_key = func.is_memo.then(|| Value::Array(args.clone()).to_json_value().to_string());
+    if let Some(cached) = memo_key.as_ref().and_then(|key| runtime.memo_get(&func.name, key)) {
+        return Ok(cached.clone());
+    }
+
+    let locals = runtime.enter_function();
+    if !captured.is_empty() {
+        for (name, value) in captured {
+            // Captured names are unique, and the scope is fresh
+            let _ = runtime.define_var(name, value.clone());
+        }
+        runtime.push_scope();
+    }
+    let mut bound = Ok(());
+    for (param, arg) in func.params.iter().zip(args) {
+        bound = runtime.define_var(&param.name, arg).map_err(Error::Runtime);
+        if bound.is_err() {
+            break;
+        }
+    }
+    // The body shares the parameters' scope, so redeclaring one is an error
+    let result = bound.and_then(|_| nested(runtime, |runtime| eval_statements(&func.body, runtime, agent)));
+    runtime.exit_function(locals);
+
+    let result = match result {
+        Err(Error::Return(value)) => Ok(value),
+        Err(Error::Break) => Err(Error::Runtime(format!("break outside of loop in fun {}", func.name))),
+        other => other,
+    };
+    if let (Some(key), Ok(value)) = (memo_key, &result) {
+        runtime.memo_insert(&func.name, key, value.clone());
+    }
+    result
+}
+
+/// Evaluate a builtin function call.
+fn eval_builtin(name: &str, args: &[Value], runtime: &mut Runtime) -> Result<Value, Error> {
+    let result = match name {
+        "cat" => {
+            // cat(value) - serialize to pretty JSON
+            if args.len() != 1 {
+                return Err(Error::Runtime("cat() takes exactly 1 argument".to_string()));
+            }
+            Value::String(args[0].to_json())
+        }
+
+        "json" => {
             // json(text) - parse JSON string
             if args.len() != 1 {
                 return Err(Error::Runtime("json() takes exactly 1 argument".to_string()));
@@ -694,6 +1903,25 @@ fn eval_builtin(name: &str, args: &[Value], runtime: &Runtime) -> Result<Value,
             Value::from_json(&text).map_err(Error::Runtime)?
         }
 
+        "json_parse" => {
+            // json_parse(text) - parse JSON, throwing a catchable error if it's malformed
+            match args {
+                [Value::String(text)] => Value::from_json(text).map_err(|e| Error::Exception(Value::String(e)))?,
+                _ => return Err(Error::Runtime("json_parse() takes a string".to_string())),
+            }
+        }
+
+        "json_stringify" => {
+            // json_stringify(value, indent?) - JSON on one line, or indented by `indent` spaces
+            match args {
+                [value] => Value::String(formats::json_stringify(value, None)),
+                [value, Value::Number(indent)] if *indent >= 0.0 => {
+                    Value::String(formats::json_stringify(value, Some(*indent as usize)))
+                }
+                _ => return Err(Error::Runtime("json_stringify() takes a value and an optional indent".to_string())),
+            }
+        }
+
         "print" => {
             // print(values...) - print to output sink (or stdout if none)
             let mut output = String::new();
@@ -707,6 +1935,14 @@ fn eval_builtin(name: &str, args: &[Value], runtime: &Runtime) -> Result<Value,
             Value::Null
         }
 
+        "log.debug" | "log.info" | "log.warn" | "log.error" => {
+            // log.info(values...) - log a line at a level, to the output sink (or stderr if none)
+            let level = LogLevel::parse(&name["log.".len()..]).expect("a log builtin names its level");
+            let text: Vec<String> = args.iter().map(Value::to_string_value).collect();
+            runtime.log(level, text.join(" ")).map_err(Error::Runtime)?;
+            Value::Null
+        }
+
         "len" => {
             if args.len() != 1 {
                 return Err(Error::Runtime("len() takes exactly 1 argument".to_string()));
@@ -747,6 +1983,52 @@ fn eval_builtin(name: &str, args: &[Value], runtime: &Runtime) -> Result<Value,
             }
         }
 
+        "entries" => {
+            if args.len() != 1 {
+                return Err(Error::Runtime("entries() takes exactly 1 argument".to_string()));
+            }
+            match &args[0] {
+                Value::Object(obj) => Value::Array(entries(obj.clone())),
+                other => return Err(Error::Runtime(format!("Cannot get entries of {}", type_name(other)))),
+            }
+        }
+
+        "unique" | "set" => {
+            // unique(array) / set(array) - the distinct elements, in the order they first appear
+            let [Value::Array(items)] = args else {
+                return Err(Error::Runtime(format!("{}() takes an array", name)));
+            };
+            let mut seen = HashSet::new();
+            let distinct = items.iter().filter(|item| seen.insert(Canonical(item))).cloned().collect();
+            Value::Array(distinct)
+        }
+
+        "merge" => {
+            // merge(a, b) - deep merge, with b's fields winning
+            match args {
+                [left @ Value::Object(_), right @ Value::Object(_)] => objects::merge(left, right),
+                _ => return Err(Error::Runtime("merge() takes two objects".to_string())),
+            }
+        }
+
+        "patch" => {
+            // patch(value, operations) - apply a JSON Patch
+            match args {
+                [target, Value::Array(operations)] => objects::patch(target, operations).map_err(Error::Runtime)?,
+                _ => return Err(Error::Runtime("patch() takes a value and an array of operations".to_string())),
+            }
+        }
+
+        "pick" | "omit" => {
+            // pick(obj, keys) / omit(obj, keys) - keep or drop the named fields
+            let (fields, keys) = match args {
+                [Value::Object(fields), Value::Array(keys)] => (fields, keys),
+                _ => return Err(Error::Runtime(format!("{}() takes an object and an array of keys", name))),
+            };
+            let keys: Vec<String> = keys.iter().map(Value::to_string_value).collect();
+            if name == "pick" { objects::pick(fields, &keys) } else { objects::omit(fields, &keys) }
+        }
+
         "typeof" => {
             if args.len() != 1 {
                 return Err(Error::Runtime("typeof() takes exactly 1 argument".to_string()));
@@ -754,10 +2036,10 @@ fn eval_builtin(name: &str, args: &[Value], runtime: &Runtime) -> Result<Value,
             Value::String(type_name(&args[0]).to_string())
         }
 
-        "read" => {
+        "read" | "file.read" => {
             // read(path) - read file contents as string
             if args.len() != 1 {
-                return Err(Error::Runtime("read() takes exactly 1 argument".to_string()));
+                return Err(Error::Runtime(format!("{}() takes exactly 1 argument", name)));
             }
             let path = resolve_path(&args[0].to_string_value(), runtime);
             let contents = fs::read_to_string(&path)
@@ -765,10 +2047,10 @@ fn eval_builtin(name: &str, args: &[Value], runtime: &Runtime) -> Result<Value,
             Value::String(contents)
         }
 
-        "write" => {
+        "write" | "file.write" => {
             // write(path, content) - write string to file
             if args.len() != 2 {
-                return Err(Error::Runtime("write() takes exactly 2 arguments".to_string()));
+                return Err(Error::Runtime(format!("{}() takes exactly 2 arguments", name)));
             }
             let path = resolve_path(&args[0].to_string_value(), runtime);
             let content = args[1].to_string_value();
@@ -777,6 +2059,378 @@ fn eval_builtin(name: &str, args: &[Value], runtime: &Runtime) -> Result<Value,
             Value::Null
         }
 
+        "file.append" => {
+            // file.append(path, content) - add to the end of a file, creating it if needed
+            if args.len() != 2 {
+                return Err(Error::Runtime("file.append() takes exactly 2 arguments".to_string()));
+            }
+            let path = resolve_path(&args[0].to_string_value(), runtime);
+            fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&path)
+                .and_then(|mut file| file.write_all(args[1].to_string_value().as_bytes()))
+                .map_err(|e| Error::Runtime(format!("Failed to append to {}: {}", path.display(), e)))?;
+            Value::Null
+        }
+
+        "file.exists" => {
+            // file.exists(path) - whether a file or directory is there
+            if args.len() != 1 {
+                return Err(Error::Runtime("file.exists() takes exactly 1 argument".to_string()));
+            }
+            Value::Boolean(resolve_path(&args[0].to_string_value(), runtime).exists())
+        }
+
+        "file.mkdir" => {
+            // file.mkdir(path) - create a directory and any missing parents
+            if args.len() != 1 {
+                return Err(Error::Runtime("file.mkdir() takes exactly 1 argument".to_string()));
+            }
+            let path = resolve_path(&args[0].to_string_value(), runtime);
+            fs::create_dir_all(&path)
+                .map_err(|e| Error::Runtime(format!("Failed to create {}: {}", path.display(), e)))?;
+            Value::Null
+        }
+
+        "file.remove" => {
+            // file.remove(path) - delete a file or an empty directory
+            if args.len() != 1 {
+                return Err(Error::Runtime("file.remove() takes exactly 1 argument".to_string()));
+            }
+            let path = resolve_path(&args[0].to_string_value(), runtime);
+            let removed = if path.is_dir() { fs::remove_dir(&path) } else { fs::remove_file(&path) };
+            removed.map_err(|e| Error::Runtime(format!("Failed to remove {}: {}", path.display(), e)))?;
+            Value::Null
+        }
+
+        "lines" => {
+            // lines(text) - split command output into lines, without line endings
+            match args {
+                [Value::String(text)] => {
+                    Value::Array(text.lines().map(|line| Value::String(line.to_string())).collect())
+                }
+                // Commands like `ls -1` already produce lines
+                [Value::Array(items)] => Value::Array(items.clone()),
+                [Value::Null] => Value::Array(Vec::new()),
+                _ => return Err(Error::Runtime("lines() takes exactly 1 argument: a string".to_string())),
+            }
+        }
+
+        "scan" => {
+            // scan(line, pattern) - the named captures of a regex match as an object, or null
+            let (line, pattern) = match args {
+                [Value::String(line), Value::String(pattern)] => (line, pattern),
+                _ => return Err(Error::Runtime("scan() takes exactly 2 arguments: a string and a pattern".to_string())),
+            };
+            let regex = Regex::new(pattern)
+                .map_err(|e| Error::Runtime(format!("scan(): invalid pattern {:?}: {}", pattern, e)))?;
+            match regex.captures(line) {
+                Some(captures) => {
+                    let fields = regex.capture_names().flatten().map(|name| {
+                        let value = captures.name(name).map_or(Value::Null, |m| Value::String(m.as_str().to_string()));
+                        (name.to_string(), value)
+                    });
+                    Value::Object(fields.collect())
+                }
+                None => Value::Null,
+            }
+        }
+
+        "env.get" => {
+            // env.get(name) - an environment variable's value, or null if it isn't set
+            if args.len() != 1 {
+                return Err(Error::Runtime("env.get() takes exactly 1 argument".to_string()));
+            }
+            runtime.env_var(&args[0].to_string_value()).map_or(Value::Null, Value::String)
+        }
+
+        "env.set" => {
+            // env.set(name, value) - set a variable for later env.get calls and shell commands
+            if args.len() != 2 {
+                return Err(Error::Runtime("env.set() takes exactly 2 arguments".to_string()));
+            }
+            let name = args[0].to_string_value();
+            if name.is_empty() || name.contains(['=', '\0']) {
+                return Err(Error::Runtime(format!("Invalid environment variable name '{}'", name)));
+            }
+            runtime.set_env_var(name, args[1].to_string_value());
+            Value::Null
+        }
+
+        "send" => {
+            // send(task, value) - leave a message in a spawned task's mailbox
+            let [Value::Task(task), message] = args else {
+                return Err(Error::Runtime("send() takes a task and a value".to_string()));
+            };
+            task.mailbox().send(message.clone());
+            Value::Null
+        }
+
+        "self.send" => {
+            // self.send(value) - leave a message for whoever spawned this task
+            let [message] = args else {
+                return Err(Error::Runtime("self.send() takes exactly 1 argument".to_string()));
+            };
+            let Some(parent) = runtime.parent_mailbox() else {
+                return Err(Error::Runtime("self.send() can only be called from a spawned task".to_string()));
+            };
+            parent.send(message.clone());
+            Value::Null
+        }
+
+        "self.receive" => return receive_message(args, runtime),
+
+        "cwd" => {
+            // cwd() - the working directory that commands and file builtins use
+            if !args.is_empty() {
+                return Err(Error::Runtime("cwd() takes no arguments".to_string()));
+            }
+            Value::String(runtime.working_dir().display().to_string())
+        }
+
+        "env.vars" => {
+            // env.vars() - every environment variable, as an object sorted by name
+            if !args.is_empty() {
+                return Err(Error::Runtime("env.vars() takes no arguments".to_string()));
+            }
+            let vars = runtime.env_vars().into_iter().map(|(name, value)| (name, Value::String(value)));
+            Value::Object(vars.collect())
+        }
+
+        "git.changed_files" => {
+            // git.changed_files(base?) - files that differ from `base` (default HEAD), as {path, status} objects
+            if args.len() > 1 {
+                return Err(Error::Runtime("git.changed_files() takes at most 1 argument".to_string()));
+            }
+            let base = args.first().map_or("HEAD".to_string(), Value::to_string_value);
+            let output = run_git(&git::changed_files_args(&base), runtime)?;
+            git::parse_changed_files(&output).map_err(Error::Runtime)?
+        }
+
+        "git.log" => {
+            // git.log(range?) - commits in `range` (default HEAD), newest first, as {hash, subject, author, date}
+            if args.len() > 1 {
+                return Err(Error::Runtime("git.log() takes at most 1 argument".to_string()));
+            }
+            let range = args.first().map_or("HEAD".to_string(), Value::to_string_value);
+            let output = run_git(&git::log_args(&range), runtime)?;
+            git::parse_log(&output).map_err(Error::Runtime)?
+        }
+
+        "render.table" => {
+            // render.table(rows, columns?) - print rows of objects as a table
+            let (rows, columns) = match args {
+                [Value::Array(rows)] => (rows, None),
+                [Value::Array(rows), Value::Array(columns)] => {
+                    (rows, Some(columns.iter().map(Value::to_string_value).collect()))
+                }
+                _ => {
+                    return Err(Error::Runtime(
+                        "render.table() takes an array of objects and an optional array of columns".to_string(),
+                    ))
+                }
+            };
+            let table = render::table(rows, columns).map_err(Error::Runtime)?;
+            runtime.print_markdown(&table).map_err(Error::Runtime)?;
+            Value::Null
+        }
+
+        "render.markdown" => {
+            // render.markdown(value) - print a value as Markdown
+            if args.len() != 1 {
+                return Err(Error::Runtime("render.markdown() takes exactly 1 argument".to_string()));
+            }
+            runtime.print_markdown(&render::markdown(&args[0])).map_err(Error::Runtime)?;
+            Value::Null
+        }
+
+        "diff.text" => {
+            // diff.text(old, new) - line diff as hunks
+            if args.len() != 2 {
+                return Err(Error::Runtime("diff.text() takes exactly 2 arguments".to_string()));
+            }
+            diff::text(&args[0].to_string_value(), &args[1].to_string_value())
+        }
+
+        "diff.json" => {
+            // diff.json(old, new) - structural diff as changes at JSON paths
+            if args.len() != 2 {
+                return Err(Error::Runtime("diff.json() takes exactly 2 arguments".to_string()));
+            }
+            diff::json(&args[0], &args[1])
+        }
+
+        "diff.render" => {
+            // diff.render(diff) - a diff from diff.text or diff.json as text
+            if args.len() != 1 {
+                return Err(Error::Runtime("diff.render() takes exactly 1 argument".to_string()));
+            }
+            Value::String(diff::render(&args[0]).map_err(Error::Runtime)?)
+        }
+
+        "approve" => {
+            // approve(message, default?) - ask the person running the program for a yes or no
+            let (message, default) = match args {
+                [message] => (message, None),
+                [message, Value::Boolean(default)] => (message, Some(*default)),
+                _ => {
+                    return Err(Error::Runtime(
+                        "approve() takes a message and an optional boolean default".to_string(),
+                    ))
+                }
+            };
+            let default_answer = default.map(|yes| if yes { "yes" } else { "no" }.to_string());
+            match runtime.prompt_user(PromptKind::Approve, None, message.to_string_value(), default_answer) {
+                Some(answer) => {
+                    let answer = answer.map_err(Error::Runtime)?;
+                    Value::Boolean(matches!(answer.trim().to_lowercase().as_str(), "y" | "yes" | "true"))
+                }
+                None => match default {
+                    Some(default) => Value::Boolean(default),
+                    None => {
+                        return Err(Error::Runtime(format!(
+                            "approve() has no one to ask and no default: {}",
+                            message.to_string_value()
+                        )))
+                    }
+                },
+            }
+        }
+
+        "template.render" => {
+            // template.render(template, bindings) - interpolate against an object
+            if args.len() != 2 {
+                return Err(Error::Runtime("template.render() takes exactly 2 arguments".to_string()));
+            }
+            Value::String(template::render(&args[0].to_string_value(), &args[1], runtime)?)
+        }
+
+        "encode.base64" => {
+            // encode.base64(text) - standard Base64 of the text's bytes
+            if args.len() != 1 {
+                return Err(Error::Runtime("encode.base64() takes exactly 1 argument".to_string()));
+            }
+            Value::String(BASE64.encode(args[0].to_string_value()))
+        }
+
+        "decode.base64" => {
+            // decode.base64(text) - the text a Base64 string encodes
+            if args.len() != 1 {
+                return Err(Error::Runtime("decode.base64() takes exactly 1 argument".to_string()));
+            }
+            let bytes = BASE64
+                .decode(args[0].to_string_value().trim())
+                .map_err(|e| Error::Runtime(format!("Invalid Base64: {}", e)))?;
+            let text = String::from_utf8(bytes)
+                .map_err(|_| Error::Runtime("decode.base64() result isn't UTF-8 text".to_string()))?;
+            Value::String(text)
+        }
+
+        "hash.sha256" => {
+            // hash.sha256(text) - hex SHA-256 digest
+            if args.len() != 1 {
+                return Err(Error::Runtime("hash.sha256() takes exactly 1 argument".to_string()));
+            }
+            Value::String(to_hex(&Sha256::digest(args[0].to_string_value())))
+        }
+
+        "hash.sha256_file" => {
+            // hash.sha256_file(path) - hex SHA-256 digest of a file, read in chunks
+            if args.len() != 1 {
+                return Err(Error::Runtime("hash.sha256_file() takes exactly 1 argument".to_string()));
+            }
+            let path = resolve_path(&args[0].to_string_value(), runtime);
+            let mut file = fs::File::open(&path)
+                .map_err(|e| Error::Runtime(format!("Failed to read {}: {}", path.display(), e)))?;
+            let mut hasher = Sha256::new();
+            io::copy(&mut file, &mut hasher)
+                .map_err(|e| Error::Runtime(format!("Failed to read {}: {}", path.display(), e)))?;
+            Value::String(to_hex(&hasher.finalize()))
+        }
+
+        "hmac.sha256" => {
+            // hmac.sha256(key, message) - hex HMAC-SHA256, as webhooks sign payloads
+            if args.len() != 2 {
+                return Err(Error::Runtime("hmac.sha256() takes exactly 2 arguments".to_string()));
+            }
+            let mut mac = Hmac::<Sha256>::new_from_slice(args[0].to_string_value().as_bytes())
+                .expect("HMAC accepts any key length");
+            mac.update(args[1].to_string_value().as_bytes());
+            Value::String(to_hex(&mac.finalize().into_bytes()))
+        }
+
+        "csv.parse" => {
+            // csv.parse(text, options?) - rows of a CSV document
+            let (text, options) = match args {
+                [text] => (text, None),
+                [text, options] => (text, Some(options)),
+                _ => return Err(Error::Runtime("csv.parse() takes 1 or 2 arguments".to_string())),
+            };
+            let options = formats::csv::Options::from_value(options).map_err(Error::Runtime)?;
+            formats::csv::read(text.to_string_value().as_bytes(), &options).map_err(Error::Runtime)?
+        }
+
+        "csv.read" => {
+            // csv.read(path, options?) - rows of a CSV file, read incrementally
+            let (path, options) = match args {
+                [path] => (path, None),
+                [path, options] => (path, Some(options)),
+                _ => return Err(Error::Runtime("csv.read() takes 1 or 2 arguments".to_string())),
+            };
+            let options = formats::csv::Options::from_value(options).map_err(Error::Runtime)?;
+            let path = resolve_path(&path.to_string_value(), runtime);
+            let file = fs::File::open(&path)
+                .map_err(|e| Error::Runtime(format!("Failed to read {}: {}", path.display(), e)))?;
+            formats::csv::read(io::BufReader::new(file), &options)
+                .map_err(|e| Error::Runtime(format!("{}: {}", path.display(), e)))?
+        }
+
+        "csv.stringify" => {
+            // csv.stringify(rows, options?) - write rows as CSV
+            let (rows, options) = match args {
+                [Value::Array(rows)] => (rows, None),
+                [Value::Array(rows), options] => (rows, Some(options)),
+                _ => {
+                    return Err(Error::Runtime(
+                        "csv.stringify() takes an array of rows and optional options".to_string(),
+                    ))
+                }
+            };
+            let options = formats::csv::Options::from_value(options).map_err(Error::Runtime)?;
+            Value::String(formats::csv::write(rows, &options).map_err(Error::Runtime)?)
+        }
+
+        "yaml.parse" | "toml.parse" => {
+            // yaml.parse(text), toml.parse(text) - parse a document
+            if args.len() != 1 {
+                return Err(Error::Runtime(format!("{}() takes exactly 1 argument", name)));
+            }
+            let text = args[0].to_string_value();
+            let parsed = if name == "yaml.parse" { formats::yaml::parse(&text) } else { formats::toml::parse(&text) };
+            parsed.map_err(Error::Runtime)?
+        }
+
+        "yaml.stringify" | "toml.stringify" => {
+            // yaml.stringify(value), toml.stringify(value) - write a document
+            if args.len() != 1 {
+                return Err(Error::Runtime(format!("{}() takes exactly 1 argument", name)));
+            }
+            let text = if name == "yaml.stringify" {
+                formats::yaml::stringify(&args[0])
+            } else {
+                formats::toml::stringify(&args[0])
+            };
+            Value::String(text.map_err(Error::Runtime)?)
+        }
+
+        "std.sleep" => {
+            // std.sleep(limit) - pause for a duration or a number of milliseconds
+            sleep(args, runtime)?
+        }
+
+        _ if name.starts_with("std.") => stdlib::call(name, args).map_err(Error::Runtime)?,
+
         _ => return Err(Error::Runtime(format!("Unknown function: {}", name))),
     };
 
@@ -786,10 +2440,46 @@ fn eval_builtin(name: &str, args: &[Value], runtime: &Runtime) -> Result<Value,
 /// Evaluate a bare shell command.
 fn eval_bare_command(
     name: &str,
-    args: &[CommandArg],
+    args: &[CommandArg<'static>],
     runtime: &mut Runtime,
     agent: Option<&AgentHandle>,
 ) -> Result<Value, Error> {
+    let cmd_args = eval_command_args(args, runtime, agent)?;
+    if name == "cd" {
+        change_dir(&cmd_args, runtime)?;
+        return Ok(Value::String(String::new()));
+    }
+    let result = exec_command(name, &cmd_args, runtime)?;
+    if !result.success() {
+        return Err(Error::Runtime(failure_message(name, &result)));
+    }
+    Ok(command_value(name, &cmd_args, result))
+}
+
+/// `cd`: change the runtime's working directory, which every later command
+/// and file builtin resolves against. No process runs, so it isn't subject
+/// to the shell policy. With no argument it goes to `$HOME`.
+fn change_dir(args: &[String], runtime: &mut Runtime) -> Result<(), Error> {
+    let target = match args {
+        [] => runtime.env_var("HOME").ok_or_else(|| Error::Runtime("cd: HOME is not set".to_string()))?,
+        [dir] => dir.clone(),
+        _ => return Err(Error::Runtime("cd: too many arguments".to_string())),
+    };
+    let dir = resolve_path(&target, runtime)
+        .canonicalize()
+        .ok()
+        .filter(|dir| dir.is_dir())
+        .ok_or_else(|| Error::Runtime(format!("cd: no such directory: {}", target)))?;
+    runtime.set_working_dir(dir);
+    Ok(())
+}
+
+/// Evaluate a command's arguments to strings.
+fn eval_command_args(
+    args: &[CommandArg<'static>],
+    runtime: &mut Runtime,
+    agent: Option<&AgentHandle>,
+) -> Result<Vec<String>, Error> {
     let mut cmd_args = Vec::new();
     for arg in args {
         match arg {
@@ -800,48 +2490,332 @@ fn eval_bare_command(
             }
         }
     }
+    Ok(cmd_args)
+}
+
+/// Execute a shell command, or take its result from the trace being replayed.
+///
+/// A command that runs and exits with a nonzero status still returns its
+/// result; only a command that can't be run at all is an error.
+fn exec_command(name: &str, args: &[String], runtime: &mut Runtime) -> Result<ShellResult, Error> {
+    if !runtime.shell_policy().permits(name) {
+        return Err(Error::Runtime(format!("Shell command '{}' is not allowed by the shell policy", name)));
+    }
 
-    exec_command(name, &cmd_args, runtime)
+    let result = match runtime.tracer_mut().replay_shell(name, args).map_err(Error::Runtime)? {
+        Some(Ok(recorded)) => ShellResult::from_recorded(&recorded)
+            .ok_or_else(|| Error::Runtime(format!("Trace has a malformed result for `{}`", name))),
+        Some(Err(message)) => Err(Error::Runtime(message)),
+        None => {
+            let result = run_command(name, args, runtime);
+            let recorded = result.as_ref().map(ShellResult::to_object).map_err(Error::clone);
+            runtime.tracer_mut().record_shell(name, args, &recorded);
+            result
+        }
+    };
+    // Events report output for a command that succeeded and a message otherwise
+    let reported = match &result {
+        Ok(result) if result.success() => Ok(command_value(name, args, result.clone())),
+        Ok(result) => Err(failure_message(name, result)),
+        Err(e) => Err(e.to_string()),
+    };
+    runtime.emit(RuntimeEvent::ShellExec { command: name.to_string(), args: args.to_vec(), result: reported });
+    result
 }
 
-/// Execute a shell command.
-fn exec_command(name: &str, args: &[String], runtime: &Runtime) -> Result<Value, Error> {
-    let output = Command::new(name)
-        .args(args)
-        .current_dir(runtime.working_dir())
-        .output()
-        .map_err(|e| Error::Runtime(format!("Failed to execute {}: {}", name, e)))?;
+/// Run a shell command in the working directory with the runtime's
+/// executor.
+fn run_command(name: &str, args: &[String], runtime: &Runtime) -> Result<ShellResult, Error> {
+    let command = ShellCommand {
+        program: name,
+        args,
+        working_dir: runtime.working_dir(),
+        env: runtime.env_overrides(),
+    };
+    let command_line: Vec<&str> = std::iter::once(name).chain(args.iter().map(String::as_str)).collect();
+    let mut wait = Wait::new(format!("`{}`", command_line.join(" ")), runtime);
+    let waiting = wait.next_wakeup(runtime).is_some();
+    let mut tick = || wait.tick(runtime);
+    let tick: Option<Tick> = if waiting { Some(&mut tick) } else { None };
+    runtime.shell_executor().execute(&command, tick)
+}
 
-    if !output.status.success() {
-        let stderr = String::from_utf8_lossy(&output.stderr);
-        return Err(Error::Runtime(format!(
-            "Command '{}' failed with exit code {:?}: {}",
-            name,
-            output.status.code(),
-            stderr.trim()
-        )));
+/// Run git like a bare command, for the `git.*` builtins, and return its
+/// output.
+fn run_git(args: &[String], runtime: &mut Runtime) -> Result<String, Error> {
+    let result = exec_command("git", args, runtime)?;
+    if !result.success() {
+        return Err(Error::Runtime(failure_message("git", &result)));
     }
+    Ok(result.stdout)
+}
 
-    let stdout = String::from_utf8_lossy(&output.stdout);
+/// Why a command that ran didn't succeed.
+fn failure_message(name: &str, result: &ShellResult) -> String {
+    format!("Command '{}' failed with exit code {:?}: {}", name, result.status, result.stderr.trim())
+}
+
+/// Whether a command lists one entry per line, like `ls -1`.
+fn lists_lines(name: &str, args: &[String]) -> bool {
+    name == "ls" && args.iter().any(|a| a.contains('1'))
+}
 
-    // For `ls -1` style commands, return as array of lines
-    if name == "ls" && args.iter().any(|a| a.contains('1')) {
-        let lines: Vec<Value> = stdout
+/// The value a bare command that succeeded evaluates to: its output, or
+/// the lines of a listing.
+fn command_value(name: &str, args: &[String], result: ShellResult) -> Value {
+    if lists_lines(name, args) {
+        let lines: Vec<Value> = result
+            .stdout
             .lines()
             .filter(|l| !l.is_empty())
             .map(|l| Value::String(l.to_string()))
             .collect();
-        return Ok(Value::Array(lines));
+        return Value::Array(lines);
     }
+    Value::String(result.stdout)
+}
 
-    Ok(Value::String(stdout.into_owned()))
+/// A blocking wait on a shell command or the agent. Sends heartbeats at the
+/// runtime's interval and enforces the deadline of any enclosing `@timeout`,
+/// as well as the runtime's default timeout.
+struct Wait {
+    /// What is being waited on, for heartbeat messages
+    what: String,
+    started: Instant,
+    next_heartbeat: Option<Instant>,
+    /// When the runtime's default timeout runs out for this wait, with the
+    /// limit in milliseconds
+    deadline: Option<(Instant, f64)>,
+}
+
+impl Wait {
+    fn new(what: String, runtime: &Runtime) -> Self {
+        let started = Instant::now();
+        let next_heartbeat = runtime.heartbeat_interval().map(|interval| started + interval);
+        let deadline = runtime.default_timeout().map(|limit| (started + limit, limit.as_secs_f64() * 1000.0));
+        Wait { what, started, next_heartbeat, deadline }
+    }
+
+    /// The deadline that runs out first, with its limit in milliseconds.
+    fn deadline(&self, runtime: &Runtime) -> Option<(Instant, f64)> {
+        runtime.deadline().into_iter().chain(self.deadline).min_by_key(|(at, _)| *at)
+    }
+
+    /// When `tick` next has something to do, if ever.
+    fn next_wakeup(&self, runtime: &Runtime) -> Option<Instant> {
+        let deadline = self.deadline(runtime).map(|(at, _)| at);
+        match (deadline, self.next_heartbeat) {
+            (Some(deadline), Some(heartbeat)) => Some(deadline.min(heartbeat)),
+            (deadline, heartbeat) => deadline.or(heartbeat),
+        }
+    }
+
+    /// Fail once the deadline has passed, and send a heartbeat when one is due.
+    fn tick(&mut self, runtime: &Runtime) -> Result<(), Error> {
+        let now = Instant::now();
+        if let Some((deadline, limit_ms)) = self.deadline(runtime) {
+            if now >= deadline {
+                return Err(timeout_error(limit_ms));
+            }
+        }
+        if self.next_heartbeat.is_some_and(|due| now >= due) {
+            runtime.heartbeat(&self.what, now - self.started);
+            self.next_heartbeat = runtime.heartbeat_interval().map(|interval| now + interval);
+        }
+        Ok(())
+    }
+}
+
+/// The `TimeoutError { message, limit }` exception a shell command or think
+/// block raises when a time limit runs out.
+fn timeout_error(limit_ms: f64) -> Error {
+    let message = format!("Timed out after {}", Value::Duration(limit_ms).to_string_value());
+    let fields = [
+        ("__tag", Value::String("TimeoutError".to_string())),
+        ("message", Value::String(message)),
+        ("limit", Value::Duration(limit_ms)),
+    ];
+    Error::Exception(Value::Object(fields.into_iter().map(|(key, value)| (key.to_string(), value)).collect()))
+}
+
+/// The `ValidationError { message, answer, attempts }` exception a think
+/// or ask block raises when its last allowed answer fails `validate`.
+fn validation_error(problem: String, answer: Value, attempts: u32) -> Error {
+    let message = format!("Answer rejected after {} attempt(s): {}", attempts, problem);
+    let fields = [
+        ("__tag", Value::String("ValidationError".to_string())),
+        ("message", Value::String(message)),
+        ("answer", answer),
+        ("attempts", Value::Number(attempts as f64)),
+    ];
+    Error::Exception(Value::Object(fields.into_iter().map(|(key, value)| (key.to_string(), value)).collect()))
+}
+
+/// `with_timeout(limit, f)`: call `f` with a deadline `limit` from now, given
+/// as a duration or a number of seconds, like a `@timeout` statement.
+fn with_timeout(args: &[Value], runtime: &mut Runtime, agent: Option<&AgentHandle>) -> Result<Value, Error> {
+    let (limit_ms, closure) = match args {
+        [Value::Duration(ms), Value::Closure(closure)] => (*ms, closure),
+        [Value::Number(secs), Value::Closure(closure)] => (secs * 1000.0, closure),
+        _ => {
+            return Err(Error::Runtime(
+                "with_timeout() takes a duration or a number of seconds, and a function".to_string(),
+            ))
+        }
+    };
+    runtime.push_deadline(limit_ms);
+    let result = call_closure(closure, Vec::new(), runtime, agent);
+    runtime.pop_deadline();
+    result
+}
+
+/// `spawn(f, args...)`: call `f` with `args` on its own thread, returning
+/// its task at once.
+fn spawn_task(args: &[Value], runtime: &Runtime, agent: Option<&AgentHandle>) -> Result<Value, Error> {
+    let Some((Value::Closure(closure), args)) = args.split_first() else {
+        return Err(Error::Runtime("spawn() takes a function and the arguments to call it with".to_string()));
+    };
+    let closure = closure.clone();
+    let args = args.to_vec();
+    let mut task_runtime = runtime.fork();
+    let mailbox = task_runtime.mailbox().clone();
+    let agent = agent.cloned();
+    let name = closure.decl.name.to_string();
+    let task = scheduler::spawn(&name, mailbox, move || {
+        call_closure(&closure, args, &mut task_runtime, agent.as_ref())
+    })?;
+    Ok(Value::Task(task))
+}
+
+/// How many items `think_all` works on at once unless told otherwise.
+const DEFAULT_THINK_CONCURRENCY: usize = 4;
+
+/// `think_all(items, f, { concurrency: n })`: call `f` on each item on its
+/// own thread, at most `n` at a time, and collect a record per item in
+/// order. Each record has the `item`, then `value` if `f` returned or
+/// `error` if it failed, the number of `thinks` it made, and the `tokens`
+/// the agent reported for them, or null if it reported none. One item
+/// failing doesn't stop the others.
+fn think_all(args: &[Value], runtime: &Runtime, agent: Option<&AgentHandle>) -> Result<Value, Error> {
+    let usage = || Error::Runtime("think_all() takes an array, a function, and optional { concurrency }".to_string());
+    let (items, closure, options) = match args {
+        [Value::Array(items), Value::Closure(closure)] => (items, closure, None),
+        [Value::Array(items), Value::Closure(closure), Value::Object(options)] => (items, closure, Some(options)),
+        _ => return Err(usage()),
+    };
+    let concurrency = match options.and_then(|options| options.get("concurrency")) {
+        None => DEFAULT_THINK_CONCURRENCY,
+        Some(Value::Number(n)) if *n >= 1.0 && n.fract() == 0.0 => *n as usize,
+        Some(_) => return Err(Error::Runtime("think_all() concurrency must be a whole number of at least 1".to_string())),
+    };
+
+    let mut pending = items.iter().cloned();
+    let mut running = VecDeque::new();
+    let mut records = Vec::with_capacity(items.len());
+    loop {
+        while running.len() < concurrency {
+            let Some(item) = pending.next() else { break };
+            let closure = closure.clone();
+            let mut item_runtime = runtime.fork();
+            let mailbox = item_runtime.mailbox().clone();
+            let agent = agent.cloned();
+            let name = closure.decl.name.to_string();
+            let task = scheduler::spawn(&name, mailbox, move || {
+                let result = call_closure(&closure, vec![item.clone()], &mut item_runtime, agent.as_ref());
+                let mut record = IndexMap::from([("item".to_string(), item)]);
+                match result {
+                    Ok(value) | Err(Error::Return(value)) => record.insert("value".to_string(), value),
+                    Err(e) => {
+                        let error = caught_value(&e).unwrap_or_else(|| Value::String(e.to_string()));
+                        record.insert("error".to_string(), error)
+                    }
+                };
+                record.insert("thinks".to_string(), Value::Number(item_runtime.thinks_charged() as f64));
+                let tokens = item_runtime.tokens_charged().map_or(Value::Null, |tokens| Value::Number(tokens as f64));
+                record.insert("tokens".to_string(), tokens);
+                Ok(Value::Object(record))
+            })?;
+            running.push_back(task);
+        }
+        // Tasks finish in any order, but their records are taken in item order
+        let Some(task) = running.pop_front() else { break };
+        records.push(join_task(Value::Task(task), runtime)?);
+    }
+    Ok(Value::Array(records))
+}
+
+/// `self.receive(timeout)`: the oldest message sent to this task or the
+/// program, or null if none arrives within `timeout`. Without a timeout it
+/// waits as long as it takes. Heartbeats and `@timeout` apply either way.
+fn receive_message(args: &[Value], runtime: &Runtime) -> Result<Value, Error> {
+    let limit = match args {
+        [] => None,
+        [Value::Duration(ms) | Value::Number(ms)] if *ms >= 0.0 => {
+            Some(Instant::now() + Duration::from_secs_f64(ms / 1000.0))
+        }
+        _ => {
+            return Err(Error::Runtime(
+                "self.receive() takes an optional timeout, as a duration or milliseconds".to_string(),
+            ))
+        }
+    };
+    let mailbox = runtime.mailbox().clone();
+    let mut wait = Wait::new("message".to_string(), runtime);
+    loop {
+        let wakeup = wait.next_wakeup(runtime).into_iter().chain(limit).min();
+        if let Some(message) = mailbox.receive(wakeup.map(|at| at.saturating_duration_since(Instant::now()))) {
+            return Ok(message);
+        }
+        if limit.is_some_and(|limit| Instant::now() >= limit) {
+            return Ok(Value::Null);
+        }
+        wait.tick(runtime)?;
+    }
+}
+
+/// `std.sleep(limit)`: pause for `limit`, a duration or milliseconds. An
+/// enclosing `@timeout` that runs out first cuts it short, and heartbeats
+/// go out meanwhile.
+fn sleep(args: &[Value], runtime: &Runtime) -> Result<Value, Error> {
+    let limit = match args {
+        [Value::Duration(ms) | Value::Number(ms)] if *ms >= 0.0 => Duration::from_secs_f64(ms / 1000.0),
+        _ => return Err(Error::Runtime("std.sleep() takes a duration or milliseconds".to_string())),
+    };
+    let until = Instant::now() + limit;
+    let mut wait = Wait::new("sleep".to_string(), runtime);
+    loop {
+        let now = Instant::now();
+        if now >= until {
+            return Ok(Value::Null);
+        }
+        let wakeup = wait.next_wakeup(runtime).map_or(until, |wakeup| wakeup.min(until));
+        std::thread::sleep(wakeup.saturating_duration_since(now));
+        wait.tick(runtime)?;
+    }
+}
+
+/// Wait for `value` to finish if it's a task, and return its result.
+/// Timeouts and heartbeats apply while it runs. Any other value is
+/// returned as it is.
+fn join_task(value: Value, runtime: &Runtime) -> Result<Value, Error> {
+    let Value::Task(task) = value else {
+        return Ok(value);
+    };
+    let mut wait = Wait::new(format!("task {}", task.name()), runtime);
+    loop {
+        let timeout = wait.next_wakeup(runtime).map(|wakeup| wakeup.saturating_duration_since(Instant::now()));
+        if let Some(result) = task.wait(timeout) {
+            return result;
+        }
+        wait.tick(runtime)?;
+    }
 }
 
 /// Evaluate a shell redirect expression.
 fn eval_shell_redirect(
-    command: &Expr,
+    command: &Expr<'static>,
     op: &RedirectOp,
-    target: &Expr,
+    target: &Expr<'static>,
     runtime: &mut Runtime,
     agent: Option<&AgentHandle>,
 ) -> Result<Value, Error> {
@@ -857,8 +2831,8 @@ fn eval_shell_redirect(
             // Check if the command is 'json' for JSON parsing
             // Can be either Identifier("json") or BareCommand { name: "json", args: [] }
             let is_json_command = match command {
-                Expr::Identifier("json") => true,
-                Expr::BareCommand { name: "json", args } if args.is_empty() => true,
+                Expr::Identifier(name) => name == "json",
+                Expr::BareCommand { name, args } => name == "json" && args.is_empty(),
                 _ => false,
             };
 
@@ -879,10 +2853,9 @@ fn eval_shell_redirect(
 
             // If the command was cat(), write as JSON
             let content = if let Expr::Call { callee, .. } = command {
-                if let Expr::Identifier("cat") = callee.as_ref() {
-                    cmd_result.to_string_value()
-                } else {
-                    cmd_result.to_string_value()
+                match &callee.node {
+                    Expr::Identifier(name) if name == "cat" => cmd_result.to_string_value(),
+                    _ => cmd_result.to_string_value(),
                 }
             } else {
                 cmd_result.to_string_value()
@@ -916,6 +2889,11 @@ fn eval_shell_redirect(
     }
 }
 
+/// Lowercase hex digits of a digest.
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
 /// Resolve a path relative to the runtime's working directory.
 fn resolve_path(path: &str, runtime: &Runtime) -> std::path::PathBuf {
     let p = std::path::Path::new(path);
@@ -927,7 +2905,7 @@ fn resolve_path(path: &str, runtime: &Runtime) -> std::path::PathBuf {
 }
 
 /// Get the type name of a value for error messages.
-fn type_name(value: &Value) -> &'static str {
+pub(crate) fn type_name(value: &Value) -> &'static str {
     match value {
         Value::Null => "null",
         Value::String(_) => "string",
@@ -935,6 +2913,22 @@ fn type_name(value: &Value) -> &'static str {
         Value::Boolean(_) => "boolean",
         Value::Array(_) => "array",
         Value::Object(_) => "object",
+        Value::Duration(_) => "duration",
+        Value::Size(_) => "size",
+        Value::Closure(_) => "function",
+        Value::Task(_) => "task",
+        Value::Conversation(_) => "conversation",
+        // Command output stands in for a string
+        Value::Shell(_) => "string",
+    }
+}
+
+/// The string a command substitution's result reads as; other values are
+/// returned unchanged.
+fn shell_as_string(value: Value) -> Value {
+    match value {
+        Value::Shell(result) => Value::String(result.text().to_string()),
+        other => other,
     }
 }
 
@@ -966,7 +2960,7 @@ mod tests {
     #[test]
     fn test_eval_number() {
         let mut rt = make_runtime();
-        let expr = Expr::Number("42");
+        let expr = Expr::Number("42".into());
         let value = eval_expr(&expr, &mut rt, None).unwrap();
         assert!(matches!(value, Value::Number(n) if n == 42.0));
     }
@@ -975,7 +2969,7 @@ mod tests {
     fn test_eval_string() {
         let mut rt = make_runtime();
         let expr = Expr::String(StringLiteral {
-            parts: vec![StringPart::Text("hello")],
+            parts: vec![StringPart::Text("hello".into())],
         });
         let value = eval_expr(&expr, &mut rt, None).unwrap();
         assert!(matches!(value, Value::String(s) if s == "hello"));
@@ -995,9 +2989,9 @@ mod tests {
     fn test_eval_array() {
         let mut rt = make_runtime();
         let expr = Expr::Array(vec![
-            Expr::Number("1"),
-            Expr::Number("2"),
-            Expr::Number("3"),
+            Expr::Number("1".into()).into(),
+            Expr::Number("2".into()).into(),
+            Expr::Number("3".into()).into(),
         ]);
         let value = eval_expr(&expr, &mut rt, None).unwrap();
         if let Value::Array(arr) = value {
@@ -1016,8 +3010,8 @@ mod tests {
         let mut rt = make_runtime();
         let expr = Expr::Binary {
             op: BinOp::Add,
-            left: Box::new(Expr::Number("1")),
-            right: Box::new(Expr::Number("2")),
+            left: Box::new(Expr::Number("1".into()).into()),
+            right: Box::new(Expr::Number("2".into()).into()),
         };
         let value = eval_expr(&expr, &mut rt, None).unwrap();
         assert!(matches!(value, Value::Number(n) if n == 3.0));
@@ -1029,11 +3023,11 @@ mod tests {
         let expr = Expr::Binary {
             op: BinOp::Add,
             left: Box::new(Expr::String(StringLiteral {
-                parts: vec![StringPart::Text("hello ")],
-            })),
+                parts: vec![StringPart::Text("hello ".into())],
+            }).into()),
             right: Box::new(Expr::String(StringLiteral {
-                parts: vec![StringPart::Text("world")],
-            })),
+                parts: vec![StringPart::Text("world".into())],
+            }).into()),
         };
         let value = eval_expr(&expr, &mut rt, None).unwrap();
         assert!(matches!(value, Value::String(s) if s == "hello world"));
@@ -1041,13 +3035,13 @@ mod tests {
 
     #[test]
     fn test_eval_builtin_cat() {
-        let rt = Runtime::default();
+        let mut rt = Runtime::default();
         let input = Value::Object(
             [("name".to_string(), Value::String("test".to_string()))]
                 .into_iter()
                 .collect(),
         );
-        let value = eval_builtin("cat", &[input], &rt).unwrap();
+        let value = eval_builtin("cat", &[input], &mut rt).unwrap();
         if let Value::String(s) = value {
             assert!(s.contains("\"name\""));
             assert!(s.contains("\"test\""));
@@ -1058,8 +3052,8 @@ mod tests {
 
     #[test]
     fn test_eval_builtin_json() {
-        let rt = Runtime::default();
-        let value = eval_builtin("json", &[Value::String(r#"{"x": 1}"#.to_string())], &rt).unwrap();
+        let mut rt = Runtime::default();
+        let value = eval_builtin("json", &[Value::String(r#"{"x": 1}"#.to_string())], &mut rt).unwrap();
         if let Value::Object(obj) = value {
             assert_eq!(obj.get("x"), Some(&Value::Number(1.0)));
         } else {
@@ -1067,14 +3061,45 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_eval_duration_arithmetic() {
+        let mut rt = make_runtime();
+        let expr = Expr::Binary {
+            op: BinOp::Add,
+            left: Box::new(Expr::Duration("1m".into()).into()),
+            right: Box::new(Expr::Duration("30s".into()).into()),
+        };
+        let value = eval_expr(&expr, &mut rt, None).unwrap();
+        assert_eq!(value, Value::Duration(90_000.0));
+        assert_eq!(value.to_string_value(), "90s");
+
+        let expr = Expr::Binary {
+            op: BinOp::Div,
+            left: Box::new(Expr::Size("2mb".into()).into()),
+            right: Box::new(Expr::Size("512kb".into()).into()),
+        };
+        assert_eq!(eval_expr(&expr, &mut rt, None).unwrap(), Value::Number(4.0));
+    }
+
+    #[test]
+    fn test_eval_quantity_kind_mismatch() {
+        let mut rt = make_runtime();
+        let expr = Expr::Binary {
+            op: BinOp::Lt,
+            left: Box::new(Expr::Duration("5s".into()).into()),
+            right: Box::new(Expr::Size("5b".into()).into()),
+        };
+        assert!(eval_expr(&expr, &mut rt, None).is_err());
+    }
+
     #[test]
     fn test_throw_exception() {
         let mut rt = make_runtime();
         let expr = Expr::Unary {
             op: UnOp::Throw,
             operand: Box::new(Expr::String(StringLiteral {
-                parts: vec![StringPart::Text("error message")],
-            })),
+                parts: vec![StringPart::Text("error message".into())],
+            }).into()),
         };
         let result = eval_expr(&expr, &mut rt, None);
         match result {
@@ -0,0 +1,178 @@
+//! A machine-readable stream of what a run is doing.
+//!
+//! Dashboards and other tools follow a run through its events, one JSON
+//! object per line, each naming its `event` and carrying the time in
+//! milliseconds since the Unix epoch:
+//!
+//! ```text
+//! {"event":"statement_started","line":3,"text":"$ cargo build","time":1760000000000}
+//! {"args":["build"],"command":"cargo","event":"shell_exec","ok":"...","time":1760000000412}
+//! {"ask":false,"event":"think_request","prompt":"...","time":1760000000413}
+//! {"entries":[{"content":"...","status":"in_progress"}],"event":"plan_update","time":...}
+//! {"event":"print","text":"...","time":...}
+//! {"event":"log","level":"warn","text":"...","time":...}
+//! {"backend":"primary","error":"...","event":"agent_fallback","next":"cache","time":...}
+//! {"event":"exception","message":"...","time":...,"value":{...}}
+//! ```
+//!
+//! Shell results use the trace's `ok`/`err` fields. An `exception` is sent
+//! for every `throw`, caught or not, with the thrown value, and once more,
+//! without one, if the run fails for any other reason.
+
+use std::fs::OpenOptions;
+use std::io::{self, BufWriter, Write};
+use std::sync::mpsc::{self, Sender};
+use std::thread::{self, JoinHandle};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde_json::{json, Value as JsonValue};
+
+use crate::runtime::{LogLevel, PlanEntryStatus, PlanUpdate};
+use crate::value::Value;
+
+/// Something a run did, as it happened.
+#[derive(Debug, Clone)]
+pub enum RuntimeEvent {
+    /// A statement is about to run.
+    StatementStarted { line: Option<usize>, text: String },
+    /// A shell command ran, or its result was replayed.
+    ShellExec { command: String, args: Vec<String>, result: Result<Value, String> },
+    /// A think or ask block is about to be answered.
+    ThinkRequest { prompt: String, ask: bool },
+    /// The plan changed.
+    PlanUpdate(PlanUpdate),
+    /// The program printed a line.
+    Print { text: String },
+    /// The program logged a line that passed the log filter.
+    Log { level: LogLevel, text: String },
+    /// A value was thrown, or the run failed.
+    Exception { message: String, value: Option<Value> },
+    /// An agent backend failed, and the next in its fallback chain is
+    /// answering instead.
+    AgentFallback { backend: String, error: String, next: String },
+}
+
+/// A sink for runtime events.
+pub type EventSink = Sender<RuntimeEvent>;
+
+impl RuntimeEvent {
+    /// The event's name, as written in its `event` field.
+    pub fn name(&self) -> &'static str {
+        match self {
+            RuntimeEvent::StatementStarted { .. } => "statement_started",
+            RuntimeEvent::ShellExec { .. } => "shell_exec",
+            RuntimeEvent::ThinkRequest { .. } => "think_request",
+            RuntimeEvent::PlanUpdate(_) => "plan_update",
+            RuntimeEvent::Print { .. } => "print",
+            RuntimeEvent::Log { .. } => "log",
+            RuntimeEvent::Exception { .. } => "exception",
+            RuntimeEvent::AgentFallback { .. } => "agent_fallback",
+        }
+    }
+
+    /// Encode this event as a single line of JSON, stamped with `time`.
+    pub fn to_json_line(&self, time: SystemTime) -> String {
+        let millis = time.duration_since(UNIX_EPOCH).map(|d| d.as_millis() as u64).unwrap_or(0);
+        let mut object = json!({ "event": self.name(), "time": millis });
+        match self {
+            RuntimeEvent::StatementStarted { line, text } => {
+                object["line"] = json!(line);
+                object["text"] = json!(text);
+            }
+            RuntimeEvent::ShellExec { command, args, result } => {
+                object["command"] = json!(command);
+                object["args"] = json!(args);
+                match result {
+                    Ok(value) => object["ok"] = value.to_json_value(),
+                    Err(message) => object["err"] = json!(message),
+                }
+            }
+            RuntimeEvent::ThinkRequest { prompt, ask } => {
+                object["prompt"] = json!(prompt);
+                object["ask"] = json!(ask);
+            }
+            RuntimeEvent::PlanUpdate(update) => {
+                let entries: Vec<JsonValue> = update
+                    .entries
+                    .iter()
+                    .map(|entry| {
+                        let status = match entry.status {
+                            PlanEntryStatus::Pending => "pending",
+                            PlanEntryStatus::InProgress => "in_progress",
+                            PlanEntryStatus::Completed => "completed",
+                        };
+                        json!({ "content": entry.content, "status": status })
+                    })
+                    .collect();
+                object["entries"] = JsonValue::Array(entries);
+            }
+            RuntimeEvent::Print { text } => object["text"] = json!(text),
+            RuntimeEvent::Log { level, text } => {
+                object["level"] = json!(level.name());
+                object["text"] = json!(text);
+            }
+            RuntimeEvent::Exception { message, value } => {
+                object["message"] = json!(message);
+                if let Some(value) = value {
+                    object["value"] = value.to_json_value();
+                }
+            }
+            RuntimeEvent::AgentFallback { backend, error, next } => {
+                object["backend"] = json!(backend);
+                object["error"] = json!(error);
+                object["next"] = json!(next);
+            }
+        }
+        object.to_string()
+    }
+}
+
+/// Write events as JSON lines to `path`, or to stderr for `-`, on a
+/// background thread. The file is appended to, so several runs (or
+/// sessions of the ACP proxy) can share one stream.
+///
+/// Returns the sink to hand to the runtime, and the writer thread, which
+/// finishes once every sender is dropped and the last event is written.
+pub fn spawn_event_writer(path: &str) -> io::Result<(EventSink, JoinHandle<()>)> {
+    let mut out: Box<dyn Write + Send> = if path == "-" {
+        Box::new(io::stderr())
+    } else {
+        Box::new(BufWriter::new(OpenOptions::new().create(true).append(true).open(path)?))
+    };
+    let (tx, rx) = mpsc::channel::<RuntimeEvent>();
+    let writer = thread::spawn(move || {
+        for event in rx {
+            // Flushed per event so a dashboard tailing the file keeps up
+            let line = event.to_json_line(SystemTime::now());
+            if writeln!(out, "{}", line).and_then(|_| out.flush()).is_err() {
+                break;
+            }
+        }
+    });
+    Ok((tx, writer))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_event_json_lines() {
+        let time = UNIX_EPOCH + Duration::from_millis(1500);
+        let shell = RuntimeEvent::ShellExec {
+            command: "git".to_string(),
+            args: vec!["status".to_string()],
+            result: Err("not a repository".to_string()),
+        };
+        assert_eq!(
+            shell.to_json_line(time),
+            r#"{"args":["status"],"command":"git","err":"not a repository","event":"shell_exec","time":1500}"#
+        );
+        let started = RuntimeEvent::StatementStarted { line: Some(3), text: "$ make".to_string() };
+        assert_eq!(
+            started.to_json_line(time),
+            r#"{"event":"statement_started","line":3,"text":"$ make","time":1500}"#
+        );
+    }
+}
@@ -0,0 +1,322 @@
+//! Data formats for the `json_stringify`, `csv.*`, `yaml.*`, and `toml.*`
+//! builtins.
+//!
+//! YAML and TOML are each behind a cargo feature of the same name. Without
+//! it the builtins still exist but fail, saying which feature to enable.
+
+use serde::Serialize;
+use serde_json::ser::{PrettyFormatter, Serializer};
+use serde_json::Value as JsonValue;
+
+use crate::value::Value;
+
+/// Convert a value for serializing, keeping whole numbers whole so that
+/// `version = 2` doesn't come back as `2.0`.
+fn to_data(value: &Value) -> JsonValue {
+    match value {
+        Value::Number(n) | Value::Duration(n) | Value::Size(n) if n.fract() == 0.0 && n.abs() < 1e15 => {
+            JsonValue::from(*n as i64)
+        }
+        Value::Array(items) => JsonValue::Array(items.iter().map(to_data).collect()),
+        Value::Object(fields) => {
+            JsonValue::Object(fields.iter().map(|(key, value)| (key.clone(), to_data(value))).collect())
+        }
+        other => other.to_json_value(),
+    }
+}
+
+/// Serialize a value as JSON, on one line or indented by `indent` spaces.
+pub(crate) fn json_stringify(value: &Value, indent: Option<usize>) -> String {
+    let data = to_data(value);
+    let Some(indent) = indent else {
+        return data.to_string();
+    };
+    let indent = " ".repeat(indent);
+    let mut out = Vec::new();
+    let mut serializer = Serializer::with_formatter(&mut out, PrettyFormatter::with_indent(indent.as_bytes()));
+    // Writing a JSON value to memory can't fail, and produces UTF-8
+    data.serialize(&mut serializer).expect("serializing JSON to memory");
+    String::from_utf8(out).expect("JSON is UTF-8")
+}
+
+pub(crate) mod csv {
+    use std::io::Read;
+
+    use ::csv::{ReaderBuilder, StringRecord, WriterBuilder};
+
+    use crate::eval::type_name;
+    use crate::value::Value;
+
+    /// Options object accepted by the `csv.*` builtins.
+    pub(crate) struct Options {
+        /// Whether the first row names the columns (default true)
+        headers: bool,
+        delimiter: u8,
+        /// Data rows to skip before reading
+        offset: usize,
+        /// Most data rows to read
+        limit: Option<usize>,
+        /// Columns to write, in order
+        columns: Option<Vec<String>>,
+    }
+
+    impl Options {
+        pub(crate) fn from_value(value: Option<&Value>) -> Result<Self, String> {
+            let mut options = Options { headers: true, delimiter: b',', offset: 0, limit: None, columns: None };
+            let fields = match value {
+                None | Some(Value::Null) => return Ok(options),
+                Some(Value::Object(fields)) => fields,
+                Some(other) => return Err(format!("CSV options must be an object, got {}", type_name(other))),
+            };
+            for (key, value) in fields {
+                match (key.as_str(), value) {
+                    ("headers", value) => options.headers = value.to_bool(),
+                    ("delimiter", Value::String(d)) if d.len() == 1 => options.delimiter = d.as_bytes()[0],
+                    ("offset", Value::Number(n)) if *n >= 0.0 => options.offset = *n as usize,
+                    ("limit", Value::Number(n)) if *n >= 0.0 => options.limit = Some(*n as usize),
+                    ("columns", Value::Array(columns)) => {
+                        options.columns = Some(columns.iter().map(Value::to_string_value).collect())
+                    }
+                    (key, value) => return Err(format!("Invalid CSV option {}: {}", key, value.to_string_value())),
+                }
+            }
+            Ok(options)
+        }
+    }
+
+    /// Read rows from any reader, a record at a time, so `offset` and
+    /// `limit` can pick a slice of a large file without loading all of it.
+    ///
+    /// With headers each row is an object keyed by column name; without,
+    /// an array. Fields are always strings.
+    pub(crate) fn read(input: impl Read, options: &Options) -> Result<Value, String> {
+        let mut reader = ReaderBuilder::new()
+            .has_headers(options.headers)
+            .delimiter(options.delimiter)
+            .flexible(true)
+            .from_reader(input);
+        let headers = if options.headers {
+            Some(reader.headers().map_err(|e| format!("CSV parse error: {}", e))?.clone())
+        } else {
+            None
+        };
+
+        let mut rows = Vec::new();
+        let mut record = StringRecord::new();
+        let mut skipped = 0;
+        while options.limit.is_none_or(|limit| rows.len() < limit) {
+            if !reader.read_record(&mut record).map_err(|e| format!("CSV parse error: {}", e))? {
+                break;
+            }
+            if skipped < options.offset {
+                skipped += 1;
+                continue;
+            }
+            let fields = record.iter().map(|field| Value::String(field.to_string()));
+            rows.push(match &headers {
+                Some(headers) => Value::Object(headers.iter().map(str::to_string).zip(fields).collect()),
+                None => Value::Array(fields.collect()),
+            });
+        }
+        Ok(Value::Array(rows))
+    }
+
+    /// Write rows as CSV. Rows of objects get a header row, from `columns`
+    /// or every key in sorted order; rows of arrays are written as is.
+    pub(crate) fn write(rows: &[Value], options: &Options) -> Result<String, String> {
+        let mut writer = WriterBuilder::new().delimiter(options.delimiter).flexible(true).from_writer(Vec::new());
+        let objects = rows.iter().all(|row| matches!(row, Value::Object(_)));
+        let columns = if objects && !rows.is_empty() {
+            let columns = options.columns.clone().unwrap_or_else(|| {
+                let mut keys: Vec<String> = rows
+                    .iter()
+                    .flat_map(|row| match row {
+                        Value::Object(fields) => fields.keys().cloned().collect(),
+                        _ => Vec::new(),
+                    })
+                    .collect();
+                keys.sort();
+                keys.dedup();
+                keys
+            });
+            if options.headers {
+                writer.write_record(&columns).map_err(|e| e.to_string())?;
+            }
+            columns
+        } else {
+            Vec::new()
+        };
+
+        for row in rows {
+            let record: Vec<String> = match row {
+                Value::Object(fields) => {
+                    columns.iter().map(|column| fields.get(column).map(field).unwrap_or_default()).collect()
+                }
+                Value::Array(items) => items.iter().map(field).collect(),
+                other => {
+                    return Err(format!("csv.stringify() rows must be objects or arrays, got {}", type_name(other)))
+                }
+            };
+            writer.write_record(&record).map_err(|e| e.to_string())?;
+        }
+        let bytes = writer.into_inner().map_err(|e| e.to_string())?;
+        String::from_utf8(bytes).map_err(|e| e.to_string())
+    }
+
+    fn field(value: &Value) -> String {
+        match value {
+            Value::Null => String::new(),
+            Value::Array(_) | Value::Object(_) => value.to_json_value().to_string(),
+            other => other.to_string_value(),
+        }
+    }
+}
+
+#[cfg(feature = "yaml")]
+pub(crate) mod yaml {
+    use super::*;
+
+    pub(crate) fn parse(text: &str) -> Result<Value, String> {
+        let json: JsonValue = serde_yaml::from_str(text).map_err(|e| format!("YAML parse error: {}", e))?;
+        Ok(Value::from_json_value(json))
+    }
+
+    pub(crate) fn stringify(value: &Value) -> Result<String, String> {
+        serde_yaml::to_string(&to_data(value)).map_err(|e| format!("Cannot write YAML: {}", e))
+    }
+}
+
+#[cfg(feature = "toml")]
+pub(crate) mod toml {
+    use super::*;
+
+    pub(crate) fn parse(text: &str) -> Result<Value, String> {
+        let table: ::toml::Table = text.parse().map_err(|e| format!("TOML parse error: {}", e))?;
+        Ok(from_toml(::toml::Value::Table(table)))
+    }
+
+    fn from_toml(value: ::toml::Value) -> Value {
+        match value {
+            ::toml::Value::String(s) => Value::String(s),
+            ::toml::Value::Integer(n) => Value::Number(n as f64),
+            ::toml::Value::Float(n) => Value::Number(n),
+            ::toml::Value::Boolean(b) => Value::Boolean(b),
+            // Dates have no value of their own, so they stay as written
+            ::toml::Value::Datetime(date) => Value::String(date.to_string()),
+            ::toml::Value::Array(items) => Value::Array(items.into_iter().map(from_toml).collect()),
+            ::toml::Value::Table(table) => {
+                Value::Object(table.into_iter().map(|(key, value)| (key, from_toml(value))).collect())
+            }
+        }
+    }
+
+    /// Write an object as a TOML document. TOML has no null, so null
+    /// fields are left out.
+    pub(crate) fn stringify(value: &Value) -> Result<String, String> {
+        let Value::Object(_) = value else {
+            return Err(format!("toml.stringify() expects an object, got {}", crate::eval::type_name(value)));
+        };
+        ::toml::to_string(&without_nulls(to_data(value))).map_err(|e| format!("Cannot write TOML: {}", e))
+    }
+
+    fn without_nulls(value: JsonValue) -> JsonValue {
+        match value {
+            JsonValue::Object(fields) => JsonValue::Object(
+                fields.into_iter().filter(|(_, value)| !value.is_null()).map(|(k, v)| (k, without_nulls(v))).collect(),
+            ),
+            JsonValue::Array(items) => JsonValue::Array(items.into_iter().map(without_nulls).collect()),
+            other => other,
+        }
+    }
+}
+
+/// Stand-ins for formats compiled out.
+#[cfg(not(all(feature = "yaml", feature = "toml")))]
+macro_rules! disabled_format {
+    ($name:ident) => {
+        pub(crate) mod $name {
+            use super::*;
+
+            fn disabled(function: &str) -> String {
+                format!(
+                    "{}.{}() isn't available; build patchwork with the `{}` feature",
+                    stringify!($name),
+                    function,
+                    stringify!($name)
+                )
+            }
+
+            pub(crate) fn parse(_text: &str) -> Result<Value, String> {
+                Err(disabled("parse"))
+            }
+
+            pub(crate) fn stringify(_value: &Value) -> Result<String, String> {
+                Err(disabled("stringify"))
+            }
+        }
+    };
+}
+
+#[cfg(not(feature = "yaml"))]
+disabled_format!(yaml);
+#[cfg(not(feature = "toml"))]
+disabled_format!(toml);
+
+#[cfg(test)]
+mod tests {
+    #[cfg(any(feature = "yaml", feature = "toml"))]
+    use super::*;
+
+    #[test]
+    fn test_csv_read_and_write() {
+        use super::csv::{read, write, Options};
+        use crate::value::Value;
+
+        let text = "name,email\nAda,ada@example.com\n\"Lovelace, A\",\n";
+        let rows = read(text.as_bytes(), &Options::from_value(None).unwrap()).unwrap();
+        let Value::Array(rows) = rows else { panic!("Expected rows") };
+        assert_eq!(rows.len(), 2);
+        let Value::Object(second) = &rows[1] else { panic!("Expected an object row") };
+        assert_eq!(second["name"], Value::String("Lovelace, A".to_string()));
+        // Quoting survives the round trip
+        let columns = Value::from_json(r#"{"columns": ["name", "email"]}"#).unwrap();
+        assert_eq!(write(&rows, &Options::from_value(Some(&columns)).unwrap()).unwrap(), text);
+
+        let slice = Value::from_json(r#"{"headers": false, "offset": 1, "limit": 1}"#).unwrap();
+        let rows = read(text.as_bytes(), &Options::from_value(Some(&slice)).unwrap()).unwrap();
+        assert_eq!(rows, Value::from_json(r#"[["Ada", "ada@example.com"]]"#).unwrap());
+        assert!(Options::from_value(Some(&Value::from_json(r#"{"delimiter": ";;"}"#).unwrap())).is_err());
+    }
+
+    #[cfg(feature = "yaml")]
+    #[test]
+    fn test_yaml_round_trip() {
+        let config = yaml::parse("on: push\njobs:\n  test:\n    runs-on: ubuntu-latest\n    timeout: 30\n").unwrap();
+        let Value::Object(fields) = &config else { panic!("Expected an object, got {:?}", config) };
+        assert_eq!(fields["on"], Value::String("push".to_string()));
+        let text = yaml::stringify(&config).unwrap();
+        assert!(text.contains("timeout: 30\n"), "whole numbers stay whole: {}", text);
+        assert_eq!(yaml::parse(&text).unwrap(), config);
+        assert!(yaml::parse("key: [unclosed").unwrap_err().starts_with("YAML parse error"));
+    }
+
+    #[cfg(feature = "toml")]
+    #[test]
+    fn test_toml_round_trip() {
+        let manifest =
+            toml::parse("[package]\nname = \"demo\"\nedition = \"2021\"\n\n[dependencies]\nserde = \"1\"\n").unwrap();
+        let text = toml::stringify(&manifest).unwrap();
+        assert_eq!(toml::parse(&text).unwrap(), manifest);
+        assert!(text.contains("[package]"));
+
+        let with_null = Value::from_json(r#"{"version": 2, "skip": null}"#).unwrap();
+        assert_eq!(toml::stringify(&with_null).unwrap(), "version = 2\n");
+        assert!(toml::stringify(&Value::Number(1.0)).is_err());
+    }
+
+    #[cfg(not(feature = "yaml"))]
+    #[test]
+    fn test_disabled_format() {
+        assert!(super::yaml::parse("a: 1").unwrap_err().contains("`yaml` feature"));
+    }
+}
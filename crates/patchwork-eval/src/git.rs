@@ -0,0 +1,118 @@
+//! Structured results from git, for the `git.changed_files` and `git.log`
+//! builtins.
+//!
+//! Each builtin runs one git command in a machine-readable format and turns
+//! its output into an array of objects, so programs don't have to split
+//! porcelain text themselves.
+
+use indexmap::IndexMap;
+
+use crate::value::Value;
+
+/// Separates the fields of one commit in `git log` output.
+const FIELD_SEP: char = '\u{1f}';
+/// Ends each commit in `git log` output.
+const RECORD_SEP: char = '\u{1e}';
+
+/// Arguments for `git diff` listing the tracked files that differ between
+/// `base` and the working tree. Renames are detected whatever the user's
+/// git config says.
+pub(crate) fn changed_files_args(base: &str) -> Vec<String> {
+    ["diff", "--name-status", "--find-renames", "-z", base, "--"].map(str::to_string).to_vec()
+}
+
+/// Arguments for `git log` listing the commits in `range`.
+pub(crate) fn log_args(range: &str) -> Vec<String> {
+    let format = format!("--format=%H{0}%s{0}%an{0}%aI{1}", FIELD_SEP, RECORD_SEP);
+    vec!["log".to_string(), format, range.to_string(), "--".to_string()]
+}
+
+/// Parse `git diff --name-status -z` output into `{path, status}` objects.
+/// Renames and copies also have `old_path`.
+pub(crate) fn parse_changed_files(output: &str) -> Result<Value, String> {
+    let mut fields = output.split('\0').filter(|field| !field.is_empty());
+    let mut files = Vec::new();
+    while let Some(code) = fields.next() {
+        let mut next_path = || fields.next().ok_or_else(|| format!("git diff output ends after status {:?}", code));
+        let mut file = IndexMap::new();
+        if code.starts_with(['R', 'C']) {
+            let old_path = next_path()?;
+            file.insert("path".to_string(), Value::String(next_path()?.to_string()));
+            file.insert("status".to_string(), Value::String(status_name(code).to_string()));
+            file.insert("old_path".to_string(), Value::String(old_path.to_string()));
+        } else {
+            file.insert("path".to_string(), Value::String(next_path()?.to_string()));
+            file.insert("status".to_string(), Value::String(status_name(code).to_string()));
+        }
+        files.push(Value::Object(file));
+    }
+    Ok(Value::Array(files))
+}
+
+/// Parse the output of `git log` with [`log_args`] into
+/// `{hash, subject, author, date}` objects.
+pub(crate) fn parse_log(output: &str) -> Result<Value, String> {
+    let mut commits = Vec::new();
+    for record in output.split(RECORD_SEP).map(str::trim).filter(|record| !record.is_empty()) {
+        let parts: Vec<&str> = record.split(FIELD_SEP).collect();
+        let [hash, subject, author, date] = parts[..] else {
+            return Err(format!("Unexpected git log output: {:?}", record));
+        };
+        let commit = [("hash", hash), ("subject", subject), ("author", author), ("date", date)]
+            .map(|(name, value)| (name.to_string(), Value::String(value.to_string())));
+        commits.push(Value::Object(IndexMap::from(commit)));
+    }
+    Ok(Value::Array(commits))
+}
+
+/// The word for a `--name-status` code, which may carry a similarity score.
+fn status_name(code: &str) -> &'static str {
+    match code.chars().next() {
+        Some('A') => "added",
+        Some('M') => "modified",
+        Some('D') => "deleted",
+        Some('R') => "renamed",
+        Some('C') => "copied",
+        Some('T') => "type_changed",
+        Some('U') => "unmerged",
+        _ => "unknown",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_changed_files() {
+        let output = "M\0src/lib.rs\0R087\0old name.rs\0new name.rs\0D\0gone.rs\0";
+        let files = parse_changed_files(output).unwrap();
+        assert_eq!(
+            files.to_json_line(),
+            concat!(
+                r#"[{"path":"src/lib.rs","status":"modified"},"#,
+                r#"{"old_path":"old name.rs","path":"new name.rs","status":"renamed"},"#,
+                r#"{"path":"gone.rs","status":"deleted"}]"#,
+            )
+        );
+        assert_eq!(parse_changed_files(""), Ok(Value::Array(Vec::new())));
+        assert!(parse_changed_files("M\0").is_err());
+    }
+
+    #[test]
+    fn test_parse_log() {
+        let output = "abc\u{1f}Fix: a\u{1f}b\u{1f}Ada\u{1f}2024-01-01T00:00:00Z\u{1e}\n";
+        assert!(parse_log(output).is_err());
+
+        let output = concat!(
+            "abc\u{1f}Fix parser\u{1f}Ada\u{1f}2024-01-01T00:00:00Z\u{1e}\n",
+            "def\u{1f}Init\u{1f}Ada\u{1f}2023-12-31T00:00:00Z\u{1e}\n",
+        );
+        let Value::Array(commits) = parse_log(output).unwrap() else { unreachable!() };
+        assert_eq!(commits.len(), 2);
+        assert_eq!(
+            commits[0].to_json_line(),
+            r#"{"author":"Ada","date":"2024-01-01T00:00:00Z","hash":"abc","subject":"Fix parser"}"#
+        );
+    }
+}
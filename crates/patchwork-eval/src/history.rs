@@ -0,0 +1,128 @@
+//! Time-travel inspection of a run's variables.
+//!
+//! When history is on, the runtime takes a snapshot of the visible bindings
+//! after each statement finishes, so a run can be queried afterwards for
+//! "what was `commit_plan` at line 42". Snapshots are stored as changes from
+//! the previous one: a binding is copied only when a statement changes it, so
+//! a long run over a large, mostly untouched environment stays cheap.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use patchwork_parser::ast::{Span, Spanned, Statement};
+
+use crate::value::Value;
+
+/// Bindings that changed when a statement finished; `None` means the name
+/// went out of scope.
+#[derive(Debug)]
+struct Snapshot {
+    line: usize,
+    changes: Vec<(String, Option<Value>)>,
+}
+
+/// Recorded environment snapshots for one run.
+#[derive(Debug, Default)]
+pub struct History {
+    /// Program text the executing AST's spans point into.
+    source: Arc<str>,
+    snapshots: Vec<Snapshot>,
+    /// Bindings as of the last snapshot.
+    current: HashMap<String, Value>,
+}
+
+impl History {
+    pub(crate) fn set_source(&mut self, source: Arc<str>) {
+        self.source = source;
+    }
+
+    /// Record the bindings visible after `stmt` ran.
+    pub(crate) fn record<'a>(
+        &mut self,
+        stmt: &Spanned<Statement>,
+        visible: impl IntoIterator<Item = (&'a String, &'a Value)>,
+    ) {
+        let Some(line) = line_of(&self.source, stmt.span) else {
+            return;
+        };
+
+        let visible: HashMap<&String, &Value> = visible.into_iter().collect();
+        let mut changes = Vec::new();
+        for (name, value) in &visible {
+            if self.current.get(*name) != Some(*value) {
+                changes.push(((*name).clone(), Some((*value).clone())));
+            }
+        }
+        self.current.retain(|name, _| {
+            let keep = visible.contains_key(name);
+            if !keep {
+                changes.push((name.clone(), None));
+            }
+            keep
+        });
+        for (name, value) in &changes {
+            if let Some(value) = value {
+                self.current.insert(name.clone(), value.clone());
+            }
+        }
+        self.snapshots.push(Snapshot { line, changes });
+    }
+
+    /// Whether a statement on `line` ran.
+    pub fn reached(&self, line: usize) -> bool {
+        self.snapshots.iter().any(|s| s.line == line)
+    }
+
+    /// The value `name` had when the statement on `line` last finished, or
+    /// `None` if the line never ran or `name` wasn't in scope there.
+    pub fn value_at(&self, name: &str, line: usize) -> Option<Value> {
+        let last = self.snapshots.iter().rposition(|s| s.line == line)?;
+        self.snapshots[..=last]
+            .iter()
+            .rev()
+            .find_map(|s| s.changes.iter().find(|(changed, _)| changed == name))
+            .and_then(|(_, value)| value.clone())
+    }
+}
+
+/// 1-based line of the start of `span` in `source`, or `None` for a node
+/// without a source position.
+pub(crate) fn line_of(source: &str, span: Span) -> Option<usize> {
+    if span == Span::default() || span.start > source.len() {
+        return None;
+    }
+    Some(span.line_col(source).0)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::Interpreter;
+    use crate::Value;
+
+    #[test]
+    fn test_value_at_line() {
+        let mut interp = Interpreter::new();
+        interp.runtime_mut().start_history();
+        let code = "{
+    var commit_plan = \"a\"
+    var count = 0
+    for var step in [\"b\", \"c\"] {
+        commit_plan = commit_plan + step
+    }
+    count = len(commit_plan)
+}";
+        interp.eval(code).unwrap();
+        let history = interp.runtime().history().expect("history is on");
+
+        let plan = |s: &str| Value::String(s.to_string());
+        assert_eq!(history.value_at("commit_plan", 2), Some(plan("a")));
+        // A line that runs more than once reports its last run
+        assert_eq!(history.value_at("commit_plan", 5), Some(plan("abc")));
+        assert_eq!(history.value_at("count", 3), Some(Value::Number(0.0)));
+        assert_eq!(history.value_at("count", 7), Some(Value::Number(3.0)));
+        // The loop variable is only in scope inside the loop
+        assert_eq!(history.value_at("step", 5), Some(Value::String("c".to_string())));
+        assert_eq!(history.value_at("step", 7), None);
+        assert!(!history.reached(40));
+    }
+}
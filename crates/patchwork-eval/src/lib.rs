@@ -5,18 +5,42 @@
 //! modeled as `Error::Exception(Value)` and propagate using Rust's `?` operator.
 
 mod agent;
+mod control;
+mod diff;
 mod error;
 mod eval;
+mod events;
+mod formats;
+mod git;
+mod methods;
+mod modules;
+mod objects;
+mod render;
 mod interpreter;
 mod runtime;
+mod scheduler;
+mod shell;
+mod stdlib;
+mod template;
+mod history;
+mod trace;
+pub mod trust;
+mod types;
 mod value;
 
-pub use agent::{AgentHandle, ThinkRequest, ThinkResponse};
+pub use agent::{AgentHandle, Conversation, FollowUp, ThinkRequest, ThinkResponse};
 pub use error::Error;
 pub use eval::{eval_block, eval_expr, eval_statement};
+pub use events::{spawn_event_writer, EventSink, RuntimeEvent};
 pub use interpreter::Interpreter;
-pub use runtime::{PlanEntry, PlanEntryStatus, PlanReporter, PlanUpdate, PrintSink, Runtime, ThoughtChunk, ThoughtReporter};
-pub use value::Value;
+pub use scheduler::Task;
+pub use runtime::{DEFAULT_RECURSION_LIMIT, DEFAULT_VALIDATION_RETRIES, LogLevel, OutputLine, OutputSink, PlanEntry, PlanEntryStatus, PlanReporter, PlanUpdate, PromptKind, Runtime, ShellPolicy, ThoughtChunk, ThoughtReporter, UserPrompt, UserPrompter, VariantPolicy};
+pub use history::History;
+pub use shell::{ProcessExecutor, ShellCommand, ShellExecutor, Tick};
+pub use trace::{parse_trace, TraceEntry};
+pub use types::{FieldType, Type, TypeCheckMode};
+pub use value::{Closure, ShellResult, Value};
+pub use patchwork_parser::Edition;
 
 /// Result type for interpreter operations.
 pub type Result<T> = std::result::Result<T, Error>;
@@ -0,0 +1,259 @@
+//! Methods called with member syntax on values, such as `text.trim()`.
+//!
+//! Objects have no methods of their own: `obj.f()` calls the function
+//! stored in field `f`.
+
+use std::cmp::Ordering;
+
+use crate::eval::type_name;
+use crate::value::Value;
+
+/// String methods with how to call each, for error messages.
+const STRING_METHODS: &[(&str, &str)] = &[
+    ("trim", "trim()"),
+    ("split", "split(separator: string)"),
+    ("replace", "replace(from: string, to: string)"),
+    ("to_lower", "to_lower()"),
+    ("to_upper", "to_upper()"),
+    ("starts_with", "starts_with(prefix: string)"),
+    ("ends_with", "ends_with(suffix: string)"),
+    ("contains", "contains(text: string)"),
+    ("lines", "lines()"),
+];
+
+/// Array methods with how to call each. `map` and `filter` call back into
+/// the program, so the evaluator handles them.
+const ARRAY_METHODS: &[(&str, &str)] = &[
+    ("len", "len()"),
+    ("join", "join(separator: string)"),
+    ("sort", "sort()"),
+    ("contains", "contains(value)"),
+    ("push", "push(value)"),
+    ("pop", "pop()"),
+    ("map", "map(f: function)"),
+    ("filter", "filter(f: function)"),
+];
+
+/// Read property `name` of a string.
+pub(crate) fn string_property(s: &str, name: &str) -> Option<Value> {
+    match name {
+        "length" => Some(Value::Number(s.chars().count() as f64)),
+        _ => None,
+    }
+}
+
+/// Call method `name` on a string.
+pub(crate) fn string_method(s: &str, name: &str, args: &[Value]) -> Result<Value, String> {
+    let value = match (name, args) {
+        ("trim", []) => Value::String(s.trim().to_string()),
+        // An empty separator splits into characters
+        ("split", [Value::String(sep)]) if sep.is_empty() => strings(s.chars().map(String::from)),
+        ("split", [Value::String(sep)]) => strings(s.split(sep.as_str()).map(str::to_string)),
+        ("replace", [Value::String(from), Value::String(to)]) => Value::String(s.replace(from.as_str(), to)),
+        ("to_lower", []) => Value::String(s.to_lowercase()),
+        ("to_upper", []) => Value::String(s.to_uppercase()),
+        ("starts_with", [Value::String(prefix)]) => Value::Boolean(s.starts_with(prefix.as_str())),
+        ("ends_with", [Value::String(suffix)]) => Value::Boolean(s.ends_with(suffix.as_str())),
+        ("contains", [Value::String(text)]) => Value::Boolean(s.contains(text.as_str())),
+        ("lines", []) => strings(s.lines().map(str::to_string)),
+        _ => return Err(misuse("string", STRING_METHODS, name, args)),
+    };
+    Ok(value)
+}
+
+/// Read property `name` of an array.
+pub(crate) fn array_property(items: &[Value], name: &str) -> Option<Value> {
+    match name {
+        "length" => Some(Value::Number(items.len() as f64)),
+        _ => None,
+    }
+}
+
+/// Call a method that reads an array without changing it.
+pub(crate) fn array_method(items: Vec<Value>, name: &str, args: &[Value]) -> Result<Value, String> {
+    let value = match (name, args) {
+        ("len", []) => Value::Number(items.len() as f64),
+        ("join", [Value::String(sep)]) => {
+            let parts: Vec<String> = items.iter().map(Value::to_string_value).collect();
+            Value::String(parts.join(sep))
+        }
+        ("sort", []) => Value::Array(sorted(items)?),
+        ("contains", [value]) => Value::Boolean(items.contains(value)),
+        _ => return Err(misuse("array", ARRAY_METHODS, name, args)),
+    };
+    Ok(value)
+}
+
+/// Whether array method `name` changes the array it's called on.
+pub(crate) fn mutates_array(name: &str) -> bool {
+    matches!(name, "push" | "pop")
+}
+
+/// Call `push` or `pop`, changing `items` in place. `push` returns the new
+/// length and `pop` the removed element, or null if there was none.
+pub(crate) fn array_mutation(items: &mut Vec<Value>, name: &str, args: &[Value]) -> Result<Value, String> {
+    match (name, args) {
+        ("push", [value]) => {
+            items.push(value.clone());
+            Ok(Value::Number(items.len() as f64))
+        }
+        ("pop", []) => Ok(items.pop().unwrap_or(Value::Null)),
+        _ => Err(misuse("array", ARRAY_METHODS, name, args)),
+    }
+}
+
+/// Order two values of the same kind: numbers, strings, booleans,
+/// durations, or sizes. Anything else has no order.
+pub(crate) fn compare(a: &Value, b: &Value) -> Option<Ordering> {
+    match (a, b) {
+        (Value::Number(a), Value::Number(b))
+        | (Value::Duration(a), Value::Duration(b))
+        | (Value::Size(a), Value::Size(b)) => Some(a.total_cmp(b)),
+        (Value::String(a), Value::String(b)) => Some(a.cmp(b)),
+        (Value::Boolean(a), Value::Boolean(b)) => Some(a.cmp(b)),
+        _ => None,
+    }
+}
+
+/// Sort values by [`compare`], failing unless they're all one orderable
+/// kind.
+fn sorted(items: Vec<Value>) -> Result<Vec<Value>, String> {
+    let keys = items.clone();
+    sorted_by_keys(items, &keys)
+}
+
+/// Sort `items` by the key computed for each, in `keys`. Items with equal
+/// keys keep their order.
+pub(crate) fn sorted_by_keys(items: Vec<Value>, keys: &[Value]) -> Result<Vec<Value>, String> {
+    check_orderable(keys, "sort")?;
+    let mut keyed: Vec<(Value, &Value)> = items.into_iter().zip(keys).collect();
+    keyed.sort_by(|(_, a), (_, b)| compare(a, b).unwrap_or(Ordering::Equal));
+    Ok(keyed.into_iter().map(|(item, _)| item).collect())
+}
+
+/// The first of `items` whose key is the smallest (for `Ordering::Less`)
+/// or largest (for `Ordering::Greater`), or null if there are none.
+pub(crate) fn extreme(items: &[Value], keys: &[Value], want: Ordering) -> Result<Value, String> {
+    let verb = if want == Ordering::Less { "take the min of" } else { "take the max of" };
+    check_orderable(keys, verb)?;
+    let mut best: Option<usize> = None;
+    for (i, key) in keys.iter().enumerate() {
+        if best.is_none_or(|b| compare(key, &keys[b]) == Some(want)) {
+            best = Some(i);
+        }
+    }
+    Ok(best.map_or(Value::Null, |i| items[i].clone()))
+}
+
+/// Add up numbers, durations, or sizes, all of one kind. An empty array
+/// sums to 0.
+pub(crate) fn sum(values: &[Value]) -> Result<Value, String> {
+    let Some(first) = values.first() else {
+        return Ok(Value::Number(0.0));
+    };
+    let mut total = 0.0;
+    for value in values {
+        match (first, value) {
+            (Value::Number(_), Value::Number(n))
+            | (Value::Duration(_), Value::Duration(n))
+            | (Value::Size(_), Value::Size(n)) => total += n,
+            _ => return Err(mixed("sum", first, value)),
+        }
+    }
+    Ok(match first {
+        Value::Duration(_) => Value::Duration(total),
+        Value::Size(_) => Value::Size(total),
+        _ => Value::Number(total),
+    })
+}
+
+/// Fail unless `keys` are all one orderable kind.
+fn check_orderable(keys: &[Value], verb: &str) -> Result<(), String> {
+    if let Some(first) = keys.first() {
+        if let Some(other) = keys.iter().find(|key| compare(first, key).is_none()) {
+            return Err(mixed(verb, first, other));
+        }
+    }
+    Ok(())
+}
+
+/// Explain why `a` and `b` can't be combined by the operation `verb`.
+fn mixed(verb: &str, a: &Value, b: &Value) -> String {
+    let (a, b) = (type_name(a), type_name(b));
+    if a == b {
+        format!("Cannot {} {} values", verb, a)
+    } else {
+        format!("Cannot {} an array mixing {} and {}", verb, a, b)
+    }
+}
+
+fn strings(items: impl Iterator<Item = String>) -> Value {
+    Value::Array(items.map(Value::String).collect())
+}
+
+/// Explain a call that matched no method: either the name is unknown, or
+/// the arguments don't fit.
+fn misuse(receiver: &str, methods: &[(&str, &str)], name: &str, args: &[Value]) -> String {
+    match methods.iter().find(|(method, _)| *method == name) {
+        Some((_, usage)) => {
+            let given: Vec<&str> = args.iter().map(type_name).collect();
+            format!("Expected {}.{}, got {}({})", receiver, usage, name, given.join(", "))
+        }
+        None => format!("{} has no method '{}'", receiver, name),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn call(s: &str, name: &str, args: &[&str]) -> Result<Value, String> {
+        let args: Vec<Value> = args.iter().map(|a| Value::String(a.to_string())).collect();
+        string_method(s, name, &args)
+    }
+
+    #[test]
+    fn test_string_methods() {
+        assert_eq!(call("  hi \n", "trim", &[]), Ok(Value::String("hi".to_string())));
+        assert_eq!(call("a,b", "split", &[","]).unwrap().to_string(), "a, b");
+        assert_eq!(call("ab", "split", &[""]).unwrap().to_string(), "a, b");
+        assert_eq!(call("a-a", "replace", &["a", "b"]), Ok(Value::String("b-b".to_string())));
+        assert_eq!(call("Ab", "to_lower", &[]), Ok(Value::String("ab".to_string())));
+        assert_eq!(call("feat: x", "starts_with", &["feat:"]), Ok(Value::Boolean(true)));
+        assert_eq!(call("one\r\ntwo\n", "lines", &[]).unwrap().to_string(), "one, two");
+        assert_eq!(string_property("héllo", "length"), Some(Value::Number(5.0)));
+
+        assert_eq!(call("x", "trim", &["y"]), Err("Expected string.trim(), got trim(string)".to_string()));
+        assert_eq!(call("x", "shout", &[]), Err("string has no method 'shout'".to_string()));
+    }
+
+    #[test]
+    fn test_array_methods() {
+        let numbers = vec![Value::Number(10.0), Value::Number(2.0), Value::Number(33.0)];
+        assert_eq!(array_method(numbers.clone(), "sort", &[]).unwrap().to_string(), "2, 10, 33");
+        assert_eq!(
+            array_method(numbers.clone(), "join", &[Value::String("-".to_string())]),
+            Ok(Value::String("10-2-33".to_string()))
+        );
+        assert_eq!(array_method(numbers.clone(), "contains", &[Value::Number(2.0)]), Ok(Value::Boolean(true)));
+        assert_eq!(array_property(&numbers, "length"), Some(Value::Number(3.0)));
+
+        let mixed = vec![Value::Number(1.0), Value::String("a".to_string())];
+        assert_eq!(array_method(mixed, "sort", &[]), Err("Cannot sort an array mixing number and string".to_string()));
+
+        let names: Vec<Value> = ["bo", "al", "cy"].iter().map(|s| Value::String(s.to_string())).collect();
+        let ages = vec![Value::Number(30.0), Value::Number(25.0), Value::Number(30.0)];
+        assert_eq!(Value::Array(sorted_by_keys(names.clone(), &ages).unwrap()).to_string(), "al, bo, cy");
+        assert_eq!(extreme(&names, &ages, Ordering::Greater), Ok(Value::String("bo".to_string())));
+        assert_eq!(extreme(&names, &ages, Ordering::Less), Ok(Value::String("al".to_string())));
+        assert_eq!(extreme(&[], &[], Ordering::Less), Ok(Value::Null));
+        assert_eq!(sum(&ages), Ok(Value::Number(85.0)));
+        assert_eq!(sum(&[Value::Duration(1000.0), Value::Duration(500.0)]), Ok(Value::Duration(1500.0)));
+        assert_eq!(sum(&names), Err("Cannot sum string values".to_string()));
+
+        let mut items = numbers;
+        assert_eq!(array_mutation(&mut items, "pop", &[]), Ok(Value::Number(33.0)));
+        assert_eq!(array_mutation(&mut items, "push", &[Value::Null]), Ok(Value::Number(3.0)));
+        assert_eq!(array_mutation(&mut items, "push", &[]), Err("Expected array.push(value), got push()".to_string()));
+    }
+}
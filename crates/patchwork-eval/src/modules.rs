@@ -0,0 +1,166 @@
+//! Finding and parsing the modules a program imports.
+//!
+//! `import ./{analyst, narrator}` loads `analyst.pw` and `narrator.pw` from
+//! the importing file's directory, and `import lib.render` loads
+//! `lib/render.pw` under the root directory. `import std` and `import
+//! std.log` name the builtin `std` and `log` namespaces, so they load
+//! nothing. Each file is read and parsed
+//! once per interpreter, however many modules import it, which also keeps
+//! modules that import each other from loading forever. A host can check
+//! each file before it's parsed, to refuse modules it doesn't trust.
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use patchwork_parser::ast::ImportPath;
+use patchwork_parser::{IntoStatic, ParseError, Program};
+
+use crate::error::Error;
+use crate::interpreter::{format_parse_error, type_check};
+use crate::runtime::Runtime;
+use crate::stdlib;
+
+/// The extension of module files.
+pub(crate) const MODULE_EXTENSION: &str = "pw";
+
+/// One module named by an import declaration.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Import {
+    /// The name the importing module calls it by
+    pub(crate) name: String,
+    pub(crate) path: PathBuf,
+}
+
+/// A module the loader has parsed.
+pub(crate) struct Module {
+    /// The canonical path of its file
+    pub(crate) path: PathBuf,
+    pub(crate) program: Arc<Program<'static>>,
+}
+
+/// Decides whether a module may load, given its canonical path and text.
+type ModuleCheck = Box<dyn Fn(&Path, &str) -> Result<(), String> + Send>;
+
+/// Parsed modules, by canonical path.
+#[derive(Default)]
+pub(crate) struct ModuleLoader {
+    /// Where non-relative imports resolve, if not the working directory
+    root: Option<PathBuf>,
+    check: Option<ModuleCheck>,
+    parsed: HashMap<PathBuf, Arc<Program<'static>>>,
+}
+
+impl ModuleLoader {
+    pub(crate) fn root(&self) -> Option<&Path> {
+        self.root.as_deref()
+    }
+
+    pub(crate) fn set_root(&mut self, root: PathBuf) {
+        self.root = Some(root);
+    }
+
+    pub(crate) fn set_check(&mut self, check: ModuleCheck) {
+        self.check = Some(check);
+    }
+
+    /// The modules an import declaration names. Relative imports resolve
+    /// against `base`, the importing file's directory, and dotted paths
+    /// against `root`.
+    pub(crate) fn resolve(path: &ImportPath, base: &Path, root: &Path) -> Vec<Import> {
+        match path {
+            ImportPath::RelativeMulti(names) => names
+                .iter()
+                .map(|name| Import {
+                    name: name.to_string(),
+                    path: base.join(&**name).with_extension(MODULE_EXTENSION),
+                })
+                .collect(),
+            // Builtins are always callable; importing them just says so
+            ImportPath::Simple(parts) if parts.first().map(|part| &**part) == Some(stdlib::NAMESPACE) => Vec::new(),
+            ImportPath::Simple(parts) => {
+                let path = parts.iter().fold(root.to_path_buf(), |path, part| path.join(&**part));
+                let name = parts.last().map_or_else(String::new, |name| name.to_string());
+                vec![Import { name, path: path.with_extension(MODULE_EXTENSION) }]
+            }
+        }
+    }
+
+    /// Parse and check the module at `path` under `runtime`'s edition and
+    /// type check mode, or return the program parsed when it was first
+    /// loaded. A file the host's check refuses isn't parsed.
+    pub(crate) fn load(&mut self, path: &Path, runtime: &Runtime) -> Result<Module, Error> {
+        let path = path
+            .canonicalize()
+            .map_err(|e| Error::Runtime(format!("Cannot import {}: {}", path.display(), e)))?;
+        if let Some(program) = self.parsed.get(&path) {
+            return Ok(Module { path, program: program.clone() });
+        }
+
+        let source = fs::read_to_string(&path)
+            .map_err(|e| Error::Runtime(format!("Error reading {}: {}", path.display(), e)))?;
+        if let Some(check) = &self.check {
+            check(&path, &source).map_err(|message| Error::Runtime(format!("Cannot import {}: {}", path.display(), message)))?;
+        }
+        let parse_error =
+            |e: &ParseError| Error::Parse(format!("{}: {}", path.display(), format_parse_error(e, &source)));
+        let program = patchwork_parser::parse_with_edition(&source, runtime.edition()).map_err(|e| parse_error(&e))?;
+        if let Some(e) = patchwork_parser::check::check(&program).first() {
+            return Err(parse_error(e));
+        }
+        type_check(&program, &source, runtime)
+            .map_err(|message| Error::Parse(format!("{}: {}", path.display(), message)))?;
+        // Registered functions outlive the import, as in `Interpreter::eval`
+        let program = Arc::new(program.into_static());
+        self.parsed.insert(path.clone(), program.clone());
+        Ok(Module { path, program })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resolve_and_cache() {
+        let dir = tempfile::tempdir().unwrap();
+        let (base, root) = (dir.path().join("agents"), dir.path());
+        let relative = ImportPath::RelativeMulti(vec!["analyst".into(), "scribe".into()]);
+        assert_eq!(
+            ModuleLoader::resolve(&relative, &base, root),
+            vec![
+                Import { name: "analyst".to_string(), path: base.join("analyst.pw") },
+                Import { name: "scribe".to_string(), path: base.join("scribe.pw") },
+            ]
+        );
+        let dotted = ImportPath::Simple(vec!["lib".into(), "render".into()]);
+        assert_eq!(
+            ModuleLoader::resolve(&dotted, &base, root),
+            vec![Import { name: "render".to_string(), path: root.join("lib/render.pw") }]
+        );
+        assert!(ModuleLoader::resolve(&ImportPath::Simple(vec!["std".into(), "log".into()]), &base, root).is_empty());
+
+        let path = root.join("helper.pw");
+        fs::write(&path, "fun help() {\n    return 1\n}\n").unwrap();
+        let (mut loader, runtime) = (ModuleLoader::default(), Runtime::default());
+        let first = loader.load(&path, &runtime).unwrap().program;
+        assert!(Arc::ptr_eq(&first, &loader.load(&path, &runtime).unwrap().program));
+        assert!(loader.load(&root.join("missing.pw"), &runtime).is_err());
+
+        let typed = root.join("typed.pw");
+        fs::write(&typed, "fun help() {\n    var n: number = \"one\"\n}\n").unwrap();
+        assert!(matches!(loader.load(&typed, &runtime), Err(Error::Parse(_))));
+
+        // The check sees each file before it's parsed, and can refuse it
+        let mut loader = ModuleLoader::default();
+        loader.set_check(Box::new(|path, source| match source.contains("help") {
+            true => Err(format!("{} isn't trusted", path.file_name().unwrap().to_string_lossy())),
+            false => Ok(()),
+        }));
+        match loader.load(&typed, &runtime) {
+            Err(Error::Runtime(msg)) => assert!(msg.ends_with("typed.pw isn't trusted"), "{}", msg),
+            other => panic!("Expected the check to refuse the module, got {:?}", other.map(|m| m.path)),
+        }
+    }
+}
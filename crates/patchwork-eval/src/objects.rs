@@ -0,0 +1,245 @@
+//! Reshaping objects, for the `merge`, `patch`, `pick`, and `omit` builtins.
+//!
+//! `patch` applies a JSON Patch (RFC 6902): an array of operations such as
+//! `{op: "replace", path: "/steps/0/name", value: "build"}`, where `path` is
+//! a JSON Pointer (RFC 6901). The operations are `add`, `remove`, `replace`,
+//! `move`, `copy`, and `test`, and either all of them apply or none do.
+
+use indexmap::IndexMap;
+
+use crate::eval::type_name;
+use crate::value::Value;
+
+/// Merge `right` into `left`. Fields that are objects on both sides merge
+/// recursively; otherwise the right side wins, including arrays and nulls.
+pub(crate) fn merge(left: &Value, right: &Value) -> Value {
+    match (left, right) {
+        (Value::Object(left), Value::Object(right)) => {
+            let mut merged = left.clone();
+            for (key, value) in right {
+                let value = match merged.get(key) {
+                    Some(existing) => merge(existing, value),
+                    None => value.clone(),
+                };
+                merged.insert(key.clone(), value);
+            }
+            Value::Object(merged)
+        }
+        (_, right) => right.clone(),
+    }
+}
+
+/// The fields of `fields` named in `keys`, in the order of `keys`. Missing
+/// fields are left out.
+pub(crate) fn pick(fields: &IndexMap<String, Value>, keys: &[String]) -> Value {
+    let picked = keys
+        .iter()
+        .filter_map(|key| fields.get(key).map(|value| (key.clone(), value.clone())))
+        .collect();
+    Value::Object(picked)
+}
+
+/// `fields` without the ones named in `keys`.
+pub(crate) fn omit(fields: &IndexMap<String, Value>, keys: &[String]) -> Value {
+    let kept = fields
+        .iter()
+        .filter(|(key, _)| !keys.contains(key))
+        .map(|(key, value)| (key.clone(), value.clone()))
+        .collect();
+    Value::Object(kept)
+}
+
+/// Apply a JSON Patch to `target`.
+pub(crate) fn patch(target: &Value, operations: &[Value]) -> Result<Value, String> {
+    let mut document = target.clone();
+    for (i, operation) in operations.iter().enumerate() {
+        apply(&mut document, operation).map_err(|e| format!("patch operation {}: {}", i, e))?;
+    }
+    Ok(document)
+}
+
+fn apply(document: &mut Value, operation: &Value) -> Result<(), String> {
+    let Value::Object(fields) = operation else {
+        return Err(format!("expected an object, got {}", type_name(operation)));
+    };
+    let string_field = |name: &str| match fields.get(name) {
+        Some(Value::String(s)) => Ok(s.as_str()),
+        Some(other) => Err(format!("`{}` must be a string, got {}", name, type_name(other))),
+        None => Err(format!("missing `{}`", name)),
+    };
+    let value = || fields.get("value").cloned().ok_or_else(|| "missing `value`".to_string());
+
+    let path = pointer(string_field("path")?)?;
+    match string_field("op")? {
+        "add" => add(document, &path, value()?),
+        "remove" => remove(document, &path).map(|_| ()),
+        "replace" => {
+            let slot = get_mut(document, &path)?;
+            *slot = value()?;
+            Ok(())
+        }
+        "move" => {
+            let from = pointer(string_field("from")?)?;
+            if path.len() > from.len() && path[..from.len()] == from[..] {
+                return Err("cannot move a value into itself".to_string());
+            }
+            let moved = remove(document, &from)?;
+            add(document, &path, moved)
+        }
+        "copy" => {
+            let copied = get_mut(document, &pointer(string_field("from")?)?)?.clone();
+            add(document, &path, copied)
+        }
+        "test" => {
+            let expected = value()?;
+            let actual = get_mut(document, &path)?;
+            if *actual == expected {
+                Ok(())
+            } else {
+                Err(format!(
+                    "test failed at {}: expected {}, found {}",
+                    join(&path), expected.to_json_value(), actual.to_json_value()
+                ))
+            }
+        }
+        other => Err(format!("unknown op `{}`", other)),
+    }
+}
+
+/// Split a JSON Pointer into its unescaped reference tokens.
+fn pointer(path: &str) -> Result<Vec<String>, String> {
+    if path.is_empty() {
+        return Ok(Vec::new());
+    }
+    let Some(rest) = path.strip_prefix('/') else {
+        return Err(format!("path `{}` must start with `/`", path));
+    };
+    Ok(rest.split('/').map(|token| token.replace("~1", "/").replace("~0", "~")).collect())
+}
+
+fn join(path: &[String]) -> String {
+    path.iter().map(|token| format!("/{}", token.replace('~', "~0").replace('/', "~1"))).collect()
+}
+
+fn get_mut<'a>(document: &'a mut Value, path: &[String]) -> Result<&'a mut Value, String> {
+    let mut current = document;
+    for (depth, token) in path.iter().enumerate() {
+        let missing = || format!("nothing at {}", join(&path[..=depth]));
+        current = match current {
+            Value::Object(fields) => fields.get_mut(token).ok_or_else(missing)?,
+            Value::Array(items) => {
+                let index = index(token, items.len())?;
+                items.get_mut(index).ok_or_else(missing)?
+            }
+            other => return Err(format!("cannot look up {} in {}", join(&path[..=depth]), type_name(other))),
+        };
+    }
+    Ok(current)
+}
+
+fn add(document: &mut Value, path: &[String], value: Value) -> Result<(), String> {
+    let Some((last, parent)) = path.split_last() else {
+        *document = value;
+        return Ok(());
+    };
+    match get_mut(document, parent)? {
+        Value::Object(fields) => {
+            fields.insert(last.clone(), value);
+        }
+        Value::Array(items) if last == "-" => items.push(value),
+        Value::Array(items) => {
+            let index = index(last, items.len())?;
+            if index > items.len() {
+                return Err(format!("index {} is past the end of {}", index, join(parent)));
+            }
+            items.insert(index, value);
+        }
+        other => return Err(format!("cannot add to {}", type_name(other))),
+    }
+    Ok(())
+}
+
+fn remove(document: &mut Value, path: &[String]) -> Result<Value, String> {
+    let Some((last, parent)) = path.split_last() else {
+        return Err("cannot remove the whole document".to_string());
+    };
+    let missing = || format!("nothing at {}", join(path));
+    match get_mut(document, parent)? {
+        Value::Object(fields) => fields.shift_remove(last).ok_or_else(missing),
+        Value::Array(items) => {
+            let index = index(last, items.len())?;
+            if index >= items.len() {
+                return Err(missing());
+            }
+            Ok(items.remove(index))
+        }
+        other => Err(format!("cannot remove from {}", type_name(other))),
+    }
+}
+
+/// An array index token. Leading zeros aren't allowed, as in RFC 6901.
+fn index(token: &str, len: usize) -> Result<usize, String> {
+    let digits = !token.is_empty() && token.bytes().all(|b| b.is_ascii_digit());
+    match token.parse() {
+        Ok(index) if digits && (token == "0" || !token.starts_with('0')) => Ok(index),
+        _ => Err(format!("`{}` is not an index into an array of {}", token, len)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn json(text: &str) -> Value {
+        Value::from_json(text).unwrap()
+    }
+
+    #[test]
+    fn test_merge_is_deep_and_right_biased() {
+        let left = json(r#"{"name": "ci", "env": {"CI": "1", "DEBUG": "0"}, "steps": ["build"]}"#);
+        let right = json(r#"{"env": {"DEBUG": "1"}, "steps": ["test"], "timeout": null}"#);
+        assert_eq!(
+            merge(&left, &right),
+            json(r#"{"name": "ci", "env": {"CI": "1", "DEBUG": "1"}, "steps": ["test"], "timeout": null}"#)
+        );
+    }
+
+    #[test]
+    fn test_pick_and_omit() {
+        let Value::Object(fields) = json(r#"{"a": 1, "b": 2, "c": 3}"#) else { unreachable!() };
+        let keys = ["c".to_string(), "a".to_string(), "z".to_string()];
+        assert_eq!(pick(&fields, &keys), json(r#"{"c": 3, "a": 1}"#));
+        assert_eq!(omit(&fields, &keys), json(r#"{"b": 2}"#));
+    }
+
+    #[test]
+    fn test_json_patch() {
+        let document = json(r#"{"steps": ["build", "test"], "env": {"a/b": 1}}"#);
+        let operations = json(
+            r#"[
+                {"op": "add", "path": "/steps/1", "value": "lint"},
+                {"op": "add", "path": "/steps/-", "value": "deploy"},
+                {"op": "replace", "path": "/env/a~1b", "value": 2},
+                {"op": "copy", "from": "/env", "path": "/backup"},
+                {"op": "move", "from": "/steps/0", "path": "/first"},
+                {"op": "remove", "path": "/env"},
+                {"op": "test", "path": "/backup/a~1b", "value": 2}
+            ]"#,
+        );
+        let Value::Array(operations) = operations else { unreachable!() };
+        assert_eq!(
+            patch(&document, &operations).unwrap(),
+            json(r#"{"steps": ["lint", "test", "deploy"], "backup": {"a/b": 2}, "first": "build"}"#)
+        );
+
+        let failing = [json(r#"{"op": "remove", "path": "/missing"}"#)];
+        assert_eq!(patch(&document, &failing), Err("patch operation 0: nothing at /missing".to_string()));
+        let failing = [json(r#"{"op": "test", "path": "/steps/0", "value": "lint"}"#)];
+        assert_eq!(
+            patch(&document, &failing),
+            Err(r#"patch operation 0: test failed at /steps/0: expected "lint", found "build""#.to_string())
+        );
+        let failing = [json(r#"{"op": "add", "path": "/steps/01", "value": 0}"#)];
+        assert!(patch(&document, &failing).unwrap_err().contains("`01` is not an index"));
+    }
+}
@@ -0,0 +1,136 @@
+//! Markdown rendering of values, for the `render.*` builtins.
+//!
+//! The output is plain Markdown, so it reads well in a terminal and renders
+//! as a table or list when printed into an ACP session.
+
+use crate::eval::type_name;
+use crate::value::Value;
+
+/// Render rows of objects as a Markdown table.
+///
+/// Columns are `columns` if given, otherwise every key of every row in
+/// sorted order. Missing cells are left empty.
+pub(crate) fn table(rows: &[Value], columns: Option<Vec<String>>) -> Result<String, String> {
+    let mut objects = Vec::new();
+    for row in rows {
+        match row {
+            Value::Object(object) => objects.push(object),
+            other => return Err(format!("render.table() rows must be objects, got {}", type_name(other))),
+        }
+    }
+    let columns = columns.unwrap_or_else(|| {
+        let mut keys: Vec<String> = objects.iter().flat_map(|object| object.keys().cloned()).collect();
+        keys.sort();
+        keys.dedup();
+        keys
+    });
+    if columns.is_empty() {
+        return Ok(String::new());
+    }
+
+    let cells: Vec<Vec<String>> = objects
+        .iter()
+        .map(|object| columns.iter().map(|column| object.get(column).map(cell).unwrap_or_default()).collect())
+        .collect();
+    // Pad columns to a common width so the table lines up as plain text too
+    let widths: Vec<usize> = columns
+        .iter()
+        .enumerate()
+        .map(|(i, column)| {
+            cells.iter().map(|row| row[i].chars().count()).chain([column.chars().count(), 3]).max().unwrap_or(3)
+        })
+        .collect();
+
+    let line = |values: &[String]| {
+        let padded: Vec<String> =
+            values.iter().zip(&widths).map(|(value, width)| format!("{:<width$}", value, width = width)).collect();
+        format!("| {} |\n", padded.join(" | "))
+    };
+    let mut out = line(&columns);
+    out.push_str(&line(&widths.iter().map(|width| "-".repeat(*width)).collect::<Vec<_>>()));
+    for row in &cells {
+        out.push_str(&line(row));
+    }
+    Ok(out)
+}
+
+/// Render any value as Markdown.
+///
+/// Strings are taken as Markdown already. Arrays of objects become tables,
+/// other arrays bullet lists, and objects lists of bold keys.
+pub(crate) fn markdown(value: &Value) -> String {
+    match value {
+        Value::String(text) if text.ends_with('\n') => text.clone(),
+        Value::Array(items) if !items.is_empty() && items.iter().all(|item| matches!(item, Value::Object(_))) => {
+            table(items, None).unwrap_or_default()
+        }
+        Value::Array(items) => items.iter().map(|item| list_item(None, item, 0)).collect(),
+        Value::Object(object) => {
+            let mut keys: Vec<&String> = object.keys().collect();
+            keys.sort();
+            keys.into_iter().map(|key| list_item(Some(key), &object[key], 0)).collect()
+        }
+        other => format!("{}\n", other.to_string_value()),
+    }
+}
+
+fn list_item(key: Option<&str>, value: &Value, depth: usize) -> String {
+    let indent = "  ".repeat(depth);
+    let label = key.map(|key| format!("**{}**: ", key)).unwrap_or_default();
+    match value {
+        Value::Array(items) if !items.is_empty() => {
+            let nested: String = items.iter().map(|item| list_item(None, item, depth + 1)).collect();
+            format!("{}- {}\n{}", indent, label.trim_end_matches(' '), nested)
+        }
+        Value::Object(object) if !object.is_empty() => {
+            let mut keys: Vec<&String> = object.keys().collect();
+            keys.sort();
+            let nested: String = keys.into_iter().map(|key| list_item(Some(key), &object[key], depth + 1)).collect();
+            format!("{}- {}\n{}", indent, label.trim_end_matches(' '), nested)
+        }
+        other => format!("{}- {}{}\n", indent, label, other.to_string_value()),
+    }
+}
+
+/// A value as a single table cell: nested values as JSON, and no pipes or
+/// newlines to break the row.
+fn cell(value: &Value) -> String {
+    let text = match value {
+        Value::Array(_) | Value::Object(_) => value.to_json_value().to_string(),
+        other => other.to_string_value(),
+    };
+    text.replace('|', "\\|").replace('\n', " ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn object(fields: &[(&str, Value)]) -> Value {
+        Value::Object(fields.iter().map(|(k, v)| (k.to_string(), v.clone())).collect())
+    }
+
+    #[test]
+    fn test_table_and_markdown() {
+        let rows = vec![
+            object(&[("sha", Value::String("a1b2".into())), ("subject", Value::String("Fix | parser".into()))]),
+            object(&[("sha", Value::String("c3".into()))]),
+        ];
+        assert_eq!(
+            table(&rows, None).unwrap(),
+            "| sha  | subject       |\n| ---- | ------------- |\n| a1b2 | Fix \\| parser |\n| c3   |               |\n"
+        );
+        assert_eq!(
+            table(&rows, Some(vec!["subject".to_string()])).unwrap().lines().next(),
+            Some("| subject       |")
+        );
+        assert!(table(&[Value::Number(1.0)], None).is_err());
+
+        let summary = object(&[
+            ("files", Value::Array(vec![Value::String("a.rs".into()), Value::String("b.rs".into())])),
+            ("passed", Value::Boolean(true)),
+        ]);
+        assert_eq!(markdown(&summary), "- **files**:\n  - a.rs\n  - b.rs\n- **passed**: true\n");
+        assert_eq!(markdown(&Value::String("# Done".into())), "# Done\n");
+    }
+}
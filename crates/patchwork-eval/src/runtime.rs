@@ -1,13 +1,87 @@
 //! Runtime environment for the Patchwork interpreter.
 
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt;
 use std::path::PathBuf;
 use std::sync::mpsc::Sender;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime};
 
+use indexmap::IndexMap;
+use patchwork_parser::ast::{ExampleDecl, FragmentDecl, FunctionDecl, Span, Spanned, Statement};
+use patchwork_parser::Edition;
+
+use crate::control::ControlFile;
+use crate::error::Error;
+use crate::events::{EventSink, RuntimeEvent};
+use crate::history::{line_of, History};
+use crate::scheduler::Mailbox;
+use crate::shell::{ProcessExecutor, ShellExecutor};
+use crate::trace::{TraceEntry, Tracer};
+use crate::types::{Type, TypeCheckMode};
 use crate::value::Value;
 
-/// A sink for print output, allowing redirection away from stdout.
-pub type PrintSink = Sender<String>;
+/// How severe a `log.*` message is. Levels are ordered, so a log filter
+/// lets through everything at or above one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum LogLevel {
+    Debug,
+    Info,
+    Warn,
+    Error,
+}
+
+impl LogLevel {
+    /// The level's name, as in `log.warn`.
+    pub fn name(self) -> &'static str {
+        match self {
+            LogLevel::Debug => "debug",
+            LogLevel::Info => "info",
+            LogLevel::Warn => "warn",
+            LogLevel::Error => "error",
+        }
+    }
+
+    /// Parse a level by name.
+    pub fn parse(name: &str) -> Option<Self> {
+        [LogLevel::Debug, LogLevel::Info, LogLevel::Warn, LogLevel::Error]
+            .into_iter()
+            .find(|level| level.name() == name)
+    }
+}
+
+impl fmt::Display for LogLevel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// A line of program output, from `print` or one of the `log.*` builtins.
+#[derive(Debug, Clone, PartialEq)]
+pub struct OutputLine {
+    /// The log level, or None for `print`
+    pub level: Option<LogLevel>,
+    pub text: String,
+    pub timestamp: SystemTime,
+    /// The statement that produced the line, as byte offsets into the
+    /// source it was parsed from
+    pub span: Option<Span>,
+}
+
+/// Printed lines show as their text, and log lines with their level:
+/// `[warn] disk almost full`.
+impl fmt::Display for OutputLine {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.level {
+            Some(level) => write!(f, "[{}] {}", level, self.text),
+            None => f.write_str(&self.text),
+        }
+    }
+}
+
+/// A sink for program output, allowing redirection away from stdout.
+pub type OutputSink = Sender<OutputLine>;
 
 /// Status of a plan entry.
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -46,6 +120,106 @@ pub struct ThoughtChunk {
 /// A sink for thought chunks, allowing the ACP proxy to stream agent reasoning.
 pub type ThoughtReporter = Sender<ThoughtChunk>;
 
+/// What a prompt for the person running the program asks for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PromptKind {
+    /// Free text, for an `ask { ... }` block with no agent to answer it
+    Ask,
+    /// A yes or no, for `approve()`; answered "yes" or "no"
+    Approve,
+}
+
+/// A question for the person running the program.
+#[derive(Debug)]
+pub struct UserPrompt {
+    pub kind: PromptKind,
+    /// The variable an ask block's answer is bound to, if any.
+    pub name: Option<String>,
+    /// The interpolated prompt or approval message.
+    pub text: String,
+    /// The answer to give if the person just presses enter, or if no one can
+    /// answer.
+    pub default: Option<String>,
+    /// Which prompt of the run this is, counting from 1.
+    pub ordinal: usize,
+    /// Channel for the answer, or an error if there's no one to give one.
+    pub response_tx: Sender<Result<String, String>>,
+}
+
+/// A sink for prompts, answered by whoever is running the program.
+pub type UserPrompter = Sender<UserPrompt>;
+
+/// Which shell commands a program may run.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum ShellPolicy {
+    /// Any command may run.
+    #[default]
+    Allow,
+    /// No commands may run.
+    Deny,
+    /// Only the named commands may run.
+    AllowList(Vec<String>),
+}
+
+impl ShellPolicy {
+    /// Whether the command `name` may run under this policy.
+    pub fn permits(&self, name: &str) -> bool {
+        match self {
+            ShellPolicy::Allow => true,
+            ShellPolicy::Deny => false,
+            ShellPolicy::AllowList(names) => names.iter().any(|n| n == name),
+        }
+    }
+}
+
+/// How a think or ask block with `variant` texts picks the one to run.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum VariantPolicy {
+    /// Use the block's first text.
+    #[default]
+    Default,
+    /// Use the variant with this name, where a block declares it.
+    Fixed(String),
+    /// Use the variant named by this environment variable, when it is set.
+    Env(String),
+    /// Pick a text at random and log the choice to stderr.
+    Random,
+}
+
+/// One level of the scope stack.
+#[derive(Debug, Clone, Default)]
+pub struct Scope {
+    vars: HashMap<String, Value>,
+    /// Names declared with `const`, which `set_var` refuses to rebind.
+    constants: HashSet<String>,
+}
+
+/// Which imported module's scope code runs in, by the order modules were
+/// first imported.
+pub(crate) type ModuleId = usize;
+
+/// The functions and config block one module's code sees: the program's
+/// own, or an imported module's.
+#[derive(Debug, Clone, Default)]
+struct Namespace {
+    functions: HashMap<String, Function>,
+    config: Option<IndexMap<String, Value>>,
+}
+
+/// A function, and the module whose scope its body runs in.
+#[derive(Debug, Clone)]
+struct Function {
+    decl: Arc<FunctionDecl<'static>>,
+    module: Option<ModuleId>,
+}
+
+/// Default limit on nested evaluation depth (expressions and blocks).
+pub const DEFAULT_RECURSION_LIMIT: usize = 10_000;
+
+/// Default number of times a think or ask block is re-asked after its
+/// `validate` clause rejects the answer.
+pub const DEFAULT_VALIDATION_RETRIES: u32 = 2;
+
 /// The runtime environment for executing Patchwork code.
 ///
 /// Holds variable bindings and execution context like the working directory.
@@ -53,43 +227,248 @@ pub type ThoughtReporter = Sender<ThoughtChunk>;
 pub struct Runtime {
     /// Variable bindings, organized as a stack of scopes.
     /// Inner vec is the most recent scope, outer vec contains parent scopes.
-    scopes: Vec<HashMap<String, Value>>,
+    scopes: Vec<Scope>,
     /// Current working directory for file operations and shell commands.
     working_dir: PathBuf,
-    /// Optional sink for print output. If None, prints go to stdout.
-    print_sink: Option<PrintSink>,
+    /// Optional sink for program output. If None, prints go to stdout and
+    /// log lines to stderr.
+    output_sink: Option<OutputSink>,
+    /// The least severe log level that's output.
+    log_filter: LogLevel,
+    /// Span of the statement being evaluated, for output lines.
+    statement_span: Option<Span>,
+    /// Span of the statement a runtime error is leaving, once it has left
+    /// the innermost one.
+    error_span: Option<Span>,
     /// Optional sink for plan updates. If None, no plan reporting.
     plan_reporter: Option<PlanReporter>,
     /// Optional sink for thought chunks. If None, no thought streaming.
     thought_reporter: Option<ThoughtReporter>,
+    /// Optional sink for prompts to the person running the program. If None,
+    /// `ask` blocks go to the agent and `approve()` takes its default.
+    user_prompter: Option<UserPrompter>,
+    /// Number of prompts sent to the user so far.
+    prompts_sent: usize,
+    /// Optional sink for runtime events. If None, no events are sent.
+    event_sink: Option<EventSink>,
+    /// Stack of active output captures (innermost last).
+    ///
+    /// While non-empty, print output is appended to the top buffer instead of
+    /// going to the output sink. Used to collect the output of do-blocks.
+    captures: Vec<String>,
+    /// Type aliases registered by `type` declarations.
+    types: HashMap<String, Type>,
+    /// User-defined functions registered from the program's `fun`
+    /// declarations and imports, and its config block.
+    program: Namespace,
+    /// The same for each imported module.
+    modules: Vec<Namespace>,
+    /// The module whose code is running, or `None` for the program's own.
+    module: Option<ModuleId>,
+    /// Few-shot examples registered from `example` declarations.
+    examples: HashMap<String, Arc<ExampleDecl<'static>>>,
+    /// Prompt text registered from `prompt fragment` declarations.
+    fragments: HashMap<String, Arc<FragmentDecl<'static>>>,
+    /// How typed parameters are checked on function entry.
+    type_check_mode: TypeCheckMode,
+    /// Which language edition programs are parsed as.
+    edition: Edition,
+    /// Which shell commands may run.
+    shell_policy: ShellPolicy,
+    /// Runs the shell commands the policy allows.
+    shell_executor: Arc<dyn ShellExecutor>,
+    /// Maximum number of think/ask blocks the program may evaluate.
+    think_budget: Option<usize>,
+    /// Number of think/ask blocks evaluated so far, shared with the tasks
+    /// the program spawned.
+    thinks_used: Arc<AtomicUsize>,
+    /// Think/ask blocks this runtime evaluated itself. A fork starts at zero.
+    thinks_charged: usize,
+    /// Tokens the agent reported for this runtime's own think blocks, if it
+    /// reported any. A fork starts with none.
+    tokens_charged: Option<u64>,
+    /// Which prompt text a block with variants runs.
+    variant_policy: VariantPolicy,
+    /// Values that replace or add to the fields of a program's config block.
+    config_overrides: HashMap<String, Value>,
+    /// Environment variables set by the program with `env.set`. They're
+    /// passed to shell commands rather than set on the interpreter process.
+    env_overrides: HashMap<String, String>,
+    /// Cached results of `memo fun` calls, keyed by function name and the
+    /// arguments' JSON encoding.
+    memo: HashMap<(String, String), Value>,
+    /// Records or replays shell and think effects.
+    tracer: Tracer,
+    /// Environment snapshots for time-travel inspection, when enabled.
+    history: Option<History>,
+    /// Current nesting depth of expression and block evaluation.
+    depth: usize,
+    /// Maximum nesting depth before evaluation fails.
+    recursion_limit: usize,
+    /// Re-asks allowed after a `validate` clause fails, unless the clause
+    /// gives its own `retries`
+    validation_retries: u32,
+    /// Limits of the enclosing `@timeout` statements, innermost last, as the
+    /// deadline and the limit in milliseconds.
+    deadlines: Vec<(Instant, f64)>,
+    /// Limit on each shell command and think block, on top of any
+    /// `@timeout`. None lets them run as long as they take.
+    default_timeout: Option<Duration>,
+    /// How often to report that the interpreter is still waiting on a shell
+    /// command or the agent. None disables heartbeats.
+    heartbeat_interval: Option<Duration>,
+    /// Program text the executing AST's spans point into.
+    source: Arc<str>,
+    /// File an operator writes to pause, resume, or abort the run.
+    control: Option<ControlFile>,
+    /// Set from another thread to stop the run before its next statement.
+    interrupt: Option<Arc<AtomicBool>>,
+    /// Messages sent to the program, or to the task this runtime runs.
+    mailbox: Arc<Mailbox>,
+    /// In a spawned task, the mailbox of whoever spawned it.
+    parent_mailbox: Option<Arc<Mailbox>>,
 }
 
 impl Runtime {
     /// Create a new runtime with the given working directory.
     pub fn new(working_dir: PathBuf) -> Self {
         Self {
-            scopes: vec![HashMap::new()],
+            scopes: vec![Scope::default()],
             working_dir,
-            print_sink: None,
+            output_sink: None,
+            log_filter: LogLevel::Debug,
+            statement_span: None,
+            error_span: None,
             plan_reporter: None,
             thought_reporter: None,
+            user_prompter: None,
+            prompts_sent: 0,
+            event_sink: None,
+            captures: Vec::new(),
+            types: HashMap::new(),
+            program: Namespace::default(),
+            modules: Vec::new(),
+            module: None,
+            examples: HashMap::new(),
+            fragments: HashMap::new(),
+            type_check_mode: TypeCheckMode::default(),
+            edition: Edition::default(),
+            shell_policy: ShellPolicy::default(),
+            shell_executor: Arc::new(ProcessExecutor),
+            think_budget: None,
+            thinks_used: Arc::new(AtomicUsize::new(0)),
+            thinks_charged: 0,
+            tokens_charged: None,
+            variant_policy: VariantPolicy::Default,
+            config_overrides: HashMap::new(),
+            env_overrides: HashMap::new(),
+            memo: HashMap::new(),
+            tracer: Tracer::Off,
+            history: None,
+            depth: 0,
+            recursion_limit: DEFAULT_RECURSION_LIMIT,
+            validation_retries: DEFAULT_VALIDATION_RETRIES,
+            deadlines: Vec::new(),
+            default_timeout: None,
+            heartbeat_interval: None,
+            source: Arc::from(""),
+            control: None,
+            interrupt: None,
+            mailbox: Arc::new(Mailbox::new()),
+            parent_mailbox: None,
         }
     }
 
-    /// Create a new runtime with a print sink for output redirection.
-    pub fn with_print_sink(working_dir: PathBuf, print_sink: PrintSink) -> Self {
+    /// Create a new runtime with an output sink for output redirection.
+    pub fn with_output_sink(working_dir: PathBuf, output_sink: OutputSink) -> Self {
         Self {
-            scopes: vec![HashMap::new()],
+            scopes: vec![Scope::default()],
             working_dir,
-            print_sink: Some(print_sink),
+            output_sink: Some(output_sink),
+            log_filter: LogLevel::Debug,
+            statement_span: None,
+            error_span: None,
             plan_reporter: None,
             thought_reporter: None,
+            user_prompter: None,
+            prompts_sent: 0,
+            event_sink: None,
+            captures: Vec::new(),
+            types: HashMap::new(),
+            program: Namespace::default(),
+            modules: Vec::new(),
+            module: None,
+            examples: HashMap::new(),
+            fragments: HashMap::new(),
+            type_check_mode: TypeCheckMode::default(),
+            edition: Edition::default(),
+            shell_policy: ShellPolicy::default(),
+            shell_executor: Arc::new(ProcessExecutor),
+            think_budget: None,
+            thinks_used: Arc::new(AtomicUsize::new(0)),
+            thinks_charged: 0,
+            tokens_charged: None,
+            variant_policy: VariantPolicy::Default,
+            config_overrides: HashMap::new(),
+            env_overrides: HashMap::new(),
+            memo: HashMap::new(),
+            tracer: Tracer::Off,
+            history: None,
+            depth: 0,
+            recursion_limit: DEFAULT_RECURSION_LIMIT,
+            validation_retries: DEFAULT_VALIDATION_RETRIES,
+            deadlines: Vec::new(),
+            default_timeout: None,
+            heartbeat_interval: None,
+            source: Arc::from(""),
+            control: None,
+            interrupt: None,
+            mailbox: Arc::new(Mailbox::new()),
+            parent_mailbox: None,
         }
     }
 
-    /// Set the print sink for output redirection.
-    pub fn set_print_sink(&mut self, sink: PrintSink) {
-        self.print_sink = Some(sink);
+    /// Set the output sink for output redirection.
+    pub fn set_output_sink(&mut self, sink: OutputSink) {
+        self.output_sink = Some(sink);
+    }
+
+    /// Drop log lines less severe than `level`. Printed output is never
+    /// filtered.
+    pub fn set_log_filter(&mut self, level: LogLevel) {
+        self.log_filter = level;
+    }
+
+    /// The least severe log level that's output.
+    pub fn log_filter(&self) -> LogLevel {
+        self.log_filter
+    }
+
+    /// Record the span of the statement about to run, returning the one it
+    /// replaces so it can be restored afterwards.
+    pub(crate) fn set_statement_span(&mut self, span: Option<Span>) -> Option<Span> {
+        std::mem::replace(&mut self.statement_span, span)
+    }
+
+    /// Note that a runtime error is leaving the statement at `span`. Only
+    /// the innermost statement counts, until another statement starts.
+    pub(crate) fn note_error_span(&mut self, span: Span) {
+        self.error_span.get_or_insert(span);
+    }
+
+    /// Forget an earlier error's statement, as another statement starts.
+    pub(crate) fn clear_error_span(&mut self) {
+        self.error_span = None;
+    }
+
+    /// The span of the statement the last runtime error came from.
+    pub(crate) fn take_error_span(&mut self) -> Option<Span> {
+        self.error_span.take()
+    }
+
+    /// An output line from the current statement.
+    fn output_line(&self, level: Option<LogLevel>, text: String) -> OutputLine {
+        OutputLine { level, text, timestamp: SystemTime::now(), span: self.statement_span }
     }
 
     /// Set the plan reporter for execution progress updates.
@@ -102,22 +481,169 @@ impl Runtime {
         self.thought_reporter = Some(reporter);
     }
 
+    /// Set the sink for prompts to the person running the program.
+    pub fn set_user_prompter(&mut self, prompter: UserPrompter) {
+        self.user_prompter = Some(prompter);
+    }
+
+    /// Set the sink for runtime events.
+    pub fn set_event_sink(&mut self, sink: EventSink) {
+        self.event_sink = Some(sink);
+    }
+
+    /// Send an event to the sink, if configured.
+    pub(crate) fn emit(&self, event: RuntimeEvent) {
+        if let Some(ref sink) = self.event_sink {
+            // Ignore errors - a tool that stopped listening doesn't stop the run
+            let _ = sink.send(event);
+        }
+    }
+
+    /// Send a `statement_started` event for `stmt`, if anyone is listening.
+    pub(crate) fn emit_statement(&self, stmt: &Spanned<Statement>) {
+        if self.event_sink.is_none() {
+            return;
+        }
+        let line = line_of(&self.source, stmt.span);
+        let text = line.and_then(|line| self.source.lines().nth(line - 1)).unwrap_or_default();
+        self.emit(RuntimeEvent::StatementStarted { line, text: text.trim().to_string() });
+    }
+
+    /// Whether prompts have somewhere to go.
+    pub(crate) fn has_user_prompter(&self) -> bool {
+        self.user_prompter.is_some()
+    }
+
+    /// Ask the person running the program, blocking until they answer.
+    ///
+    /// Returns None if no prompter is set.
+    pub(crate) fn prompt_user(
+        &mut self,
+        kind: PromptKind,
+        name: Option<&str>,
+        text: String,
+        default: Option<String>,
+    ) -> Option<Result<String, String>> {
+        let prompter = self.user_prompter.as_ref()?;
+        self.prompts_sent += 1;
+        let (response_tx, response_rx) = std::sync::mpsc::channel();
+        let name = name.map(str::to_string);
+        let prompt = UserPrompt { kind, name, text, default, ordinal: self.prompts_sent, response_tx };
+        if prompter.send(prompt).is_err() {
+            return Some(Err("Prompt channel disconnected".to_string()));
+        }
+        Some(response_rx.recv().unwrap_or_else(|_| Err("Prompt was never answered".to_string())))
+    }
+
     /// Send a print message to the sink, or stdout if no sink is configured.
     ///
     /// Returns Ok(()) on success, or Err if the channel is disconnected.
-    pub fn print(&self, message: String) -> Result<(), String> {
-        if let Some(ref sink) = self.print_sink {
-            sink.send(message).map_err(|e| format!("Print channel disconnected: {}", e))
-        } else {
-            println!("{}", message);
+    pub fn print(&mut self, message: String) -> Result<(), String> {
+        if let Some(buffer) = self.captures.last_mut() {
+            buffer.push_str(&message);
+            buffer.push('\n');
             Ok(())
+        } else {
+            self.emit(RuntimeEvent::Print { text: message.clone() });
+            if let Some(ref sink) = self.output_sink {
+                let line = self.output_line(None, message);
+                sink.send(line).map_err(|e| format!("Output channel disconnected: {}", e))
+            } else {
+                println!("{}", message);
+                Ok(())
+            }
         }
     }
 
+    /// Send a log message to the sink, or stderr if no sink is configured,
+    /// unless the log filter drops it.
+    ///
+    /// Log lines are diagnostics rather than program output, so they skip
+    /// captures and never end up in a prompt.
+    pub fn log(&mut self, level: LogLevel, message: String) -> Result<(), String> {
+        if level < self.log_filter {
+            return Ok(());
+        }
+        self.emit(RuntimeEvent::Log { level, text: message.clone() });
+        let line = self.output_line(Some(level), message);
+        match self.output_sink {
+            Some(ref sink) => sink.send(line).map_err(|e| format!("Output channel disconnected: {}", e)),
+            None => {
+                eprintln!("{}", line);
+                Ok(())
+            }
+        }
+    }
+
+    /// Print a rendered Markdown block.
+    ///
+    /// An output sink may be concatenating chunks into one Markdown document,
+    /// as ACP clients do, so the block is sent starting on a fresh line and
+    /// separated from whatever came before.
+    pub fn print_markdown(&mut self, markdown: &str) -> Result<(), String> {
+        let block = markdown.trim_end_matches('\n');
+        if self.captures.is_empty() && self.output_sink.is_some() {
+            self.print(format!("\n\n{}\n", block))
+        } else {
+            self.print(block.to_string())
+        }
+    }
+
+    /// Report that the interpreter is still waiting on `what`.
+    ///
+    /// Heartbeats aren't program output, so they skip captures and go to the
+    /// output sink as info-level log lines if one is set, or stderr
+    /// otherwise. A log filter above info drops them.
+    pub fn heartbeat(&self, what: &str, elapsed: Duration) {
+        if LogLevel::Info < self.log_filter {
+            return;
+        }
+        let elapsed = Value::Duration((elapsed.as_secs() * 1000) as f64).to_string_value();
+        let message = format!("[patchwork] still waiting on {} ({} elapsed)", what, elapsed);
+        match self.output_sink {
+            Some(ref sink) => {
+                let _ = sink.send(self.output_line(Some(LogLevel::Info), message));
+            }
+            None => eprintln!("{}", message),
+        }
+    }
+
+    /// Report a problem that doesn't stop the run.
+    ///
+    /// Like heartbeats, warnings skip captures and go to the output sink, as
+    /// warn-level log lines, if one is set, or stderr otherwise. A log
+    /// filter above warn drops them.
+    pub(crate) fn warn(&self, message: String) {
+        if LogLevel::Warn < self.log_filter {
+            return;
+        }
+        let line = self.output_line(Some(LogLevel::Warn), message);
+        match self.output_sink {
+            Some(ref sink) => {
+                let _ = sink.send(line);
+            }
+            None => eprintln!("{}", line),
+        }
+    }
+
+    /// Start capturing print output into a fresh buffer.
+    ///
+    /// Captures nest: output goes to the most recently started capture.
+    pub fn begin_capture(&mut self) {
+        self.captures.push(String::new());
+    }
+
+    /// Stop the innermost capture and return the output collected since
+    /// the matching `begin_capture`.
+    pub fn end_capture(&mut self) -> String {
+        self.captures.pop().unwrap_or_default()
+    }
+
     /// Send a plan update to the reporter, if configured.
     ///
     /// Silently does nothing if no reporter is configured.
     pub fn report_plan(&self, update: PlanUpdate) {
+        self.emit(RuntimeEvent::PlanUpdate(update.clone()));
         if let Some(ref reporter) = self.plan_reporter {
             // Ignore errors - if the channel is disconnected, we just don't report
             let _ = reporter.send(update);
@@ -144,9 +670,511 @@ impl Runtime {
         self.working_dir = dir;
     }
 
+    /// Register a type alias, replacing any earlier declaration of the same name.
+    pub fn define_type(&mut self, name: &str, ty: Type) {
+        self.types.insert(name.to_string(), ty);
+    }
+
+    /// Get the registered type aliases.
+    pub fn types(&self) -> &HashMap<String, Type> {
+        &self.types
+    }
+
+    fn namespace(&self) -> &Namespace {
+        match self.module {
+            Some(module) => &self.modules[module],
+            None => &self.program,
+        }
+    }
+
+    fn namespace_mut(&mut self) -> &mut Namespace {
+        match self.module {
+            Some(module) => &mut self.modules[module],
+            None => &mut self.program,
+        }
+    }
+
+    /// Register a user-defined function, replacing any earlier one of the same name.
+    ///
+    /// Cached `memo fun` results of the replaced function are dropped.
+    pub fn define_function(&mut self, decl: FunctionDecl<'static>) {
+        self.memo.retain(|(func, _), _| *func != decl.name);
+        let func = Function { decl: Arc::new(decl), module: self.module };
+        self.namespace_mut().functions.insert(func.decl.name.to_string(), func);
+    }
+
+    /// Register a function of `module` under a name other than its own, as
+    /// an imported module's exports are.
+    pub(crate) fn define_function_as(&mut self, name: &str, decl: Arc<FunctionDecl<'static>>, module: ModuleId) {
+        self.namespace_mut().functions.insert(name.to_string(), Function { decl, module: Some(module) });
+    }
+
+    /// Unregister a user-defined function and its cached results.
+    pub fn remove_function(&mut self, name: &str) {
+        self.memo.retain(|(func, _), _| func != name);
+        self.namespace_mut().functions.remove(name);
+    }
+
+    /// Look up a user-defined function by name, as the running code sees it.
+    pub fn get_function(&self, name: &str) -> Option<Arc<FunctionDecl<'static>>> {
+        self.resolve_function(name).map(|(decl, _)| decl)
+    }
+
+    /// Look up a function by name, with the module whose scope it runs in.
+    pub(crate) fn resolve_function(&self, name: &str) -> Option<(Arc<FunctionDecl<'static>>, Option<ModuleId>)> {
+        let func = self.namespace().functions.get(name)?;
+        Some((func.decl.clone(), func.module))
+    }
+
+    /// Start a scope for a newly imported module.
+    pub(crate) fn new_module(&mut self) -> ModuleId {
+        self.modules.push(Namespace::default());
+        self.modules.len() - 1
+    }
+
+    /// The module whose code is running, or `None` for the program's own.
+    pub(crate) fn module(&self) -> Option<ModuleId> {
+        self.module
+    }
+
+    /// Run code in `module`'s scope from here on, so calls find its own
+    /// functions and `config` is its config block. Returns the module to
+    /// enter again afterwards.
+    pub(crate) fn enter_module(&mut self, module: Option<ModuleId>) -> Option<ModuleId> {
+        let caller = std::mem::replace(&mut self.module, module);
+        if caller == module {
+            return caller;
+        }
+        let had_config = match caller {
+            Some(caller) => self.modules[caller].config.is_some(),
+            None => self.program.config.is_some(),
+        };
+        let config = self.namespace().config.clone();
+        let globals = &mut self.scopes[0];
+        match config {
+            Some(config) => {
+                globals.vars.insert("config".to_string(), Value::Object(config));
+                globals.constants.insert("config".to_string());
+            }
+            None if had_config => {
+                globals.vars.remove("config");
+                globals.constants.remove("config");
+            }
+            None => {}
+        }
+        caller
+    }
+
+    /// Register a few-shot example, replacing any earlier one of the same name.
+    pub fn define_example(&mut self, decl: ExampleDecl<'static>) {
+        self.examples.insert(decl.name.to_string(), Arc::new(decl));
+    }
+
+    /// Look up a few-shot example by name.
+    pub fn get_example(&self, name: &str) -> Option<Arc<ExampleDecl<'static>>> {
+        self.examples.get(name).cloned()
+    }
+
+    /// Register a prompt fragment, replacing any earlier one of the same name.
+    pub fn define_fragment(&mut self, decl: FragmentDecl<'static>) {
+        self.fragments.insert(decl.name.to_string(), Arc::new(decl));
+    }
+
+    /// Get the registered prompt fragments.
+    pub fn fragments(&self) -> &HashMap<String, Arc<FragmentDecl<'static>>> {
+        &self.fragments
+    }
+
+    /// Get the mode used for parameter type checks on function entry.
+    pub fn type_check_mode(&self) -> TypeCheckMode {
+        self.type_check_mode
+    }
+
+    /// Set the mode used for parameter type checks on function entry.
+    pub fn set_type_check_mode(&mut self, mode: TypeCheckMode) {
+        self.type_check_mode = mode;
+    }
+
+    /// Get the language edition programs are parsed as.
+    pub fn edition(&self) -> Edition {
+        self.edition
+    }
+
+    /// Set the language edition programs are parsed as.
+    pub fn set_edition(&mut self, edition: Edition) {
+        self.edition = edition;
+    }
+
+    /// Get the policy deciding which shell commands may run.
+    pub fn shell_policy(&self) -> &ShellPolicy {
+        &self.shell_policy
+    }
+
+    /// Set the policy deciding which shell commands may run.
+    pub fn set_shell_policy(&mut self, policy: ShellPolicy) {
+        self.shell_policy = policy;
+    }
+
+    /// Get the executor that runs shell commands.
+    pub fn shell_executor(&self) -> &Arc<dyn ShellExecutor> {
+        &self.shell_executor
+    }
+
+    /// Run shell commands with `executor` instead of as local processes.
+    pub fn set_shell_executor(&mut self, executor: Arc<dyn ShellExecutor>) {
+        self.shell_executor = executor;
+    }
+
+    /// Set the maximum nesting depth of expression and block evaluation.
+    pub fn set_recursion_limit(&mut self, limit: usize) {
+        self.recursion_limit = limit;
+    }
+
+    /// How many times a block is re-asked after its `validate` clause fails,
+    /// when the clause doesn't say.
+    pub fn validation_retries(&self) -> u32 {
+        self.validation_retries
+    }
+
+    /// Set the default number of re-asks for `validate` clauses.
+    pub fn set_validation_retries(&mut self, retries: u32) {
+        self.validation_retries = retries;
+    }
+
+    /// How often heartbeats are sent during long waits, if at all.
+    pub fn heartbeat_interval(&self) -> Option<Duration> {
+        self.heartbeat_interval
+    }
+
+    /// Send heartbeats at `interval` while waiting on a shell command or the
+    /// agent, or never if None.
+    pub fn set_heartbeat_interval(&mut self, interval: Option<Duration>) {
+        self.heartbeat_interval = interval;
+    }
+
+    /// Start a `@timeout` limit of `limit_ms` milliseconds from now.
+    pub fn push_deadline(&mut self, limit_ms: f64) {
+        let limit = Duration::from_secs_f64(limit_ms.max(0.0) / 1000.0);
+        self.deadlines.push((Instant::now() + limit, limit_ms));
+    }
+
+    /// End the innermost `@timeout` limit.
+    pub fn pop_deadline(&mut self) {
+        self.deadlines.pop();
+    }
+
+    /// Get the limit on each shell command and think block, if any.
+    pub fn default_timeout(&self) -> Option<Duration> {
+        self.default_timeout
+    }
+
+    /// Limit every shell command and think block to `timeout`, or lift the
+    /// limit with None. One that runs over raises a `TimeoutError`.
+    pub fn set_default_timeout(&mut self, timeout: Option<Duration>) {
+        self.default_timeout = timeout;
+    }
+
+    /// The earliest active deadline, with the limit in milliseconds that set it.
+    pub fn deadline(&self) -> Option<(Instant, f64)> {
+        self.deadlines.iter().copied().min_by_key(|(at, _)| *at)
+    }
+
+    /// Enter one level of nested evaluation.
+    ///
+    /// Returns an error once the recursion limit is reached.
+    pub fn enter_nested(&mut self) -> Result<(), String> {
+        if self.depth >= self.recursion_limit {
+            return Err(format!("Recursion limit exceeded ({} nested evaluations)", self.recursion_limit));
+        }
+        self.depth += 1;
+        Ok(())
+    }
+
+    /// Leave a level of nested evaluation.
+    pub fn exit_nested(&mut self) {
+        self.depth = self.depth.saturating_sub(1);
+    }
+
+    /// Look up a cached `memo fun` result.
+    pub fn memo_get(&self, func: &str, key: &str) -> Option<&Value> {
+        self.memo.get(&(func.to_string(), key.to_string()))
+    }
+
+    /// Cache a `memo fun` result.
+    pub fn memo_insert(&mut self, func: &str, key: String, value: Value) {
+        self.memo.insert((func.to_string(), key), value);
+    }
+
+    /// Get the policy choosing between prompt variants.
+    pub fn variant_policy(&self) -> &VariantPolicy {
+        &self.variant_policy
+    }
+
+    /// Set the policy choosing between prompt variants.
+    pub fn set_variant_policy(&mut self, policy: VariantPolicy) {
+        self.variant_policy = policy;
+    }
+
+    /// Set values that override fields of the program's config block.
+    pub fn set_config_overrides(&mut self, overrides: HashMap<String, Value>) {
+        self.config_overrides = overrides;
+    }
+
+    /// Bind the program's config block, with overrides applied, as the
+    /// global constant `config`, replacing any earlier program's.
+    ///
+    /// While an imported module's items are registered, this is the
+    /// module's own config block instead, which overrides don't change and
+    /// only the module's code sees.
+    pub fn set_config(&mut self, mut config: IndexMap<String, Value>) {
+        if self.module.is_none() {
+            config.extend(self.config_overrides.clone());
+        }
+        self.namespace_mut().config = Some(config.clone());
+        let globals = &mut self.scopes[0];
+        globals.vars.insert("config".to_string(), Value::Object(config));
+        globals.constants.insert("config".to_string());
+    }
+
+    /// Look up an environment variable, preferring values set by the
+    /// program over the process environment.
+    pub fn env_var(&self, name: &str) -> Option<String> {
+        self.env_overrides.get(name).cloned().or_else(|| std::env::var(name).ok())
+    }
+
+    /// Set an environment variable for the program and the shell commands
+    /// it runs.
+    pub fn set_env_var(&mut self, name: String, value: String) {
+        self.env_overrides.insert(name, value);
+    }
+
+    /// Environment variables set by the program, to pass to shell commands.
+    pub fn env_overrides(&self) -> &HashMap<String, String> {
+        &self.env_overrides
+    }
+
+    /// Every environment variable the program sees, sorted by name.
+    /// Variables whose name or value isn't valid UTF-8 are left out.
+    pub fn env_vars(&self) -> BTreeMap<String, String> {
+        let mut vars: BTreeMap<String, String> = std::env::vars_os()
+            .filter_map(|(name, value)| Some((name.into_string().ok()?, value.into_string().ok()?)))
+            .collect();
+        vars.extend(self.env_overrides.clone());
+        vars
+    }
+
+    /// Limit how many think/ask blocks the program may evaluate.
+    pub fn set_think_budget(&mut self, budget: Option<usize>) {
+        self.think_budget = budget;
+    }
+
+    /// Count a think/ask evaluation against the budget.
+    ///
+    /// Returns an error once the budget is exhausted.
+    pub fn charge_think(&mut self) -> Result<(), String> {
+        let budget = self.think_budget;
+        self.thinks_used
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |used| match budget {
+                Some(budget) if used >= budget => None,
+                _ => Some(used + 1),
+            })
+            .map_err(|_| format!("Think budget exhausted ({} allowed)", budget.unwrap_or_default()))?;
+        self.thinks_charged += 1;
+        Ok(())
+    }
+
+    /// How many think/ask blocks this runtime evaluated, not counting those
+    /// of the runtime it was forked from or of its own forks.
+    pub(crate) fn thinks_charged(&self) -> usize {
+        self.thinks_charged
+    }
+
+    /// Add tokens the agent reported using for one of this runtime's think
+    /// blocks.
+    pub(crate) fn charge_tokens(&mut self, tokens: u64) {
+        *self.tokens_charged.get_or_insert(0) += tokens;
+    }
+
+    /// The tokens the agent reported for this runtime's own think blocks,
+    /// or `None` if it reported none.
+    pub(crate) fn tokens_charged(&self) -> Option<u64> {
+        self.tokens_charged
+    }
+
+    /// Start recording shell and think effects into a trace.
+    pub fn start_recording(&mut self) {
+        self.tracer = Tracer::recording();
+    }
+
+    /// Stop recording and return the trace collected so far, including that
+    /// of every task spawned meanwhile.
+    pub fn take_trace(&mut self) -> Vec<TraceEntry> {
+        std::mem::take(&mut self.tracer).entries()
+    }
+
+    /// Replay a recorded trace: shell commands and think blocks take their
+    /// results from `entries` instead of running.
+    pub fn start_replay(&mut self, entries: Vec<TraceEntry>) {
+        self.tracer = Tracer::replaying(entries);
+    }
+
+    pub(crate) fn tracer_mut(&mut self) -> &mut Tracer {
+        &mut self.tracer
+    }
+
+    /// Start recording environment snapshots after each statement.
+    pub fn start_history(&mut self) {
+        self.history = Some(History::default());
+    }
+
+    /// Snapshots recorded so far, if history is on.
+    pub fn history(&self) -> Option<&History> {
+        self.history.as_ref()
+    }
+
+    pub(crate) fn set_source(&mut self, source: Arc<str>) {
+        if let Some(history) = &mut self.history {
+            history.set_source(source.clone());
+        }
+        self.source = source;
+    }
+
+    /// Watch `path` for operator commands between statements, or stop
+    /// watching with `None`. See the `control` module for the commands.
+    pub fn set_control_file(&mut self, path: Option<PathBuf>) {
+        self.control = path.map(ControlFile::new);
+    }
+
+    /// Stop the run before its next statement whenever `flag` is raised.
+    /// The flag stays raised so every task the run spawned stops as well;
+    /// lower it before starting another run.
+    pub fn set_interrupt_flag(&mut self, flag: Option<Arc<AtomicBool>>) {
+        self.interrupt = flag;
+    }
+
+    /// Act on an interrupt or the control file, if any, before running `stmt`.
+    pub(crate) fn check_control(&mut self, stmt: &Spanned<Statement>) -> Result<(), Error> {
+        if self.interrupt.as_ref().is_some_and(|flag| flag.load(Ordering::SeqCst)) {
+            return Err(Error::Runtime("Interrupted".to_string()));
+        }
+        let Some(mut control) = self.control.take() else {
+            return Ok(());
+        };
+        let result = control.check(|| crate::control::describe(&self.source, stmt), |message| self.warn(message));
+        self.control = Some(control);
+        result
+    }
+
+    /// Snapshot the visible bindings after `stmt` ran, if history is on.
+    pub(crate) fn record_snapshot(&mut self, stmt: &Spanned<Statement>) {
+        let Some(history) = &mut self.history else {
+            return;
+        };
+        // Inner scopes come last so they shadow outer ones
+        let mut visible = HashMap::new();
+        for scope in &self.scopes {
+            visible.extend(scope.vars.iter());
+        }
+        history.record(stmt, visible);
+    }
+
+    /// A runtime for a task spawned from this one, to run on its own thread.
+    ///
+    /// The task sees the same globals, declarations, settings, and sinks, and
+    /// counts against the same think budget. Its memo cache starts empty and
+    /// it isn't snapshotted. It records to or replays from its own stream of
+    /// the trace, watches the same control file, and stops on the same
+    /// interrupt.
+    pub(crate) fn fork(&self) -> Runtime {
+        Runtime {
+            scopes: vec![self.scopes[0].clone()],
+            working_dir: self.working_dir.clone(),
+            output_sink: self.output_sink.clone(),
+            log_filter: self.log_filter,
+            statement_span: None,
+            error_span: None,
+            plan_reporter: self.plan_reporter.clone(),
+            thought_reporter: self.thought_reporter.clone(),
+            user_prompter: self.user_prompter.clone(),
+            prompts_sent: 0,
+            event_sink: self.event_sink.clone(),
+            captures: Vec::new(),
+            types: self.types.clone(),
+            program: self.program.clone(),
+            modules: self.modules.clone(),
+            module: self.module,
+            examples: self.examples.clone(),
+            fragments: self.fragments.clone(),
+            type_check_mode: self.type_check_mode,
+            edition: self.edition,
+            shell_policy: self.shell_policy.clone(),
+            shell_executor: self.shell_executor.clone(),
+            think_budget: self.think_budget,
+            thinks_used: self.thinks_used.clone(),
+            thinks_charged: 0,
+            tokens_charged: None,
+            variant_policy: self.variant_policy.clone(),
+            config_overrides: self.config_overrides.clone(),
+            env_overrides: self.env_overrides.clone(),
+            memo: HashMap::new(),
+            tracer: self.tracer.fork(),
+            history: None,
+            depth: 0,
+            recursion_limit: self.recursion_limit,
+            validation_retries: self.validation_retries,
+            deadlines: Vec::new(),
+            default_timeout: self.default_timeout,
+            heartbeat_interval: self.heartbeat_interval,
+            source: self.source.clone(),
+            control: self.control.as_ref().map(ControlFile::fork),
+            interrupt: self.interrupt.clone(),
+            mailbox: Arc::new(Mailbox::new()),
+            parent_mailbox: Some(self.mailbox.clone()),
+        }
+    }
+
+    /// The mailbox `self.receive` takes messages from.
+    pub(crate) fn mailbox(&self) -> &Arc<Mailbox> {
+        &self.mailbox
+    }
+
+    /// The mailbox `self.send` leaves messages in, if this runtime runs a
+    /// spawned task.
+    pub(crate) fn parent_mailbox(&self) -> Option<&Arc<Mailbox>> {
+        self.parent_mailbox.as_ref()
+    }
+
+    /// Enter a function call.
+    ///
+    /// Hides the caller's local scopes so the callee sees only globals, and
+    /// pushes a fresh scope for its parameters. Returns the hidden scopes, which
+    /// must be handed back to `exit_function`.
+    pub fn enter_function(&mut self) -> Vec<Scope> {
+        let locals = self.scopes.split_off(1);
+        self.scopes.push(Scope::default());
+        locals
+    }
+
+    /// Leave a function call, restoring the caller's scopes.
+    pub fn exit_function(&mut self, locals: Vec<Scope>) {
+        self.scopes.truncate(1);
+        self.scopes.extend(locals);
+    }
+
+    /// Set every scope aside, leaving a single empty one, for code that
+    /// must see none of the program's variables.
+    pub(crate) fn isolate(&mut self) -> Vec<Scope> {
+        std::mem::replace(&mut self.scopes, vec![Scope::default()])
+    }
+
+    /// Bring back the scopes set aside by `isolate`.
+    pub(crate) fn restore(&mut self, scopes: Vec<Scope>) {
+        self.scopes = scopes;
+    }
+
     /// Push a new scope onto the scope stack (entering a block).
     pub fn push_scope(&mut self) {
-        self.scopes.push(HashMap::new());
+        self.scopes.push(Scope::default());
     }
 
     /// Pop the current scope from the stack (leaving a block).
@@ -163,47 +1191,124 @@ impl Runtime {
         let current_scope = self.scopes.last_mut()
             .expect("scope stack should never be empty");
 
-        if current_scope.contains_key(name) {
+        if current_scope.vars.contains_key(name) {
             return Err(format!("Variable '{}' already defined in this scope", name));
         }
 
-        current_scope.insert(name.to_string(), value);
+        current_scope.vars.insert(name.to_string(), value);
+        Ok(())
+    }
+
+    /// Define a constant in the current scope.
+    ///
+    /// Like `define_var`, but later assignments to it fail.
+    pub fn define_const(&mut self, name: &str, value: Value) -> Result<(), String> {
+        self.define_var(name, value)?;
+        let current_scope = self.scopes.last_mut()
+            .expect("scope stack should never be empty");
+        current_scope.constants.insert(name.to_string());
         Ok(())
     }
 
     /// Get the value of a variable, searching from innermost to outermost scope.
     pub fn get_var(&self, name: &str) -> Option<&Value> {
         for scope in self.scopes.iter().rev() {
-            if let Some(value) = scope.get(name) {
+            if let Some(value) = scope.vars.get(name) {
                 return Some(value);
             }
         }
         None
     }
 
+    /// Copies of the variables a closure created here captures: everything in
+    /// scope but globals, with inner bindings shadowing outer ones.
+    pub fn capture_locals(&self) -> HashMap<String, Value> {
+        let mut captured = HashMap::new();
+        for scope in &self.scopes[1..] {
+            captured.extend(scope.vars.iter().map(|(name, value)| (name.clone(), value.clone())));
+        }
+        captured
+    }
+
     /// Set the value of an existing variable.
     ///
     /// Searches from innermost to outermost scope for the variable.
-    /// Returns an error if the variable doesn't exist.
+    /// Returns an error if the variable doesn't exist or is a constant.
     pub fn set_var(&mut self, name: &str, value: Value) -> Result<(), String> {
         for scope in self.scopes.iter_mut().rev() {
-            if scope.contains_key(name) {
-                scope.insert(name.to_string(), value);
+            if scope.constants.contains(name) {
+                return Err(format!("Cannot assign to constant '{}'", name));
+            }
+            if scope.vars.contains_key(name) {
+                scope.vars.insert(name.to_string(), value);
                 return Ok(());
             }
         }
         Err(format!("Variable '{}' not defined", name))
     }
+
+    /// The value of an existing variable, to change where it's stored.
+    ///
+    /// Fails like `set_var` if the variable doesn't exist or is a constant.
+    pub fn var_mut(&mut self, name: &str) -> Result<&mut Value, String> {
+        for scope in self.scopes.iter_mut().rev() {
+            if scope.constants.contains(name) {
+                return Err(format!("Cannot assign to constant '{}'", name));
+            }
+            if let Some(value) = scope.vars.get_mut(name) {
+                return Ok(value);
+            }
+        }
+        Err(format!("Variable '{}' not defined", name))
+    }
 }
 
 impl Default for Runtime {
     fn default() -> Self {
         Self {
-            scopes: vec![HashMap::new()],
+            scopes: vec![Scope::default()],
             working_dir: std::env::current_dir().unwrap_or_else(|_| PathBuf::from("/")),
-            print_sink: None,
+            output_sink: None,
+            log_filter: LogLevel::Debug,
+            statement_span: None,
+            error_span: None,
             plan_reporter: None,
             thought_reporter: None,
+            user_prompter: None,
+            prompts_sent: 0,
+            event_sink: None,
+            captures: Vec::new(),
+            types: HashMap::new(),
+            program: Namespace::default(),
+            modules: Vec::new(),
+            module: None,
+            examples: HashMap::new(),
+            fragments: HashMap::new(),
+            type_check_mode: TypeCheckMode::default(),
+            edition: Edition::default(),
+            shell_policy: ShellPolicy::default(),
+            shell_executor: Arc::new(ProcessExecutor),
+            think_budget: None,
+            thinks_used: Arc::new(AtomicUsize::new(0)),
+            thinks_charged: 0,
+            tokens_charged: None,
+            variant_policy: VariantPolicy::Default,
+            config_overrides: HashMap::new(),
+            env_overrides: HashMap::new(),
+            memo: HashMap::new(),
+            tracer: Tracer::Off,
+            history: None,
+            depth: 0,
+            recursion_limit: DEFAULT_RECURSION_LIMIT,
+            validation_retries: DEFAULT_VALIDATION_RETRIES,
+            deadlines: Vec::new(),
+            default_timeout: None,
+            heartbeat_interval: None,
+            source: Arc::from(""),
+            control: None,
+            interrupt: None,
+            mailbox: Arc::new(Mailbox::new()),
+            parent_mailbox: None,
         }
     }
 }
@@ -240,6 +1345,20 @@ mod tests {
         assert!(result.is_err());
     }
 
+    #[test]
+    fn test_set_const_fails() {
+        let mut rt = Runtime::default();
+        rt.define_const("x", Value::Number(1.0)).unwrap();
+        assert_eq!(rt.set_var("x", Value::Number(2.0)), Err("Cannot assign to constant 'x'".to_string()));
+
+        // A shadowing variable is assignable, and the constant survives it
+        rt.push_scope();
+        rt.define_var("x", Value::Number(3.0)).unwrap();
+        rt.set_var("x", Value::Number(4.0)).unwrap();
+        rt.pop_scope();
+        assert_eq!(rt.get_var("x"), Some(&Value::Number(1.0)));
+    }
+
     #[test]
     fn test_scope_shadowing() {
         let mut rt = Runtime::default();
@@ -261,4 +1380,16 @@ mod tests {
         rt.push_scope();
         assert_eq!(rt.get_var("x"), Some(&Value::Number(1.0)));
     }
+
+    #[test]
+    fn test_nested_capture() {
+        let mut rt = Runtime::default();
+        rt.begin_capture();
+        rt.print("outer".to_string()).unwrap();
+        rt.begin_capture();
+        rt.print("inner".to_string()).unwrap();
+        assert_eq!(rt.end_capture(), "inner\n");
+        rt.print("outer again".to_string()).unwrap();
+        assert_eq!(rt.end_capture(), "outer\nouter again\n");
+    }
 }
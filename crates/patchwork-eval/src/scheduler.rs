@@ -0,0 +1,218 @@
+//! Running functions concurrently, for the `spawn` builtin and `await`.
+//!
+//! Each task gets its own thread and a runtime forked from the one that
+//! spawned it. Values cross between threads by copy: the task gets its
+//! arguments when it starts, and whoever awaits it gets its result, so a
+//! task can't change the spawner's variables. `task.await` blocks until the
+//! task finishes, then returns its value or rethrows what it threw, and
+//! awaiting it again gives the same outcome.
+//!
+//! Tasks also pass messages. The program and every task have a [`Mailbox`]:
+//! `send(task, value)` puts a value in a task's, `self.send(value)` in the
+//! spawner's, and `self.receive(timeout)` takes the next one from the
+//! caller's own.
+
+use std::fmt;
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::error::Error;
+use crate::value::Value;
+
+/// A function running on its own thread, as returned by `spawn`.
+pub struct Task {
+    /// Name of the function the task runs
+    name: String,
+    state: Mutex<TaskState>,
+    /// Signalled when a waiter finishes with the receiver
+    changed: Condvar,
+    /// Messages sent to the task, which it takes with `self.receive`
+    mailbox: Arc<Mailbox>,
+}
+
+enum TaskState {
+    Running(Receiver<Result<Value, Error>>),
+    /// Someone took the receiver to wait on it
+    Waiting,
+    Finished(Result<Value, Error>),
+}
+
+impl Task {
+    /// Name of the function the task runs.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// The mailbox the task receives from.
+    pub(crate) fn mailbox(&self) -> &Arc<Mailbox> {
+        &self.mailbox
+    }
+
+    /// Wait up to `timeout` for the task to finish, or as long as it takes
+    /// with `None`. Returns None if it's still running.
+    pub(crate) fn wait(&self, timeout: Option<Duration>) -> Option<Result<Value, Error>> {
+        let deadline = timeout.map(|timeout| Instant::now() + timeout);
+        let mut state = self.lock();
+        loop {
+            match std::mem::replace(&mut *state, TaskState::Waiting) {
+                TaskState::Finished(result) => {
+                    *state = TaskState::Finished(result.clone());
+                    return Some(result);
+                }
+                TaskState::Running(rx) => {
+                    // Block without the lock, so other callers can still poll
+                    drop(state);
+                    let received = match deadline {
+                        Some(deadline) => rx.recv_timeout(deadline.saturating_duration_since(Instant::now())),
+                        None => rx.recv().map_err(|_| RecvTimeoutError::Disconnected),
+                    };
+                    state = self.lock();
+                    let result = match received {
+                        Ok(result) => result,
+                        Err(RecvTimeoutError::Disconnected) => Err(self.panicked()),
+                        Err(RecvTimeoutError::Timeout) => {
+                            // Hand the receiver back for the next waiter
+                            *state = TaskState::Running(rx);
+                            self.changed.notify_all();
+                            return None;
+                        }
+                    };
+                    *state = TaskState::Finished(result.clone());
+                    self.changed.notify_all();
+                    return Some(result);
+                }
+                TaskState::Waiting => {
+                    // Someone else has the receiver; wait for them to finish with it
+                    let left = deadline.map(|deadline| deadline.saturating_duration_since(Instant::now()));
+                    state = match left {
+                        Some(left) if left.is_zero() => return None,
+                        Some(left) => self.changed.wait_timeout(state, left).unwrap_or_else(|e| e.into_inner()).0,
+                        None => self.changed.wait(state).unwrap_or_else(|e| e.into_inner()),
+                    };
+                }
+            }
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, TaskState> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn panicked(&self) -> Error {
+        Error::Runtime(format!("Task {} panicked", self.name))
+    }
+}
+
+impl fmt::Debug for Task {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Task").field("name", &self.name).finish_non_exhaustive()
+    }
+}
+
+/// Tasks are equal only to themselves.
+impl PartialEq for Task {
+    fn eq(&self, other: &Self) -> bool {
+        std::ptr::eq(self, other)
+    }
+}
+
+/// Messages waiting for a task or the program, oldest first.
+#[derive(Debug)]
+pub(crate) struct Mailbox {
+    tx: Sender<Value>,
+    rx: Mutex<Receiver<Value>>,
+}
+
+impl Mailbox {
+    pub(crate) fn new() -> Self {
+        let (tx, rx) = mpsc::channel();
+        Mailbox { tx, rx: Mutex::new(rx) }
+    }
+
+    /// Leave a message. This never blocks.
+    pub(crate) fn send(&self, message: Value) {
+        // The mailbox holds its own receiver, so the channel can't be closed
+        let _ = self.tx.send(message);
+    }
+
+    /// Take the oldest message, waiting up to `timeout` for one to arrive,
+    /// or as long as it takes with `None`. Returns None if none arrived.
+    pub(crate) fn receive(&self, timeout: Option<Duration>) -> Option<Value> {
+        let rx = self.rx.lock().unwrap_or_else(|e| e.into_inner());
+        match timeout {
+            Some(timeout) => rx.recv_timeout(timeout).ok(),
+            None => rx.recv().ok(),
+        }
+    }
+}
+
+/// Start `run` on a new thread and return its task at once. Messages for
+/// the task go to `mailbox`.
+pub(crate) fn spawn<F>(name: &str, mailbox: Arc<Mailbox>, run: F) -> Result<Arc<Task>, Error>
+where
+    F: FnOnce() -> Result<Value, Error> + Send + 'static,
+{
+    let (tx, rx) = mpsc::channel();
+    thread::Builder::new()
+        .name(format!("patchwork task {}", name))
+        .spawn(move || {
+            // The task may be dropped without being awaited
+            let _ = tx.send(run());
+        })
+        .map_err(|e| Error::Runtime(format!("Failed to start task {}: {}", name, e)))?;
+    Ok(Arc::new(Task { name: name.to_string(), state: Mutex::new(TaskState::Running(rx)), changed: Condvar::new(), mailbox }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_wait_caches_the_outcome() {
+        let task = spawn("slow", Arc::new(Mailbox::new()), || {
+            thread::sleep(Duration::from_millis(50));
+            Ok(Value::Number(1.0))
+        })
+        .unwrap();
+        assert!(task.wait(Some(Duration::ZERO)).is_none());
+        assert_eq!(task.wait(None).unwrap().unwrap(), Value::Number(1.0));
+        assert_eq!(task.wait(Some(Duration::ZERO)).unwrap().unwrap(), Value::Number(1.0));
+
+        let task = spawn("broken", Arc::new(Mailbox::new()), || panic!("boom")).unwrap();
+        assert_eq!(task.wait(None).unwrap().unwrap_err().to_string(), "Runtime error: Task broken panicked");
+    }
+
+    #[test]
+    fn test_waiting_doesnt_block_polling() {
+        let task = spawn("slow", Arc::new(Mailbox::new()), || {
+            thread::sleep(Duration::from_millis(300));
+            Ok(Value::Number(1.0))
+        })
+        .unwrap();
+        let waiter = {
+            let task = task.clone();
+            thread::spawn(move || task.wait(None))
+        };
+        thread::sleep(Duration::from_millis(50));
+        // The waiter holds the receiver, but a poll still returns at once
+        let started = Instant::now();
+        assert!(task.wait(Some(Duration::ZERO)).is_none());
+        assert!(task.wait(Some(Duration::from_millis(20))).is_none());
+        assert!(started.elapsed() < Duration::from_millis(200));
+        // A second waiter gets the outcome the first one received
+        assert_eq!(task.wait(None).unwrap().unwrap(), Value::Number(1.0));
+        assert_eq!(waiter.join().unwrap().unwrap().unwrap(), Value::Number(1.0));
+    }
+
+    #[test]
+    fn test_mailbox_delivers_in_order() {
+        let mailbox = Mailbox::new();
+        assert_eq!(mailbox.receive(Some(Duration::ZERO)), None);
+        mailbox.send(Value::Number(1.0));
+        mailbox.send(Value::Number(2.0));
+        assert_eq!(mailbox.receive(None), Some(Value::Number(1.0)));
+        assert_eq!(mailbox.receive(Some(Duration::ZERO)), Some(Value::Number(2.0)));
+    }
+}
@@ -0,0 +1,111 @@
+//! Running the shell commands a program asks for.
+//!
+//! The interpreter hands every command that passes the shell policy to the
+//! runtime's [`ShellExecutor`]. [`ProcessExecutor`], the default, runs it
+//! as a local process. Embedders can supply their own to run commands in a
+//! container, answer them from fixtures in tests, or refuse some binaries
+//! outright. Trace recording and replay happen before the executor is
+//! asked, so an executor only sees commands that actually need to run.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::io::{self, Read};
+use std::path::Path;
+use std::process::{Command, Output, Stdio};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use crate::error::Error;
+use crate::value::ShellResult;
+
+/// A command for a [`ShellExecutor`] to run.
+#[derive(Debug, Clone, Copy)]
+pub struct ShellCommand<'a> {
+    pub program: &'a str,
+    pub args: &'a [String],
+    /// The runtime's working directory
+    pub working_dir: &'a Path,
+    /// Variables the program set with `env.set`, to add to the command's
+    /// environment
+    pub env: &'a HashMap<String, String>,
+}
+
+/// Called every few milliseconds while a command runs. When it fails, the
+/// command should be stopped and the error returned; that's how `@timeout`
+/// limits reach a running command. It also sends the runtime's heartbeats.
+pub type Tick<'a> = &'a mut dyn FnMut() -> Result<(), Error>;
+
+/// Runs shell commands for the interpreter.
+pub trait ShellExecutor: Send + Sync {
+    /// Run `command` to completion and return what it printed and how it
+    /// exited. A command that exits with a nonzero status is still `Ok`;
+    /// errors are for commands that couldn't run at all. `tick` is `None`
+    /// when nothing needs checking while the command runs.
+    fn execute(&self, command: &ShellCommand, tick: Option<Tick>) -> Result<ShellResult, Error>;
+}
+
+impl fmt::Debug for dyn ShellExecutor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("ShellExecutor")
+    }
+}
+
+/// Runs commands as child processes of the interpreter, with stdin closed.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ProcessExecutor;
+
+impl ShellExecutor for ProcessExecutor {
+    fn execute(&self, command: &ShellCommand, tick: Option<Tick>) -> Result<ShellResult, Error> {
+        let mut process = Command::new(command.program);
+        process.args(command.args).current_dir(command.working_dir).envs(command.env);
+        let output = match tick {
+            Some(tick) => output_while(&mut process, command.program, tick)?,
+            None => process
+                .output()
+                .map_err(|e| Error::Runtime(format!("Failed to execute {}: {}", command.program, e)))?,
+        };
+        Ok(ShellResult {
+            stdout: String::from_utf8_lossy(&output.stdout).into_owned(),
+            stderr: String::from_utf8_lossy(&output.stderr).into_owned(),
+            status: output.status.code(),
+        })
+    }
+}
+
+/// Run `command` like `Command::output`, calling `tick` every few
+/// milliseconds while it runs. If `tick` fails, the command is killed and
+/// the failure returned.
+fn output_while(command: &mut Command, name: &str, tick: Tick) -> Result<Output, Error> {
+    let failed = |e: io::Error| Error::Runtime(format!("Failed to execute {}: {}", name, e));
+    let mut child = command
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(failed)?;
+    // Drain the pipes on their own threads so a full pipe can't stall the child
+    let stdout = child.stdout.take().map(read_to_end);
+    let stderr = child.stderr.take().map(read_to_end);
+    loop {
+        if let Some(status) = child.try_wait().map_err(failed)? {
+            let collect = |reader: Option<JoinHandle<Vec<u8>>>| {
+                reader.and_then(|reader| reader.join().ok()).unwrap_or_default()
+            };
+            return Ok(Output { status, stdout: collect(stdout), stderr: collect(stderr) });
+        }
+        if let Err(e) = tick() {
+            let _ = child.kill();
+            let _ = child.wait();
+            return Err(e);
+        }
+        thread::sleep(Duration::from_millis(10));
+    }
+}
+
+fn read_to_end(mut pipe: impl Read + Send + 'static) -> JoinHandle<Vec<u8>> {
+    thread::spawn(move || {
+        let mut bytes = Vec::new();
+        let _ = pipe.read_to_end(&mut bytes);
+        bytes
+    })
+}
@@ -0,0 +1,157 @@
+//! The `std` namespace: `std.now()`, `std.random()`, `std.uuid()`, number
+//! helpers, and `std.format(...)`.
+//!
+//! These need nothing from the runtime; `std.sleep` waits under the
+//! runtime's timeouts, so it lives with the other blocking waits in
+//! `eval.rs`. Randomness comes from the standard library's per-map hash
+//! keys, which are seeded from the OS and differ on every call.
+
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::eval::type_name;
+use crate::value::Value;
+
+/// The namespace, as written in `import std` and `std.now()`.
+pub(crate) const NAMESPACE: &str = "std";
+
+/// Call the `std` builtin `name`, such as `"std.now"`.
+pub(crate) fn call(name: &str, args: &[Value]) -> Result<Value, String> {
+    let value = match (name, args) {
+        ("std.now", []) => Value::Number(now()),
+        ("std.random", []) => Value::Number(random()),
+        ("std.uuid", []) => Value::String(uuid()),
+        ("std.now" | "std.random" | "std.uuid", _) => return Err(format!("{}() takes no arguments", name)),
+        ("std.min" | "std.max", _) => {
+            let numbers = numbers(name, args)?;
+            let pick = if name == "std.min" { f64::min } else { f64::max };
+            let picked = numbers.into_iter().reduce(pick);
+            Value::Number(picked.ok_or_else(|| format!("{}() takes one or more numbers", name))?)
+        }
+        ("std.abs" | "std.floor" | "std.ceil", [Value::Number(n)]) => Value::Number(match name {
+            "std.abs" => n.abs(),
+            "std.floor" => n.floor(),
+            _ => n.ceil(),
+        }),
+        ("std.abs" | "std.floor" | "std.ceil", _) => return Err(format!("{}() takes a number", name)),
+        ("std.format", [Value::String(template), args @ ..]) => Value::String(format(template, args)?),
+        ("std.format", _) => return Err("std.format() takes a template string and the values to fill it".to_string()),
+        _ => return Err(format!("Unknown function: {}", name)),
+    };
+    Ok(value)
+}
+
+/// Milliseconds since the Unix epoch.
+fn now() -> f64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map_or(0.0, |since| since.as_secs_f64() * 1000.0).floor()
+}
+
+/// 64 random bits.
+fn random_bits() -> u64 {
+    RandomState::new().build_hasher().finish()
+}
+
+/// A number from 0 up to but not including 1.
+fn random() -> f64 {
+    // The top 53 bits fill an f64's mantissa exactly
+    (random_bits() >> 11) as f64 / (1u64 << 53) as f64
+}
+
+/// A random (version 4) UUID, in its hyphenated lowercase form.
+fn uuid() -> String {
+    let mut bytes = [0u8; 16];
+    bytes[..8].copy_from_slice(&random_bits().to_be_bytes());
+    bytes[8..].copy_from_slice(&random_bits().to_be_bytes());
+    bytes[6] = (bytes[6] & 0x0f) | 0x40;
+    bytes[8] = (bytes[8] & 0x3f) | 0x80;
+    let hex: String = bytes.iter().map(|byte| format!("{:02x}", byte)).collect();
+    format!("{}-{}-{}-{}-{}", &hex[..8], &hex[8..12], &hex[12..16], &hex[16..20], &hex[20..])
+}
+
+/// The arguments of `std.min` or `std.max`: numbers, or one array of them.
+fn numbers(name: &str, args: &[Value]) -> Result<Vec<f64>, String> {
+    let args = match args {
+        [Value::Array(items)] => items.as_slice(),
+        _ => args,
+    };
+    args.iter()
+        .map(|arg| match arg {
+            Value::Number(n) => Ok(*n),
+            other => Err(format!("{}() takes numbers, got {}", name, type_name(other))),
+        })
+        .collect()
+}
+
+/// Fill each `{}` in `template` with the next argument's string form.
+/// `{{` and `}}` write literal braces.
+fn format(template: &str, args: &[Value]) -> Result<String, String> {
+    let given = args.len();
+    let mut out = String::new();
+    let mut args = args.iter();
+    let mut placeholders = 0;
+    let mut chars = template.chars().peekable();
+    while let Some(c) = chars.next() {
+        match (c, chars.peek()) {
+            ('{', Some('{')) | ('}', Some('}')) => {
+                chars.next();
+                out.push(c);
+            }
+            ('{', Some('}')) => {
+                chars.next();
+                placeholders += 1;
+                if let Some(arg) = args.next() {
+                    out.push_str(&arg.to_string_value());
+                }
+            }
+            _ => out.push(c),
+        }
+    }
+    if placeholders != given {
+        return Err(format!(
+            "std.format() template has {} placeholder{} but was given {} value{}",
+            placeholders,
+            if placeholders == 1 { "" } else { "s" },
+            given,
+            if given == 1 { "" } else { "s" },
+        ));
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_and_numbers() {
+        let args = [Value::String("disk".to_string()), Value::Number(3.0)];
+        assert_eq!(format("{} is {}% {{full}}", &args).unwrap(), "disk is 3% {full}");
+        assert_eq!(
+            format("{} and {}", &args[..1]).unwrap_err(),
+            "std.format() template has 2 placeholders but was given 1 value"
+        );
+        assert!(format("{}", &args).is_err());
+
+        let ok = |name: &str, args: &[Value]| call(name, args).unwrap();
+        assert_eq!(ok("std.min", &[Value::Number(2.0), Value::Number(-1.5)]), Value::Number(-1.5));
+        let array = Value::Array(vec![Value::Number(2.0), Value::Number(7.0)]);
+        assert_eq!(ok("std.max", &[array]), Value::Number(7.0));
+        assert_eq!(ok("std.floor", &[Value::Number(-1.5)]), Value::Number(-2.0));
+        assert_eq!(ok("std.ceil", &[Value::Number(1.2)]), Value::Number(2.0));
+        assert_eq!(ok("std.abs", &[Value::Number(-3.0)]), Value::Number(3.0));
+        assert!(call("std.max", &[]).is_err());
+        assert!(call("std.abs", &[Value::String("1".to_string())]).is_err());
+    }
+
+    #[test]
+    fn test_random_values() {
+        let n = random();
+        assert!((0.0..1.0).contains(&n));
+        let (a, b) = (uuid(), uuid());
+        assert_ne!(a, b);
+        assert_eq!(a.len(), 36);
+        assert_eq!(&a[14..15], "4");
+        assert!(matches!(&a[19..20], "8" | "9" | "a" | "b"));
+    }
+}
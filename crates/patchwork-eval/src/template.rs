@@ -0,0 +1,205 @@
+//! Text templates, for the `template.render` builtin.
+//!
+//! Templates interpolate like string literals, with `$name` and `${path}`,
+//! but they see only the bindings passed in, not the variables of the
+//! program rendering them. A `${...}` names a value in the bindings, like
+//! `${pr.files[0]}`: identifiers, fields and indexes by literal or by
+//! another path. Calls, operators, command substitution (`$(...)`) and
+//! everything else are refused, so rendering a template never runs
+//! anything. `\$` writes a literal `$`; other text, backslashes included,
+//! is copied as is.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, OnceLock};
+
+use patchwork_parser::ast::{Expr, Item, Program, Spanned, Statement, StringPart};
+use patchwork_parser::{Edition, IntoStatic, ParseError};
+
+use crate::error::Error;
+use crate::eval::{eval_expr, type_name};
+use crate::runtime::Runtime;
+use crate::value::Value;
+
+/// Render `template` with `bindings`, an object of variables.
+pub(crate) fn render(template: &str, bindings: &Value, runtime: &mut Runtime) -> Result<String, Error> {
+    let Value::Object(bindings) = bindings else {
+        return Err(Error::Runtime(format!(
+            "template.render() bindings must be an object, got {}",
+            type_name(bindings)
+        )));
+    };
+
+    let saved = runtime.isolate();
+    let mut bound = Ok(());
+    for (name, value) in bindings {
+        bound = runtime.define_var(name, value.clone()).map_err(Error::Runtime);
+        if bound.is_err() {
+            break;
+        }
+    }
+    let result = bound.and_then(|_| expand(template, runtime));
+    runtime.restore(saved);
+    result
+}
+
+fn expand(template: &str, runtime: &mut Runtime) -> Result<String, Error> {
+    let mut out = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(at) = rest.find(['$', '\\']) {
+        out.push_str(&rest[..at]);
+        let tail = &rest[at..];
+        let offset = template.len() - tail.len();
+        if let Some(after) = tail.strip_prefix("\\$") {
+            out.push('$');
+            rest = after;
+        } else if let Some(inner) = tail.strip_prefix("${") {
+            let end = closing_brace(inner)
+                .ok_or_else(|| Error::Runtime(format!("Unclosed ${{ in template at offset {}", offset)))?;
+            out.push_str(&eval_source(&inner[..end], runtime)?.to_string_value());
+            rest = &inner[end + 1..];
+        } else if tail.starts_with("$(") {
+            return Err(Error::Runtime(format!(
+                "Templates can't run commands ($( at offset {}); pass the output in the bindings",
+                offset
+            )));
+        } else if let Some(name) = identifier(&tail[1..]).filter(|_| tail.starts_with('$')) {
+            let value = runtime
+                .get_var(name)
+                .ok_or_else(|| Error::Runtime(format!("Template variable not bound: {}", name)))?;
+            out.push_str(&value.to_string_value());
+            rest = &tail[1 + name.len()..];
+        } else {
+            // A lone `$` or a backslash that doesn't escape one
+            out.push_str(&tail[..1]);
+            rest = &tail[1..];
+        }
+    }
+    out.push_str(rest);
+    Ok(out)
+}
+
+/// Byte offset of the `}` closing an interpolation, skipping nested braces
+/// and braces inside string literals.
+fn closing_brace(text: &str) -> Option<usize> {
+    let mut depth = 0;
+    let mut quote = None;
+    let mut escaped = false;
+    for (i, c) in text.char_indices() {
+        match (quote, c) {
+            (Some(_), _) if escaped => escaped = false,
+            (Some(_), '\\') => escaped = true,
+            (Some(q), c) if c == q => quote = None,
+            (Some(_), _) => {}
+            (None, '"' | '\'') => quote = Some(c),
+            (None, '{') => depth += 1,
+            (None, '}') if depth == 0 => return Some(i),
+            (None, '}') => depth -= 1,
+            _ => {}
+        }
+    }
+    None
+}
+
+/// The identifier at the start of `text`, as in `$name`.
+fn identifier(text: &str) -> Option<&str> {
+    let end = text.find(|c: char| !(c.is_ascii_alphanumeric() || c == '_')).unwrap_or(text.len());
+    let name = &text[..end];
+    name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_').then_some(name)
+}
+
+/// Parse and evaluate one interpolated expression.
+fn eval_source(source: &str, runtime: &mut Runtime) -> Result<Value, Error> {
+    let program = parsed(source, runtime.edition())
+        .map_err(|e| Error::Runtime(format!("Invalid template expression ${{{}}}: {}", source, e)))?;
+    let expr = program.items.iter().find_map(|item| match &item.node {
+        Item::Skill(skill) => match skill.body.statements.first().map(|stmt| &stmt.node) {
+            Some(Statement::Return(Some(expr))) => Some(expr),
+            _ => None,
+        },
+        _ => None,
+    });
+    let expr = expr.ok_or_else(|| Error::Runtime(format!("Invalid template expression ${{{}}}", source)))?;
+    if !is_path(expr) {
+        return Err(Error::Runtime(format!(
+            "Template expressions can only name bindings, like ${{a.b[0]}}; got ${{{}}}",
+            source
+        )));
+    }
+    eval_expr(expr, runtime, None)
+}
+
+/// Whether `expr` only reads a value: a name, with fields and indexes that
+/// are literals or paths themselves.
+fn is_path(expr: &Spanned<Expr>) -> bool {
+    match &expr.node {
+        Expr::Identifier(_) => true,
+        Expr::Member { object, .. } => is_path(object),
+        Expr::Index { object, index } => is_path(object) && (is_literal(index) || is_path(index)),
+        Expr::Paren(inner) => is_path(inner),
+        _ => false,
+    }
+}
+
+/// Whether `expr` is a number or a string without interpolations.
+fn is_literal(expr: &Spanned<Expr>) -> bool {
+    match &expr.node {
+        Expr::Number(_) => true,
+        Expr::String(literal) => literal.parts.iter().all(|part| matches!(part, StringPart::Text(_))),
+        _ => false,
+    }
+}
+
+/// Template programs by expression text and edition.
+type Parsed = HashMap<(String, Edition), Arc<Program<'static>>>;
+
+/// The program wrapping an interpolated expression, parsed once per text.
+///
+/// Evaluation needs an AST that lives as long as the closures it may create,
+/// so the program owns its text instead of borrowing the wrapper.
+fn parsed(source: &str, edition: Edition) -> Result<Arc<Program<'static>>, ParseError> {
+    static PARSED: OnceLock<Mutex<Parsed>> = OnceLock::new();
+    let mut parsed = PARSED.get_or_init(Default::default).lock().unwrap_or_else(|e| e.into_inner());
+    if let Some(program) = parsed.get(&(source.to_string(), edition)) {
+        return Ok(program.clone());
+    }
+    let program_text = format!("skill __template__() {{\nreturn ({})\n}}", source);
+    let program = Arc::new(patchwork_parser::parse_with_edition(&program_text, edition)?.into_static());
+    parsed.insert((source.to_string(), edition), program.clone());
+    Ok(program)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_against_bindings_only() {
+        let mut runtime = Runtime::new(std::env::temp_dir());
+        runtime.define_var("secret", Value::String("hidden".to_string())).unwrap();
+        let bindings = Value::from_json(r#"{"title": "Fix lexer", "files": ["a.rs", "b.rs"], "n": 2}"#).unwrap();
+
+        let text = render("$title: ${n} files (${files[0]}, ...) cost \\$5 {ok}", &bindings, &mut runtime).unwrap();
+        assert_eq!(text, "Fix lexer: 2 files (a.rs, ...) cost $5 {ok}");
+        // The program's own variables aren't visible, and stay intact
+        let err = render("${secret}", &bindings, &mut runtime).unwrap_err();
+        assert_eq!(err.to_string(), "Runtime error: Undefined variable: secret");
+        assert_eq!(runtime.get_var("secret"), Some(&Value::String("hidden".to_string())));
+
+        assert!(render("$(rm -rf /)", &bindings, &mut runtime).unwrap_err().to_string().contains("can't run commands"));
+        assert!(render("${n", &bindings, &mut runtime).unwrap_err().to_string().contains("Unclosed"));
+    }
+
+    #[test]
+    fn test_render_only_reads_paths() {
+        let mut runtime = Runtime::new(std::env::temp_dir());
+        let bindings = Value::from_json(r#"{"pr": {"files": ["a.rs"], "n": 0}, "key": "files"}"#).unwrap();
+        let text = render("${pr.files[0]} ${pr[\"files\"][pr.n]} ${pr[key][0]}", &bindings, &mut runtime).unwrap();
+        assert_eq!(text, "a.rs a.rs a.rs");
+
+        let refused = ["${$(rm -rf /)}", "${f()}", "${pr.files.push(1)}", "${\"$(id)\"}", "${pr.files[\"${$(id)}\"]}", "${pr.n + 1}"];
+        for refused in refused {
+            let err = render(refused, &bindings, &mut runtime).unwrap_err().to_string();
+            assert!(err.contains("can only name bindings"), "{}: {}", refused, err);
+        }
+    }
+}
@@ -0,0 +1,330 @@
+//! Recording and replaying a program's effects.
+//!
+//! A trace lists, in order, the result of every shell command and think block
+//! a run performed. Replaying a trace feeds those results back instead of
+//! running the commands or asking the agent, so a past run can be re-executed
+//! deterministically for postmortem debugging.
+//!
+//! Traces are stored as JSON lines, one entry per line:
+//!
+//! ```text
+//! {"kind":"shell","command":"git","args":["status"],"ok":{"stdout":"...","stderr":"","status":0}}
+//! {"kind":"think","prompt":"...","ok":{"files":[]},"nested":0}
+//! ```
+//!
+//! Values round-trip through JSON, so durations and sizes replay as numbers.
+//! A shell entry records what the command printed and its exit status, or
+//! an error if it couldn't run; traces that recorded just the output of a
+//! command that succeeded still replay.
+//!
+//! Spawned tasks run concurrently, so each records into a stream of its own.
+//! The program's entries come first, then each task's after a line naming
+//! it. A task is numbered by the order its parent spawned it in, with its
+//! parent's number before a dot, so a replay gives every task the same id:
+//!
+//! ```text
+//! {"kind":"task","id":"1.2"}
+//! ```
+
+use std::collections::{BTreeMap, VecDeque};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+
+use serde_json::{json, Value as JsonValue};
+
+use crate::error::Error;
+use crate::value::Value;
+
+/// One recorded effect.
+#[derive(Debug, Clone, PartialEq)]
+pub enum TraceEntry {
+    /// A shell command and its output (or failure message).
+    Shell {
+        command: String,
+        args: Vec<String>,
+        result: Result<Value, String>,
+    },
+    /// A think or ask block and the agent's answer.
+    Think {
+        prompt: String,
+        result: Result<Value, String>,
+        /// Number of entries recorded while the agent ran the block's
+        /// do-blocks; they directly follow this entry.
+        nested: usize,
+    },
+    /// The start of a spawned task's entries.
+    Task { id: String },
+}
+
+impl TraceEntry {
+    /// Encode this entry as a single line of JSON.
+    pub fn to_json_line(&self) -> String {
+        let (mut object, result) = match self {
+            TraceEntry::Shell { command, args, result } => {
+                (json!({ "kind": "shell", "command": command, "args": args }), result)
+            }
+            TraceEntry::Think { prompt, result, nested } => {
+                (json!({ "kind": "think", "prompt": prompt, "nested": nested }), result)
+            }
+            TraceEntry::Task { id } => return json!({ "kind": "task", "id": id }).to_string(),
+        };
+        match result {
+            Ok(value) => object["ok"] = value.to_json_value(),
+            Err(message) => object["err"] = JsonValue::String(message.clone()),
+        }
+        object.to_string()
+    }
+
+    /// Decode an entry from a line of JSON.
+    pub fn from_json_line(line: &str) -> Result<Self, String> {
+        let object: JsonValue =
+            serde_json::from_str(line).map_err(|e| format!("Invalid trace entry: {}", e))?;
+        let field = |name: &str| {
+            object
+                .get(name)
+                .and_then(JsonValue::as_str)
+                .map(str::to_string)
+                .ok_or_else(|| format!("Trace entry is missing '{}'", name))
+        };
+        if field("kind")? == "task" {
+            return Ok(TraceEntry::Task { id: field("id")? });
+        }
+        let result = match (object.get("ok"), object.get("err")) {
+            (Some(ok), _) => Ok(Value::from_json_value(ok.clone())),
+            (None, Some(JsonValue::String(err))) => Err(err.clone()),
+            _ => return Err("Trace entry has no result".to_string()),
+        };
+
+        match field("kind")?.as_str() {
+            "shell" => Ok(TraceEntry::Shell {
+                command: field("command")?,
+                args: object
+                    .get("args")
+                    .and_then(JsonValue::as_array)
+                    .map(|args| args.iter().filter_map(|a| a.as_str().map(str::to_string)).collect())
+                    .unwrap_or_default(),
+                result,
+            }),
+            "think" => Ok(TraceEntry::Think {
+                prompt: field("prompt")?,
+                result,
+                nested: object.get("nested").and_then(JsonValue::as_u64).unwrap_or(0) as usize,
+            }),
+            other => Err(format!("Unknown trace entry kind '{}'", other)),
+        }
+    }
+}
+
+/// Parse a JSON-lines trace, skipping blank lines.
+pub fn parse_trace(text: &str) -> Result<Vec<TraceEntry>, String> {
+    text.lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(i, line)| TraceEntry::from_json_line(line).map_err(|e| format!("line {}: {}", i + 1, e)))
+        .collect()
+}
+
+/// Whether effects are being recorded, replayed, or neither.
+#[derive(Debug, Default)]
+pub(crate) enum Tracer {
+    #[default]
+    Off,
+    Recording(Streams),
+    Replaying(Streams),
+}
+
+/// Every task's entries, shared by the runtimes of one run, and the stream
+/// one of them reads or writes.
+#[derive(Debug)]
+pub(crate) struct Streams {
+    /// The task this runtime runs, empty for the program itself
+    task: String,
+    /// Tasks spawned from this one so far
+    spawned: AtomicUsize,
+    entries: Arc<Mutex<BTreeMap<String, VecDeque<TraceEntry>>>>,
+}
+
+impl Streams {
+    fn new(entries: BTreeMap<String, VecDeque<TraceEntry>>) -> Self {
+        Streams { task: String::new(), spawned: AtomicUsize::new(0), entries: Arc::new(Mutex::new(entries)) }
+    }
+
+    /// The stream of the next task spawned from this one.
+    fn child(&self) -> Self {
+        let n = self.spawned.fetch_add(1, Ordering::SeqCst) + 1;
+        let task = match self.task.as_str() {
+            "" => n.to_string(),
+            parent => format!("{}.{}", parent, n),
+        };
+        Streams { task, spawned: AtomicUsize::new(0), entries: self.entries.clone() }
+    }
+
+    /// Run `f` on this task's entries.
+    fn with<T>(&self, f: impl FnOnce(&mut VecDeque<TraceEntry>) -> T) -> T {
+        let mut entries = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+        f(entries.entry(self.task.clone()).or_default())
+    }
+}
+
+impl Tracer {
+    pub fn recording() -> Self {
+        Tracer::Recording(Streams::new(BTreeMap::new()))
+    }
+
+    /// Replay `entries`, split into the streams their task lines start.
+    pub fn replaying(entries: Vec<TraceEntry>) -> Self {
+        let mut streams: BTreeMap<String, VecDeque<TraceEntry>> = BTreeMap::new();
+        let mut task = String::new();
+        for entry in entries {
+            match entry {
+                TraceEntry::Task { id } => task = id,
+                entry => streams.entry(task.clone()).or_default().push_back(entry),
+            }
+        }
+        Tracer::Replaying(Streams::new(streams))
+    }
+
+    /// The tracer for a task spawned from this runtime, which records to or
+    /// replays from the task's own stream.
+    pub fn fork(&self) -> Self {
+        match self {
+            Tracer::Off => Tracer::Off,
+            Tracer::Recording(streams) => Tracer::Recording(streams.child()),
+            Tracer::Replaying(streams) => Tracer::Replaying(streams.child()),
+        }
+    }
+
+    /// Every task's recorded entries, the program's first, each task's after
+    /// a line naming it.
+    pub fn entries(&self) -> Vec<TraceEntry> {
+        let Tracer::Recording(streams) = self else {
+            return Vec::new();
+        };
+        let streams = streams.entries.lock().unwrap_or_else(|e| e.into_inner());
+        let mut entries = Vec::new();
+        for (task, stream) in streams.iter() {
+            if !task.is_empty() {
+                entries.push(TraceEntry::Task { id: task.clone() });
+            }
+            entries.extend(stream.iter().cloned());
+        }
+        entries
+    }
+
+    /// When replaying, take the recorded result for this shell command.
+    ///
+    /// Returns `Ok(None)` when not replaying, and an error if the program
+    /// asked for something other than what the trace recorded next.
+    pub fn replay_shell(&mut self, command: &str, args: &[String]) -> Result<Option<Result<Value, String>>, String> {
+        let Tracer::Replaying(streams) = self else {
+            return Ok(None);
+        };
+        match streams.with(VecDeque::pop_front) {
+            Some(TraceEntry::Shell { command: recorded, args: recorded_args, result })
+                if recorded == command && recorded_args == args =>
+            {
+                Ok(Some(result))
+            }
+            other => Err(divergence(&format!("shell command `{}`", command), other.as_ref())),
+        }
+    }
+
+    pub fn record_shell(&mut self, command: &str, args: &[String], result: &Result<Value, Error>) {
+        if let Tracer::Recording(streams) = self {
+            streams.with(|entries| {
+                entries.push_back(TraceEntry::Shell {
+                    command: command.to_string(),
+                    args: args.to_vec(),
+                    result: to_recorded(result),
+                })
+            });
+        }
+    }
+
+    /// When replaying, take the recorded answer for a think block, skipping
+    /// the entries its do-blocks recorded.
+    pub fn replay_think(&mut self, prompt: &str) -> Result<Option<Result<Value, String>>, String> {
+        let Tracer::Replaying(streams) = self else {
+            return Ok(None);
+        };
+        streams.with(|entries| match entries.pop_front() {
+            Some(TraceEntry::Think { result, nested, .. }) => {
+                entries.drain(..nested.min(entries.len()));
+                Ok(Some(result))
+            }
+            other => Err(divergence(&format!("think block \"{}\"", prompt), other.as_ref())),
+        })
+    }
+
+    /// Reserve a slot for a think block before the agent runs it.
+    pub fn begin_think(&mut self, prompt: &str) -> Option<usize> {
+        let Tracer::Recording(streams) = self else {
+            return None;
+        };
+        streams.with(|entries| {
+            entries.push_back(TraceEntry::Think {
+                prompt: prompt.to_string(),
+                result: Ok(Value::Null),
+                nested: 0,
+            });
+            Some(entries.len() - 1)
+        })
+    }
+
+    /// Fill in a think block's slot once the agent has answered.
+    pub fn end_think(&mut self, slot: Option<usize>, outcome: &Result<Value, Error>) {
+        let (Tracer::Recording(streams), Some(slot)) = (self, slot) else {
+            return;
+        };
+        streams.with(|entries| {
+            let nested_count = entries.len() - slot - 1;
+            if let Some(TraceEntry::Think { result, nested, .. }) = entries.get_mut(slot) {
+                *result = to_recorded(outcome);
+                *nested = nested_count;
+            }
+        })
+    }
+}
+
+fn to_recorded(result: &Result<Value, Error>) -> Result<Value, String> {
+    match result {
+        Ok(value) => Ok(value.clone()),
+        Err(Error::Runtime(message)) => Err(message.clone()),
+        Err(other) => Err(other.to_string()),
+    }
+}
+
+fn divergence(requested: &str, recorded: Option<&TraceEntry>) -> String {
+    let recorded = match recorded {
+        Some(TraceEntry::Shell { command, .. }) => format!("shell command `{}`", command),
+        Some(TraceEntry::Think { prompt, .. }) => format!("think block \"{}\"", prompt),
+        // Task lines are consumed when the trace is split into streams
+        Some(TraceEntry::Task { id }) => format!("the start of task {}", id),
+        None => "the end of the trace".to_string(),
+    };
+    format!("Replay diverged: program ran {} but the trace has {}", requested, recorded)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_entries_round_trip_through_json() {
+        let entries = vec![
+            TraceEntry::Shell {
+                command: "git".to_string(),
+                args: vec!["status".to_string()],
+                result: Ok(Value::String("clean".to_string())),
+            },
+            TraceEntry::Think {
+                prompt: "Summarize".to_string(),
+                result: Err("agent unavailable".to_string()),
+                nested: 1,
+            },
+            TraceEntry::Task { id: "1.2".to_string() },
+        ];
+        let text: Vec<String> = entries.iter().map(TraceEntry::to_json_line).collect();
+        assert_eq!(parse_trace(&text.join("\n")).unwrap(), entries);
+    }
+}
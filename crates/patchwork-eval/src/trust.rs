@@ -0,0 +1,119 @@
+//! Trust grants for running program files.
+//!
+//! A program can run shell commands, so hosts won't run a file the user
+//! hasn't trusted. A grant covers one file with its current contents:
+//! editing the file, or moving it, takes the trust away again. The modules
+//! a program imports run commands too, so each needs a grant of its own,
+//! checked as it's imported (see [`Interpreter::require_trusted_modules`]).
+//!
+//! Grants live in a per-user store, one per line as the SHA-256 of the
+//! file's contents and its canonical path:
+//!
+//! ```text
+//! 3a7bd3e2360a3d29eea436fcfb7e44c735d117c42d1c1835420b6b9942dd4f1b /home/me/flows/release.pw
+//! ```
+//!
+//! The store is `$PATCHWORK_TRUST_STORE` if set, and otherwise
+//! `patchwork/trusted` under `$XDG_CONFIG_HOME` or `~/.config`.
+//!
+//! [`Interpreter::require_trusted_modules`]: crate::Interpreter::require_trusted_modules
+
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use sha2::{Digest, Sha256};
+
+/// Environment variable overriding where grants are stored.
+pub const STORE_VAR: &str = "PATCHWORK_TRUST_STORE";
+
+/// Where this user's grants are stored, or `None` without a home directory.
+pub fn store_path() -> Option<PathBuf> {
+    if let Some(path) = env::var_os(STORE_VAR) {
+        return Some(PathBuf::from(path));
+    }
+    let config = env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))?;
+    Some(config.join("patchwork").join("trusted"))
+}
+
+/// Hex SHA-256 of a program's text.
+pub fn content_hash(code: &str) -> String {
+    Sha256::digest(code.as_bytes()).iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// The grants in `store` as `(hash, path)`; a missing store has none.
+fn read_grants(store: &Path) -> Vec<(String, PathBuf)> {
+    let text = fs::read_to_string(store).unwrap_or_default();
+    text.lines()
+        .filter_map(|line| line.split_once(' '))
+        .map(|(hash, path)| (hash.to_string(), PathBuf::from(path)))
+        .collect()
+}
+
+fn canonical(file: &Path) -> Result<PathBuf, String> {
+    fs::canonicalize(file).map_err(|e| format!("Error resolving '{}': {}", file.display(), e))
+}
+
+/// Whether `file`, whose text is `code`, has been trusted as it is now.
+pub fn is_trusted(store: &Path, file: &Path, code: &str) -> bool {
+    let Ok(path) = canonical(file) else {
+        return false;
+    };
+    let hash = content_hash(code);
+    read_grants(store).iter().any(|grant| grant.0 == hash && grant.1 == path)
+}
+
+/// Check that `file`, whose text is `code`, has been trusted, for hosts
+/// with no one to ask.
+pub fn require_grant(file: &Path, code: &str) -> Result<(), String> {
+    let store = store_path().ok_or_else(|| format!("No trust store found; set ${}", STORE_VAR))?;
+    if is_trusted(&store, file, code) {
+        return Ok(());
+    }
+    let path = file.display();
+    Err(format!("'{}' isn't trusted; run `patchwork trust {}`", path, path))
+}
+
+/// Trust `file` with its text `code`, replacing any earlier grant for it.
+pub fn grant(store: &Path, file: &Path, code: &str) -> Result<(), String> {
+    let path = canonical(file)?;
+    let mut grants = read_grants(store);
+    grants.retain(|(_, granted)| *granted != path);
+    grants.push((content_hash(code), path));
+
+    if let Some(dir) = store.parent() {
+        fs::create_dir_all(dir).map_err(|e| format!("Error creating '{}': {}", dir.display(), e))?;
+    }
+    let text: String = grants.iter().map(|(hash, path)| format!("{} {}\n", hash, path.display())).collect();
+    fs::write(store, text).map_err(|e| format!("Error writing '{}': {}", store.display(), e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_grants_follow_content() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = dir.path().join("config").join("trusted");
+        let file = dir.path().join("flow.pw");
+        fs::write(&file, "skill main() {}").unwrap();
+
+        assert!(!is_trusted(&store, &file, "skill main() {}"));
+        grant(&store, &file, "skill main() {}").unwrap();
+        assert!(is_trusted(&store, &file, "skill main() {}"));
+        // Edited contents need a new grant, which replaces the old one
+        assert!(!is_trusted(&store, &file, "skill main() { $ rm -rf / }"));
+        grant(&store, &file, "skill main() { $ ls }").unwrap();
+        assert!(is_trusted(&store, &file, "skill main() { $ ls }"));
+        assert!(!is_trusted(&store, &file, "skill main() {}"));
+        assert_eq!(fs::read_to_string(&store).unwrap().lines().count(), 1);
+
+        // The same contents elsewhere aren't trusted
+        let copy = dir.path().join("copy.pw");
+        fs::write(&copy, "skill main() { $ ls }").unwrap();
+        assert!(!is_trusted(&store, &copy, "skill main() { $ ls }"));
+    }
+}
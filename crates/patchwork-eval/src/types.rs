@@ -0,0 +1,447 @@
+//! Runtime type descriptions for the Patchwork interpreter.
+//!
+//! Type annotations in the AST borrow from the source text, so declarations are
+//! converted into owned [`Type`] values that the runtime can keep around. Named
+//! types are resolved through the aliases registered by `type` declarations.
+
+use std::collections::HashMap;
+use std::fmt;
+
+use indexmap::IndexMap;
+use patchwork_parser::ast::{Spanned, TypeExpr, TypeField};
+use patchwork_typecheck::{resolve_aliases, MAX_ALIAS_DEPTH};
+
+use crate::value::Value;
+
+/// An owned type description.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Type {
+    /// Matches any value.
+    Any,
+    Null,
+    String,
+    Number,
+    Boolean,
+    Duration,
+    Size,
+    /// A closure or named function used as a value.
+    Function,
+    /// A reference to a type alias, resolved at use.
+    Named(String),
+    /// A string literal type: `"success"`.
+    Literal(String),
+    /// An array whose elements all have the given type (`[T]` or `list<T>`).
+    Array(Box<Type>),
+    /// An object used as a string-keyed map: `map<string, T>`.
+    Map(Box<Type>),
+    /// Null or the given type: `option<T>`.
+    Option(Box<Type>),
+    /// An object with (at least) the given fields.
+    Object(Vec<FieldType>),
+    /// Any one of the given types.
+    Union(Vec<Type>),
+    /// A tagged variant: an object whose `__tag` is `tag`.
+    Variant { tag: String, fields: Vec<FieldType> },
+}
+
+/// How typed function parameters are checked when a call crosses into them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TypeCheckMode {
+    /// Skip boundary checks entirely.
+    Off,
+    /// Report mismatches on stderr but continue the call.
+    Warn,
+    /// Fail the call with a runtime error.
+    #[default]
+    Error,
+}
+
+/// A field in an object or variant type.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FieldType {
+    pub key: String,
+    pub ty: Type,
+    pub optional: bool,
+}
+
+impl Type {
+    /// Convert a parsed type annotation into an owned type.
+    pub fn from_expr(expr: &TypeExpr) -> Type {
+        match expr {
+            TypeExpr::Name(name) => Type::from_name(name),
+            TypeExpr::Object(fields) => Type::Object(fields.iter().map(FieldType::from_field).collect()),
+            TypeExpr::Array(elem) => Type::Array(Box::new(Type::from_expr(elem))),
+            TypeExpr::Union(types) => Type::Union(types.iter().map(|ty| Type::from_expr(ty)).collect()),
+            TypeExpr::Literal(text) => Type::Literal(text.to_string()),
+            TypeExpr::Generic { name, args } => Type::from_generic(name, args),
+            TypeExpr::Variant { tag, fields } => Type::Variant {
+                tag: tag.to_string(),
+                fields: fields.iter().map(FieldType::from_field).collect(),
+            },
+        }
+    }
+
+    /// Instantiate one of the builtin generic containers.
+    ///
+    /// Anything else (unknown names, wrong arity, non-string map keys) becomes a
+    /// named reference that fails to resolve, so misuse surfaces as an
+    /// "Unknown type" error when the type is used.
+    fn from_generic(name: &str, args: &[Spanned<TypeExpr>]) -> Type {
+        match (name, args) {
+            ("list", [elem]) => Type::Array(Box::new(Type::from_expr(elem))),
+            ("option", [inner]) => Type::Option(Box::new(Type::from_expr(inner))),
+            ("map", [key, value]) if matches!(&key.node, TypeExpr::Name(key) if key == "string") => {
+                Type::Map(Box::new(Type::from_expr(value)))
+            }
+            _ => {
+                let args: Vec<String> = args.iter().map(|a| Type::from_expr(a).to_string()).collect();
+                Type::Named(format!("{}<{}>", name, args.join(", ")))
+            }
+        }
+    }
+
+    /// Map a type name to a builtin type, or a reference to an alias.
+    fn from_name(name: &str) -> Type {
+        match name {
+            "any" => Type::Any,
+            "null" => Type::Null,
+            "string" => Type::String,
+            "number" | "int" | "float" => Type::Number,
+            "bool" | "boolean" => Type::Boolean,
+            "duration" => Type::Duration,
+            "size" => Type::Size,
+            "function" => Type::Function,
+            other => Type::Named(other.to_string()),
+        }
+    }
+
+    /// Follow aliases until reaching a structural type.
+    pub fn resolve<'a>(&'a self, aliases: &'a HashMap<String, Type>) -> Result<&'a Type, String> {
+        let resolved = resolve_aliases(self, |ty| match ty {
+            Type::Named(name) => aliases.get(name),
+            _ => None,
+        })
+        .map_err(|_| "Type alias nesting too deep (cyclic alias?)".to_string())?;
+        match resolved {
+            Type::Named(name) => Err(format!("Unknown type '{}'", name)),
+            other => Ok(other),
+        }
+    }
+
+    /// Expand every alias reference, producing the full structural shape.
+    ///
+    /// Unknown names are left as-is so partially-declared shapes still display.
+    pub fn expand(&self, aliases: &HashMap<String, Type>) -> Type {
+        self.expand_depth(aliases, 0)
+    }
+
+    fn expand_depth(&self, aliases: &HashMap<String, Type>, depth: usize) -> Type {
+        if depth > MAX_ALIAS_DEPTH {
+            return self.clone();
+        }
+        let expand_fields = |fields: &[FieldType]| {
+            fields
+                .iter()
+                .map(|f| FieldType {
+                    key: f.key.clone(),
+                    ty: f.ty.expand_depth(aliases, depth + 1),
+                    optional: f.optional,
+                })
+                .collect()
+        };
+        match self {
+            Type::Named(name) => match aliases.get(name) {
+                Some(ty) => ty.expand_depth(aliases, depth + 1),
+                None => self.clone(),
+            },
+            Type::Array(elem) => Type::Array(Box::new(elem.expand_depth(aliases, depth + 1))),
+            Type::Map(value) => Type::Map(Box::new(value.expand_depth(aliases, depth + 1))),
+            Type::Option(inner) => Type::Option(Box::new(inner.expand_depth(aliases, depth + 1))),
+            Type::Object(fields) => Type::Object(expand_fields(fields)),
+            Type::Union(types) => {
+                Type::Union(types.iter().map(|t| t.expand_depth(aliases, depth + 1)).collect())
+            }
+            Type::Variant { tag, fields } => Type::Variant {
+                tag: tag.clone(),
+                fields: expand_fields(fields),
+            },
+            other => other.clone(),
+        }
+    }
+
+    /// Check that a value conforms to this type.
+    ///
+    /// On mismatch, returns a message naming the offending path, e.g.
+    /// `at .commits[2].hash: expected string, got number`.
+    pub fn check(&self, value: &Value, aliases: &HashMap<String, Type>) -> Result<(), String> {
+        self.check_at(value, aliases, &mut String::new())
+    }
+
+    fn check_at(&self, value: &Value, aliases: &HashMap<String, Type>, path: &mut String) -> Result<(), String> {
+        let ty = self.resolve(aliases)?;
+        let mismatch = |path: &str| {
+            let at = if path.is_empty() { "value" } else { path };
+            Err(format!("at {}: expected {}, got {}", at, ty, describe(value)))
+        };
+
+        match (ty, value) {
+            (Type::Any, _)
+            | (Type::Null, Value::Null)
+            | (Type::String, Value::String(_) | Value::Shell(_))
+            | (Type::Number, Value::Number(_))
+            | (Type::Boolean, Value::Boolean(_))
+            | (Type::Duration, Value::Duration(_))
+            | (Type::Size, Value::Size(_))
+            | (Type::Function, Value::Closure(_)) => Ok(()),
+            (Type::Literal(expected), Value::String(s)) if expected == s => Ok(()),
+            (Type::Literal(expected), Value::Shell(result)) if expected == result.text() => Ok(()),
+            (Type::Array(elem), Value::Array(items)) => {
+                for (i, item) in items.iter().enumerate() {
+                    let len = path.len();
+                    path.push_str(&format!("[{}]", i));
+                    elem.check_at(item, aliases, path)?;
+                    path.truncate(len);
+                }
+                Ok(())
+            }
+            (Type::Map(value_ty), Value::Object(map)) => {
+                // Sort keys so the first reported mismatch is deterministic
+                let mut keys: Vec<&String> = map.keys().collect();
+                keys.sort();
+                for key in keys {
+                    let len = path.len();
+                    path.push_str(&format!("[{:?}]", key));
+                    value_ty.check_at(&map[key], aliases, path)?;
+                    path.truncate(len);
+                }
+                Ok(())
+            }
+            (Type::Option(_), Value::Null) => Ok(()),
+            (Type::Option(inner), _) => inner.check_at(value, aliases, path),
+            (Type::Object(fields), Value::Object(map)) => check_fields(fields, map, aliases, path),
+            (Type::Variant { tag, fields }, Value::Object(map)) => match map.get("__tag") {
+                Some(Value::String(actual)) if actual == tag => check_fields(fields, map, aliases, path),
+                _ => mismatch(path),
+            },
+            (Type::Union(types), _) => {
+                if types.iter().any(|t| t.check_at(value, aliases, &mut path.clone()).is_ok()) {
+                    Ok(())
+                } else {
+                    mismatch(path)
+                }
+            }
+            _ => mismatch(path),
+        }
+    }
+}
+
+/// Check the declared fields of an object or variant type.
+fn check_fields(
+    fields: &[FieldType],
+    map: &IndexMap<String, Value>,
+    aliases: &HashMap<String, Type>,
+    path: &mut String,
+) -> Result<(), String> {
+    for field in fields {
+        let len = path.len();
+        path.push('.');
+        path.push_str(&field.key);
+        match map.get(&field.key) {
+            Some(v) => field.ty.check_at(v, aliases, path)?,
+            None if field.optional => {}
+            None => return Err(format!("at {}: missing field of type {}", path, field.ty)),
+        }
+        path.truncate(len);
+    }
+    Ok(())
+}
+
+/// Short description of a value for mismatch messages.
+fn describe(value: &Value) -> String {
+    match value {
+        Value::Null => "null".to_string(),
+        Value::String(s) => format!("string {:?}", s),
+        Value::Number(_) => "number".to_string(),
+        Value::Boolean(_) => "boolean".to_string(),
+        Value::Array(_) => "array".to_string(),
+        Value::Object(map) => match map.get("__tag") {
+            Some(Value::String(tag)) => format!("variant {}", tag),
+            _ => "object".to_string(),
+        },
+        Value::Duration(_) => "duration".to_string(),
+        Value::Size(_) => "size".to_string(),
+        Value::Closure(_) => "function".to_string(),
+        Value::Task(_) => "task".to_string(),
+        Value::Conversation(_) => "conversation".to_string(),
+        Value::Shell(result) => format!("string {:?}", result.text()),
+    }
+}
+
+impl FieldType {
+    fn from_field(field: &TypeField) -> FieldType {
+        FieldType {
+            key: field.key.to_string(),
+            ty: Type::from_expr(&field.type_expr),
+            optional: field.optional,
+        }
+    }
+}
+
+impl fmt::Display for Type {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Type::Any => write!(f, "any"),
+            Type::Null => write!(f, "null"),
+            Type::String => write!(f, "string"),
+            Type::Number => write!(f, "number"),
+            Type::Boolean => write!(f, "boolean"),
+            Type::Duration => write!(f, "duration"),
+            Type::Size => write!(f, "size"),
+            Type::Function => write!(f, "function"),
+            Type::Named(name) => write!(f, "{}", name),
+            Type::Literal(text) => write!(f, "{:?}", text),
+            Type::Array(elem) => write!(f, "[{}]", elem),
+            Type::Map(value) => write!(f, "map<string, {}>", value),
+            Type::Option(inner) => write!(f, "option<{}>", inner),
+            Type::Object(fields) => write_fields(f, fields),
+            Type::Union(types) => {
+                for (i, ty) in types.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, " | ")?;
+                    }
+                    write!(f, "{}", ty)?;
+                }
+                Ok(())
+            }
+            Type::Variant { tag, fields } => {
+                write!(f, "{} ", tag)?;
+                write_fields(f, fields)
+            }
+        }
+    }
+}
+
+fn write_fields(f: &mut fmt::Formatter<'_>, fields: &[FieldType]) -> fmt::Result {
+    if fields.is_empty() {
+        return write!(f, "{{}}");
+    }
+    write!(f, "{{ ")?;
+    for (i, field) in fields.iter().enumerate() {
+        if i > 0 {
+            write!(f, ", ")?;
+        }
+        let opt = if field.optional { "?" } else { "" };
+        write!(f, "{}{}: {}", field.key, opt, field.ty)?;
+    }
+    write!(f, " }}")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use patchwork_parser::{parse, Item};
+
+    /// Parse `type` declarations into an alias table.
+    fn aliases(src: &str) -> HashMap<String, Type> {
+        let program = parse(src).expect("parse failed");
+        program
+            .items
+            .iter()
+            .filter_map(|item| match &item.node {
+                Item::Type(decl) => Some((decl.name.to_string(), Type::from_expr(&decl.type_expr))),
+                _ => None,
+            })
+            .collect()
+    }
+
+    fn obj(fields: &[(&str, Value)]) -> Value {
+        Value::Object(fields.iter().map(|(k, v)| (k.to_string(), v.clone())).collect())
+    }
+
+    #[test]
+    fn test_check_through_alias() {
+        let types = aliases(
+            "type commit = { hash: string, files: [string] }\ntype commit_plan = { commits: [commit] }",
+        );
+        let plan = Type::Named("commit_plan".to_string());
+
+        let good = obj(&[(
+            "commits",
+            Value::Array(vec![obj(&[
+                ("hash", Value::String("abc".into())),
+                ("files", Value::Array(vec![Value::String("a.rs".into())])),
+            ])]),
+        )]);
+        assert!(plan.check(&good, &types).is_ok());
+
+        let bad = obj(&[(
+            "commits",
+            Value::Array(vec![obj(&[("hash", Value::Number(1.0)), ("files", Value::Array(vec![]))])]),
+        )]);
+        let err = plan.check(&bad, &types).unwrap_err();
+        assert!(err.contains(".commits[0].hash"), "unexpected message: {}", err);
+    }
+
+    #[test]
+    fn test_expand_alias_for_display() {
+        let types = aliases("type status = \"ok\" | \"failed\"\ntype report = { status: status }");
+        let expanded = Type::Named("report".to_string()).expand(&types);
+        assert_eq!(expanded.to_string(), "{ status: \"ok\" | \"failed\" }");
+    }
+
+    #[test]
+    fn test_check_variant_tag() {
+        let types = aliases("type result = Success {hash: string} | Failure {reason: string}");
+        let ty = Type::Named("result".to_string());
+        let failure = obj(&[
+            ("__tag", Value::String("Failure".into())),
+            ("reason", Value::String("boom".into())),
+        ]);
+        assert!(ty.check(&failure, &types).is_ok());
+
+        let untagged = obj(&[("reason", Value::String("boom".into()))]);
+        assert!(ty.check(&untagged, &types).is_err());
+    }
+
+    #[test]
+    fn test_check_generic_containers() {
+        let types = aliases(
+            "type commit = { hash: string }\ntype index = { by_author: map<string, list<commit>>, reviewer: option<string> }",
+        );
+        let ty = Type::Named("index".to_string());
+        let commit = obj(&[("hash", Value::String("abc".into()))]);
+
+        let good = obj(&[
+            ("by_author", obj(&[("ann", Value::Array(vec![commit.clone()]))])),
+            ("reviewer", Value::Null),
+        ]);
+        assert!(ty.check(&good, &types).is_ok());
+
+        let bad = obj(&[
+            ("by_author", obj(&[("ann", Value::Array(vec![Value::Number(1.0)]))])),
+            ("reviewer", Value::String("bo".into())),
+        ]);
+        let err = ty.check(&bad, &types).unwrap_err();
+        assert!(err.contains(".by_author[\"ann\"][0]"), "unexpected message: {}", err);
+    }
+
+    #[test]
+    fn test_invalid_generic_is_unknown_type() {
+        let types = aliases("type bad = map<number, string>");
+        let err = Type::Named("bad".to_string()).check(&Value::Null, &types).unwrap_err();
+        assert!(err.contains("Unknown type 'map<number, string>'"), "unexpected message: {}", err);
+    }
+
+    #[test]
+    fn test_cyclic_alias_is_error() {
+        let types = aliases("type a = b\ntype b = a");
+        assert!(Type::Named("a".to_string()).check(&Value::Null, &types).is_err());
+    }
+
+    #[test]
+    fn test_builtin_names_agree_with_type_checker() {
+        for name in patchwork_typecheck::BUILTIN_TYPES {
+            assert!(!matches!(Type::from_name(name), Type::Named(_)), "'{}' isn't a builtin here", name);
+        }
+    }
+}
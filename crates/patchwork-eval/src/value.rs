@@ -1,10 +1,20 @@
 //! Runtime values for the Patchwork interpreter.
 
+use std::collections::hash_map::DefaultHasher;
 use std::collections::HashMap;
 use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::sync::Arc;
+
+use indexmap::IndexMap;
+use patchwork_parser::ast::FunctionDecl;
 
 use serde_json::Value as JsonValue;
 
+use crate::agent::Conversation;
+use crate::runtime::ModuleId;
+use crate::scheduler::Task;
+
 /// A runtime value in the Patchwork language.
 #[derive(Debug, Clone, PartialEq)]
 pub enum Value {
@@ -18,8 +28,128 @@ pub enum Value {
     Boolean(bool),
     /// An array of values.
     Array(Vec<Value>),
-    /// An object with string keys.
-    Object(HashMap<String, Value>),
+    /// An object with string keys, which remembers the order its fields
+    /// were added in.
+    Object(IndexMap<String, Value>),
+    /// A duration, stored in milliseconds (from literals like `5s`).
+    Duration(f64),
+    /// A size, stored in bytes (from literals like `10kb`; 1kb = 1024b).
+    Size(f64),
+    /// A function value, from a `fun(...) { ... }` expression or a named
+    /// function used as a value.
+    Closure(Arc<Closure>),
+    /// The result of a command substitution, `$(cmd)`. Wherever a string is
+    /// expected it reads as the command's output.
+    Shell(Arc<ShellResult>),
+    /// A function running concurrently, from `spawn(f, args...)`.
+    Task(Arc<Task>),
+    /// A conversation with an agent, from `think.start { ... }`.
+    Conversation(Arc<Conversation>),
+}
+
+/// What a command printed and how it exited.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct ShellResult {
+    pub stdout: String,
+    pub stderr: String,
+    /// Exit code, or None if the command was killed by a signal.
+    pub status: Option<i32>,
+}
+
+impl ShellResult {
+    /// Whether the command exited with status 0.
+    pub fn success(&self) -> bool {
+        self.status == Some(0)
+    }
+
+    /// Standard output without trailing newlines, which is how the result
+    /// reads as a string.
+    pub fn text(&self) -> &str {
+        self.stdout.trim_end_matches('\n')
+    }
+
+    /// Read field `name`: `stdout`, `stderr`, `status`, or `success`.
+    pub(crate) fn field(&self, name: &str) -> Option<Value> {
+        match name {
+            "stdout" => Some(Value::String(self.stdout.clone())),
+            "stderr" => Some(Value::String(self.stderr.clone())),
+            "status" => Some(self.status.map_or(Value::Null, |code| Value::Number(code as f64))),
+            "success" => Some(Value::Boolean(self.success())),
+            _ => None,
+        }
+    }
+
+    /// The result as an object of its fields, as recorded in traces.
+    pub(crate) fn to_object(&self) -> Value {
+        let fields = ["stdout", "stderr", "status"].map(|name| (name.to_string(), self.field(name).unwrap()));
+        Value::Object(IndexMap::from(fields))
+    }
+
+    /// Rebuild a result from [`ShellResult::to_object`], or from the plain
+    /// output that older traces recorded for commands that succeeded.
+    pub(crate) fn from_recorded(value: &Value) -> Option<Self> {
+        match value {
+            Value::Object(fields) => {
+                let text = |name: &str| match fields.get(name) {
+                    Some(Value::String(s)) => Some(s.clone()),
+                    _ => None,
+                };
+                let status = match fields.get("status")? {
+                    Value::Number(code) => Some(*code as i32),
+                    _ => None,
+                };
+                Some(ShellResult { stdout: text("stdout")?, stderr: text("stderr")?, status })
+            }
+            other => Some(ShellResult { stdout: other.to_string_value(), stderr: String::new(), status: Some(0) }),
+        }
+    }
+}
+
+/// A function and the variables it captured where it was created.
+#[derive(Debug)]
+pub struct Closure {
+    pub decl: Arc<FunctionDecl<'static>>,
+    /// Copies of the local variables in scope when the closure was created.
+    /// Globals aren't copied; the body reads them as any function does.
+    pub captured: HashMap<String, Value>,
+    /// The imported module whose scope the body runs in, or `None` for the
+    /// program's own.
+    pub(crate) module: Option<ModuleId>,
+}
+
+/// Closures are equal only to themselves.
+impl PartialEq for Closure {
+    fn eq(&self, other: &Self) -> bool {
+        std::ptr::eq(self, other)
+    }
+}
+
+/// Duration units from largest to smallest, in milliseconds.
+pub(crate) const DURATION_UNITS: &[(&str, f64)] = &[
+    ("h", 3_600_000.0),
+    ("m", 60_000.0),
+    ("s", 1_000.0),
+    ("ms", 1.0),
+];
+
+/// Size units from largest to smallest, in bytes.
+pub(crate) const SIZE_UNITS: &[(&str, f64)] = &[
+    ("gb", 1024.0 * 1024.0 * 1024.0),
+    ("mb", 1024.0 * 1024.0),
+    ("kb", 1024.0),
+    ("b", 1.0),
+];
+
+/// Format a quantity using the largest unit that divides it evenly.
+fn format_quantity(amount: f64, units: &[(&str, f64)]) -> String {
+    for (suffix, scale) in units {
+        let count = amount / scale;
+        if amount != 0.0 && count == count.trunc() {
+            return format!("{}{}", Value::Number(count).to_string_value(), suffix);
+        }
+    }
+    let (suffix, _) = units[units.len() - 1];
+    format!("{}{}", Value::Number(amount).to_string_value(), suffix)
 }
 
 impl Value {
@@ -46,6 +176,12 @@ impl Value {
                 items.join(", ")
             }
             Value::Object(_) => "[object Object]".to_string(),
+            Value::Duration(ms) => format_quantity(*ms, DURATION_UNITS),
+            Value::Size(bytes) => format_quantity(*bytes, SIZE_UNITS),
+            Value::Closure(closure) => format!("[fun {}]", closure.decl.name),
+            Value::Shell(result) => result.text().to_string(),
+            Value::Task(task) => format!("[task {}]", task.name()),
+            Value::Conversation(_) => "[conversation]".to_string(),
         }
     }
 
@@ -57,7 +193,9 @@ impl Value {
             Value::Number(n) => *n != 0.0 && !n.is_nan(),
             Value::Boolean(b) => *b,
             Value::Array(arr) => !arr.is_empty(),
-            Value::Object(_) => true,
+            Value::Object(_) | Value::Closure(_) | Value::Task(_) | Value::Conversation(_) => true,
+            Value::Duration(n) | Value::Size(n) => *n != 0.0 && !n.is_nan(),
+            Value::Shell(result) => !result.text().is_empty(),
         }
     }
 
@@ -73,8 +211,14 @@ impl Value {
         Ok(Value::from_json_value(json))
     }
 
+    /// Parse a YAML string into a Value. Needs the `yaml` feature; without
+    /// it this always fails.
+    pub fn from_yaml(s: &str) -> Result<Value, String> {
+        crate::formats::yaml::parse(s)
+    }
+
     /// Convert a serde_json Value to our Value type.
-    fn from_json_value(json: JsonValue) -> Value {
+    pub(crate) fn from_json_value(json: JsonValue) -> Value {
         match json {
             JsonValue::Null => Value::Null,
             JsonValue::Bool(b) => Value::Boolean(b),
@@ -98,17 +242,25 @@ impl Value {
         serde_json::to_string_pretty(&json).unwrap_or_else(|_| "null".to_string())
     }
 
+    /// Convert this Value to JSON on a single line, for line-delimited streams.
+    pub fn to_json_line(&self) -> String {
+        self.to_json_value().to_string()
+    }
+
     /// Convert this Value to a serde_json Value.
-    fn to_json_value(&self) -> JsonValue {
+    pub(crate) fn to_json_value(&self) -> JsonValue {
         match self {
-            Value::Null => JsonValue::Null,
+            // Functions and tasks aren't data, so they serialize as null, as in JavaScript arrays
+            Value::Null | Value::Closure(_) | Value::Task(_) | Value::Conversation(_) => JsonValue::Null,
             Value::Boolean(b) => JsonValue::Bool(*b),
-            Value::Number(n) => {
+            // Durations serialize as milliseconds and sizes as bytes
+            Value::Number(n) | Value::Duration(n) | Value::Size(n) => {
                 serde_json::Number::from_f64(*n)
                     .map(JsonValue::Number)
                     .unwrap_or(JsonValue::Null)
             }
             Value::String(s) => JsonValue::String(s.clone()),
+            Value::Shell(result) => JsonValue::String(result.text().to_string()),
             Value::Array(arr) => {
                 JsonValue::Array(arr.iter().map(|v| v.to_json_value()).collect())
             }
@@ -133,3 +285,99 @@ impl Default for Value {
         Value::Null
     }
 }
+
+/// A value compared and hashed by content, for deduplicating and grouping.
+///
+/// This differs from `==` in two places: every NaN equals every other NaN,
+/// and objects are compared without regard to field order. `0` and `-0`
+/// are equal as usual. Numbers, durations, and sizes are different kinds,
+/// so `1000` and `1s` never match, and closures match only themselves.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Canonical<'a>(pub &'a Value);
+
+impl PartialEq for Canonical<'_> {
+    fn eq(&self, other: &Self) -> bool {
+        match (self.0, other.0) {
+            (Value::Number(a), Value::Number(b))
+            | (Value::Duration(a), Value::Duration(b))
+            | (Value::Size(a), Value::Size(b)) => a == b || (a.is_nan() && b.is_nan()),
+            (Value::Array(a), Value::Array(b)) => {
+                a.len() == b.len() && a.iter().zip(b).all(|(a, b)| Canonical(a) == Canonical(b))
+            }
+            (Value::Object(a), Value::Object(b)) => {
+                a.len() == b.len()
+                    && a.iter().all(|(key, a)| b.get(key).is_some_and(|b| Canonical(a) == Canonical(b)))
+            }
+            (Value::Closure(a), Value::Closure(b)) => Arc::ptr_eq(a, b),
+            (Value::Task(a), Value::Task(b)) => Arc::ptr_eq(a, b),
+            (Value::Conversation(a), Value::Conversation(b)) => Arc::ptr_eq(a, b),
+            (a, b) => a == b,
+        }
+    }
+}
+
+impl Eq for Canonical<'_> {}
+
+impl Hash for Canonical<'_> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        std::mem::discriminant(self.0).hash(state);
+        match self.0 {
+            Value::Null => {}
+            Value::String(s) => s.hash(state),
+            Value::Boolean(b) => b.hash(state),
+            Value::Number(n) | Value::Duration(n) | Value::Size(n) => {
+                // Equal numbers must hash alike: fold -0 into 0 and every NaN into one
+                let n = if *n == 0.0 { 0.0 } else if n.is_nan() { f64::NAN } else { *n };
+                n.to_bits().hash(state);
+            }
+            Value::Array(items) => {
+                items.len().hash(state);
+                for item in items {
+                    Canonical(item).hash(state);
+                }
+            }
+            Value::Object(fields) => {
+                // Combine per-field hashes with a sum, so field order doesn't matter
+                let mut combined = 0u64;
+                for (key, value) in fields {
+                    let mut hasher = DefaultHasher::new();
+                    key.hash(&mut hasher);
+                    Canonical(value).hash(&mut hasher);
+                    combined = combined.wrapping_add(hasher.finish());
+                }
+                fields.len().hash(state);
+                combined.hash(state);
+            }
+            Value::Closure(closure) => Arc::as_ptr(closure).hash(state),
+            Value::Task(task) => Arc::as_ptr(task).hash(state),
+            Value::Conversation(conversation) => Arc::as_ptr(conversation).hash(state),
+            Value::Shell(result) => result.hash(state),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+
+    #[test]
+    fn test_canonical_equality() {
+        let values = [
+            Value::Number(0.0),
+            Value::Number(-0.0),
+            Value::Number(f64::NAN),
+            Value::Number(-f64::NAN),
+            Value::Duration(0.0),
+            Value::from_json(r#"{"a": 1, "b": [2]}"#).unwrap(),
+            Value::Object(IndexMap::from([
+                ("b".to_string(), Value::Array(vec![Value::Number(2.0)])),
+                ("a".to_string(), Value::Number(1.0)),
+            ])),
+        ];
+        let distinct: HashSet<Canonical> = values.iter().map(Canonical).collect();
+        assert_eq!(distinct.len(), 4);
+        assert!(distinct.contains(&Canonical(&Value::Number(f64::NAN))));
+        assert!(!distinct.contains(&Canonical(&Value::Number(1.0))));
+    }
+}
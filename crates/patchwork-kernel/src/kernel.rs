@@ -0,0 +1,355 @@
+//! The kernel's sockets and request handling.
+
+use std::time::Duration;
+
+use serde_json::{json, Value as JsonValue};
+use zeromq::{PubSocket, RepSocket, RouterSocket, Socket, SocketRecv, SocketSend, ZmqMessage};
+
+use patchwork_eval::{PlanEntryStatus, PlanUpdate};
+
+use crate::wire::{Message, Session, PROTOCOL_VERSION};
+use crate::worker::{Outcome, Worker};
+
+/// How often a running cell is polled for output.
+const POLL_INTERVAL: Duration = Duration::from_millis(20);
+
+/// Where to listen, from the connection file Jupyter starts the kernel with.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConnectionInfo {
+    pub transport: String,
+    pub ip: String,
+    pub shell_port: u64,
+    pub iopub_port: u64,
+    pub stdin_port: u64,
+    pub control_port: u64,
+    pub hb_port: u64,
+    pub key: String,
+}
+
+impl ConnectionInfo {
+    pub fn from_json(text: &str) -> Result<Self, String> {
+        let json: JsonValue = serde_json::from_str(text).map_err(|e| format!("invalid connection file: {}", e))?;
+        let scheme = json["signature_scheme"].as_str().unwrap_or("hmac-sha256");
+        if scheme != "hmac-sha256" {
+            return Err(format!("unsupported signature scheme '{}'", scheme));
+        }
+        let string = |name: &str| {
+            json[name].as_str().map(str::to_string).ok_or_else(|| format!("connection file has no {}", name))
+        };
+        let port = |name: &str| json[name].as_u64().ok_or_else(|| format!("connection file has no {}", name));
+        Ok(Self {
+            transport: string("transport")?,
+            ip: string("ip")?,
+            shell_port: port("shell_port")?,
+            iopub_port: port("iopub_port")?,
+            stdin_port: port("stdin_port")?,
+            control_port: port("control_port")?,
+            hb_port: port("hb_port")?,
+            key: json["key"].as_str().unwrap_or("").to_string(),
+        })
+    }
+
+    fn endpoint(&self, port: u64) -> String {
+        format!("{}://{}:{}", self.transport, self.ip, port)
+    }
+}
+
+pub struct Kernel {
+    session: Session,
+    shell: RouterSocket,
+    control: RouterSocket,
+    iopub: PubSocket,
+    /// Bound so frontends can connect; the kernel never asks for input
+    _stdin: RouterSocket,
+    worker: Worker,
+    execution_count: u64,
+}
+
+impl Kernel {
+    /// Bind every socket and start the heartbeat.
+    pub async fn bind(info: &ConnectionInfo) -> Result<Self, String> {
+        let bind_error = |e: zeromq::ZmqError| format!("Error binding kernel sockets: {}", e);
+        let mut shell = RouterSocket::new();
+        shell.bind(&info.endpoint(info.shell_port)).await.map_err(bind_error)?;
+        let mut control = RouterSocket::new();
+        control.bind(&info.endpoint(info.control_port)).await.map_err(bind_error)?;
+        let mut iopub = PubSocket::new();
+        iopub.bind(&info.endpoint(info.iopub_port)).await.map_err(bind_error)?;
+        let mut stdin = RouterSocket::new();
+        stdin.bind(&info.endpoint(info.stdin_port)).await.map_err(bind_error)?;
+        let mut heartbeat = RepSocket::new();
+        heartbeat.bind(&info.endpoint(info.hb_port)).await.map_err(bind_error)?;
+
+        // The frontend checks the kernel is alive by having pings echoed
+        tokio::spawn(async move {
+            while let Ok(ping) = heartbeat.recv().await {
+                if heartbeat.send(ping).await.is_err() {
+                    break;
+                }
+            }
+        });
+
+        Ok(Self {
+            session: Session::new(&info.key),
+            shell,
+            control,
+            iopub,
+            _stdin: stdin,
+            worker: Worker::spawn(),
+            execution_count: 0,
+        })
+    }
+
+    /// Serve requests until the frontend asks the kernel to shut down.
+    pub async fn run(mut self) -> Result<(), String> {
+        loop {
+            let shutdown = tokio::select! {
+                message = self.shell.recv() => match message {
+                    Ok(message) => self.on_shell(message).await,
+                    Err(e) => return Err(format!("Error on shell socket: {}", e)),
+                },
+                message = self.control.recv() => match message {
+                    Ok(message) => self.on_control(message).await,
+                    Err(e) => return Err(format!("Error on control socket: {}", e)),
+                },
+            };
+            if shutdown {
+                return Ok(());
+            }
+        }
+    }
+
+    async fn send(&mut self, socket: Channel, message: Message) {
+        let frames: ZmqMessage = self.session.encode(&message);
+        // A frontend that went away can't be told anything; keep serving others
+        let _ = match socket {
+            Channel::Shell => self.shell.send(frames).await,
+            Channel::Control => self.control.send(frames).await,
+            Channel::IoPub => self.iopub.send(frames).await,
+        };
+    }
+
+    async fn publish(&mut self, parent: &Message, msg_type: &str, content: JsonValue) {
+        let message = self.session.broadcast(parent, msg_type, content);
+        self.send(Channel::IoPub, message).await;
+    }
+
+    async fn set_status(&mut self, parent: &Message, state: &str) {
+        self.publish(parent, "status", json!({ "execution_state": state })).await;
+    }
+
+    /// Handle a shell request; returns whether the kernel should exit.
+    async fn on_shell(&mut self, message: ZmqMessage) -> bool {
+        let request = match self.session.decode(message) {
+            Ok(request) => request,
+            Err(e) => {
+                eprintln!("[patchwork-kernel] dropping message: {}", e);
+                return false;
+            }
+        };
+
+        self.set_status(&request, "busy").await;
+        let mut shutdown = false;
+        let reply = match request.msg_type() {
+            "kernel_info_request" => Some(("kernel_info_reply", kernel_info())),
+            "execute_request" => Some(("execute_reply", self.execute(&request).await)),
+            "is_complete_request" => Some(("is_complete_reply", json!({ "status": "unknown" }))),
+            "complete_request" => {
+                let cursor = request.content["cursor_pos"].clone();
+                Some((
+                    "complete_reply",
+                    json!({ "status": "ok", "matches": [], "cursor_start": cursor, "cursor_end": cursor, "metadata": {} }),
+                ))
+            }
+            "inspect_request" => Some(("inspect_reply", json!({ "status": "ok", "found": false, "data": {}, "metadata": {} }))),
+            "history_request" => Some(("history_reply", json!({ "status": "ok", "history": [] }))),
+            "comm_info_request" => Some(("comm_info_reply", json!({ "status": "ok", "comms": {} }))),
+            "shutdown_request" => {
+                shutdown = true;
+                Some(("shutdown_reply", shutdown_reply(&request)))
+            }
+            other => {
+                eprintln!("[patchwork-kernel] ignoring unsupported request {}", other);
+                None
+            }
+        };
+        if let Some((msg_type, content)) = reply {
+            let reply = self.session.reply(&request, msg_type, content);
+            self.send(Channel::Shell, reply).await;
+        }
+        self.set_status(&request, "idle").await;
+        shutdown
+    }
+
+    /// Handle a control request; returns whether the kernel should exit.
+    async fn on_control(&mut self, message: ZmqMessage) -> bool {
+        let request = match self.session.decode(message) {
+            Ok(request) => request,
+            Err(e) => {
+                eprintln!("[patchwork-kernel] dropping message: {}", e);
+                return false;
+            }
+        };
+        let (msg_type, content, shutdown) = match request.msg_type() {
+            "interrupt_request" => {
+                self.worker.interrupt();
+                ("interrupt_reply", json!({ "status": "ok" }), false)
+            }
+            "shutdown_request" => ("shutdown_reply", shutdown_reply(&request), true),
+            "kernel_info_request" => ("kernel_info_reply", kernel_info(), false),
+            other => {
+                eprintln!("[patchwork-kernel] ignoring unsupported control request {}", other);
+                return false;
+            }
+        };
+        let reply = self.session.reply(&request, msg_type, content);
+        self.send(Channel::Control, reply).await;
+        shutdown
+    }
+
+    /// Run a cell, streaming its output, and build the execute reply.
+    async fn execute(&mut self, request: &Message) -> JsonValue {
+        let code = request.content["code"].as_str().unwrap_or("").to_string();
+        let silent = request.content["silent"].as_bool().unwrap_or(false);
+        if !silent {
+            self.execution_count += 1;
+            let count = self.execution_count;
+            self.publish(request, "execute_input", json!({ "code": code, "execution_count": count })).await;
+        }
+        let count = self.execution_count;
+
+        self.worker.start(&code);
+        let mut plan_shown = false;
+        let mut ticker = tokio::time::interval(POLL_INTERVAL);
+        let outcome = loop {
+            tokio::select! {
+                _ = ticker.tick() => {
+                    let progress = self.worker.poll();
+                    if !progress.printed.is_empty() && !silent {
+                        let text: String = progress.printed.iter().map(|line| format!("{}\n", line)).collect();
+                        self.publish(request, "stream", json!({ "name": "stdout", "text": text })).await;
+                    }
+                    // Only the latest plan matters; later updates replace the display
+                    if let Some(plan) = progress.plans.last().filter(|_| !silent) {
+                        let display_id = format!("plan-{}", request.header["msg_id"].as_str().unwrap_or(""));
+                        let msg_type = if plan_shown { "update_display_data" } else { "display_data" };
+                        plan_shown = true;
+                        self.publish(request, msg_type, json!({
+                            "data": render_plan(plan),
+                            "metadata": {},
+                            "transient": { "display_id": display_id },
+                        })).await;
+                    }
+                    if let Some(outcome) = progress.outcome {
+                        break outcome;
+                    }
+                }
+                // Interrupts arrive on the control socket while the cell runs
+                message = self.control.recv() => {
+                    if let Ok(message) = message {
+                        self.on_control(message).await;
+                    }
+                }
+            }
+        };
+
+        match outcome {
+            Outcome::Value(value) => {
+                if let (Some(value), false) = (value, silent) {
+                    self.publish(request, "execute_result", json!({
+                        "execution_count": count,
+                        "data": { "text/plain": value },
+                        "metadata": {},
+                    }))
+                    .await;
+                }
+                json!({ "status": "ok", "execution_count": count, "user_expressions": {} })
+            }
+            Outcome::Error(message) => {
+                let error = json!({ "ename": "Error", "evalue": message, "traceback": [message] });
+                if !silent {
+                    self.publish(request, "error", error.clone()).await;
+                }
+                let mut reply = json!({ "status": "error", "execution_count": count });
+                reply.as_object_mut().unwrap().extend(error.as_object().unwrap().clone());
+                reply
+            }
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+enum Channel {
+    Shell,
+    Control,
+    IoPub,
+}
+
+fn kernel_info() -> JsonValue {
+    json!({
+        "status": "ok",
+        "protocol_version": PROTOCOL_VERSION,
+        "implementation": "patchwork",
+        "implementation_version": env!("CARGO_PKG_VERSION"),
+        "language_info": {
+            "name": "patchwork",
+            "version": env!("CARGO_PKG_VERSION"),
+            "mimetype": "text/x-patchwork",
+            "file_extension": ".pw",
+        },
+        "banner": "Patchwork",
+        "help_links": [],
+    })
+}
+
+fn shutdown_reply(request: &Message) -> JsonValue {
+    json!({ "status": "ok", "restart": request.content["restart"].as_bool().unwrap_or(false) })
+}
+
+/// A plan as a Markdown checklist, with a plain-text fallback.
+fn render_plan(plan: &PlanUpdate) -> JsonValue {
+    let lines = |checked: &str, unchecked: &str, current: &str| {
+        plan.entries
+            .iter()
+            .map(|entry| {
+                let mark = match entry.status {
+                    PlanEntryStatus::Completed => checked,
+                    PlanEntryStatus::InProgress => current,
+                    PlanEntryStatus::Pending => unchecked,
+                };
+                format!("{}{}", mark, entry.content)
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    };
+    json!({
+        "text/plain": lines("[x] ", "[ ] ", "[>] "),
+        "text/markdown": lines("- [x] ", "- [ ] ", "- [ ] ▶ "),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use patchwork_eval::PlanEntry;
+
+    #[test]
+    fn test_connection_info_and_plan() {
+        let info = ConnectionInfo::from_json(
+            r#"{"transport": "tcp", "ip": "127.0.0.1", "shell_port": 5001, "iopub_port": 5002,
+                "stdin_port": 5003, "control_port": 5004, "hb_port": 5005, "key": "k",
+                "signature_scheme": "hmac-sha256"}"#,
+        )
+        .unwrap();
+        assert_eq!(info.endpoint(info.shell_port), "tcp://127.0.0.1:5001");
+        assert!(ConnectionInfo::from_json(r#"{"signature_scheme": "hmac-md5"}"#).is_err());
+
+        let plan = PlanUpdate {
+            entries: vec![
+                PlanEntry { content: "fetch".to_string(), status: PlanEntryStatus::Completed },
+                PlanEntry { content: "review".to_string(), status: PlanEntryStatus::InProgress },
+            ],
+        };
+        assert_eq!(render_plan(&plan)["text/plain"], "[x] fetch\n[>] review");
+    }
+}
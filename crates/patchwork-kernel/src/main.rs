@@ -0,0 +1,89 @@
+//! Jupyter kernel for Patchwork.
+//!
+//! Jupyter starts the kernel with a connection file naming the ports to
+//! listen on. Each notebook cell runs in one persistent interpreter, so
+//! variables and declarations carry over between cells; `print` output and
+//! plan updates stream to the notebook while a cell runs, and interrupting
+//! the kernel stops the cell before its next statement.
+//!
+//! `patchwork-kernel install` registers the kernel with Jupyter.
+
+mod kernel;
+mod wire;
+mod worker;
+
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+use std::process;
+
+use serde_json::json;
+
+use kernel::{ConnectionInfo, Kernel};
+
+const USAGE: &str = "\
+Usage:
+    patchwork-kernel <connection-file>
+    patchwork-kernel install
+
+`install` registers the kernel with Jupyter, in $JUPYTER_DATA_DIR if set.";
+
+/// Jupyter's per-user data directory.
+fn jupyter_data_dir() -> Option<PathBuf> {
+    if let Some(dir) = env::var_os("JUPYTER_DATA_DIR") {
+        return Some(PathBuf::from(dir));
+    }
+    if cfg!(windows) {
+        return env::var_os("APPDATA").map(|dir| PathBuf::from(dir).join("jupyter"));
+    }
+    let home = PathBuf::from(env::var_os("HOME")?);
+    if cfg!(target_os = "macos") {
+        return Some(home.join("Library").join("Jupyter"));
+    }
+    let data = env::var_os("XDG_DATA_HOME").map(PathBuf::from).unwrap_or_else(|| home.join(".local").join("share"));
+    Some(data.join("jupyter"))
+}
+
+/// Write the kernelspec that tells Jupyter how to start this kernel.
+fn install() -> Result<(), String> {
+    let exe = env::current_exe().map_err(|e| format!("Error finding the kernel executable: {}", e))?;
+    let dir = jupyter_data_dir()
+        .ok_or_else(|| "Can't find Jupyter's data directory; set $JUPYTER_DATA_DIR".to_string())?
+        .join("kernels")
+        .join("patchwork");
+    let spec = json!({
+        "argv": [exe, "{connection_file}"],
+        "display_name": "Patchwork",
+        "language": "patchwork",
+        "interrupt_mode": "message",
+    });
+    fs::create_dir_all(&dir).map_err(|e| format!("Error creating '{}': {}", dir.display(), e))?;
+    let path = dir.join("kernel.json");
+    fs::write(&path, format!("{:#}\n", spec)).map_err(|e| format!("Error writing '{}': {}", path.display(), e))?;
+    eprintln!("Installed the Patchwork kernel in {}", dir.display());
+    Ok(())
+}
+
+async fn serve(connection_file: &str) -> Result<(), String> {
+    let text = fs::read_to_string(connection_file)
+        .map_err(|e| format!("Error reading connection file '{}': {}", connection_file, e))?;
+    let info = ConnectionInfo::from_json(&text)?;
+    Kernel::bind(&info).await?.run().await
+}
+
+#[tokio::main]
+async fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let result = match args.as_slice() {
+        [command] if command == "install" => install(),
+        [file] if !file.starts_with('-') => serve(file).await,
+        _ => {
+            eprintln!("{}", USAGE);
+            process::exit(2);
+        }
+    };
+    if let Err(message) = result {
+        eprintln!("{}", message);
+        process::exit(1);
+    }
+}
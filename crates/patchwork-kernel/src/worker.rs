@@ -0,0 +1,154 @@
+//! The interpreter thread behind the kernel.
+//!
+//! Evaluation blocks, so a persistent `Interpreter` lives on its own thread
+//! and runs one cell at a time. The kernel polls it for the cell's printed
+//! lines and plan updates while it runs, and can interrupt it between
+//! statements.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::Arc;
+use std::thread;
+
+use patchwork_eval::{Error, Interpreter, OutputLine, PlanUpdate, Value};
+use patchwork_parser::literate::Cell;
+
+/// How a cell finished.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Outcome {
+    /// The cell's value, unless it was null
+    Value(Option<String>),
+    Error(String),
+}
+
+/// What a running cell produced since the last poll.
+#[derive(Debug, Default)]
+pub struct Progress {
+    /// Printed lines, and logged ones with their level: `[warn] ...`
+    pub printed: Vec<String>,
+    pub plans: Vec<PlanUpdate>,
+    /// Set once the cell has finished; everything it printed comes first
+    pub outcome: Option<Outcome>,
+}
+
+pub struct Worker {
+    cells: Sender<String>,
+    printed: Receiver<OutputLine>,
+    plans: Receiver<PlanUpdate>,
+    outcomes: Receiver<Outcome>,
+    interrupt: Arc<AtomicBool>,
+}
+
+impl Worker {
+    /// Start an interpreter thread.
+    pub fn spawn() -> Self {
+        let (cell_tx, cell_rx) = mpsc::channel::<String>();
+        let (print_tx, print_rx) = mpsc::channel();
+        let (plan_tx, plan_rx) = mpsc::channel();
+        let (outcome_tx, outcome_rx) = mpsc::channel();
+        let interrupt = Arc::new(AtomicBool::new(false));
+
+        let flag = interrupt.clone();
+        thread::spawn(move || {
+            let mut interpreter = Interpreter::new();
+            interpreter.set_output_sink(print_tx);
+            interpreter.set_plan_reporter(plan_tx);
+            interpreter.runtime_mut().set_interrupt_flag(Some(flag.clone()));
+            // Cells are typed in, but the files they import need trust
+            interpreter.require_trusted_modules();
+            for code in cell_rx {
+                // An interrupt that arrived between cells is stale
+                flag.store(false, Ordering::SeqCst);
+                // Cells are written like blocks of a literate program
+                let cell = Cell { code: &code, offset: 0 };
+                let outcome = match interpreter.eval_cell(&cell.program_text()) {
+                    Ok(Value::Null) => Outcome::Value(None),
+                    Ok(value) => Outcome::Value(Some(value.to_string_value())),
+                    // Point at the line the user typed, not the wrapped program's
+                    Err(Error::RuntimeAt { message, line, column }) => {
+                        Outcome::Error(Error::RuntimeAt { message, line: cell.code_line(line), column }.to_string())
+                    }
+                    Err(e) => Outcome::Error(e.to_string()),
+                };
+                if outcome_tx.send(outcome).is_err() {
+                    break;
+                }
+            }
+        });
+
+        Self { cells: cell_tx, printed: print_rx, plans: plan_rx, outcomes: outcome_rx, interrupt }
+    }
+
+    /// Start running a cell.
+    pub fn start(&self, code: &str) {
+        let _ = self.cells.send(code.to_string());
+    }
+
+    /// Stop the running cell before its next statement.
+    pub fn interrupt(&self) {
+        self.interrupt.store(true, Ordering::SeqCst);
+    }
+
+    /// Collect what the running cell has produced so far.
+    pub fn poll(&self) -> Progress {
+        // Check for the outcome first: the cell's output was sent before it,
+        // so draining afterwards can't miss any
+        let outcome = self.outcomes.try_recv().ok();
+        Progress {
+            printed: self.printed.try_iter().map(|line| line.to_string()).collect(),
+            plans: self.plans.try_iter().collect(),
+            outcome,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn run(worker: &Worker, code: &str) -> (Vec<String>, Outcome) {
+        worker.start(code);
+        let mut printed = Vec::new();
+        loop {
+            let progress = worker.poll();
+            printed.extend(progress.printed);
+            if let Some(outcome) = progress.outcome {
+                return (printed, outcome);
+            }
+            thread::sleep(Duration::from_millis(5));
+        }
+    }
+
+    #[test]
+    fn test_cells_share_a_session() {
+        let worker = Worker::spawn();
+        assert_eq!(run(&worker, "var greeting = \"hi\""), (Vec::new(), Outcome::Value(None)));
+        assert_eq!(
+            run(&worker, "print(greeting)\ngreeting + \"!\""),
+            (vec!["hi".to_string()], Outcome::Value(Some("hi!".to_string())))
+        );
+        let (_, outcome) = run(&worker, "missing");
+        assert_eq!(outcome, Outcome::Error("Runtime error at line 1, column 1: Undefined variable: missing".to_string()));
+    }
+
+    #[test]
+    fn test_interrupt_stops_the_cell() {
+        let worker = Worker::spawn();
+        worker.start("var n = 0\nprint(\"started\")\nwhile (true) {\n    n = n + 1\n}");
+        // Interrupt once the loop is underway
+        while worker.poll().printed.is_empty() {
+            thread::sleep(Duration::from_millis(5));
+        }
+        worker.interrupt();
+        let outcome = loop {
+            if let Some(outcome) = worker.poll().outcome {
+                break outcome;
+            }
+            thread::sleep(Duration::from_millis(5));
+        };
+        assert_eq!(outcome, Outcome::Error("Runtime error at line 3, column 1: Interrupted".to_string()));
+        // The session survives the interrupt
+        assert_eq!(run(&worker, "n > 0").1, Outcome::Value(Some("true".to_string())));
+    }
+}
@@ -9,6 +9,62 @@ mod lexer {
 // Re-export the main types
 pub use lexer::{Mode, Rule, LexData};
 
+/// A language edition. Later editions reserve more words as keywords, so
+/// programs that use those words as identifiers keep working until they
+/// opt in.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Edition {
+    #[default]
+    E2024,
+    E2025,
+}
+
+impl Edition {
+    pub const LATEST: Edition = Edition::E2025;
+
+    /// Parse an edition year such as `"2025"`.
+    pub fn parse(year: &str) -> Option<Self> {
+        match year {
+            "2024" => Some(Edition::E2024),
+            "2025" => Some(Edition::E2025),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Edition::E2024 => "2024",
+            Edition::E2025 => "2025",
+        }
+    }
+
+    /// Whether `word` is a keyword in this edition that earlier editions
+    /// lexed as an identifier.
+    pub fn reserves(self, word: &str) -> bool {
+        EDITION_KEYWORDS
+            .iter()
+            .any(|(keyword, since)| *keyword == word && self >= *since)
+    }
+}
+
+impl std::fmt::Display for Edition {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Keywords added after the first edition, with the edition that reserves
+/// them. Keywords every edition has are rules in `lexer.alex`.
+pub const EDITION_KEYWORDS: &[(&str, Edition)] = &[
+    ("match", Edition::E2025),
+    ("try", Edition::E2025),
+    ("catch", Edition::E2025),
+    ("finally", Edition::E2025),
+    ("continue", Edition::E2025),
+    ("example", Edition::E2025),
+    ("fragment", Edition::E2025),
+];
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 enum DelimiterType {
     Brace,  // Waiting for }
@@ -36,6 +92,14 @@ pub struct LexerContext {
     in_shell_mode: bool,
     /// Track if we should return to Shell mode after yielding current token
     return_to_shell: bool,
+    /// Track if we saw `think`, `ask`, `variant "name"`, `example name` or
+    /// `prompt fragment name`, whose next LBrace opens a prompt
+    pending_prompt: bool,
+    /// Parentheses open in a clause between `think` and its prompt, as in
+    /// `think(tools: [...]) {`
+    pending_parens: usize,
+    /// Which edition's keywords apply
+    edition: Edition,
 }
 
 impl LexerContext {
@@ -50,9 +114,21 @@ impl LexerContext {
             in_shell_interpolation: false,
             in_shell_mode: false,
             return_to_shell: false,
+            pending_prompt: false,
+            pending_parens: 0,
+            edition: Edition::default(),
         }
     }
 
+    /// A context for lexing source written for `edition`.
+    pub fn with_edition(edition: Edition) -> Self {
+        Self { edition, ..Self::new() }
+    }
+
+    pub fn edition(&self) -> Edition {
+        self.edition
+    }
+
     fn push_mode(&mut self, mode: Mode, delimiter: DelimiterType) {
         self.mode_stack.push(mode);
         self.depth_stack.push(1);
@@ -233,7 +309,7 @@ where
                 context.last_token = None;
                 return Ok(());
             }
-            Rule::Identifier if context.in_string_interpolation && context.last_token == Some(Rule::Dollar) => {
+            Rule::Identifier | Rule::Example | Rule::Fragment if context.in_string_interpolation && context.last_token == Some(Rule::Dollar) => {
                 // We're tokenizing an identifier directly after $ in a string (simple $id case)
                 // This is NOT ${...}, so return to InString mode after identifier
                 let span = lexer.span();
@@ -246,7 +322,7 @@ where
                 context.last_token = None;
                 return Ok(());
             }
-            Rule::Identifier if context.in_prompt_interpolation && context.last_token == Some(Rule::Dollar) => {
+            Rule::Identifier | Rule::Example | Rule::Fragment if context.in_prompt_interpolation && context.last_token == Some(Rule::Dollar) => {
                 // We're tokenizing an identifier directly after $ in a prompt (simple $id case)
                 // This is NOT ${...}, so return to Prompt mode after identifier
                 let span = lexer.span();
@@ -262,11 +338,34 @@ where
             Rule::Think | Rule::Ask => {
                 // When we see think/ask, record it. On next LBrace, transition to Prompt
                 context.last_token = Some(rule);
+                context.pending_prompt = true;
             }
             Rule::Do => {
                 // When we see do in Prompt state, record it. On next LBrace, transition to Code
                 context.last_token = Some(rule);
             }
+            Rule::Variant => {
+                // `variant "name" {` - the name is a string, so remember across it
+                // that the next LBrace opens a prompt
+                context.pending_prompt = true;
+            }
+            Rule::Example | Rule::Fragment => {
+                // `example name {` and `prompt fragment name {` open a prompt,
+                // but only where the word is a keyword
+                let word = if rule == Rule::Example { "example" } else { "fragment" };
+                context.pending_prompt = context.edition.reserves(word);
+                context.last_token = None;
+            }
+            Rule::Dot if context.last_token == Some(Rule::Think) => {
+                // `think.start {` opens a prompt too
+            }
+            Rule::Identifier | Rule::LBracket | Rule::RBracket | Rule::Comma | Rule::StringText
+                if context.pending_prompt =>
+            {
+                // The name in `example name {`, `fragment name {` or `variant "name" {`, or the list in
+                // `think with examples [a, b] {`, comes before the prompt opens
+                context.last_token = None;
+            }
             Rule::LBrace => {
                 // First yield the token
                 let span = lexer.span();
@@ -274,8 +373,9 @@ where
                 lexer.yield_token(token);
 
                 // Then check if this follows a context operator and transition states
+                let opens_prompt = context.pending_parens == 0 && std::mem::take(&mut context.pending_prompt);
                 match context.last_token {
-                    Some(Rule::Think) | Some(Rule::Ask) => {
+                    _ if opens_prompt && lexer.mode() == Mode::Code => {
                         // Transition Code -> Prompt
                         context.push_mode(Mode::Prompt, DelimiterType::Brace);
                         lexer.begin(Mode::Prompt);
@@ -333,6 +433,9 @@ where
             Rule::LParen if lexer.mode() == Mode::Code => {
                 // Track LParen to detect ($ pattern
                 context.last_token = Some(rule);
+                if context.pending_prompt {
+                    context.pending_parens += 1;
+                }
             }
             Rule::RParen if context.in_shell_mode && context.delimiter_stack.last() == Some(&DelimiterType::Paren) => {
                 // ) in shell mode with Paren delimiter → exit shell mode
@@ -468,9 +571,18 @@ where
             Rule::Whitespace | Rule::Newline => {
                 // Keep last token for whitespace - don't clear it
             }
+            Rule::RParen if context.pending_parens > 0 => {
+                context.pending_parens -= 1;
+                context.last_token = None;
+            }
+            _ if context.pending_parens > 0 => {
+                // Inside the clause of `think(tools: [...]) {`, before the prompt opens
+                context.last_token = None;
+            }
             _ => {
                 // Clear last token for any other token
                 context.last_token = None;
+                context.pending_prompt = false;
             }
         }
 
@@ -626,6 +738,24 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_duration_and_size_literals() -> Result<(), ParlexError> {
+        // Unit suffixes bind to the number; longest match picks mb over m
+        let tokens = collect_tokens("5s 200ms 2m 1h 10kb 2mb 7")?;
+
+        assert_eq!(tokens, vec![
+            Rule::Duration, Rule::Whitespace,
+            Rule::Duration, Rule::Whitespace,
+            Rule::Duration, Rule::Whitespace,
+            Rule::Duration, Rule::Whitespace,
+            Rule::Size, Rule::Whitespace,
+            Rule::Size, Rule::Whitespace,
+            Rule::Number,
+            Rule::End
+        ]);
+        Ok(())
+    }
+
     #[test]
     fn test_strings_chunked() -> Result<(), ParlexError> {
         let tokens = collect_tokens(r#""hello""#)?;
@@ -927,6 +1057,84 @@ var session_id = "historian-${timestamp}""#;
         Ok(())
     }
 
+    #[test]
+    fn test_think_variant_blocks() -> Result<(), ParlexError> {
+        let input = "think { Hi } variant \"v2\" { Hello }";
+        let tokens = collect_tokens(input)?;
+
+        assert_eq!(tokens, vec![
+            Rule::Think,
+            Rule::Whitespace,
+            Rule::LBrace,
+            Rule::Whitespace,
+            Rule::PromptText,  // "Hi"
+            Rule::Whitespace,
+            Rule::RBrace,
+            Rule::Whitespace,
+            Rule::Variant,
+            Rule::Whitespace,
+            Rule::StringStart,
+            Rule::StringText,  // "v2"
+            Rule::StringEnd,
+            Rule::Whitespace,
+            Rule::LBrace,
+            Rule::Whitespace,
+            Rule::PromptText,  // "Hello"
+            Rule::Whitespace,
+            Rule::RBrace,
+            Rule::End
+        ]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_think_with_examples() -> Result<(), ParlexError> {
+        let input = "think with examples [a, b] { Hi }";
+        let tokens = collect_tokens(input)?;
+
+        assert_eq!(tokens, vec![
+            Rule::Think,
+            Rule::Whitespace,
+            Rule::Identifier,  // "with"
+            Rule::Whitespace,
+            Rule::Identifier,  // "examples"
+            Rule::Whitespace,
+            Rule::LBracket,
+            Rule::Identifier,  // "a"
+            Rule::Comma,
+            Rule::Whitespace,
+            Rule::Identifier,  // "b"
+            Rule::RBracket,
+            Rule::Whitespace,
+            Rule::LBrace,
+            Rule::Whitespace,
+            Rule::PromptText,  // "Hi"
+            Rule::Whitespace,
+            Rule::RBrace,
+            Rule::End
+        ]);
+
+        // An example's body is prompt text once `example` is a keyword
+        let mut lexer = lex_str("example a { Hi }")?;
+        let mut context = LexerContext::with_edition(Edition::E2025);
+        let mut tokens = Vec::new();
+        while let Some(token) = lexer.try_next_with_context(&mut context)? {
+            tokens.push(token.rule);
+        }
+        assert!(tokens.contains(&Rule::PromptText));
+        assert!(!collect_tokens("example a { Hi }")?.contains(&Rule::PromptText));
+        Ok(())
+    }
+
+    #[test]
+    fn test_think_with_tools() -> Result<(), ParlexError> {
+        let tokens = collect_tokens(r#"think(tools: ["grep"]) { Hi }"#)?;
+        assert_eq!(tokens.iter().filter(|rule| **rule == Rule::PromptText).count(), 1);
+        assert!(!collect_tokens("f(think, x: 1) { Hi }")?.contains(&Rule::PromptText));
+        assert!(collect_tokens(r#"think.start(tools: ["grep"]) { Hi }"#)?.contains(&Rule::PromptText));
+        Ok(())
+    }
+
     #[test]
     fn test_nested_think_blocks() -> Result<(), ParlexError> {
         let input = "think { Outer do { think { Inner } } }";
@@ -0,0 +1,386 @@
+//! Opt-in "deep check" warnings, computed when a document is saved.
+//!
+//! These go beyond parse errors to point out code that will likely misbehave
+//! at run time:
+//!
+//! - a shell command interpolating a variable that isn't set where the
+//!   command runs, which fails the run with "Undefined variable"
+//! - a think or ask block that interpolates no variables, so the agent sees
+//!   nothing of the program's state
+//!
+//! Enable it with the `patchwork.deepCheck` setting. Findings are published
+//! with the parse diagnostics on save and cleared by the next edit, since
+//! their positions no longer match the text.
+
+use std::collections::HashSet;
+
+use patchwork_parser::ast::{
+    Block, CommandArg, Expr, Item, MatchPattern, Param, Pattern, Program, PromptBlock, PromptItem, Span, Spanned,
+    Statement, StringPart,
+};
+use patchwork_parser::ast_dump::item_name;
+use patchwork_parser::{parse, tokenize, ParserToken};
+use serde_json::Value as JsonValue;
+use tower_lsp::lsp_types::*;
+
+use crate::byte_offset_to_position;
+use crate::inlay_hints::children;
+
+/// Whether the client's settings turn the deep check on.
+pub fn deep_check_enabled(settings: &JsonValue) -> bool {
+    let settings = settings.get("patchwork").unwrap_or(settings);
+    settings.get("deepCheck").and_then(JsonValue::as_bool).unwrap_or(false)
+}
+
+/// Deep-check findings for a document, or none if it doesn't parse.
+pub fn compute_deep_check(text: &str) -> Vec<Diagnostic> {
+    let Ok(program) = parse(text) else {
+        return Vec::new();
+    };
+
+    let mut analyzer = Analyzer::new(text, &program);
+    analyzer.visit_program(&program);
+    let mut diagnostics = analyzer.diagnostics;
+
+    // Prompt nodes carry no positions, so match them to their keywords in
+    // source order, as inlay hints do
+    let keywords: Vec<(usize, usize)> = tokenize(text)
+        .map(|tokens| {
+            tokens
+                .into_iter()
+                .filter(|(_, tok, _)| matches!(tok, ParserToken::Think | ParserToken::Ask))
+                .map(|(start, _, end)| (start, end))
+                .collect()
+        })
+        .unwrap_or_default();
+    if keywords.len() == analyzer.prompt_count {
+        for (index, keyword) in analyzer.unbound_prompts {
+            let (start, end) = keywords[index];
+            diagnostics.push(finding(
+                text,
+                (start, end),
+                DiagnosticSeverity::HINT,
+                format!("This {} block has no bindings; the agent sees none of the program's variables", keyword),
+            ));
+        }
+    }
+
+    diagnostics.sort_by_key(|d| (d.range.start.line, d.range.start.character));
+    diagnostics
+}
+
+fn finding(text: &str, (start, end): (usize, usize), severity: DiagnosticSeverity, message: String) -> Diagnostic {
+    Diagnostic {
+        range: Range::new(byte_offset_to_position(text, start), byte_offset_to_position(text, end)),
+        severity: Some(severity),
+        source: Some("patchwork deep check".to_string()),
+        message,
+        ..Diagnostic::default()
+    }
+}
+
+struct Analyzer<'t> {
+    text: &'t str,
+    /// Names bound in each enclosing scope, innermost last.
+    scopes: Vec<HashSet<&'t str>>,
+    diagnostics: Vec<Diagnostic>,
+    /// Number of think/ask nodes seen so far, in source order.
+    prompt_count: usize,
+    /// Prompt nodes that interpolate nothing, with their keyword.
+    unbound_prompts: Vec<(usize, &'static str)>,
+}
+
+impl<'t> Analyzer<'t> {
+    fn new(text: &'t str, program: &'t Program<'t>) -> Self {
+        // Top-level declarations are visible everywhere
+        let mut globals: HashSet<&'t str> = program.items.iter().filter_map(|item| item_name(item)).collect();
+        globals.insert("self");
+        if program.items.iter().any(|item| matches!(&item.node, Item::Config(_))) {
+            globals.insert("config");
+        }
+        Self {
+            text,
+            scopes: vec![globals],
+            diagnostics: Vec::new(),
+            prompt_count: 0,
+            unbound_prompts: Vec::new(),
+        }
+    }
+
+    fn visit_program(&mut self, program: &'t Program<'t>) {
+        for item in &program.items {
+            match &item.node {
+                Item::Skill(skill) => self.visit_body(&skill.params, &skill.body),
+                Item::Worker(worker) => self.visit_body(&worker.params, &worker.body),
+                Item::Function(func) => self.visit_body(&func.params, &func.body),
+                Item::Trait(trait_decl) => {
+                    for method in &trait_decl.methods {
+                        self.visit_body(&method.params, &method.body);
+                    }
+                }
+                Item::Import(_) | Item::Type(_) | Item::Config(_) | Item::Example(_) | Item::Fragment(_) => {}
+            }
+        }
+    }
+
+    fn visit_body(&mut self, params: &'t [Spanned<Param<'t>>], body: &'t Block<'t>) {
+        self.scopes.push(params.iter().map(|param| &*param.name).collect());
+        for stmt in &body.statements {
+            self.visit_statement(stmt);
+        }
+        self.scopes.pop();
+    }
+
+    fn visit_block(&mut self, block: &'t Block<'t>) {
+        self.scopes.push(HashSet::new());
+        for stmt in &block.statements {
+            self.visit_statement(stmt);
+        }
+        self.scopes.pop();
+    }
+
+    fn visit_statement(&mut self, stmt: &'t Statement<'t>) {
+        match stmt {
+            Statement::VarDecl { pattern, init } => {
+                if let Some(init) = init {
+                    self.visit_expr(init);
+                }
+                self.bind(pattern);
+            }
+            Statement::ConstDecl { pattern, init } => {
+                self.visit_expr(init);
+                self.bind(pattern);
+            }
+            Statement::Expr(expr) | Statement::Return(Some(expr)) => self.visit_expr(expr),
+            Statement::If { condition, then_block, else_block } => {
+                self.visit_expr(condition);
+                self.visit_block(then_block);
+                if let Some(else_block) = else_block {
+                    self.visit_block(else_block);
+                }
+            }
+            Statement::ForIn { pattern, iter, body } => {
+                self.visit_expr(iter);
+                self.scopes.push(HashSet::new());
+                self.bind(pattern);
+                self.visit_block(body);
+                self.scopes.pop();
+            }
+            Statement::While { condition, body } => {
+                self.visit_expr(condition);
+                self.visit_block(body);
+            }
+            Statement::WhileVar { pattern, init, body } => {
+                self.visit_expr(init);
+                self.scopes.push(HashSet::new());
+                self.bind(pattern);
+                self.visit_block(body);
+                self.scopes.pop();
+            }
+            Statement::Timeout { limit, body } => {
+                self.visit_expr(limit);
+                self.visit_statement(body);
+            }
+            Statement::Try { body, catch, finally_block } => {
+                self.visit_block(body);
+                if let Some(catch) = catch {
+                    self.scopes.push(catch.var.as_deref().into_iter().collect());
+                    self.visit_block(&catch.body);
+                    self.scopes.pop();
+                }
+                if let Some(finally_block) = finally_block {
+                    self.visit_block(finally_block);
+                }
+            }
+            Statement::Return(None) | Statement::Succeed | Statement::Break | Statement::TypeDecl { .. } => {}
+        }
+    }
+
+    fn bind(&mut self, pattern: &'t Pattern<'t>) {
+        match pattern {
+            Pattern::Identifier { name, .. } => {
+                if let Some(scope) = self.scopes.last_mut() {
+                    scope.insert(name);
+                }
+            }
+            Pattern::Object(fields) => {
+                for field in fields {
+                    self.bind(&field.pattern);
+                }
+            }
+            Pattern::Array(items) => {
+                for item in items {
+                    self.bind(item);
+                }
+            }
+            Pattern::Ignore => {}
+        }
+    }
+
+    fn is_bound(&self, name: &str) -> bool {
+        self.scopes.iter().any(|scope| scope.contains(name))
+    }
+
+    fn visit_expr(&mut self, expr: &'t Expr<'t>) {
+        match expr {
+            Expr::Think(prompt) => self.visit_prompt(prompt, "think"),
+            Expr::Ask(prompt) => self.visit_prompt(prompt, "ask"),
+            Expr::Do(block) => self.visit_block(block),
+            Expr::Lambda { params, body } => {
+                self.scopes.push(params.iter().map(|param| &*param.name).collect());
+                self.visit_block(body);
+                self.scopes.pop();
+            }
+            Expr::Match { subject, arms } => {
+                self.visit_expr(subject);
+                for arm in arms {
+                    self.scopes.push(match &arm.pattern.node {
+                        MatchPattern::Type { name: Some(name), .. } | MatchPattern::Binding(name) => {
+                            HashSet::from([&**name])
+                        }
+                        _ => HashSet::new(),
+                    });
+                    self.visit_block(&arm.body);
+                    self.scopes.pop();
+                }
+            }
+            Expr::BareCommand { args, .. } => {
+                for arg in args {
+                    if let CommandArg::String(literal) = arg {
+                        let mut names = Vec::new();
+                        for part in &literal.parts {
+                            if let StringPart::Interpolation(inner) = part {
+                                references(inner, &mut names);
+                            }
+                        }
+                        self.check_set(&names);
+                    }
+                }
+                for child in children(expr) {
+                    self.visit_expr(child);
+                }
+            }
+            _ => {
+                for child in children(expr) {
+                    self.visit_expr(child);
+                }
+            }
+        }
+    }
+
+    /// Warn about each of a shell command's interpolated names that isn't set.
+    fn check_set(&mut self, names: &[(&'t str, Span)]) {
+        for &(name, span) in names {
+            if self.is_bound(name) || span == Span::default() {
+                continue;
+            }
+            self.diagnostics.push(finding(
+                self.text,
+                (span.start, span.end),
+                DiagnosticSeverity::WARNING,
+                format!("Shell command interpolates `{}`, which isn't set here", name),
+            ));
+        }
+    }
+
+    fn visit_prompt(&mut self, prompt: &'t PromptBlock<'t>, keyword: &'static str) {
+        let index = self.prompt_count;
+        self.prompt_count += 1;
+
+        let items = prompt.items.iter().chain(prompt.variants.iter().flat_map(|variant| &variant.items));
+        let bound = items.clone().any(|item| match item {
+            PromptItem::Interpolation(expr) => {
+                let mut names = Vec::new();
+                references(expr, &mut names);
+                !names.is_empty()
+            }
+            PromptItem::Code(_) => true,
+            PromptItem::Text(_) => false,
+        });
+        if !bound {
+            self.unbound_prompts.push((index, keyword));
+        }
+
+        if let Some(tools) = &prompt.tools {
+            self.visit_expr(tools);
+        }
+        for item in items {
+            match item {
+                PromptItem::Interpolation(expr) => self.visit_expr(expr),
+                PromptItem::Code(block) => self.visit_block(block),
+                PromptItem::Text(_) => {}
+            }
+        }
+        if let Some(validator) = &prompt.validator {
+            self.scopes.push(HashSet::from([&*validator.param]));
+            self.visit_block(&validator.body);
+            self.scopes.pop();
+        }
+    }
+}
+
+/// The variables an interpolated expression reads, and where each is read.
+fn references<'t>(expr: &'t Spanned<Expr<'t>>, names: &mut Vec<(&'t str, Span)>) {
+    match &expr.node {
+        Expr::Identifier(name) => names.push((name, expr.span)),
+        Expr::Object(fields) | Expr::Variant { fields, .. } => {
+            for field in fields {
+                match &field.value {
+                    Some(value) => references(value, names),
+                    None => names.push((&field.key, field.key.span)),
+                }
+            }
+        }
+        // The callee of a named call is a function, not a variable
+        Expr::Call { callee, args } => {
+            if !matches!(callee.node, Expr::Identifier(_)) {
+                references(callee, names);
+            }
+            for arg in args {
+                references(arg, names);
+            }
+        }
+        // Nested prompts, do blocks, and lambdas are checked on their own
+        Expr::Think(_) | Expr::Ask(_) | Expr::Do(_) | Expr::Lambda { .. } => {}
+        _ => {
+            for child in children(expr) {
+                references(child, names);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_deep_check_findings() {
+        let text = "skill main(dir) {\n    var name = \"x\"\n    $ mkdir \"${dir}/${nmae}\"\n    var a = think { Say hi }\n    var b = think { Greet $name }\n}\n";
+        let findings: Vec<_> = compute_deep_check(text)
+            .into_iter()
+            .map(|d| (d.range.start.line, d.range.start.character, d.severity.unwrap(), d.message))
+            .collect();
+        assert_eq!(
+            findings,
+            vec![
+                (
+                    2,
+                    22,
+                    DiagnosticSeverity::WARNING,
+                    "Shell command interpolates `nmae`, which isn't set here".to_string()
+                ),
+                (
+                    3,
+                    12,
+                    DiagnosticSeverity::HINT,
+                    "This think block has no bindings; the agent sees none of the program's variables".to_string()
+                ),
+            ]
+        );
+
+        let settings = serde_json::json!({ "patchwork": { "deepCheck": true } });
+        assert!(deep_check_enabled(&settings));
+        assert!(!deep_check_enabled(&serde_json::json!({})));
+    }
+}
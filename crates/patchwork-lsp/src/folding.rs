@@ -0,0 +1,137 @@
+//! Folding ranges for blocks, prompts, and multi-line literals.
+//!
+//! Ranges come from the parser's token stream rather than a character scan:
+//! braces inside strings never become tokens, and braces that are literal
+//! prompt text are told apart from real blocks by the token that opens them.
+
+use patchwork_parser::{tokenize, ParserToken};
+use tower_lsp::lsp_types::*;
+
+use crate::byte_offset_to_position;
+
+/// What an open bracket delimits.
+#[derive(Clone, Copy, PartialEq)]
+enum Delimiter {
+    /// A code block or object literal.
+    Code,
+    /// The body of a `think` or `ask` block.
+    Prompt,
+    /// An `${expr}` interpolation inside a prompt or string.
+    Interpolation,
+    /// Literal braces written as part of prompt text.
+    PromptText,
+    /// An array literal or type.
+    Bracket,
+}
+
+impl Delimiter {
+    fn folds(self) -> bool {
+        !matches!(self, Delimiter::Interpolation | Delimiter::PromptText)
+    }
+}
+
+/// Compute the folding ranges for a document.
+pub fn compute_folding_ranges(text: &str) -> Vec<FoldingRange> {
+    let Ok(tokens) = tokenize(text) else {
+        return Vec::new();
+    };
+
+    let mut ranges = Vec::new();
+    let mut stack: Vec<(Delimiter, usize)> = Vec::new();
+    let mut prev: Option<&ParserToken> = None;
+    // `variant "name" {`, `example name {`, `fragment name {` and `think with examples [a] {`
+    // open a prompt, with more tokens between keyword and brace
+    let mut pending_prompt = false;
+
+    for (start, token, _) in &tokens {
+        match token {
+            ParserToken::LBrace => {
+                let in_prompt = matches!(
+                    stack.last(),
+                    Some((Delimiter::Prompt | Delimiter::PromptText, _))
+                );
+                let delimiter = match prev {
+                    _ if std::mem::take(&mut pending_prompt) => Delimiter::Prompt,
+                    Some(ParserToken::Dollar) => Delimiter::Interpolation,
+                    Some(ParserToken::Do) => Delimiter::Code,
+                    _ if in_prompt => Delimiter::PromptText,
+                    _ => Delimiter::Code,
+                };
+                stack.push((delimiter, *start));
+            }
+            ParserToken::Think | ParserToken::Ask | ParserToken::Variant | ParserToken::Example | ParserToken::Fragment => {
+                pending_prompt = true
+            }
+            ParserToken::LBracket => stack.push((Delimiter::Bracket, *start)),
+            ParserToken::RBrace | ParserToken::RBracket => {
+                let closes_bracket = matches!(token, ParserToken::RBracket);
+                // On unbalanced input, leave the stack alone
+                if let Some(&(delimiter, open)) = stack.last() {
+                    if (delimiter == Delimiter::Bracket) == closes_bracket {
+                        stack.pop();
+                        if delimiter.folds() {
+                            push_range(text, open, *start, &mut ranges);
+                        }
+                    }
+                }
+            }
+            ParserToken::Identifier(_)
+            | ParserToken::Comma
+            | ParserToken::StringStart
+            | ParserToken::StringText(_)
+            | ParserToken::StringEnd
+            | ParserToken::Newline(_)
+            | ParserToken::Whitespace(_) => {}
+            _ => pending_prompt = false,
+        }
+        if !matches!(token, ParserToken::Newline(_) | ParserToken::Whitespace(_)) {
+            prev = Some(token);
+        }
+    }
+
+    ranges.sort_by_key(|range| (range.start_line, range.end_line));
+    ranges
+}
+
+/// Fold from the line of `open` through the line before `close`, keeping the
+/// closing delimiter visible.
+fn push_range(text: &str, open: usize, close: usize, ranges: &mut Vec<FoldingRange>) {
+    let start_line = byte_offset_to_position(text, open).line;
+    let close_line = byte_offset_to_position(text, close).line;
+    if close_line <= start_line + 1 {
+        return;
+    }
+    ranges.push(FoldingRange {
+        start_line,
+        start_character: None,
+        end_line: close_line - 1,
+        end_character: None,
+        kind: Some(FoldingRangeKind::Region),
+        collapsed_text: None,
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lines(text: &str) -> Vec<(u32, u32)> {
+        compute_folding_ranges(text)
+            .into_iter()
+            .map(|range| (range.start_line, range.end_line))
+            .collect()
+    }
+
+    #[test]
+    fn test_folds_blocks_and_prompts() {
+        let text = "skill main() {\n    var plan = think {\n        Write a plan.\n        Use the format {\n            steps: list\n        }\n    }\n}\n";
+        // The literal braces in the prompt text don't fold on their own
+        assert_eq!(lines(text), vec![(0, 6), (1, 5)]);
+    }
+
+    #[test]
+    fn test_ignores_braces_in_strings() {
+        let text = "skill main() {\n    var s = \"{\"\n    var items = [\n        1,\n        2\n    ]\n}\n";
+        assert_eq!(lines(text), vec![(0, 5), (2, 4)]);
+    }
+}
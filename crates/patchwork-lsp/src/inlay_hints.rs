@@ -0,0 +1,545 @@
+//! Inlay hints for inferred variable types and prompt bindings.
+//!
+//! The AST doesn't record spans, but identifiers borrow directly from the
+//! document text, so their positions are recovered from the slice addresses.
+//! Prompt keywords are matched to their `think`/`ask` nodes by source order
+//! using the parser's token stream.
+
+use std::collections::HashMap;
+
+use patchwork_eval::{FieldType, Type};
+use patchwork_parser::ast::{
+    BinOp, Block, CommandArg, Expr, Item, MatchPattern, Pattern, Program, PromptBlock, PromptItem, Span, Spanned,
+    Statement, StringPart, UnOp,
+};
+use patchwork_parser::{parse, tokenize, ParserToken};
+use tower_lsp::lsp_types::*;
+
+use crate::byte_offset_to_position;
+
+/// Compute inlay hints for a document, limited to `range`.
+pub fn compute_inlay_hints(text: &str, range: Range) -> Vec<InlayHint> {
+    let Ok(program) = parse(text) else {
+        return Vec::new();
+    };
+
+    let mut collector = HintCollector::new(text);
+    collector.visit_program(&program);
+
+    let mut hints = collector.type_hints;
+
+    // Only trust the keyword positions if every prompt node found its keyword
+    let keyword_ends: Vec<usize> = tokenize(text)
+        .map(|tokens| {
+            tokens
+                .into_iter()
+                .filter(|(_, tok, _)| matches!(tok, ParserToken::Think | ParserToken::Ask))
+                .map(|(_, _, end)| end)
+                .collect()
+        })
+        .unwrap_or_default();
+    if keyword_ends.len() == collector.prompt_count {
+        for (index, bindings) in collector.prompt_bindings {
+            hints.push(InlayHint {
+                position: byte_offset_to_position(text, keyword_ends[index]),
+                label: InlayHintLabel::String(format!("uses: {}", bindings.join(", "))),
+                kind: None,
+                text_edits: None,
+                tooltip: Some(InlayHintTooltip::String(
+                    "Variables this prompt interpolates".to_string(),
+                )),
+                padding_left: Some(true),
+                padding_right: None,
+                data: None,
+            });
+        }
+    }
+
+    hints.retain(|hint| range.start <= hint.position && hint.position <= range.end);
+    hints.sort_by_key(|hint| (hint.position.line, hint.position.character));
+    hints
+}
+
+struct HintCollector<'t> {
+    text: &'t str,
+    /// Inferred types of variables in scope, innermost last.
+    scopes: Vec<HashMap<String, Type>>,
+    type_hints: Vec<InlayHint>,
+    /// Number of think/ask nodes seen so far, in source order.
+    prompt_count: usize,
+    /// Interpolated variables for each prompt node that has any.
+    prompt_bindings: Vec<(usize, Vec<String>)>,
+}
+
+impl<'t> HintCollector<'t> {
+    fn new(text: &'t str) -> Self {
+        Self {
+            text,
+            scopes: vec![HashMap::new()],
+            type_hints: Vec::new(),
+            prompt_count: 0,
+            prompt_bindings: Vec::new(),
+        }
+    }
+
+    fn visit_program(&mut self, program: &Program) {
+        for item in &program.items {
+            match &item.node {
+                Item::Skill(skill) => self.visit_body(&skill.params, &skill.body),
+                Item::Worker(worker) => self.visit_body(&worker.params, &worker.body),
+                Item::Function(func) => self.visit_body(&func.params, &func.body),
+                Item::Trait(trait_decl) => {
+                    for method in &trait_decl.methods {
+                        self.visit_body(&method.params, &method.body);
+                    }
+                }
+                Item::Import(_) | Item::Type(_) | Item::Config(_) | Item::Example(_) | Item::Fragment(_) => {}
+            }
+        }
+    }
+
+    fn visit_body(&mut self, params: &[Spanned<patchwork_parser::ast::Param>], body: &Block) {
+        self.scopes.push(HashMap::new());
+        for param in params {
+            let ty = param.type_ann.as_deref().map(Type::from_expr).unwrap_or(Type::Any);
+            self.define(&param.name, ty);
+        }
+        self.visit_block(body);
+        self.scopes.pop();
+    }
+
+    fn visit_block(&mut self, block: &Block) {
+        self.scopes.push(HashMap::new());
+        for stmt in &block.statements {
+            self.visit_statement(stmt);
+        }
+        self.scopes.pop();
+    }
+
+    fn visit_statement(&mut self, stmt: &Statement) {
+        match stmt {
+            Statement::VarDecl { pattern, init } => {
+                let ty = match init {
+                    Some(expr) => {
+                        self.visit_expr(expr);
+                        self.infer(expr)
+                    }
+                    None => Type::Any,
+                };
+                self.bind_pattern(pattern, ty);
+            }
+            Statement::ConstDecl { pattern, init } => {
+                self.visit_expr(init);
+                let ty = self.infer(init);
+                self.bind_pattern(pattern, ty);
+            }
+            Statement::Expr(expr) | Statement::Return(Some(expr)) => self.visit_expr(expr),
+            Statement::If { condition, then_block, else_block } => {
+                self.visit_expr(condition);
+                self.visit_block(then_block);
+                if let Some(else_block) = else_block {
+                    self.visit_block(else_block);
+                }
+            }
+            Statement::ForIn { pattern, iter, body } => {
+                self.visit_expr(iter);
+                let element = match self.infer(iter) {
+                    Type::Array(element) => *element,
+                    _ => Type::Any,
+                };
+                self.scopes.push(HashMap::new());
+                self.bind_pattern(pattern, element);
+                self.visit_block(body);
+                self.scopes.pop();
+            }
+            Statement::While { condition, body } => {
+                self.visit_expr(condition);
+                self.visit_block(body);
+            }
+            Statement::WhileVar { pattern, init, body } => {
+                self.visit_expr(init);
+                let ty = self.infer(init);
+                self.scopes.push(HashMap::new());
+                self.bind_pattern(pattern, ty);
+                self.visit_block(body);
+                self.scopes.pop();
+            }
+            Statement::Timeout { limit, body } => {
+                self.visit_expr(limit);
+                self.visit_statement(body);
+            }
+            Statement::Try { body, catch, finally_block } => {
+                self.visit_block(body);
+                if let Some(catch) = catch {
+                    // Anything can be thrown, so the caught value has no type to show
+                    self.scopes.push(HashMap::new());
+                    if let Some(var) = &catch.var {
+                        self.define(var, Type::Any);
+                    }
+                    self.visit_block(&catch.body);
+                    self.scopes.pop();
+                }
+                if let Some(finally_block) = finally_block {
+                    self.visit_block(finally_block);
+                }
+            }
+            Statement::Return(None)
+            | Statement::Succeed
+            | Statement::Break
+            | Statement::TypeDecl { .. } => {}
+        }
+    }
+
+    fn visit_expr(&mut self, expr: &Expr) {
+        match expr {
+            Expr::Think(prompt) | Expr::Ask(prompt) => self.visit_prompt(prompt),
+            Expr::Do(block) => self.visit_block(block),
+            Expr::Lambda { params, body } => self.visit_body(params, body),
+            Expr::Match { subject, arms } => {
+                self.visit_expr(subject);
+                let subject_ty = self.infer(subject);
+                for arm in arms {
+                    self.scopes.push(HashMap::new());
+                    match &arm.pattern.node {
+                        MatchPattern::Type { name: Some(name), type_expr } => {
+                            self.define(name, Type::from_expr(type_expr));
+                        }
+                        MatchPattern::Binding(name) => {
+                            self.hint_binding(arm.pattern.span, &subject_ty);
+                            self.define(name, subject_ty.clone());
+                        }
+                        _ => {}
+                    }
+                    self.visit_block(&arm.body);
+                    self.scopes.pop();
+                }
+            }
+            _ => {
+                for child in children(expr) {
+                    self.visit_expr(child);
+                }
+            }
+        }
+    }
+
+    fn visit_prompt(&mut self, prompt: &PromptBlock) {
+        let index = self.prompt_count;
+        self.prompt_count += 1;
+
+        let mut bindings = Vec::new();
+        for item in &prompt.items {
+            if let PromptItem::Interpolation(expr) = item {
+                collect_references(expr, &mut bindings);
+            }
+        }
+        if !bindings.is_empty() {
+            self.prompt_bindings.push((index, bindings));
+        }
+
+        // Visit in source order so nested prompts are numbered after this one
+        if let Some(tools) = &prompt.tools {
+            self.visit_expr(tools);
+        }
+        for item in &prompt.items {
+            match item {
+                PromptItem::Interpolation(expr) => self.visit_expr(expr),
+                PromptItem::Code(block) => self.visit_block(block),
+                PromptItem::Text(_) => {}
+            }
+        }
+        if let Some(validator) = &prompt.validator {
+            self.scopes.push(HashMap::new());
+            self.define(&validator.param, Type::Any);
+            self.visit_block(&validator.body);
+            self.scopes.pop();
+        }
+    }
+
+    /// Bind the names in a pattern, hinting unannotated identifiers.
+    fn bind_pattern(&mut self, pattern: &Spanned<Pattern>, ty: Type) {
+        match &pattern.node {
+            Pattern::Identifier { name, type_ann: Some(type_ann) } => {
+                self.define(name, Type::from_expr(type_ann));
+            }
+            Pattern::Identifier { name, type_ann: None } => {
+                self.hint_binding(pattern.span, &ty);
+                self.define(name, ty);
+            }
+            Pattern::Ignore => {}
+            Pattern::Object(fields) => {
+                for field in fields {
+                    let field_ty = match (&field.type_ann, &ty) {
+                        (Some(type_ann), _) => Type::from_expr(type_ann),
+                        (None, Type::Object(types)) => types
+                            .iter()
+                            .find(|f| f.key == field.key)
+                            .map(|f| f.ty.clone())
+                            .unwrap_or(Type::Any),
+                        _ => Type::Any,
+                    };
+                    self.bind_pattern(&field.pattern, field_ty);
+                }
+            }
+            Pattern::Array(patterns) => {
+                let element = match &ty {
+                    Type::Array(element) => (**element).clone(),
+                    _ => Type::Any,
+                };
+                for pattern in patterns {
+                    self.bind_pattern(pattern, element.clone());
+                }
+            }
+        }
+    }
+
+    /// Hint the type of the binding written at `span`.
+    fn hint_binding(&mut self, span: Span, ty: &Type) {
+        // Bindings built outside the parser have nowhere to show a hint
+        if *ty == Type::Any || span == Span::default() {
+            return;
+        }
+        self.type_hints.push(InlayHint {
+            position: byte_offset_to_position(self.text, span.end),
+            label: InlayHintLabel::String(format!(": {ty}")),
+            kind: Some(InlayHintKind::TYPE),
+            text_edits: None,
+            tooltip: None,
+            padding_left: None,
+            padding_right: None,
+            data: None,
+        });
+    }
+
+    fn define(&mut self, name: &str, ty: Type) {
+        if let Some(scope) = self.scopes.last_mut() {
+            scope.insert(name.to_string(), ty);
+        }
+    }
+
+    fn lookup(&self, name: &str) -> Type {
+        self.scopes
+            .iter()
+            .rev()
+            .find_map(|scope| scope.get(name).cloned())
+            .unwrap_or(Type::Any)
+    }
+
+    /// Infer the type of an expression, or `Type::Any` when it can't be known.
+    fn infer(&self, expr: &Expr) -> Type {
+        match expr {
+            Expr::Identifier(name) => self.lookup(name),
+            Expr::Number(_) => Type::Number,
+            Expr::Duration(_) => Type::Duration,
+            Expr::Size(_) => Type::Size,
+            Expr::String(_) | Expr::CommandSubst(_) => Type::String,
+            Expr::True | Expr::False => Type::Boolean,
+            Expr::Think(_) | Expr::Ask(_) => Type::String,
+            Expr::Array(elements) => {
+                let mut types = elements.iter().map(|e| self.infer(e));
+                let Some(first) = types.next() else {
+                    return Type::Any;
+                };
+                if types.all(|ty| ty == first) {
+                    Type::Array(Box::new(first))
+                } else {
+                    Type::Array(Box::new(Type::Any))
+                }
+            }
+            Expr::Object(fields) => Type::Object(self.infer_fields(fields)),
+            Expr::Variant { tag, fields } => Type::Variant {
+                tag: tag.to_string(),
+                fields: self.infer_fields(fields),
+            },
+            Expr::Binary { op, left, right } => match op {
+                BinOp::Eq | BinOp::NotEq | BinOp::Lt | BinOp::Gt | BinOp::And | BinOp::Or => {
+                    Type::Boolean
+                }
+                BinOp::Add | BinOp::Sub | BinOp::Mul | BinOp::Div => {
+                    infer_arithmetic(op, self.infer(left), self.infer(right))
+                }
+                _ => Type::Any,
+            },
+            Expr::Unary { op: UnOp::Not, .. } => Type::Boolean,
+            Expr::Unary { op: UnOp::Neg, operand } => match self.infer(operand) {
+                ty @ (Type::Number | Type::Duration | Type::Size) => ty,
+                _ => Type::Any,
+            },
+            Expr::Member { object, field } => match self.infer(object) {
+                Type::Object(fields) | Type::Variant { fields, .. } => fields
+                    .into_iter()
+                    .find(|f| f.key == *field)
+                    .map(|f| f.ty)
+                    .unwrap_or(Type::Any),
+                _ => Type::Any,
+            },
+            Expr::Index { object, .. } => match self.infer(object) {
+                Type::Array(element) => *element,
+                _ => Type::Any,
+            },
+            Expr::Paren(inner) => self.infer(inner),
+            _ => Type::Any,
+        }
+    }
+
+    fn infer_fields(&self, fields: &[patchwork_parser::ast::ObjectField]) -> Vec<FieldType> {
+        fields
+            .iter()
+            .map(|field| FieldType {
+                key: field.key.to_string(),
+                ty: match &field.value {
+                    Some(value) => self.infer(value),
+                    None => self.lookup(&field.key),
+                },
+                optional: false,
+            })
+            .collect()
+    }
+}
+
+fn infer_arithmetic(op: &BinOp, left: Type, right: Type) -> Type {
+    match (op, left, right) {
+        (BinOp::Add, Type::String, _) | (BinOp::Add, _, Type::String) => Type::String,
+        (_, Type::Number, Type::Number) => Type::Number,
+        (BinOp::Add | BinOp::Sub, Type::Duration, Type::Duration) => Type::Duration,
+        (BinOp::Add | BinOp::Sub, Type::Size, Type::Size) => Type::Size,
+        (BinOp::Mul | BinOp::Div, ty @ (Type::Duration | Type::Size), Type::Number) => ty,
+        _ => Type::Any,
+    }
+}
+
+/// The direct subexpressions of an expression, in source order.
+///
+/// Prompt and do blocks, match arms, and lambda bodies contain statements
+/// rather than expressions, so callers handle them separately.
+pub(crate) fn children<'a, 'i>(expr: &'a Expr<'i>) -> Vec<&'a Spanned<Expr<'i>>> {
+    match expr {
+        Expr::Array(elements) => elements.iter().collect(),
+        Expr::Object(fields) | Expr::Variant { fields, .. } => {
+            fields.iter().filter_map(|f| f.value.as_ref()).collect()
+        }
+        Expr::Binary { left, right, .. }
+        | Expr::ShellPipe { left, right }
+        | Expr::ShellAnd { left, right }
+        | Expr::ShellOr { left, right } => vec![left, right],
+        Expr::ShellRedirect { command, target, .. } => vec![command, target],
+        Expr::Unary { operand: inner, .. }
+        | Expr::Member { object: inner, .. }
+        | Expr::PostIncrement(inner)
+        | Expr::PostDecrement(inner)
+        | Expr::Paren(inner)
+        | Expr::Await(inner)
+        | Expr::AwaitAll(inner)
+        | Expr::CommandSubst(inner) => vec![inner],
+        Expr::Index { object, index } => vec![object, index],
+        Expr::Match { subject, .. } => vec![subject],
+        Expr::Call { callee, args } => std::iter::once(&**callee).chain(args).collect(),
+        Expr::String(literal) => string_children(&literal.parts),
+        Expr::BareCommand { args, .. } => args
+            .iter()
+            .flat_map(|arg| match arg {
+                CommandArg::String(literal) => string_children(&literal.parts),
+                CommandArg::Literal(_) => Vec::new(),
+            })
+            .collect(),
+        Expr::Identifier(_)
+        | Expr::Number(_)
+        | Expr::Duration(_)
+        | Expr::Size(_)
+        | Expr::True
+        | Expr::False
+        | Expr::Think(_)
+        | Expr::Ask(_)
+        | Expr::Do(_)
+        | Expr::Lambda { .. } => Vec::new(),
+    }
+}
+
+fn string_children<'a, 'i>(parts: &'a [StringPart<'i>]) -> Vec<&'a Spanned<Expr<'i>>> {
+    parts
+        .iter()
+        .filter_map(|part| match part {
+            StringPart::Interpolation(expr) => Some(&**expr),
+            StringPart::Text(_) => None,
+        })
+        .collect()
+}
+
+/// Collect the variables an interpolated expression reads, without duplicates.
+fn collect_references(expr: &Expr, names: &mut Vec<String>) {
+    match expr {
+        Expr::Identifier(name) => push_unique(names, name),
+        Expr::Object(fields) | Expr::Variant { fields, .. } => {
+            for field in fields {
+                match &field.value {
+                    Some(value) => collect_references(value, names),
+                    None => push_unique(names, &field.key),
+                }
+            }
+        }
+        // The callee of a named call is a function, not a captured variable
+        Expr::Call { callee, args } => {
+            if !matches!(callee.node, Expr::Identifier(_)) {
+                collect_references(callee, names);
+            }
+            for arg in args {
+                collect_references(arg, names);
+            }
+        }
+        // Nested prompts, do blocks, and lambdas report their own bindings
+        Expr::Think(_) | Expr::Ask(_) | Expr::Do(_) | Expr::Lambda { .. } => {}
+        _ => {
+            for child in children(expr) {
+                collect_references(child, names);
+            }
+        }
+    }
+}
+
+fn push_unique(names: &mut Vec<String>, name: &str) {
+    if !names.iter().any(|n| n == name) {
+        names.push(name.to_string());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn labels(text: &str) -> Vec<(u32, u32, String)> {
+        let everything = Range::new(Position::new(0, 0), Position::new(u32::MAX, 0));
+        compute_inlay_hints(text, everything)
+            .into_iter()
+            .map(|hint| {
+                let InlayHintLabel::String(label) = hint.label else {
+                    panic!("Expected string label");
+                };
+                (hint.position.line, hint.position.character, label)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_inferred_type_hints() {
+        let text = "skill main() {\n    var count = 3\n    var names = [\"a\", \"b\"]\n    for var name in names {\n        var delay = 5s\n    }\n}\n";
+        assert_eq!(
+            labels(text),
+            vec![
+                (1, 13, ": number".to_string()),
+                (2, 13, ": [string]".to_string()),
+                (3, 16, ": string".to_string()),
+                (4, 17, ": duration".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_prompt_binding_hints() {
+        let text = "skill main(diff) {\n    var title = \"x\"\n    var summary: string = think {\n        Summarize $diff for ${title}.\n    }\n}\n";
+        let hints = labels(text);
+        assert!(
+            hints.contains(&(2, 31, "uses: diff, title".to_string())),
+            "unexpected hints: {:?}",
+            hints
+        );
+    }
+}
@@ -1,3 +1,19 @@
+mod deep_check;
+mod folding;
+mod inlay_hints;
+mod on_type_formatting;
+mod runner;
+mod signature_help;
+
+use deep_check::{compute_deep_check, deep_check_enabled};
+use folding::compute_folding_ranges;
+use inlay_hints::compute_inlay_hints;
+use on_type_formatting::compute_on_type_edits;
+use runner::{RunProfile, RunRequest, RUN_FILE, RUN_SELECTION};
+use signature_help::compute_signature_help;
+use patchwork_eval::{LogLevel, Type};
+use patchwork_parser::ast::{Block, Item, Program, Statement};
+use patchwork_parser::check::check;
 use patchwork_parser::parse;
 use patchwork_parser::ParseError;
 use regex::Regex;
@@ -12,6 +28,10 @@ use tower_lsp::{Client, LanguageServer, LspService, Server};
 struct Backend {
     client: Client,
     documents: Arc<RwLock<HashMap<Url, String>>>,
+    /// Settings for `patchwork.runFile` and `patchwork.runSelection`.
+    run_profile: Arc<RwLock<RunProfile>>,
+    /// Whether saving a document runs the deep check.
+    deep_check: Arc<RwLock<bool>>,
 }
 
 impl Backend {
@@ -19,9 +39,20 @@ impl Backend {
         Self {
             client,
             documents: Arc::new(RwLock::new(HashMap::new())),
+            run_profile: Arc::new(RwLock::new(RunProfile::default())),
+            deep_check: Arc::new(RwLock::new(false)),
         }
     }
 
+    async fn report_progress(&self, token: &NumberOrString, progress: WorkDoneProgress) {
+        self.client
+            .send_notification::<notification::Progress>(ProgressParams {
+                token: token.clone(),
+                value: ProgressParamsValue::WorkDone(progress),
+            })
+            .await;
+    }
+
     async fn publish_diagnostics(&self, uri: Url, text: String) {
         let diagnostics = compute_diagnostics(&text);
         let _ = self
@@ -33,14 +64,37 @@ impl Backend {
 
 #[tower_lsp::async_trait]
 impl LanguageServer for Backend {
-    async fn initialize(&self, _: InitializeParams) -> tower_lsp::jsonrpc::Result<InitializeResult> {
+    async fn initialize(&self, params: InitializeParams) -> tower_lsp::jsonrpc::Result<InitializeResult> {
+        if let Some(options) = &params.initialization_options {
+            *self.run_profile.write().await = RunProfile::from_settings(options);
+            *self.deep_check.write().await = deep_check_enabled(options);
+        }
+
         Ok(InitializeResult {
             capabilities: ServerCapabilities {
-                text_document_sync: Some(TextDocumentSyncCapability::Kind(
-                    TextDocumentSyncKind::FULL,
-                )),
+                text_document_sync: Some(TextDocumentSyncCapability::Options(TextDocumentSyncOptions {
+                    open_close: Some(true),
+                    change: Some(TextDocumentSyncKind::FULL),
+                    save: Some(TextDocumentSyncSaveOptions::Supported(true)),
+                    ..TextDocumentSyncOptions::default()
+                })),
                 hover_provider: Some(HoverProviderCapability::Simple(true)),
                 completion_provider: Some(CompletionOptions::default()),
+                inlay_hint_provider: Some(OneOf::Left(true)),
+                folding_range_provider: Some(FoldingRangeProviderCapability::Simple(true)),
+                document_on_type_formatting_provider: Some(DocumentOnTypeFormattingOptions {
+                    first_trigger_character: "\n".to_string(),
+                    more_trigger_character: None,
+                }),
+                execute_command_provider: Some(ExecuteCommandOptions {
+                    commands: vec![RUN_FILE.to_string(), RUN_SELECTION.to_string()],
+                    work_done_progress_options: WorkDoneProgressOptions::default(),
+                }),
+                signature_help_provider: Some(SignatureHelpOptions {
+                    trigger_characters: Some(vec!["(".to_string(), ",".to_string()]),
+                    retrigger_characters: None,
+                    work_done_progress_options: WorkDoneProgressOptions::default(),
+                }),
                 ..ServerCapabilities::default()
             },
             server_info: Some(ServerInfo {
@@ -82,6 +136,115 @@ impl LanguageServer for Backend {
         self.publish_diagnostics(uri, text).await;
     }
 
+    async fn did_save(&self, params: DidSaveTextDocumentParams) {
+        if !*self.deep_check.read().await {
+            return;
+        }
+        let uri = params.text_document.uri;
+        let Some(text) = self.documents.read().await.get(&uri).cloned() else {
+            return;
+        };
+        let mut diagnostics = compute_diagnostics(&text);
+        diagnostics.extend(compute_deep_check(&text));
+        let _ = self.client.publish_diagnostics(uri, diagnostics, None).await;
+    }
+
+    async fn did_change_configuration(&self, params: DidChangeConfigurationParams) {
+        *self.run_profile.write().await = RunProfile::from_settings(&params.settings);
+        *self.deep_check.write().await = deep_check_enabled(&params.settings);
+    }
+
+    async fn execute_command(
+        &self,
+        params: ExecuteCommandParams,
+    ) -> tower_lsp::jsonrpc::Result<Option<serde_json::Value>> {
+        let request = RunRequest::from_arguments(&params.command, &params.arguments)
+            .map_err(tower_lsp::jsonrpc::Error::invalid_params)?;
+        let code = {
+            let docs = self.documents.read().await;
+            let Some(text) = docs.get(&request.uri) else {
+                return Err(tower_lsp::jsonrpc::Error::invalid_params(format!(
+                    "Document not open: {}",
+                    request.uri
+                )));
+            };
+            request.code(text)
+        };
+        let profile = self.run_profile.read().await.clone();
+        let default_dir = request
+            .uri
+            .to_file_path()
+            .ok()
+            .and_then(|path| path.parent().map(|dir| dir.to_path_buf()));
+
+        let token = NumberOrString::String(format!("patchwork-run-{}", request.uri));
+        let progress = self
+            .client
+            .send_request::<request::WorkDoneProgressCreate>(WorkDoneProgressCreateParams {
+                token: token.clone(),
+            })
+            .await
+            .is_ok();
+        if progress {
+            self.report_progress(&token, WorkDoneProgress::Begin(WorkDoneProgressBegin {
+                title: format!("Running {}", request.uri.path()),
+                ..WorkDoneProgressBegin::default()
+            }))
+            .await;
+        }
+
+        // Stream printed and logged lines back as they happen, logs at their level
+        let backend = self.clone();
+        let stream_token = token.clone();
+        let handle = tokio::runtime::Handle::current();
+        let result = tokio::task::spawn_blocking(move || {
+            runner::run(code, profile, default_dir, |output| {
+                let kind = match output.level {
+                    None | Some(LogLevel::Debug) => MessageType::LOG,
+                    Some(LogLevel::Info) => MessageType::INFO,
+                    Some(LogLevel::Warn) => MessageType::WARNING,
+                    Some(LogLevel::Error) => MessageType::ERROR,
+                };
+                let line = output.to_string();
+                handle.block_on(async {
+                    backend.client.log_message(kind, &line).await;
+                    if progress {
+                        backend
+                            .report_progress(&stream_token, WorkDoneProgress::Report(WorkDoneProgressReport {
+                                message: Some(line),
+                                ..WorkDoneProgressReport::default()
+                            }))
+                            .await;
+                    }
+                });
+            })
+        })
+        .await
+        .unwrap_or_else(|e| Err(format!("Run failed: {e}")));
+
+        let summary = match &result {
+            Ok(value) => format!("Finished: {}", value.to_string_value()),
+            Err(message) => message.clone(),
+        };
+        if progress {
+            self.report_progress(&token, WorkDoneProgress::End(WorkDoneProgressEnd {
+                message: Some(summary.clone()),
+            }))
+            .await;
+        }
+
+        match result {
+            Ok(value) => {
+                self.client.log_message(MessageType::INFO, summary).await;
+                Ok(Some(serde_json::Value::String(value.to_json())))
+            }
+            Err(message) => {
+                self.client.show_message(MessageType::ERROR, message).await;
+                Ok(None)
+            }
+        }
+    }
+
     async fn hover(&self, params: HoverParams) -> tower_lsp::jsonrpc::Result<Option<Hover>> {
         let uri = params.text_document_position_params.text_document.uri;
         let position = params.text_document_position_params.position;
@@ -94,7 +257,10 @@ impl LanguageServer for Backend {
         };
 
         if let Some((range, word)) = word_at_position(&text, position) {
-            let contents = hover_contents_for(&word);
+            let aliases = parse(&text)
+                .map(|program| collect_type_aliases(&program))
+                .unwrap_or_default();
+            let contents = hover_contents_for(&word, &aliases);
             return Ok(Some(Hover {
                 contents,
                 range: Some(range),
@@ -104,6 +270,64 @@ impl LanguageServer for Backend {
         Ok(None)
     }
 
+    async fn inlay_hint(
+        &self,
+        params: InlayHintParams,
+    ) -> tower_lsp::jsonrpc::Result<Option<Vec<InlayHint>>> {
+        let docs = self.documents.read().await;
+        let Some(text) = docs.get(&params.text_document.uri) else {
+            return Ok(None);
+        };
+        Ok(Some(compute_inlay_hints(text, params.range)))
+    }
+
+    async fn folding_range(
+        &self,
+        params: FoldingRangeParams,
+    ) -> tower_lsp::jsonrpc::Result<Option<Vec<FoldingRange>>> {
+        let docs = self.documents.read().await;
+        let Some(text) = docs.get(&params.text_document.uri) else {
+            return Ok(None);
+        };
+        Ok(Some(compute_folding_ranges(text)))
+    }
+
+    async fn signature_help(
+        &self,
+        params: SignatureHelpParams,
+    ) -> tower_lsp::jsonrpc::Result<Option<SignatureHelp>> {
+        let uri = params.text_document_position_params.text_document.uri;
+        let position = params.text_document_position_params.position;
+
+        let docs = self.documents.read().await;
+        let Some(text) = docs.get(&uri) else {
+            return Ok(None);
+        };
+
+        // Relative imports resolve against the document's directory
+        let base_dir = uri
+            .to_file_path()
+            .ok()
+            .and_then(|path| path.parent().map(|dir| dir.to_path_buf()));
+        let offset = position_to_byte_offset(text, position);
+        Ok(compute_signature_help(text, offset, base_dir.as_deref()))
+    }
+
+    async fn on_type_formatting(
+        &self,
+        params: DocumentOnTypeFormattingParams,
+    ) -> tower_lsp::jsonrpc::Result<Option<Vec<TextEdit>>> {
+        let uri = params.text_document_position.text_document.uri;
+        let position = params.text_document_position.position;
+
+        let docs = self.documents.read().await;
+        let Some(text) = docs.get(&uri) else {
+            return Ok(None);
+        };
+        let edits = compute_on_type_edits(text, position, &params.options);
+        Ok((!edits.is_empty()).then_some(edits))
+    }
+
     async fn completion(
         &self,
         params: CompletionParams,
@@ -139,43 +363,33 @@ impl LanguageServer for Backend {
 
 fn compute_diagnostics(text: &str) -> Vec<Diagnostic> {
     match parse(text) {
-        Ok(_) => Vec::new(),
+        Ok(program) => {
+            let types = patchwork_typecheck::check(&program, text).into_iter().map(ParseError::from);
+            let mut errors: Vec<_> = check(&program).into_iter().chain(types).collect();
+            errors.sort_by_key(|err| err.span());
+            errors.into_iter().map(|err| diagnostic_from_error(err, text)).collect()
+        }
         Err(err) => vec![diagnostic_from_error(err, text)],
     }
 }
 
 fn diagnostic_from_error(err: ParseError, text: &str) -> Diagnostic {
-    let (message, byte_offset, span) = match err {
-        ParseError::LexerError {
-            message,
-            byte_offset,
-            span,
-        } => (message, byte_offset, span),
-        ParseError::UnexpectedToken {
-            message,
-            byte_offset,
-            span,
-        } => (message, byte_offset, span),
-    };
-
-    let range = if let Some((start, end)) = span {
-        let start_pos = byte_offset_to_position(text, start);
-        // Ensure the range spans at least one character to avoid zero-length diagnostics.
-        let end_pos = byte_offset_to_position(text, if end <= start { start + 1 } else { end });
-        Range {
-            start: start_pos,
-            end: end_pos,
-        }
-    } else if let Some(pos) = byte_offset {
-        let p = byte_offset_to_position(text, pos);
-        Range { start: p, end: p }
-    } else {
-        Range {
+    let range = match err.span() {
+        Some((start, end)) => Range {
+            start: byte_offset_to_position(text, start),
+            // Ensure the range spans at least one character to avoid zero-length diagnostics.
+            end: byte_offset_to_position(text, if end <= start { start + 1 } else { end }),
+        },
+        None => Range {
             start: Position::new(0, 0),
             end: Position::new(0, 1),
-        }
+        },
+    };
+    // What the parser expected, for clients that offer fixes or completions
+    let data = match (err.found(), err.expected()) {
+        (None, []) => None,
+        (found, expected) => Some(serde_json::json!({ "found": found, "expected": expected })),
     };
-
 
     Diagnostic {
         range,
@@ -183,10 +397,10 @@ fn diagnostic_from_error(err: ParseError, text: &str) -> Diagnostic {
         code: None,
         code_description: None,
         source: Some("patchwork".to_string()),
-        message,
+        message: err.message(),
         related_information: None,
         tags: None,
-        data: None,
+        data,
     }
 }
 
@@ -227,6 +441,22 @@ fn byte_offset_to_position(text: &str, byte_offset: usize) -> Position {
     Position::new(line as u32, col as u32)
 }
 
+fn position_to_byte_offset(text: &str, position: Position) -> usize {
+    let mut offset = 0;
+    for (line, line_str) in text.split_inclusive('\n').enumerate() {
+        if line == position.line as usize {
+            return offset
+                + line_str
+                    .char_indices()
+                    .nth(position.character as usize)
+                    .map(|(idx, _)| idx)
+                    .unwrap_or(line_str.trim_end_matches('\n').len());
+        }
+        offset += line_str.len();
+    }
+    text.len()
+}
+
 fn word_at_position(text: &str, position: Position) -> Option<(Range, String)> {
     let Position { line, character } = position;
     let line = line as usize;
@@ -288,9 +518,9 @@ fn prefix_at_position(text: &str, position: Position) -> Option<(Range, String)>
 
 static IDENT_RE: Lazy<Regex> = Lazy::new(|| Regex::new(r"[A-Za-z_][A-Za-z0-9_]*").unwrap());
 static KEYWORDS: &[&str] = &[
-    "worker", "trait", "skill", "task", "fun", "type", "var", "if", "else", "for", "while",
-    "await", "return", "succeed", "fail", "break", "continue", "import", "from", "export",
-    "think", "ask", "do", "self", "true", "false",
+    "worker", "trait", "skill", "task", "fun", "memo", "type", "var", "const", "if", "else", "for", "while",
+    "await", "return", "succeed", "fail", "break", "continue", "try", "catch", "finally", "import", "from", "export",
+    "think", "ask", "do", "match", "variant", "example", "fragment", "self", "true", "false",
 ];
 
 fn collect_identifiers(text: &str) -> Vec<String> {
@@ -301,8 +531,63 @@ fn collect_identifiers(text: &str) -> Vec<String> {
     seen.into_iter().collect()
 }
 
-fn hover_contents_for(symbol: &str) -> HoverContents {
-    if KEYWORDS.contains(&symbol) {
+/// Collect every `type` declaration in the document, at top level or in bodies.
+fn collect_type_aliases(program: &Program) -> HashMap<String, Type> {
+    fn visit_block(block: &Block, aliases: &mut HashMap<String, Type>) {
+        for stmt in &block.statements {
+            match &stmt.node {
+                Statement::TypeDecl { name, type_expr } => {
+                    aliases.insert(name.to_string(), Type::from_expr(type_expr));
+                }
+                Statement::If { then_block, else_block, .. } => {
+                    visit_block(then_block, aliases);
+                    if let Some(else_block) = else_block {
+                        visit_block(else_block, aliases);
+                    }
+                }
+                Statement::ForIn { body, .. }
+                | Statement::While { body, .. }
+                | Statement::WhileVar { body, .. } => visit_block(body, aliases),
+                Statement::Try { body, catch, finally_block } => {
+                    visit_block(body, aliases);
+                    for block in catch.iter().map(|catch| &catch.body).chain(finally_block) {
+                        visit_block(block, aliases);
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    let mut aliases = HashMap::new();
+    for item in &program.items {
+        match &item.node {
+            Item::Type(decl) => {
+                aliases.insert(decl.name.to_string(), Type::from_expr(&decl.type_expr));
+            }
+            Item::Skill(skill) => visit_block(&skill.body, &mut aliases),
+            Item::Worker(worker) => visit_block(&worker.body, &mut aliases),
+            Item::Function(func) => visit_block(&func.body, &mut aliases),
+            Item::Trait(trait_decl) => {
+                for method in &trait_decl.methods {
+                    visit_block(&method.body, &mut aliases);
+                }
+            }
+            Item::Import(_) | Item::Config(_) | Item::Example(_) | Item::Fragment(_) => {}
+        }
+    }
+    aliases
+}
+
+fn hover_contents_for(symbol: &str, aliases: &HashMap<String, Type>) -> HoverContents {
+    if let Some(ty) = aliases.get(symbol) {
+        // Show the alias with every nested alias expanded to its full shape
+        let expanded = ty.expand(aliases);
+        HoverContents::Markup(MarkupContent {
+            kind: MarkupKind::Markdown,
+            value: format!("```patchwork\ntype {symbol} = {expanded}\n```"),
+        })
+    } else if KEYWORDS.contains(&symbol) {
         HoverContents::Scalar(MarkedString::String(format!("keyword `{symbol}`")))
     } else {
         HoverContents::Scalar(MarkedString::String(format!("identifier `{symbol}`")))
@@ -331,3 +616,40 @@ async fn main() {
     let (service, socket) = LspService::new(|client| Backend::new(client));
     Server::new(stdin, stdout, socket).serve(service).await;
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hover_expands_type_alias() {
+        let text = "type status = \"ok\" | \"failed\"\ntype report = { status: status, notes: [string] }\n";
+        let program = parse(text).expect("parse failed");
+        let aliases = collect_type_aliases(&program);
+
+        match hover_contents_for("report", &aliases) {
+            HoverContents::Markup(markup) => {
+                assert!(markup.value.contains("type report = { status: \"ok\" | \"failed\", notes: [string] }"),
+                    "unexpected hover: {}", markup.value);
+            }
+            other => panic!("Expected markup hover, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_type_check_diagnostics() {
+        let text = "type status = \"ok\" | \"failed\"\n\nfun f(s: sttus) {\n    var st: status = \"done\"\n}\n";
+        let diagnostics = compute_diagnostics(text);
+        let found: Vec<_> = diagnostics.iter().map(|d| (d.range.start.line, d.message.as_str())).collect();
+        assert_eq!(
+            found,
+            vec![
+                (2, "Unknown type 'sttus'"),
+                (
+                    3,
+                    "Initial value of 'st' does not match type status: at value: expected \"ok\" | \"failed\", got string \"done\""
+                ),
+            ]
+        );
+    }
+}
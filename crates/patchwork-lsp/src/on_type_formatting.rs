@@ -0,0 +1,158 @@
+//! On-type formatting when a newline is typed.
+//!
+//! Newlines separate statements, so an editor that doesn't understand prompt
+//! blocks tends to leave `think {` bodies misaligned or the closing brace
+//! stuck to the end of the prompt. On each newline we:
+//!
+//! - indent the new line one level past a line that opened a block, or align
+//!   it with the previous line (which keeps prompt paragraphs flush);
+//! - move a `}` that followed the cursor onto its own line at the opener's
+//!   indentation;
+//! - close a `think`/`ask`/`do` block that was opened but never closed.
+
+use once_cell::sync::Lazy;
+use regex::Regex;
+use tower_lsp::lsp_types::*;
+
+/// A line ending in the opening brace of a think, ask, or do block.
+static PROMPT_OPENER_RE: Lazy<Regex> = Lazy::new(|| Regex::new(r"\b(think|ask|do)\s*\{\s*$").unwrap());
+
+/// Compute the edits to apply after a newline was typed at `position`.
+pub fn compute_on_type_edits(text: &str, position: Position, options: &FormattingOptions) -> Vec<TextEdit> {
+    let lines: Vec<&str> = text.split('\n').collect();
+    let line = position.line as usize;
+    if line == 0 || line >= lines.len() {
+        return Vec::new();
+    }
+
+    let current = lines[line].trim_end_matches('\r');
+    let prev = lines[line - 1].trim_end_matches('\r');
+
+    // Align with the nearest non-blank line above
+    let anchor = lines[..line]
+        .iter()
+        .rev()
+        .map(|l| l.trim_end_matches('\r'))
+        .find(|l| !l.trim().is_empty())
+        .unwrap_or("");
+    let base_indent = leading_whitespace(anchor);
+    let opens_block = prev.trim_end().ends_with('{');
+
+    let unit = if options.insert_spaces {
+        " ".repeat(options.tab_size as usize)
+    } else {
+        "\t".to_string()
+    };
+    let target_indent = if opens_block {
+        format!("{base_indent}{unit}")
+    } else {
+        base_indent.to_string()
+    };
+
+    let current_indent = leading_whitespace(current);
+    let rest = &current[current_indent.len()..];
+    let indent_range = Range::new(
+        Position::new(position.line, 0),
+        Position::new(position.line, current_indent.chars().count() as u32),
+    );
+
+    if opens_block && rest.starts_with('}') {
+        // `think {|}` + Enter: give the body its own line above the brace
+        return vec![TextEdit {
+            range: indent_range,
+            new_text: format!("{target_indent}\n{base_indent}"),
+        }];
+    }
+
+    if opens_block && PROMPT_OPENER_RE.is_match(prev) && unclosed_braces(text) > 0 {
+        // Rewrite the whole line so the indent and closing brace land in one edit
+        let end = Position::new(position.line, current.chars().count() as u32);
+        return vec![TextEdit {
+            range: Range::new(indent_range.start, end),
+            new_text: format!("{target_indent}{rest}\n{base_indent}}}"),
+        }];
+    }
+
+    if current_indent == target_indent {
+        return Vec::new();
+    }
+    vec![TextEdit {
+        range: indent_range,
+        new_text: target_indent,
+    }]
+}
+
+fn leading_whitespace(line: &str) -> &str {
+    &line[..line.len() - line.trim_start().len()]
+}
+
+/// Count of `{` without a matching `}`, ignoring braces inside string literals.
+fn unclosed_braces(text: &str) -> i64 {
+    let mut depth = 0i64;
+    let mut in_string = false;
+    let mut escaped = false;
+    for ch in text.chars() {
+        if in_string {
+            match ch {
+                _ if escaped => escaped = false,
+                '\\' => escaped = true,
+                '"' => in_string = false,
+                _ => {}
+            }
+            continue;
+        }
+        match ch {
+            '"' => in_string = true,
+            '{' => depth += 1,
+            '}' => depth -= 1,
+            _ => {}
+        }
+    }
+    depth
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn options() -> FormattingOptions {
+        FormattingOptions {
+            tab_size: 4,
+            insert_spaces: true,
+            ..FormattingOptions::default()
+        }
+    }
+
+    /// Apply a single-line edit (the only kind produced here) to `text`.
+    fn apply(text: &str, edits: &[TextEdit]) -> String {
+        let mut lines: Vec<String> = text.split('\n').map(str::to_string).collect();
+        if let [edit] = edits {
+            let line = &mut lines[edit.range.start.line as usize];
+            let start = edit.range.start.character as usize;
+            let end = edit.range.end.character as usize;
+            line.replace_range(start..end, &edit.new_text);
+        }
+        lines.join("\n")
+    }
+
+    #[test]
+    fn test_newline_after_think_opens_and_closes_block() {
+        let text = "skill main() {\n    var x = think {\n\n}\n";
+        let edits = compute_on_type_edits(text, Position::new(2, 0), &options());
+        assert_eq!(apply(text, &edits), "skill main() {\n    var x = think {\n        \n    }\n}\n");
+    }
+
+    #[test]
+    fn test_newline_between_braces_splits_block() {
+        let text = "skill main() {\n    var x = think {\n}\n}\n";
+        let edits = compute_on_type_edits(text, Position::new(2, 0), &options());
+        assert_eq!(apply(text, &edits), "skill main() {\n    var x = think {\n        \n    }\n}\n");
+    }
+
+    #[test]
+    fn test_prompt_text_stays_aligned() {
+        let text = "    var x = think {\n        First line of the prompt.\n\n    }\n";
+        let edits = compute_on_type_edits(text, Position::new(2, 0), &options());
+        assert_eq!(apply(text, &edits), "    var x = think {\n        First line of the prompt.\n        \n    }\n");
+    }
+}
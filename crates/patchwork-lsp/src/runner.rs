@@ -0,0 +1,160 @@
+//! Running a file or selection through the interpreter from the editor.
+//!
+//! The server handles `patchwork.runFile` and `patchwork.runSelection` via
+//! `workspace/executeCommand`. Code runs on a blocking thread, and each line
+//! it prints or logs is handed to a callback so the server can stream it back.
+
+use std::path::PathBuf;
+use std::sync::mpsc;
+
+use patchwork_eval::{Interpreter, OutputLine, TypeCheckMode, Value};
+use serde_json::Value as JsonValue;
+use tower_lsp::lsp_types::{Range, Url};
+
+/// Run the whole document: `[uri]`.
+pub const RUN_FILE: &str = "patchwork.runFile";
+/// Run part of a document: `[uri, range]`.
+pub const RUN_SELECTION: &str = "patchwork.runSelection";
+
+/// Runtime settings for code run from the editor.
+///
+/// Read from the `run` section of the client's initialization options or of
+/// its `patchwork` workspace settings:
+///
+/// ```json
+/// { "run": { "workingDir": "/path/to/repo", "typeCheck": "warn" } }
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct RunProfile {
+    /// Directory the program runs in; defaults to the document's directory.
+    pub working_dir: Option<PathBuf>,
+    pub type_check: TypeCheckMode,
+}
+
+impl RunProfile {
+    pub fn from_settings(settings: &JsonValue) -> Self {
+        let settings = settings.get("patchwork").unwrap_or(settings);
+        let Some(run) = settings.get("run") else {
+            return Self::default();
+        };
+        let type_check = match run.get("typeCheck").and_then(JsonValue::as_str) {
+            Some("off") => TypeCheckMode::Off,
+            Some("warn") => TypeCheckMode::Warn,
+            _ => TypeCheckMode::Error,
+        };
+        Self {
+            working_dir: run
+                .get("workingDir")
+                .and_then(JsonValue::as_str)
+                .map(PathBuf::from),
+            type_check,
+        }
+    }
+}
+
+/// The document and the code a run command asks for.
+pub struct RunRequest {
+    pub uri: Url,
+    /// Selected range, for `patchwork.runSelection`.
+    pub range: Option<Range>,
+}
+
+impl RunRequest {
+    /// Decode a command's arguments.
+    pub fn from_arguments(command: &str, arguments: &[JsonValue]) -> Result<Self, String> {
+        let uri = arguments
+            .first()
+            .and_then(|arg| serde_json::from_value::<Url>(arg.clone()).ok())
+            .ok_or_else(|| format!("{command} expects a document URI as its first argument"))?;
+        let range = match command {
+            RUN_FILE => None,
+            RUN_SELECTION => Some(
+                arguments
+                    .get(1)
+                    .and_then(|arg| serde_json::from_value::<Range>(arg.clone()).ok())
+                    .ok_or_else(|| format!("{command} expects a range as its second argument"))?,
+            ),
+            _ => return Err(format!("Unknown command: {command}")),
+        };
+        Ok(Self { uri, range })
+    }
+
+    /// The code to run from `text`. A selection of statements is wrapped in a
+    /// block so the interpreter runs it as a program.
+    pub fn code(&self, text: &str) -> String {
+        let Some(range) = self.range else {
+            return text.to_string();
+        };
+        let start = crate::position_to_byte_offset(text, range.start);
+        let end = crate::position_to_byte_offset(text, range.end).max(start);
+        let selection = text[start..end].trim();
+        if selection.starts_with('{') {
+            selection.to_string()
+        } else {
+            format!("{{\n{selection}\n}}")
+        }
+    }
+}
+
+/// Run `code` with `profile`, calling `on_output` for each printed or logged line.
+pub fn run(
+    code: String,
+    profile: RunProfile,
+    default_dir: Option<PathBuf>,
+    mut on_output: impl FnMut(OutputLine),
+) -> Result<Value, String> {
+    let (output_tx, output_rx) = mpsc::channel();
+    let working_dir = profile.working_dir.or(default_dir);
+
+    // The interpreter owns the sender, so the receiver drains until it finishes
+    let worker = std::thread::spawn(move || {
+        let mut interpreter = match working_dir {
+            Some(dir) => Interpreter::with_working_dir(dir),
+            None => Interpreter::new(),
+        };
+        interpreter.set_type_check_mode(profile.type_check);
+        interpreter.set_output_sink(output_tx);
+        // The open buffer is the user's own, but the files it imports need trust
+        interpreter.require_trusted_modules();
+        interpreter.eval(&code).map_err(|e| e.to_string())
+    });
+
+    for line in output_rx {
+        on_output(line);
+    }
+    worker
+        .join()
+        .unwrap_or_else(|_| Err("Interpreter panicked".to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tower_lsp::lsp_types::Position;
+
+    #[test]
+    fn test_run_selection_streams_output() {
+        let text = "skill main() {\n    var x = 2\n    print(\"hello\")\n    x + 1\n}\n";
+        let request = RunRequest::from_arguments(
+            RUN_SELECTION,
+            &[
+                serde_json::json!("file:///tmp/main.pw"),
+                serde_json::json!(Range::new(Position::new(1, 0), Position::new(3, 9))),
+            ],
+        )
+        .expect("valid arguments");
+
+        let mut output = Vec::new();
+        let result = run(request.code(text), RunProfile::default(), None, |line| output.push(line.to_string()));
+        assert_eq!(result, Ok(Value::Number(3.0)));
+        assert_eq!(output, vec!["hello".to_string()]);
+    }
+
+    #[test]
+    fn test_profile_from_settings() {
+        let settings = serde_json::json!({ "patchwork": { "run": { "typeCheck": "warn", "workingDir": "/repo" } } });
+        let profile = RunProfile::from_settings(&settings);
+        assert_eq!(profile.type_check, TypeCheckMode::Warn);
+        assert_eq!(profile.working_dir, Some(PathBuf::from("/repo")));
+    }
+}
@@ -0,0 +1,401 @@
+//! Signature help for calls to functions, skills, workers, and builtins.
+//!
+//! Signatures come from declarations in the current document, from the default
+//! exports of files pulled in with `import ./{...}`, and from the interpreter's
+//! builtins. Parameter descriptions are read from `# @arg name description`
+//! lines in the comment block above a declaration.
+
+use std::path::Path;
+
+use patchwork_eval::Type;
+use patchwork_parser::ast::{ImportPath, Item, Param, Program, Span, Spanned};
+use patchwork_parser::{parse, AstCache};
+use tower_lsp::lsp_types::*;
+
+/// Builtin functions provided by the interpreter, as `(name, params, doc)`.
+/// Namespaced builtins are listed by their full name, like `file.read`.
+const BUILTINS: &[(&str, &[&str], &str)] = &[
+    ("cat", &["value"], "Convert a value to its string form."),
+    ("json", &["text: string"], "Parse a JSON string into a value."),
+    ("json_parse", &["text: string"], "Parse a JSON string into a value, throwing if it's malformed."),
+    ("json_stringify", &["value", "indent?: number"], "A value as JSON, on one line or indented."),
+    ("print", &["...values"], "Print values separated by spaces."),
+    ("len", &["value"], "Length of a string, array, or object."),
+    ("keys", &["object"], "The keys of an object, as an array."),
+    ("values", &["object"], "The values of an object, as an array."),
+    ("entries", &["object"], "The fields of an object as `{key, value}` objects, in order."),
+    ("unique", &["array"], "The distinct elements of an array, in the order they first appear."),
+    ("group_by", &["array", "key: function"], "Group elements by `key(element)`, as `{key, items}` objects."),
+    ("count_by", &["array", "key: function"], "How many elements share each `key(element)`, as an object."),
+    ("sort_by", &["array", "key: function"], "The elements sorted by `key(element)`, keeping ties in order."),
+    ("min", &["array", "key?: function"], "The element with the smallest key, or null if the array is empty."),
+    ("max", &["array", "key?: function"], "The element with the largest key, or null if the array is empty."),
+    ("sum", &["array", "key?: function"], "The total of the numbers, durations, or sizes in an array."),
+    ("merge", &["a: object", "b: object"], "Merge `b` into `a`, recursing into objects; `b` wins otherwise."),
+    ("patch", &["value", "operations: array"], "Apply a JSON Patch (RFC 6902) to a value."),
+    ("pick", &["object", "keys: array"], "An object with only the named fields."),
+    ("omit", &["object", "keys: array"], "An object without the named fields."),
+    ("lines", &["text: string"], "Split text, such as command output, into lines."),
+    ("scan", &["line: string", "pattern: string"], "The named captures of a regex match as an object, or null."),
+    (
+        "with_timeout",
+        &["limit: duration | number", "body: function"],
+        "Call `body`, raising a `TimeoutError` if a command or think block inside runs past `limit`.",
+    ),
+    ("spawn", &["f: function", "args..."], "Call `f` on its own thread and return its task; `.await` it for the result."),
+    ("send", &["task", "message"], "Leave a message in a spawned task's mailbox, for its `self.receive()`."),
+    (
+        "think_all",
+        &["items: array", "f: function", "options?: object"],
+        "Call `f` on each item concurrently, `concurrency` at a time, and return a record per item.",
+    ),
+    ("typeof", &["value"], "The name of a value's type."),
+    ("cwd", &[], "The working directory, which `$ cd` changes."),
+    ("read", &["path: string"], "Read a file relative to the working directory."),
+    ("write", &["path: string", "content: string"], "Write a file relative to the working directory."),
+    ("approve", &["message: string", "default?: boolean"], "Ask the person running the program for a yes or no."),
+    ("log.debug", &["...values"], "Log values separated by spaces at debug level."),
+    ("log.info", &["...values"], "Log values separated by spaces at info level."),
+    ("log.warn", &["...values"], "Log values separated by spaces at warn level."),
+    ("log.error", &["...values"], "Log values separated by spaces at error level."),
+    ("file.read", &["path: string"], "Read a file relative to the working directory."),
+    ("file.write", &["path: string", "content: string"], "Write a file relative to the working directory."),
+    ("file.append", &["path: string", "content: string"], "Add to the end of a file, creating it if needed."),
+    ("file.exists", &["path: string"], "Whether a file or directory is there."),
+    ("file.mkdir", &["path: string"], "Create a directory and any missing parents."),
+    ("file.remove", &["path: string"], "Delete a file or an empty directory."),
+    ("env.get", &["name: string"], "An environment variable's value, or null if it isn't set."),
+    ("env.set", &["name: string", "value: string"], "Set a variable for later `env.get` calls and shell commands."),
+    ("env.vars", &[], "Every environment variable, as an object sorted by name."),
+    ("self.send", &["message"], "Leave a message for whoever spawned this task."),
+    ("self.receive", &["timeout?: duration | number"], "Take the next message from this task's mailbox."),
+    ("git.changed_files", &["base?: string"], "Files that differ from `base` (default HEAD), as `{path, status}` objects."),
+    ("git.log", &["range?: string"], "Commits in `range` (default HEAD), newest first."),
+    ("render.table", &["rows: array", "columns?: array"], "Print rows of objects as a table."),
+    ("render.markdown", &["value"], "Print a value as Markdown."),
+    ("diff.text", &["old: string", "new: string"], "A line diff of two texts, as hunks."),
+    ("diff.json", &["old", "new"], "A structural diff of two values, as changes at JSON paths."),
+    ("diff.render", &["diff"], "A diff from `diff.text` or `diff.json` as text."),
+    ("template.render", &["template: string", "bindings: object"], "Interpolate `${...}` paths in a template against an object."),
+    ("encode.base64", &["text: string"], "Standard Base64 of the text's bytes."),
+    ("decode.base64", &["text: string"], "The text a Base64 string encodes."),
+    ("hash.sha256", &["text: string"], "Hex SHA-256 digest of a text."),
+    ("hash.sha256_file", &["path: string"], "Hex SHA-256 digest of a file."),
+    ("hmac.sha256", &["key: string", "message: string"], "Hex HMAC-SHA256 of a message, as webhooks sign payloads."),
+    ("csv.parse", &["text: string", "options?: object"], "The rows of a CSV document."),
+    ("csv.read", &["path: string", "options?: object"], "The rows of a CSV file, read incrementally."),
+    ("csv.stringify", &["rows: array", "options?: object"], "Write rows as CSV."),
+    ("yaml.parse", &["text: string"], "Parse a YAML document into a value."),
+    ("yaml.stringify", &["value"], "A value as a YAML document."),
+    ("toml.parse", &["text: string"], "Parse a TOML document into a value."),
+    ("toml.stringify", &["value"], "A value as a TOML document."),
+    ("std.sleep", &["limit: duration | number"], "Pause for a duration or a number of milliseconds."),
+];
+
+/// A callable's signature, ready to render.
+struct Signature {
+    name: String,
+    /// Parameter labels (`name: type`) with their `@arg` descriptions.
+    params: Vec<(String, Option<String>)>,
+    doc: Option<String>,
+}
+
+/// Compute signature help at `offset`, resolving relative imports against `base_dir`.
+pub fn compute_signature_help(text: &str, offset: usize, base_dir: Option<&Path>) -> Option<SignatureHelp> {
+    let (callee, active_param) = call_context(text, offset)?;
+    let signature = find_signature(text, &callee, offset, base_dir)?;
+
+    // Parameter labels are given as offsets into the rendered label
+    let mut label = format!("{}(", signature.name);
+    let mut parameters = Vec::new();
+    for (i, (param, doc)) in signature.params.iter().enumerate() {
+        if i > 0 {
+            label.push_str(", ");
+        }
+        let start = label.encode_utf16().count() as u32;
+        label.push_str(param);
+        let end = label.encode_utf16().count() as u32;
+        parameters.push(ParameterInformation {
+            label: ParameterLabel::LabelOffsets([start, end]),
+            documentation: doc.clone().map(Documentation::String),
+        });
+    }
+    label.push(')');
+
+    // Variadic builtins keep the last parameter active
+    let active_param = match signature.params.last() {
+        Some((last, _)) if last.starts_with("...") => active_param.min(signature.params.len() - 1),
+        _ => active_param,
+    };
+
+    Some(SignatureHelp {
+        signatures: vec![SignatureInformation {
+            label,
+            documentation: signature.doc.map(Documentation::String),
+            parameters: Some(parameters),
+            active_parameter: Some(active_param as u32),
+        }],
+        active_signature: Some(0),
+        active_parameter: Some(active_param as u32),
+    })
+}
+
+/// Find the innermost unclosed call around `offset`, returning the callee's
+/// name with any member path (`file.read`) and the index of the argument
+/// being typed.
+fn call_context(text: &str, offset: usize) -> Option<(String, usize)> {
+    let prefix = text.get(..offset)?;
+    let mut depth = 0usize;
+    let mut commas = 0;
+    let mut in_string = false;
+    let mut prev: Option<char> = None;
+
+    for (i, ch) in prefix.char_indices().rev() {
+        // Scanning backwards, an escaped quote is seen before its backslash
+        if ch == '\\' && prev == Some('"') {
+            in_string = !in_string;
+        }
+        prev = Some(ch);
+        if ch == '"' {
+            in_string = !in_string;
+            continue;
+        }
+        if in_string {
+            continue;
+        }
+        match ch {
+            ')' | ']' | '}' => depth += 1,
+            '(' if depth == 0 => {
+                let before = prefix[..i].trim_end();
+                let name_start = before
+                    .rfind(|c: char| !(c.is_ascii_alphanumeric() || c == '_' || c == '.'))
+                    .map(|p| p + 1)
+                    .unwrap_or(0);
+                // A method on a literal, like `"a".len(`, has no name to look up
+                let name = before[name_start..].trim_start_matches('.');
+                return (!name.is_empty()).then(|| (name.to_string(), commas));
+            }
+            '[' | '{' if depth == 0 => return None,
+            '(' | '[' | '{' => depth -= 1,
+            ',' if depth == 0 => commas += 1,
+            _ => {}
+        }
+    }
+    None
+}
+
+fn find_signature(text: &str, name: &str, offset: usize, base_dir: Option<&Path>) -> Option<Signature> {
+    // The call being typed usually doesn't parse yet, so fall back to the
+    // document without the cursor's line
+    let patched = without_line_at(text, offset);
+    let parsed = parse(text)
+        .ok()
+        .map(|program| (text, program))
+        .or_else(|| parse(&patched).ok().map(|program| (patched.as_str(), program)));
+
+    if let Some((text, program)) = &parsed {
+        if let Some(signature) = declared_signature(program, text, |decl| decl == name) {
+            return Some(signature);
+        }
+
+        // `import ./{analyst}` binds `analyst` to the default export of ./analyst.pw
+        if let Some(dir) = base_dir {
+            let imported = program.items.iter().any(|item| {
+                matches!(&item.node, Item::Import(import)
+                    if matches!(&import.path, ImportPath::RelativeMulti(names) if names.iter().any(|n| n == name)))
+            });
+            if imported {
+                let path = dir.join(format!("{name}.pw"));
+                if let Ok(source) = std::fs::read_to_string(path) {
+                    // Imported libraries rarely change between requests
+                    if let Ok(module) = AstCache::in_temp_dir().parse(&source) {
+                        if let Some(mut signature) = default_export_signature(&module, &source) {
+                            signature.name = name.to_string();
+                            return Some(signature);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    BUILTINS
+        .iter()
+        .find(|(builtin, _, _)| *builtin == name)
+        .map(|(builtin, params, doc)| Signature {
+            name: builtin.to_string(),
+            params: params.iter().map(|p| (p.to_string(), None)).collect(),
+            doc: Some(doc.to_string()),
+        })
+}
+
+/// A copy of `text` with the line containing `offset` left empty.
+fn without_line_at(text: &str, offset: usize) -> String {
+    let start = text[..offset].rfind('\n').map(|p| p + 1).unwrap_or(0);
+    let end = text[offset..].find('\n').map(|p| offset + p).unwrap_or(text.len());
+    format!("{}{}", &text[..start], &text[end..])
+}
+
+/// The signature of the first declaration whose name satisfies `matches`.
+fn declared_signature(program: &Program, text: &str, matches: impl Fn(&str) -> bool) -> Option<Signature> {
+    let mut candidates: Vec<(&str, Span, &[Spanned<Param>])> = Vec::new();
+    for item in &program.items {
+        match &item.node {
+            Item::Function(func) => candidates.push((&func.name, item.span, &func.params)),
+            Item::Skill(skill) => candidates.push((&skill.name, item.span, &skill.params)),
+            Item::Worker(worker) => candidates.push((&worker.name, item.span, &worker.params)),
+            Item::Trait(trait_decl) => {
+                for method in &trait_decl.methods {
+                    candidates.push((&method.name, method.span, &method.params));
+                }
+            }
+            Item::Import(_) | Item::Type(_) | Item::Config(_) | Item::Example(_) | Item::Fragment(_) => {}
+        }
+    }
+
+    let (name, span, params) = candidates.into_iter().find(|(name, _, _)| matches(name))?;
+    Some(build_signature(text, name, span, params))
+}
+
+fn default_export_signature(program: &Program, text: &str) -> Option<Signature> {
+    let default_name = program.items.iter().find_map(|item| match &item.node {
+        Item::Function(f) if f.is_default => Some(&*f.name),
+        Item::Skill(s) if s.is_default => Some(&*s.name),
+        Item::Worker(w) if w.is_default => Some(&*w.name),
+        _ => None,
+    })?;
+    declared_signature(program, text, |name| name == default_name)
+}
+
+fn build_signature(text: &str, name: &str, span: Span, params: &[Spanned<Param>]) -> Signature {
+    let (doc, arg_docs) = doc_comment(text, span);
+    let params = params
+        .iter()
+        .map(|param| {
+            let label = match &param.type_ann {
+                Some(type_ann) => format!("{}: {}", param.name, Type::from_expr(type_ann)),
+                None => param.name.to_string(),
+            };
+            let arg_doc = arg_docs
+                .iter()
+                .find(|(arg, _)| *arg == param.name)
+                .and_then(|(_, doc)| doc.clone());
+            (label, arg_doc)
+        })
+        .collect();
+    Signature {
+        name: name.to_string(),
+        params,
+        doc,
+    }
+}
+
+/// Read the comment block above the declaration parsed from `span`.
+///
+/// Returns the summary text and each `@arg` entry with its optional description.
+fn doc_comment(text: &str, span: Span) -> (Option<String>, Vec<(String, Option<String>)>) {
+    // Declarations built outside the parser have no comments to read
+    if span == Span::default() {
+        return (None, Vec::new());
+    }
+    let Some(before) = text.get(..span.start) else {
+        return (None, Vec::new());
+    };
+
+    // Skip the start of the declaration's own line, then any `@skill`-style annotations
+    let line_start = before.rfind('\n').map_or(0, |newline| newline + 1);
+    let mut lines: Vec<&str> = before[..line_start].lines().rev().map(str::trim).collect();
+    while lines.first().is_some_and(|line| line.starts_with('@')) {
+        lines.remove(0);
+    }
+    let comments: Vec<&str> = lines
+        .into_iter()
+        .take_while(|line| line.starts_with('#'))
+        .map(|line| line.trim_start_matches('#').trim())
+        .collect();
+
+    let mut summary = Vec::new();
+    let mut args = Vec::new();
+    for line in comments.into_iter().rev() {
+        if let Some(rest) = line.strip_prefix("@arg ") {
+            let mut parts = rest.trim().splitn(2, char::is_whitespace);
+            let arg = parts.next().unwrap_or_default().to_string();
+            let description = parts.next().map(|d| d.trim().to_string());
+            args.push((arg, description));
+        } else if !line.starts_with('@') && !line.is_empty() {
+            summary.push(line);
+        }
+    }
+
+    let summary = (!summary.is_empty()).then(|| summary.join(" "));
+    (summary, args)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn label_at(text: &str, marker: &str) -> (String, u32) {
+        let offset = text.find(marker).expect("marker") + marker.len();
+        let help = compute_signature_help(text, offset, None).expect("signature help");
+        let signature = &help.signatures[0];
+        (signature.label.clone(), help.active_parameter.unwrap())
+    }
+
+    #[test]
+    fn test_signature_from_declaration_and_arg_docs() {
+        let text = "# Say hello.\n#\n# @arg who The person to greet\nfun greet(who: string, times: number) {\n    print(who)\n}\n\nskill main() {\n    greet(\"a, b\", 2)\n}\n";
+        let (label, active) = label_at(text, "greet(\"a, b\", ");
+        assert_eq!(label, "greet(who: string, times: number)");
+        assert_eq!(active, 1);
+
+        let offset = text.find("greet(\"").unwrap() + 6;
+        let help = compute_signature_help(text, offset, None).unwrap();
+        let signature = &help.signatures[0];
+        assert_eq!(signature.documentation, Some(Documentation::String("Say hello.".to_string())));
+        let params = signature.parameters.as_ref().unwrap();
+        assert_eq!(
+            params[0].documentation,
+            Some(Documentation::String("The person to greet".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_signature_for_builtin() {
+        let text = "skill main() {\n    write(\"out.txt\", \n}\n";
+        let (label, active) = label_at(text, "write(\"out.txt\", ");
+        assert_eq!(label, "write(path: string, content: string)");
+        assert_eq!(active, 1);
+    }
+
+    #[test]
+    fn test_signature_for_namespaced_builtin() {
+        let text = "skill main() {\n    var out = template.render(\"${a}\", \n    hmac.sha256(\n}\n";
+        let (label, active) = label_at(text, "template.render(\"${a}\", ");
+        assert_eq!(label, "template.render(template: string, bindings: object)");
+        assert_eq!(active, 1);
+        let (label, active) = label_at(text, "hmac.sha256(");
+        assert_eq!(label, "hmac.sha256(key: string, message: string)");
+        assert_eq!(active, 0);
+
+        // Only the full name matches, so `x.read(` isn't `file.read`
+        let text = "skill main() {\n    x.read(\n}\n";
+        let offset = text.find("x.read(").unwrap() + "x.read(".len();
+        assert!(compute_signature_help(text, offset, None).is_none());
+    }
+
+    #[test]
+    fn test_signature_for_imported_worker() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(
+            dir.path().join("analyst.pw"),
+            "# @arg description What to analyze\nexport default worker analyst(description: string) {\n}\n",
+        )
+        .unwrap();
+
+        let text = "import ./{analyst}\n\nskill main() {\n    analyst(\n}\n";
+        let offset = text.find("analyst(").unwrap() + "analyst(".len();
+        let help = compute_signature_help(text, offset, Some(dir.path())).expect("signature help");
+        assert_eq!(help.signatures[0].label, "analyst(description: string)");
+    }
+}
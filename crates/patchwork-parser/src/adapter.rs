@@ -1,7 +1,8 @@
-use patchwork_lexer::{LexerContext, PatchworkToken, Rule};
+use patchwork_lexer::{Edition, LexerContext, PatchworkToken, Rule};
 use parlex::ParlexError;
 use std::any::Any;
 use try_next::TryNextWithContext;
+use crate::error::ParseError;
 use crate::token::ParserToken;
 
 /// Build a lookup table of line start byte offsets
@@ -38,33 +39,12 @@ fn position_to_offset(input: &str, line_starts: &[usize], line: usize, column: u
     line_start + input[line_start..].len()
 }
 
-/// Error type for the parser
-#[derive(Debug)]
-pub enum ParseError {
-    LexerError {
-        message: String,
-        byte_offset: Option<usize>,
-        span: Option<(usize, usize)>,
-    },
-    UnexpectedToken {
-        message: String,
-        byte_offset: Option<usize>,
-        span: Option<(usize, usize)>,
-    },
-}
-
-impl std::fmt::Display for ParseError {
-    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-        match self {
-            ParseError::LexerError { message, .. } => write!(f, "Lexer error: {}", message),
-            ParseError::UnexpectedToken { message, .. } => {
-                write!(f, "Unexpected token: {}", message)
-            }
-        }
-    }
-}
-
-impl std::error::Error for ParseError {}
+/// Deepest bracket nesting the parser accepts.
+///
+/// The generated parser, the AST dumper, and the interpreter all recurse once
+/// per level, so pathological inputs like ten thousand `(` would overflow the
+/// stack long before they meant anything. Real programs stay far below this.
+pub const MAX_NESTING_DEPTH: usize = 512;
 
 /// Adapter that wraps a patchwork lexer and produces tokens in lalrpop format
 /// Implements Iterator<Item = Result<Spanned<ParserToken, usize>, ParseError>>
@@ -77,6 +57,11 @@ where
     context: LexerContext,
     /// Precomputed byte offsets of line starts for efficient position->offset conversion
     line_starts: Vec<usize>,
+    /// Currently open `(`, `[` and `{` tokens
+    depth: usize,
+    /// The nesting depth of the `if`, `while`, `for`, `match` or `trait`
+    /// header being lexed, whose `{` opens a block rather than a variant
+    header: Option<usize>,
 }
 
 impl<'input, L> LexerAdapter<'input, L>
@@ -84,12 +69,19 @@ where
     L: TryNextWithContext<LexerContext, Item = PatchworkToken, Error: std::fmt::Display>,
 {
     pub fn new(input: &'input str, lexer: L) -> Self {
+        Self::with_edition(input, lexer, Edition::default())
+    }
+
+    /// An adapter that rejects words `edition` reserves as keywords.
+    pub fn with_edition(input: &'input str, lexer: L, edition: Edition) -> Self {
         let line_starts = build_line_starts(input);
         Self {
             input,
             lexer,
-            context: LexerContext::default(),
+            context: LexerContext::with_edition(edition),
             line_starts,
+            depth: 0,
+            header: None,
         }
     }
 
@@ -113,10 +105,12 @@ where
             Rule::Think => ParserToken::Think,
             Rule::Ask => ParserToken::Ask,
             Rule::Do => ParserToken::Do,
+            Rule::Variant => ParserToken::Variant,
             Rule::Import => ParserToken::Import,
             Rule::Export => ParserToken::Export,
             Rule::From => ParserToken::From,
             Rule::Var => ParserToken::Var,
+            Rule::Const => ParserToken::Const,
             Rule::If => ParserToken::If,
             Rule::Else => ParserToken::Else,
             Rule::For => ParserToken::For,
@@ -126,6 +120,7 @@ where
             Rule::Trait => ParserToken::Trait,
             Rule::Skill => ParserToken::Skill,
             Rule::Fun => ParserToken::Fun,
+            Rule::Memo => ParserToken::Memo,
             Rule::Default => ParserToken::Default,
             Rule::Type => ParserToken::Type,
             Rule::Return => ParserToken::Return,
@@ -138,9 +133,24 @@ where
             Rule::True => ParserToken::True,
             Rule::False => ParserToken::False,
             Rule::Number => ParserToken::Number(text),
-            Rule::Identifier => ParserToken::Identifier(text),
+            Rule::Duration => ParserToken::Duration(text),
+            Rule::Size => ParserToken::Size(text),
+            // `example` and `fragment` are edition keywords, so they start out identifiers
+            Rule::Identifier | Rule::Example | Rule::Fragment => {
+                // An identifier glued to `{` names a tagged variant (`Success{...}`).
+                // Requiring adjacency keeps `if ready {` parsing as a condition + block,
+                // and in a header `if ready{` does too, since the `{` opens its block.
+                let is_interpolation = self.input[..start].ends_with('$');
+                let in_header = self.header == Some(self.depth);
+                if !is_interpolation && !in_header && self.input[end..].starts_with('{') {
+                    ParserToken::Tag(text)
+                } else {
+                    ParserToken::Identifier(text)
+                }
+            }
             Rule::Ellipsis => ParserToken::Ellipsis,
             Rule::Arrow => ParserToken::Arrow,
+            Rule::FatArrow => ParserToken::FatArrow,
             Rule::Eq => ParserToken::Eq,
             Rule::Neq => ParserToken::Neq,
             Rule::Lte => ParserToken::Lte,
@@ -230,7 +240,58 @@ where
                         continue;
                     }
 
-                    let parser_token = self.convert_token(token.rule, start, end);
+                    let mut parser_token = self.convert_token(token.rule, start, end);
+                    let edition = self.context.edition();
+                    // Edition keywords are identifiers to the lexer
+                    if let ParserToken::Identifier(word) | ParserToken::Tag(word) = parser_token {
+                        if edition.reserves(word) {
+                            parser_token = match word {
+                                "match" => ParserToken::Match,
+                                "try" => ParserToken::Try,
+                                "catch" => ParserToken::Catch,
+                                "finally" => ParserToken::Finally,
+                                "example" => ParserToken::Example,
+                                "fragment" => ParserToken::Fragment,
+                                _ => parser_token,
+                            };
+                        }
+                    }
+                    match parser_token {
+                        // Other reserved words have no grammar of their own
+                        // yet, so using one is an error rather than an identifier
+                        ParserToken::Identifier(word) | ParserToken::Tag(word) if edition.reserves(word) => {
+                            return Some(Err(ParseError::UnexpectedToken {
+                                message: format!("`{}` is a reserved keyword in edition {}", word, edition),
+                                byte_offset: Some(start),
+                                span: Some((start, end)),
+                            }));
+                        }
+                        ParserToken::If
+                        | ParserToken::While
+                        | ParserToken::For
+                        | ParserToken::Match
+                        | ParserToken::Trait => self.header = Some(self.depth),
+                        ParserToken::LParen | ParserToken::LBracket | ParserToken::LBrace => {
+                            if parser_token == ParserToken::LBrace && self.header == Some(self.depth) {
+                                self.header = None;
+                            }
+                            self.depth += 1;
+                            if self.depth > MAX_NESTING_DEPTH {
+                                return Some(Err(ParseError::UnexpectedToken {
+                                    message: format!(
+                                        "Nesting too deep (more than {} levels)",
+                                        MAX_NESTING_DEPTH
+                                    ),
+                                    byte_offset: Some(start),
+                                    span: Some((start, end)),
+                                }));
+                            }
+                        }
+                        ParserToken::RParen | ParserToken::RBracket | ParserToken::RBrace => {
+                            self.depth = self.depth.saturating_sub(1);
+                        }
+                        _ => {}
+                    }
                     return Some(Ok((start, parser_token, end)));
                 }
                 Ok(None) => return None,
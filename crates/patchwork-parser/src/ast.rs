@@ -1,16 +1,105 @@
 /// Abstract Syntax Tree types for patchwork
 ///
 /// These types represent the parsed structure of patchwork programs.
-/// All types carry a lifetime 'input for zero-copy string slices.
+/// All types carry a lifetime 'input: the parser borrows names and text from
+/// the source, and `IntoStatic` copies them out when a tree has to outlive it.
+///
+/// Every item, statement, pattern, type expression, and expression in the
+/// tree is wrapped in `Spanned`, which records the byte range of source it
+/// was parsed from.
+
+use std::borrow::Cow;
+use std::fmt;
+use std::ops::{Deref, DerefMut};
+
+use serde::{Deserialize, Serialize};
+
+/// A byte range of source text, `start` inclusive and `end` exclusive.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Span {
+    pub fn new(start: usize, end: usize) -> Self {
+        Span { start, end }
+    }
+
+    /// 1-based line and column of the start of the span in `source`.
+    pub fn line_col(&self, source: &str) -> (usize, usize) {
+        let before = &source[..self.start.min(source.len())];
+        let line = before.matches('\n').count() + 1;
+        let column = before.rfind('\n').map_or(before.len(), |newline| before.len() - newline - 1) + 1;
+        (line, column)
+    }
+}
+
+/// A syntax node and the span of source it was parsed from.
+///
+/// Derefs to the node, so it can be passed wherever the node is expected;
+/// match on `node` to look inside. Equality ignores spans, so trees compare
+/// by structure whatever the layout of their source.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Spanned<T> {
+    pub node: T,
+    pub span: Span,
+}
+
+impl<T> Spanned<T> {
+    pub fn new(node: T, start: usize, end: usize) -> Self {
+        Spanned { node, span: Span::new(start, end) }
+    }
+}
+
+/// Nodes built outside the parser have no source, and an empty span.
+impl<T> From<T> for Spanned<T> {
+    fn from(node: T) -> Self {
+        Spanned { node, span: Span::default() }
+    }
+}
+
+impl<T> Deref for Spanned<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.node
+    }
+}
+
+impl<T> DerefMut for Spanned<T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.node
+    }
+}
+
+impl<T: PartialEq> PartialEq for Spanned<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.node == other.node
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for Spanned<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.node.fmt(f)?;
+        write!(f, " @{}..{}", self.span.start, self.span.end)
+    }
+}
+
+impl<T: fmt::Display> fmt::Display for Spanned<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.node.fmt(f)
+    }
+}
 
 /// A complete patchwork program
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Program<'input> {
-    pub items: Vec<Item<'input>>,
+    pub items: Vec<Spanned<Item<'input>>>,
 }
 
-/// Top-level item (import, skill, worker, trait, function, or type declaration)
-#[derive(Debug, Clone, PartialEq)]
+/// Top-level item (import, skill, worker, trait, function, type declaration, or config block)
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub enum Item<'input> {
     Import(ImportDecl<'input>),
     Skill(SkillDecl<'input>),
@@ -18,184 +107,277 @@ pub enum Item<'input> {
     Trait(TraitDecl<'input>),
     Function(FunctionDecl<'input>),
     Type(TypeDeclItem<'input>),
+    Config(ConfigDecl<'input>),
+    Example(ExampleDecl<'input>),
+    Fragment(FragmentDecl<'input>),
 }
 
 /// Import declaration: `import std.log` or `import ./{analyst, narrator}`
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct ImportDecl<'input> {
     pub path: ImportPath<'input>,
 }
 
 /// Import path - either simple dotted path or relative multi-import
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub enum ImportPath<'input> {
     /// Simple path: `std.log` or `./foo`
-    Simple(Vec<&'input str>),
+    Simple(Vec<Cow<'input, str>>),
     /// Relative multi-import: `./{analyst, narrator, scribe}`
-    RelativeMulti(Vec<&'input str>),
+    RelativeMulti(Vec<Cow<'input, str>>),
 }
 
 /// Skill declaration: `skill name(params) { body }`
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct SkillDecl<'input> {
-    pub name: &'input str,
-    pub params: Vec<Param<'input>>,
+    pub name: Cow<'input, str>,
+    pub params: Vec<Spanned<Param<'input>>>,
     pub body: Block<'input>,
+    pub annotations: Vec<Spanned<Annotation<'input>>>,
     pub is_exported: bool,
     pub is_default: bool,
 }
 
 /// Worker declaration: `worker name(params) { body }`
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct WorkerDecl<'input> {
-    pub name: &'input str,
-    pub params: Vec<Param<'input>>,
+    pub name: Cow<'input, str>,
+    pub params: Vec<Spanned<Param<'input>>>,
     pub body: Block<'input>,
+    pub annotations: Vec<Spanned<Annotation<'input>>>,
     pub is_exported: bool,
     pub is_default: bool,
 }
 
 /// Trait declaration: `trait name { methods }` or `trait name: super_trait { methods }`
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct TraitDecl<'input> {
-    pub name: &'input str,
-    pub super_trait: Option<TypeExpr<'input>>,
-    pub methods: Vec<FunctionDecl<'input>>,
+    pub name: Cow<'input, str>,
+    pub super_trait: Option<Spanned<TypeExpr<'input>>>,
+    pub methods: Vec<Spanned<FunctionDecl<'input>>>,
+    pub annotations: Vec<Spanned<Annotation<'input>>>,
     pub is_exported: bool,
     pub is_default: bool,
 }
 
 /// Function declaration: `fun name(params) { body }`
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct FunctionDecl<'input> {
-    pub name: &'input str,
-    pub params: Vec<Param<'input>>,
+    pub name: Cow<'input, str>,
+    pub params: Vec<Spanned<Param<'input>>>,
     pub body: Block<'input>,
-    pub annotations: Vec<Annotation<'input>>,
+    pub annotations: Vec<Spanned<Annotation<'input>>>,
     pub is_exported: bool,
     pub is_default: bool,
+    /// `memo fun`: results are cached by argument values for the run
+    pub is_memo: bool,
 }
 
-/// Annotation: `@skill` or `@command`
-#[derive(Debug, Clone, PartialEq)]
+/// Annotation on a declaration: `@skill`, `@command narrate`, `@color purple`
+///
+/// Any declaration can carry annotations; `check` validates the names it
+/// knows and where they may appear.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Annotation<'input> {
-    pub name: &'input str,
-    pub arg: Option<&'input str>,
+    pub name: Cow<'input, str>,
+    pub arg: Option<Cow<'input, str>>,
 }
 
 /// Type declaration: `type name = TypeExpr`
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct TypeDeclItem<'input> {
-    pub name: &'input str,
-    pub type_expr: TypeExpr<'input>,
+    pub name: Cow<'input, str>,
+    pub type_expr: Spanned<TypeExpr<'input>>,
+    pub annotations: Vec<Spanned<Annotation<'input>>>,
+}
+
+/// Module configuration: `config { model: "sonnet", max_retries: 3 }`
+///
+/// Functions in the module read it as a constant `config` object.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ConfigDecl<'input> {
+    pub fields: Vec<ObjectField<'input>>,
+}
+
+/// A few-shot example for think blocks: `example review_ok { ... }`
+///
+/// The body is prompt text. A think or ask block names the examples it
+/// wants with `think with examples [review_ok] { ... }`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ExampleDecl<'input> {
+    pub name: Cow<'input, str>,
+    pub items: Vec<PromptItem<'input>>,
+}
+
+/// Reusable prompt text: `prompt fragment guidelines { ... }`
+///
+/// Think and ask blocks splice it in with `${guidelines}`, unless a variable
+/// of that name is in scope.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FragmentDecl<'input> {
+    pub name: Cow<'input, str>,
+    pub items: Vec<PromptItem<'input>>,
 }
 
 /// Function/task/skill parameter
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Param<'input> {
-    pub name: &'input str,
-    pub type_ann: Option<TypeExpr<'input>>,
+    pub name: Cow<'input, str>,
+    pub type_ann: Option<Spanned<TypeExpr<'input>>>,
 }
 
 /// Block of statements: `{ stmt1; stmt2; ... }`
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Block<'input> {
-    pub statements: Vec<Statement<'input>>,
+    pub statements: Vec<Spanned<Statement<'input>>>,
 }
 
 /// Pattern for destructuring in variable declarations
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub enum Pattern<'input> {
     /// Simple identifier pattern: `var x = ...` or `var x: type = ...`
     Identifier {
-        name: &'input str,
-        type_ann: Option<TypeExpr<'input>>,
+        name: Cow<'input, str>,
+        type_ann: Option<Spanned<TypeExpr<'input>>>,
     },
     /// Ignore pattern: `var _ = ...`
     Ignore,
     /// Object destructuring pattern: `var {x, y} = ...`
     Object(Vec<ObjectPatternField<'input>>),
     /// Array destructuring pattern: `var [x, y, z] = ...`
-    Array(Vec<Pattern<'input>>),
+    Array(Vec<Spanned<Pattern<'input>>>),
 }
 
 /// Field in an object destructuring pattern
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct ObjectPatternField<'input> {
     /// Key name in the object being destructured
-    pub key: &'input str,
+    pub key: Cow<'input, str>,
     /// Optional nested pattern (for now just identifier, could expand later)
-    pub pattern: Pattern<'input>,
+    pub pattern: Spanned<Pattern<'input>>,
     /// Optional type annotation for this field
-    pub type_ann: Option<TypeExpr<'input>>,
+    pub type_ann: Option<Spanned<TypeExpr<'input>>>,
 }
 
 /// Statement in a block
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub enum Statement<'input> {
     /// Variable declaration: `var x = expr` or `var {x, y} = expr`
     VarDecl {
-        pattern: Pattern<'input>,
-        init: Option<Expr<'input>>,
+        pattern: Spanned<Pattern<'input>>,
+        init: Option<Spanned<Expr<'input>>>,
+    },
+    /// Constant declaration: `const x = expr`; the names it binds can't be
+    /// assigned to afterwards
+    ConstDecl {
+        pattern: Spanned<Pattern<'input>>,
+        init: Spanned<Expr<'input>>,
+    },
+    /// Time-limited statement: `@timeout(30s) stmt`; shell commands and think
+    /// blocks it runs fail once the limit has passed
+    Timeout {
+        limit: Spanned<Expr<'input>>,
+        body: Box<Spanned<Statement<'input>>>,
     },
     /// Expression statement (expression used as statement)
-    Expr(Expr<'input>),
+    Expr(Spanned<Expr<'input>>),
     /// If statement: `if expr { ... } else { ... }`
     If {
-        condition: Expr<'input>,
+        condition: Spanned<Expr<'input>>,
         then_block: Block<'input>,
         else_block: Option<Block<'input>>,
     },
     /// For-in loop: `for var x in expr { ... }`
+    ///
+    /// Each item is bound to `pattern`, so `for var {key, value} in obj`
+    /// destructures the entries an object iterates as.
     ForIn {
-        var: &'input str,
-        iter: Expr<'input>,
+        pattern: Spanned<Pattern<'input>>,
+        iter: Spanned<Expr<'input>>,
         body: Block<'input>,
     },
     /// While loop: `while (expr) { ... }`
     While {
-        condition: Expr<'input>,
+        condition: Spanned<Expr<'input>>,
         body: Block<'input>,
     },
+    /// While-var loop: `while var msg = expr { ... }`
+    ///
+    /// Re-evaluates `init` before each iteration and binds it to `pattern`;
+    /// the loop ends when the value is null.
+    WhileVar {
+        pattern: Spanned<Pattern<'input>>,
+        init: Spanned<Expr<'input>>,
+        body: Block<'input>,
+    },
+    /// Try statement: `try { ... } catch (e) { ... } finally { ... }`
+    ///
+    /// At least one of `catch` and `finally_block` is present.
+    Try {
+        body: Block<'input>,
+        catch: Option<CatchClause<'input>>,
+        finally_block: Option<Block<'input>>,
+    },
     /// Return statement: `return` or `return expr`
-    Return(Option<Expr<'input>>),
+    Return(Option<Spanned<Expr<'input>>>),
     /// Succeed statement (for tasks): `succeed`
     Succeed,
     /// Break statement (for loops): `break`
     Break,
     /// Type declaration: `type Foo = { ... }`
     TypeDecl {
-        name: &'input str,
-        type_expr: TypeExpr<'input>,
+        name: Cow<'input, str>,
+        type_expr: Spanned<TypeExpr<'input>>,
     },
 }
 
+/// The `catch (e) { ... }` clause of a try statement
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CatchClause<'input> {
+    /// The name the caught value is bound to, if the clause names one
+    pub var: Option<Cow<'input, str>>,
+    pub body: Block<'input>,
+}
+
 /// Type expression
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub enum TypeExpr<'input> {
     /// Simple type name: `string`, `int`, etc.
-    Name(&'input str),
+    Name(Cow<'input, str>),
     /// Object type: `{ x: string, y: int }`
     Object(Vec<TypeField<'input>>),
     /// Array type: `[string]`
-    Array(Box<TypeExpr<'input>>),
+    Array(Box<Spanned<TypeExpr<'input>>>),
     /// Union type: `"success" | "error"` or `string | int`
-    Union(Vec<TypeExpr<'input>>),
+    Union(Vec<Spanned<TypeExpr<'input>>>),
     /// String literal type: `"success"`
-    Literal(&'input str),
+    Literal(Cow<'input, str>),
+    /// Generic container type: `list<T>`, `map<string, T>`, `option<T>`
+    Generic {
+        name: Cow<'input, str>,
+        args: Vec<Spanned<TypeExpr<'input>>>,
+    },
+    /// Tagged variant type: `Success { hash: string }`
+    ///
+    /// A union of variants forms a tagged union:
+    /// `type result = Success { hash: string } | Failure { reason: string }`
+    Variant {
+        tag: Cow<'input, str>,
+        fields: Vec<TypeField<'input>>,
+    },
 }
 
 /// Field in an object type
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct TypeField<'input> {
-    pub key: &'input str,
-    pub type_expr: TypeExpr<'input>,
+    pub key: Cow<'input, str>,
+    pub type_expr: Spanned<TypeExpr<'input>>,
     /// For future optional field syntax `key?: type`
     pub optional: bool,
 }
 
 /// Binary operator
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub enum BinOp {
     // Arithmetic
     Add,      // +
@@ -217,7 +399,7 @@ pub enum BinOp {
 }
 
 /// Unary operator
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub enum UnOp {
     Not,      // !
     Neg,      // -
@@ -225,32 +407,32 @@ pub enum UnOp {
 }
 
 /// String literal with interpolation support
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct StringLiteral<'input> {
     /// Parts of the string - mixture of text and interpolated expressions
     pub parts: Vec<StringPart<'input>>,
 }
 
 /// Part of a string literal - either text or an interpolated expression
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub enum StringPart<'input> {
     /// Plain text: `"hello"` or text between interpolations
-    Text(&'input str),
+    Text(Cow<'input, str>),
     /// Interpolated expression: `${expr}`, `$(cmd)`, or `$id`
-    Interpolation(Box<Expr<'input>>),
+    Interpolation(Box<Spanned<Expr<'input>>>),
 }
 
 /// Command argument - either a literal string or an interpolated string
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub enum CommandArg<'input> {
     /// Literal argument: `mkdir -p work_dir` → "-p" and "work_dir"
-    Literal(&'input str),
+    Literal(Cow<'input, str>),
     /// Interpolated string argument: `mkdir "${dir}"` → String with interpolation
     String(StringLiteral<'input>),
 }
 
 /// Redirection operator for shell-style I/O redirection
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub enum RedirectOp {
     /// Standard output redirection: `>`
     Out,
@@ -265,12 +447,16 @@ pub enum RedirectOp {
 }
 
 /// Expression
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub enum Expr<'input> {
     /// Identifier reference: `foo`
-    Identifier(&'input str),
+    Identifier(Cow<'input, str>),
     /// Number literal: `42`, `3.14`
-    Number(&'input str),
+    Number(Cow<'input, str>),
+    /// Duration literal: `200ms`, `5s`, `2m`, `1h`
+    Duration(Cow<'input, str>),
+    /// Size literal: `512b`, `10kb`, `2mb`, `1gb`
+    Size(Cow<'input, str>),
     /// String literal: `"hello"`
     String(StringLiteral<'input>),
     /// Boolean literal: `true`
@@ -278,101 +464,607 @@ pub enum Expr<'input> {
     /// Boolean literal: `false`
     False,
     /// Array literal: `[1, 2, 3]`
-    Array(Vec<Expr<'input>>),
+    Array(Vec<Spanned<Expr<'input>>>),
     /// Object literal: `{x: 1, y: 2}` or `{x, y}` (shorthand)
     Object(Vec<ObjectField<'input>>),
+    /// Tagged variant construction: `Success{hash: h}` (no space before `{`)
+    Variant {
+        tag: Cow<'input, str>,
+        fields: Vec<ObjectField<'input>>,
+    },
     /// Binary operation: `a + b`, `x == y`
     Binary {
         op: BinOp,
-        left: Box<Expr<'input>>,
-        right: Box<Expr<'input>>,
+        left: Box<Spanned<Expr<'input>>>,
+        right: Box<Spanned<Expr<'input>>>,
     },
     /// Unary operation: `!x`, `-5`
     Unary {
         op: UnOp,
-        operand: Box<Expr<'input>>,
+        operand: Box<Spanned<Expr<'input>>>,
     },
     /// Function call: `foo(a, b, c)`
     Call {
-        callee: Box<Expr<'input>>,
-        args: Vec<Expr<'input>>,
+        callee: Box<Spanned<Expr<'input>>>,
+        args: Vec<Spanned<Expr<'input>>>,
     },
     /// Member access: `obj.field`
     Member {
-        object: Box<Expr<'input>>,
-        field: &'input str,
+        object: Box<Spanned<Expr<'input>>>,
+        field: Cow<'input, str>,
     },
     /// Index access: `arr[i]`
     Index {
-        object: Box<Expr<'input>>,
-        index: Box<Expr<'input>>,
+        object: Box<Spanned<Expr<'input>>>,
+        index: Box<Spanned<Expr<'input>>>,
     },
     /// Postfix increment: `x++`
-    PostIncrement(Box<Expr<'input>>),
+    PostIncrement(Box<Spanned<Expr<'input>>>),
     /// Postfix decrement: `x--`
-    PostDecrement(Box<Expr<'input>>),
+    PostDecrement(Box<Spanned<Expr<'input>>>),
     /// Parenthesized expression: `(expr)`
-    Paren(Box<Expr<'input>>),
+    Paren(Box<Spanned<Expr<'input>>>),
     /// Await expression: `expr.await`
-    Await(Box<Expr<'input>>),
+    Await(Box<Spanned<Expr<'input>>>),
+    /// Await several tasks at once: `await all [a, b]`
+    AwaitAll(Box<Spanned<Expr<'input>>>),
     /// Think expression: `think { ... }`
     Think(PromptBlock<'input>),
     /// Ask expression: `ask { ... }`
     Ask(PromptBlock<'input>),
     /// Do expression: `do { ... }`
     Do(Block<'input>),
+    /// Match expression: `match status { "success" => { ... } _ => { ... } }`
+    ///
+    /// The first arm whose pattern fits the subject runs, and the match takes
+    /// the value of its block.
+    Match {
+        subject: Box<Spanned<Expr<'input>>>,
+        arms: Vec<MatchArm<'input>>,
+    },
+    /// Anonymous function: `fun(x) { x * 2 }`
+    ///
+    /// Evaluates to a closure holding the variables in scope where it's
+    /// written.
+    Lambda {
+        params: Vec<Spanned<Param<'input>>>,
+        body: Block<'input>,
+    },
     /// Bare command invocation: `mkdir -p work_dir`
     BareCommand {
-        name: &'input str,
+        name: Cow<'input, str>,
         args: Vec<CommandArg<'input>>,
     },
     /// Command substitution: `$(shell_expr)`
     /// Executes shell expression and returns stdout as string
-    CommandSubst(Box<Expr<'input>>),
+    CommandSubst(Box<Spanned<Expr<'input>>>),
     /// Shell pipe: `cmd1 | cmd2`
     ShellPipe {
-        left: Box<Expr<'input>>,
-        right: Box<Expr<'input>>,
+        left: Box<Spanned<Expr<'input>>>,
+        right: Box<Spanned<Expr<'input>>>,
     },
     /// Shell logical and: `cmd1 && cmd2`
     ShellAnd {
-        left: Box<Expr<'input>>,
-        right: Box<Expr<'input>>,
+        left: Box<Spanned<Expr<'input>>>,
+        right: Box<Spanned<Expr<'input>>>,
     },
     /// Shell logical or: `cmd1 || cmd2`
     ShellOr {
-        left: Box<Expr<'input>>,
-        right: Box<Expr<'input>>,
+        left: Box<Spanned<Expr<'input>>>,
+        right: Box<Spanned<Expr<'input>>>,
     },
     /// Shell redirect: `cmd > file` or `cmd 2> file`
     ShellRedirect {
-        command: Box<Expr<'input>>,
+        command: Box<Spanned<Expr<'input>>>,
         op: RedirectOp,
-        target: Box<Expr<'input>>,
+        target: Box<Spanned<Expr<'input>>>,
+    },
+}
+
+/// One arm of a match expression: `pattern => { ... }`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MatchArm<'input> {
+    pub pattern: Spanned<MatchPattern<'input>>,
+    pub body: Block<'input>,
+}
+
+/// What a match arm tests its subject against
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum MatchPattern<'input> {
+    /// A string, number or boolean literal the subject must equal
+    Literal(Spanned<Expr<'input>>),
+    /// A type the subject must have, bound to `name` unless that's `_`:
+    /// `s: string`, `r: Success`. A name that isn't a type matches a variant
+    /// with that tag.
+    Type {
+        name: Option<Cow<'input, str>>,
+        type_expr: Spanned<TypeExpr<'input>>,
     },
+    /// Any subject, bound to a name: `other`
+    Binding(Cow<'input, str>),
+    /// `_`: any subject
+    Wildcard,
 }
 
 /// Object field in an object literal
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct ObjectField<'input> {
-    pub key: &'input str,
+    pub key: Spanned<Cow<'input, str>>,
     /// Value expression - None for shorthand syntax `{x}` meaning `{x: x}`
-    pub value: Option<Expr<'input>>,
+    pub value: Option<Spanned<Expr<'input>>>,
 }
 
 /// Prompt block content - mixture of text and embedded code
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct PromptBlock<'input> {
     pub items: Vec<PromptItem<'input>>,
+    /// Named alternative texts: `think { ... } variant "v2" { ... }`
+    pub variants: Vec<PromptVariant<'input>>,
+    /// Module-level examples spliced in ahead of the text:
+    /// `think with examples [a, b] { ... }`
+    pub examples: Vec<Spanned<Cow<'input, str>>>,
+    /// A check on the answer that re-asks when it fails:
+    /// `think { ... } validate (r) { r.commits.length > 0 }`
+    pub validator: Option<Box<PromptValidator<'input>>>,
+    /// The tools the agent may use while answering, as an array of names:
+    /// `think(tools: ["read_file", "grep"]) { ... }`
+    pub tools: Option<Box<Spanned<Expr<'input>>>>,
+    /// Whether the block opens a conversation the program can reply to:
+    /// `think.start { ... }`
+    pub conversation: bool,
+}
+
+/// The `validate` clause of a think or ask block. The body runs with the
+/// answer bound to `param`; a falsy result or a thrown value fails the check.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PromptValidator<'input> {
+    pub param: Cow<'input, str>,
+    /// How many times to re-ask before giving up: `validate (r) retries 3 { ... }`.
+    /// The runtime's default when absent.
+    pub retries: Option<Cow<'input, str>>,
+    pub body: Block<'input>,
+}
+
+/// A named alternative text for a prompt block, chosen by the runtime's
+/// variant policy.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PromptVariant<'input> {
+    pub name: Cow<'input, str>,
+    pub items: Vec<PromptItem<'input>>,
 }
 
 /// Item within a prompt block
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub enum PromptItem<'input> {
     /// Raw prompt text
-    Text(&'input str),
+    Text(Cow<'input, str>),
     /// Variable or expression interpolation: `$var` or `${expr}`
-    Interpolation(Expr<'input>),
+    Interpolation(Spanned<Expr<'input>>),
     /// Embedded code block: `do { ... }`
     Code(Block<'input>),
 }
+
+/// Copy the text a tree borrows from its source, so the tree can outlive it.
+pub trait IntoStatic {
+    type Static: 'static;
+
+    fn into_static(self) -> Self::Static;
+}
+
+impl IntoStatic for Cow<'_, str> {
+    type Static = Cow<'static, str>;
+
+    fn into_static(self) -> Self::Static {
+        Cow::Owned(self.into_owned())
+    }
+}
+
+impl<T: IntoStatic> IntoStatic for Spanned<T> {
+    type Static = Spanned<T::Static>;
+
+    fn into_static(self) -> Self::Static {
+        Spanned { node: self.node.into_static(), span: self.span }
+    }
+}
+
+impl<T: IntoStatic> IntoStatic for Box<T> {
+    type Static = Box<T::Static>;
+
+    fn into_static(self) -> Self::Static {
+        Box::new((*self).into_static())
+    }
+}
+
+impl<T: IntoStatic> IntoStatic for Option<T> {
+    type Static = Option<T::Static>;
+
+    fn into_static(self) -> Self::Static {
+        self.map(T::into_static)
+    }
+}
+
+impl<T: IntoStatic> IntoStatic for Vec<T> {
+    type Static = Vec<T::Static>;
+
+    fn into_static(self) -> Self::Static {
+        self.into_iter().map(T::into_static).collect()
+    }
+}
+
+impl IntoStatic for Program<'_> {
+    type Static = Program<'static>;
+
+    fn into_static(self) -> Self::Static {
+        Program { items: self.items.into_static() }
+    }
+}
+
+impl IntoStatic for Item<'_> {
+    type Static = Item<'static>;
+
+    fn into_static(self) -> Self::Static {
+        match self {
+            Item::Import(decl) => Item::Import(ImportDecl { path: decl.path.into_static() }),
+            Item::Skill(decl) => Item::Skill(decl.into_static()),
+            Item::Worker(decl) => Item::Worker(decl.into_static()),
+            Item::Trait(decl) => Item::Trait(decl.into_static()),
+            Item::Function(decl) => Item::Function(decl.into_static()),
+            Item::Type(decl) => Item::Type(TypeDeclItem {
+                name: decl.name.into_static(),
+                type_expr: decl.type_expr.into_static(),
+                annotations: decl.annotations.into_static(),
+            }),
+            Item::Config(decl) => Item::Config(ConfigDecl { fields: decl.fields.into_static() }),
+            Item::Example(decl) => Item::Example(decl.into_static()),
+            Item::Fragment(decl) => Item::Fragment(FragmentDecl {
+                name: decl.name.into_static(),
+                items: decl.items.into_static(),
+            }),
+        }
+    }
+}
+
+impl IntoStatic for ImportPath<'_> {
+    type Static = ImportPath<'static>;
+
+    fn into_static(self) -> Self::Static {
+        match self {
+            ImportPath::Simple(parts) => ImportPath::Simple(parts.into_static()),
+            ImportPath::RelativeMulti(names) => ImportPath::RelativeMulti(names.into_static()),
+        }
+    }
+}
+
+impl IntoStatic for SkillDecl<'_> {
+    type Static = SkillDecl<'static>;
+
+    fn into_static(self) -> Self::Static {
+        SkillDecl {
+            name: self.name.into_static(),
+            params: self.params.into_static(),
+            body: self.body.into_static(),
+            annotations: self.annotations.into_static(),
+            is_exported: self.is_exported,
+            is_default: self.is_default,
+        }
+    }
+}
+
+impl IntoStatic for WorkerDecl<'_> {
+    type Static = WorkerDecl<'static>;
+
+    fn into_static(self) -> Self::Static {
+        WorkerDecl {
+            name: self.name.into_static(),
+            params: self.params.into_static(),
+            body: self.body.into_static(),
+            annotations: self.annotations.into_static(),
+            is_exported: self.is_exported,
+            is_default: self.is_default,
+        }
+    }
+}
+
+impl IntoStatic for TraitDecl<'_> {
+    type Static = TraitDecl<'static>;
+
+    fn into_static(self) -> Self::Static {
+        TraitDecl {
+            name: self.name.into_static(),
+            super_trait: self.super_trait.into_static(),
+            methods: self.methods.into_static(),
+            annotations: self.annotations.into_static(),
+            is_exported: self.is_exported,
+            is_default: self.is_default,
+        }
+    }
+}
+
+impl IntoStatic for FunctionDecl<'_> {
+    type Static = FunctionDecl<'static>;
+
+    fn into_static(self) -> Self::Static {
+        FunctionDecl {
+            name: self.name.into_static(),
+            params: self.params.into_static(),
+            body: self.body.into_static(),
+            annotations: self.annotations.into_static(),
+            is_exported: self.is_exported,
+            is_default: self.is_default,
+            is_memo: self.is_memo,
+        }
+    }
+}
+
+impl IntoStatic for Annotation<'_> {
+    type Static = Annotation<'static>;
+
+    fn into_static(self) -> Self::Static {
+        Annotation { name: self.name.into_static(), arg: self.arg.into_static() }
+    }
+}
+
+impl IntoStatic for ExampleDecl<'_> {
+    type Static = ExampleDecl<'static>;
+
+    fn into_static(self) -> Self::Static {
+        ExampleDecl { name: self.name.into_static(), items: self.items.into_static() }
+    }
+}
+
+impl IntoStatic for Param<'_> {
+    type Static = Param<'static>;
+
+    fn into_static(self) -> Self::Static {
+        Param { name: self.name.into_static(), type_ann: self.type_ann.into_static() }
+    }
+}
+
+impl IntoStatic for Block<'_> {
+    type Static = Block<'static>;
+
+    fn into_static(self) -> Self::Static {
+        Block { statements: self.statements.into_static() }
+    }
+}
+
+impl IntoStatic for Pattern<'_> {
+    type Static = Pattern<'static>;
+
+    fn into_static(self) -> Self::Static {
+        match self {
+            Pattern::Identifier { name, type_ann } => {
+                Pattern::Identifier { name: name.into_static(), type_ann: type_ann.into_static() }
+            }
+            Pattern::Ignore => Pattern::Ignore,
+            Pattern::Object(fields) => Pattern::Object(fields.into_static()),
+            Pattern::Array(items) => Pattern::Array(items.into_static()),
+        }
+    }
+}
+
+impl IntoStatic for ObjectPatternField<'_> {
+    type Static = ObjectPatternField<'static>;
+
+    fn into_static(self) -> Self::Static {
+        ObjectPatternField {
+            key: self.key.into_static(),
+            pattern: self.pattern.into_static(),
+            type_ann: self.type_ann.into_static(),
+        }
+    }
+}
+
+impl IntoStatic for Statement<'_> {
+    type Static = Statement<'static>;
+
+    fn into_static(self) -> Self::Static {
+        match self {
+            Statement::VarDecl { pattern, init } => {
+                Statement::VarDecl { pattern: pattern.into_static(), init: init.into_static() }
+            }
+            Statement::ConstDecl { pattern, init } => {
+                Statement::ConstDecl { pattern: pattern.into_static(), init: init.into_static() }
+            }
+            Statement::Timeout { limit, body } => {
+                Statement::Timeout { limit: limit.into_static(), body: body.into_static() }
+            }
+            Statement::Expr(expr) => Statement::Expr(expr.into_static()),
+            Statement::If { condition, then_block, else_block } => Statement::If {
+                condition: condition.into_static(),
+                then_block: then_block.into_static(),
+                else_block: else_block.into_static(),
+            },
+            Statement::ForIn { pattern, iter, body } => Statement::ForIn {
+                pattern: pattern.into_static(),
+                iter: iter.into_static(),
+                body: body.into_static(),
+            },
+            Statement::While { condition, body } => {
+                Statement::While { condition: condition.into_static(), body: body.into_static() }
+            }
+            Statement::WhileVar { pattern, init, body } => Statement::WhileVar {
+                pattern: pattern.into_static(),
+                init: init.into_static(),
+                body: body.into_static(),
+            },
+            Statement::Try { body, catch, finally_block } => Statement::Try {
+                body: body.into_static(),
+                catch: catch.map(|catch| CatchClause { var: catch.var.into_static(), body: catch.body.into_static() }),
+                finally_block: finally_block.into_static(),
+            },
+            Statement::Return(expr) => Statement::Return(expr.into_static()),
+            Statement::Succeed => Statement::Succeed,
+            Statement::Break => Statement::Break,
+            Statement::TypeDecl { name, type_expr } => {
+                Statement::TypeDecl { name: name.into_static(), type_expr: type_expr.into_static() }
+            }
+        }
+    }
+}
+
+impl IntoStatic for TypeExpr<'_> {
+    type Static = TypeExpr<'static>;
+
+    fn into_static(self) -> Self::Static {
+        match self {
+            TypeExpr::Name(name) => TypeExpr::Name(name.into_static()),
+            TypeExpr::Object(fields) => TypeExpr::Object(fields.into_static()),
+            TypeExpr::Array(element) => TypeExpr::Array(element.into_static()),
+            TypeExpr::Union(members) => TypeExpr::Union(members.into_static()),
+            TypeExpr::Literal(text) => TypeExpr::Literal(text.into_static()),
+            TypeExpr::Generic { name, args } => TypeExpr::Generic { name: name.into_static(), args: args.into_static() },
+            TypeExpr::Variant { tag, fields } => TypeExpr::Variant { tag: tag.into_static(), fields: fields.into_static() },
+        }
+    }
+}
+
+impl IntoStatic for TypeField<'_> {
+    type Static = TypeField<'static>;
+
+    fn into_static(self) -> Self::Static {
+        TypeField { key: self.key.into_static(), type_expr: self.type_expr.into_static(), optional: self.optional }
+    }
+}
+
+impl IntoStatic for StringLiteral<'_> {
+    type Static = StringLiteral<'static>;
+
+    fn into_static(self) -> Self::Static {
+        StringLiteral { parts: self.parts.into_static() }
+    }
+}
+
+impl IntoStatic for StringPart<'_> {
+    type Static = StringPart<'static>;
+
+    fn into_static(self) -> Self::Static {
+        match self {
+            StringPart::Text(text) => StringPart::Text(text.into_static()),
+            StringPart::Interpolation(expr) => StringPart::Interpolation(expr.into_static()),
+        }
+    }
+}
+
+impl IntoStatic for CommandArg<'_> {
+    type Static = CommandArg<'static>;
+
+    fn into_static(self) -> Self::Static {
+        match self {
+            CommandArg::Literal(text) => CommandArg::Literal(text.into_static()),
+            CommandArg::String(literal) => CommandArg::String(literal.into_static()),
+        }
+    }
+}
+
+impl IntoStatic for Expr<'_> {
+    type Static = Expr<'static>;
+
+    fn into_static(self) -> Self::Static {
+        match self {
+            Expr::Identifier(name) => Expr::Identifier(name.into_static()),
+            Expr::Number(text) => Expr::Number(text.into_static()),
+            Expr::Duration(text) => Expr::Duration(text.into_static()),
+            Expr::Size(text) => Expr::Size(text.into_static()),
+            Expr::String(literal) => Expr::String(literal.into_static()),
+            Expr::True => Expr::True,
+            Expr::False => Expr::False,
+            Expr::Array(items) => Expr::Array(items.into_static()),
+            Expr::Object(fields) => Expr::Object(fields.into_static()),
+            Expr::Variant { tag, fields } => Expr::Variant { tag: tag.into_static(), fields: fields.into_static() },
+            Expr::Binary { op, left, right } => {
+                Expr::Binary { op, left: left.into_static(), right: right.into_static() }
+            }
+            Expr::Unary { op, operand } => Expr::Unary { op, operand: operand.into_static() },
+            Expr::Call { callee, args } => Expr::Call { callee: callee.into_static(), args: args.into_static() },
+            Expr::Member { object, field } => Expr::Member { object: object.into_static(), field: field.into_static() },
+            Expr::Index { object, index } => Expr::Index { object: object.into_static(), index: index.into_static() },
+            Expr::PostIncrement(inner) => Expr::PostIncrement(inner.into_static()),
+            Expr::PostDecrement(inner) => Expr::PostDecrement(inner.into_static()),
+            Expr::Paren(inner) => Expr::Paren(inner.into_static()),
+            Expr::Await(inner) => Expr::Await(inner.into_static()),
+            Expr::AwaitAll(inner) => Expr::AwaitAll(inner.into_static()),
+            Expr::Think(prompt) => Expr::Think(prompt.into_static()),
+            Expr::Ask(prompt) => Expr::Ask(prompt.into_static()),
+            Expr::Do(block) => Expr::Do(block.into_static()),
+            Expr::Match { subject, arms } => Expr::Match { subject: subject.into_static(), arms: arms.into_static() },
+            Expr::Lambda { params, body } => Expr::Lambda { params: params.into_static(), body: body.into_static() },
+            Expr::BareCommand { name, args } => Expr::BareCommand { name: name.into_static(), args: args.into_static() },
+            Expr::CommandSubst(inner) => Expr::CommandSubst(inner.into_static()),
+            Expr::ShellPipe { left, right } => Expr::ShellPipe { left: left.into_static(), right: right.into_static() },
+            Expr::ShellAnd { left, right } => Expr::ShellAnd { left: left.into_static(), right: right.into_static() },
+            Expr::ShellOr { left, right } => Expr::ShellOr { left: left.into_static(), right: right.into_static() },
+            Expr::ShellRedirect { command, op, target } => {
+                Expr::ShellRedirect { command: command.into_static(), op, target: target.into_static() }
+            }
+        }
+    }
+}
+
+impl IntoStatic for MatchArm<'_> {
+    type Static = MatchArm<'static>;
+
+    fn into_static(self) -> Self::Static {
+        MatchArm { pattern: self.pattern.into_static(), body: self.body.into_static() }
+    }
+}
+
+impl IntoStatic for MatchPattern<'_> {
+    type Static = MatchPattern<'static>;
+
+    fn into_static(self) -> Self::Static {
+        match self {
+            MatchPattern::Literal(expr) => MatchPattern::Literal(expr.into_static()),
+            MatchPattern::Type { name, type_expr } => {
+                MatchPattern::Type { name: name.into_static(), type_expr: type_expr.into_static() }
+            }
+            MatchPattern::Binding(name) => MatchPattern::Binding(name.into_static()),
+            MatchPattern::Wildcard => MatchPattern::Wildcard,
+        }
+    }
+}
+
+impl IntoStatic for ObjectField<'_> {
+    type Static = ObjectField<'static>;
+
+    fn into_static(self) -> Self::Static {
+        ObjectField { key: self.key.into_static(), value: self.value.into_static() }
+    }
+}
+
+impl IntoStatic for PromptBlock<'_> {
+    type Static = PromptBlock<'static>;
+
+    fn into_static(self) -> Self::Static {
+        PromptBlock {
+            items: self.items.into_static(),
+            variants: self
+                .variants
+                .into_iter()
+                .map(|variant| PromptVariant { name: variant.name.into_static(), items: variant.items.into_static() })
+                .collect(),
+            examples: self.examples.into_static(),
+            validator: self.validator.map(|validator| {
+                Box::new(PromptValidator {
+                    param: validator.param.into_static(),
+                    retries: validator.retries.into_static(),
+                    body: validator.body.into_static(),
+                })
+            }),
+            tools: self.tools.into_static(),
+            conversation: self.conversation,
+        }
+    }
+}
+
+impl IntoStatic for PromptItem<'_> {
+    type Static = PromptItem<'static>;
+
+    fn into_static(self) -> Self::Static {
+        match self {
+            PromptItem::Text(text) => PromptItem::Text(text.into_static()),
+            PromptItem::Interpolation(expr) => PromptItem::Interpolation(expr.into_static()),
+            PromptItem::Code(block) => PromptItem::Code(block.into_static()),
+        }
+    }
+}
@@ -5,14 +5,214 @@
 use crate::ast::*;
 use std::fmt::Write as FmtWrite;
 
+/// Indentation past which `write_expr` elides the rest of the tree
+const MAX_DUMP_DEPTH: usize = 1024;
+
 /// Dump a program AST as a pretty-printed tree
 pub fn dump_program(program: &Program) -> String {
-    let mut out = String::new();
+    let mut out = Dumper::new(false);
     write_program(&mut out, program, 0).unwrap();
-    out
+    out.text
+}
+
+/// How `dump_program_with` renders the tree.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum DumpFormat {
+    /// The indented text tree `dump_program` prints
+    #[default]
+    Tree,
+    /// Nested `{"node", "span", "children"}` objects, pretty-printed
+    Json,
+    /// The same JSON on a single line
+    CompactJson,
+}
+
+/// Options for `dump_program_with`.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct DumpOptions {
+    pub format: DumpFormat,
+    /// Attach byte spans to the nodes that carry them.
+    pub spans: bool,
+    /// Drop nodes more than this many levels below `Program`.
+    pub max_depth: Option<usize>,
+    /// Only dump top-level items with this name.
+    pub item: Option<String>,
+}
+
+/// Dump a program as configured by `options`.
+///
+/// A line's span covers the node it shows. Items, parameters, statements,
+/// patterns, expressions and types carry spans; lines for parts of a node,
+/// such as `Params:` or `Then:`, have none.
+pub fn dump_program_with(program: &Program, options: &DumpOptions) -> String {
+    let mut out = Dumper::new(options.spans);
+    writeln!(out, "Program:").unwrap();
+    for item in &program.items {
+        if options.item.as_deref().is_none_or(|name| item_name(item) == Some(name)) {
+            write_item(&mut out, item, 1).unwrap();
+        }
+    }
+
+    let nodes = out.nodes(options.max_depth);
+    let mut rendered = String::new();
+    match options.format {
+        DumpFormat::Tree => render_tree(&mut rendered, &nodes),
+        DumpFormat::Json => render_json(&mut rendered, &nodes, Some(0)),
+        DumpFormat::CompactJson => render_json(&mut rendered, &nodes, None),
+    }
+    if options.format != DumpFormat::Tree {
+        rendered.push('\n');
+    }
+    rendered
+}
+
+/// The declared name of a top-level item; imports and config blocks have none.
+pub fn item_name<'a>(item: &'a Item) -> Option<&'a str> {
+    match item {
+        Item::Import(_) | Item::Config(_) => None,
+        Item::Skill(decl) => Some(&decl.name),
+        Item::Worker(decl) => Some(&decl.name),
+        Item::Trait(decl) => Some(&decl.name),
+        Item::Function(decl) => Some(&decl.name),
+        Item::Type(decl) => Some(&decl.name),
+        Item::Example(decl) => Some(&decl.name),
+        Item::Fragment(decl) => Some(&decl.name),
+    }
+}
+
+/// Text sink for the writers below, which also remembers the source span
+/// of each line that shows a spanned node.
+struct Dumper {
+    text: String,
+    /// Whether to remember spans at all
+    marking: bool,
+    lines: usize,
+    spans: Vec<(usize, (usize, usize))>,
+}
+
+impl Dumper {
+    fn new(marking: bool) -> Self {
+        Self { text: String::new(), marking, lines: 0, spans: Vec::new() }
+    }
+
+    /// Attach `span` to the next line written.
+    fn mark(&mut self, span: Span) {
+        // Nodes built outside the parser have an empty span
+        if self.marking && span != Span::default() {
+            self.spans.push((self.lines, (span.start, span.end)));
+        }
+    }
+
+    /// The written lines as a tree, cut off below `max_depth`.
+    fn nodes(&self, max_depth: Option<usize>) -> Vec<Node<'_>> {
+        let mut spans = self.spans.iter().peekable();
+        let mut nodes = Vec::new();
+        for (index, line) in self.text.lines().enumerate() {
+            let label = line.trim_start_matches(' ');
+            let span = spans.next_if(|(at, _)| *at == index).map(|(_, span)| *span);
+            nodes.push(Node { depth: (line.len() - label.len()) / 2, label, span });
+        }
+        if let Some(max) = max_depth {
+            // Keep one `...` line wherever children were dropped
+            nodes.dedup_by(|next, kept| next.depth > max && kept.depth > max);
+            for node in nodes.iter_mut().filter(|node| node.depth > max) {
+                *node = Node { depth: max + 1, label: "...", span: None };
+            }
+        }
+        nodes
+    }
+}
+
+impl FmtWrite for Dumper {
+    fn write_str(&mut self, s: &str) -> std::fmt::Result {
+        self.lines += s.matches('\n').count();
+        self.text.push_str(s);
+        Ok(())
+    }
+}
+
+/// One line of the dump: its nesting depth, text, and source span.
+struct Node<'a> {
+    depth: usize,
+    label: &'a str,
+    span: Option<(usize, usize)>,
+}
+
+fn render_tree(out: &mut String, nodes: &[Node]) {
+    for node in nodes {
+        out.push_str(&"  ".repeat(node.depth));
+        out.push_str(node.label);
+        if let Some((start, end)) = node.span {
+            let _ = write!(out, " @{}..{}", start, end);
+        }
+        out.push('\n');
+    }
+}
+
+/// Render `nodes[0]` and the nodes nested under it, which follow it in
+/// order. `indent` is `None` for single-line output.
+fn render_json(out: &mut String, nodes: &[Node], indent: Option<usize>) {
+    let (first, rest) = nodes.split_first().expect("the dump always has a Program line");
+    let newline = |out: &mut String, level: usize| {
+        if let Some(base) = indent {
+            out.push('\n');
+            out.push_str(&"  ".repeat(base + level));
+        }
+    };
+    let (separator, comma) = if indent.is_some() { (": ", ", ") } else { (":", ",") };
+
+    out.push('{');
+    newline(out, 1);
+    let _ = write!(out, "\"node\"{}{}", separator, json_string(first.label.trim_end().trim_end_matches(':')));
+    if let Some((start, end)) = first.span {
+        out.push(',');
+        newline(out, 1);
+        let _ = write!(out, "\"span\"{}[{}{}{}]", separator, start, comma, end);
+    }
+
+    let children: Vec<usize> = rest
+        .iter()
+        .enumerate()
+        .take_while(|(_, node)| node.depth > first.depth)
+        .filter(|(_, node)| node.depth == first.depth + 1)
+        .map(|(at, _)| at)
+        .collect();
+    if !children.is_empty() {
+        out.push(',');
+        newline(out, 1);
+        let _ = write!(out, "\"children\"{}[", separator);
+        for (n, &at) in children.iter().enumerate() {
+            if n > 0 {
+                out.push(',');
+            }
+            newline(out, 2);
+            render_json(out, &rest[at..], indent.map(|base| base + 2));
+        }
+        newline(out, 1);
+        out.push(']');
+    }
+    newline(out, 0);
+    out.push('}');
 }
 
-fn write_program(out: &mut String, program: &Program, indent: usize) -> std::fmt::Result {
+fn json_string(text: &str) -> String {
+    let mut quoted = String::from('"');
+    for c in text.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            c if (c as u32) < 0x20 => {
+                let _ = write!(quoted, "\\u{:04x}", c as u32);
+            }
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+fn write_program(out: &mut Dumper, program: &Program, indent: usize) -> std::fmt::Result {
     writeln!(out, "{}Program:", "  ".repeat(indent))?;
     for item in &program.items {
         write_item(out, item, indent + 1)?;
@@ -20,9 +220,10 @@ fn write_program(out: &mut String, program: &Program, indent: usize) -> std::fmt
     Ok(())
 }
 
-fn write_item(out: &mut String, item: &Item, indent: usize) -> std::fmt::Result {
+fn write_item(out: &mut Dumper, item: &Spanned<Item>, indent: usize) -> std::fmt::Result {
     let prefix = "  ".repeat(indent);
-    match item {
+    out.mark(item.span);
+    match &item.node {
         Item::Import(decl) => {
             writeln!(out, "{}Import:", prefix)?;
             write_import_path(out, &decl.path, indent + 1)?;
@@ -32,6 +233,7 @@ fn write_item(out: &mut String, item: &Item, indent: usize) -> std::fmt::Result
             if decl.is_exported { modifiers.push_str("export "); }
             if decl.is_default { modifiers.push_str("default "); }
             writeln!(out, "{}{}Skill: {}", prefix, modifiers, decl.name)?;
+            write_annotations(out, &decl.annotations, indent + 1)?;
             write_params(out, &decl.params, indent + 1)?;
             write_block(out, &decl.body, indent + 1)?;
         }
@@ -40,6 +242,7 @@ fn write_item(out: &mut String, item: &Item, indent: usize) -> std::fmt::Result
             if decl.is_exported { modifiers.push_str("export "); }
             if decl.is_default { modifiers.push_str("default "); }
             writeln!(out, "{}{}Worker: {}", prefix, modifiers, decl.name)?;
+            write_annotations(out, &decl.annotations, indent + 1)?;
             write_params(out, &decl.params, indent + 1)?;
             write_block(out, &decl.body, indent + 1)?;
         }
@@ -48,6 +251,7 @@ fn write_item(out: &mut String, item: &Item, indent: usize) -> std::fmt::Result
             if decl.is_exported { modifiers.push_str("export "); }
             if decl.is_default { modifiers.push_str("default "); }
             writeln!(out, "{}{}Trait: {}", prefix, modifiers, decl.name)?;
+            write_annotations(out, &decl.annotations, indent + 1)?;
             if let Some(super_trait) = &decl.super_trait {
                 writeln!(out, "{}  SuperTrait:", prefix)?;
                 write_type_expr(out, super_trait, indent + 2)?;
@@ -62,13 +266,33 @@ fn write_item(out: &mut String, item: &Item, indent: usize) -> std::fmt::Result
         }
         Item::Type(decl) => {
             writeln!(out, "{}Type: {} =", prefix, decl.name)?;
+            write_annotations(out, &decl.annotations, indent + 1)?;
             write_type_expr(out, &decl.type_expr, indent + 1)?;
         }
+        Item::Config(decl) => {
+            writeln!(out, "{}Config:", prefix)?;
+            for field in &decl.fields {
+                if let Some(value) = &field.value {
+                    writeln!(out, "{}  {}: ", prefix, field.key)?;
+                    write_expr(out, value, indent + 2)?;
+                } else {
+                    writeln!(out, "{}  {} (shorthand)", prefix, field.key)?;
+                }
+            }
+        }
+        Item::Example(decl) => {
+            writeln!(out, "{}Example: {}", prefix, decl.name)?;
+            write_prompt_items(out, &decl.items, indent + 1)?;
+        }
+        Item::Fragment(decl) => {
+            writeln!(out, "{}Fragment: {}", prefix, decl.name)?;
+            write_prompt_items(out, &decl.items, indent + 1)?;
+        }
     }
     Ok(())
 }
 
-fn write_import_path(out: &mut String, path: &ImportPath, indent: usize) -> std::fmt::Result {
+fn write_import_path(out: &mut Dumper, path: &ImportPath, indent: usize) -> std::fmt::Result {
     let prefix = "  ".repeat(indent);
     match path {
         ImportPath::Simple(parts) => {
@@ -81,24 +305,38 @@ fn write_import_path(out: &mut String, path: &ImportPath, indent: usize) -> std:
     Ok(())
 }
 
-fn write_function_decl(out: &mut String, decl: &FunctionDecl, indent: usize) -> std::fmt::Result {
+fn write_function_decl(out: &mut Dumper, decl: &FunctionDecl, indent: usize) -> std::fmt::Result {
     let prefix = "  ".repeat(indent);
     let mut modifiers = String::new();
     if decl.is_exported { modifiers.push_str("export "); }
     if decl.is_default { modifiers.push_str("default "); }
+    if decl.is_memo { modifiers.push_str("memo "); }
     writeln!(out, "{}{}Function: {}", prefix, modifiers, decl.name)?;
+    write_annotations(out, &decl.annotations, indent + 1)?;
     write_params(out, &decl.params, indent + 1)?;
     write_block(out, &decl.body, indent + 1)?;
     Ok(())
 }
 
-fn write_params(out: &mut String, params: &[Param], indent: usize) -> std::fmt::Result {
+fn write_annotations(out: &mut Dumper, annotations: &[Spanned<Annotation>], indent: usize) -> std::fmt::Result {
+    let prefix = "  ".repeat(indent);
+    for annotation in annotations {
+        match &annotation.node.arg {
+            Some(arg) => writeln!(out, "{}Annotation: @{} {}", prefix, annotation.node.name, arg)?,
+            None => writeln!(out, "{}Annotation: @{}", prefix, annotation.node.name)?,
+        }
+    }
+    Ok(())
+}
+
+fn write_params(out: &mut Dumper, params: &[Spanned<Param>], indent: usize) -> std::fmt::Result {
     let prefix = "  ".repeat(indent);
     if params.is_empty() {
         writeln!(out, "{}Params: (none)", prefix)?;
     } else {
         writeln!(out, "{}Params:", prefix)?;
         for param in params {
+            out.mark(param.span);
             if let Some(type_ann) = &param.type_ann {
                 writeln!(out, "{}  - {}: ", prefix, param.name)?;
                 write_type_expr(out, type_ann, indent + 2)?;
@@ -110,7 +348,7 @@ fn write_params(out: &mut String, params: &[Param], indent: usize) -> std::fmt::
     Ok(())
 }
 
-fn write_block(out: &mut String, block: &Block, indent: usize) -> std::fmt::Result {
+fn write_block(out: &mut Dumper, block: &Block, indent: usize) -> std::fmt::Result {
     let prefix = "  ".repeat(indent);
     writeln!(out, "{}Block:", prefix)?;
     if block.statements.is_empty() {
@@ -123,9 +361,10 @@ fn write_block(out: &mut String, block: &Block, indent: usize) -> std::fmt::Resu
     Ok(())
 }
 
-fn write_statement(out: &mut String, stmt: &Statement, indent: usize) -> std::fmt::Result {
+fn write_statement(out: &mut Dumper, stmt: &Spanned<Statement>, indent: usize) -> std::fmt::Result {
     let prefix = "  ".repeat(indent);
-    match stmt {
+    out.mark(stmt.span);
+    match &stmt.node {
         Statement::VarDecl { pattern, init } => {
             writeln!(out, "{}VarDecl:", prefix)?;
             write_pattern(out, pattern, indent + 1)?;
@@ -134,6 +373,18 @@ fn write_statement(out: &mut String, stmt: &Statement, indent: usize) -> std::fm
                 write_expr(out, expr, indent + 2)?;
             }
         }
+        Statement::ConstDecl { pattern, init } => {
+            writeln!(out, "{}ConstDecl:", prefix)?;
+            write_pattern(out, pattern, indent + 1)?;
+            writeln!(out, "{}  Init:", prefix)?;
+            write_expr(out, init, indent + 2)?;
+        }
+        Statement::Timeout { limit, body } => {
+            writeln!(out, "{}Timeout:", prefix)?;
+            writeln!(out, "{}  Limit:", prefix)?;
+            write_expr(out, limit, indent + 2)?;
+            write_statement(out, body, indent + 1)?;
+        }
         Statement::Expr(expr) => {
             writeln!(out, "{}ExprStmt:", prefix)?;
             write_expr(out, expr, indent + 1)?;
@@ -149,8 +400,14 @@ fn write_statement(out: &mut String, stmt: &Statement, indent: usize) -> std::fm
                 write_block(out, else_blk, indent + 2)?;
             }
         }
-        Statement::ForIn { var, iter, body } => {
-            writeln!(out, "{}For: var {} in", prefix, var)?;
+        Statement::ForIn { pattern, iter, body } => {
+            if let Pattern::Identifier { name, type_ann: None } = &pattern.node {
+                writeln!(out, "{}For: var {} in", prefix, name)?;
+            } else {
+                writeln!(out, "{}For:", prefix)?;
+                write_pattern(out, pattern, indent + 1)?;
+                writeln!(out, "{}  In:", prefix)?;
+            }
             write_expr(out, iter, indent + 1)?;
             write_block(out, body, indent + 1)?;
         }
@@ -159,6 +416,30 @@ fn write_statement(out: &mut String, stmt: &Statement, indent: usize) -> std::fm
             write_expr(out, condition, indent + 1)?;
             write_block(out, body, indent + 1)?;
         }
+        Statement::Try { body, catch, finally_block } => {
+            writeln!(out, "{}Try:", prefix)?;
+            write_block(out, body, indent + 1)?;
+            if let Some(catch) = catch {
+                match &catch.var {
+                    Some(var) => {
+                        writeln!(out, "{}  Catch: {}", prefix, var)?;
+                    }
+                    None => writeln!(out, "{}  Catch:", prefix)?,
+                }
+                write_block(out, &catch.body, indent + 2)?;
+            }
+            if let Some(finally_block) = finally_block {
+                writeln!(out, "{}  Finally:", prefix)?;
+                write_block(out, finally_block, indent + 2)?;
+            }
+        }
+        Statement::WhileVar { pattern, init, body } => {
+            writeln!(out, "{}WhileVar:", prefix)?;
+            write_pattern(out, pattern, indent + 1)?;
+            writeln!(out, "{}  Init:", prefix)?;
+            write_expr(out, init, indent + 2)?;
+            write_block(out, body, indent + 1)?;
+        }
         Statement::Return(expr) => {
             if let Some(e) = expr {
                 writeln!(out, "{}Return:", prefix)?;
@@ -181,9 +462,10 @@ fn write_statement(out: &mut String, stmt: &Statement, indent: usize) -> std::fm
     Ok(())
 }
 
-fn write_pattern(out: &mut String, pattern: &Pattern, indent: usize) -> std::fmt::Result {
+fn write_pattern(out: &mut Dumper, pattern: &Spanned<Pattern>, indent: usize) -> std::fmt::Result {
     let prefix = "  ".repeat(indent);
-    match pattern {
+    out.mark(pattern.span);
+    match &pattern.node {
         Pattern::Identifier { name, type_ann } => {
             if let Some(ty) = type_ann {
                 writeln!(out, "{}Pattern: {} :", prefix, name)?;
@@ -216,15 +498,28 @@ fn write_pattern(out: &mut String, pattern: &Pattern, indent: usize) -> std::fmt
     Ok(())
 }
 
-fn write_expr(out: &mut String, expr: &Expr, indent: usize) -> std::fmt::Result {
+fn write_expr(out: &mut Dumper, expr: &Spanned<Expr>, indent: usize) -> std::fmt::Result {
     let prefix = "  ".repeat(indent);
-    match expr {
+    // Long operator chains nest without brackets, so the parser's
+    // nesting limit doesn't bound them; elide instead of recursing further
+    if indent > MAX_DUMP_DEPTH {
+        writeln!(out, "{}...", prefix)?;
+        return Ok(());
+    }
+    out.mark(expr.span);
+    match &expr.node {
         Expr::Identifier(name) => {
             writeln!(out, "{}Identifier: {}", prefix, name)?;
         }
         Expr::Number(n) => {
             writeln!(out, "{}Number: {}", prefix, n)?;
         }
+        Expr::Duration(d) => {
+            writeln!(out, "{}Duration: {}", prefix, d)?;
+        }
+        Expr::Size(s) => {
+            writeln!(out, "{}Size: {}", prefix, s)?;
+        }
         Expr::String(s) => {
             writeln!(out, "{}String:", prefix)?;
             write_string_literal(out, s, indent + 1)?;
@@ -252,6 +547,17 @@ fn write_expr(out: &mut String, expr: &Expr, indent: usize) -> std::fmt::Result
                 }
             }
         }
+        Expr::Variant { tag, fields } => {
+            writeln!(out, "{}Variant: {}", prefix, tag)?;
+            for field in fields {
+                if let Some(value) = &field.value {
+                    writeln!(out, "{}  {}: ", prefix, field.key)?;
+                    write_expr(out, value, indent + 2)?;
+                } else {
+                    writeln!(out, "{}  {} (shorthand)", prefix, field.key)?;
+                }
+            }
+        }
         Expr::Binary { op, left, right } => {
             writeln!(out, "{}Binary: {:?}", prefix, op)?;
             writeln!(out, "{}  Left:", prefix)?;
@@ -286,7 +592,8 @@ fn write_expr(out: &mut String, expr: &Expr, indent: usize) -> std::fmt::Result
             write_expr(out, index, indent + 2)?;
         }
         Expr::Think(prompt) => {
-            writeln!(out, "{}Think:", prefix)?;
+            let name = if prompt.conversation { "Think.start" } else { "Think" };
+            writeln!(out, "{}{}:", prefix, name)?;
             write_prompt_block(out, prompt, indent + 1)?;
         }
         Expr::Ask(prompt) => {
@@ -346,6 +653,10 @@ fn write_expr(out: &mut String, expr: &Expr, indent: usize) -> std::fmt::Result
             writeln!(out, "{}Await:", prefix)?;
             write_expr(out, e, indent + 1)?;
         }
+        Expr::AwaitAll(e) => {
+            writeln!(out, "{}AwaitAll:", prefix)?;
+            write_expr(out, e, indent + 1)?;
+        }
         Expr::Paren(e) => {
             writeln!(out, "{}Paren:", prefix)?;
             write_expr(out, e, indent + 1)?;
@@ -354,11 +665,38 @@ fn write_expr(out: &mut String, expr: &Expr, indent: usize) -> std::fmt::Result
             writeln!(out, "{}Do:", prefix)?;
             write_block(out, block, indent + 1)?;
         }
+        Expr::Match { subject, arms } => {
+            writeln!(out, "{}Match:", prefix)?;
+            write_expr(out, subject, indent + 1)?;
+            for arm in arms {
+                out.mark(arm.pattern.span);
+                match &arm.pattern.node {
+                    MatchPattern::Literal(literal) => {
+                        writeln!(out, "{}  Arm:", prefix)?;
+                        write_expr(out, literal, indent + 2)?;
+                    }
+                    MatchPattern::Type { name, type_expr } => {
+                        writeln!(out, "{}  Arm: {} :", prefix, name.as_deref().unwrap_or("_"))?;
+                        write_type_expr(out, type_expr, indent + 2)?;
+                    }
+                    MatchPattern::Binding(name) => {
+                        writeln!(out, "{}  Arm: {}", prefix, name)?;
+                    }
+                    MatchPattern::Wildcard => writeln!(out, "{}  Arm: _", prefix)?,
+                }
+                write_block(out, &arm.body, indent + 2)?;
+            }
+        }
+        Expr::Lambda { params, body } => {
+            writeln!(out, "{}Lambda:", prefix)?;
+            write_params(out, params, indent + 1)?;
+            write_block(out, body, indent + 1)?;
+        }
     }
     Ok(())
 }
 
-fn write_string_literal(out: &mut String, s: &StringLiteral, indent: usize) -> std::fmt::Result {
+fn write_string_literal(out: &mut Dumper, s: &StringLiteral, indent: usize) -> std::fmt::Result {
     let prefix = "  ".repeat(indent);
     for part in &s.parts {
         match part {
@@ -374,9 +712,34 @@ fn write_string_literal(out: &mut String, s: &StringLiteral, indent: usize) -> s
     Ok(())
 }
 
-fn write_prompt_block(out: &mut String, prompt: &PromptBlock, indent: usize) -> std::fmt::Result {
+fn write_prompt_block(out: &mut Dumper, prompt: &PromptBlock, indent: usize) -> std::fmt::Result {
+    let prefix = "  ".repeat(indent);
+    if let Some(tools) = &prompt.tools {
+        writeln!(out, "{}Tools:", prefix)?;
+        write_expr(out, tools, indent + 1)?;
+    }
+    if !prompt.examples.is_empty() {
+        let names: Vec<&str> = prompt.examples.iter().map(|name| &*name.node).collect();
+        writeln!(out, "{}Examples: {}", prefix, names.join(", "))?;
+    }
+    write_prompt_items(out, &prompt.items, indent)?;
+    for variant in &prompt.variants {
+        writeln!(out, "{}Variant: {:?}", prefix, variant.name)?;
+        write_prompt_items(out, &variant.items, indent + 1)?;
+    }
+    if let Some(validator) = &prompt.validator {
+        match &validator.retries {
+            Some(retries) => writeln!(out, "{}Validate: {} (retries {})", prefix, validator.param, retries)?,
+            None => writeln!(out, "{}Validate: {}", prefix, validator.param)?,
+        }
+        write_block(out, &validator.body, indent + 1)?;
+    }
+    Ok(())
+}
+
+fn write_prompt_items(out: &mut Dumper, items: &[PromptItem], indent: usize) -> std::fmt::Result {
     let prefix = "  ".repeat(indent);
-    for item in &prompt.items {
+    for item in items {
         match item {
             PromptItem::Text(t) => {
                 writeln!(out, "{}Text: {:?}", prefix, t)?;
@@ -394,7 +757,7 @@ fn write_prompt_block(out: &mut String, prompt: &PromptBlock, indent: usize) ->
     Ok(())
 }
 
-fn write_command_arg(out: &mut String, arg: &CommandArg, indent: usize) -> std::fmt::Result {
+fn write_command_arg(out: &mut Dumper, arg: &CommandArg, indent: usize) -> std::fmt::Result {
     let prefix = "  ".repeat(indent);
     match arg {
         CommandArg::Literal(s) => {
@@ -408,9 +771,10 @@ fn write_command_arg(out: &mut String, arg: &CommandArg, indent: usize) -> std::
     Ok(())
 }
 
-fn write_type_expr(out: &mut String, ty: &TypeExpr, indent: usize) -> std::fmt::Result {
+fn write_type_expr(out: &mut Dumper, ty: &Spanned<TypeExpr>, indent: usize) -> std::fmt::Result {
     let prefix = "  ".repeat(indent);
-    match ty {
+    out.mark(ty.span);
+    match &ty.node {
         TypeExpr::Name(name) => {
             writeln!(out, "{}Type: {}", prefix, name)?;
         }
@@ -434,6 +798,19 @@ fn write_type_expr(out: &mut String, ty: &TypeExpr, indent: usize) -> std::fmt::
         TypeExpr::Literal(lit) => {
             writeln!(out, "{}Literal: {:?}", prefix, lit)?;
         }
+        TypeExpr::Generic { name, args } => {
+            writeln!(out, "{}GenericType: {}", prefix, name)?;
+            for arg in args {
+                write_type_expr(out, arg, indent + 1)?;
+            }
+        }
+        TypeExpr::Variant { tag, fields } => {
+            writeln!(out, "{}VariantType: {}", prefix, tag)?;
+            for field in fields {
+                writeln!(out, "{}  {}: ", prefix, field.key)?;
+                write_type_expr(out, &field.type_expr, indent + 2)?;
+            }
+        }
     }
     Ok(())
 }
@@ -468,4 +845,32 @@ mod tests {
         assert!(dump.contains("status:"));
         assert!(dump.contains("code:"));
     }
+
+    #[test]
+    fn test_dump_options() {
+        let input = "fun keep(x) { return x + 1 }\nfun other() {}";
+        let program = parse(input).unwrap();
+        let options = DumpOptions {
+            format: DumpFormat::CompactJson,
+            spans: true,
+            max_depth: Some(3),
+            item: Some("keep".to_string()),
+        };
+
+        assert_eq!(
+            dump_program_with(&program, &options),
+            concat!(
+                r#"{"node":"Program","children":[{"node":"Function: keep","span":[0,28],"children":["#,
+                r#"{"node":"Params","children":[{"node":"- x","span":[9,10]}]},"#,
+                r#"{"node":"Block","children":[{"node":"Return","span":[14,26],"children":[{"node":"..."}]}]}]}]}"#,
+                "\n",
+            )
+        );
+
+        let tree = DumpOptions { format: DumpFormat::Tree, ..options };
+        assert_eq!(
+            dump_program_with(&program, &tree),
+            "Program:\n  Function: keep @0..28\n    Params:\n      - x @9..10\n    Block:\n      Return: @14..26\n        ...\n"
+        );
+    }
 }
@@ -0,0 +1,143 @@
+//! On-disk cache of parsed programs, keyed by source content.
+//!
+//! Entries are the AST serialized with bincode, under the SHA-256 of the
+//! source and the parser version, so any change to either changes the key.
+//! A cached program owns its strings, but keeps the spans of the source it
+//! was parsed from. An entry that is missing or unreadable is replaced by a
+//! normal parse.
+
+use std::path::{Path, PathBuf};
+
+use bincode::Options;
+use sha2::{Digest, Sha256};
+
+use crate::ast::*;
+use crate::ParseError;
+
+/// Bumped whenever the AST changes shape
+const FORMAT_VERSION: u32 = 12;
+
+/// A directory of cached ASTs.
+#[derive(Debug, Clone)]
+pub struct AstCache {
+    dir: PathBuf,
+}
+
+impl AstCache {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self { dir: dir.into() }
+    }
+
+    /// The cache under the system temp directory.
+    pub fn in_temp_dir() -> Self {
+        Self::new(std::env::temp_dir().join("patchwork-ast-cache"))
+    }
+
+    /// Parse `source`, loading the AST from the cache when a matching entry
+    /// exists and storing it otherwise. Parse errors are never cached.
+    pub fn parse<'input>(&self, source: &'input str) -> Result<Program<'input>, ParseError> {
+        let path = self.entry_path(source);
+        if let Some(program) = std::fs::read(&path).ok().and_then(|bytes| decode(&bytes)) {
+            return Ok(program);
+        }
+
+        let program = crate::parse(source)?;
+        // A cache that can't be written is only a missed speedup
+        if let Some(bytes) = encode(&program) {
+            let _ = std::fs::create_dir_all(&self.dir).and_then(|_| write_atomically(&path, &bytes));
+        }
+        Ok(program)
+    }
+
+    fn entry_path(&self, source: &str) -> PathBuf {
+        let mut hasher = Sha256::new();
+        hasher.update(FORMAT_VERSION.to_le_bytes());
+        hasher.update(env!("CARGO_PKG_VERSION"));
+        hasher.update([0]);
+        hasher.update(source);
+        let hex: String = hasher.finalize().iter().map(|b| format!("{:02x}", b)).collect();
+        self.dir.join(format!("{}.ast", hex))
+    }
+}
+
+/// Write through a temporary file so a concurrent reader never sees a
+/// partial entry.
+fn write_atomically(path: &Path, bytes: &[u8]) -> std::io::Result<()> {
+    let tmp = path.with_extension(format!("tmp{}", std::process::id()));
+    std::fs::write(&tmp, bytes)?;
+    std::fs::rename(&tmp, path)
+}
+
+/// Encode `program` as a cache entry.
+pub fn encode(program: &Program) -> Option<Vec<u8>> {
+    bincode::DefaultOptions::new().serialize(program).ok()
+}
+
+/// Decode an entry produced by `encode`, or `None` if the bytes don't
+/// describe a program.
+pub fn decode(bytes: &[u8]) -> Option<Program<'static>> {
+    bincode::DefaultOptions::new().deserialize(bytes).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SOURCE: &str = r#"import ./{analyst}
+
+type verdict = Pass { note: string } | Fail { reasons: [string] }
+
+export default memo fun review(files: [string], depth) {
+    var {summary, risky} = think {
+        Review ${files} at depth $depth.
+    } variant "terse" {
+        Review $files.
+    }
+    for var f in files {
+        if risky && !f.done {
+            $(git diff "${f}" | head -n 5) > "out.txt"
+        } else {
+            print("skip", f[0], -1)
+        }
+    }
+    return Pass{note: summary}
+}
+"#;
+
+    #[test]
+    fn test_round_trip_keeps_spans() {
+        let program = crate::parse(SOURCE).unwrap();
+        let decoded = decode(&encode(&program).unwrap()).unwrap();
+        assert_eq!(decoded, program);
+        // Equality ignores spans, so compare them separately
+        let spans = |p: &Program| p.items.iter().map(|item| item.span).collect::<Vec<_>>();
+        assert_eq!(spans(&decoded), spans(&program));
+
+        let Item::Function(decl) = &decoded.items[2].node else { panic!("Expected Function item") };
+        let for_loop = decl.body.statements[1].span;
+        assert!(SOURCE[for_loop.start..for_loop.end].starts_with("for var f in files {"));
+    }
+
+    #[test]
+    fn test_cache_misses_on_changed_source() {
+        let dir = std::env::temp_dir().join(format!("patchwork-ast-cache-test-{}", std::process::id()));
+        let cache = AstCache::new(&dir);
+
+        assert_eq!(cache.parse(SOURCE).unwrap(), crate::parse(SOURCE).unwrap());
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 1);
+        assert_eq!(cache.parse(SOURCE).unwrap(), crate::parse(SOURCE).unwrap());
+
+        let edited = SOURCE.replace("depth $depth", "level $depth");
+        assert_eq!(cache.parse(&edited).unwrap(), crate::parse(&edited).unwrap());
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 2);
+
+        // An unreadable entry falls back to parsing and is rewritten
+        for entry in std::fs::read_dir(&dir).unwrap() {
+            std::fs::write(entry.unwrap().path(), b"garbage").unwrap();
+        }
+        assert_eq!(cache.parse(SOURCE).unwrap(), crate::parse(SOURCE).unwrap());
+        assert!(decode(&std::fs::read(cache.entry_path(SOURCE)).unwrap()).is_some());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
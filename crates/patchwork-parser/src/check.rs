@@ -0,0 +1,638 @@
+//! Static checks that run on a parsed program before it executes.
+//!
+//! These catch mistakes the grammar can't express, using the same scoping
+//! rules as the interpreter: each block opens a scope, and a function body
+//! shares its parameters' scope.
+//!
+//! - Assigning to a name declared with `const`.
+//! - A `match` on a name declared with a union type that leaves some of the
+//!   union's members without an arm.
+//! - An annotation the language doesn't define, on a declaration it doesn't
+//!   apply to, or with an argument it doesn't take.
+//! - A think or ask block attaching an example the module doesn't declare.
+
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
+
+use crate::ast::*;
+use crate::types::{resolve_aliases, MAX_ALIAS_DEPTH};
+use crate::ParseError;
+
+/// Colors `@color` accepts, as agent hosts display them.
+const ANNOTATION_COLORS: &[&str] = &["red", "blue", "green", "yellow", "purple", "orange", "pink", "cyan"];
+
+/// Run every check on `program` and report each problem as an error
+/// spanning the offending source.
+pub fn check(program: &Program) -> Vec<ParseError> {
+    let checker = Checker::run(program);
+    let assignments =
+        checker.found.iter().map(|(name, span)| (*span, format!("Cannot assign to constant '{}'", name)));
+    let matches = checker.inexhaustive.iter().map(|(subject, span, missing)| {
+        let message = format!("Match on '{}' doesn't cover {}; add arms for them or a `_` arm", subject, missing.join(", "));
+        (*span, message)
+    });
+    let examples =
+        checker.unknown_examples.iter().map(|name| (name.span, format!("Unknown example '{}'", name.node)));
+    let mut errors: Vec<_> = assignments
+        .chain(matches)
+        .chain(examples)
+        .map(|(span, message)| ParseError::Check { message, span: (span.start, span.end) })
+        .collect();
+    for (annotation, kind) in annotations(program) {
+        if let Some(message) = annotation_problem(&annotation.node, kind) {
+            errors.push(ParseError::Check { message, span: (annotation.span.start, annotation.span.end) });
+        }
+    }
+    errors.sort_by_key(|error| error.span());
+    errors
+}
+
+/// Every annotation in `program`, with the kind of declaration it's on.
+fn annotations<'a>(program: &'a Program<'a>) -> Vec<(&'a Spanned<Annotation<'a>>, &'static str)> {
+    let mut found = Vec::new();
+    for item in &program.items {
+        let (annotations, kind) = match &item.node {
+            Item::Skill(decl) => (&decl.annotations, "skill"),
+            Item::Worker(decl) => (&decl.annotations, "worker"),
+            Item::Function(decl) => (&decl.annotations, "function"),
+            Item::Type(decl) => (&decl.annotations, "type"),
+            Item::Trait(decl) => {
+                for method in &decl.methods {
+                    found.extend(method.annotations.iter().map(|annotation| (annotation, "function")));
+                }
+                (&decl.annotations, "trait")
+            }
+            Item::Import(_) | Item::Config(_) | Item::Example(_) | Item::Fragment(_) => continue,
+        };
+        found.extend(annotations.iter().map(|annotation| (annotation, kind)));
+    }
+    found
+}
+
+/// What's wrong with `annotation` on a declaration of `kind`, if anything.
+fn annotation_problem(annotation: &Annotation, kind: &str) -> Option<String> {
+    let name = &*annotation.name;
+    let applies = match name {
+        // Entry points for hosts: `@skill`, `@skill name`, `@command name`
+        "skill" | "command" => kind == "function",
+        // How a host shows an agent: `@color purple`
+        "color" => matches!(kind, "skill" | "worker" | "trait"),
+        // `@deprecated`, or `@deprecated replacement`
+        "deprecated" => true,
+        _ => return Some(format!("Unknown annotation `@{}`", name)),
+    };
+    if !applies {
+        return Some(format!("`@{}` doesn't apply to a {}", name, kind));
+    }
+    match (name, annotation.arg.as_deref()) {
+        ("color", Some(color)) if ANNOTATION_COLORS.contains(&color) => None,
+        ("color", _) => Some(format!("`@color` takes one of {}", ANNOTATION_COLORS.join(", "))),
+        _ => None,
+    }
+}
+
+/// Find assignments to names declared with `const`.
+///
+/// Returns the assigned identifier of each one, with the span of the
+/// assignment's target.
+pub fn const_assignments<'a>(program: &'a Program<'a>) -> Vec<(&'a str, Span)> {
+    Checker::run(program).found
+}
+
+/// A name in scope.
+#[derive(Clone, Copy)]
+struct Binding<'a> {
+    constant: bool,
+    /// The type the name was declared with, if any
+    ty: Option<&'a TypeExpr<'a>>,
+}
+
+impl<'a> Binding<'a> {
+    fn var(ty: Option<&'a Spanned<TypeExpr<'a>>>) -> Self {
+        Binding { constant: false, ty: ty.map(|ty| &ty.node) }
+    }
+}
+
+struct Checker<'a> {
+    scopes: Vec<HashMap<&'a str, Binding<'a>>>,
+    /// Type aliases, from `type` declarations anywhere in the program
+    aliases: HashMap<&'a str, &'a TypeExpr<'a>>,
+    /// Names assigned to although they are constants
+    found: Vec<(&'a str, Span)>,
+    /// Each match subject whose declared union isn't covered, with the
+    /// members left out
+    inexhaustive: Vec<(&'a str, Span, Vec<String>)>,
+    /// Names of the module's `example` declarations
+    examples: HashSet<&'a str>,
+    /// Examples attached to a prompt but never declared
+    unknown_examples: Vec<&'a Spanned<Cow<'a, str>>>,
+}
+
+impl<'a> Checker<'a> {
+    fn run(program: &'a Program<'a>) -> Self {
+        // A config block binds `config` as a global constant
+        let has_config = program.items.iter().any(|item| matches!(&item.node, Item::Config(_)));
+        let globals = if has_config {
+            HashMap::from([("config", Binding { constant: true, ty: None })])
+        } else {
+            HashMap::new()
+        };
+        let aliases = program
+            .items
+            .iter()
+            .filter_map(|item| match &item.node {
+                Item::Type(decl) => Some((&*decl.name, &decl.type_expr.node)),
+                _ => None,
+            })
+            .collect();
+        let examples = program
+            .items
+            .iter()
+            .filter_map(|item| match &item.node {
+                Item::Example(decl) => Some(&*decl.name),
+                _ => None,
+            })
+            .collect();
+        let mut checker = Checker {
+            scopes: vec![globals],
+            aliases,
+            found: Vec::new(),
+            inexhaustive: Vec::new(),
+            examples,
+            unknown_examples: Vec::new(),
+        };
+        for item in &program.items {
+            match &item.node {
+                Item::Skill(decl) => checker.callable(&decl.params, &decl.body),
+                Item::Worker(decl) => checker.callable(&decl.params, &decl.body),
+                Item::Function(decl) => checker.callable(&decl.params, &decl.body),
+                Item::Trait(decl) => {
+                    for method in &decl.methods {
+                        checker.callable(&method.params, &method.body);
+                    }
+                }
+                Item::Config(decl) => {
+                    for value in decl.fields.iter().filter_map(|field| field.value.as_ref()) {
+                        checker.expr(value);
+                    }
+                }
+                Item::Example(decl) => checker.prompt_items(&decl.items),
+                Item::Fragment(decl) => checker.prompt_items(&decl.items),
+                Item::Import(_) | Item::Type(_) => {}
+            }
+        }
+        checker
+    }
+
+    fn prompt_items(&mut self, items: &'a [PromptItem<'a>]) {
+        for item in items {
+            match item {
+                PromptItem::Interpolation(expr) => self.expr(expr),
+                PromptItem::Code(block) => self.block(block),
+                PromptItem::Text(_) => {}
+            }
+        }
+    }
+
+    fn callable(&mut self, params: &'a [Spanned<Param<'a>>], body: &'a Block<'a>) {
+        self.scopes.push(params.iter().map(|param| (&*param.name, Binding::var(param.type_ann.as_ref()))).collect());
+        self.statements(body);
+        self.scopes.pop();
+    }
+
+    fn block(&mut self, block: &'a Block<'a>) {
+        self.scopes.push(HashMap::new());
+        self.statements(block);
+        self.scopes.pop();
+    }
+
+    fn statements(&mut self, block: &'a Block<'a>) {
+        for stmt in &block.statements {
+            self.statement(stmt);
+        }
+    }
+
+    fn statement(&mut self, stmt: &'a Statement<'a>) {
+        match stmt {
+            Statement::VarDecl { pattern, init } => {
+                if let Some(init) = init {
+                    self.expr(init);
+                }
+                self.bind(pattern, false);
+            }
+            Statement::ConstDecl { pattern, init } => {
+                self.expr(init);
+                self.bind(pattern, true);
+            }
+            Statement::Expr(expr) | Statement::Return(Some(expr)) => self.expr(expr),
+            Statement::If { condition, then_block, else_block } => {
+                self.expr(condition);
+                self.block(then_block);
+                if let Some(else_block) = else_block {
+                    self.block(else_block);
+                }
+            }
+            Statement::ForIn { pattern, iter, body } => {
+                self.expr(iter);
+                self.scopes.push(HashMap::new());
+                self.bind(pattern, false);
+                self.block(body);
+                self.scopes.pop();
+            }
+            Statement::While { condition, body } => {
+                self.expr(condition);
+                self.block(body);
+            }
+            Statement::WhileVar { pattern, init, body } => {
+                self.expr(init);
+                self.scopes.push(HashMap::new());
+                self.bind(pattern, false);
+                self.block(body);
+                self.scopes.pop();
+            }
+            Statement::Timeout { limit, body } => {
+                self.expr(limit);
+                self.statement(body);
+            }
+            Statement::Try { body, catch, finally_block } => {
+                self.block(body);
+                if let Some(catch) = catch {
+                    self.scopes.push(catch.var.iter().map(|var| (&**var, Binding::var(None))).collect());
+                    self.block(&catch.body);
+                    self.scopes.pop();
+                }
+                if let Some(finally_block) = finally_block {
+                    self.block(finally_block);
+                }
+            }
+            Statement::TypeDecl { name, type_expr } => {
+                self.aliases.insert(name, &type_expr.node);
+            }
+            Statement::Return(None) | Statement::Succeed | Statement::Break => {}
+        }
+    }
+
+    fn bind(&mut self, pattern: &'a Pattern<'a>, constant: bool) {
+        match pattern {
+            Pattern::Identifier { name, type_ann } => {
+                if let Some(scope) = self.scopes.last_mut() {
+                    scope.insert(name, Binding { constant, ty: type_ann.as_ref().map(|ty| &ty.node) });
+                }
+            }
+            Pattern::Object(fields) => {
+                for field in fields {
+                    self.bind(&field.pattern, constant);
+                }
+            }
+            Pattern::Array(items) => {
+                for item in items {
+                    self.bind(item, constant);
+                }
+            }
+            Pattern::Ignore => {}
+        }
+    }
+
+    fn lookup(&self, name: &str) -> Option<Binding<'a>> {
+        self.scopes.iter().rev().find_map(|scope| scope.get(name)).copied()
+    }
+
+    fn is_const(&self, name: &str) -> bool {
+        self.lookup(name).is_some_and(|binding| binding.constant)
+    }
+
+    fn match_arms(&mut self, subject: &'a Spanned<Expr<'a>>, arms: &'a [MatchArm<'a>]) {
+        let name = match &subject.node {
+            Expr::Identifier(name) => Some(&**name),
+            Expr::Paren(inner) => match &inner.node {
+                Expr::Identifier(name) => Some(&**name),
+                _ => None,
+            },
+            _ => None,
+        };
+        if let Some((name, ty)) = name.and_then(|name| Some((name, self.lookup(name)?.ty?))) {
+            let missing = self.uncovered(ty, arms);
+            if !missing.is_empty() {
+                self.inexhaustive.push((name, subject.span, missing));
+            }
+        }
+
+        for arm in arms {
+            let binding = match &arm.pattern.node {
+                MatchPattern::Type { name: Some(name), type_expr } => Some((&**name, Binding::var(Some(type_expr)))),
+                MatchPattern::Binding(name) => Some((&**name, Binding::var(None))),
+                _ => None,
+            };
+            self.scopes.push(binding.into_iter().collect());
+            self.block(&arm.body);
+            self.scopes.pop();
+        }
+    }
+
+    /// The members of the union `ty` that no arm matches, as a message
+    /// names them. Subjects of other types aren't checked.
+    fn uncovered(&self, ty: &'a TypeExpr<'a>, arms: &'a [MatchArm<'a>]) -> Vec<String> {
+        if !matches!(self.resolve(ty), TypeExpr::Union(_)) {
+            return Vec::new();
+        }
+        let mut literals = Vec::new();
+        let mut types = Vec::new();
+        for arm in arms {
+            match &arm.pattern.node {
+                MatchPattern::Wildcard | MatchPattern::Binding(_) => return Vec::new(),
+                MatchPattern::Literal(literal) => literals.push(&literal.node),
+                MatchPattern::Type { type_expr, .. } => types.extend(self.members(&type_expr.node)),
+            }
+        }
+
+        let covered = |member: &TypeExpr| {
+            types.iter().any(|ty| match (ty, member) {
+                (TypeExpr::Name(name), TypeExpr::Literal(_)) if name == "string" => true,
+                // A name that isn't an alias matches the variant it tags
+                (TypeExpr::Name(tag), TypeExpr::Variant { tag: member_tag, .. }) => tag == member_tag,
+                _ => *ty == member,
+            }) || match member {
+                TypeExpr::Literal(text) => literals.iter().any(|literal| match literal {
+                    Expr::String(s) => literal_text(s) == *text,
+                    _ => false,
+                }),
+                TypeExpr::Name(name) if name == "boolean" => {
+                    literals.iter().any(|literal| matches!(literal, Expr::True))
+                        && literals.iter().any(|literal| matches!(literal, Expr::False))
+                }
+                _ => false,
+            }
+        };
+        self.members(ty).into_iter().filter(|member| !covered(member)).map(describe_member).collect()
+    }
+
+    /// `ty` with aliases followed, as far as they go.
+    fn resolve(&self, ty: &'a TypeExpr<'a>) -> &'a TypeExpr<'a> {
+        resolve_aliases(ty, |ty| match ty {
+            TypeExpr::Name(name) => self.aliases.get(&**name).copied(),
+            _ => None,
+        })
+        .unwrap_or_else(|ty| ty)
+    }
+
+    /// The types a value of type `ty` can be one of, with unions flattened.
+    fn members(&self, ty: &'a TypeExpr<'a>) -> Vec<&'a TypeExpr<'a>> {
+        let mut members = Vec::new();
+        let mut pending = vec![(ty, 0)];
+        while let Some((ty, depth)) = pending.pop() {
+            match self.resolve(ty) {
+                TypeExpr::Union(types) if depth < MAX_ALIAS_DEPTH => {
+                    pending.extend(types.iter().rev().map(|ty| (&ty.node, depth + 1)));
+                }
+                resolved => members.push(resolved),
+            }
+        }
+        members
+    }
+
+    fn expr(&mut self, expr: &'a Expr<'a>) {
+        match expr {
+            Expr::Binary { op: BinOp::Assign, left, right } => {
+                if let Expr::Identifier(name) = &left.node {
+                    if self.is_const(name) {
+                        self.found.push((name, left.span));
+                    }
+                }
+                self.expr(left);
+                self.expr(right);
+            }
+            Expr::Binary { left, right, .. }
+            | Expr::Index { object: left, index: right }
+            | Expr::ShellPipe { left, right }
+            | Expr::ShellAnd { left, right }
+            | Expr::ShellOr { left, right }
+            | Expr::ShellRedirect { command: left, target: right, .. } => {
+                self.expr(left);
+                self.expr(right);
+            }
+            Expr::Object(fields) | Expr::Variant { fields, .. } => {
+                for value in fields.iter().filter_map(|field| field.value.as_ref()) {
+                    self.expr(value);
+                }
+            }
+            Expr::Member { object, .. } => self.expr(object),
+            Expr::Array(items) => {
+                for item in items {
+                    self.expr(item);
+                }
+            }
+            Expr::Call { callee, args } => {
+                self.expr(callee);
+                for arg in args {
+                    self.expr(arg);
+                }
+            }
+            Expr::Unary { operand: inner, .. }
+            | Expr::PostIncrement(inner)
+            | Expr::PostDecrement(inner)
+            | Expr::Paren(inner)
+            | Expr::Await(inner)
+            | Expr::AwaitAll(inner)
+            | Expr::CommandSubst(inner) => self.expr(inner),
+            Expr::String(s) => self.string(s),
+            Expr::BareCommand { args, .. } => {
+                for arg in args {
+                    if let CommandArg::String(s) = arg {
+                        self.string(s);
+                    }
+                }
+            }
+            Expr::Think(prompt) | Expr::Ask(prompt) => {
+                let unknown = prompt.examples.iter().filter(|name| !self.examples.contains(&*name.node));
+                self.unknown_examples.extend(unknown);
+                if let Some(tools) = &prompt.tools {
+                    self.expr(tools);
+                }
+                let variants = prompt.variants.iter().map(|variant| &variant.items);
+                for items in std::iter::once(&prompt.items).chain(variants) {
+                    self.prompt_items(items);
+                }
+                if let Some(validator) = &prompt.validator {
+                    self.scopes.push(HashMap::from([(&*validator.param, Binding::var(None))]));
+                    self.statements(&validator.body);
+                    self.scopes.pop();
+                }
+            }
+            Expr::Do(block) => self.block(block),
+            Expr::Match { subject, arms } => {
+                self.expr(subject);
+                self.match_arms(subject, arms);
+            }
+            Expr::Lambda { params, body } => self.callable(params, body),
+            Expr::Identifier(_)
+            | Expr::Number(_)
+            | Expr::Duration(_)
+            | Expr::Size(_)
+            | Expr::True
+            | Expr::False => {}
+        }
+    }
+
+    fn string(&mut self, s: &'a StringLiteral<'a>) {
+        for part in &s.parts {
+            if let StringPart::Interpolation(expr) = part {
+                self.expr(expr);
+            }
+        }
+    }
+}
+
+/// The text of a string literal with no interpolation.
+fn literal_text<'a>(s: &'a StringLiteral) -> &'a str {
+    match s.parts.as_slice() {
+        [StringPart::Text(text)] => text,
+        _ => "",
+    }
+}
+
+/// A union member as a message names it.
+fn describe_member(ty: &TypeExpr) -> String {
+    match ty {
+        TypeExpr::Literal(text) => format!("\"{}\"", text),
+        TypeExpr::Name(name) | TypeExpr::Variant { tag: name, .. } | TypeExpr::Generic { name, .. } => name.to_string(),
+        TypeExpr::Array(_) => "arrays".to_string(),
+        TypeExpr::Object(_) => "objects".to_string(),
+        TypeExpr::Union(_) => "unions".to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{parse, parse_with_edition, Edition};
+
+    #[test]
+    fn test_const_assignments() {
+        let source = r#"fun f(limit) {
+    const dir = "/tmp"
+    var count = 0
+    count = 1
+    limit = 2
+    dir = "/"
+    if true {
+        var dir = "shadow"
+        dir = "ok"
+    }
+    for var item in [1] {
+        dir = "loop"
+    }
+}"#;
+        let program = parse(source).unwrap();
+        let spans: Vec<_> = check(&program)
+            .into_iter()
+            .map(|error| match error {
+                ParseError::Check { message, span } => {
+                    assert_eq!(message, "Cannot assign to constant 'dir'");
+                    span
+                }
+                other => panic!("Expected a check error, got {:?}", other),
+            })
+            .collect();
+        let first = source.find("dir = \"/\"").unwrap();
+        let second = source.find("dir = \"loop\"").unwrap();
+        assert_eq!(spans, vec![(first, first + 3), (second, second + 3)]);
+    }
+
+    #[test]
+    fn test_match_exhaustiveness() {
+        let source = r#"type Outcome = "success" | "error" | Failure { reason: string }
+
+fun f(s: Outcome, flag: boolean) {
+    match s {
+        "success" => { 1 }
+    }
+    match s {
+        "success" => { 1 }
+        _: Failure => { 2 }
+        other => { 3 }
+    }
+    match s {
+        m: string => { 1 }
+        f: Failure => { 2 }
+    }
+    match flag {
+        true => { 1 }
+        false => { 0 }
+    }
+}"#;
+        let program = parse_with_edition(source, Edition::E2025).unwrap();
+        let messages: Vec<_> = check(&program)
+            .into_iter()
+            .map(|error| match error {
+                ParseError::Check { message, .. } => message,
+                other => panic!("Expected a check error, got {:?}", other),
+            })
+            .collect();
+        assert_eq!(
+            messages,
+            vec!["Match on 's' doesn't cover \"error\", Failure; add arms for them or a `_` arm".to_string()]
+        );
+    }
+
+    #[test]
+    fn test_annotations() {
+        let source = r#"@color purple
+@deprecated historian
+skill analyst() {}
+
+@color mauve
+worker scribe() {}
+
+trait Historian {
+    @skill narrate
+    @command
+    fun narrate() {}
+}
+
+@skill
+type Id = string
+
+@retry
+fun f() {}"#;
+        let program = parse(source).unwrap();
+        let errors: Vec<_> = check(&program)
+            .into_iter()
+            .map(|error| match error {
+                ParseError::Check { message, span: (start, end) } => (message, &source[start..end]),
+                other => panic!("Expected a check error, got {:?}", other),
+            })
+            .collect();
+        assert_eq!(
+            errors,
+            vec![
+                (format!("`@color` takes one of {}", ANNOTATION_COLORS.join(", ")), "@color mauve"),
+                ("`@skill` doesn't apply to a type".to_string(), "@skill"),
+                ("Unknown annotation `@retry`".to_string(), "@retry"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_unknown_examples() {
+        let source = r#"example approve {
+    Diff: adds a test. Verdict: approve
+}
+
+fun review(diff) {
+    think with examples [approve, reject] {
+        Diff: $diff. Verdict?
+    }
+}"#;
+        let program = parse_with_edition(source, Edition::E2025).unwrap();
+        let errors: Vec<_> = check(&program)
+            .into_iter()
+            .map(|error| match error {
+                ParseError::Check { message, span } => (message, span),
+                other => panic!("Expected a check error, got {:?}", other),
+            })
+            .collect();
+        let at = source.find("reject").unwrap();
+        assert_eq!(errors, vec![("Unknown example 'reject'".to_string(), (at, at + 6))]);
+        assert_eq!(check(&program)[0].to_string(), "Invalid program: Unknown example 'reject'");
+    }
+}
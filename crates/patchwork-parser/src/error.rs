@@ -0,0 +1,233 @@
+//! Errors from lexing, parsing and checking a program.
+//!
+//! Grammar failures keep what the parser knew when it stopped: the token it
+//! found and the tokens it would have accepted there, so tools can offer
+//! more than a message. Expected tokens are named as the grammar names them:
+//! keywords and punctuation in double quotes (`"import"`, `"{"`), other
+//! kinds of token bare (`identifier`, `newline`).
+
+use std::fmt;
+
+use crate::ast::Span;
+
+/// Expected tokens listed in a message before the rest are summarized.
+const MAX_LISTED: usize = 6;
+
+/// Error type for the parser
+#[derive(Debug, Clone, PartialEq)]
+pub enum ParseError {
+    /// The lexer couldn't read the input.
+    LexerError {
+        message: String,
+        byte_offset: Option<usize>,
+        span: Option<(usize, usize)>,
+    },
+    /// A token the program can't use where it is, such as a reserved word.
+    UnexpectedToken {
+        message: String,
+        byte_offset: Option<usize>,
+        span: Option<(usize, usize)>,
+    },
+    /// The grammar allows none of the rules that could start with `found`.
+    UnrecognizedToken {
+        /// The token's source text.
+        found: String,
+        expected: Vec<String>,
+        span: (usize, usize),
+    },
+    /// The input ended where the grammar expected more.
+    UnexpectedEof { expected: Vec<String>, byte_offset: usize },
+    /// A token after the end of what would otherwise be a whole program.
+    ExtraToken { found: String, span: (usize, usize) },
+    /// Input that isn't any token.
+    InvalidToken { byte_offset: usize },
+    /// A program that parses, but that a check after parsing rejects, such
+    /// as one assigning to a constant.
+    Check { message: String, span: (usize, usize) },
+}
+
+impl ParseError {
+    /// The source the error is about. Errors found at a single point have
+    /// an empty span there.
+    pub fn span(&self) -> Option<(usize, usize)> {
+        match self {
+            ParseError::LexerError { byte_offset, span, .. } | ParseError::UnexpectedToken { byte_offset, span, .. } => {
+                span.or(byte_offset.map(|at| (at, at)))
+            }
+            ParseError::UnrecognizedToken { span, .. }
+            | ParseError::ExtraToken { span, .. }
+            | ParseError::Check { span, .. } => Some(*span),
+            ParseError::UnexpectedEof { byte_offset, .. } | ParseError::InvalidToken { byte_offset } => {
+                Some((*byte_offset, *byte_offset))
+            }
+        }
+    }
+
+    /// The tokens the grammar would have accepted, if it got that far.
+    pub fn expected(&self) -> &[String] {
+        match self {
+            ParseError::UnrecognizedToken { expected, .. } | ParseError::UnexpectedEof { expected, .. } => expected,
+            _ => &[],
+        }
+    }
+
+    /// The source text of the token the parser stopped at, if it stopped
+    /// at one.
+    pub fn found(&self) -> Option<&str> {
+        match self {
+            ParseError::UnrecognizedToken { found, .. } | ParseError::ExtraToken { found, .. } => Some(found),
+            _ => None,
+        }
+    }
+
+    /// What went wrong, without the error's position.
+    pub fn message(&self) -> String {
+        match self {
+            ParseError::LexerError { message, .. }
+            | ParseError::UnexpectedToken { message, .. }
+            | ParseError::Check { message, .. } => message.clone(),
+            ParseError::UnrecognizedToken { found, expected, .. } => {
+                format!("Unexpected {}{}", describe_found(found), describe_expected(expected))
+            }
+            ParseError::UnexpectedEof { expected, .. } => {
+                format!("Unexpected end of file{}", describe_expected(expected))
+            }
+            ParseError::ExtraToken { found, .. } => format!("Unexpected {} after the end of the program", describe_found(found)),
+            ParseError::InvalidToken { .. } => "Invalid token".to_string(),
+        }
+    }
+
+    /// The same error with every byte offset passed through `map`, for
+    /// errors in a piece of a larger document.
+    pub fn map_offsets(self, map: impl Fn(usize) -> usize) -> ParseError {
+        let map_span = |(start, end): (usize, usize)| (map(start), map(end));
+        match self {
+            ParseError::LexerError { message, byte_offset, span } => ParseError::LexerError {
+                message,
+                byte_offset: byte_offset.map(&map),
+                span: span.map(map_span),
+            },
+            ParseError::UnexpectedToken { message, byte_offset, span } => ParseError::UnexpectedToken {
+                message,
+                byte_offset: byte_offset.map(&map),
+                span: span.map(map_span),
+            },
+            ParseError::UnrecognizedToken { found, expected, span } => {
+                ParseError::UnrecognizedToken { found, expected, span: map_span(span) }
+            }
+            ParseError::UnexpectedEof { expected, byte_offset } => {
+                ParseError::UnexpectedEof { expected, byte_offset: map(byte_offset) }
+            }
+            ParseError::ExtraToken { found, span } => ParseError::ExtraToken { found, span: map_span(span) },
+            ParseError::InvalidToken { byte_offset } => ParseError::InvalidToken { byte_offset: map(byte_offset) },
+            ParseError::Check { message, span } => ParseError::Check { message, span: map_span(span) },
+        }
+    }
+
+    /// Render the error as a diagnostic pointing into `source`, read from
+    /// `path`:
+    ///
+    /// ```text
+    /// error: Unexpected `)`, expected one of identifier, number
+    ///  --> deploy.pw:2:13
+    ///   |
+    /// 2 |     var x = )
+    ///   |             ^
+    /// ```
+    pub fn render(&self, source: &str, path: &str) -> String {
+        let mut out = format!("error: {}\n", self.message());
+        let Some((start, end)) = self.span() else {
+            out.push_str(&format!(" --> {}\n", path));
+            return out;
+        };
+        let (line, column) = Span::new(start, end).line_col(source);
+        let text = source.lines().nth(line - 1).unwrap_or_default();
+        let gutter = " ".repeat(line.to_string().len());
+        // Underline the span's first line, or one column for a point
+        let width = source.get(start..end).and_then(|text| text.lines().next()).map_or(0, |text| text.chars().count());
+        let before = text.get(..column - 1).unwrap_or_default();
+        let indent: String = before.chars().map(|c| if c == '\t' { '\t' } else { ' ' }).collect();
+        out.push_str(&format!("{}--> {}:{}:{}\n", gutter, path, line, column));
+        out.push_str(&format!("{} |\n", gutter));
+        out.push_str(&format!("{} | {}\n", line, text));
+        out.push_str(&format!("{} | {}{}\n", gutter, indent, "^".repeat(width.max(1))));
+        out
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ParseError::LexerError { message, .. } => write!(f, "Lexer error: {}", message),
+            ParseError::UnexpectedToken { message, .. } => write!(f, "Unexpected token: {}", message),
+            ParseError::Check { message, .. } => write!(f, "Invalid program: {}", message),
+            _ => write!(f, "Syntax error: {}", self.message()),
+        }
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+/// A found token as a message names it.
+fn describe_found(found: &str) -> String {
+    match found.trim() {
+        "" if found.contains('\n') => "end of line".to_string(),
+        "" => "whitespace".to_string(),
+        text => format!("`{}`", text),
+    }
+}
+
+/// `, expected ...` for a message, or nothing if the grammar said nothing.
+fn describe_expected(expected: &[String]) -> String {
+    match expected {
+        [] => String::new(),
+        [only] => format!(", expected {}", only),
+        _ if expected.len() <= MAX_LISTED => format!(", expected one of {}", expected.join(", ")),
+        _ => format!(
+            ", expected one of {} or {} more",
+            expected[..MAX_LISTED].join(", "),
+            expected.len() - MAX_LISTED
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse;
+
+    #[test]
+    fn test_grammar_errors_are_structured() {
+        let source = "skill main() {\n    var x = )\n}";
+        let error = parse(source).unwrap_err();
+        let at = source.rfind(')').unwrap();
+        match &error {
+            ParseError::UnrecognizedToken { found, expected, span } => {
+                assert_eq!(found, ")");
+                assert_eq!(*span, (at, at + 1));
+                assert!(expected.iter().any(|token| token == "identifier"), "{:?}", expected);
+                assert!(expected.iter().any(|token| token == "\"(\""), "{:?}", expected);
+            }
+            other => panic!("Expected UnrecognizedToken, got {:?}", other),
+        }
+        assert_eq!(error.found(), Some(")"));
+        assert!(error.message().starts_with("Unexpected `)`, expected one of "), "{}", error.message());
+
+        let rendered = error.render(source, "main.pw");
+        let lines: Vec<&str> = rendered.lines().collect();
+        assert_eq!(lines[1..], [" --> main.pw:2:13", "  |", "2 |     var x = )", "  |             ^"], "{}", rendered);
+
+        let eof = parse("skill main() {").unwrap_err();
+        assert!(matches!(eof, ParseError::UnexpectedEof { byte_offset: 14, .. }), "{:?}", eof);
+        assert!(eof.expected().contains(&"\"}\"".to_string()), "{:?}", eof);
+        assert_eq!(eof.map_offsets(|at| at + 100).span(), Some((114, 114)));
+    }
+
+    #[test]
+    fn test_expected_lists_are_summarized() {
+        let expected: Vec<String> = (1..=8).map(|n| n.to_string()).collect();
+        assert_eq!(describe_expected(&expected[..1]), ", expected 1");
+        assert_eq!(describe_expected(&expected), ", expected one of 1, 2, 3, 4, 5, 6 or 2 more");
+        assert_eq!(describe_found("\n"), "end of line");
+    }
+}
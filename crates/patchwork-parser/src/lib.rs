@@ -2,6 +2,13 @@ pub mod token;
 pub mod adapter;
 pub mod ast;
 pub mod ast_dump;
+pub mod cache;
+pub mod token_diff;
+pub mod migrate;
+pub mod check;
+pub mod error;
+pub mod literate;
+pub mod types;
 
 // Include generated parser code from lalrpop
 #[allow(clippy::all)]
@@ -9,16 +16,24 @@ mod patchwork {
     include!(concat!(env!("OUT_DIR"), "/patchwork.rs"));
 }
 
-pub use adapter::{LexerAdapter, ParseError};
+pub use adapter::{LexerAdapter, MAX_NESTING_DEPTH};
+pub use error::ParseError;
 pub use token::ParserToken;
+pub use cache::AstCache;
 pub use ast::*;
+pub use patchwork_lexer::Edition;
 
 use patchwork_lexer::lex_str;
 use lalrpop_util::ParseError as LalrpopError;
-use crate::adapter::ParseError::{LexerError, UnexpectedToken};
+use crate::error::ParseError::{ExtraToken, InvalidToken, LexerError, UnexpectedEof, UnrecognizedToken};
 
 /// Parse a patchwork program from a string
 pub fn parse(input: &str) -> Result<Program<'_>, ParseError> {
+    parse_with_edition(input, Edition::default())
+}
+
+/// Parse a patchwork program written for `edition`.
+pub fn parse_with_edition(input: &str, edition: Edition) -> Result<Program<'_>, ParseError> {
     // Create lexer
     let lexer = lex_str(input).map_err(|e| LexerError {
         message: e.to_string(),
@@ -27,50 +42,66 @@ pub fn parse(input: &str) -> Result<Program<'_>, ParseError> {
     })?;
 
     // Create adapter
-    let adapter = LexerAdapter::new(input, lexer);
+    let adapter = LexerAdapter::with_edition(input, lexer, edition);
 
     // Parse using generated parser
     patchwork::ProgramParser::new()
         .parse(input, adapter)
         .map_err(|e| match e {
-            LalrpopError::InvalidToken { location } => UnexpectedToken {
-                message: "Invalid token".to_string(),
-                byte_offset: Some(location),
-                span: Some((location, location)),
-            },
-            LalrpopError::UnrecognizedEof { location, expected } => UnexpectedToken {
-                message: format!("Unexpected end of file, expected: {:?}", expected),
-                byte_offset: Some(location),
-                span: Some((location, location)),
-            },
-            LalrpopError::UnrecognizedToken { token, expected } => {
-                let (location, tok, end) = token;
-                UnexpectedToken {
-                    message: format!("Unexpected token {:?}, expected: {:?}", tok, expected),
-                    byte_offset: Some(location),
-                    span: Some((location, end)),
-                }
-            }
-            LalrpopError::ExtraToken { token } => {
-                let (location, tok, end) = token;
-                UnexpectedToken {
-                    message: format!("Extra token {:?}", tok),
-                    byte_offset: Some(location),
-                    span: Some((location, end)),
-                }
-            }
-            LalrpopError::User { error } => UnexpectedToken {
-                message: format!("{:?}", error),
-                byte_offset: None,
-                span: None,
-            },
+            LalrpopError::InvalidToken { location } => InvalidToken { byte_offset: location },
+            LalrpopError::UnrecognizedEof { location, expected } => UnexpectedEof { expected, byte_offset: location },
+            // The adapter ends the input with an empty token
+            LalrpopError::UnrecognizedToken { token: (start, _, _), expected } if start == input.len() => {
+                UnexpectedEof { expected, byte_offset: start }
+            }
+            // An edition keyword where a name was expected is most likely
+            // an older program's identifier
+            LalrpopError::UnrecognizedToken { token: (start, _, end), expected }
+                if edition.reserves(&input[start..end]) && expected.iter().any(|e| e == "identifier") =>
+            {
+                ParseError::UnexpectedToken {
+                    message: format!("`{}` is a reserved keyword in edition {}", &input[start..end], edition),
+                    byte_offset: Some(start),
+                    span: Some((start, end)),
+                }
+            }
+            LalrpopError::UnrecognizedToken { token: (start, _, end), expected } => {
+                UnrecognizedToken { found: input[start..end].to_string(), expected, span: (start, end) }
+            }
+            LalrpopError::ExtraToken { token: (start, _, end) } => {
+                ExtraToken { found: input[start..end].to_string(), span: (start, end) }
+            }
+            // The adapter's own errors already carry a message and span
+            LalrpopError::User { error } => error,
         })
 }
 
+/// Tokenize a patchwork program, returning each token with its byte span.
+///
+/// This runs the same lexer and adapter the parser uses, so the tokens match
+/// what the grammar sees. Editor tooling uses it to recover source positions
+/// that the AST does not record.
+pub fn tokenize(input: &str) -> Result<Vec<(usize, ParserToken<'_>, usize)>, ParseError> {
+    let lexer = lex_str(input).map_err(|e| LexerError {
+        message: e.to_string(),
+        byte_offset: None,
+        span: None,
+    })?;
+    LexerAdapter::new(input, lexer).collect()
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    /// The expression of an expression statement.
+    fn expr_stmt<'a>(stmt: &'a Spanned<Statement<'a>>) -> &'a Expr<'a> {
+        match &stmt.node {
+            Statement::Expr(expr) => expr,
+            other => panic!("Expected expression statement, got {:?}", other),
+        }
+    }
+
     #[test]
     fn test_parse_empty() {
         // Empty input should parse successfully (empty program)
@@ -90,7 +121,7 @@ mod tests {
         let program = result.unwrap();
         assert_eq!(program.items.len(), 1);
 
-        match &program.items[0] {
+        match &program.items[0].node {
             Item::Import(decl) => {
                 match &decl.path {
                     ImportPath::Simple(parts) => {
@@ -113,7 +144,7 @@ mod tests {
         let program = result.unwrap();
         assert_eq!(program.items.len(), 1);
 
-        match &program.items[0] {
+        match &program.items[0].node {
             Item::Import(decl) => {
                 match &decl.path {
                     ImportPath::RelativeMulti(names) => {
@@ -138,7 +169,7 @@ mod tests {
         let program = result.unwrap();
         assert_eq!(program.items.len(), 1);
 
-        match &program.items[0] {
+        match &program.items[0].node {
             Item::Skill(decl) => {
                 assert_eq!(decl.name, "foo");
                 assert_eq!(decl.params.len(), 0);
@@ -157,7 +188,7 @@ mod tests {
         let program = result.unwrap();
         assert_eq!(program.items.len(), 1);
 
-        match &program.items[0] {
+        match &program.items[0].node {
             Item::Skill(decl) => {
                 assert_eq!(decl.name, "rewriting_git_branch");
                 assert_eq!(decl.params.len(), 1);
@@ -176,7 +207,7 @@ mod tests {
         let program = result.unwrap();
         assert_eq!(program.items.len(), 1);
 
-        match &program.items[0] {
+        match &program.items[0].node {
             Item::Worker(decl) => {
                 assert_eq!(decl.name, "analyst");
                 assert_eq!(decl.params.len(), 3);
@@ -197,7 +228,7 @@ mod tests {
         let program = result.unwrap();
         assert_eq!(program.items.len(), 1);
 
-        match &program.items[0] {
+        match &program.items[0].node {
             Item::Function(decl) => {
                 assert_eq!(decl.name, "helper");
                 assert_eq!(decl.params.len(), 2);
@@ -227,11 +258,11 @@ mod tests {
         assert_eq!(program.items.len(), 5);
 
         // Check item types
-        assert!(matches!(program.items[0], Item::Import(_)));
-        assert!(matches!(program.items[1], Item::Skill(_)));
-        assert!(matches!(program.items[2], Item::Worker(_)));
-        assert!(matches!(program.items[3], Item::Worker(_)));
-        assert!(matches!(program.items[4], Item::Function(_)));
+        assert!(matches!(program.items[0].node, Item::Import(_)));
+        assert!(matches!(program.items[1].node, Item::Skill(_)));
+        assert!(matches!(program.items[2].node, Item::Worker(_)));
+        assert!(matches!(program.items[3].node, Item::Worker(_)));
+        assert!(matches!(program.items[4].node, Item::Function(_)));
     }
 
     #[test]
@@ -250,14 +281,14 @@ mod tests {
         assert_eq!(program.items.len(), 2);
 
         // Verify import
-        match &program.items[0] {
+        match &program.items[0].node {
             Item::Import(decl) => {
                 match &decl.path {
                     ImportPath::RelativeMulti(names) => {
                         assert_eq!(names.len(), 3);
-                        assert!(names.contains(&"analyst"));
-                        assert!(names.contains(&"narrator"));
-                        assert!(names.contains(&"scribe"));
+                        assert!(names.contains(&"analyst".into()));
+                        assert!(names.contains(&"narrator".into()));
+                        assert!(names.contains(&"scribe".into()));
                     }
                     _ => panic!("Expected RelativeMulti import"),
                 }
@@ -266,7 +297,7 @@ mod tests {
         }
 
         // Verify skill
-        match &program.items[1] {
+        match &program.items[1].node {
             Item::Skill(decl) => {
                 assert_eq!(decl.name, "rewriting_git_branch");
                 assert_eq!(decl.params.len(), 1);
@@ -289,15 +320,15 @@ mod tests {
         assert!(result.is_ok(), "Failed to parse var x: {:?}", result);
 
         let program = result.unwrap();
-        let func = match &program.items[0] {
+        let func = match &program.items[0].node {
             Item::Worker(f) => f,
             _ => panic!("Expected worker"),
         };
 
         assert_eq!(func.body.statements.len(), 1);
-        match &func.body.statements[0] {
+        match &func.body.statements[0].node {
             Statement::VarDecl { pattern, init } => {
-                match pattern {
+                match &pattern.node {
                     Pattern::Identifier { name, type_ann } => {
                         assert_eq!(*name, "x");
                         assert!(type_ann.is_none());
@@ -321,15 +352,15 @@ mod tests {
         assert!(result.is_ok(), "Failed to parse var x = foo: {:?}", result);
 
         let program = result.unwrap();
-        let func = match &program.items[0] {
+        let func = match &program.items[0].node {
             Item::Worker(f) => f,
             _ => panic!("Expected worker"),
         };
 
         assert_eq!(func.body.statements.len(), 1);
-        match &func.body.statements[0] {
+        match &func.body.statements[0].node {
             Statement::VarDecl { pattern, init } => {
-                match pattern {
+                match &pattern.node {
                     Pattern::Identifier { name, type_ann } => {
                         assert_eq!(*name, "x");
                         assert!(type_ann.is_none());
@@ -337,7 +368,7 @@ mod tests {
                     _ => panic!("Expected identifier pattern"),
                 }
                 assert!(init.is_some());
-                match init.as_ref().unwrap() {
+                match &init.as_ref().unwrap().node {
                     Expr::Identifier(id) => assert_eq!(*id, "foo"),
                     _ => panic!("Expected identifier expression"),
                 }
@@ -357,19 +388,19 @@ mod tests {
         assert!(result.is_ok(), "Failed to parse var x: string: {:?}", result);
 
         let program = result.unwrap();
-        let func = match &program.items[0] {
+        let func = match &program.items[0].node {
             Item::Worker(f) => f,
             _ => panic!("Expected worker"),
         };
 
         assert_eq!(func.body.statements.len(), 1);
-        match &func.body.statements[0] {
+        match &func.body.statements[0].node {
             Statement::VarDecl { pattern, init } => {
-                match pattern {
+                match &pattern.node {
                     Pattern::Identifier { name, type_ann } => {
                         assert_eq!(*name, "x");
                         assert!(type_ann.is_some());
-                        match type_ann.as_ref().unwrap() {
+                        match &type_ann.as_ref().unwrap().node {
                             TypeExpr::Name(t) => assert_eq!(*t, "string"),
                             _ => panic!("Expected Name type"),
                         }
@@ -393,15 +424,15 @@ mod tests {
         assert!(result.is_ok(), "Failed to parse var x: int = 42: {:?}", result);
 
         let program = result.unwrap();
-        let func = match &program.items[0] {
+        let func = match &program.items[0].node {
             Item::Worker(f) => f,
             _ => panic!("Expected worker"),
         };
 
         assert_eq!(func.body.statements.len(), 1);
-        match &func.body.statements[0] {
+        match &func.body.statements[0].node {
             Statement::VarDecl { pattern, init } => {
-                match pattern {
+                match &pattern.node {
                     Pattern::Identifier { name, type_ann } => {
                         assert_eq!(*name, "x");
                         assert!(type_ann.is_some());
@@ -414,6 +445,32 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_const_decl() {
+        let input = r#"
+            worker test() {
+                const work_dir = "/tmp"
+                var config = { const: true }
+            }
+        "#;
+        let program = parse(input).unwrap();
+        let func = match &program.items[0].node {
+            Item::Worker(f) => f,
+            _ => panic!("Expected worker"),
+        };
+
+        match &func.body.statements[0].node {
+            Statement::ConstDecl { pattern, init } => {
+                assert!(matches!(&pattern.node, Pattern::Identifier { name, .. } if name == "work_dir"));
+                assert!(matches!(init.node, Expr::String(_)));
+            }
+            other => panic!("Expected ConstDecl, got {:?}", other),
+        }
+
+        // A constant must be initialized
+        assert!(parse("worker test() {\n    const x\n}").is_err());
+    }
+
     // ==================== Control Flow ====================
 
     #[test]
@@ -429,15 +486,15 @@ mod tests {
         assert!(result.is_ok(), "Failed to parse if statement: {:?}", result);
 
         let program = result.unwrap();
-        let func = match &program.items[0] {
+        let func = match &program.items[0].node {
             Item::Worker(f) => f,
             _ => panic!("Expected worker"),
         };
 
         assert_eq!(func.body.statements.len(), 1);
-        match &func.body.statements[0] {
+        match &func.body.statements[0].node {
             Statement::If { condition, then_block, else_block } => {
-                match condition {
+                match &condition.node {
                     Expr::Identifier(id) => assert_eq!(*id, "condition"),
                     _ => panic!("Expected identifier"),
                 }
@@ -463,12 +520,12 @@ mod tests {
         assert!(result.is_ok(), "Failed to parse if-else: {:?}", result);
 
         let program = result.unwrap();
-        let func = match &program.items[0] {
+        let func = match &program.items[0].node {
             Item::Worker(f) => f,
             _ => panic!("Expected worker"),
         };
 
-        match &func.body.statements[0] {
+        match &func.body.statements[0].node {
             Statement::If { condition: _, then_block, else_block } => {
                 assert_eq!(then_block.statements.len(), 1);
                 assert!(else_block.is_some());
@@ -491,15 +548,15 @@ mod tests {
         assert!(result.is_ok(), "Failed to parse for loop: {:?}", result);
 
         let program = result.unwrap();
-        let func = match &program.items[0] {
+        let func = match &program.items[0].node {
             Item::Worker(f) => f,
             _ => panic!("Expected worker"),
         };
 
-        match &func.body.statements[0] {
-            Statement::ForIn { var, iter, body } => {
-                assert_eq!(*var, "item");
-                match iter {
+        match &func.body.statements[0].node {
+            Statement::ForIn { pattern, iter, body } => {
+                assert!(matches!(&pattern.node, Pattern::Identifier { name, .. } if name == "item"));
+                match &iter.node {
                     Expr::Identifier(id) => assert_eq!(*id, "items"),
                     _ => panic!("Expected identifier"),
                 }
@@ -509,6 +566,33 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_for_loop_with_pattern() {
+        let input = r#"
+            worker test() {
+                for var {key, value} in config {
+                    print(key)
+                }
+            }
+        "#;
+        let program = parse(input).expect("Should parse for loop with pattern");
+        let func = match &program.items[0].node {
+            Item::Worker(f) => f,
+            _ => panic!("Expected worker"),
+        };
+
+        match &func.body.statements[0].node {
+            Statement::ForIn { pattern, .. } => match &pattern.node {
+                Pattern::Object(fields) => {
+                    let keys: Vec<&str> = fields.iter().map(|field| &*field.key).collect();
+                    assert_eq!(keys, ["key", "value"]);
+                }
+                _ => panic!("Expected object pattern"),
+            },
+            _ => panic!("Expected ForIn statement"),
+        }
+    }
+
     #[test]
     fn test_while_loop() {
         let input = r#"
@@ -522,14 +606,14 @@ mod tests {
         assert!(result.is_ok(), "Failed to parse while loop: {:?}", result);
 
         let program = result.unwrap();
-        let func = match &program.items[0] {
+        let func = match &program.items[0].node {
             Item::Worker(f) => f,
             _ => panic!("Expected worker"),
         };
 
-        match &func.body.statements[0] {
+        match &func.body.statements[0].node {
             Statement::While { condition, body } => {
-                match condition {
+                match &condition.node {
                     Expr::Identifier(id) => assert_eq!(*id, "condition"),
                     _ => panic!("Expected identifier"),
                 }
@@ -539,6 +623,37 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_while_var_loop() {
+        let input = r#"
+            worker test() {
+                while var msg = self.receive(5000) {
+                    var x = msg
+                }
+            }
+        "#;
+        let result = parse(input);
+        assert!(result.is_ok(), "Failed to parse while-var loop: {:?}", result);
+
+        let program = result.unwrap();
+        let func = match &program.items[0].node {
+            Item::Worker(f) => f,
+            _ => panic!("Expected worker"),
+        };
+
+        match &func.body.statements[0].node {
+            Statement::WhileVar { pattern, init, body } => {
+                match &pattern.node {
+                    Pattern::Identifier { name, .. } => assert_eq!(*name, "msg"),
+                    _ => panic!("Expected identifier pattern"),
+                }
+                assert!(matches!(&init.node, Expr::Call { .. }), "Expected call, got {:?}", init);
+                assert_eq!(body.statements.len(), 1);
+            }
+            _ => panic!("Expected WhileVar statement"),
+        }
+    }
+
     // ==================== Flow Control Keywords ====================
 
     #[test]
@@ -552,12 +667,12 @@ mod tests {
         assert!(result.is_ok(), "Failed to parse return: {:?}", result);
 
         let program = result.unwrap();
-        let func = match &program.items[0] {
+        let func = match &program.items[0].node {
             Item::Worker(f) => f,
             _ => panic!("Expected worker"),
         };
 
-        match &func.body.statements[0] {
+        match &func.body.statements[0].node {
             Statement::Return(expr) => {
                 assert!(expr.is_none(), "Expected return with no value");
             }
@@ -576,15 +691,15 @@ mod tests {
         assert!(result.is_ok(), "Failed to parse return value: {:?}", result);
 
         let program = result.unwrap();
-        let func = match &program.items[0] {
+        let func = match &program.items[0].node {
             Item::Worker(f) => f,
             _ => panic!("Expected worker"),
         };
 
-        match &func.body.statements[0] {
+        match &func.body.statements[0].node {
             Statement::Return(expr) => {
                 assert!(expr.is_some(), "Expected return with value");
-                match expr.as_ref().unwrap() {
+                match &expr.as_ref().unwrap().node {
                     Expr::Identifier(id) => assert_eq!(*id, "value"),
                     _ => panic!("Expected identifier"),
                 }
@@ -606,21 +721,21 @@ mod tests {
         assert!(result.is_ok(), "Failed to parse succeed/throw/break: {:?}", result);
 
         let program = result.unwrap();
-        let task = match &program.items[0] {
+        let task = match &program.items[0].node {
             Item::Worker(t) => t,
             _ => panic!("Expected worker"),
         };
 
         assert_eq!(task.body.statements.len(), 3);
-        assert!(matches!(task.body.statements[0], Statement::Succeed));
+        assert!(matches!(task.body.statements[0].node, Statement::Succeed));
 
         // Check throw is a unary expression
-        match &task.body.statements[1] {
-            Statement::Expr(Expr::Unary { op: UnOp::Throw, .. }) => {},
+        match expr_stmt(&task.body.statements[1]) {
+            Expr::Unary { op: UnOp::Throw, .. } => {},
             _ => panic!("Expected throw expression"),
         }
 
-        assert!(matches!(task.body.statements[2], Statement::Break));
+        assert!(matches!(task.body.statements[2].node, Statement::Break));
     }
 
     // ==================== Statement Separation ====================
@@ -639,7 +754,7 @@ mod tests {
         assert!(result.is_ok(), "Failed to parse return with newline: {:?}", result);
 
         let program = result.unwrap();
-        let func = match &program.items[0] {
+        let func = match &program.items[0].node {
             Item::Worker(f) => f,
             _ => panic!("Expected worker"),
         };
@@ -647,15 +762,15 @@ mod tests {
         // Should have TWO statements: return (no value) and x (expression statement)
         assert_eq!(func.body.statements.len(), 2, "Expected 2 statements");
 
-        match &func.body.statements[0] {
+        match &func.body.statements[0].node {
             Statement::Return(expr) => {
                 assert!(expr.is_none(), "return should have no value (separated by newline)");
             }
             _ => panic!("Expected Return statement"),
         }
 
-        match &func.body.statements[1] {
-            Statement::Expr(Expr::Identifier(id)) => {
+        match expr_stmt(&func.body.statements[1]) {
+            Expr::Identifier(id) => {
                 assert_eq!(*id, "x");
             }
             _ => panic!("Expected expression statement"),
@@ -673,7 +788,7 @@ mod tests {
         assert!(result.is_ok(), "Failed to parse semicolon-separated statements: {:?}", result);
 
         let program = result.unwrap();
-        let func = match &program.items[0] {
+        let func = match &program.items[0].node {
             Item::Worker(f) => f,
             _ => panic!("Expected worker"),
         };
@@ -698,7 +813,7 @@ mod tests {
         assert!(result.is_ok(), "Failed to parse multiple statements: {:?}", result);
 
         let program = result.unwrap();
-        let func = match &program.items[0] {
+        let func = match &program.items[0].node {
             Item::Worker(f) => f,
             _ => panic!("Expected worker"),
         };
@@ -719,19 +834,51 @@ mod tests {
         assert!(result.is_ok(), "Failed to parse expression statements: {:?}", result);
 
         let program = result.unwrap();
-        let func = match &program.items[0] {
+        let func = match &program.items[0].node {
             Item::Worker(f) => f,
             _ => panic!("Expected worker"),
         };
 
         assert_eq!(func.body.statements.len(), 3);
-        assert!(matches!(func.body.statements[0], Statement::Expr(Expr::Identifier(_))));
-        assert!(matches!(func.body.statements[1], Statement::Expr(Expr::Number(_))));
-        assert!(matches!(func.body.statements[2], Statement::Expr(Expr::True)));
+        assert!(matches!(expr_stmt(&func.body.statements[0]), Expr::Identifier(_)));
+        assert!(matches!(expr_stmt(&func.body.statements[1]), Expr::Number(_)));
+        assert!(matches!(expr_stmt(&func.body.statements[2]), Expr::True));
     }
 
     // ==================== Basic Expression Tests ====================
 
+    #[test]
+    fn test_duration_and_size_literals() {
+        let input = r#"
+            worker test() {
+                var timeout = 5s
+                retry(200ms, 10kb)
+            }
+        "#;
+        let result = parse(input);
+        assert!(result.is_ok(), "Failed to parse unit literals: {:?}", result);
+
+        let program = result.unwrap();
+        let func = match &program.items[0].node {
+            Item::Worker(f) => f,
+            _ => panic!("Expected worker"),
+        };
+
+        match &func.body.statements[0].node {
+            Statement::VarDecl { init, .. } => {
+                assert!(matches!(init.as_deref(), Some(Expr::Duration(v)) if v == "5s"));
+            }
+            _ => panic!("Expected VarDecl"),
+        }
+        match expr_stmt(&func.body.statements[1]) {
+            Expr::Call { args, .. } => {
+                assert!(matches!(&args[0].node, Expr::Duration(v) if v == "200ms"));
+                assert!(matches!(&args[1].node, Expr::Size(v) if v == "10kb"));
+            }
+            _ => panic!("Expected call"),
+        }
+    }
+
     #[test]
     fn test_literals() {
         let input = r#"
@@ -747,17 +894,17 @@ mod tests {
         assert!(result.is_ok(), "Failed to parse literals: {:?}", result);
 
         let program = result.unwrap();
-        let func = match &program.items[0] {
+        let func = match &program.items[0].node {
             Item::Worker(f) => f,
             _ => panic!("Expected worker"),
         };
 
         assert_eq!(func.body.statements.len(), 5);
-        assert!(matches!(func.body.statements[0], Statement::Expr(Expr::Number("42"))));
-        assert!(matches!(func.body.statements[1], Statement::Expr(Expr::String(_))));
-        assert!(matches!(func.body.statements[2], Statement::Expr(Expr::True)));
-        assert!(matches!(func.body.statements[3], Statement::Expr(Expr::False)));
-        assert!(matches!(func.body.statements[4], Statement::Expr(Expr::Identifier("foo"))));
+        assert!(matches!(expr_stmt(&func.body.statements[0]), Expr::Number(v) if v == "42"));
+        assert!(matches!(expr_stmt(&func.body.statements[1]), Expr::String(_)));
+        assert!(matches!(expr_stmt(&func.body.statements[2]), Expr::True));
+        assert!(matches!(expr_stmt(&func.body.statements[3]), Expr::False));
+        assert!(matches!(expr_stmt(&func.body.statements[4]), Expr::Identifier(v) if v == "foo"));
     }
 
     #[test]
@@ -771,18 +918,18 @@ mod tests {
         assert!(result.is_ok(), "Failed to parse string literal: {:?}", result);
 
         let program = result.unwrap();
-        let func = match &program.items[0] {
+        let func = match &program.items[0].node {
             Item::Worker(f) => f,
             _ => panic!("Expected worker"),
         };
 
-        match &func.body.statements[0] {
+        match &func.body.statements[0].node {
             Statement::VarDecl { pattern, init } => {
-                match pattern {
+                match &pattern.node {
                     Pattern::Identifier { name, .. } => assert_eq!(*name, "x"),
                     _ => panic!("Expected identifier pattern"),
                 }
-                match init.as_ref().unwrap() {
+                match &init.as_ref().unwrap().node {
                     Expr::String(s) => {
                         assert_eq!(s.parts.len(), 1);
                         match &s.parts[0] {
@@ -811,7 +958,7 @@ mod tests {
         assert!(result.is_ok(), "Failed to parse binary arithmetic: {:?}", result);
 
         let program = result.unwrap();
-        let func = match &program.items[0] {
+        let func = match &program.items[0].node {
             Item::Worker(f) => f,
             _ => panic!("Expected worker"),
         };
@@ -819,8 +966,8 @@ mod tests {
         assert_eq!(func.body.statements.len(), 4);
 
         // Check first binary op: 1 + 2
-        match &func.body.statements[0] {
-            Statement::Expr(Expr::Binary { op, .. }) => {
+        match expr_stmt(&func.body.statements[0]) {
+            Expr::Binary { op, .. } => {
                 assert!(matches!(op, BinOp::Add));
             }
             _ => panic!("Expected binary expression"),
@@ -839,20 +986,20 @@ mod tests {
         assert!(result.is_ok(), "Failed to parse precedence: {:?}", result);
 
         let program = result.unwrap();
-        let func = match &program.items[0] {
+        let func = match &program.items[0].node {
             Item::Worker(f) => f,
             _ => panic!("Expected worker"),
         };
 
-        match &func.body.statements[0] {
+        match &func.body.statements[0].node {
             Statement::VarDecl { init, .. } => {
-                match init.as_ref().unwrap() {
+                match &init.as_ref().unwrap().node {
                     // Should be: Add(1, Mul(2, 3))
                     Expr::Binary { op: BinOp::Add, left, right } => {
                         // Left should be 1
-                        assert!(matches!(**left, Expr::Number("1")));
+                        assert!(matches!(&left.node, Expr::Number(v) if v == "1"));
                         // Right should be 2 * 3
-                        match &**right {
+                        match &right.node {
                             Expr::Binary { op: BinOp::Mul, .. } => {},
                             _ => panic!("Expected multiplication on right side"),
                         }
@@ -878,7 +1025,7 @@ mod tests {
         assert!(result.is_ok(), "Failed to parse comparisons: {:?}", result);
 
         let program = result.unwrap();
-        let func = match &program.items[0] {
+        let func = match &program.items[0].node {
             Item::Worker(f) => f,
             _ => panic!("Expected worker"),
         };
@@ -887,8 +1034,8 @@ mod tests {
 
         let ops = vec![BinOp::Eq, BinOp::NotEq, BinOp::Lt, BinOp::Gt];
         for (i, expected_op) in ops.iter().enumerate() {
-            match &func.body.statements[i] {
-                Statement::Expr(Expr::Binary { op, .. }) => {
+            match expr_stmt(&func.body.statements[i]) {
+                Expr::Binary { op, .. } => {
                     assert_eq!(op, expected_op);
                 }
                 _ => panic!("Expected binary expression"),
@@ -908,20 +1055,20 @@ mod tests {
         assert!(result.is_ok(), "Failed to parse logical ops: {:?}", result);
 
         let program = result.unwrap();
-        let func = match &program.items[0] {
+        let func = match &program.items[0].node {
             Item::Worker(f) => f,
             _ => panic!("Expected worker"),
         };
 
         assert_eq!(func.body.statements.len(), 2);
 
-        match &func.body.statements[0] {
-            Statement::Expr(Expr::Binary { op: BinOp::And, .. }) => {},
+        match expr_stmt(&func.body.statements[0]) {
+            Expr::Binary { op: BinOp::And, .. } => {},
             _ => panic!("Expected && expression"),
         }
 
-        match &func.body.statements[1] {
-            Statement::Expr(Expr::Binary { op: BinOp::Or, .. }) => {},
+        match expr_stmt(&func.body.statements[1]) {
+            Expr::Binary { op: BinOp::Or, .. } => {},
             _ => panic!("Expected || expression"),
         }
     }
@@ -938,20 +1085,20 @@ mod tests {
         assert!(result.is_ok(), "Failed to parse unary ops: {:?}", result);
 
         let program = result.unwrap();
-        let func = match &program.items[0] {
+        let func = match &program.items[0].node {
             Item::Worker(f) => f,
             _ => panic!("Expected worker"),
         };
 
         assert_eq!(func.body.statements.len(), 2);
 
-        match &func.body.statements[0] {
-            Statement::Expr(Expr::Unary { op: UnOp::Not, .. }) => {},
+        match expr_stmt(&func.body.statements[0]) {
+            Expr::Unary { op: UnOp::Not, .. } => {},
             _ => panic!("Expected ! expression"),
         }
 
-        match &func.body.statements[1] {
-            Statement::Expr(Expr::Unary { op: UnOp::Neg, .. }) => {},
+        match expr_stmt(&func.body.statements[1]) {
+            Expr::Unary { op: UnOp::Neg, .. } => {},
             _ => panic!("Expected - expression"),
         }
     }
@@ -967,14 +1114,14 @@ mod tests {
         assert!(result.is_ok(), "Failed to parse function call: {:?}", result);
 
         let program = result.unwrap();
-        let func = match &program.items[0] {
+        let func = match &program.items[0].node {
             Item::Worker(f) => f,
             _ => panic!("Expected worker"),
         };
 
-        match &func.body.statements[0] {
-            Statement::Expr(Expr::Call { callee, args }) => {
-                match &**callee {
+        match expr_stmt(&func.body.statements[0]) {
+            Expr::Call { callee, args } => {
+                match &callee.node {
                     Expr::Identifier(name) => assert_eq!(*name, "log"),
                     _ => panic!("Expected identifier as callee"),
                 }
@@ -996,16 +1143,16 @@ mod tests {
         assert!(result.is_ok(), "Failed to parse member access: {:?}", result);
 
         let program = result.unwrap();
-        let func = match &program.items[0] {
+        let func = match &program.items[0].node {
             Item::Worker(f) => f,
             _ => panic!("Expected worker"),
         };
 
         assert_eq!(func.body.statements.len(), 2);
 
-        match &func.body.statements[0] {
-            Statement::Expr(Expr::Member { object, field }) => {
-                match &**object {
+        match expr_stmt(&func.body.statements[0]) {
+            Expr::Member { object, field } => {
+                match &object.node {
                     Expr::Identifier(name) => assert_eq!(*name, "commit"),
                     _ => panic!("Expected identifier as object"),
                 }
@@ -1026,17 +1173,17 @@ mod tests {
         assert!(result.is_ok(), "Failed to parse method call: {:?}", result);
 
         let program = result.unwrap();
-        let func = match &program.items[0] {
+        let func = match &program.items[0].node {
             Item::Worker(f) => f,
             _ => panic!("Expected worker"),
         };
 
-        match &func.body.statements[0] {
-            Statement::Expr(Expr::Call { callee, args }) => {
+        match expr_stmt(&func.body.statements[0]) {
+            Expr::Call { callee, args } => {
                 // Callee should be self.receive
-                match &**callee {
+                match &callee.node {
                     Expr::Member { object, field } => {
-                        match &**object {
+                        match &object.node {
                             Expr::Identifier(name) => assert_eq!(*name, "self"),
                             _ => panic!("Expected self as object"),
                         }
@@ -1062,20 +1209,20 @@ mod tests {
         assert!(result.is_ok(), "Failed to parse index access: {:?}", result);
 
         let program = result.unwrap();
-        let func = match &program.items[0] {
+        let func = match &program.items[0].node {
             Item::Worker(f) => f,
             _ => panic!("Expected worker"),
         };
 
         assert_eq!(func.body.statements.len(), 2);
 
-        match &func.body.statements[0] {
-            Statement::Expr(Expr::Index { object, index }) => {
-                match &**object {
+        match expr_stmt(&func.body.statements[0]) {
+            Expr::Index { object, index } => {
+                match &object.node {
                     Expr::Identifier(name) => assert_eq!(*name, "arr"),
                     _ => panic!("Expected identifier as object"),
                 }
-                match &**index {
+                match &index.node {
                     Expr::Identifier(name) => assert_eq!(*name, "i"),
                     _ => panic!("Expected identifier as index"),
                 }
@@ -1095,15 +1242,15 @@ mod tests {
         assert!(result.is_ok(), "Failed to parse range: {:?}", result);
 
         let program = result.unwrap();
-        let func = match &program.items[0] {
+        let func = match &program.items[0].node {
             Item::Worker(f) => f,
             _ => panic!("Expected worker"),
         };
 
-        match &func.body.statements[0] {
-            Statement::Expr(Expr::Binary { op: BinOp::Range, left, right }) => {
-                assert!(matches!(**left, Expr::Number("1")));
-                assert!(matches!(**right, Expr::Number("3")));
+        match expr_stmt(&func.body.statements[0]) {
+            Expr::Binary { op: BinOp::Range, left, right } => {
+                assert!(matches!(&left.node, Expr::Number(v) if v == "1"));
+                assert!(matches!(&right.node, Expr::Number(v) if v == "3"));
             }
             _ => panic!("Expected range expression"),
         }
@@ -1120,24 +1267,24 @@ mod tests {
         assert!(result.is_ok(), "Failed to parse parenthesized expr: {:?}", result);
 
         let program = result.unwrap();
-        let func = match &program.items[0] {
+        let func = match &program.items[0].node {
             Item::Worker(f) => f,
             _ => panic!("Expected worker"),
         };
 
         // Should parse as Mul(Paren(Add(x, y)), z)
-        match &func.body.statements[0] {
-            Statement::Expr(Expr::Binary { op: BinOp::Mul, left, right }) => {
-                match &**left {
+        match expr_stmt(&func.body.statements[0]) {
+            Expr::Binary { op: BinOp::Mul, left, right } => {
+                match &left.node {
                     Expr::Paren(inner) => {
-                        match &**inner {
+                        match &inner.node {
                             Expr::Binary { op: BinOp::Add, .. } => {},
                             _ => panic!("Expected Add inside parens"),
                         }
                     }
                     _ => panic!("Expected parenthesized expression"),
                 }
-                assert!(matches!(**right, Expr::Identifier("z")));
+                assert!(matches!(&right.node, Expr::Identifier(v) if v == "z"));
             }
             _ => panic!("Expected multiplication"),
         }
@@ -1154,17 +1301,17 @@ mod tests {
         assert!(result.is_ok(), "Failed to parse complex expression: {:?}", result);
 
         let program = result.unwrap();
-        let func = match &program.items[0] {
+        let func = match &program.items[0].node {
             Item::Worker(f) => f,
             _ => panic!("Expected worker"),
         };
 
         // Should parse successfully - verify it's a var decl with a complex init
-        match &func.body.statements[0] {
+        match &func.body.statements[0].node {
             Statement::VarDecl { init, .. } => {
                 assert!(init.is_some(), "Expected init expression");
                 // It should be an Eq comparison
-                match init.as_ref().unwrap() {
+                match &init.as_ref().unwrap().node {
                     Expr::Binary { op: BinOp::Eq, .. } => {},
                     _ => panic!("Expected == comparison at top level"),
                 }
@@ -1188,17 +1335,17 @@ mod tests {
         assert_eq!(program.items.len(), 1);
 
         // Verify it's a task with a var decl containing a Think expression
-        match &program.items[0] {
+        match &program.items[0].node {
             Item::Worker(task) => {
                 assert_eq!(task.body.statements.len(), 1);
-                match &task.body.statements[0] {
+                match &task.body.statements[0].node {
                     Statement::VarDecl { pattern, init } => {
-                        match pattern {
+                        match &pattern.node {
                             Pattern::Identifier { name, .. } => assert_eq!(*name, "x"),
                             _ => panic!("Expected identifier pattern"),
                         }
                         assert!(init.is_some());
-                        match init.as_ref().unwrap() {
+                        match &init.as_ref().unwrap().node {
                             Expr::Think(_) => {}, // Success!
                             _ => panic!("Expected Think expression"),
                         }
@@ -1222,12 +1369,12 @@ mod tests {
         let program = parse(input).expect("Should parse");
         assert_eq!(program.items.len(), 1);
 
-        match &program.items[0] {
+        match &program.items[0].node {
             Item::Worker(task) => {
                 assert_eq!(task.body.statements.len(), 1);
-                match &task.body.statements[0] {
+                match &task.body.statements[0].node {
                     Statement::VarDecl { init, .. } => {
-                        match init.as_ref().unwrap() {
+                        match &init.as_ref().unwrap().node {
                             Expr::Ask(_) => {}, // Success!
                             _ => panic!("Expected Ask expression"),
                         }
@@ -1239,6 +1386,217 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_think_with_variants() {
+        let input = r#"
+            worker test() {
+                var a = think {
+                    Summarize briefly
+                } variant "v2" {
+                    Summarize in detail
+                }
+                var b = ask variant "polite" { May I? } variant "terse" { OK? }
+            }
+        "#;
+        let program = parse(input).expect("Should parse");
+
+        let Item::Worker(task) = &program.items[0].node else {
+            panic!("Expected worker");
+        };
+        let prompts: Vec<&PromptBlock> = task
+            .body
+            .statements
+            .iter()
+            .map(|stmt| match &stmt.node {
+                Statement::VarDecl { init: Some(init), .. } => match &init.node {
+                    Expr::Think(p) | Expr::Ask(p) => p,
+                    other => panic!("Expected prompt, got {:?}", other),
+                },
+                other => panic!("Expected prompt var decl, got {:?}", other),
+            })
+            .collect();
+
+        fn names<'a>(p: &'a PromptBlock) -> Vec<&'a str> {
+            p.variants.iter().map(|v| &*v.name).collect()
+        }
+        assert_eq!(names(prompts[0]), vec!["v2"]);
+        assert_eq!(prompts[0].items, vec![PromptItem::Text("Summarize briefly".into())]);
+        assert_eq!(prompts[0].variants[0].items, vec![PromptItem::Text("Summarize in detail".into())]);
+        // A named first text is also the default
+        assert_eq!(names(prompts[1]), vec!["polite", "terse"]);
+        assert_eq!(prompts[1].items, prompts[1].variants[0].items);
+    }
+
+    #[test]
+    fn test_think_with_examples() {
+        let input = r#"example approve {
+    Diff: adds a test. Verdict: approve
+}
+
+worker test() {
+    var a = ask with examples [approve, reject] {
+        Verdict?
+    }
+}"#;
+        let program = parse_with_edition(input, Edition::E2025).expect("Should parse");
+        let Item::Example(example) = &program.items[0].node else {
+            panic!("Expected example");
+        };
+        assert_eq!(example.name, "approve");
+        assert_eq!(example.items, vec![PromptItem::Text("Diff: adds a test. Verdict: approve".into())]);
+
+        let Item::Worker(task) = &program.items[1].node else {
+            panic!("Expected worker");
+        };
+        let Statement::VarDecl { init: Some(init), .. } = &task.body.statements[0].node else {
+            panic!("Expected var decl");
+        };
+        let Expr::Ask(prompt) = &init.node else {
+            panic!("Expected ask, got {:?}", init.node);
+        };
+        assert_eq!(prompt.examples.iter().map(|name| &*name.node).collect::<Vec<_>>(), vec!["approve", "reject"]);
+        assert_eq!(prompt.items, vec![PromptItem::Text("Verdict?".into())]);
+
+        assert!(parse_with_edition("fun f() {\n    think with samples [a] { Hi }\n}", Edition::E2025).is_err());
+
+        let fragment = "prompt fragment style {\n    Be ${tone}\n}";
+        let program = parse_with_edition(fragment, Edition::E2025).expect("Should parse");
+        let Item::Fragment(decl) = &program.items[0].node else {
+            panic!("Expected fragment, got {:?}", program.items[0].node);
+        };
+        assert_eq!(decl.name, "style");
+        assert_eq!(decl.items.len(), 2);
+        assert!(parse_with_edition("text fragment style {\n    Be\n}", Edition::E2025).is_err());
+        // `example` is only a keyword from edition 2025 on
+        assert!(parse("fun f(example) {\n    example\n}").is_ok());
+        assert!(parse(input).is_err());
+    }
+
+    #[test]
+    fn test_think_with_validator() {
+        let input = r#"worker test() {
+    var plan = think {
+        Plan the commits.
+    } validate (r) retries 3 {
+        r.commits.length > 0
+    }
+    var name = ask { Name? } validate (n) { n != "" }
+}"#;
+        let program = parse(input).expect("Should parse");
+        let Item::Worker(task) = &program.items[0].node else {
+            panic!("Expected worker");
+        };
+        let validators: Vec<_> = task
+            .body
+            .statements
+            .iter()
+            .map(|stmt| match &stmt.node {
+                Statement::VarDecl { init: Some(init), .. } => match &init.node {
+                    Expr::Think(prompt) | Expr::Ask(prompt) => prompt.validator.as_deref().expect("Expected validator"),
+                    other => panic!("Expected prompt, got {:?}", other),
+                },
+                other => panic!("Expected var decl, got {:?}", other),
+            })
+            .collect();
+        assert_eq!((&*validators[0].param, validators[0].retries.as_deref()), ("r", Some("3")));
+        assert_eq!(validators[0].body.statements.len(), 1);
+        assert_eq!((&*validators[1].param, validators[1].retries.as_deref()), ("n", None));
+
+        assert!(parse("fun f() {
+    think { Hi } verify (r) { r }
+}").is_err());
+        assert!(parse("fun f() {
+    think { Hi } validate (r) tries 2 { r }
+}").is_err());
+    }
+
+    #[test]
+    fn test_think_with_tools() {
+        let input = r#"fun f(readonly) {
+    var a = think(tools: ["read_file", "grep"]) { Summarize the diff. }
+    var b = think(tools: readonly) with examples [good] { Review it. }
+}"#;
+        let program = parse(input).expect("Should parse");
+        let Item::Function(func) = &program.items[0].node else {
+            panic!("Expected function");
+        };
+        let tools: Vec<_> = func
+            .body
+            .statements
+            .iter()
+            .map(|stmt| match &stmt.node {
+                Statement::VarDecl { init: Some(init), .. } => match &init.node {
+                    Expr::Think(prompt) => (&prompt.items, &prompt.tools.as_deref().expect("Expected tools").node),
+                    other => panic!("Expected think, got {:?}", other),
+                },
+                other => panic!("Expected var decl, got {:?}", other),
+            })
+            .collect();
+        assert_eq!(*tools[0].0, vec![PromptItem::Text("Summarize the diff.".into())]);
+        assert!(matches!(tools[0].1, Expr::Array(items) if items.len() == 2));
+        assert_eq!(*tools[1].0, vec![PromptItem::Text("Review it.".into())]);
+        assert_eq!(tools[1].1, &Expr::Identifier("readonly".into()));
+
+        assert!(parse("fun f() {
+    var a = think(only: []) { Hi }
+}").is_err());
+    }
+
+    #[test]
+    fn test_think_start() {
+        let input = r#"fun f() {
+    var chat = think.start(tools: ["grep"]) { Draft a plan. }
+    var once = think { Draft a plan. }
+}"#;
+        let program = parse(input).expect("Should parse");
+        let Item::Function(func) = &program.items[0].node else {
+            panic!("Expected function");
+        };
+        let conversations: Vec<_> = func
+            .body
+            .statements
+            .iter()
+            .map(|stmt| match &stmt.node {
+                Statement::VarDecl { init: Some(init), .. } => match &init.node {
+                    Expr::Think(prompt) => (prompt.conversation, &prompt.items),
+                    other => panic!("Expected think, got {:?}", other),
+                },
+                other => panic!("Expected var decl, got {:?}", other),
+            })
+            .collect();
+        let text = vec![PromptItem::Text("Draft a plan.".into())];
+        assert_eq!(conversations, vec![(true, &text), (false, &text)]);
+
+        assert!(parse("fun f() {
+    var a = think.begin { Hi }
+}").is_err());
+    }
+
+    #[test]
+    fn test_await_all() {
+        let input = "fun f(a, b) {
+    var results = await all [a, b.await]
+}";
+        let program = parse(input).expect("Should parse");
+        let Item::Function(func) = &program.items[0].node else {
+            panic!("Expected function");
+        };
+        let Statement::VarDecl { init: Some(init), .. } = &func.body.statements[0].node else {
+            panic!("Expected var decl");
+        };
+        let Expr::AwaitAll(operand) = &init.node else {
+            panic!("Expected await all, got {:?}", init.node);
+        };
+        let Expr::Array(items) = &operand.node else {
+            panic!("Expected array, got {:?}", operand.node);
+        };
+        assert!(matches!(items[1].node, Expr::Await(_)));
+
+        assert!(parse("fun f(a) {
+    var x = await any [a]
+}").is_err());
+    }
+
     #[test]
     fn test_think_with_fallback() {
         let input = r#"
@@ -1254,15 +1612,15 @@ mod tests {
         assert_eq!(program.items.len(), 1);
 
         // The || creates a Binary expr with Think on left and Ask on right
-        match &program.items[0] {
+        match &program.items[0].node {
             Item::Worker(task) => {
-                match &task.body.statements[0] {
+                match &task.body.statements[0].node {
                     Statement::VarDecl { init, .. } => {
-                        match init.as_ref().unwrap() {
+                        match &init.as_ref().unwrap().node {
                             Expr::Binary { op: BinOp::Or, left, right } => {
                                 // Left should be Think, right should be Ask
-                                assert!(matches!(**left, Expr::Think(_)));
-                                assert!(matches!(**right, Expr::Ask(_)));
+                                assert!(matches!(left.node, Expr::Think(_)));
+                                assert!(matches!(right.node, Expr::Ask(_)));
                             }
                             _ => panic!("Expected Binary Or expression"),
                         }
@@ -1292,11 +1650,11 @@ mod tests {
 
         // PromptBlock should have multiple items: text words, then code block, then more text words
         // Note: lexer splits prompt text into individual words
-        match &program.items[0] {
+        match &program.items[0].node {
             Item::Worker(task) => {
-                match &task.body.statements[0] {
+                match &task.body.statements[0].node {
                     Statement::VarDecl { init, .. } => {
-                        match init.as_ref().unwrap() {
+                        match &init.as_ref().unwrap().node {
                             Expr::Think(prompt_block) => {
                                 // Should have at least some items
                                 assert!(prompt_block.items.len() > 0);
@@ -1432,11 +1790,11 @@ mod tests {
         let program = parse(input).expect("Should parse");
 
         // Extract the prompt block from: program -> worker -> var decl -> think expr
-        match &program.items[0] {
+        match &program.items[0].node {
             Item::Worker(task) => {
-                match &task.body.statements[0] {
+                match &task.body.statements[0].node {
                     Statement::VarDecl { init, .. } => {
-                        match init.as_ref().unwrap() {
+                        match &init.as_ref().unwrap().node {
                             Expr::Think(prompt) => {
                                 // Should have exactly 3 items:
                                 // 1. Text("This is a multi-word sentence with")
@@ -1457,7 +1815,7 @@ mod tests {
 
                                 // Verify second item is interpolation
                                 match &prompt.items[1] {
-                                    PromptItem::Interpolation(Expr::Identifier("variable")) => {},
+                                    PromptItem::Interpolation(expr) if matches!(&expr.node, Expr::Identifier(v) if v == "variable") => {},
                                     _ => panic!("Expected second item to be Interpolation($variable)"),
                                 }
 
@@ -1491,14 +1849,14 @@ mod tests {
             }
         "#;
         let program = parse(input).expect("Should parse");
-        let func = match &program.items[0] {
+        let func = match &program.items[0].node {
             Item::Worker(f) => f,
             _ => panic!("Expected worker"),
         };
 
-        match &func.body.statements[0] {
+        match &func.body.statements[0].node {
             Statement::VarDecl { init, .. } => {
-                match init.as_ref().unwrap() {
+                match &init.as_ref().unwrap().node {
                     Expr::String(s) => {
                         assert_eq!(s.parts.len(), 2);
                         match &s.parts[0] {
@@ -1507,7 +1865,7 @@ mod tests {
                         }
                         match &s.parts[1] {
                             StringPart::Interpolation(expr) => {
-                                match expr.as_ref() {
+                                match &expr.node {
                                     Expr::Identifier(id) => assert_eq!(*id, "name"),
                                     _ => panic!("Expected identifier"),
                                 }
@@ -1531,14 +1889,14 @@ mod tests {
             }
         "#;
         let program = parse(input).expect("Should parse");
-        let func = match &program.items[0] {
+        let func = match &program.items[0].node {
             Item::Worker(f) => f,
             _ => panic!("Expected worker"),
         };
 
-        match &func.body.statements[0] {
+        match &func.body.statements[0].node {
             Statement::VarDecl { init, .. } => {
-                match init.as_ref().unwrap() {
+                match &init.as_ref().unwrap().node {
                     Expr::String(s) => {
                         assert_eq!(s.parts.len(), 2);
                         match &s.parts[0] {
@@ -1547,7 +1905,7 @@ mod tests {
                         }
                         match &s.parts[1] {
                             StringPart::Interpolation(expr) => {
-                                match expr.as_ref() {
+                                match &expr.node {
                                     Expr::Binary { op: BinOp::Add, .. } => {},
                                     _ => panic!("Expected binary add expression"),
                                 }
@@ -1572,14 +1930,14 @@ mod tests {
             }
         "#;
         let program = parse(input).expect("Should parse");
-        let func = match &program.items[0] {
+        let func = match &program.items[0].node {
             Item::Worker(f) => f,
             _ => panic!("Expected worker"),
         };
 
-        match &func.body.statements[0] {
+        match &func.body.statements[0].node {
             Statement::VarDecl { init, .. } => {
-                match init.as_ref().unwrap() {
+                match &init.as_ref().unwrap().node {
                     Expr::String(s) => {
                         assert_eq!(s.parts.len(), 2);
                         match &s.parts[0] {
@@ -1588,7 +1946,7 @@ mod tests {
                         }
                         match &s.parts[1] {
                             StringPart::Interpolation(expr) => {
-                                match expr.as_ref() {
+                                match &expr.node {
                                     Expr::Identifier(id) => assert_eq!(*id, "timestamp"),
                                     _ => panic!("Expected identifier"),
                                 }
@@ -1612,14 +1970,14 @@ mod tests {
             }
         "#;
         let program = parse(input).expect("Should parse");
-        let func = match &program.items[0] {
+        let func = match &program.items[0].node {
             Item::Worker(f) => f,
             _ => panic!("Expected worker"),
         };
 
-        match &func.body.statements[0] {
+        match &func.body.statements[0].node {
             Statement::VarDecl { init, .. } => {
-                match init.as_ref().unwrap() {
+                match &init.as_ref().unwrap().node {
                     Expr::String(s) => {
                         // "Hello ", $first, " ", $last
                         assert_eq!(s.parts.len(), 4);
@@ -1629,7 +1987,7 @@ mod tests {
                         }
                         match &s.parts[1] {
                             StringPart::Interpolation(expr) => {
-                                match expr.as_ref() {
+                                match &expr.node {
                                     Expr::Identifier(id) => assert_eq!(*id, "first"),
                                     _ => panic!("Expected identifier"),
                                 }
@@ -1642,7 +2000,7 @@ mod tests {
                         }
                         match &s.parts[3] {
                             StringPart::Interpolation(expr) => {
-                                match expr.as_ref() {
+                                match &expr.node {
                                     Expr::Identifier(id) => assert_eq!(*id, "last"),
                                     _ => panic!("Expected identifier"),
                                 }
@@ -1666,14 +2024,14 @@ mod tests {
             }
         "#;
         let program = parse(input).expect("Should parse");
-        let func = match &program.items[0] {
+        let func = match &program.items[0].node {
             Item::Worker(f) => f,
             _ => panic!("Expected worker"),
         };
 
-        match &func.body.statements[0] {
+        match &func.body.statements[0].node {
             Statement::VarDecl { init, .. } => {
-                match init.as_ref().unwrap() {
+                match &init.as_ref().unwrap().node {
                     Expr::String(s) => {
                         // $base, "/", ${work_dir}, "/state-", $(timestamp), ".json"
                         assert_eq!(s.parts.len(), 6);
@@ -1681,7 +2039,7 @@ mod tests {
                         // $base
                         match &s.parts[0] {
                             StringPart::Interpolation(expr) => {
-                                match expr.as_ref() {
+                                match &expr.node {
                                     Expr::Identifier(id) => assert_eq!(*id, "base"),
                                     _ => panic!("Expected identifier"),
                                 }
@@ -1698,7 +2056,7 @@ mod tests {
                         // ${work_dir}
                         match &s.parts[2] {
                             StringPart::Interpolation(expr) => {
-                                match expr.as_ref() {
+                                match &expr.node {
                                     Expr::Identifier(id) => assert_eq!(*id, "work_dir"),
                                     _ => panic!("Expected identifier"),
                                 }
@@ -1715,7 +2073,7 @@ mod tests {
                         // $(timestamp)
                         match &s.parts[4] {
                             StringPart::Interpolation(expr) => {
-                                match expr.as_ref() {
+                                match &expr.node {
                                     Expr::Identifier(id) => assert_eq!(*id, "timestamp"),
                                     _ => panic!("Expected identifier"),
                                 }
@@ -1762,18 +2120,18 @@ mod tests {
         let program = parse(input).expect("Should parse empty array");
         assert_eq!(program.items.len(), 1);
 
-        let func = match &program.items[0] {
+        let func = match &program.items[0].node {
             Item::Worker(f) => f,
             _ => panic!("Expected worker"),
         };
 
-        match &func.body.statements[0] {
+        match &func.body.statements[0].node {
             Statement::VarDecl { pattern, init } => {
-                match pattern {
+                match &pattern.node {
                     Pattern::Identifier { name, .. } => assert_eq!(*name, "arr"),
                     _ => panic!("Expected identifier pattern"),
                 }
-                match init.as_ref().unwrap() {
+                match &init.as_ref().unwrap().node {
                     Expr::Array(elements) => assert_eq!(elements.len(), 0),
                     _ => panic!("Expected array literal"),
                 }
@@ -1791,17 +2149,17 @@ mod tests {
         "#;
         let program = parse(input).expect("Should parse array with elements");
 
-        let func = match &program.items[0] {
+        let func = match &program.items[0].node {
             Item::Worker(f) => f,
             _ => panic!("Expected worker"),
         };
 
-        match &func.body.statements[0] {
+        match &func.body.statements[0].node {
             Statement::VarDecl { pattern: _, init } => {
-                match init.as_ref().unwrap() {
+                match &init.as_ref().unwrap().node {
                     Expr::Array(elements) => {
                         assert_eq!(elements.len(), 3);
-                        match &elements[0] {
+                        match &elements[0].node {
                             Expr::Number(n) => assert_eq!(*n, "1"),
                             _ => panic!("Expected number"),
                         }
@@ -1822,20 +2180,20 @@ mod tests {
         "#;
         let program = parse(input).expect("Should parse array with objects");
 
-        let func = match &program.items[0] {
+        let func = match &program.items[0].node {
             Item::Worker(f) => f,
             _ => panic!("Expected worker"),
         };
 
-        match &func.body.statements[0] {
+        match &func.body.statements[0].node {
             Statement::VarDecl { pattern: _, init } => {
-                match init.as_ref().unwrap() {
+                match &init.as_ref().unwrap().node {
                     Expr::Array(elements) => {
                         assert_eq!(elements.len(), 2);
-                        match &elements[0] {
+                        match &elements[0].node {
                             Expr::Object(fields) => {
                                 assert_eq!(fields.len(), 1);
-                                assert_eq!(fields[0].key, "num");
+                                assert_eq!(fields[0].key.node, "num");
                             }
                             _ => panic!("Expected object"),
                         }
@@ -1856,14 +2214,14 @@ mod tests {
         "#;
         let program = parse(input).expect("Should parse empty object");
 
-        let func = match &program.items[0] {
+        let func = match &program.items[0].node {
             Item::Worker(f) => f,
             _ => panic!("Expected worker"),
         };
 
-        match &func.body.statements[0] {
+        match &func.body.statements[0].node {
             Statement::VarDecl { pattern: _, init } => {
-                match init.as_ref().unwrap() {
+                match &init.as_ref().unwrap().node {
                     Expr::Object(fields) => assert_eq!(fields.len(), 0),
                     _ => panic!("Expected object literal"),
                 }
@@ -1881,19 +2239,19 @@ mod tests {
         "#;
         let program = parse(input).expect("Should parse object with fields");
 
-        let func = match &program.items[0] {
+        let func = match &program.items[0].node {
             Item::Worker(f) => f,
             _ => panic!("Expected worker"),
         };
 
-        match &func.body.statements[0] {
+        match &func.body.statements[0].node {
             Statement::VarDecl { pattern: _, init } => {
-                match init.as_ref().unwrap() {
+                match &init.as_ref().unwrap().node {
                     Expr::Object(fields) => {
                         assert_eq!(fields.len(), 2);
-                        assert_eq!(fields[0].key, "x");
+                        assert_eq!(fields[0].key.node, "x");
                         assert!(fields[0].value.is_some());
-                        assert_eq!(fields[1].key, "y");
+                        assert_eq!(fields[1].key.node, "y");
                         assert!(fields[1].value.is_some());
                     }
                     _ => panic!("Expected object literal"),
@@ -1912,19 +2270,19 @@ mod tests {
         "#;
         let program = parse(input).expect("Should parse object with shorthand");
 
-        let func = match &program.items[0] {
+        let func = match &program.items[0].node {
             Item::Worker(f) => f,
             _ => panic!("Expected worker"),
         };
 
-        match &func.body.statements[0] {
+        match &func.body.statements[0].node {
             Statement::VarDecl { pattern: _, init } => {
-                match init.as_ref().unwrap() {
+                match &init.as_ref().unwrap().node {
                     Expr::Object(fields) => {
                         assert_eq!(fields.len(), 2);
-                        assert_eq!(fields[0].key, "session_id");
+                        assert_eq!(fields[0].key.node, "session_id");
                         assert!(fields[0].value.is_none(), "Shorthand should have no value");
-                        assert_eq!(fields[1].key, "timestamp");
+                        assert_eq!(fields[1].key.node, "timestamp");
                         assert!(fields[1].value.is_none(), "Shorthand should have no value");
                     }
                     _ => panic!("Expected object literal"),
@@ -1943,19 +2301,19 @@ mod tests {
         "#;
         let program = parse(input).expect("Should parse object with mixed syntax");
 
-        let func = match &program.items[0] {
+        let func = match &program.items[0].node {
             Item::Worker(f) => f,
             _ => panic!("Expected worker"),
         };
 
-        match &func.body.statements[0] {
+        match &func.body.statements[0].node {
             Statement::VarDecl { pattern: _, init } => {
-                match init.as_ref().unwrap() {
+                match &init.as_ref().unwrap().node {
                     Expr::Object(fields) => {
                         assert_eq!(fields.len(), 2);
-                        assert_eq!(fields[0].key, "x");
+                        assert_eq!(fields[0].key.node, "x");
                         assert!(fields[0].value.is_some());
-                        assert_eq!(fields[1].key, "y");
+                        assert_eq!(fields[1].key.node, "y");
                         assert!(fields[1].value.is_none());
                     }
                     _ => panic!("Expected object literal"),
@@ -1974,14 +2332,14 @@ mod tests {
         "#;
         let program = parse(input).expect("Should parse simple destructuring");
 
-        let func = match &program.items[0] {
+        let func = match &program.items[0].node {
             Item::Worker(f) => f,
             _ => panic!("Expected worker"),
         };
 
-        match &func.body.statements[0] {
+        match &func.body.statements[0].node {
             Statement::VarDecl { pattern, init: _ } => {
-                match pattern {
+                match &pattern.node {
                     Pattern::Object(fields) => {
                         assert_eq!(fields.len(), 2);
                         assert_eq!(fields[0].key, "x");
@@ -2005,14 +2363,14 @@ mod tests {
         "#;
         let program = parse(input).expect("Should parse destructuring with types");
 
-        let func = match &program.items[0] {
+        let func = match &program.items[0].node {
             Item::Worker(f) => f,
             _ => panic!("Expected worker"),
         };
 
-        match &func.body.statements[0] {
+        match &func.body.statements[0].node {
             Statement::VarDecl { pattern, init: _ } => {
-                match pattern {
+                match &pattern.node {
                     Pattern::Object(fields) => {
                         assert_eq!(fields.len(), 2);
                         assert_eq!(fields[0].key, "x");
@@ -2036,18 +2394,18 @@ mod tests {
         "#;
         let program = parse(input).expect("Should parse await");
 
-        let skill = match &program.items[0] {
+        let skill = match &program.items[0].node {
             Item::Skill(s) => s,
             _ => panic!("Expected skill"),
         };
 
-        match &skill.body.statements[0] {
+        match &skill.body.statements[0].node {
             Statement::Expr(expr) => {
-                match expr {
+                match &expr.node {
                     Expr::Await(inner) => {
-                        match inner.as_ref() {
+                        match &inner.node {
                             Expr::Call { callee, args } => {
-                                match callee.as_ref() {
+                                match &callee.node {
                                     Expr::Identifier(id) => {
                                         assert_eq!(*id, "foo");
                                         assert_eq!(args.len(), 0);
@@ -2075,18 +2433,18 @@ mod tests {
         "#;
         let program = parse(input).expect("Should parse await with multiple calls");
 
-        let skill = match &program.items[0] {
+        let skill = match &program.items[0].node {
             Item::Skill(s) => s,
             _ => panic!("Expected skill"),
         };
 
-        match &skill.body.statements[0] {
+        match &skill.body.statements[0].node {
             Statement::Expr(expr) => {
-                match expr {
+                match &expr.node {
                     Expr::Await(inner) => {
-                        match inner.as_ref() {
+                        match &inner.node {
                             Expr::Call { callee, args } => {
-                                match callee.as_ref() {
+                                match &callee.node {
                                     Expr::Identifier(id) => assert_eq!(*id, "coordinator"),
                                     _ => panic!("Expected identifier"),
                                 }
@@ -2113,25 +2471,25 @@ mod tests {
         "#;
         let program = parse(input).expect("Should parse complex nested structure");
 
-        let func = match &program.items[0] {
+        let func = match &program.items[0].node {
             Item::Worker(f) => f,
             _ => panic!("Expected worker"),
         };
 
-        match &func.body.statements[0] {
+        match &func.body.statements[0].node {
             Statement::VarDecl { pattern, init } => {
-                match pattern {
+                match &pattern.node {
                     Pattern::Identifier { name, .. } => assert_eq!(*name, "plan"),
                     _ => panic!("Expected identifier pattern"),
                 }
-                match init.as_ref().unwrap() {
+                match &init.as_ref().unwrap().node {
                     Expr::Object(fields) => {
                         assert_eq!(fields.len(), 2);
                         // First field: commits: [...]
-                        assert_eq!(fields[0].key, "commits");
+                        assert_eq!(fields[0].key.node, "commits");
                         assert!(fields[0].value.is_some());
                         // Second field: session_id (shorthand)
-                        assert_eq!(fields[1].key, "session_id");
+                        assert_eq!(fields[1].key.node, "session_id");
                         assert!(fields[1].value.is_none());
                     }
                     _ => panic!("Expected object"),
@@ -2153,18 +2511,18 @@ mod tests {
         "#;
         let program = parse(input).expect("Should parse simple type annotation");
 
-        let func = match &program.items[0] {
+        let func = match &program.items[0].node {
             Item::Worker(f) => f,
             _ => panic!("Expected worker"),
         };
 
-        match &func.body.statements[0] {
+        match &func.body.statements[0].node {
             Statement::VarDecl { pattern, .. } => {
-                match pattern {
+                match &pattern.node {
                     Pattern::Identifier { name, type_ann } => {
                         assert_eq!(*name, "x");
                         assert!(type_ann.is_some());
-                        match type_ann.as_ref().unwrap() {
+                        match &type_ann.as_ref().unwrap().node {
                             TypeExpr::Name(n) => assert_eq!(*n, "string"),
                             _ => panic!("Expected Name type"),
                         }
@@ -2186,19 +2544,19 @@ mod tests {
         "#;
         let program = parse(input).expect("Should parse array type");
 
-        let func = match &program.items[0] {
+        let func = match &program.items[0].node {
             Item::Worker(f) => f,
             _ => panic!("Expected worker"),
         };
 
-        match &func.body.statements[0] {
+        match &func.body.statements[0].node {
             Statement::VarDecl { pattern, .. } => {
-                match pattern {
+                match &pattern.node {
                     Pattern::Identifier { name, type_ann } => {
                         assert_eq!(*name, "items");
-                        match type_ann.as_ref().unwrap() {
+                        match &type_ann.as_ref().unwrap().node {
                             TypeExpr::Array(elem_type) => {
-                                match elem_type.as_ref() {
+                                match &elem_type.node {
                                     TypeExpr::Name(n) => assert_eq!(*n, "string"),
                                     _ => panic!("Expected Name type for array element"),
                                 }
@@ -2223,24 +2581,24 @@ mod tests {
         "#;
         let program = parse(input).expect("Should parse union type");
 
-        let func = match &program.items[0] {
+        let func = match &program.items[0].node {
             Item::Worker(f) => f,
             _ => panic!("Expected worker"),
         };
 
-        match &func.body.statements[0] {
+        match &func.body.statements[0].node {
             Statement::VarDecl { pattern, .. } => {
-                match pattern {
+                match &pattern.node {
                     Pattern::Identifier { name, type_ann } => {
                         assert_eq!(*name, "status");
-                        match type_ann.as_ref().unwrap() {
+                        match &type_ann.as_ref().unwrap().node {
                             TypeExpr::Union(types) => {
                                 assert_eq!(types.len(), 2);
-                                match &types[0] {
+                                match &types[0].node {
                                     TypeExpr::Literal(s) => assert_eq!(*s, "success"),
                                     _ => panic!("Expected Literal type"),
                                 }
-                                match &types[1] {
+                                match &types[1].node {
                                     TypeExpr::Literal(s) => assert_eq!(*s, "error"),
                                     _ => panic!("Expected Literal type"),
                                 }
@@ -2265,26 +2623,26 @@ mod tests {
         "#;
         let program = parse(input).expect("Should parse object type");
 
-        let func = match &program.items[0] {
+        let func = match &program.items[0].node {
             Item::Worker(f) => f,
             _ => panic!("Expected worker"),
         };
 
-        match &func.body.statements[0] {
+        match &func.body.statements[0].node {
             Statement::VarDecl { pattern, .. } => {
-                match pattern {
+                match &pattern.node {
                     Pattern::Identifier { name, type_ann } => {
                         assert_eq!(*name, "msg");
-                        match type_ann.as_ref().unwrap() {
+                        match &type_ann.as_ref().unwrap().node {
                             TypeExpr::Object(fields) => {
                                 assert_eq!(fields.len(), 2);
                                 assert_eq!(fields[0].key, "x");
-                                match &fields[0].type_expr {
+                                match &fields[0].type_expr.node {
                                     TypeExpr::Name(n) => assert_eq!(*n, "string"),
                                     _ => panic!("Expected Name type"),
                                 }
                                 assert_eq!(fields[1].key, "y");
-                                match &fields[1].type_expr {
+                                match &fields[1].type_expr.node {
                                     TypeExpr::Name(n) => assert_eq!(*n, "int"),
                                     _ => panic!("Expected Name type"),
                                 }
@@ -2309,26 +2667,26 @@ mod tests {
         "#;
         let program = parse(input).expect("Should parse destructuring with types");
 
-        let func = match &program.items[0] {
+        let func = match &program.items[0].node {
             Item::Worker(f) => f,
             _ => panic!("Expected worker"),
         };
 
-        match &func.body.statements[0] {
+        match &func.body.statements[0].node {
             Statement::VarDecl { pattern, .. } => {
-                match pattern {
+                match &pattern.node {
                     Pattern::Object(fields) => {
                         assert_eq!(fields.len(), 2);
                         // First field: x: string
                         assert_eq!(fields[0].key, "x");
                         assert!(fields[0].type_ann.is_some());
-                        match fields[0].type_ann.as_ref().unwrap() {
+                        match &fields[0].type_ann.as_ref().unwrap().node {
                             TypeExpr::Name(n) => assert_eq!(*n, "string"),
                             _ => panic!("Expected Name type"),
                         }
                         // Second field: y: int
                         assert_eq!(fields[1].key, "y");
-                        match fields[1].type_ann.as_ref().unwrap() {
+                        match &fields[1].type_ann.as_ref().unwrap().node {
                             TypeExpr::Name(n) => assert_eq!(*n, "int"),
                             _ => panic!("Expected Name type"),
                         }
@@ -2346,10 +2704,10 @@ mod tests {
         let input = "type username = string";
         let program = parse(input).expect("Should parse simple type declaration");
 
-        match &program.items[0] {
+        match &program.items[0].node {
             Item::Type(type_decl) => {
                 assert_eq!(type_decl.name, "username");
-                match &type_decl.type_expr {
+                match &type_decl.type_expr.node {
                     TypeExpr::Name(n) => assert_eq!(*n, "string"),
                     _ => panic!("Expected Name type"),
                 }
@@ -2364,17 +2722,17 @@ mod tests {
         let input = r#"type status = "success" | "error""#;
         let program = parse(input).expect("Should parse union type declaration");
 
-        match &program.items[0] {
+        match &program.items[0].node {
             Item::Type(type_decl) => {
                 assert_eq!(type_decl.name, "status");
-                match &type_decl.type_expr {
+                match &type_decl.type_expr.node {
                     TypeExpr::Union(types) => {
                         assert_eq!(types.len(), 2);
-                        match &types[0] {
+                        match &types[0].node {
                             TypeExpr::Literal(s) => assert_eq!(*s, "success"),
                             _ => panic!("Expected Literal type"),
                         }
-                        match &types[1] {
+                        match &types[1].node {
                             TypeExpr::Literal(s) => assert_eq!(*s, "error"),
                             _ => panic!("Expected Literal type"),
                         }
@@ -2386,6 +2744,129 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_generic_type_annotations() {
+        let input = r#"
+            fun summarize(commits: list<commit>, by_author: map<string, list<string>>, note: option<string>) {
+                return commits
+            }
+        "#;
+        let program = parse(input).expect("Should parse generic types");
+
+        let func = match &program.items[0].node {
+            Item::Function(f) => f,
+            _ => panic!("Expected function"),
+        };
+
+        match &func.params[0].type_ann.as_ref().unwrap().node {
+            TypeExpr::Generic { name, args } => {
+                assert_eq!(*name, "list");
+                assert_eq!(args.len(), 1);
+                assert!(matches!(&args[0].node, TypeExpr::Name(v) if v == "commit"));
+            }
+            other => panic!("Expected generic type, got {:?}", other),
+        }
+        match &func.params[1].type_ann.as_ref().unwrap().node {
+            TypeExpr::Generic { name, args } => {
+                assert_eq!(*name, "map");
+                assert_eq!(args.len(), 2);
+                assert!(matches!(&args[1].node, TypeExpr::Generic { name, .. } if name == "list"));
+            }
+            other => panic!("Expected generic type, got {:?}", other),
+        }
+        assert!(matches!(func.params[2].type_ann.as_deref(), Some(TypeExpr::Generic { name, .. }) if name == "option"));
+    }
+
+    #[test]
+    fn test_type_declaration_tagged_union() {
+        let input = r#"type result = Success {hash: string} | Failure {reason: string}"#;
+        let program = parse(input).expect("Should parse tagged union declaration");
+
+        match &program.items[0].node {
+            Item::Type(type_decl) => match &type_decl.type_expr.node {
+                TypeExpr::Union(types) => {
+                    assert_eq!(types.len(), 2);
+                    match &types[0].node {
+                        TypeExpr::Variant { tag, fields } => {
+                            assert_eq!(*tag, "Success");
+                            assert_eq!(fields[0].key, "hash");
+                        }
+                        _ => panic!("Expected Variant type"),
+                    }
+                    match &types[1].node {
+                        TypeExpr::Variant { tag, .. } => assert_eq!(*tag, "Failure"),
+                        _ => panic!("Expected Variant type"),
+                    }
+                }
+                _ => panic!("Expected Union type"),
+            },
+            _ => panic!("Expected type declaration"),
+        }
+    }
+
+    #[test]
+    fn test_variant_construction() {
+        let input = r#"
+            worker test(h) {
+                var r = Success{hash: h}
+                if ready {
+                    r
+                }
+            }
+        "#;
+        let program = parse(input).expect("Should parse variant construction");
+
+        let func = match &program.items[0].node {
+            Item::Worker(f) => f,
+            _ => panic!("Expected worker"),
+        };
+
+        match &func.body.statements[0].node {
+            Statement::VarDecl { init: Some(init), .. } => {
+                let Expr::Variant { tag, fields } = &init.node else { panic!("Expected variant, got {:?}", init) };
+                assert_eq!(*tag, "Success");
+                assert_eq!(fields.len(), 1);
+                assert_eq!(fields[0].key.node, "hash");
+            }
+            other => panic!("Expected variant construction, got {:?}", other),
+        }
+        // A space before `{` still means condition + block
+        assert!(matches!(func.body.statements[1].node, Statement::If { .. }));
+    }
+
+    #[test]
+    fn test_header_brace_is_not_a_tag() {
+        let input = r#"
+            worker test(items, ready) {
+                if ready{
+                    Done{}
+                }
+                for var x in items{
+                    x
+                }
+            }
+
+            trait Foo{
+                fun bar() {}
+            }
+
+            trait Baz: Foo{
+                fun qux() {}
+            }
+        "#;
+        let program = parse(input).expect("A header's `{` should open its block");
+
+        let Item::Worker(worker) = &program.items[0].node else { panic!("Expected worker") };
+        let Statement::If { then_block, .. } = &worker.body.statements[0].node else {
+            panic!("Expected if, got {:?}", worker.body.statements[0])
+        };
+        // Tags still work inside the block
+        assert!(matches!(&then_block.statements[0].node, Statement::Expr(e) if matches!(e.node, Expr::Variant { .. })));
+        assert!(matches!(worker.body.statements[1].node, Statement::ForIn { .. }));
+        assert!(matches!(&program.items[1].node, Item::Trait(decl) if decl.name == "Foo"));
+        assert!(matches!(&program.items[2].node, Item::Trait(decl) if decl.name == "Baz"));
+    }
+
     #[test]
     fn test_type_declaration_object() {
         // Test type declaration with object type
@@ -2397,15 +2878,15 @@ mod tests {
         "#;
         let program = parse(input).expect("Should parse object type declaration");
 
-        match &program.items[0] {
+        match &program.items[0].node {
             Item::Type(type_decl) => {
                 assert_eq!(type_decl.name, "scribe_result");
-                match &type_decl.type_expr {
+                match &type_decl.type_expr.node {
                     TypeExpr::Object(fields) => {
                         assert_eq!(fields.len(), 2);
                         // First field: status: "success" | "error"
                         assert_eq!(fields[0].key, "status");
-                        match &fields[0].type_expr {
+                        match &fields[0].type_expr.node {
                             TypeExpr::Union(types) => {
                                 assert_eq!(types.len(), 2);
                             }
@@ -2413,7 +2894,7 @@ mod tests {
                         }
                         // Second field: commit_hash: string
                         assert_eq!(fields[1].key, "commit_hash");
-                        match &fields[1].type_expr {
+                        match &fields[1].type_expr.node {
                             TypeExpr::Name(n) => assert_eq!(*n, "string"),
                             _ => panic!("Expected Name type"),
                         }
@@ -2435,21 +2916,21 @@ mod tests {
         "#;
         let program = parse(input).expect("Should parse nested array type");
 
-        let func = match &program.items[0] {
+        let func = match &program.items[0].node {
             Item::Worker(f) => f,
             _ => panic!("Expected worker"),
         };
 
-        match &func.body.statements[0] {
+        match &func.body.statements[0].node {
             Statement::VarDecl { pattern, .. } => {
-                match pattern {
+                match &pattern.node {
                     Pattern::Identifier { name, type_ann } => {
                         assert_eq!(*name, "matrix");
-                        match type_ann.as_ref().unwrap() {
+                        match &type_ann.as_ref().unwrap().node {
                             TypeExpr::Array(outer) => {
-                                match outer.as_ref() {
+                                match &outer.node {
                                     TypeExpr::Array(inner) => {
-                                        match inner.as_ref() {
+                                        match &inner.node {
                                             TypeExpr::Name(n) => assert_eq!(*n, "string"),
                                             _ => panic!("Expected Name type"),
                                         }
@@ -2477,28 +2958,28 @@ mod tests {
         "#;
         let program = parse(input).expect("Should parse complex union type");
 
-        let func = match &program.items[0] {
+        let func = match &program.items[0].node {
             Item::Worker(f) => f,
             _ => panic!("Expected worker"),
         };
 
-        match &func.body.statements[0] {
+        match &func.body.statements[0].node {
             Statement::VarDecl { pattern, .. } => {
-                match pattern {
+                match &pattern.node {
                     Pattern::Identifier { name, type_ann } => {
                         assert_eq!(*name, "value");
-                        match type_ann.as_ref().unwrap() {
+                        match &type_ann.as_ref().unwrap().node {
                             TypeExpr::Union(types) => {
                                 assert_eq!(types.len(), 3);
-                                match &types[0] {
+                                match &types[0].node {
                                     TypeExpr::Name(n) => assert_eq!(*n, "string"),
                                     _ => panic!("Expected Name type"),
                                 }
-                                match &types[1] {
+                                match &types[1].node {
                                     TypeExpr::Name(n) => assert_eq!(*n, "int"),
                                     _ => panic!("Expected Name type"),
                                 }
-                                match &types[2] {
+                                match &types[2].node {
                                     TypeExpr::Literal(s) => assert_eq!(*s, "none"),
                                     _ => panic!("Expected Literal type"),
                                 }
@@ -2523,19 +3004,19 @@ mod tests {
         "#;
         let program = parse(input).expect("Should parse array of object type");
 
-        let func = match &program.items[0] {
+        let func = match &program.items[0].node {
             Item::Worker(f) => f,
             _ => panic!("Expected worker"),
         };
 
-        match &func.body.statements[0] {
+        match &func.body.statements[0].node {
             Statement::VarDecl { pattern, .. } => {
-                match pattern {
+                match &pattern.node {
                     Pattern::Identifier { name, type_ann } => {
                         assert_eq!(*name, "records");
-                        match type_ann.as_ref().unwrap() {
+                        match &type_ann.as_ref().unwrap().node {
                             TypeExpr::Array(elem_type) => {
-                                match elem_type.as_ref() {
+                                match &elem_type.node {
                                     TypeExpr::Object(fields) => {
                                         assert_eq!(fields.len(), 2);
                                         assert_eq!(fields[0].key, "name");
@@ -2567,10 +3048,10 @@ mod tests {
         assert_eq!(program.items.len(), 3);
 
         // First: type username = string
-        match &program.items[0] {
+        match &program.items[0].node {
             Item::Type(type_decl) => {
                 assert_eq!(type_decl.name, "username");
-                match &type_decl.type_expr {
+                match &type_decl.type_expr.node {
                     TypeExpr::Name(n) => assert_eq!(*n, "string"),
                     _ => panic!("Expected Name type"),
                 }
@@ -2579,10 +3060,10 @@ mod tests {
         }
 
         // Second: type status = "active" | "inactive"
-        match &program.items[1] {
+        match &program.items[1].node {
             Item::Type(type_decl) => {
                 assert_eq!(type_decl.name, "status");
-                match &type_decl.type_expr {
+                match &type_decl.type_expr.node {
                     TypeExpr::Union(_) => {},
                     _ => panic!("Expected Union type"),
                 }
@@ -2591,20 +3072,20 @@ mod tests {
         }
 
         // Third: type user = {name: username, status: status}
-        match &program.items[2] {
+        match &program.items[2].node {
             Item::Type(type_decl) => {
                 assert_eq!(type_decl.name, "user");
-                match &type_decl.type_expr {
+                match &type_decl.type_expr.node {
                     TypeExpr::Object(fields) => {
                         assert_eq!(fields.len(), 2);
                         assert_eq!(fields[0].key, "name");
                         assert_eq!(fields[1].key, "status");
                         // Note: username and status here are Name types (referencing other type declarations)
-                        match &fields[0].type_expr {
+                        match &fields[0].type_expr.node {
                             TypeExpr::Name(n) => assert_eq!(*n, "username"),
                             _ => panic!("Expected Name type"),
                         }
-                        match &fields[1].type_expr {
+                        match &fields[1].type_expr.node {
                             TypeExpr::Name(n) => assert_eq!(*n, "status"),
                             _ => panic!("Expected Name type"),
                         }
@@ -2624,12 +3105,12 @@ mod tests {
         let program = parse(input).unwrap();
         assert_eq!(program.items.len(), 1);
 
-        match &program.items[0] {
+        match &program.items[0].node {
             Item::Worker(func) => {
                 assert_eq!(func.body.statements.len(), 1);
-                match &func.body.statements[0] {
+                match &func.body.statements[0].node {
                     Statement::VarDecl { pattern, init } => {
-                        match pattern {
+                        match &pattern.node {
                             Pattern::Identifier { name, .. } => assert_eq!(*name, "x"),
                             _ => panic!("Expected Identifier pattern"),
                         }
@@ -2661,7 +3142,7 @@ worker test() {
         let program = parse(input).unwrap();
         assert_eq!(program.items.len(), 1);
 
-        match &program.items[0] {
+        match &program.items[0].node {
             Item::Worker(func) => {
                 assert_eq!(func.body.statements.len(), 2);
             }
@@ -2679,7 +3160,7 @@ worker foo(session_id, work_dir) {}
         let program = parse(input).unwrap();
         assert_eq!(program.items.len(), 1);
 
-        match &program.items[0] {
+        match &program.items[0].node {
             Item::Worker(task) => {
                 assert_eq!(task.name, "foo");
                 assert_eq!(task.params.len(), 2);
@@ -2697,7 +3178,7 @@ skill analyst() {}
         let program = parse(input).unwrap();
         assert_eq!(program.items.len(), 1);
 
-        match &program.items[0] {
+        match &program.items[0].node {
             Item::Skill(skill) => {
                 assert_eq!(skill.name, "analyst");
             }
@@ -2705,6 +3186,33 @@ skill analyst() {}
         }
     }
 
+    #[test]
+    fn test_annotations_on_declarations() {
+        let input = "@color purple\n@deprecated\nexport skill analyst() {}\n\n@deprecated Id\ntype Key = string\n\ntrait Historian {\n    @skill narrate\n    fun narrate() {}\n}";
+        let program = parse(input).unwrap();
+        assert_eq!(program.items.len(), 3);
+
+        let Item::Skill(skill) = &program.items[0].node else {
+            panic!("Expected skill declaration");
+        };
+        assert!(skill.is_exported);
+        let annotations: Vec<_> = skill.annotations.iter().map(|a| (&*a.node.name, a.node.arg.as_deref())).collect();
+        assert_eq!(annotations, vec![("color", Some("purple")), ("deprecated", None)]);
+        assert_eq!(skill.annotations[0].span, Span::new(0, 13));
+        // The item's span covers its annotations
+        assert_eq!(program.items[0].span.start, 0);
+
+        let Item::Type(decl) = &program.items[1].node else {
+            panic!("Expected type declaration");
+        };
+        assert_eq!(decl.annotations[0].node.arg.as_deref(), Some("Id"));
+        let Item::Trait(decl) = &program.items[2].node else {
+            panic!("Expected trait declaration");
+        };
+        assert!(decl.annotations.is_empty());
+        assert_eq!(decl.methods[0].annotations[0].node.name, "skill");
+    }
+
     #[test]
     fn test_multiple_comments_and_code() {
         let input = r#"
@@ -2724,13 +3232,13 @@ skill bar(x) {
         assert_eq!(program.items.len(), 2);
 
         // First item is import
-        match &program.items[0] {
+        match &program.items[0].node {
             Item::Import(_) => {},
             _ => panic!("Expected import"),
         }
 
         // Second item is skill
-        match &program.items[1] {
+        match &program.items[1].node {
             Item::Skill(skill) => {
                 assert_eq!(skill.name, "bar");
                 assert_eq!(skill.body.statements.len(), 2); // var and return
@@ -2766,9 +3274,9 @@ worker test() {
         let program = parse(input).unwrap();
         assert_eq!(program.items.len(), 1);
 
-        match &program.items[0] {
+        match &program.items[0].node {
             Item::Worker(func) => {
-                match &func.body.statements[0] {
+                match &func.body.statements[0].node {
                     Statement::If { then_block, else_block, .. } => {
                         assert_eq!(then_block.statements.len(), 1);
                         assert!(else_block.is_some());
@@ -2843,7 +3351,7 @@ skill rewriting_git_branch(changeset_description) {
         let program = parse(input).unwrap();
         assert_eq!(program.items.len(), 2); // import + skill
 
-        match &program.items[1] {
+        match &program.items[1].node {
             Item::Skill(skill) => {
                 assert_eq!(skill.name, "rewriting_git_branch");
                 assert_eq!(skill.params.len(), 1);
@@ -2880,14 +3388,14 @@ skill rewriting_git_branch(changeset_description) {
         assert_eq!(program.items.len(), 2); // import + skill
 
         // Verify import parsed correctly
-        match &program.items[0] {
+        match &program.items[0].node {
             Item::Import(import) => {
                 match &import.path {
                     ImportPath::RelativeMulti(names) => {
                         assert_eq!(names.len(), 3);
-                        assert!(names.contains(&"analyst"));
-                        assert!(names.contains(&"narrator"));
-                        assert!(names.contains(&"scribe"));
+                        assert!(names.contains(&"analyst".into()));
+                        assert!(names.contains(&"narrator".into()));
+                        assert!(names.contains(&"scribe".into()));
                     }
                     _ => panic!("Expected RelativeMulti import"),
                 }
@@ -2896,7 +3404,7 @@ skill rewriting_git_branch(changeset_description) {
         }
 
         // Verify skill parsed correctly with comments
-        match &program.items[1] {
+        match &program.items[1].node {
             Item::Skill(skill) => {
                 assert_eq!(skill.name, "rewriting_git_branch");
                 assert_eq!(skill.params.len(), 1);
@@ -2968,7 +3476,7 @@ skill rewriting_git_branch(changeset_description) {
         assert_eq!(program.items.len(), 2, "Expected import + skill");
 
         // First item: import ./{analyst, narrator, scribe}
-        match &program.items[0] {
+        match &program.items[0].node {
             Item::Import(decl) => {
                 match &decl.path {
                     ImportPath::RelativeMulti(names) => {
@@ -2984,7 +3492,7 @@ skill rewriting_git_branch(changeset_description) {
         }
 
         // Second item: skill rewriting_git_branch(changeset_description)
-        match &program.items[1] {
+        match &program.items[1].node {
             Item::Skill(skill) => {
                 assert_eq!(skill.name, "rewriting_git_branch");
                 assert_eq!(skill.params.len(), 1);
@@ -2996,9 +3504,9 @@ skill rewriting_git_branch(changeset_description) {
 
                 // First three should be var declarations with command substitution
                 for i in 0..3 {
-                    match &skill.body.statements[i] {
+                    match &skill.body.statements[i].node {
                         Statement::VarDecl { pattern, init } => {
-                            match pattern {
+                            match &pattern.node {
                                 Pattern::Identifier { name, .. } => {
                                     match i {
                                         0 => assert_eq!(*name, "timestamp"),
@@ -3028,17 +3536,17 @@ skill rewriting_git_branch(changeset_description) {
         // Should have 2 items: import and task
         assert_eq!(program.items.len(), 2);
 
-        match &program.items[1] {
+        match &program.items[1].node {
             Item::Worker(task) => {
                 assert_eq!(task.name, "analyst");
 
                 // Find a var decl that has think || ask pattern
                 let mut found_think_ask = false;
                 for stmt in &task.body.statements {
-                    if let Statement::VarDecl { init: Some(expr), .. } = stmt {
+                    if let Statement::VarDecl { init: Some(expr), .. } = &stmt.node {
                         // Check if it's a Binary OR with Think on left
-                        if let Expr::Binary { op: BinOp::Or, left, right } = expr {
-                            if matches!(&**left, Expr::Think(_)) && matches!(&**right, Expr::Ask(_)) {
+                        if let Expr::Binary { op: BinOp::Or, left, right } = &expr.node {
+                            if matches!(&left.node, Expr::Think(_)) && matches!(&right.node, Expr::Ask(_)) {
                                 found_think_ask = true;
                                 break;
                             }
@@ -3057,11 +3565,11 @@ skill rewriting_git_branch(changeset_description) {
         let input = "worker test() { var x = $(date +%s) }";
         let program = parse(input).expect("Should parse");
 
-        match &program.items[0] {
+        match &program.items[0].node {
             Item::Worker(task) => {
-                match &task.body.statements[0] {
+                match &task.body.statements[0].node {
                     Statement::VarDecl { pattern, init } => {
-                        match pattern {
+                        match &pattern.node {
                             Pattern::Identifier { name, .. } => {
                                 assert_eq!(*name, "x");
                             }
@@ -3069,10 +3577,10 @@ skill rewriting_git_branch(changeset_description) {
                         }
 
                         // Init should be CommandSubst wrapping a BareCommand
-                        match init.as_ref().unwrap() {
+                        match &init.as_ref().unwrap().node {
                             Expr::CommandSubst(inner) => {
                                 // Inner should be BareCommand
-                                match inner.as_ref() {
+                                match &inner.node {
                                     Expr::BareCommand { name, args } => {
                                         assert_eq!(*name, "date");
                                         assert_eq!(args.len(), 1);
@@ -3102,10 +3610,10 @@ skill rewriting_git_branch(changeset_description) {
         let input = "worker test() {\n    $ mkdir -p work_dir\n}";
         let program = parse(input).expect("Should parse");
 
-        match &program.items[0] {
+        match &program.items[0].node {
             Item::Worker(task) => {
-                match &task.body.statements[0] {
-                    Statement::Expr(Expr::BareCommand { name, args }) => {
+                match expr_stmt(&task.body.statements[0]) {
+                    Expr::BareCommand { name, args } => {
                         assert_eq!(*name, "mkdir");
                         assert_eq!(args.len(), 2);
 
@@ -3132,11 +3640,11 @@ skill rewriting_git_branch(changeset_description) {
         let input = r#"worker test() { var x = "session-${timestamp}" }"#;
         let program = parse(input).expect("Should parse");
 
-        match &program.items[0] {
+        match &program.items[0].node {
             Item::Worker(task) => {
-                match &task.body.statements[0] {
+                match &task.body.statements[0].node {
                     Statement::VarDecl { init: Some(expr), .. } => {
-                        match expr {
+                        match &expr.node {
                             Expr::String(lit) => {
                                 // Should have 2 parts: text + interpolation
                                 assert_eq!(lit.parts.len(), 2);
@@ -3150,7 +3658,7 @@ skill rewriting_git_branch(changeset_description) {
 
                                 match &lit.parts[1] {
                                     StringPart::Interpolation(expr) => {
-                                        match &**expr {
+                                        match &expr.node {
                                             Expr::Identifier(name) => {
                                                 assert_eq!(*name, "timestamp");
                                             }
@@ -3234,21 +3742,21 @@ skill rewriting_git_branch(changeset_description) {
         let program = parse(input).expect("Should parse shell operators");
 
         // Navigate to the init expression
-        match &program.items[0] {
+        match &program.items[0].node {
             Item::Worker(task) => {
-                match &task.body.statements[0] {
+                match &task.body.statements[0].node {
                     Statement::VarDecl { init, .. } => {
-                        match init.as_ref().unwrap() {
+                        match &init.as_ref().unwrap().node {
                             Expr::CommandSubst(inner) => {
                                 // Should be ShellOr at top level
-                                match inner.as_ref() {
+                                match &inner.node {
                                     Expr::ShellOr { left, right } => {
                                         // Left should be ShellRedirect
-                                        match left.as_ref() {
+                                        match &left.node {
                                             Expr::ShellRedirect { command, op, target } => {
                                                 assert_eq!(*op, RedirectOp::ErrOut);
                                                 // Command should be BareCommand
-                                                match command.as_ref() {
+                                                match &command.node {
                                                     Expr::BareCommand { name, args } => {
                                                         assert_eq!(*name, "git");
                                                         assert_eq!(args.len(), 3);
@@ -3256,7 +3764,7 @@ skill rewriting_git_branch(changeset_description) {
                                                     _ => panic!("Expected BareCommand in redirect"),
                                                 }
                                                 // Target should be Identifier
-                                                match target.as_ref() {
+                                                match &target.node {
                                                     Expr::Identifier(id) => {
                                                         assert_eq!(*id, "/dev/null");
                                                     }
@@ -3266,7 +3774,7 @@ skill rewriting_git_branch(changeset_description) {
                                             _ => panic!("Expected ShellRedirect on left"),
                                         }
                                         // Right should be BareCommand
-                                        match right.as_ref() {
+                                        match &right.node {
                                             Expr::BareCommand { name, args } => {
                                                 assert_eq!(*name, "git");
                                                 assert_eq!(args.len(), 3);
@@ -3297,12 +3805,12 @@ skill rewriting_git_branch(changeset_description) {
         "#;
         let program = parse(input).expect("Should parse pipe operator");
 
-        match &program.items[0] {
+        match &program.items[0].node {
             Item::Worker(task) => {
-                match &task.body.statements[0] {
-                    Statement::Expr(Expr::ShellPipe { left, right }) => {
+                match expr_stmt(&task.body.statements[0]) {
+                    Expr::ShellPipe { left, right } => {
                         // Left should be "cat file.txt"
-                        match left.as_ref() {
+                        match &left.node {
                             Expr::BareCommand { name, args } => {
                                 assert_eq!(*name, "cat");
                                 assert_eq!(args.len(), 1);
@@ -3310,7 +3818,7 @@ skill rewriting_git_branch(changeset_description) {
                             _ => panic!("Expected BareCommand on left of pipe"),
                         }
                         // Right should be "grep pattern"
-                        match right.as_ref() {
+                        match &right.node {
                             Expr::BareCommand { name, args } => {
                                 assert_eq!(*name, "grep");
                                 assert_eq!(args.len(), 1);
@@ -3335,18 +3843,18 @@ skill rewriting_git_branch(changeset_description) {
         "#;
         let program = parse(input).expect("Should parse redirect");
 
-        match &program.items[0] {
+        match &program.items[0].node {
             Item::Worker(task) => {
-                match &task.body.statements[0] {
-                    Statement::Expr(Expr::ShellRedirect { command, op, target }) => {
+                match expr_stmt(&task.body.statements[0]) {
+                    Expr::ShellRedirect { command, op, target } => {
                         assert_eq!(*op, RedirectOp::Out);
-                        match command.as_ref() {
+                        match &command.node {
                             Expr::BareCommand { name, .. } => {
                                 assert_eq!(*name, "echo");
                             }
                             _ => panic!("Expected BareCommand"),
                         }
-                        match target.as_ref() {
+                        match &target.node {
                             Expr::Identifier(id) => {
                                 assert_eq!(*id, "output.txt");
                             }
@@ -3390,6 +3898,138 @@ worker test() {
         let result = parse(input);
         assert!(result.is_ok(), "Failed to parse backtick in prompt: {:?}", result);
     }
+
+    #[test]
+    fn test_edition_reserves_keywords() {
+        let input = "fun f(match) {\n    var try = match\n}";
+        assert!(parse(input).is_ok());
+
+        match parse_with_edition(input, Edition::E2025) {
+            Err(ParseError::UnexpectedToken { message, span, .. }) => {
+                assert_eq!(message, "`match` is a reserved keyword in edition 2025");
+                assert_eq!(span, Some((6, 11)));
+            }
+            other => panic!("Expected reserved keyword error, got {:?}", other),
+        }
+        assert!(parse_with_edition("fun f(matches) {}", Edition::E2025).is_ok());
+    }
+
+    #[test]
+    fn test_try_statement() {
+        let input = "fun f() {\n    try {\n        risky()\n    } catch (e) {\n        log(e)\n    } finally {\n        done()\n    }\n}";
+        let program = parse_with_edition(input, Edition::E2025).unwrap();
+        let Item::Function(func) = &program.items[0].node else {
+            panic!("Expected function");
+        };
+        match &func.body.statements[0].node {
+            Statement::Try { body, catch: Some(catch), finally_block: Some(finally_block) } => {
+                assert_eq!(body.statements.len(), 1);
+                assert_eq!(catch.var.as_deref(), Some("e"));
+                assert_eq!(catch.body.statements.len(), 1);
+                assert_eq!(finally_block.statements.len(), 1);
+            }
+            other => panic!("Expected Try statement, got {:?}", other),
+        }
+
+        assert!(parse_with_edition("fun f() {\n    try {\n        risky()\n    } catch {\n    }\n}", Edition::E2025).is_ok());
+        assert!(parse_with_edition("fun f() {\n    try {\n        risky()\n    }\n}", Edition::E2025).is_err());
+        // Earlier editions keep the words as identifiers
+        assert!(parse("fun f() {\n    var catch = 1\n}").is_ok());
+    }
+
+    #[test]
+    fn test_match_expression() {
+        let input = "fun f(r) {\n    var x = match r {\n        \"ok\" => { 1 }\n        n: number => { n }, _ => { 0 }\n    }\n}";
+        let program = parse_with_edition(input, Edition::E2025).unwrap();
+        let Item::Function(func) = &program.items[0].node else {
+            panic!("Expected function");
+        };
+        let Statement::VarDecl { init: Some(init), .. } = &func.body.statements[0].node else {
+            panic!("Expected var declaration");
+        };
+        match &init.node {
+            Expr::Match { subject, arms } => {
+                assert_eq!(subject.node, Expr::Identifier("r".into()));
+                let patterns: Vec<_> = arms.iter().map(|arm| &arm.pattern.node).collect();
+                assert!(matches!(patterns[0], MatchPattern::Literal(_)));
+                assert!(matches!(&patterns[1], MatchPattern::Type { name: Some(name), .. } if name == "n"));
+                assert_eq!(patterns[2], &MatchPattern::Wildcard);
+            }
+            other => panic!("Expected Match expression, got {:?}", other),
+        }
+
+        assert!(parse_with_edition("fun f(r) {\n    match r {\n        \"$r\" => { 1 }\n    }\n}", Edition::E2025).is_err());
+        assert!(parse("fun f() {\n    var match = 1\n}").is_ok());
+    }
+
+    #[test]
+    fn test_lambda_expression() {
+        let input = "fun f(items) {\n    map(items, fun(x: number, i) { x * i })\n}";
+        let program = parse(input).unwrap();
+        let Item::Function(func) = &program.items[0].node else {
+            panic!("Expected function");
+        };
+        let Expr::Call { args, .. } = expr_stmt(&func.body.statements[0]) else {
+            panic!("Expected call");
+        };
+        match &args[1].node {
+            Expr::Lambda { params, body } => {
+                assert_eq!(params.iter().map(|p| &*p.name).collect::<Vec<_>>(), vec!["x", "i"]);
+                assert!(params[0].type_ann.is_some());
+                assert_eq!(body.statements.len(), 1);
+            }
+            other => panic!("Expected Lambda, got {:?}", other),
+        }
+        assert!(parse("fun f() {\n    var g = fun() {}\n}").is_ok());
+    }
+
+    #[test]
+    fn test_deep_nesting_is_rejected() {
+        let prefix = "fun f() {\n    var x = ";
+        let nested =
+            |depth: usize| format!("{}{}1{}\n}}", prefix, "(".repeat(depth), ")".repeat(depth));
+
+        assert!(parse(&nested(100)).is_ok());
+
+        let input = nested(100_000);
+        match parse(&input) {
+            Err(ParseError::UnexpectedToken { message, span: Some((start, _)), .. }) => {
+                assert!(message.contains("Nesting too deep"), "{}", message);
+                // The function body's `{` is the first level
+                assert_eq!(start, prefix.len() + MAX_NESTING_DEPTH - 1);
+            }
+            other => panic!("Expected nesting error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_nodes_carry_source_spans() {
+        let input = "fun main(n: int) {\n    var [a, b] = pair(n + 1)\n    $ echo $a\n}";
+        let program = parse(input).expect("Should parse");
+        let text = |span: Span| &input[span.start..span.end];
+
+        assert_eq!(text(program.items[0].span), input);
+        let Item::Function(decl) = &program.items[0].node else { panic!("Expected Function item") };
+        assert_eq!(text(decl.params[0].type_ann.as_ref().unwrap().span), "int");
+
+        let var = &decl.body.statements[0];
+        assert_eq!(text(var.span), "var [a, b] = pair(n + 1)");
+        assert_eq!(var.span.line_col(input), (2, 5));
+        let Statement::VarDecl { pattern, init: Some(init) } = &var.node else { panic!("Expected VarDecl") };
+        assert_eq!(text(pattern.span), "[a, b]");
+        let Pattern::Array(names) = &pattern.node else { panic!("Expected array pattern") };
+        assert_eq!(text(names[1].span), "b");
+        let Expr::Call { callee, args } = &init.node else { panic!("Expected call") };
+        assert_eq!(text(callee.span), "pair");
+        assert_eq!(text(args[0].span), "n + 1");
+
+        let shell = &decl.body.statements[1];
+        assert_eq!(text(shell.span), "$ echo $a");
+        let Expr::BareCommand { args, .. } = expr_stmt(shell) else { panic!("Expected command") };
+        let CommandArg::String(arg) = &args[0] else { panic!("Expected interpolated argument") };
+        let StringPart::Interpolation(var) = &arg.parts[0] else { panic!("Expected interpolation") };
+        assert_eq!(text(var.span), "$a");
+    }
 }
 
 #[cfg(test)]
@@ -3422,7 +4062,7 @@ mod debug_tests {
         let program = result.unwrap();
         assert_eq!(program.items.len(), 1);
 
-        match &program.items[0] {
+        match &program.items[0].node {
             Item::Worker(decl) => {
                 assert_eq!(decl.name, "analyst");
                 assert_eq!(decl.params.len(), 2);
@@ -3441,7 +4081,7 @@ mod debug_tests {
         let program = result.unwrap();
         assert_eq!(program.items.len(), 1);
 
-        match &program.items[0] {
+        match &program.items[0].node {
             Item::Skill(decl) => {
                 assert_eq!(decl.name, "rewriting_git_branch");
                 assert_eq!(decl.params.len(), 1);
@@ -3460,7 +4100,7 @@ mod debug_tests {
         let program = result.unwrap();
         assert_eq!(program.items.len(), 1);
 
-        match &program.items[0] {
+        match &program.items[0].node {
             Item::Function(decl) => {
                 assert_eq!(decl.name, "validate_trees");
                 assert_eq!(decl.params.len(), 2);
@@ -3470,6 +4110,21 @@ mod debug_tests {
         }
     }
 
+    #[test]
+    fn test_memo_function() {
+        let input = "export memo fun fib(n) {}\nfun plain() {}";
+        let program = parse(input).expect("Failed to parse memo function");
+
+        match (&program.items[0].node, &program.items[1].node) {
+            (Item::Function(memo), Item::Function(plain)) => {
+                assert_eq!(memo.name, "fib");
+                assert!(memo.is_memo && memo.is_exported);
+                assert!(!plain.is_memo);
+            }
+            other => panic!("Expected two Function items, got {:?}", other),
+        }
+    }
+
     #[test]
     fn test_non_exported_declarations() {
         let input = r#"
@@ -3483,21 +4138,21 @@ mod debug_tests {
         let program = result.unwrap();
         assert_eq!(program.items.len(), 3);
 
-        match &program.items[0] {
+        match &program.items[0].node {
             Item::Worker(decl) => {
                 assert!(!decl.is_exported, "Worker should not be exported");
             }
             _ => panic!("Expected Worker item"),
         }
 
-        match &program.items[1] {
+        match &program.items[1].node {
             Item::Function(decl) => {
                 assert!(!decl.is_exported, "Function should not be exported");
             }
             _ => panic!("Expected Function item"),
         }
 
-        match &program.items[2] {
+        match &program.items[2].node {
             Item::Skill(decl) => {
                 assert!(!decl.is_exported, "Skill should not be exported");
             }
@@ -3518,7 +4173,7 @@ mod debug_tests {
         let program = result.unwrap();
         assert_eq!(program.items.len(), 3);
 
-        match &program.items[0] {
+        match &program.items[0].node {
             Item::Worker(decl) => {
                 assert_eq!(decl.name, "main");
                 assert!(decl.is_exported, "First task should be exported");
@@ -3526,7 +4181,7 @@ mod debug_tests {
             _ => panic!("Expected Worker item"),
         }
 
-        match &program.items[1] {
+        match &program.items[1].node {
             Item::Function(decl) => {
                 assert_eq!(decl.name, "helper");
                 assert!(!decl.is_exported, "Helper function should not be exported");
@@ -3534,7 +4189,7 @@ mod debug_tests {
             _ => panic!("Expected Function item"),
         }
 
-        match &program.items[2] {
+        match &program.items[2].node {
             Item::Function(decl) => {
                 assert_eq!(decl.name, "utility");
                 assert!(decl.is_exported, "Utility function should be exported");
@@ -3553,25 +4208,25 @@ mod debug_tests {
         "#;
         let program = parse(input).expect("Should parse simple array pattern");
 
-        let func = match &program.items[0] {
+        let func = match &program.items[0].node {
             Item::Worker(f) => f,
             _ => panic!("Expected worker"),
         };
 
-        match &func.body.statements[0] {
+        match &func.body.statements[0].node {
             Statement::VarDecl { pattern, init: _ } => {
-                match pattern {
+                match &pattern.node {
                     Pattern::Array(patterns) => {
                         assert_eq!(patterns.len(), 3);
-                        match &patterns[0] {
+                        match &patterns[0].node {
                             Pattern::Identifier { name, .. } => assert_eq!(*name, "x"),
                             _ => panic!("Expected identifier pattern"),
                         }
-                        match &patterns[1] {
+                        match &patterns[1].node {
                             Pattern::Identifier { name, .. } => assert_eq!(*name, "y"),
                             _ => panic!("Expected identifier pattern"),
                         }
-                        match &patterns[2] {
+                        match &patterns[2].node {
                             Pattern::Identifier { name, .. } => assert_eq!(*name, "z"),
                             _ => panic!("Expected identifier pattern"),
                         }
@@ -3592,22 +4247,22 @@ mod debug_tests {
         "#;
         let program = parse(input).expect("Should parse array pattern with ignore");
 
-        let func = match &program.items[0] {
+        let func = match &program.items[0].node {
             Item::Worker(f) => f,
             _ => panic!("Expected worker"),
         };
 
-        match &func.body.statements[0] {
+        match &func.body.statements[0].node {
             Statement::VarDecl { pattern, init: _ } => {
-                match pattern {
+                match &pattern.node {
                     Pattern::Array(patterns) => {
                         assert_eq!(patterns.len(), 3);
-                        assert!(matches!(patterns[0], Pattern::Ignore));
-                        match &patterns[1] {
+                        assert!(matches!(patterns[0].node, Pattern::Ignore));
+                        match &patterns[1].node {
                             Pattern::Identifier { name, .. } => assert_eq!(*name, "result"),
                             _ => panic!("Expected identifier pattern"),
                         }
-                        assert!(matches!(patterns[2], Pattern::Ignore));
+                        assert!(matches!(patterns[2].node, Pattern::Ignore));
                     }
                     _ => panic!("Expected array pattern"),
                 }
@@ -3616,6 +4271,40 @@ mod debug_tests {
         }
     }
 
+    #[test]
+    fn test_array_pattern_nested() {
+        let input = r#"
+            worker test() {
+                var [first, [_, inner], {name}] = rows()
+            }
+        "#;
+        let program = parse(input).expect("Should parse nested array pattern");
+
+        let func = match &program.items[0].node {
+            Item::Worker(f) => f,
+            _ => panic!("Expected worker"),
+        };
+
+        let patterns = match &func.body.statements[0].node {
+            Statement::VarDecl { pattern, .. } => match &pattern.node {
+                Pattern::Array(patterns) => patterns,
+                _ => panic!("Expected array pattern"),
+            },
+            _ => panic!("Expected var decl"),
+        };
+        assert_eq!(patterns.len(), 3);
+        assert!(matches!(&patterns[0].node, Pattern::Identifier { name, .. } if name == "first"));
+        match &patterns[1].node {
+            Pattern::Array(inner) => {
+                assert_eq!(inner.len(), 2);
+                assert!(matches!(inner[0].node, Pattern::Ignore));
+                assert!(matches!(&inner[1].node, Pattern::Identifier { name, .. } if name == "inner"));
+            }
+            _ => panic!("Expected nested array pattern"),
+        }
+        assert!(matches!(patterns[2].node, Pattern::Object(_)));
+    }
+
     #[test]
     fn test_ignore_pattern_standalone() {
         let input = r#"
@@ -3625,14 +4314,14 @@ mod debug_tests {
         "#;
         let program = parse(input).expect("Should parse standalone ignore pattern");
 
-        let func = match &program.items[0] {
+        let func = match &program.items[0].node {
             Item::Worker(f) => f,
             _ => panic!("Expected worker"),
         };
 
-        match &func.body.statements[0] {
+        match &func.body.statements[0].node {
             Statement::VarDecl { pattern, init: _ } => {
-                assert!(matches!(pattern, Pattern::Ignore));
+                assert!(matches!(&pattern.node, Pattern::Ignore));
             }
             _ => panic!("Expected var decl"),
         }
@@ -3649,7 +4338,7 @@ mod debug_tests {
         "#;
         let program = parse(input).expect("Should parse trait with super-trait");
 
-        let trait_decl = match &program.items[0] {
+        let trait_decl = match &program.items[0].node {
             Item::Trait(t) => t,
             _ => panic!("Expected trait"),
         };
@@ -3659,7 +4348,7 @@ mod debug_tests {
         assert!(trait_decl.is_default);
         assert!(trait_decl.super_trait.is_some());
 
-        match &trait_decl.super_trait {
+        match trait_decl.super_trait.as_deref() {
             Some(TypeExpr::Name(name)) => {
                 assert_eq!(*name, "Agent");
             }
@@ -3681,7 +4370,7 @@ mod debug_tests {
         "#;
         let program = parse(input).expect("Should parse trait without super-trait");
 
-        let trait_decl = match &program.items[0] {
+        let trait_decl = match &program.items[0].node {
             Item::Trait(t) => t,
             _ => panic!("Expected trait"),
         };
@@ -3704,7 +4393,7 @@ mod debug_tests {
         "#;
         let program = parse(input).expect("Should parse trait with array super-trait");
 
-        let trait_decl = match &program.items[0] {
+        let trait_decl = match &program.items[0].node {
             Item::Trait(t) => t,
             _ => panic!("Expected trait"),
         };
@@ -3713,9 +4402,9 @@ mod debug_tests {
         assert!(trait_decl.super_trait.is_some());
 
         // Verify it's an array type
-        match &trait_decl.super_trait {
+        match trait_decl.super_trait.as_deref() {
             Some(TypeExpr::Array(inner)) => {
-                match &**inner {
+                match &inner.node {
                     TypeExpr::Name(name) => assert_eq!(*name, "Agent"),
                     _ => panic!("Expected array of Agent"),
                 }
@@ -3723,3 +4412,15 @@ mod debug_tests {
             _ => panic!("Expected super-trait to be TypeExpr::Array"),
         }
     }
+
+    #[test]
+    fn test_tokenize_reports_keyword_spans() {
+        let input = "skill main() {\n    var x = think { hi }\n}";
+        let tokens = tokenize(input).expect("Should tokenize");
+
+        let (start, _, end) = tokens
+            .iter()
+            .find(|(_, tok, _)| matches!(tok, ParserToken::Think))
+            .expect("Should find think token");
+        assert_eq!(&input[*start..*end], "think");
+    }
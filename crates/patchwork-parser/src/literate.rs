@@ -0,0 +1,183 @@
+//! Literate programs: Markdown files (`.pw.md`) whose fenced `patchwork`
+//! code blocks run in order.
+//!
+//! Each block is a cell. A cell that starts with a declaration (`skill`,
+//! `fun`, `type`, ...) is a program of its own; any other cell is a list of
+//! statements, run as the body of a `__main__` skill the way the interpreter
+//! runs a bare `{ ... }` block. Errors are reported at byte offsets in the
+//! Markdown file, so they point at the right line of the document.
+
+use crate::check::check;
+use crate::{parse_with_edition, tokenize, Edition, ParseError, ParserToken};
+
+/// Prefix wrapping a statement cell into a program.
+const MAIN_PREFIX: &str = "skill __main__() {\n";
+const MAIN_SUFFIX: &str = "\n}";
+
+/// A fenced `patchwork` code block in a Markdown document.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Cell<'a> {
+    /// The code between the fences
+    pub code: &'a str,
+    /// Byte offset of the code in the document
+    pub offset: usize,
+}
+
+impl Cell<'_> {
+    /// Whether the cell declares items rather than running statements.
+    pub fn is_declarations(&self) -> bool {
+        let Ok(tokens) = tokenize(self.code) else {
+            return false;
+        };
+        let mut tokens = tokens
+            .into_iter()
+            .map(|(_, token, _)| token)
+            .filter(|token| !matches!(token, ParserToken::Whitespace(_) | ParserToken::Newline(_)));
+        match tokens.next() {
+            Some(
+                ParserToken::Skill
+                | ParserToken::Worker
+                | ParserToken::Trait
+                | ParserToken::Fun
+                | ParserToken::Memo
+                | ParserToken::Import
+                | ParserToken::Export
+                | ParserToken::Type
+                | ParserToken::Tag("config"),
+            ) => true,
+            // `config {`, as opposed to a statement reading `config.x`
+            Some(ParserToken::Identifier("config")) => tokens.next() == Some(ParserToken::LBrace),
+            _ => false,
+        }
+    }
+
+    /// The program to run for this cell.
+    pub fn program_text(&self) -> String {
+        if self.is_declarations() {
+            self.code.to_string()
+        } else {
+            format!("{}{}{}", MAIN_PREFIX, self.code, MAIN_SUFFIX)
+        }
+    }
+
+    /// The line of the cell's code at `line` of its [`program_text`](Self::program_text).
+    pub fn code_line(&self, line: usize) -> usize {
+        if self.is_declarations() {
+            line
+        } else {
+            // The wrapper takes up the first line
+            line.saturating_sub(1).max(1)
+        }
+    }
+
+    /// Parse and check the cell, reporting errors at document offsets.
+    pub fn check(&self, edition: Edition) -> Vec<ParseError> {
+        let text = self.program_text();
+        let prefix = if self.is_declarations() { 0 } else { MAIN_PREFIX.len() };
+        let errors = match parse_with_edition(&text, edition) {
+            Ok(program) => check(&program),
+            Err(error) => vec![error],
+        };
+        // Offsets in the wrapper map to the nearest end of the cell
+        let code_len = self.code.len();
+        let map = |at: usize| self.offset + at.saturating_sub(prefix).min(code_len);
+        errors.into_iter().map(|error| error.map_offsets(map)).collect()
+    }
+}
+
+/// The `patchwork` (or `pw`) code blocks of a Markdown document, in order.
+///
+/// Blocks are fenced with three or more backticks or tildes, optionally
+/// indented up to three spaces. Other code blocks are prose, so they're
+/// skipped, as is an unclosed block at the end of the document.
+pub fn cells(markdown: &str) -> Vec<Cell<'_>> {
+    let mut cells = Vec::new();
+    // The open fence and where its code starts
+    let mut open: Option<(&str, usize)> = None;
+    let mut in_other_block = None;
+
+    let mut line_start = 0;
+    for line in markdown.split_inclusive('\n') {
+        let next = line_start + line.len();
+        let trimmed = line.trim_end_matches(['\n', '\r']);
+        match (open, in_other_block) {
+            (Some((fence, code_start)), _) => {
+                if is_closing(trimmed, fence) {
+                    let code = markdown[code_start..line_start].strip_suffix('\n').unwrap_or("");
+                    cells.push(Cell { code, offset: code_start });
+                    open = None;
+                }
+            }
+            (None, Some(fence)) => {
+                if is_closing(trimmed, fence) {
+                    in_other_block = None;
+                }
+            }
+            (None, None) => {
+                if let Some((fence, info)) = opening(trimmed) {
+                    let language = info.split_whitespace().next().unwrap_or("");
+                    if language == "patchwork" || language == "pw" {
+                        open = Some((fence, next));
+                    } else {
+                        in_other_block = Some(fence);
+                    }
+                }
+            }
+        }
+        line_start = next;
+    }
+    cells
+}
+
+/// The fence and info string of an opening fence line.
+fn opening(line: &str) -> Option<(&str, &str)> {
+    let indent = line.len() - line.trim_start_matches(' ').len();
+    if indent > 3 {
+        return None;
+    }
+    let rest = &line[indent..];
+    let marker = rest.chars().next().filter(|c| *c == '`' || *c == '~')?;
+    let len = rest.len() - rest.trim_start_matches(marker).len();
+    if len < 3 {
+        return None;
+    }
+    Some((&rest[..len], rest[len..].trim()))
+}
+
+fn is_closing(line: &str, fence: &str) -> bool {
+    let rest = line.trim_start_matches(' ');
+    if line.len() - rest.len() > 3 {
+        return false;
+    }
+    let marker = fence.chars().next().unwrap_or('`');
+    let len = rest.len() - rest.trim_start_matches(marker).len();
+    len >= fence.len() && rest[len..].trim().is_empty()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cells_and_offsets() {
+        let markdown = "# Release notes\n\n```patchwork\nfun greet(name) {\n    return \"hi \" + name\n}\n```\n\n```sh\n```patchwork\n```\n\n~~~pw\nvar x = greet(\"a\")\nx = )\n~~~\n";
+        let found = cells(markdown);
+        assert_eq!(found.len(), 2);
+        assert_eq!(found[0].code, "fun greet(name) {\n    return \"hi \" + name\n}");
+        assert!(found[0].is_declarations());
+        assert_eq!(&markdown[found[1].offset..], "var x = greet(\"a\")\nx = )\n~~~\n");
+        assert!(!found[1].is_declarations());
+
+        assert!(found[0].check(Edition::default()).is_empty());
+        let errors = found[1].check(Edition::default());
+        let error_at = match &errors[..] {
+            [error] => error.span().unwrap().0,
+            other => panic!("Expected one parse error, got {:?}", other),
+        };
+        assert_eq!(&markdown[error_at..error_at + 1], ")");
+
+        // Lines of the wrapped program map back to lines of the cell
+        assert_eq!(found[0].code_line(2), 2);
+        assert_eq!(found[1].code_line(3), 2);
+    }
+}
@@ -0,0 +1,283 @@
+//! Source rewrites for moving a program to a newer edition.
+//!
+//! The program is parsed under its current edition, and every identifier
+//! the target edition reserves is renamed, both where it is bound and
+//! where it is used. Field names are data rather than bindings, so those
+//! are reported for a human to decide on instead of being rewritten.
+
+use std::collections::HashSet;
+
+use crate::ast::*;
+use crate::{parse_with_edition, tokenize, Edition, ParseError, ParserToken};
+
+/// The result of migrating one source.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Migration {
+    /// The rewritten source
+    pub source: String,
+    /// Each rename as `(byte span in the original, old name, new name)`
+    pub renames: Vec<((usize, usize), String, String)>,
+    /// Problems left for a human, with the byte offset they refer to
+    pub warnings: Vec<(usize, String)>,
+}
+
+/// Rewrite `source`, written for `from`, so it parses under `to`.
+pub fn migrate(source: &str, from: Edition, to: Edition) -> Result<Migration, ParseError> {
+    let program = parse_with_edition(source, from)?;
+    let mut field_names = HashSet::new();
+    for item in &program.items {
+        collect_item(item, &mut field_names);
+    }
+
+    let tokens = tokenize(source)?;
+    let identifiers: Vec<(usize, &str, usize)> = tokens
+        .iter()
+        .filter_map(|(start, token, end)| match token {
+            ParserToken::Identifier(name) | ParserToken::Tag(name) => Some((*start, *name, *end)),
+            _ => None,
+        })
+        .collect();
+    let taken: HashSet<&str> = identifiers.iter().map(|(_, name, _)| *name).collect();
+
+    let mut migration = Migration { source: String::new(), renames: Vec::new(), warnings: Vec::new() };
+    let mut copied = 0;
+    for (start, name, end) in identifiers {
+        if !to.reserves(name) || from.reserves(name) {
+            continue;
+        }
+        if field_names.contains(&start) {
+            migration.warnings.push((
+                start,
+                format!("`{}` is a field name and is reserved in edition {}; rename it by hand", name, to),
+            ));
+            continue;
+        }
+        let mut renamed = format!("{}_", name);
+        while taken.contains(renamed.as_str()) {
+            renamed.push('_');
+        }
+        migration.source.push_str(&source[copied..start]);
+        migration.source.push_str(&renamed);
+        copied = end;
+        migration.renames.push(((start, end), name.to_string(), renamed));
+    }
+    migration.source.push_str(&source[copied..]);
+    Ok(migration)
+}
+
+/// Record the offsets of names that key into data: object keys, member
+/// fields, and destructured keys.
+fn collect_item(item: &Item, out: &mut HashSet<usize>) {
+    match item {
+        Item::Skill(decl) => collect_block(&decl.body, out),
+        Item::Worker(decl) => collect_block(&decl.body, out),
+        Item::Function(decl) => collect_block(&decl.body, out),
+        Item::Trait(decl) => {
+            for method in &decl.methods {
+                collect_block(&method.body, out);
+            }
+        }
+        Item::Config(decl) => {
+            for field in &decl.fields {
+                out.insert(field.key.span.start);
+                if let Some(value) = &field.value {
+                    collect_expr(value, out);
+                }
+            }
+        }
+        Item::Example(decl) => collect_prompt_items(&decl.items, out),
+        Item::Fragment(decl) => collect_prompt_items(&decl.items, out),
+        Item::Import(_) | Item::Type(_) => {}
+    }
+}
+
+fn collect_block(block: &Block, out: &mut HashSet<usize>) {
+    for stmt in &block.statements {
+        collect_statement(stmt, out);
+    }
+}
+
+fn collect_statement(stmt: &Statement, out: &mut HashSet<usize>) {
+    match stmt {
+        Statement::VarDecl { pattern, init } => {
+            collect_pattern(pattern, out);
+            if let Some(init) = init {
+                collect_expr(init, out);
+            }
+        }
+        Statement::ConstDecl { pattern, init } => {
+            collect_pattern(pattern, out);
+            collect_expr(init, out);
+        }
+        Statement::Expr(expr) | Statement::Return(Some(expr)) => collect_expr(expr, out),
+        Statement::If { condition, then_block, else_block } => {
+            collect_expr(condition, out);
+            collect_block(then_block, out);
+            if let Some(else_block) = else_block {
+                collect_block(else_block, out);
+            }
+        }
+        Statement::ForIn { pattern, iter, body } => {
+            collect_pattern(pattern, out);
+            collect_expr(iter, out);
+            collect_block(body, out);
+        }
+        Statement::While { condition, body } => {
+            collect_expr(condition, out);
+            collect_block(body, out);
+        }
+        Statement::WhileVar { pattern, init, body } => {
+            collect_pattern(pattern, out);
+            collect_expr(init, out);
+            collect_block(body, out);
+        }
+        Statement::Timeout { limit, body } => {
+            collect_expr(limit, out);
+            collect_statement(body, out);
+        }
+        Statement::Try { body, catch, finally_block } => {
+            collect_block(body, out);
+            for block in catch.iter().map(|catch| &catch.body).chain(finally_block) {
+                collect_block(block, out);
+            }
+        }
+        Statement::Return(None) | Statement::Succeed | Statement::Break | Statement::TypeDecl { .. } => {}
+    }
+}
+
+fn collect_pattern(pattern: &Pattern, out: &mut HashSet<usize>) {
+    match pattern {
+        Pattern::Object(fields) => {
+            for field in fields {
+                // The field's pattern is parsed from its key
+                out.insert(field.pattern.span.start);
+                collect_pattern(&field.pattern, out);
+            }
+        }
+        Pattern::Array(items) => {
+            for item in items {
+                collect_pattern(item, out);
+            }
+        }
+        Pattern::Identifier { .. } | Pattern::Ignore => {}
+    }
+}
+
+fn collect_expr(expr: &Spanned<Expr>, out: &mut HashSet<usize>) {
+    match &expr.node {
+        Expr::Object(fields) | Expr::Variant { fields, .. } => {
+            for field in fields {
+                out.insert(field.key.span.start);
+                if let Some(value) = &field.value {
+                    collect_expr(value, out);
+                }
+            }
+        }
+        Expr::Member { object, field } => {
+            // A member expression ends with its field
+            out.insert(expr.span.end - field.len());
+            collect_expr(object, out);
+        }
+        Expr::Array(items) => {
+            for item in items {
+                collect_expr(item, out);
+            }
+        }
+        Expr::Call { callee, args } => {
+            collect_expr(callee, out);
+            for arg in args {
+                collect_expr(arg, out);
+            }
+        }
+        Expr::Binary { left, right, .. }
+        | Expr::Index { object: left, index: right }
+        | Expr::ShellPipe { left, right }
+        | Expr::ShellAnd { left, right }
+        | Expr::ShellOr { left, right }
+        | Expr::ShellRedirect { command: left, target: right, .. } => {
+            collect_expr(left, out);
+            collect_expr(right, out);
+        }
+        Expr::Unary { operand: inner, .. }
+        | Expr::PostIncrement(inner)
+        | Expr::PostDecrement(inner)
+        | Expr::Paren(inner)
+        | Expr::Await(inner)
+        | Expr::AwaitAll(inner)
+        | Expr::CommandSubst(inner) => collect_expr(inner, out),
+        Expr::String(s) => collect_string(s, out),
+        Expr::BareCommand { args, .. } => {
+            for arg in args {
+                if let CommandArg::String(s) = arg {
+                    collect_string(s, out);
+                }
+            }
+        }
+        Expr::Think(prompt) | Expr::Ask(prompt) => {
+            let variants = prompt.variants.iter().map(|variant| &variant.items);
+            for items in std::iter::once(&prompt.items).chain(variants) {
+                collect_prompt_items(items, out);
+            }
+            if let Some(validator) = &prompt.validator {
+                collect_block(&validator.body, out);
+            }
+            if let Some(tools) = &prompt.tools {
+                collect_expr(tools, out);
+            }
+        }
+        Expr::Do(block) | Expr::Lambda { body: block, .. } => collect_block(block, out),
+        Expr::Match { subject, arms } => {
+            collect_expr(subject, out);
+            for arm in arms {
+                collect_block(&arm.body, out);
+            }
+        }
+        Expr::Identifier(_)
+        | Expr::Number(_)
+        | Expr::Duration(_)
+        | Expr::Size(_)
+        | Expr::True
+        | Expr::False => {}
+    }
+}
+
+fn collect_prompt_items(items: &[PromptItem], out: &mut HashSet<usize>) {
+    for item in items {
+        match item {
+            PromptItem::Interpolation(expr) => collect_expr(expr, out),
+            PromptItem::Code(block) => collect_block(block, out),
+            PromptItem::Text(_) => {}
+        }
+    }
+}
+
+fn collect_string(s: &StringLiteral, out: &mut HashSet<usize>) {
+    for part in &s.parts {
+        if let StringPart::Interpolation(expr) = part {
+            collect_expr(expr, out);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_renames_reserved_bindings() {
+        let source = "fun f(match, try_) {\n    var r = { match: match }\n    print(\"$try\", r.match)\n}";
+        let migration = migrate(source, Edition::E2024, Edition::E2025).unwrap();
+
+        // `try` is unbound here, but renaming it still gets the program parsing
+        assert_eq!(
+            migration.source,
+            "fun f(match_, try_) {\n    var r = { match: match_ }\n    print(\"$try__\", r.match)\n}"
+        );
+        assert_eq!(migration.renames.len(), 3);
+        assert_eq!(
+            migration.warnings.iter().map(|(at, _)| *at).collect::<Vec<_>>(),
+            vec![source.find("match:").unwrap(), source.rfind("match").unwrap()]
+        );
+        assert!(migrate(source, Edition::E2025, Edition::E2025).is_err());
+    }
+}
@@ -17,12 +17,17 @@ pub enum ParserToken<'input> {
     Think,
     Ask,
     Do,
+    Variant,
+    /// `example` and `fragment` are keywords from edition 2025 on
+    Example,
+    Fragment,
 
     // Keywords
     Import,
     Export,
     From,
     Var,
+    Const,
     If,
     Else,
     For,
@@ -32,12 +37,18 @@ pub enum ParserToken<'input> {
     Trait,
     Skill,
     Fun,
+    Memo,
     Default,
     Type,
     Return,
     Succeed,
     Throw,
     Break,
+    /// `match`, `try`, `catch` and `finally` are keywords from edition 2025 on
+    Match,
+    Try,
+    Catch,
+    Finally,
     SelfKw,
     In,
     Underscore,
@@ -46,11 +57,16 @@ pub enum ParserToken<'input> {
     True,
     False,
     Number(&'input str),
+    Duration(&'input str),
+    Size(&'input str),
     Identifier(&'input str),
+    /// Identifier immediately followed by `{` (variant construction: `Success{...}`)
+    Tag(&'input str),
 
     // Multi-character operators
     Ellipsis,
     Arrow,
+    FatArrow,
     Eq,
     Neq,
     Lte,
@@ -0,0 +1,151 @@
+//! Token-level diffs between two sources.
+//!
+//! Comparing what the grammar sees, rather than the text, shows where an
+//! edit changes how the lexer splits a file: a missed mode transition, such
+//! as a prompt that swallows its closing brace, turns into a long run of
+//! removed and added tokens after a one-character change.
+
+use crate::{tokenize, ParseError, ParserToken};
+
+/// A token with its byte span in its source.
+pub type SpannedToken<'input> = (usize, ParserToken<'input>, usize);
+
+/// One step of a token diff.
+#[derive(Debug, Clone, PartialEq)]
+pub enum TokenChange<'a> {
+    /// In both sources; carries the old and new spans
+    Same(SpannedToken<'a>, SpannedToken<'a>),
+    Removed(SpannedToken<'a>),
+    Added(SpannedToken<'a>),
+}
+
+/// Tokenize both sources and diff the token streams, ignoring positions.
+pub fn diff_tokens<'a>(old: &'a str, new: &'a str) -> Result<Vec<TokenChange<'a>>, ParseError> {
+    Ok(diff_token_lists(tokenize(old)?, tokenize(new)?))
+}
+
+/// Diff two token lists by longest common subsequence.
+pub fn diff_token_lists<'a>(old: Vec<SpannedToken<'a>>, new: Vec<SpannedToken<'a>>) -> Vec<TokenChange<'a>> {
+    // Edits are usually local, so only the middle needs the quadratic table
+    let prefix = old.iter().zip(&new).take_while(|(a, b)| a.1 == b.1).count();
+    let suffix = old[prefix..]
+        .iter()
+        .rev()
+        .zip(new[prefix..].iter().rev())
+        .take_while(|(a, b)| a.1 == b.1)
+        .count();
+    let old_mid = &old[prefix..old.len() - suffix];
+    let new_mid = &new[prefix..new.len() - suffix];
+
+    // lcs[i][j]: common subsequence length of old_mid[i..] and new_mid[j..]
+    let mut lcs = vec![vec![0usize; new_mid.len() + 1]; old_mid.len() + 1];
+    for i in (0..old_mid.len()).rev() {
+        for j in (0..new_mid.len()).rev() {
+            lcs[i][j] = if old_mid[i].1 == new_mid[j].1 {
+                lcs[i + 1][j + 1] + 1
+            } else {
+                lcs[i + 1][j].max(lcs[i][j + 1])
+            };
+        }
+    }
+
+    let mut changes: Vec<TokenChange> = old[..prefix]
+        .iter()
+        .zip(&new[..prefix])
+        .map(|(a, b)| TokenChange::Same(a.clone(), b.clone()))
+        .collect();
+    let (mut i, mut j) = (0, 0);
+    while i < old_mid.len() || j < new_mid.len() {
+        if i < old_mid.len() && j < new_mid.len() && old_mid[i].1 == new_mid[j].1 {
+            changes.push(TokenChange::Same(old_mid[i].clone(), new_mid[j].clone()));
+            i += 1;
+            j += 1;
+        } else if i < old_mid.len() && (j == new_mid.len() || lcs[i + 1][j] >= lcs[i][j + 1]) {
+            // Removals first, so a replaced token reads `-old` then `+new`
+            changes.push(TokenChange::Removed(old_mid[i].clone()));
+            i += 1;
+        } else {
+            changes.push(TokenChange::Added(new_mid[j].clone()));
+            j += 1;
+        }
+    }
+    changes.extend(
+        old[old.len() - suffix..]
+            .iter()
+            .zip(&new[new.len() - suffix..])
+            .map(|(a, b)| TokenChange::Same(a.clone(), b.clone())),
+    );
+    changes
+}
+
+/// Render changes like a unified diff: `-` and `+` lines with `line:col`
+/// positions, `context` unchanged tokens around each change, and `...`
+/// between hunks. Returns an empty string when nothing changed.
+pub fn render_token_diff(old: &str, new: &str, changes: &[TokenChange], context: usize) -> String {
+    let changed: Vec<usize> = changes
+        .iter()
+        .enumerate()
+        .filter(|(_, change)| !matches!(change, TokenChange::Same(..)))
+        .map(|(at, _)| at)
+        .collect();
+
+    let mut out = String::new();
+    let mut next = 0;
+    for (n, &at) in changed.iter().enumerate() {
+        let start = at.saturating_sub(context).max(next);
+        if n > 0 && start > next {
+            out.push_str("...\n");
+        }
+        // Stop at the next change; its own pass prints what follows
+        let end = changed.get(n + 1).map_or(at + context + 1, |&following| following.min(at + context + 1));
+        let end = end.min(changes.len());
+        for change in &changes[start..end] {
+            let line = match change {
+                TokenChange::Same(_, (start, token, _)) => format!("  {:?} {}", token, position(new, *start)),
+                TokenChange::Removed((start, token, _)) => format!("- {:?} {}", token, position(old, *start)),
+                TokenChange::Added((start, token, _)) => format!("+ {:?} {}", token, position(new, *start)),
+            };
+            out.push_str(&line);
+            out.push('\n');
+        }
+        next = end;
+    }
+    out
+}
+
+/// 1-based `line:col` of a byte offset.
+fn position(source: &str, offset: usize) -> String {
+    let before = &source[..offset];
+    let line = before.matches('\n').count() + 1;
+    let col = before.rsplit('\n').next().map_or(0, |l| l.chars().count()) + 1;
+    format!("{}:{}", line, col)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_diff_reports_changed_tokens() {
+        let old = "fun f() {\n    var x = a + b\n}";
+        let new = "fun f() {\n    var x = a - b\n    print(x)\n}";
+        let changes = diff_tokens(old, new).unwrap();
+
+        assert!(diff_tokens(old, old).unwrap().iter().all(|c| matches!(c, TokenChange::Same(..))));
+        assert_eq!(
+            render_token_diff(old, new, &changes, 1),
+            concat!(
+                "  Identifier(\"a\") 2:13\n",
+                "- Plus 2:15\n",
+                "+ Minus 2:15\n",
+                "  Identifier(\"b\") 2:17\n",
+                "+ Newline(\"\\n\") 2:18\n",
+                "+ Identifier(\"print\") 3:5\n",
+                "+ LParen 3:10\n",
+                "+ Identifier(\"x\") 3:11\n",
+                "+ RParen 3:12\n",
+                "  Newline(\"\\n\") 3:13\n",
+            )
+        );
+    }
+}
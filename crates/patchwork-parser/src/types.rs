@@ -0,0 +1,28 @@
+//! Type names and alias resolution shared by every pass that reads types.
+//!
+//! The static checks, the type checker, and the interpreter each keep their
+//! own table of `type` declarations, but agree here on which names are
+//! builtin and how far aliases are followed. The checks in this crate can't
+//! depend on `patchwork-typecheck`, so the definitions live here, and the
+//! type checker re-exports them for the crates above it.
+
+/// Alias nesting followed before giving up, which guards against cycles.
+pub const MAX_ALIAS_DEPTH: usize = 32;
+
+/// Type names the interpreter defines.
+pub const BUILTIN_TYPES: &[&str] =
+    &["any", "null", "string", "number", "int", "float", "bool", "boolean", "duration", "size", "function"];
+
+/// Follow aliases from `ty`, where `alias_of` gives the type an alias stands
+/// for, or `None` for a type that isn't one. Returns the first type that
+/// isn't an alias, or `Err` with the last type reached once aliases nest
+/// deeper than `MAX_ALIAS_DEPTH`.
+pub fn resolve_aliases<T: Copy>(mut ty: T, alias_of: impl Fn(T) -> Option<T>) -> Result<T, T> {
+    for _ in 0..MAX_ALIAS_DEPTH {
+        match alias_of(ty) {
+            Some(aliased) => ty = aliased,
+            None => return Ok(ty),
+        }
+    }
+    Err(ty)
+}
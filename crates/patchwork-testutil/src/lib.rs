@@ -0,0 +1,215 @@
+//! Fixtures for testing Patchwork programs that work with git.
+//!
+//! [`GitRepo`] creates a repository in a temporary directory, scripts a
+//! history into it commit by commit, and reads back the branches and
+//! commits a program left behind. The directory is removed when the repo
+//! is dropped. Every helper panics when git fails, with git's own error in
+//! the message, since these are meant to be called from tests.
+//!
+//! ```no_run
+//! use patchwork_testutil::GitRepo;
+//!
+//! let repo = GitRepo::new();
+//! repo.commit("Add readme", &[("README.md", "hello\n")]);
+//! repo.branch("feature");
+//! repo.commit("Add parser", &[("src/parser.rs", "")]);
+//!
+//! // ... run the skill under test with `repo.path()` as its working directory ...
+//!
+//! assert_eq!(repo.subjects("feature"), ["Add readme", "Add parser"]);
+//! ```
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+
+use tempfile::TempDir;
+
+/// Author of fixture commits, also configured in the repo so programs
+/// under test can commit without a global git identity.
+const USER_NAME: &str = "Patchwork Tests";
+const USER_EMAIL: &str = "tests@patchwork.invalid";
+
+/// Every fixture commit is dated here, so the same script always produces
+/// the same hashes.
+const COMMIT_DATE: &str = "2024-01-01T00:00:00Z";
+
+/// One commit, as read back by [`GitRepo::log`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct Commit {
+    pub hash: String,
+    /// First line of the message
+    pub subject: String,
+    /// Rest of the message, without the blank line after the subject
+    pub body: String,
+    /// Paths the commit added, changed, or removed, sorted
+    pub files: Vec<String>,
+}
+
+/// A git repository in a temporary directory.
+pub struct GitRepo {
+    dir: TempDir,
+}
+
+impl GitRepo {
+    /// An empty repository whose default branch is `main`.
+    pub fn new() -> Self {
+        let dir = tempfile::tempdir().expect("failed to create a temporary directory");
+        let repo = GitRepo { dir };
+        repo.git(&["init", "-q", "-b", "main"]);
+        repo.git(&["config", "user.name", USER_NAME]);
+        repo.git(&["config", "user.email", USER_EMAIL]);
+        repo.git(&["config", "commit.gpgsign", "false"]);
+        repo
+    }
+
+    /// The working tree, to run programs in.
+    pub fn path(&self) -> &Path {
+        self.dir.path()
+    }
+
+    /// Run git in the repo and return its output with surrounding
+    /// whitespace trimmed.
+    pub fn git(&self, args: &[&str]) -> String {
+        let output = Command::new("git")
+            .args(args)
+            .current_dir(self.path())
+            .env("GIT_CONFIG_NOSYSTEM", "1")
+            .env("GIT_CONFIG_GLOBAL", "/dev/null")
+            .env("GIT_AUTHOR_DATE", COMMIT_DATE)
+            .env("GIT_COMMITTER_DATE", COMMIT_DATE)
+            .output()
+            .expect("failed to run git");
+        if !output.status.success() {
+            panic!("git {} failed: {}", args.join(" "), String::from_utf8_lossy(&output.stderr).trim());
+        }
+        String::from_utf8_lossy(&output.stdout).trim().to_string()
+    }
+
+    /// Write a file in the working tree, creating its parent directories.
+    pub fn write(&self, path: &str, contents: &str) {
+        let path = self.path().join(path);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).expect("failed to create directories");
+        }
+        fs::write(&path, contents).unwrap_or_else(|e| panic!("failed to write {}: {}", path.display(), e));
+    }
+
+    /// Read a file from the working tree, or `None` if it doesn't exist.
+    pub fn read(&self, path: &str) -> Option<String> {
+        fs::read_to_string(self.path().join(path)).ok()
+    }
+
+    /// Write `files`, stage everything in the working tree, and commit it.
+    /// Returns the new commit's hash.
+    pub fn commit(&self, message: &str, files: &[(&str, &str)]) -> String {
+        for (path, contents) in files {
+            self.write(path, contents);
+        }
+        self.git(&["add", "-A"]);
+        self.git(&["commit", "-q", "--allow-empty", "-m", message]);
+        self.git(&["rev-parse", "HEAD"])
+    }
+
+    /// Create a branch at the current commit and switch to it.
+    pub fn branch(&self, name: &str) {
+        self.git(&["checkout", "-q", "-b", name]);
+    }
+
+    /// Switch to an existing branch or commit.
+    pub fn checkout(&self, rev: &str) {
+        self.git(&["checkout", "-q", rev]);
+    }
+
+    /// The checked-out branch, or `HEAD` when detached.
+    pub fn current_branch(&self) -> String {
+        self.git(&["rev-parse", "--abbrev-ref", "HEAD"])
+    }
+
+    /// Local branch names, sorted.
+    pub fn branches(&self) -> Vec<String> {
+        lines(&self.git(&["for-each-ref", "--format=%(refname:short)", "refs/heads"]))
+    }
+
+    /// Commits reachable from `rev`, oldest first.
+    pub fn log(&self, rev: &str) -> Vec<Commit> {
+        let hashes = lines(&self.git(&["rev-list", "--reverse", rev]));
+        hashes
+            .into_iter()
+            .map(|hash| {
+                let subject = self.git(&["show", "-s", "--format=%s", &hash]);
+                let body = self.git(&["show", "-s", "--format=%b", &hash]);
+                let mut files = lines(&self.git(&["diff-tree", "--no-commit-id", "--name-only", "-r", "--root", &hash]));
+                files.sort();
+                Commit { hash, subject, body, files }
+            })
+            .collect()
+    }
+
+    /// Subjects of the commits reachable from `rev`, oldest first.
+    pub fn subjects(&self, rev: &str) -> Vec<String> {
+        lines(&self.git(&["log", "--reverse", "--format=%s", rev]))
+    }
+
+    /// Every local branch with the subjects of its commits, oldest first.
+    /// Handy for asserting the whole shape of a repo in one comparison.
+    pub fn history(&self) -> BTreeMap<String, Vec<String>> {
+        self.branches()
+            .into_iter()
+            .map(|branch| {
+                let subjects = self.subjects(&branch);
+                (branch, subjects)
+            })
+            .collect()
+    }
+
+    /// Hash of the tree at `rev`, to check two branches end with the same
+    /// files.
+    pub fn tree(&self, rev: &str) -> String {
+        self.git(&["rev-parse", &format!("{}^{{tree}}", rev)])
+    }
+}
+
+impl Default for GitRepo {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn lines(output: &str) -> Vec<String> {
+    output.lines().filter(|line| !line.is_empty()).map(str::to_string).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_scripted_history() {
+        let repo = GitRepo::new();
+        let first = repo.commit("Add readme", &[("README.md", "hello\n")]);
+        repo.branch("feature");
+        repo.commit("Add parser\n\nWith tests.", &[("src/parser.rs", "fn parse() {}\n"), ("src/lib.rs", "")]);
+
+        assert_eq!(repo.current_branch(), "feature");
+        assert_eq!(repo.branches(), ["feature", "main"]);
+        let log = repo.log("feature");
+        assert_eq!(log[0].hash, first);
+        assert_eq!(log[1].subject, "Add parser");
+        assert_eq!(log[1].body, "With tests.");
+        assert_eq!(log[1].files, ["src/lib.rs", "src/parser.rs"]);
+        assert_eq!(
+            repo.history(),
+            BTreeMap::from([
+                ("feature".to_string(), vec!["Add readme".to_string(), "Add parser".to_string()]),
+                ("main".to_string(), vec!["Add readme".to_string()]),
+            ])
+        );
+        assert_ne!(repo.tree("main"), repo.tree("feature"));
+
+        // Fixed identities and dates make the same script reproducible
+        let again = GitRepo::new();
+        assert_eq!(again.commit("Add readme", &[("README.md", "hello\n")]), first);
+    }
+}
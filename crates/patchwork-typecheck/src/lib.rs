@@ -0,0 +1,704 @@
+//! Static type checking for Patchwork programs.
+//!
+//! The interpreter checks a typed parameter when a call crosses into it.
+//! This pass finds what can be known before anything runs:
+//!
+//! - Every type name resolves, to a builtin such as `string` or
+//!   `list<T>`, a `type` declaration, or the tag of a declared variant.
+//! - A `var` or `const` declared with a type starts out with a value of
+//!   that type.
+//! - A name declared with a type, such as a union of string literals, is
+//!   only assigned values the type allows.
+//!
+//! Only literals are checked against types; a value the program computes
+//! is left to the interpreter. Types can also come from outside the program,
+//! such as the earlier cells of a session, which callers name with
+//! [`check_with_types`]. A program that imports modules may use the types
+//! they declare, which load after it's checked, so its type names aren't
+//! resolved.
+
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+
+use patchwork_parser::ast::*;
+use patchwork_parser::ParseError;
+
+/// The builtin type names and alias resolution every pass shares, defined
+/// with the parser's static checks, which can't depend on this crate.
+pub use patchwork_parser::types::{resolve_aliases, BUILTIN_TYPES, MAX_ALIAS_DEPTH};
+
+/// What a diagnostic is about.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DiagnosticKind {
+    /// A type name no builtin, declaration, or variant defines.
+    UnknownType,
+    /// A generic container with the wrong type arguments.
+    TypeArguments,
+    /// A value its declared type doesn't allow.
+    Mismatch,
+}
+
+/// A problem the type checker found.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    pub kind: DiagnosticKind,
+    pub message: String,
+    /// The offending type name or value
+    pub span: Span,
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+/// Diagnostics report like the parser's static checks.
+impl From<Diagnostic> for ParseError {
+    fn from(diagnostic: Diagnostic) -> Self {
+        let Span { start, end } = diagnostic.span;
+        ParseError::Check { message: diagnostic.message, span: (start, end) }
+    }
+}
+
+/// Check `program`, parsed from `source`, and report each problem in
+/// source order.
+pub fn check(program: &Program, source: &str) -> Vec<Diagnostic> {
+    check_with_types(program, source, &[])
+}
+
+/// Check `program` as [`check`] does, treating `known` as declared types.
+pub fn check_with_types(program: &Program, source: &str, known: &[&str]) -> Vec<Diagnostic> {
+    let mut checker = Checker::new(program, source, known);
+    for item in &program.items {
+        match &item.node {
+            Item::Skill(decl) => checker.callable(&decl.params, &decl.body),
+            Item::Worker(decl) => checker.callable(&decl.params, &decl.body),
+            Item::Function(decl) => checker.callable(&decl.params, &decl.body),
+            Item::Trait(decl) => {
+                for method in &decl.methods {
+                    checker.callable(&method.params, &method.body);
+                }
+            }
+            Item::Config(decl) => {
+                for value in decl.fields.iter().filter_map(|field| field.value.as_ref()) {
+                    checker.expr(value);
+                }
+            }
+            Item::Example(decl) => checker.prompt_items(&decl.items),
+            Item::Fragment(decl) => checker.prompt_items(&decl.items),
+            Item::Type(decl) => checker.type_names(&decl.type_expr),
+            Item::Import(_) => {}
+        }
+    }
+    let mut diagnostics = checker.diagnostics;
+    diagnostics.sort_by_key(|diagnostic| (diagnostic.span.start, diagnostic.span.end));
+    diagnostics
+}
+
+/// A type as written, and the type of a name in scope.
+type TypeRef<'a> = &'a Spanned<TypeExpr<'a>>;
+
+struct Checker<'a> {
+    source: &'a str,
+    /// Type declarations, from `type` items and those statements reached so far
+    aliases: HashMap<&'a str, TypeRef<'a>>,
+    /// Tags of the variants declared types include
+    tags: HashSet<&'a str>,
+    /// Types declared outside the program
+    known: HashSet<String>,
+    /// Whether names that resolve to nothing are reported
+    resolve_names: bool,
+    /// Names in scope, with the types they were declared with
+    scopes: Vec<HashMap<&'a str, Option<TypeRef<'a>>>>,
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl<'a> Checker<'a> {
+    fn new(program: &'a Program<'a>, source: &'a str, known: &[&str]) -> Self {
+        let mut checker = Checker {
+            source,
+            aliases: HashMap::new(),
+            tags: HashSet::new(),
+            known: known.iter().map(|name| name.to_string()).collect(),
+            resolve_names: !program.items.iter().any(|item| matches!(&item.node, Item::Import(_))),
+            scopes: vec![HashMap::new()],
+            diagnostics: Vec::new(),
+        };
+        for item in &program.items {
+            if let Item::Type(decl) = &item.node {
+                checker.declare(&decl.name, &decl.type_expr);
+            }
+        }
+        checker
+    }
+
+    fn declare(&mut self, name: &'a str, ty: TypeRef<'a>) {
+        self.aliases.insert(name, ty);
+        let mut pending = vec![ty];
+        while let Some(ty) = pending.pop() {
+            match &ty.node {
+                TypeExpr::Variant { tag, fields } => {
+                    self.tags.insert(tag);
+                    pending.extend(fields.iter().map(|field| &field.type_expr));
+                }
+                TypeExpr::Object(fields) => pending.extend(fields.iter().map(|field| &field.type_expr)),
+                TypeExpr::Array(elem) => pending.push(elem),
+                TypeExpr::Union(types) | TypeExpr::Generic { args: types, .. } => pending.extend(types),
+                TypeExpr::Name(_) | TypeExpr::Literal(_) => {}
+            }
+        }
+    }
+
+    fn report(&mut self, kind: DiagnosticKind, message: String, span: Span) {
+        self.diagnostics.push(Diagnostic { kind, message, span });
+    }
+
+    /// Report the names in `ty` that don't resolve, and generic containers
+    /// with the wrong arguments.
+    fn type_names(&mut self, ty: &'a Spanned<TypeExpr<'a>>) {
+        match &ty.node {
+            TypeExpr::Name(name) => {
+                let declared = BUILTIN_TYPES.contains(&&**name)
+                    || self.aliases.contains_key(&**name)
+                    || self.tags.contains(&**name)
+                    || self.known.contains(&**name);
+                if self.resolve_names && !declared {
+                    self.report(DiagnosticKind::UnknownType, format!("Unknown type '{}'", name), ty.span);
+                }
+            }
+            TypeExpr::Generic { name, args } => {
+                let problem = match (&**name, args.as_slice()) {
+                    ("list" | "option", [_]) => None,
+                    ("list" | "option", _) => Some(format!("`{}` takes one type argument", name)),
+                    ("map", [key, _]) if matches!(&key.node, TypeExpr::Name(key) if key == "string") => None,
+                    ("map", _) => Some("`map` takes a `string` key type and a value type".to_string()),
+                    _ => {
+                        self.report(DiagnosticKind::UnknownType, format!("Unknown type '{}'", name), ty.span);
+                        None
+                    }
+                };
+                if let Some(message) = problem {
+                    self.report(DiagnosticKind::TypeArguments, message, ty.span);
+                }
+                for arg in args {
+                    self.type_names(arg);
+                }
+            }
+            TypeExpr::Object(fields) | TypeExpr::Variant { fields, .. } => {
+                for field in fields {
+                    self.type_names(&field.type_expr);
+                }
+            }
+            TypeExpr::Array(elem) => self.type_names(elem),
+            TypeExpr::Union(types) => {
+                for ty in types {
+                    self.type_names(ty);
+                }
+            }
+            TypeExpr::Literal(_) => {}
+        }
+    }
+
+    fn prompt_items(&mut self, items: &'a [PromptItem<'a>]) {
+        for item in items {
+            match item {
+                PromptItem::Interpolation(expr) => self.expr(expr),
+                PromptItem::Code(block) => self.block(block),
+                PromptItem::Text(_) => {}
+            }
+        }
+    }
+
+    fn callable(&mut self, params: &'a [Spanned<Param<'a>>], body: &'a Block<'a>) {
+        for ty in params.iter().filter_map(|param| param.type_ann.as_ref()) {
+            self.type_names(ty);
+        }
+        self.scopes.push(params.iter().map(|param| (&*param.name, param.type_ann.as_ref())).collect());
+        self.statements(body);
+        self.scopes.pop();
+    }
+
+    fn block(&mut self, block: &'a Block<'a>) {
+        self.scopes.push(HashMap::new());
+        self.statements(block);
+        self.scopes.pop();
+    }
+
+    fn statements(&mut self, block: &'a Block<'a>) {
+        for stmt in &block.statements {
+            self.statement(stmt);
+        }
+    }
+
+    fn statement(&mut self, stmt: &'a Statement<'a>) {
+        match stmt {
+            Statement::VarDecl { pattern, init } => {
+                if let Some(init) = init {
+                    self.expr(init);
+                    self.initializer(pattern, init);
+                }
+                self.bind(pattern);
+            }
+            Statement::ConstDecl { pattern, init } => {
+                self.expr(init);
+                self.initializer(pattern, init);
+                self.bind(pattern);
+            }
+            Statement::Expr(expr) | Statement::Return(Some(expr)) => self.expr(expr),
+            Statement::If { condition, then_block, else_block } => {
+                self.expr(condition);
+                self.block(then_block);
+                if let Some(else_block) = else_block {
+                    self.block(else_block);
+                }
+            }
+            Statement::ForIn { pattern, iter: init, body } | Statement::WhileVar { pattern, init, body } => {
+                self.expr(init);
+                self.scopes.push(HashMap::new());
+                self.bind(pattern);
+                self.block(body);
+                self.scopes.pop();
+            }
+            Statement::While { condition, body } => {
+                self.expr(condition);
+                self.block(body);
+            }
+            Statement::Timeout { limit, body } => {
+                self.expr(limit);
+                self.statement(body);
+            }
+            Statement::Try { body, catch, finally_block } => {
+                self.block(body);
+                if let Some(catch) = catch {
+                    self.scopes.push(catch.var.iter().map(|var| (&**var, None)).collect());
+                    self.block(&catch.body);
+                    self.scopes.pop();
+                }
+                if let Some(finally_block) = finally_block {
+                    self.block(finally_block);
+                }
+            }
+            Statement::TypeDecl { name, type_expr } => {
+                self.declare(name, type_expr);
+                self.type_names(type_expr);
+            }
+            Statement::Return(None) | Statement::Succeed | Statement::Break => {}
+        }
+    }
+
+    /// Check the value a `var` or `const` declared with a type starts with.
+    fn initializer(&mut self, pattern: &'a Pattern<'a>, init: &'a Spanned<Expr<'a>>) {
+        if let Pattern::Identifier { name, type_ann: Some(ty) } = pattern {
+            if let Some(reason) = self.mismatch(ty, &init.node, &mut String::new(), 0) {
+                let message =
+                    format!("Initial value of '{}' does not match type {}: {}", name, self.text(ty), reason);
+                self.report(DiagnosticKind::Mismatch, message, init.span);
+            }
+        }
+    }
+
+    fn bind(&mut self, pattern: &'a Pattern<'a>) {
+        match pattern {
+            Pattern::Identifier { name, type_ann } => {
+                if let Some(ty) = type_ann {
+                    self.type_names(ty);
+                }
+                if let Some(scope) = self.scopes.last_mut() {
+                    scope.insert(name, type_ann.as_ref());
+                }
+            }
+            Pattern::Object(fields) => {
+                for field in fields {
+                    if let Some(ty) = &field.type_ann {
+                        self.type_names(ty);
+                    }
+                    self.bind(&field.pattern);
+                }
+            }
+            Pattern::Array(items) => {
+                for item in items {
+                    self.bind(item);
+                }
+            }
+            Pattern::Ignore => {}
+        }
+    }
+
+    fn lookup(&self, name: &str) -> Option<TypeRef<'a>> {
+        self.scopes.iter().rev().find_map(|scope| scope.get(name)).copied().flatten()
+    }
+
+    fn expr(&mut self, expr: &'a Spanned<Expr<'a>>) {
+        match &expr.node {
+            Expr::Binary { op: BinOp::Assign, left, right } => {
+                self.expr(left);
+                self.expr(right);
+                if let Expr::Identifier(name) = &left.node {
+                    let Some(ty) = self.lookup(name) else { return };
+                    if let Some(reason) = self.mismatch(ty, &right.node, &mut String::new(), 0) {
+                        let message =
+                            format!("Value assigned to '{}' does not match type {}: {}", name, self.text(ty), reason);
+                        self.report(DiagnosticKind::Mismatch, message, right.span);
+                    }
+                }
+            }
+            Expr::Binary { left, right, .. }
+            | Expr::Index { object: left, index: right }
+            | Expr::ShellPipe { left, right }
+            | Expr::ShellAnd { left, right }
+            | Expr::ShellOr { left, right }
+            | Expr::ShellRedirect { command: left, target: right, .. } => {
+                self.expr(left);
+                self.expr(right);
+            }
+            Expr::Object(fields) | Expr::Variant { fields, .. } => {
+                for value in fields.iter().filter_map(|field| field.value.as_ref()) {
+                    self.expr(value);
+                }
+            }
+            Expr::Member { object, .. } => self.expr(object),
+            Expr::Array(items) => {
+                for item in items {
+                    self.expr(item);
+                }
+            }
+            Expr::Call { callee, args } => {
+                self.expr(callee);
+                for arg in args {
+                    self.expr(arg);
+                }
+            }
+            Expr::Unary { operand: inner, .. }
+            | Expr::PostIncrement(inner)
+            | Expr::PostDecrement(inner)
+            | Expr::Paren(inner)
+            | Expr::Await(inner)
+            | Expr::AwaitAll(inner)
+            | Expr::CommandSubst(inner) => self.expr(inner),
+            Expr::String(s) => self.string(s),
+            Expr::BareCommand { args, .. } => {
+                for arg in args {
+                    if let CommandArg::String(s) = arg {
+                        self.string(s);
+                    }
+                }
+            }
+            Expr::Think(prompt) | Expr::Ask(prompt) => {
+                if let Some(tools) = &prompt.tools {
+                    self.expr(tools);
+                }
+                let variants = prompt.variants.iter().map(|variant| &variant.items);
+                for items in std::iter::once(&prompt.items).chain(variants) {
+                    self.prompt_items(items);
+                }
+                if let Some(validator) = &prompt.validator {
+                    self.scopes.push(HashMap::from([(&*validator.param, None)]));
+                    self.statements(&validator.body);
+                    self.scopes.pop();
+                }
+            }
+            Expr::Do(block) => self.block(block),
+            Expr::Match { subject, arms } => {
+                self.expr(subject);
+                for arm in arms {
+                    // Arms test for types, where a bare name may be a variant's tag
+                    let binding = match &arm.pattern.node {
+                        MatchPattern::Type { name: Some(name), type_expr } => Some((&**name, Some(type_expr))),
+                        MatchPattern::Binding(name) => Some((&**name, None)),
+                        _ => None,
+                    };
+                    self.scopes.push(binding.into_iter().collect());
+                    self.block(&arm.body);
+                    self.scopes.pop();
+                }
+            }
+            Expr::Lambda { params, body } => self.callable(params, body),
+            Expr::Identifier(_)
+            | Expr::Number(_)
+            | Expr::Duration(_)
+            | Expr::Size(_)
+            | Expr::True
+            | Expr::False => {}
+        }
+    }
+
+    fn string(&mut self, s: &'a StringLiteral<'a>) {
+        for part in &s.parts {
+            if let StringPart::Interpolation(expr) = part {
+                self.expr(expr);
+            }
+        }
+    }
+
+    /// `ty` with aliases followed, as far as they go.
+    fn resolve(&self, ty: TypeRef<'a>) -> TypeRef<'a> {
+        resolve_aliases(ty, |ty| match &ty.node {
+            TypeExpr::Name(name) => self.aliases.get(&**name).copied(),
+            _ => None,
+        })
+        .unwrap_or_else(|ty| ty)
+    }
+
+    /// The source of a type annotation, on one line.
+    fn text(&self, ty: TypeRef<'a>) -> String {
+        let text = self.source.get(ty.span.start..ty.span.end).unwrap_or_default();
+        text.split_whitespace().collect::<Vec<_>>().join(" ")
+    }
+
+    /// Why `expr` is certainly not a value of type `ty`, as the interpreter
+    /// would say it, or None if it is or might be. `path` locates `expr`
+    /// within the value being checked.
+    fn mismatch(&self, ty: TypeRef<'a>, expr: &Expr, path: &mut String, depth: usize) -> Option<String> {
+        let ty = self.resolve(ty);
+        let expr = literal(expr)?;
+        if depth > MAX_ALIAS_DEPTH {
+            return None;
+        }
+        let mismatch = |path: &str| {
+            let at = if path.is_empty() { "value" } else { path };
+            Some(format!("at {}: expected {}, got {}", at, self.text(ty), describe(expr)))
+        };
+
+        match (&ty.node, expr) {
+            (TypeExpr::Name(name), _) => {
+                let matches = match &**name {
+                    "string" => matches!(expr, Expr::String(_)),
+                    "number" | "int" | "float" => matches!(expr, Expr::Number(_) | Expr::Unary { .. }),
+                    "bool" | "boolean" => matches!(expr, Expr::True | Expr::False),
+                    "duration" => matches!(expr, Expr::Duration(_)),
+                    "size" => matches!(expr, Expr::Size(_)),
+                    "function" => matches!(expr, Expr::Lambda { .. }),
+                    "null" => false,
+                    // `any`, and names that don't resolve here
+                    _ => true,
+                };
+                if matches {
+                    None
+                } else {
+                    mismatch(path)
+                }
+            }
+            (TypeExpr::Literal(expected), Expr::String(s)) => match plain_text(s) {
+                Some(text) if text != *expected => mismatch(path),
+                // Interpolated text might be anything
+                _ => None,
+            },
+            (TypeExpr::Array(elem), Expr::Array(items)) => self.elements(elem, items, path, depth),
+            (TypeExpr::Generic { name, args }, Expr::Array(items)) if name == "list" && args.len() == 1 => {
+                self.elements(&args[0], items, path, depth)
+            }
+            (TypeExpr::Generic { name, args }, _) if name == "option" && args.len() == 1 => {
+                self.mismatch(&args[0], expr, path, depth + 1)
+            }
+            (TypeExpr::Generic { name, args }, Expr::Object(fields)) if name == "map" && args.len() == 2 => {
+                for field in fields {
+                    let Some(value) = &field.value else { continue };
+                    let len = path.len();
+                    path.push_str(&format!("[{:?}]", field.key));
+                    if let Some(reason) = self.mismatch(&args[1], &value.node, path, depth + 1) {
+                        return Some(reason);
+                    }
+                    path.truncate(len);
+                }
+                None
+            }
+            // Containers named wrongly are reported where they're written
+            (TypeExpr::Generic { name, args }, _)
+                if !matches!((&**name, args.len()), ("list" | "option", 1) | ("map", 2)) =>
+            {
+                None
+            }
+            (TypeExpr::Object(types), Expr::Object(fields) | Expr::Variant { fields, .. }) => {
+                self.fields(types, fields, path, depth)
+            }
+            (TypeExpr::Variant { tag, fields: types }, Expr::Variant { tag: actual, fields }) if tag == actual => {
+                self.fields(types, fields, path, depth)
+            }
+            (TypeExpr::Union(types), _) => {
+                let allowed = types.iter().any(|member| {
+                    self.mismatch(member, expr, &mut path.clone(), depth + 1).is_none()
+                });
+                if allowed {
+                    None
+                } else {
+                    mismatch(path)
+                }
+            }
+            _ => mismatch(path),
+        }
+    }
+
+    fn elements(&self, elem: TypeRef<'a>, items: &[Spanned<Expr>], path: &mut String, depth: usize) -> Option<String> {
+        for (i, item) in items.iter().enumerate() {
+            let len = path.len();
+            path.push_str(&format!("[{}]", i));
+            if let Some(reason) = self.mismatch(elem, &item.node, path, depth + 1) {
+                return Some(reason);
+            }
+            path.truncate(len);
+        }
+        None
+    }
+
+    /// Check the declared fields of an object or variant type against the
+    /// fields of a literal.
+    fn fields(&self, types: &'a [TypeField<'a>], fields: &[ObjectField], path: &mut String, depth: usize) -> Option<String> {
+        for ty in types {
+            let len = path.len();
+            path.push('.');
+            path.push_str(&ty.key);
+            match fields.iter().find(|field| *field.key == ty.key) {
+                Some(ObjectField { value: Some(value), .. }) => {
+                    if let Some(reason) = self.mismatch(&ty.type_expr, &value.node, path, depth + 1) {
+                        return Some(reason);
+                    }
+                }
+                // `{name}` takes the value of a variable
+                Some(ObjectField { value: None, .. }) => {}
+                None if ty.optional => {}
+                None => return Some(format!("at {}: missing field of type {}", path, self.text(&ty.type_expr))),
+            }
+            path.truncate(len);
+        }
+        None
+    }
+}
+
+/// `expr` if it's a literal, with parentheses removed.
+fn literal<'e, 'a>(expr: &'e Expr<'a>) -> Option<&'e Expr<'a>> {
+    match expr {
+        Expr::Paren(inner) => literal(&inner.node),
+        Expr::Unary { op: UnOp::Neg, operand } if matches!(operand.node, Expr::Number(_)) => Some(expr),
+        Expr::String(_)
+        | Expr::Number(_)
+        | Expr::Duration(_)
+        | Expr::Size(_)
+        | Expr::True
+        | Expr::False
+        | Expr::Array(_)
+        | Expr::Object(_)
+        | Expr::Variant { .. }
+        | Expr::Lambda { .. } => Some(expr),
+        _ => None,
+    }
+}
+
+/// The text of a string literal with no interpolation.
+fn plain_text<'a>(s: &'a StringLiteral) -> Option<&'a str> {
+    match s.parts.as_slice() {
+        [] => Some(""),
+        [StringPart::Text(text)] => Some(text),
+        _ => None,
+    }
+}
+
+/// A literal as a message names it, as the interpreter describes values.
+fn describe(expr: &Expr) -> String {
+    match expr {
+        Expr::String(s) => match plain_text(s) {
+            Some(text) => format!("string {:?}", text),
+            None => "string".to_string(),
+        },
+        Expr::Number(_) | Expr::Unary { .. } => "number".to_string(),
+        Expr::True | Expr::False => "boolean".to_string(),
+        Expr::Duration(_) => "duration".to_string(),
+        Expr::Size(_) => "size".to_string(),
+        Expr::Array(_) => "array".to_string(),
+        Expr::Variant { tag, .. } => format!("variant {}", tag),
+        Expr::Lambda { .. } => "function".to_string(),
+        _ => "object".to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use patchwork_parser::parse;
+
+    fn messages(source: &str) -> Vec<(DiagnosticKind, String, &str)> {
+        let program = parse(source).unwrap();
+        check(&program, source)
+            .into_iter()
+            .map(|d| (d.kind, d.message, &source[d.span.start..d.span.end]))
+            .collect()
+    }
+
+    #[test]
+    fn test_unknown_types() {
+        let source = r#"type Status = "ok" | Failure { reason: strng }
+
+fun f(s: Status, fail: Failure, items: list<Item>, index: map<number, string>, r: Reslt) {
+    var count: numbr = 1
+}"#;
+        let found = messages(source);
+        assert_eq!(
+            found,
+            vec![
+                (DiagnosticKind::UnknownType, "Unknown type 'strng'".to_string(), "strng"),
+                (DiagnosticKind::UnknownType, "Unknown type 'Item'".to_string(), "Item"),
+                (
+                    DiagnosticKind::TypeArguments,
+                    "`map` takes a `string` key type and a value type".to_string(),
+                    "map<number, string>"
+                ),
+                (DiagnosticKind::UnknownType, "Unknown type 'Reslt'".to_string(), "Reslt"),
+                (DiagnosticKind::UnknownType, "Unknown type 'numbr'".to_string(), "numbr"),
+            ]
+        );
+
+        // Types the program didn't declare can be named by the caller
+        let source = "fun f(r: Report) {}";
+        let program = parse(source).unwrap();
+        assert!(check_with_types(&program, source, &["Report"]).is_empty());
+        let source = "import ./{reports}\n\nfun f(r: Report) {}";
+        assert!(messages(source).is_empty());
+    }
+
+    #[test]
+    fn test_literal_initializers_and_assignments() {
+        let source = r#"type Status = "ok" | "error"
+type Point = { x: number, y: number }
+
+fun f(name) {
+    var status: Status = "ok"
+    status = "oops"
+    status = name
+    const limit: duration = 30
+    var point: Point = { x: 1, y: "2" }
+    var origin: Point = { x: 0 }
+    var tags: list<string> = ["a", 2]
+    var greeting: string = "hi ${name}"
+    if true {
+        var status = 1
+        status = 2
+    }
+}"#;
+        let found: Vec<(String, &str)> = messages(source).into_iter().map(|(_, message, at)| (message, at)).collect();
+        assert_eq!(
+            found,
+            vec![
+                (
+                    "Value assigned to 'status' does not match type Status: at value: expected \"ok\" | \"error\", got string \"oops\"".to_string(),
+                    "\"oops\""
+                ),
+                (
+                    "Initial value of 'limit' does not match type duration: at value: expected duration, got number".to_string(),
+                    "30"
+                ),
+                (
+                    "Initial value of 'point' does not match type Point: at .y: expected number, got string \"2\"".to_string(),
+                    "{ x: 1, y: \"2\" }"
+                ),
+                (
+                    "Initial value of 'origin' does not match type Point: at .y: missing field of type number".to_string(),
+                    "{ x: 0 }"
+                ),
+                (
+                    "Initial value of 'tags' does not match type list<string>: at [1]: expected string, got number".to_string(),
+                    "[\"a\", 2]"
+                ),
+            ]
+        );
+    }
+}